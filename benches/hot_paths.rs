@@ -0,0 +1,206 @@
+//! Benchmarks of the hot paths: the Noise handshake, encrypting and decrypting
+//! frames, (de)serializing each message type and writing them on a transport.
+//!
+//! Run them with `cargo bench`, or `cargo bench -- <filter>` for a subset.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use revault_net::{
+    message::{ErrorResponse, Notification, Request, Response, ResponseResult},
+    noise::{
+        gen_keypair, KKChannel, KKHandshakeActOne, KKHandshakeActTwo, NoiseEncryptedHeader,
+        NoiseEncryptedMessage, NOISE_MESSAGE_HEADER_SIZE, NOISE_PLAINTEXT_MAX_SIZE,
+    },
+    transport::KKTransport,
+    Error,
+};
+use serde::{Deserialize, Serialize};
+use std::{convert::TryInto, net::TcpListener, thread, time::Instant};
+
+// Perform a whole handshake in memory, returning the initiator and responder channels
+fn channels() -> (KKChannel, KKChannel) {
+    let ((initiator_pubkey, initiator_privkey), (responder_pubkey, responder_privkey)) =
+        (gen_keypair(), gen_keypair());
+
+    let (cli_act_1, msg_1) =
+        KKHandshakeActOne::initiator(&initiator_privkey, &responder_pubkey).unwrap();
+    let serv_act_1 =
+        KKHandshakeActOne::responder(&responder_privkey, &[initiator_pubkey], &msg_1).unwrap();
+    let (serv_act_2, msg_2) = KKHandshakeActTwo::responder(serv_act_1).unwrap();
+    let cli_act_2 = KKHandshakeActTwo::initiator(cli_act_1, &msg_2).unwrap();
+
+    (
+        KKChannel::from_handshake(cli_act_2).unwrap(),
+        KKChannel::from_handshake(serv_act_2).unwrap(),
+    )
+}
+
+fn handshake(c: &mut Criterion) {
+    c.bench_function("handshake", |b| b.iter(channels));
+}
+
+fn encryption(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encryption");
+
+    for size in [100, 1_000, 10_000, NOISE_PLAINTEXT_MAX_SIZE].iter() {
+        let msg = vec![0x42; *size];
+        group.throughput(Throughput::Bytes(*size as u64));
+
+        let (mut initiator, _) = channels();
+        group.bench_with_input(BenchmarkId::new("encrypt", size), &msg, |b, msg| {
+            b.iter(|| initiator.encrypt_message(black_box(msg)).unwrap())
+        });
+
+        // The nonces must stay in sync, so each iteration decrypts a new frame
+        let (mut initiator, mut responder) = channels();
+        group.bench_with_input(BenchmarkId::new("decrypt", size), &msg, |b, msg| {
+            b.iter_batched(
+                || initiator.encrypt_message(msg).unwrap().0,
+                |frame| {
+                    let (header, body) = frame.split_at(NOISE_MESSAGE_HEADER_SIZE);
+                    responder
+                        .decrypt_header(&NoiseEncryptedHeader(header.try_into().unwrap()))
+                        .unwrap();
+                    responder
+                        .decrypt_message(&NoiseEncryptedMessage(body.to_vec()))
+                        .unwrap()
+                },
+                criterion::BatchSize::SmallInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+// Benchmark decoding and re-encoding this message as a T
+fn bench_message<'a, T>(c: &mut Criterion, name: &str, message: &'a str)
+where
+    T: Deserialize<'a> + Serialize,
+{
+    let mut group = c.benchmark_group(format!("json/{}", name));
+    group.throughput(Throughput::Bytes(message.len() as u64));
+
+    group.bench_function("decode", |b| {
+        b.iter(|| serde_json::from_str::<T>(black_box(message)).unwrap())
+    });
+    let parsed: T = serde_json::from_str(message).unwrap();
+    group.bench_function("encode", |b| {
+        b.iter(|| serde_json::to_vec(black_box(&parsed)).unwrap())
+    });
+
+    group.finish();
+}
+
+macro_rules! bench_vectors {
+    ($c:expr, $($ty:ty => [$($vector:literal),*]),*) => {
+        $($(
+            bench_message::<$ty>(
+                $c,
+                $vector,
+                include_str!(concat!("../contrib/test_vectors/", $vector, ".json")).trim_end(),
+            );
+        )*)*
+    };
+}
+
+fn json(c: &mut Criterion) {
+    bench_vectors!(c,
+        Request => [
+            "coord_sig", "get_sigs", "get_spend_tx", "set_spend_tx", "sign", "subscribe",
+            "wt_sig"
+        ],
+        Response<ResponseResult> => [
+            "sig_result", "sign_result_refused", "sigs", "sigs_empty", "spend_tx",
+            "spend_tx_not_found", "subscribe_result", "wt_sig_result"
+        ],
+        Notification => ["new_sig"],
+        ErrorResponse => ["error"]
+    );
+}
+
+// A client transport connected to a server one, each draining whatever the other
+// writes on its own thread
+fn transports() -> (KKTransport, KKTransport) {
+    let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
+        (gen_keypair(), gen_keypair());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client_thread =
+        thread::spawn(move || KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap());
+    let server = KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+    let client = client_thread.join().unwrap();
+
+    (client, server)
+}
+
+// Read and drop the messages received on this transport until it's disconnected
+fn drain(mut transport: KKTransport, requests: bool) {
+    thread::spawn(move || loop {
+        let res = if requests {
+            transport.incoming().next().map(|req| req.map(|_| ()))
+        } else {
+            Some(transport.read_notification().map(|_| ()))
+        };
+        if let None | Some(Err(Error::Disconnected(_))) = res {
+            return;
+        }
+    });
+}
+
+fn transport(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transport");
+
+    let requests = [
+        (
+            "coord_sig",
+            include_str!("../contrib/test_vectors/coord_sig.json"),
+        ),
+        (
+            "get_sigs",
+            include_str!("../contrib/test_vectors/get_sigs.json"),
+        ),
+        (
+            "set_spend_tx",
+            include_str!("../contrib/test_vectors/set_spend_tx.json"),
+        ),
+    ];
+    for (name, message) in requests.iter() {
+        let req: Request = serde_json::from_str(message).unwrap();
+        let (mut client, server) = transports();
+        drain(server, true);
+        // Don't wait for a response, there won't be any
+        group.bench_function(format!("send/{}", name), |b| {
+            b.iter(|| {
+                client
+                    .send_req_before::<()>(&req, Instant::now())
+                    .unwrap_err()
+            })
+        });
+    }
+
+    let responses = [
+        ("sigs", include_str!("../contrib/test_vectors/sigs.json")),
+        (
+            "sig_result",
+            include_str!("../contrib/test_vectors/sig_result.json"),
+        ),
+        (
+            "spend_tx",
+            include_str!("../contrib/test_vectors/spend_tx.json"),
+        ),
+    ];
+    for (name, message) in responses.iter() {
+        let resp: Response<ResponseResult> = serde_json::from_str(message).unwrap();
+        let (client, mut server) = transports();
+        drain(client, false);
+        group.bench_function(format!("respond/{}", name), |b| {
+            b.iter(|| server.respond(resp.id, resp.result.clone()).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, handshake, encryption, json, transport);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+//! Check a running server handles all the requests it is specified to.
+//!
+//! Usage:
+//! ```text
+//! cargo run --example conformance --features testing -- \
+//!     <coordinator|watchtower|cosigner> <host:port> <server noise pubkey> <our noise privkey>
+//! ```
+//! The keys are hex encoded, the server must have ours among its clients'.
+
+use bitcoin::hashes::hex::FromHex;
+use revault_net::{
+    message::method::Peer,
+    noise::{PublicKey, SecretKey},
+    testing::check_peer,
+    transport::KKTransport,
+};
+use std::{convert::TryInto, env, net::SocketAddr, process, time::Duration};
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: conformance <coordinator|watchtower|cosigner> <host:port> <server noise pubkey> <our noise privkey>"
+    );
+    process::exit(2)
+}
+
+fn key(hex: &str) -> [u8; 32] {
+    Vec::from_hex(hex)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .unwrap_or_else(|| usage())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 4 {
+        usage();
+    }
+
+    let peer = match args[0].as_str() {
+        "coordinator" => Peer::Coordinator,
+        "watchtower" => Peer::Watchtower,
+        "cosigner" => Peer::Cosigner,
+        _ => usage(),
+    };
+    let addr: SocketAddr = args[1].parse().unwrap_or_else(|_| usage());
+    let server_pubkey = PublicKey(key(&args[2]));
+    let our_privkey = SecretKey(key(&args[3]));
+
+    let mut transport =
+        KKTransport::connect(addr, &our_privkey, &server_pubkey).unwrap_or_else(|e| {
+            eprintln!("Connecting to '{}': {}", addr, e);
+            process::exit(1)
+        });
+    let report = check_peer(&mut transport, peer, Duration::from_secs(10));
+    print!("{}", report);
+
+    if !report.is_conformant() {
+        process::exit(1);
+    }
+}
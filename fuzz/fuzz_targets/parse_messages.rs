@@ -0,0 +1,20 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use revault_net::message::{ErrorResponse, Notification, Request, Response, ResponseResult};
+
+// Any message we accept must be re-serialized into a message we accept too
+macro_rules! check_reparse {
+    ($data:expr, $type:ty) => {
+        if let Ok(msg) = serde_json::from_slice::<$type>($data) {
+            let ser = serde_json::to_vec(&msg).expect("Serializing a parsed message");
+            serde_json::from_slice::<$type>(&ser).expect("Parsing a re-serialized message");
+        }
+    };
+}
+
+fuzz_target!(|data: &[u8]| {
+    check_reparse!(data, Request);
+    check_reparse!(data, Response<ResponseResult>);
+    check_reparse!(data, ErrorResponse);
+    check_reparse!(data, Notification);
+});
@@ -0,0 +1,34 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use revault_net::message::coordinator::{NewSpendTxEvent, SpendTx};
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn check_tx(tx_str: &str) {
+    let tx_json = serde_json::to_string(tx_str).unwrap();
+
+    let spend_tx = format!(r#"{{"transaction":{}}}"#, tx_json);
+    if let Ok(msg) = serde_json::from_str::<SpendTx>(&spend_tx) {
+        let ser = serde_json::to_string(&msg).unwrap();
+        assert_eq!(serde_json::from_str::<SpendTx>(&ser).unwrap(), msg);
+    }
+
+    let event = format!(
+        r#"{{"subscription_id":0,"deposit_outpoint":"0000000000000000000000000000000000000000000000000000000000000000:0","transaction":{}}}"#,
+        tx_json
+    );
+    if let Ok(msg) = serde_json::from_str::<NewSpendTxEvent>(&event) {
+        let ser = serde_json::to_string(&msg).unwrap();
+        assert_eq!(serde_json::from_str::<NewSpendTxEvent>(&ser).unwrap(), msg);
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    // The transaction decoder, behind both encodings
+    check_tx(&hex(data));
+    check_tx(&base64::encode(data));
+    // The encodings themselves
+    check_tx(&String::from_utf8_lossy(data));
+});
@@ -0,0 +1,80 @@
+/*
+ * C bindings of revault_net, built with the `ffi` feature.
+ *
+ * See the documentation of the `ffi` module for the conventions: messages are JSON
+ * strings, returned strings are freed with revault_string_free and every function
+ * returns REVAULT_OK or the class of its error, described by revault_last_error.
+ */
+
+#ifndef REVAULT_NET_H
+#define REVAULT_NET_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define REVAULT_KEY_SIZE 32
+
+#define REVAULT_OK 0
+#define REVAULT_ERROR_INVALID_ARGUMENT 1
+#define REVAULT_ERROR_TIMEOUT 2
+#define REVAULT_ERROR_DISCONNECTED 3
+#define REVAULT_ERROR_REMOTE 4
+#define REVAULT_ERROR_OTHER 5
+
+/* An encrypted and authenticated connection to a peer */
+typedef struct RevaultTransport RevaultTransport;
+/* A socket listening for incoming connections */
+typedef struct RevaultListener RevaultListener;
+
+int revault_keypair_generate(uint8_t pubkey[REVAULT_KEY_SIZE],
+                             uint8_t privkey[REVAULT_KEY_SIZE]);
+
+int revault_transport_connect(const char *addr,
+                              const uint8_t privkey[REVAULT_KEY_SIZE],
+                              const uint8_t their_pubkey[REVAULT_KEY_SIZE],
+                              RevaultTransport **transport);
+
+int revault_listener_bind(const char *addr, RevaultListener **listener);
+
+/* their_pubkeys holds n_pubkeys keys of REVAULT_KEY_SIZE bytes, one after the other */
+int revault_transport_accept(const RevaultListener *listener,
+                             const uint8_t privkey[REVAULT_KEY_SIZE],
+                             const uint8_t *their_pubkeys, size_t n_pubkeys,
+                             RevaultTransport **transport);
+
+/* 0 means reads block indefinitely */
+int revault_transport_set_read_timeout(RevaultTransport *transport, uint64_t timeout_ms);
+
+int revault_transport_remote_static(const RevaultTransport *transport,
+                                    uint8_t pubkey[REVAULT_KEY_SIZE]);
+
+int revault_transport_send_request(RevaultTransport *transport, const char *request,
+                                   char **result);
+
+int revault_transport_read_request(RevaultTransport *transport, char **request);
+
+int revault_transport_respond(RevaultTransport *transport, uint32_t id, const char *result);
+
+int revault_transport_respond_error(RevaultTransport *transport, uint32_t id, int64_t code,
+                                    const char *message);
+
+int revault_transport_read_notification(RevaultTransport *transport, char **notification);
+
+/* Valid until the next failing call on this thread, must not be freed */
+const char *revault_last_error(void);
+
+void revault_string_free(char *s);
+
+void revault_transport_free(RevaultTransport *transport);
+
+void revault_listener_free(RevaultListener *listener);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* REVAULT_NET_H */
@@ -0,0 +1,186 @@
+//! Networking on a dedicated thread
+//!
+//! [spawn] moves a connection to its own thread and returns a [Bridge] to it: requests
+//! are sent to the peer through a channel, and the responses and notifications it
+//! sends are received from another. This allows synchronous daemons to integrate with
+//! the network without handling the connection themselves.
+
+use crate::{
+    error::Error,
+    instrument::log_warn,
+    message::{ErrorResponse, Notification, NotificationParams, Request, Response},
+    transport::KKTransport,
+};
+
+use std::{
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// How long the networking thread waits for a request before checking for messages
+// from the peer
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A message received by the networking thread
+#[derive(Debug)]
+pub enum Event {
+    /// The response to one of our requests. The result is left as JSON, to be parsed
+    /// by the caller according to the request it was for (see [Event::parse]).
+    Response(Response<serde_json::Value>),
+    /// The error response to one of our requests
+    ErrorResponse(ErrorResponse),
+    /// A notification sent by the peer
+    Notification(NotificationParams),
+    /// The connection failed. No more event will be received.
+    Error(Error),
+}
+
+impl Event {
+    // Parse a message from the peer, which is either a response or a notification
+    fn from_slice(msg: &[u8]) -> Result<Self, Error> {
+        if let Ok(resp) = serde_json::from_slice::<Response<serde_json::Value>>(msg) {
+            return Ok(Self::Response(resp));
+        }
+        if let Ok(resp) = serde_json::from_slice::<ErrorResponse>(msg) {
+            return Ok(Self::ErrorResponse(resp));
+        }
+        let notif: Notification = serde_json::from_slice(msg)?;
+        Ok(Self::Notification(notif.params()))
+    }
+
+    /// Parse the result of a response as `T`
+    pub fn parse<T: serde::de::DeserializeOwned>(result: serde_json::Value) -> Result<T, Error> {
+        serde_json::from_value(result).map_err(|e| e.into())
+    }
+}
+
+/// The channels to a networking thread, see [spawn].
+#[derive(Debug)]
+pub struct Bridge {
+    /// Requests to send to the peer. Dropping it stops the networking thread.
+    pub requests: Sender<Request<'static>>,
+    /// Responses and notifications received from the peer
+    pub events: Receiver<Event>,
+    /// The networking thread. It stops once `requests` is dropped, or after a
+    /// connection failure.
+    pub thread: JoinHandle<()>,
+}
+
+/// Move this connection to a dedicated thread, sending the requests and receiving the
+/// responses and notifications through channels.
+pub fn spawn(transport: KKTransport) -> Result<Bridge, Error> {
+    let (requests, requests_rx) = mpsc::channel();
+    let (events_tx, events) = mpsc::channel();
+
+    let thread = thread::Builder::new()
+        .name("revault_net bridge".to_string())
+        .spawn(move || run(transport, requests_rx, events_tx))?;
+
+    Ok(Bridge {
+        requests,
+        events,
+        thread,
+    })
+}
+
+fn run(mut transport: KKTransport, requests: Receiver<Request>, events: Sender<Event>) {
+    loop {
+        match requests.recv_timeout(POLL_INTERVAL) {
+            Ok(req) => {
+                if let Err(e) = transport.write_req(&req) {
+                    let _ = events.send(Event::Error(e));
+                    return;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        while transport.readable() {
+            let event = match transport.read_message() {
+                Ok(msg) => match Event::from_slice(&msg) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        log_warn!("Ignoring invalid message: '{}'", e);
+                        continue;
+                    }
+                },
+                Err(e) => {
+                    let _ = events.send(Event::Error(e));
+                    return;
+                }
+            };
+            if events.send(event).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{local_endpoints, secp_sig};
+    use crate::message::{
+        coordinator::{GetSigs, NewSigEvent, Sigs},
+        RequestParams, ResponseResult, SigSet,
+    };
+
+    use bitcoin::Txid;
+
+    #[test]
+    fn bridge() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let (pubkey, signature) = secp_sig();
+        let event = NewSigEvent {
+            subscription_id: 0,
+            pubkey,
+            signature,
+            id: Txid::default(),
+        };
+        let srv_event = event.clone();
+        let srv_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+            transport.send_notification(&srv_event.into()).unwrap();
+            transport
+                .read_req(|params| {
+                    assert!(matches!(params, RequestParams::GetSigs(_)));
+                    Some(ResponseResult::Sigs(Sigs {
+                        signatures: SigSet::new(),
+                    }))
+                })
+                .unwrap();
+        });
+
+        let transport = KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        let bridge = spawn(transport).unwrap();
+        let req = Request::from(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        let id = req.id();
+        bridge.requests.send(req).unwrap();
+
+        match bridge.events.recv().unwrap() {
+            Event::Notification(params) => assert_eq!(params, NotificationParams::NewSig(event)),
+            e => panic!("Unexpected event {:?}", e),
+        }
+        match bridge.events.recv().unwrap() {
+            Event::Response(resp) => {
+                assert_eq!(resp.id, id);
+                let sigs: Sigs = Event::parse(resp.result).unwrap();
+                assert!(sigs.signatures.is_empty());
+            }
+            e => panic!("Unexpected event {:?}", e),
+        }
+
+        // The server closed the connection
+        srv_thread.join().unwrap();
+        assert!(matches!(bridge.events.recv().unwrap(), Event::Error(_)));
+        bridge.thread.join().unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+//! Capability flags
+//!
+//! Optional features of the protocol (CBOR-encoded messages, batched requests,
+//! subscriptions, base64 transactions) need both ends to support them. Right after the handshake,
+//! each end may advertise its [Capabilities] to the other (see
+//! [KKTransport::initiate_capabilities](crate::transport::KKTransport::initiate_capabilities)
+//! and [KKTransport::respond_capabilities](crate::transport::KKTransport::respond_capabilities)),
+//! and only use those both support, as negotiated by [Capabilities::negotiate].
+//!
+//! They are sent as a list of names, so that a peer advertising capabilities we don't
+//! know of (from a later version) is still understood: the unknown ones are ignored.
+//!
+//! The exchange is opt-in: both ends must do it, as a peer that does not would take
+//! the advertisement for a request.
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+
+/// An optional feature of the protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Capability {
+    /// Batches of requests in a single message
+    Batching,
+    /// Subscriptions to the new signatures and Spend transactions of a coordinator
+    Subscriptions,
+    /// CBOR-encoded messages
+    Cbor,
+    /// Raw transactions encoded as base64 rather than hex (see
+    /// [TxEncoding](crate::message::TxEncoding))
+    Base64Tx,
+}
+
+impl Capability {
+    /// All the capabilities known to this version
+    pub const ALL: [Capability; 4] = [
+        Capability::Batching,
+        Capability::Subscriptions,
+        Capability::Cbor,
+        Capability::Base64Tx,
+    ];
+
+    /// The name of the capability on the wire
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::Batching => "batching",
+            Capability::Subscriptions => "subscriptions",
+            Capability::Cbor => "cbor",
+            Capability::Base64Tx => "base64_tx",
+        }
+    }
+
+    /// The capability with this name, if known
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|cap| cap.name() == name)
+    }
+
+    fn bit(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A set of [Capability], advertised by an end of the connection or negotiated by both
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities {
+    bits: u32,
+}
+
+impl Capabilities {
+    /// No capability
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All the capabilities known to this version
+    pub fn all() -> Self {
+        Capability::ALL
+            .iter()
+            .fold(Self::new(), |caps, cap| caps.with(*cap))
+    }
+
+    /// Add this capability
+    pub fn with(mut self, capability: Capability) -> Self {
+        self.bits |= capability.bit();
+        self
+    }
+
+    /// Whether this capability is part of the set
+    pub fn contains(&self, capability: Capability) -> bool {
+        self.bits & capability.bit() != 0
+    }
+
+    /// Whether there is no capability in the set
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// The capabilities of the set
+    pub fn iter(&self) -> impl Iterator<Item = Capability> + '_ {
+        Capability::ALL
+            .iter()
+            .copied()
+            .filter(move |cap| self.contains(*cap))
+    }
+
+    /// The capabilities to use with a peer advertising these ones: those both ends
+    /// support.
+    pub fn negotiate(&self, theirs: &Capabilities) -> Capabilities {
+        Self {
+            bits: self.bits & theirs.bits,
+        }
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = self.iter().map(|cap| cap.name()).collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+// The message advertising the capabilities of an end
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Advertisement {
+    capabilities: Vec<String>,
+}
+
+impl From<&Capabilities> for Advertisement {
+    fn from(caps: &Capabilities) -> Self {
+        Self {
+            capabilities: caps.iter().map(|cap| cap.name().to_string()).collect(),
+        }
+    }
+}
+
+impl From<Advertisement> for Capabilities {
+    fn from(adv: Advertisement) -> Self {
+        adv.capabilities
+            .iter()
+            .filter_map(|name| Capability::from_name(name))
+            .fold(Capabilities::new(), |caps, cap| caps.with(cap))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities_negotiation() {
+        let ours = Capabilities::new()
+            .with(Capability::Batching)
+            .with(Capability::Subscriptions);
+        let theirs = Capabilities::new()
+            .with(Capability::Subscriptions)
+            .with(Capability::Cbor);
+        let negotiated = ours.negotiate(&theirs);
+        assert!(negotiated.contains(Capability::Subscriptions));
+        assert!(!negotiated.contains(Capability::Batching));
+        assert!(!negotiated.contains(Capability::Cbor));
+        assert_eq!(negotiated, theirs.negotiate(&ours));
+        assert!(ours.negotiate(&Capabilities::new()).is_empty());
+        assert_eq!(Capabilities::all().negotiate(&ours), ours);
+        assert_eq!(
+            Capabilities::all().to_string(),
+            "batching,subscriptions,cbor,base64_tx"
+        );
+
+        // Unknown capabilities are ignored
+        let adv: Advertisement =
+            serde_json::from_str(r#"{"capabilities":["teleportation","cbor"]}"#).unwrap();
+        assert_eq!(
+            Capabilities::from(adv),
+            Capabilities::new().with(Capability::Cbor)
+        );
+        assert_eq!(
+            serde_json::to_string(&Advertisement::from(&ours)).unwrap(),
+            r#"{"capabilities":["batching","subscriptions"]}"#
+        );
+    }
+}
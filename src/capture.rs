@@ -0,0 +1,138 @@
+//! Wire capture
+//!
+//! A debugging facility to record the decrypted messages a [KKTransport](crate::transport::KKTransport)
+//! sends and receives, in order to diagnose interoperability issues between
+//! implementations. It is opt-in, per transport, with
+//! [set_capture](crate::transport::KKTransport::set_capture).
+//!
+//! Each message is written on its own line, prefixed by the time (in milliseconds
+//! since the UNIX epoch) and its direction:
+//! ```text
+//! 1620000000000 out {"method":"get_sigs","params":{"id":"..."},"id":12}
+//! 1620000000042 in {"result":{"signatures":{}},"id":12}
+//! ```
+//! Signatures, public keys and transactions can be redacted out of the capture.
+
+use crate::{instrument::log_warn, message, metrics::Direction};
+
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub(crate) const REDACTED: &str = "<redacted>";
+
+/// Where to write the captured messages
+pub struct Capture {
+    writer: Mutex<Box<dyn Write + Send>>,
+    redact: bool,
+}
+
+impl fmt::Debug for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Capture")
+            .field("redact", &self.redact)
+            .finish()
+    }
+}
+
+impl Capture {
+    /// Capture the messages to this writer, without redacting them.
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Capture {
+            writer: Mutex::new(Box::new(writer)),
+            redact: false,
+        }
+    }
+
+    /// Capture the messages to this file, appending to it if it already exists.
+    pub fn to_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file: File = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(file))
+    }
+
+    /// Replace the signatures, public keys and transactions in the captured messages.
+    pub fn redacted(mut self, redact: bool) -> Self {
+        self.redact = redact;
+        self
+    }
+
+    // Record a message that was sent or received
+    pub(crate) fn record(&self, direction: Direction, msg: &[u8]) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let direction = match direction {
+            Direction::Inbound => "in",
+            Direction::Outbound => "out",
+        };
+        let msg = self.format(msg);
+
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writeln!(writer, "{} {} {}", timestamp, direction, msg) {
+            log_warn!("Error writing message capture: '{}'", e);
+        }
+    }
+
+    fn format(&self, msg: &[u8]) -> String {
+        if !self.redact {
+            return String::from_utf8_lossy(msg).into_owned();
+        }
+
+        match serde_json::from_slice::<serde_json::Value>(msg) {
+            Ok(mut value) => {
+                message::redact_json(&mut value, &|value| *value = REDACTED.into());
+                value.to_string()
+            }
+            // We can't tell what's sensitive in there
+            Err(_) => format!("<{} bytes not JSON>", msg.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn capture() {
+        let msg = br#"{"method":"sig","params":{"pubkey":"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c","signature":"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2","id":"0000000000000000000000000000000000000000000000000000000000000000"},"id":12}"#;
+
+        let buf = SharedBuf::default();
+        let capture = Capture::new(buf.clone());
+        capture.record(Direction::Outbound, msg);
+        let captured = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let mut parts = captured.trim_end().splitn(3, ' ');
+        parts.next().unwrap().parse::<u128>().unwrap();
+        assert_eq!(parts.next(), Some("out"));
+        assert_eq!(parts.next().unwrap().as_bytes(), &msg[..]);
+
+        let buf = SharedBuf::default();
+        let capture = Capture::new(buf.clone()).redacted(true);
+        capture.record(Direction::Inbound, msg);
+        capture.record(Direction::Inbound, b"\x00\x01");
+        let captured = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = captured.lines().collect();
+        assert!(lines[0].ends_with(r#" in {"id":12,"method":"sig","params":{"id":"0000000000000000000000000000000000000000000000000000000000000000","pubkey":"<redacted>","signature":"<redacted>"}}"#), "{}", lines[0]);
+        assert!(lines[1].ends_with(" in <2 bytes not JSON>"));
+    }
+}
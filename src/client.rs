@@ -0,0 +1,2654 @@
+//! Typed clients
+//!
+//! This module provides high-level wrappers around a [KKTransport] for each server
+//! of the Revault network, taking care of creating the requests and checking the
+//! responses. A [broadcast] helper sends a request to multiple peers at once, a
+//! [QuorumBroadcast] only until enough of them acknowledged it. A
+//! [CosignerOrchestrator] gets a Spend transaction signed by all the cosigning servers.
+//! For single exchanges without a client, see the [flows](crate::flows) module.
+//!
+//! How the clients retry failed requests is configured with a [RetryPolicy], and how
+//! the initial connection is with a [RetryConfig] (see [connect_with_retries]). They
+//! keep track of the round-trip latency of their requests, per method (see
+//! [Latencies]). Calls can be given a correlation id, to follow them through the logs,
+//! metrics and errors (see [with_correlation_id]). The [CoordinatorClient] can measure the skew of
+//! our clock to the coordinator's (see [CoordinatorClient::sync_time]). The requests
+//! may be recorded in a [Journal] before being sent, to survive a crash (see
+//! [journal]).
+
+use crate::{
+    clock::clock_offset,
+    config::MethodTimeouts,
+    error::{Error, MessageError},
+    instrument::{log_debug, log_warn},
+    journal::{self, Journal, JournalEntry},
+    message::{
+        coordinator::{
+            GetRevocationSigs, GetSigs, GetSpendTx, GetSpendTxChunk, GetTime, HasSigs, NotModified,
+            RevocationSigs, RevocationTxType, SchnorrSig, SchnorrSigs, ServerTime, SetSpendResult,
+            SetSpendTx, SetSpendTxChunk, SetSpendTxCommit, Sig, SigResult, Sigs, SigsCount,
+            SigsTag, SpendTx, SpendTxChunk, SpendTxChunks, UploadStarted,
+        },
+        method, watchtower, Request, SigSet,
+    },
+    noise::{PublicKey, SecretKey},
+    transport::KKTransport,
+};
+
+#[cfg(feature = "revault_tx")]
+use crate::message::cosigner;
+use bitcoin::{secp256k1::schnorrsig, OutPoint, Transaction, Txid};
+#[cfg(feature = "revault_tx")]
+use revault_tx::transactions::{RevaultTransaction, SpendTransaction};
+
+use std::{
+    cell::RefCell,
+    cmp,
+    collections::BTreeMap,
+    net::{SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// The delay to wait for before retrying a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backoff {
+    /// Retry immediately
+    None,
+    /// Always wait for the same duration
+    Constant(Duration),
+    /// Wait for `initial`, then twice as long after each attempt, up to `max`
+    Exponential {
+        /// The delay before the first retry
+        initial: Duration,
+        /// The maximum delay
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// The delay to wait for after this number of failed attempts
+    pub fn delay(&self, attempts: u32) -> Duration {
+        match *self {
+            Self::None => Duration::from_secs(0),
+            Self::Constant(delay) => delay,
+            Self::Exponential { initial, max } => initial
+                .checked_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+                .map(|delay| std::cmp::min(delay, max))
+                .unwrap_or(max),
+        }
+    }
+}
+
+/// Whether a request failing with this error may succeed if sent again: it timed out.
+/// Sending it again on the same transport is fine even if the response was being read
+/// when it timed out, the next read resuming it.
+pub fn is_transient(error: &Error) -> bool {
+    matches!(error.inner(), Error::Timeout(_))
+}
+
+thread_local! {
+    static CORRELATION_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// Restores the previous correlation id when going out of scope, even on panic
+struct CorrelationGuard(Option<String>);
+
+impl Drop for CorrelationGuard {
+    fn drop(&mut self) {
+        CORRELATION_ID.with(|id| *id.borrow_mut() = self.0.take());
+    }
+}
+
+/// Run `f`, making client calls on the current thread, with this opaque correlation id
+/// attached to them (for instance the deposit outpoint of the vault they are for). It
+/// is not sent to the peer but is part of the logs, it can be queried by the
+/// [MetricsSink](crate::metrics::MetricsSink) with [correlation_id], and an error
+/// returned by `f` is wrapped into an [Error::Correlated].
+pub fn with_correlation_id<T, F>(correlation_id: &str, f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error>,
+{
+    let prev = CORRELATION_ID.with(|id| id.replace(Some(correlation_id.to_string())));
+    let _guard = CorrelationGuard(prev);
+    f().map_err(|error| match error {
+        Error::Correlated { .. } => error,
+        error => Error::Correlated {
+            correlation_id: correlation_id.to_string(),
+            error: Box::new(error),
+        },
+    })
+}
+
+/// The correlation id attached to the client calls made on the current thread, if any
+pub fn correlation_id() -> Option<String> {
+    CORRELATION_ID.with(|id| id.borrow().clone())
+}
+
+/// How a client retries failed requests: how many times, how long to wait for between
+/// attempts, and what errors are worth retrying.
+///
+/// By default, requests are not retried. Clients retry a request by sending it again
+/// with the same id, allowing the server to recognize it (see [crate::server::Dedup]).
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Backoff,
+    retryable: fn(&Error) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: Backoff::None,
+            retryable: is_transient,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy not retrying requests
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum number of times a request is sent, including the first attempt
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = std::cmp::max(max_attempts, 1);
+        self
+    }
+
+    /// Set the delay between two attempts
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Set which errors are worth retrying. By default, only timeouts are (see
+    /// [is_transient]).
+    pub fn retry_if(mut self, retryable: fn(&Error) -> bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    /// Call `f` until it succeeds, it fails with an error not worth retrying, or the
+    /// maximum number of attempts is reached.
+    pub fn run<T, F>(&self, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Result<T, Error>,
+    {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match f() {
+                Err(e) if attempts < self.max_attempts && (self.retryable)(&e) => {
+                    let delay = self.backoff.delay(attempts);
+                    log_debug!(
+                        "Attempt {}/{} failed with '{}', retrying in {:?}",
+                        attempts,
+                        self.max_attempts,
+                        e,
+                        delay
+                    );
+                    thread::sleep(delay);
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+/// How [connect_with_retries] retries to establish a connection: how many times, how
+/// long to wait for between attempts and how long to try for at most.
+///
+/// By default, up to 5 attempts are made with an exponential backoff from 1 second to
+/// 30 seconds, each of them timing out after 20 seconds, without an overall deadline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryConfig {
+    max_attempts: u32,
+    backoff: Backoff,
+    attempt_timeout: Duration,
+    deadline: Option<Duration>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            backoff: Backoff::Exponential {
+                initial: Duration::from_secs(1),
+                max: Duration::from_secs(30),
+            },
+            attempt_timeout: Duration::from_secs(20),
+            deadline: None,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// The default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum number of connection attempts, including the first one
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = std::cmp::max(max_attempts, 1);
+        self
+    }
+
+    /// Set the delay between two attempts
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Set how long a single attempt, connection and handshake, may take. This is also
+    /// the read timeout of the established connection.
+    pub fn attempt_timeout(mut self, attempt_timeout: Duration) -> Self {
+        self.attempt_timeout = attempt_timeout;
+        self
+    }
+
+    /// Give up once this duration elapsed since the first attempt, whatever the number
+    /// of attempts left. An attempt is not started if its backoff would end past the
+    /// deadline, and the last one is cut short to end by it.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+}
+
+/// Connect to the server at this address and enact the Noise handshake with it,
+/// retrying failed attempts according to this [RetryConfig]. Returns the error of the
+/// last attempt if none succeeded.
+pub fn connect_with_retries(
+    addr: SocketAddr,
+    my_noise_privkey: &SecretKey,
+    their_noise_pubkey: &PublicKey,
+    config: &RetryConfig,
+) -> Result<KKTransport, Error> {
+    let start = Instant::now();
+    let remaining = || config.deadline.map(|d| d.saturating_sub(start.elapsed()));
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+        let timeout = match remaining() {
+            Some(remaining) => std::cmp::min(remaining, config.attempt_timeout),
+            None => config.attempt_timeout,
+        };
+        let res = TcpStream::connect_timeout(&addr, timeout)
+            .and_then(|stream| stream.set_read_timeout(Some(timeout)).map(|_| stream))
+            .map_err(Error::from)
+            .and_then(|stream| {
+                KKTransport::connect_stream(stream, my_noise_privkey, their_noise_pubkey)
+            });
+
+        match res {
+            Ok(mut transport) => {
+                transport.set_read_timeout(Some(config.attempt_timeout))?;
+                return Ok(transport);
+            }
+            Err(e) => {
+                let delay = config.backoff.delay(attempts);
+                let out_of_time = remaining()
+                    .map(|remaining| remaining <= delay)
+                    .unwrap_or(false);
+                if attempts >= config.max_attempts || out_of_time {
+                    return Err(e);
+                }
+                log_debug!(
+                    "Connection attempt {}/{} to '{}' failed with '{}', retrying in {:?}",
+                    attempts,
+                    config.max_attempts,
+                    addr,
+                    e,
+                    delay
+                );
+                thread::sleep(delay);
+            }
+        }
+    }
+}
+
+/// The upper bounds of the buckets of a [LatencyHistogram]. Slower requests are
+/// counted in a last, unbounded, bucket.
+pub const LATENCY_BUCKETS: [Duration; 12] = [
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_millis(2_500),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+    Duration::from_secs(30),
+];
+
+/// A histogram of the round-trip latency of requests
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LatencyHistogram {
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    count: u64,
+    sum: Duration,
+}
+
+impl LatencyHistogram {
+    /// Count a request answered in `latency`
+    pub fn record(&mut self, latency: Duration) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += latency;
+    }
+
+    /// The number of requests counted
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The total latency of the requests counted
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// The average latency of the requests counted, if any
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.sum / self.count as u32)
+    }
+
+    /// The number of requests in each bucket, along with its upper bound (`None` for
+    /// the last, unbounded, one). The counts are not cumulative.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        LATENCY_BUCKETS
+            .iter()
+            .map(|bound| Some(*bound))
+            .chain(std::iter::once(None))
+            .zip(self.buckets.iter().copied())
+    }
+
+    /// An upper bound of the latency of this proportion (between 0 and 1) of the
+    /// requests, or `None` if it would be unbounded or no request was counted.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = (q.clamp(0.0, 1.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bound, count) in self.buckets() {
+            seen += count;
+            if seen >= rank {
+                return bound;
+            }
+        }
+        None
+    }
+}
+
+/// The latency histograms of the requests sent by a client, per method
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Latencies(BTreeMap<String, LatencyHistogram>);
+
+impl Latencies {
+    /// The histogram for requests of this method, if any was sent
+    pub fn get(&self, method: &str) -> Option<&LatencyHistogram> {
+        self.0.get(method)
+    }
+
+    /// The histograms of all the methods requests were sent for
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &LatencyHistogram)> {
+        self.0.iter().map(|(method, hist)| (method.as_str(), hist))
+    }
+
+    // Count a request for this method answered in `latency`
+    fn record(&mut self, method: &str, latency: Duration) {
+        match self.0.get_mut(method) {
+            Some(hist) => hist.record(latency),
+            None => {
+                let mut hist = LatencyHistogram::default();
+                hist.record(latency);
+                self.0.insert(method.to_string(), hist);
+            }
+        }
+    }
+}
+
+// Send a request according to this policy, recording the latency of each attempt. It
+// is recorded in the journal beforehand, and marked as answered once it was.
+fn send_req<T: serde::de::DeserializeOwned>(
+    transport: &mut KKTransport,
+    req: &Request,
+    journal: Option<&dyn Journal>,
+    timeouts: &MethodTimeouts,
+    retry_policy: &RetryPolicy,
+    latencies: &mut Latencies,
+) -> Result<T, Error> {
+    if let Some(journal) = journal {
+        journal
+            .record(&JournalEntry::new(req)?)
+            .map_err(Error::Journal)?;
+    }
+
+    let res = timeouts.run(transport, req.method(), |transport| {
+        retry_policy.run(|| {
+            let start = Instant::now();
+            let res = transport.send_req(req);
+            latencies.record(req.method(), start.elapsed());
+            res
+        })
+    });
+
+    if let Some(journal) = journal.filter(|_| journal::is_answered(&res)) {
+        // The request would at worst be sent again, so don't lose the response
+        if let Err(e) = journal.mark_answered(req.id()) {
+            log_warn!("Error marking request '{}' as answered: '{}'", req.id(), e);
+        }
+    }
+    res
+}
+
+// The response to a get_sigs with a tag
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum SigsIfChanged {
+    // The echoed tag tells us nothing more
+    NotModified(#[allow(dead_code)] NotModified),
+    Sigs(Sigs),
+}
+
+/// A cache of the complete signature sets returned by the coordinator, per txid.
+///
+/// A set is complete once it has the number of signatures the transaction needs, it
+/// won't change anymore unless new signatures are pushed for this transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigsCache {
+    complete_len: usize,
+    sigs: BTreeMap<Txid, SigSet>,
+    hits: u64,
+}
+
+impl SigsCache {
+    /// A cache of the sets having at least `complete_len` signatures
+    pub fn new(complete_len: usize) -> Self {
+        Self {
+            complete_len,
+            sigs: BTreeMap::new(),
+            hits: 0,
+        }
+    }
+
+    /// The complete signature set for this transaction, if it is cached
+    pub fn get(&mut self, txid: &Txid) -> Option<&SigSet> {
+        let sigs = self.sigs.get(txid);
+        if sigs.is_some() {
+            self.hits += 1;
+        }
+        sigs
+    }
+
+    /// Cache this signature set if it is complete
+    pub fn insert(&mut self, txid: Txid, sigs: &SigSet) {
+        if sigs.len() >= self.complete_len {
+            self.sigs.insert(txid, sigs.clone());
+        }
+    }
+
+    /// Forget the signature set for this transaction
+    pub fn invalidate(&mut self, txid: &Txid) {
+        self.sigs.remove(txid);
+    }
+
+    /// Forget all the signature sets
+    pub fn clear(&mut self) {
+        self.sigs.clear();
+    }
+
+    /// The number of signature sets cached
+    pub fn len(&self) -> usize {
+        self.sigs.len()
+    }
+
+    /// Whether no signature set is cached
+    pub fn is_empty(&self) -> bool {
+        self.sigs.is_empty()
+    }
+
+    /// The number of queries served from the cache so far
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+}
+
+/// A client to the coordinator, to share signatures and Spend transactions.
+///
+/// Requests are retried according to its [RetryPolicy], by default they are not. The
+/// signatures it gets may be cached (see [SigsCache]), by default they are not.
+#[derive(Debug)]
+pub struct CoordinatorClient {
+    transport: KKTransport,
+    journal: Option<Arc<dyn Journal>>,
+    timeouts: MethodTimeouts,
+    retry_policy: RetryPolicy,
+    latencies: Latencies,
+    sigs_cache: Option<SigsCache>,
+    clock_offset: Option<i64>,
+}
+
+impl CoordinatorClient {
+    /// Create a client using an already established connection to the coordinator
+    pub fn new(transport: KKTransport) -> Self {
+        Self {
+            transport,
+            journal: None,
+            timeouts: MethodTimeouts::default(),
+            retry_policy: RetryPolicy::default(),
+            latencies: Latencies::default(),
+            sigs_cache: None,
+            clock_offset: None,
+        }
+    }
+
+    /// Connect to the coordinator at this address
+    pub fn connect(
+        addr: SocketAddr,
+        my_noise_privkey: &SecretKey,
+        coordinator_noise_pubkey: &PublicKey,
+    ) -> Result<Self, Error> {
+        KKTransport::connect(addr, my_noise_privkey, coordinator_noise_pubkey).map(Self::new)
+    }
+
+    /// Set how failed requests are retried
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Set the time given to the coordinator to answer the requests of each method.
+    /// Otherwise the read timeout of the connection applies.
+    pub fn with_method_timeouts(mut self, timeouts: MethodTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Record the requests in this journal before sending them, to know after a crash
+    /// which ones the coordinator may not have received (see [journal])
+    pub fn with_journal(mut self, journal: Arc<dyn Journal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Serve the repeated queries for the complete signature sets (having at least
+    /// `complete_len` signatures) locally. A set is invalidated when a signature for
+    /// its transaction is shared through this client.
+    pub fn with_sigs_cache(mut self, complete_len: usize) -> Self {
+        self.sigs_cache = Some(SigsCache::new(complete_len));
+        self
+    }
+
+    /// The cache of the signature sets, if any
+    pub fn sigs_cache(&mut self) -> Option<&mut SigsCache> {
+        self.sigs_cache.as_mut()
+    }
+
+    fn send_req<T: serde::de::DeserializeOwned>(&mut self, req: &Request) -> Result<T, Error> {
+        send_req(
+            &mut self.transport,
+            req,
+            self.journal.as_deref(),
+            &self.timeouts,
+            &self.retry_policy,
+            &mut self.latencies,
+        )
+    }
+
+    // Send a request for this method, which must be acknowledged
+    pub(crate) fn send_acked_req<T>(
+        &mut self,
+        req: &Request,
+        method: &'static str,
+    ) -> Result<(), Error>
+    where
+        T: serde::de::DeserializeOwned + Acknowledgement,
+    {
+        let resp: T = self.send_req(req)?;
+        if !resp.is_ack() {
+            return Err(Error::NotAcknowledged(method));
+        }
+
+        Ok(())
+    }
+
+    /// Share a signature for a transaction with the coordinator
+    pub fn send_sig(&mut self, sig: Sig) -> Result<(), Error> {
+        self.resend_sig(sig).map(|_| ())
+    }
+
+    /// Share again a signature which may already have been sent, for instance after
+    /// a timeout. Returns whether the coordinator already had it, which older
+    /// coordinators don't tell (it is then always `false`).
+    pub fn resend_sig(&mut self, sig: Sig) -> Result<bool, Error> {
+        if let Some(cache) = self.sigs_cache.as_mut() {
+            cache.invalidate(&sig.id);
+        }
+        let resp: SigResult = self.send_req(&self.transport.request(sig))?;
+        if !resp.ack {
+            return Err(Error::NotAcknowledged(method::SIG));
+        }
+
+        Ok(resp.already_known)
+    }
+
+    /// Get the signatures the coordinator has for this transaction, only if they
+    /// differ from the ones with this tag (see [Sigs::tag]). Returns `None` if they
+    /// didn't change.
+    pub fn get_sigs_if_changed(
+        &mut self,
+        txid: Txid,
+        tag: SigsTag,
+    ) -> Result<Option<SigSet>, Error> {
+        let resp: SigsIfChanged = self.send_req(&self.transport.request(GetSigs {
+            id: txid,
+            if_none_match: Some(tag),
+        }))?;
+        match resp {
+            SigsIfChanged::NotModified(_) => Ok(None),
+            SigsIfChanged::Sigs(sigs) => {
+                if let Some(cache) = self.sigs_cache.as_mut() {
+                    cache.insert(txid, &sigs.signatures);
+                }
+                Ok(Some(sigs.signatures))
+            }
+        }
+    }
+
+    /// Share a Schnorr signature for a transaction with the coordinator
+    pub fn send_schnorr_sig(&mut self, sig: SchnorrSig) -> Result<(), Error> {
+        let resp: SigResult = self.send_req(&self.transport.request(sig))?;
+        if !resp.ack {
+            return Err(Error::NotAcknowledged(method::SIG));
+        }
+
+        Ok(())
+    }
+
+    /// Get all the signatures the coordinator has for this transaction
+    pub fn get_sigs(&mut self, txid: Txid) -> Result<SigSet, Error> {
+        if let Some(sigs) = self.sigs_cache.as_mut().and_then(|cache| cache.get(&txid)) {
+            return Ok(sigs.clone());
+        }
+
+        let resp: Sigs = self.send_req(&self.transport.request(GetSigs {
+            id: txid,
+            if_none_match: None,
+        }))?;
+        if let Some(cache) = self.sigs_cache.as_mut() {
+            cache.insert(txid, &resp.signatures);
+        }
+        Ok(resp.signatures)
+    }
+
+    /// Get the signatures the coordinator has for all these revocation transactions of
+    /// a vault, in a single request. Checks it answered for each of them.
+    pub fn get_revocation_sigs(
+        &mut self,
+        txids: BTreeMap<RevocationTxType, Txid>,
+    ) -> Result<RevocationSigs, Error> {
+        let resp: RevocationSigs = self.send_req(&self.transport.request(GetRevocationSigs {
+            txids: txids.clone(),
+        }))?;
+        for (tx_type, txid) in txids {
+            let (got, sigs) = resp
+                .signatures
+                .get(&tx_type)
+                .and_then(|sigs| sigs.iter().next())
+                .ok_or(MessageError::MissingField("signatures"))?;
+            if *got != txid {
+                return Err(MessageError::TxidMismatch {
+                    expected: txid,
+                    got: *got,
+                }
+                .into());
+            }
+            if let Some(cache) = self.sigs_cache.as_mut() {
+                cache.insert(txid, sigs);
+            }
+        }
+
+        Ok(resp)
+    }
+
+    /// The number of signatures the coordinator has for this transaction, without
+    /// fetching them. Older coordinators don't understand it.
+    pub fn count_sigs(&mut self, txid: Txid) -> Result<u32, Error> {
+        let resp: SigsCount = self.send_req(&self.transport.request(HasSigs { id: txid }))?;
+        Ok(resp.count)
+    }
+
+    /// Get all the Schnorr signatures the coordinator has for this transaction
+    pub fn get_schnorr_sigs(
+        &mut self,
+        txid: Txid,
+    ) -> Result<BTreeMap<schnorrsig::PublicKey, schnorrsig::Signature>, Error> {
+        let resp: SchnorrSigs = self.send_req(&self.transport.request(GetSigs {
+            id: txid,
+            if_none_match: None,
+        }))?;
+        Ok(resp.signatures)
+    }
+
+    /// Store a Spend transaction on the coordinator
+    pub fn set_spend_tx(&mut self, msg: SetSpendTx) -> Result<(), Error> {
+        let resp: SetSpendResult = self.send_req(&self.transport.request(msg))?;
+        if !resp.ack {
+            return Err(Error::NotAcknowledged(method::SET_SPEND_TX));
+        }
+
+        Ok(())
+    }
+
+    /// Store a Spend transaction on the coordinator, uploading it in chunks of at most
+    /// `chunk_size` bytes for it to not be limited by the maximum size of a message.
+    pub fn set_spend_tx_chunked(
+        &mut self,
+        msg: &SetSpendTx,
+        chunk_size: usize,
+    ) -> Result<(), Error> {
+        let (begin, chunks) = msg.to_chunks(chunk_size);
+        let UploadStarted { upload_id } = self.send_req(&self.transport.request(begin))?;
+        for (index, data) in chunks.into_iter().enumerate() {
+            let chunk = SetSpendTxChunk {
+                upload_id,
+                index: index as u32,
+                data,
+            };
+            self.send_acked_req::<SigResult>(
+                &self.transport.request(chunk),
+                method::SET_SPEND_TX_CHUNK,
+            )?;
+        }
+        self.send_acked_req::<SetSpendResult>(
+            &self.transport.request(SetSpendTxCommit { upload_id }),
+            method::SET_SPEND_TX_COMMIT,
+        )
+    }
+
+    /// Get the Spend transaction the coordinator has for this vault, if any
+    pub fn get_spend_tx(&mut self, deposit_outpoint: OutPoint) -> Result<SpendTx, Error> {
+        self.send_req(&self.transport.request(GetSpendTx { deposit_outpoint }))
+    }
+
+    /// Get the Spend transaction the coordinator has for this vault, if any, downloading
+    /// it in chunks for it to not be limited by the maximum size of a message. It is
+    /// checked against the digest sent by the coordinator.
+    pub fn get_spend_tx_chunked(
+        &mut self,
+        deposit_outpoint: OutPoint,
+    ) -> Result<Option<Transaction>, Error> {
+        let mut chunks = SpendTxChunks::new();
+        loop {
+            let get_chunk = GetSpendTxChunk {
+                deposit_outpoint,
+                chunk_index: chunks.next_index(),
+            };
+            let chunk: SpendTxChunk = self.send_req(&self.transport.request(get_chunk))?;
+            if let Some(transaction) = chunks.push(chunk)? {
+                return Ok(transaction);
+            }
+        }
+    }
+
+    /// Measure the offset of the coordinator's clock to ours, in seconds (positive if
+    /// it is ahead), from its time and the round trip of the request. It is stored, see
+    /// [clock_offset](Self::clock_offset), for instance to set the expiry of the
+    /// messages by the coordinator's clock (see
+    /// [ClockPolicy::with_offset](crate::clock::ClockPolicy::with_offset)).
+    pub fn sync_time(&mut self) -> Result<i64, Error> {
+        let sent_at = SystemTime::now();
+        let resp: ServerTime = self.send_req(&self.transport.request(GetTime {}))?;
+        let offset = clock_offset(sent_at, SystemTime::now(), resp.time);
+        self.clock_offset = Some(offset);
+
+        Ok(offset)
+    }
+
+    /// The offset of the coordinator's clock to ours as last measured by
+    /// [sync_time](Self::sync_time), if ever
+    pub fn clock_offset(&self) -> Option<i64> {
+        self.clock_offset
+    }
+
+    /// The latency of the requests sent to the coordinator so far, per method
+    pub fn latencies(&self) -> &Latencies {
+        &self.latencies
+    }
+
+    /// Get the underlying connection to the coordinator
+    pub fn transport(&mut self) -> &mut KKTransport {
+        &mut self.transport
+    }
+
+    /// Get back the underlying connection to the coordinator
+    pub fn into_transport(self) -> KKTransport {
+        self.transport
+    }
+}
+
+/// Whether a request failing with this error may succeed if sent to another coordinator:
+/// the connection to this one failed or timed out.
+pub fn is_connection_failure(error: &Error) -> bool {
+    matches!(
+        error.inner(),
+        Error::Timeout(_) | Error::Transport(_) | Error::Disconnected(_) | Error::Noise(_)
+    )
+}
+
+/// A client to several coordinators, failing over to the next one when the connection
+/// to the active one fails (see [is_connection_failure]).
+///
+/// Connections are established lazily, in the order the coordinators were given. Their
+/// requests are retried according to its [RetryPolicy] before failing over.
+#[derive(Debug)]
+pub struct FailoverCoordinatorClient {
+    coordinators: Vec<(SocketAddr, PublicKey)>,
+    clients: Vec<Option<CoordinatorClient>>,
+    my_noise_privkey: SecretKey,
+    active: usize,
+    retry_policy: RetryPolicy,
+    timeouts: MethodTimeouts,
+    journal: Option<Arc<dyn Journal>>,
+}
+
+impl FailoverCoordinatorClient {
+    /// Create a client to these coordinators, identified by their address and Noise
+    /// public key. The first one is the active one.
+    ///
+    /// # Panics
+    /// - If no coordinator is given
+    pub fn new(coordinators: Vec<(SocketAddr, PublicKey)>, my_noise_privkey: SecretKey) -> Self {
+        assert!(!coordinators.is_empty(), "No coordinator to connect to");
+        let clients = coordinators.iter().map(|_| None).collect();
+        Self {
+            coordinators,
+            clients,
+            my_noise_privkey,
+            active: 0,
+            retry_policy: RetryPolicy::default(),
+            timeouts: MethodTimeouts::default(),
+            journal: None,
+        }
+    }
+
+    /// Set how failed requests are retried on a coordinator before failing over
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Set the time given to the coordinators to answer the requests of each method
+    pub fn with_method_timeouts(mut self, timeouts: MethodTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Record the requests sent to any of the coordinators in this journal (see
+    /// [CoordinatorClient::with_journal])
+    pub fn with_journal(mut self, journal: Arc<dyn Journal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// The Noise public key of the coordinator requests are currently sent to, if any
+    pub fn active(&self) -> Option<PublicKey> {
+        self.coordinators
+            .get(self.active)
+            .map(|(_, pubkey)| *pubkey)
+    }
+
+    // The client to the coordinator at this index, connecting to it if needed
+    fn client(&mut self, index: usize) -> Result<&mut CoordinatorClient, Error> {
+        if self.clients[index].is_none() {
+            let (addr, pubkey) = &self.coordinators[index];
+            let mut client = CoordinatorClient::connect(*addr, &self.my_noise_privkey, pubkey)?
+                .with_retry_policy(self.retry_policy.clone())
+                .with_method_timeouts(self.timeouts.clone());
+            client.journal = self.journal.clone();
+            self.clients[index] = Some(client);
+        }
+        Ok(self.clients[index].as_mut().expect("Just connected"))
+    }
+
+    // Call `f` on the coordinator at this index, dropping its connection if it failed
+    fn call<T, F>(&mut self, index: usize, f: &mut F) -> Result<T, Error>
+    where
+        F: FnMut(&mut CoordinatorClient) -> Result<T, Error>,
+    {
+        let res = self.client(index).and_then(f);
+        if matches!(&res, Err(e) if is_connection_failure(e)) {
+            self.clients[index] = None;
+        }
+        res
+    }
+
+    // Call `f` on the active coordinator, failing over to the next ones until it doesn't
+    // fail because of the connection. The error of the last one tried is returned if
+    // they all did.
+    fn run<T, F>(&mut self, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut(&mut CoordinatorClient) -> Result<T, Error>,
+    {
+        let mut last_error = None;
+        for _ in 0..self.coordinators.len() {
+            match self.call(self.active, &mut f) {
+                Err(e) if is_connection_failure(&e) => {
+                    log_debug!(
+                        "Coordinator {} failed with '{}', failing over",
+                        self.coordinators[self.active].0,
+                        e
+                    );
+                    self.active = (self.active + 1) % self.coordinators.len();
+                    last_error = Some(e);
+                }
+                res => return res,
+            }
+        }
+
+        Err(last_error.expect("There is at least one coordinator"))
+    }
+
+    // Call `f` on each of the coordinators, returning their outcome in order
+    fn run_all<T, F>(&mut self, mut f: F) -> Vec<(PublicKey, Result<T, Error>)>
+    where
+        F: FnMut(&mut CoordinatorClient) -> Result<T, Error>,
+    {
+        (0..self.coordinators.len())
+            .map(|index| (self.coordinators[index].1, self.call(index, &mut f)))
+            .collect()
+    }
+
+    /// Share a signature for a transaction with the active coordinator
+    pub fn send_sig(&mut self, sig: Sig) -> Result<(), Error> {
+        self.run(|client| client.send_sig(sig.clone()))
+    }
+
+    /// Share a signature for a transaction with all the coordinators
+    pub fn send_sig_to_all(&mut self, sig: Sig) -> Vec<(PublicKey, Result<(), Error>)> {
+        self.run_all(|client| client.send_sig(sig.clone()))
+    }
+
+    /// Share a Schnorr signature for a transaction with the active coordinator
+    pub fn send_schnorr_sig(&mut self, sig: SchnorrSig) -> Result<(), Error> {
+        self.run(|client| client.send_schnorr_sig(sig.clone()))
+    }
+
+    /// Share a Schnorr signature for a transaction with all the coordinators
+    pub fn send_schnorr_sig_to_all(
+        &mut self,
+        sig: SchnorrSig,
+    ) -> Vec<(PublicKey, Result<(), Error>)> {
+        self.run_all(|client| client.send_schnorr_sig(sig.clone()))
+    }
+
+    /// Get all the signatures the active coordinator has for this transaction
+    pub fn get_sigs(&mut self, txid: Txid) -> Result<SigSet, Error> {
+        self.run(|client| client.get_sigs(txid))
+    }
+
+    /// Get the signatures the active coordinator has for all these revocation
+    /// transactions of a vault, in a single request
+    pub fn get_revocation_sigs(
+        &mut self,
+        txids: BTreeMap<RevocationTxType, Txid>,
+    ) -> Result<RevocationSigs, Error> {
+        self.run(|client| client.get_revocation_sigs(txids.clone()))
+    }
+
+    /// The number of signatures the active coordinator has for this transaction
+    pub fn count_sigs(&mut self, txid: Txid) -> Result<u32, Error> {
+        self.run(|client| client.count_sigs(txid))
+    }
+
+    /// Get all the Schnorr signatures the active coordinator has for this transaction
+    pub fn get_schnorr_sigs(
+        &mut self,
+        txid: Txid,
+    ) -> Result<BTreeMap<schnorrsig::PublicKey, schnorrsig::Signature>, Error> {
+        self.run(|client| client.get_schnorr_sigs(txid))
+    }
+
+    /// Store a Spend transaction on the active coordinator
+    pub fn set_spend_tx(&mut self, msg: SetSpendTx) -> Result<(), Error> {
+        self.run(|client| client.set_spend_tx(msg.clone()))
+    }
+
+    /// Get the Spend transaction the active coordinator has for this vault, if any
+    pub fn get_spend_tx(&mut self, deposit_outpoint: OutPoint) -> Result<SpendTx, Error> {
+        self.run(|client| client.get_spend_tx(deposit_outpoint))
+    }
+}
+
+/// A client to a watchtower, to share the revocation signatures for a vault.
+///
+/// Each request is given `timeout` to be answered, unless its method has a timeout of
+/// its own, and is retried according to its [RetryPolicy] if it was not.
+#[derive(Debug)]
+pub struct WatchtowerClient {
+    transport: KKTransport,
+    timeout: Duration,
+    timeouts: MethodTimeouts,
+    journal: Option<Arc<dyn Journal>>,
+    retry_policy: RetryPolicy,
+    latencies: Latencies,
+}
+
+impl WatchtowerClient {
+    /// Create a client using an already established connection to the watchtower.
+    /// Requests are given 20 seconds to be answered and are not retried by default.
+    pub fn new(transport: KKTransport) -> Self {
+        Self {
+            transport,
+            timeout: Duration::from_secs(20),
+            timeouts: MethodTimeouts::default(),
+            journal: None,
+            retry_policy: RetryPolicy::default(),
+            latencies: Latencies::default(),
+        }
+    }
+
+    /// Connect to the watchtower at this address
+    pub fn connect(
+        addr: SocketAddr,
+        my_noise_privkey: &SecretKey,
+        watchtower_noise_pubkey: &PublicKey,
+    ) -> Result<Self, Error> {
+        KKTransport::connect(addr, my_noise_privkey, watchtower_noise_pubkey).map(Self::new)
+    }
+
+    /// Set the time given to the watchtower to answer each request
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the time given to the watchtower to answer the requests of some methods,
+    /// instead of the one of [WatchtowerClient::with_timeout]
+    pub fn with_method_timeouts(mut self, timeouts: MethodTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Record the requests in this journal before sending them, to know after a crash
+    /// which revocation signatures the watchtower may not have received (see [journal])
+    pub fn with_journal(mut self, journal: Arc<dyn Journal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Set the number of times a request that timed out is sent again, immediately.
+    /// This is a shorthand for a [RetryPolicy] with `retries + 1` attempts.
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        let max_attempts = std::convert::TryFrom::try_from(retries.saturating_add(1));
+        self.retry_policy = RetryPolicy::new().max_attempts(max_attempts.unwrap_or(u32::MAX));
+        self
+    }
+
+    /// Set how failed requests are retried
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    fn send_req<T: serde::de::DeserializeOwned>(&mut self, req: &Request) -> Result<T, Error> {
+        self.transport.set_read_timeout(Some(self.timeout))?;
+        send_req(
+            &mut self.transport,
+            req,
+            self.journal.as_deref(),
+            &self.timeouts,
+            &self.retry_policy,
+            &mut self.latencies,
+        )
+    }
+
+    /// Share all the signatures for a revocation transaction of a vault with the
+    /// watchtower, and check it acknowledged them.
+    pub fn share_revocation_sigs(&mut self, sig: watchtower::Sig) -> Result<(), Error> {
+        let txid = sig.txid;
+        let resp: watchtower::SigResult = self.send_req(&self.transport.request(sig))?;
+        check_revocation_sigs_ack(txid, &resp)
+    }
+
+    /// Get the spending policy the watchtower enforces
+    pub fn get_spend_policy(&mut self) -> Result<watchtower::SpendPolicy, Error> {
+        self.send_req(&self.transport.request(watchtower::GetSpendPolicy {}))
+    }
+
+    /// Get back the revocation signatures the watchtower holds for this vault, one
+    /// [watchtower::Sig] per revocation transaction, for instance to rebuild the
+    /// presigned transactions after restoring the wallet from seed. None if the
+    /// watchtower doesn't guard the vault.
+    pub fn recover_revocation_sigs(
+        &mut self,
+        deposit_outpoint: OutPoint,
+    ) -> Result<Vec<watchtower::Sig>, Error> {
+        let resp: watchtower::RecoveredSigs = self.send_req(
+            &self
+                .transport
+                .request(watchtower::RecoverSigs { deposit_outpoint }),
+        )?;
+        if let Some(sig) = resp
+            .sigs
+            .iter()
+            .find(|sig| sig.deposit_outpoint != deposit_outpoint)
+        {
+            return Err(MessageError::DepositOutpointMismatch {
+                expected: deposit_outpoint,
+                got: sig.deposit_outpoint,
+            }
+            .into());
+        }
+
+        Ok(resp.sigs)
+    }
+
+    /// Submit a Spend transaction to the watchtower before unvaulting these vaults,
+    /// and get its approval or rejection. To ask all the watchtowers at once, use
+    /// [broadcast] and [BroadcastResponses::all_acked].
+    pub fn approve_spend(
+        &mut self,
+        deposit_outpoints: Vec<OutPoint>,
+        spend_tx: Transaction,
+    ) -> Result<watchtower::SpendApproval, Error> {
+        let txid = spend_tx.txid();
+        let resp: watchtower::SpendApproval =
+            self.send_req(&self.transport.request(watchtower::ApproveSpend {
+                deposit_outpoints,
+                spend_tx,
+            }))?;
+        if resp.txid != txid {
+            return Err(MessageError::TxidMismatch {
+                expected: txid,
+                got: resp.txid,
+            }
+            .into());
+        }
+
+        Ok(resp)
+    }
+
+    /// The latency of the requests sent to the watchtower so far, per method
+    pub fn latencies(&self) -> &Latencies {
+        &self.latencies
+    }
+
+    /// Get the underlying connection to the watchtower
+    pub fn transport(&mut self) -> &mut KKTransport {
+        &mut self.transport
+    }
+
+    /// Get back the underlying connection to the watchtower
+    pub fn into_transport(self) -> KKTransport {
+        self.transport
+    }
+}
+
+/// A client to a cosigning server, to get its signatures for a Spend transaction.
+///
+/// Requests are retried according to its [RetryPolicy], by default they are not.
+#[cfg(feature = "revault_tx")]
+#[derive(Debug)]
+pub struct CosignerClient {
+    transport: KKTransport,
+    journal: Option<Arc<dyn Journal>>,
+    timeouts: MethodTimeouts,
+    retry_policy: RetryPolicy,
+    latencies: Latencies,
+}
+
+#[cfg(feature = "revault_tx")]
+impl CosignerClient {
+    /// Create a client using an already established connection to the cosigning server
+    pub fn new(transport: KKTransport) -> Self {
+        Self {
+            transport,
+            journal: None,
+            timeouts: MethodTimeouts::default(),
+            retry_policy: RetryPolicy::default(),
+            latencies: Latencies::default(),
+        }
+    }
+
+    /// Connect to the cosigning server at this address
+    pub fn connect(
+        addr: SocketAddr,
+        my_noise_privkey: &SecretKey,
+        cosigner_noise_pubkey: &PublicKey,
+    ) -> Result<Self, Error> {
+        KKTransport::connect(addr, my_noise_privkey, cosigner_noise_pubkey).map(Self::new)
+    }
+
+    /// Set how failed requests are retried
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Set the time given to the cosigning server to answer the requests of each
+    /// method, for instance more for it to sign than for it to give its key. Otherwise
+    /// the read timeout of the connection applies.
+    pub fn with_method_timeouts(mut self, timeouts: MethodTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Record the requests in this journal before sending them, to know after a crash
+    /// which ones the cosigning server may not have received (see [journal])
+    pub fn with_journal(mut self, journal: Arc<dyn Journal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Ask the cosigning server to sign this Spend transaction, and get it back with
+    /// its signatures. A cosigning server refuses to sign a Spend transaction if it
+    /// already signed another one spending the same vaults, which is reported as
+    /// [Error::NotAcknowledged].
+    pub fn sign(&mut self, spend_tx: SpendTransaction) -> Result<SpendTransaction, Error> {
+        self.send_sign_req(cosigner::SignRequest::new(spend_tx))
+    }
+
+    /// Like [CosignerClient::sign], but the cosigning server must not sign the Spend
+    /// transaction after this time, in seconds since the UNIX epoch.
+    pub fn sign_before(
+        &mut self,
+        spend_tx: SpendTransaction,
+        expires_at: u64,
+    ) -> Result<SpendTransaction, Error> {
+        self.send_sign_req(cosigner::SignRequest::new(spend_tx).with_expiry(expires_at))
+    }
+
+    fn send_sign_req(&mut self, req: cosigner::SignRequest) -> Result<SpendTransaction, Error> {
+        let txid = req.tx.txid();
+        let req = self.transport.request(req);
+        let resp: cosigner::SignResult = send_req(
+            &mut self.transport,
+            &req,
+            self.journal.as_deref(),
+            &self.timeouts,
+            &self.retry_policy,
+            &mut self.latencies,
+        )?;
+        check_signed_tx(txid, resp)
+    }
+
+    /// Ask the cosigning server to sign these Spend transactions in a single request.
+    /// Each transaction is signed or refused on its own: the result is, in the same
+    /// order, the transaction with its signatures or `None` if the cosigning server
+    /// refused to sign it.
+    pub fn sign_batch(
+        &mut self,
+        spend_txs: Vec<SpendTransaction>,
+    ) -> Result<Vec<Option<SpendTransaction>>, Error> {
+        let txids: Vec<Txid> = spend_txs.iter().map(|tx| tx.txid()).collect();
+        let req = self
+            .transport
+            .request(cosigner::BatchSignRequest::new(spend_txs));
+        let resp: cosigner::BatchSignResult = send_req(
+            &mut self.transport,
+            &req,
+            self.journal.as_deref(),
+            &self.timeouts,
+            &self.retry_policy,
+            &mut self.latencies,
+        )?;
+
+        if resp.txs.len() != txids.len() {
+            return Err(MessageError::BatchLengthMismatch {
+                expected: txids.len(),
+                got: resp.txs.len(),
+            }
+            .into());
+        }
+        for (txid, signed_tx) in txids.iter().zip(resp.txs.iter()) {
+            if let Some(signed_tx) = signed_tx {
+                if signed_tx.txid() != *txid {
+                    return Err(MessageError::TxidMismatch {
+                        expected: *txid,
+                        got: signed_tx.txid(),
+                    }
+                    .into());
+                }
+            }
+        }
+
+        Ok(resp.txs)
+    }
+
+    /// Get the key the cosigning server signs with and how, to check it is the one
+    /// configured (see [GetPubkeyResult::is_compatible](cosigner::GetPubkeyResult::is_compatible)).
+    pub fn get_pubkey(&mut self) -> Result<cosigner::GetPubkeyResult, Error> {
+        let req = self.transport.request(cosigner::GetPubkey {});
+        send_req(
+            &mut self.transport,
+            &req,
+            self.journal.as_deref(),
+            &self.timeouts,
+            &self.retry_policy,
+            &mut self.latencies,
+        )
+    }
+
+    /// Check this announcement of a new signing key from the cosigning server is
+    /// signed by the key we currently know it by, and acknowledge it. A cosigning
+    /// server not rotating to this key is reported as [Error::NotAcknowledged].
+    pub fn ack_key_rotation(
+        &mut self,
+        rotation: &cosigner::KeyRotation,
+        current_pubkey: &bitcoin::secp256k1::PublicKey,
+    ) -> Result<(), Error> {
+        rotation.verify(
+            &bitcoin::secp256k1::Secp256k1::verification_only(),
+            current_pubkey,
+        )?;
+
+        let req = self.transport.request(cosigner::AckKeyRotation {
+            new_pubkey: rotation.new_pubkey,
+        });
+        let resp: cosigner::AckKeyRotationResult = send_req(
+            &mut self.transport,
+            &req,
+            self.journal.as_deref(),
+            &self.timeouts,
+            &self.retry_policy,
+            &mut self.latencies,
+        )?;
+        if !resp.ack || resp.new_pubkey != rotation.new_pubkey {
+            return Err(Error::NotAcknowledged(method::ACK_KEY_ROTATION));
+        }
+
+        Ok(())
+    }
+
+    /// The latency of the requests sent to the cosigning server so far, per method
+    pub fn latencies(&self) -> &Latencies {
+        &self.latencies
+    }
+
+    /// Get the underlying connection to the cosigning server
+    pub fn transport(&mut self) -> &mut KKTransport {
+        &mut self.transport
+    }
+
+    /// Get back the underlying connection to the cosigning server
+    pub fn into_transport(self) -> KKTransport {
+        self.transport
+    }
+}
+
+/// How a [CosignerOrchestrator] asks the cosigning servers for their signatures
+#[cfg(feature = "verify")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CosigningPolicy {
+    /// One after the other, stopping at the first that does not sign: the next ones
+    /// don't commit to a Spend transaction that can't be completed anyway.
+    Sequential,
+    /// All at once
+    Parallel,
+}
+
+/// The outcome of a [CosignerOrchestrator::sign]
+#[cfg(feature = "verify")]
+#[derive(Debug)]
+pub struct CosigningResult {
+    /// The Spend transaction, with the signatures of all the cosigning servers that
+    /// signed it
+    pub spend_tx: SpendTransaction,
+    /// The cosigning servers that did not sign it (or whose signatures are invalid),
+    /// by signing key, and why. With a [CosigningPolicy::Sequential], the ones not
+    /// asked are not reported.
+    pub failures: Vec<(bitcoin::secp256k1::PublicKey, Error)>,
+}
+
+#[cfg(feature = "verify")]
+impl CosigningResult {
+    /// Whether all the cosigning servers signed the Spend transaction
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Gets a Spend transaction signed by all the cosigning servers of the deployment.
+///
+/// The signatures returned by each of them are checked against each input of the
+/// Spend transaction we sent, for the key it is configured with, before being merged
+/// into our PSBT. A cosigning server returning anything else than its own valid
+/// signatures is reported as a failure, and nothing it returned is merged.
+#[cfg(feature = "verify")]
+#[derive(Debug)]
+pub struct CosignerOrchestrator {
+    cosigners: Vec<(bitcoin::secp256k1::PublicKey, CosignerClient)>,
+    policy: CosigningPolicy,
+}
+
+#[cfg(feature = "verify")]
+impl CosignerOrchestrator {
+    /// Orchestrate these cosigning servers, each given with the key it signs with
+    pub fn new(
+        cosigners: Vec<(bitcoin::secp256k1::PublicKey, CosignerClient)>,
+        policy: CosigningPolicy,
+    ) -> Self {
+        Self { cosigners, policy }
+    }
+
+    /// Ask all the cosigning servers to sign this Spend transaction, and merge their
+    /// signatures into it.
+    pub fn sign(&mut self, spend_tx: SpendTransaction) -> CosigningResult {
+        let secp = bitcoin::secp256k1::Secp256k1::verification_only();
+        let mut result = CosigningResult {
+            spend_tx,
+            failures: Vec::new(),
+        };
+
+        match self.policy {
+            CosigningPolicy::Sequential => {
+                for (pubkey, client) in self.cosigners.iter_mut() {
+                    let merged = client.sign(result.spend_tx.clone()).and_then(|signed_tx| {
+                        merge_cosigner_sigs(&secp, &result.spend_tx, &signed_tx, pubkey)
+                    });
+                    match merged {
+                        Ok(merged_tx) => result.spend_tx = merged_tx,
+                        Err(e) => {
+                            result.failures.push((*pubkey, e));
+                            break;
+                        }
+                    }
+                }
+            }
+            CosigningPolicy::Parallel => {
+                let spend_tx = &result.spend_tx;
+                let signed_txs: Vec<_> = thread::scope(|s| {
+                    let handles: Vec<_> = self
+                        .cosigners
+                        .iter_mut()
+                        .map(|(pubkey, client)| {
+                            s.spawn(move || (*pubkey, client.sign(spend_tx.clone())))
+                        })
+                        .collect();
+                    handles
+                        .into_iter()
+                        .map(|h| h.join().expect("Cosigning thread panicked"))
+                        .collect()
+                });
+
+                for (pubkey, signed_tx) in signed_txs {
+                    match signed_tx.and_then(|signed_tx| {
+                        merge_cosigner_sigs(&secp, &result.spend_tx, &signed_tx, &pubkey)
+                    }) {
+                        Ok(merged_tx) => result.spend_tx = merged_tx,
+                        Err(e) => result.failures.push((pubkey, e)),
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Get back the clients of the cosigning servers, with the key each signs with
+    pub fn into_cosigners(self) -> Vec<(bitcoin::secp256k1::PublicKey, CosignerClient)> {
+        self.cosigners
+    }
+}
+
+// Check the signature of this cosigning server for each input of our Spend transaction,
+// as returned by it, and add them to a copy of ours.
+#[cfg(feature = "verify")]
+fn merge_cosigner_sigs<C: bitcoin::secp256k1::Verification>(
+    secp: &bitcoin::secp256k1::Secp256k1<C>,
+    spend_tx: &SpendTransaction,
+    signed_tx: &SpendTransaction,
+    pubkey: &bitcoin::secp256k1::PublicKey,
+) -> Result<SpendTransaction, Error> {
+    let bitcoin_pubkey = bitcoin::PublicKey {
+        compressed: true,
+        key: *pubkey,
+    };
+    let mut merged_tx = spend_tx.clone();
+
+    for (input_index, psbtin) in signed_tx.inner_tx().inputs.iter().enumerate() {
+        let (sighash_byte, der_sig) = psbtin
+            .partial_sigs
+            .get(&bitcoin_pubkey)
+            .and_then(|sig| sig.split_last())
+            .ok_or(MessageError::MissingSignature {
+                pubkey: *pubkey,
+                input_index,
+            })?;
+        // It must sign with the signature hash type of our input
+        let sighash_type = spend_tx.inner_tx().inputs[input_index]
+            .sighash_type
+            .unwrap_or(bitcoin::SigHashType::All);
+        if sighash_type.as_u32() != *sighash_byte as u32 {
+            return Err(MessageError::InvalidSignatureFor(*pubkey).into());
+        }
+        let signature = bitcoin::secp256k1::Signature::from_der(der_sig)
+            .map_err(|_| MessageError::InvalidSignatureFor(*pubkey))?;
+        crate::verify::verify_signature(
+            secp,
+            spend_tx,
+            input_index,
+            sighash_type,
+            pubkey,
+            &signature,
+        )?;
+        merged_tx
+            .add_signature(input_index, bitcoin_pubkey, (signature, sighash_type))
+            .map_err(MessageError::InputSatisfaction)?;
+    }
+
+    Ok(merged_tx)
+}
+
+/// A response acknowledging (or not) a request
+pub trait Acknowledgement {
+    /// Whether the request was acknowledged
+    fn is_ack(&self) -> bool;
+}
+
+impl Acknowledgement for watchtower::SigResult {
+    fn is_ack(&self) -> bool {
+        self.ack
+    }
+}
+
+impl Acknowledgement for watchtower::SpendApproval {
+    fn is_ack(&self) -> bool {
+        self.approved
+    }
+}
+
+impl Acknowledgement for SigResult {
+    fn is_ack(&self) -> bool {
+        self.ack
+    }
+}
+
+impl Acknowledgement for SetSpendResult {
+    fn is_ack(&self) -> bool {
+        self.ack
+    }
+}
+
+#[cfg(feature = "revault_tx")]
+impl Acknowledgement for cosigner::SignResult {
+    fn is_ack(&self) -> bool {
+        self.tx.is_some()
+    }
+}
+
+// Check the watchtower acknowledged the revocation signatures for this transaction
+pub(crate) fn check_revocation_sigs_ack(
+    txid: Txid,
+    resp: &watchtower::SigResult,
+) -> Result<(), Error> {
+    if resp.txid != txid {
+        return Err(MessageError::TxidMismatch {
+            expected: txid,
+            got: resp.txid,
+        }
+        .into());
+    }
+    if !resp.ack {
+        return Err(Error::NotAcknowledged(method::SIG));
+    }
+
+    Ok(())
+}
+
+// Get the transaction with this txid the cosigning server signed, if it did
+#[cfg(feature = "revault_tx")]
+pub(crate) fn check_signed_tx(
+    txid: Txid,
+    resp: cosigner::SignResult,
+) -> Result<SpendTransaction, Error> {
+    let signed_tx = resp.tx.ok_or(Error::NotAcknowledged(method::SIGN))?;
+    if signed_tx.txid() != txid {
+        return Err(MessageError::TxidMismatch {
+            expected: txid,
+            got: signed_tx.txid(),
+        }
+        .into());
+    }
+
+    Ok(signed_tx)
+}
+
+/// The responses of each peer to a [broadcast] request
+#[derive(Debug)]
+pub struct BroadcastResponses<T>(pub Vec<(PublicKey, Result<T, Error>)>);
+
+impl<T: Acknowledgement> BroadcastResponses<T> {
+    /// The peers that acknowledged the request
+    pub fn acked(&self) -> Vec<PublicKey> {
+        self.0
+            .iter()
+            .filter_map(|(peer, resp)| match resp {
+                Ok(resp) if resp.is_ack() => Some(*peer),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether all the peers acknowledged the request
+    pub fn all_acked(&self) -> bool {
+        self.0
+            .iter()
+            .all(|(_, resp)| matches!(resp, Ok(resp) if resp.is_ack()))
+    }
+
+    /// The peers that did not respond in time. The request was abandoned on their
+    /// connection, but the peer may be stalled: it should be reconnected to rather than
+    /// sent other requests on it.
+    pub fn timed_out(&self) -> Vec<PublicKey> {
+        self.0
+            .iter()
+            .filter_map(|(peer, resp)| match resp {
+                Err(e) if is_transient(e) => Some(*peer),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Send the same request to all these peers concurrently, giving each of them `timeout`
+/// to respond. The responses are returned in the order of the connections, the
+/// connections of the peers that timed out being flagged by
+/// [BroadcastResponses::timed_out].
+pub fn broadcast<T>(
+    transports: &mut [KKTransport],
+    req: &Request,
+    timeout: Duration,
+) -> BroadcastResponses<T>
+where
+    T: serde::de::DeserializeOwned + Send,
+{
+    let responses = thread::scope(|s| {
+        let handles: Vec<_> = transports
+            .iter_mut()
+            .map(|transport| {
+                s.spawn(move || {
+                    let prev_timeout = transport.read_timeout()?;
+                    transport.set_read_timeout(Some(timeout))?;
+                    let resp = transport.send_req(req);
+                    transport.set_read_timeout(prev_timeout)?;
+                    resp
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("Broadcast thread panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    BroadcastResponses(
+        transports
+            .iter()
+            .map(|transport| transport.remote_static())
+            .zip(responses)
+            .collect(),
+    )
+}
+
+// How often the peers of a [QuorumBroadcast] check whether the outcome is decided
+const QUORUM_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// What became of the request of a [QuorumBroadcast] for a peer
+#[derive(Debug)]
+pub enum PeerOutcome<T> {
+    /// The peer responded in time, or the exchange failed
+    Responded(Result<T, Error>),
+    /// The outcome was decided before the peer responded. The request was abandoned:
+    /// its response, if any, will be dropped.
+    Cancelled,
+}
+
+/// Send the same request to a set of peers concurrently, resolving as soon as
+/// `threshold` of them acknowledged it (or too many refused it, or failed to respond,
+/// for the threshold to be reached). For instance to consider the revocation
+/// signatures of a vault delivered once enough watchtowers, as per the policy of the
+/// deployment, stored them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuorumBroadcast {
+    threshold: usize,
+    timeout: Duration,
+}
+
+impl QuorumBroadcast {
+    /// Wait for `threshold` acknowledgements, giving the peers `timeout` to respond
+    pub fn new(threshold: usize, timeout: Duration) -> Self {
+        Self { threshold, timeout }
+    }
+
+    /// Send this request to all these peers. The outcomes are returned in the order
+    /// of the connections.
+    pub fn send<T>(&self, transports: &mut [KKTransport], req: &Request) -> QuorumResponses<T>
+    where
+        T: serde::de::DeserializeOwned + Acknowledgement + Send,
+    {
+        let (threshold, peers_count) = (self.threshold, transports.len());
+        let deadline = Instant::now() + self.timeout;
+        let decided = AtomicBool::new(threshold == 0);
+        let (sender, receiver) = mpsc::channel();
+
+        let outcomes: Vec<Option<PeerOutcome<T>>> = thread::scope(|s| {
+            for (i, transport) in transports.iter_mut().enumerate() {
+                let (sender, decided) = (sender.clone(), &decided);
+                s.spawn(move || {
+                    let outcome = match transport.write_req(req) {
+                        Ok(()) => poll_until_decided::<T>(transport, req.id(), deadline, decided),
+                        Err(e) => PeerOutcome::Responded(Err(e)),
+                    };
+                    // The receiver outlives the scope
+                    let _ = sender.send((i, outcome));
+                });
+            }
+            drop(sender);
+
+            let mut outcomes: Vec<_> = (0..peers_count).map(|_| None).collect();
+            let (mut acked, mut failed) = (0, 0);
+            for (i, outcome) in receiver.iter() {
+                match outcome {
+                    PeerOutcome::Responded(Ok(ref resp)) if resp.is_ack() => acked += 1,
+                    PeerOutcome::Responded(_) => failed += 1,
+                    PeerOutcome::Cancelled => {}
+                }
+                outcomes[i] = Some(outcome);
+                if acked >= threshold || peers_count - failed < threshold {
+                    decided.store(true, Ordering::SeqCst);
+                }
+            }
+            outcomes
+        });
+
+        QuorumResponses {
+            threshold,
+            outcomes: transports
+                .iter()
+                .map(|transport| transport.remote_static())
+                .zip(outcomes.into_iter().map(|o| o.expect("Every peer reports")))
+                .collect(),
+        }
+    }
+}
+
+// Wait for the response to the request with this id until the deadline, or until
+// the outcome of the broadcast is decided.
+fn poll_until_decided<T>(
+    transport: &mut KKTransport,
+    id: u32,
+    deadline: Instant,
+    decided: &AtomicBool,
+) -> PeerOutcome<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    loop {
+        let poll_deadline = cmp::min(deadline, Instant::now() + QUORUM_POLL_INTERVAL);
+        match transport.poll_response(id, poll_deadline) {
+            Ok(Some(resp)) => return PeerOutcome::Responded(Ok(resp)),
+            Err(e) => return PeerOutcome::Responded(Err(e)),
+            Ok(None) if Instant::now() >= deadline => {
+                transport.abandon(id);
+                return PeerOutcome::Responded(Err(Error::Timeout(Some(id))));
+            }
+            Ok(None) if decided.load(Ordering::SeqCst) => {
+                transport.abandon(id);
+                return PeerOutcome::Cancelled;
+            }
+            Ok(None) => {}
+        }
+    }
+}
+
+/// The outcome of a [QuorumBroadcast] for each peer
+#[derive(Debug)]
+pub struct QuorumResponses<T> {
+    /// The number of acknowledgements that were waited for
+    pub threshold: usize,
+    /// What became of the request for each peer
+    pub outcomes: Vec<(PublicKey, PeerOutcome<T>)>,
+}
+
+impl<T: Acknowledgement> QuorumResponses<T> {
+    /// The peers that acknowledged the request
+    pub fn acked(&self) -> Vec<PublicKey> {
+        self.outcomes
+            .iter()
+            .filter_map(|(peer, outcome)| match outcome {
+                PeerOutcome::Responded(Ok(resp)) if resp.is_ack() => Some(*peer),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether at least `threshold` peers acknowledged the request
+    pub fn is_reached(&self) -> bool {
+        self.acked().len() >= self.threshold
+    }
+
+    /// The outcome for the peers that did not acknowledge the request
+    pub fn remainder(&self) -> impl Iterator<Item = &(PublicKey, PeerOutcome<T>)> {
+        self.outcomes.iter().filter(
+            |(_, outcome)| !matches!(outcome, PeerOutcome::Responded(Ok(resp)) if resp.is_ack()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "revault_tx")]
+    use crate::fixtures;
+    use crate::fixtures::{local_endpoints, secp_sig};
+    use crate::message::{RequestParams, ResponseResult};
+
+    use crate::noise::gen_keypair;
+    use bitcoin::hashes::Hash;
+    use std::{net::TcpListener, thread};
+
+    // Run the client on a thread and answer its requests with `responses`, in order
+    fn with_server<C, T>(client: C, responses: Vec<ResponseResult>) -> T
+    where
+        C: FnOnce(KKTransport) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_thread = thread::spawn(move || {
+            client(KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap())
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        for resp in responses {
+            server_transport.read_req(|_| Some(resp)).unwrap();
+        }
+
+        cli_thread.join().unwrap()
+    }
+
+    #[test]
+    fn coordinator_client() {
+        let (pubkey, signature) = secp_sig();
+        let txid = Txid::default();
+        let signatures: SigSet = [(pubkey, signature)].iter().cloned().collect();
+
+        let sigs = signatures.clone();
+        let (sent, got_sigs, spend_tx) = with_server(
+            move |transport| {
+                let mut client = CoordinatorClient::new(transport);
+                let sent = client.send_sig(Sig {
+                    pubkey,
+                    signature,
+                    id: txid,
+                });
+                let refused = client.send_sig(Sig {
+                    pubkey,
+                    signature,
+                    id: txid,
+                });
+                assert!(matches!(refused, Err(Error::NotAcknowledged(method::SIG))));
+                let got_sigs = client.get_sigs(txid).unwrap();
+                let spend_tx = client.get_spend_tx(OutPoint::default()).unwrap();
+                let latencies = client.latencies();
+                assert_eq!(latencies.get(method::SIG).unwrap().count(), 2);
+                assert_eq!(latencies.get(method::GET_SIGS).unwrap().count(), 1);
+                assert!(latencies.get(method::SET_SPEND_TX).is_none());
+                (sent.is_ok(), got_sigs, spend_tx)
+            },
+            vec![
+                ResponseResult::Sig(SigResult::stored()),
+                ResponseResult::Sig(SigResult::refused()),
+                ResponseResult::Sigs(Sigs { signatures: sigs }),
+                ResponseResult::SpendTx(SpendTx::not_found()),
+            ],
+        );
+        assert!(sent);
+        assert_eq!(got_sigs, signatures);
+        assert!(!spend_tx.is_found());
+
+        // A response of the wrong type is an error
+        let res = with_server(
+            |transport| CoordinatorClient::new(transport).get_sigs(Txid::default()),
+            vec![ResponseResult::Sig(SigResult::stored())],
+        );
+        assert!(matches!(res, Err(Error::Json(_))));
+    }
+
+    #[test]
+    fn watchtower_client() {
+        let (pubkey, signature) = secp_sig();
+        let txid = Txid::default();
+        let msg = watchtower::Sig {
+            signatures: [(pubkey, signature)].iter().cloned().collect(),
+            txid,
+            deposit_outpoint: OutPoint::default(),
+        };
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_msg = msg.clone();
+        let cli_thread = thread::spawn(move || {
+            let mut client = WatchtowerClient::connect(addr, &client_privkey, &server_pubkey)
+                .unwrap()
+                .with_timeout(Duration::from_millis(200));
+            // Without retry, the first request times out
+            assert!(matches!(
+                client.share_revocation_sigs(cli_msg.clone()),
+                Err(Error::Timeout(Some(_)))
+            ));
+            // With a retry, it's sent again
+            let mut client = client.with_retries(1);
+            client.share_revocation_sigs(cli_msg.clone()).unwrap();
+            // The watchtower must answer for the same transaction
+            assert!(matches!(
+                client.share_revocation_sigs(cli_msg),
+                Err(Error::Message(MessageError::TxidMismatch { .. }))
+            ));
+
+            // Spends are submitted for approval
+            let approval = client
+                .approve_spend(vec![OutPoint::default()], spend_tx())
+                .unwrap();
+            assert!(approval.is_ack());
+            let approval = client
+                .approve_spend(vec![OutPoint::default()], spend_tx())
+                .unwrap();
+            assert_eq!(approval.reason.as_deref(), Some("Unknown destination"));
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        // Don't answer the first request nor the first attempt of the second one
+        for _ in 0..2 {
+            server_transport
+                .read_req(|params| {
+                    assert_eq!(params, RequestParams::WtSig(msg.clone()));
+                    None
+                })
+                .unwrap();
+        }
+        server_transport
+            .read_req(|_| {
+                Some(ResponseResult::WtSig(watchtower::SigResult {
+                    ack: true,
+                    txid,
+                }))
+            })
+            .unwrap();
+        server_transport
+            .read_req(|_| {
+                Some(ResponseResult::WtSig(watchtower::SigResult {
+                    ack: true,
+                    txid: Txid::from_slice(&[1; 32]).unwrap(),
+                }))
+            })
+            .unwrap();
+        let spend_txid = spend_tx().txid();
+        server_transport
+            .read_req(|_| {
+                Some(ResponseResult::WtSpendApproval(
+                    watchtower::SpendApproval::approve(spend_txid),
+                ))
+            })
+            .unwrap();
+        server_transport
+            .read_req(|_| {
+                Some(ResponseResult::WtSpendApproval(
+                    watchtower::SpendApproval::reject(spend_txid, "Unknown destination"),
+                ))
+            })
+            .unwrap();
+
+        cli_thread.join().unwrap();
+    }
+
+    fn spend_tx() -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![Default::default()],
+            output: vec![Default::default()],
+        }
+    }
+
+    #[test]
+    fn retry_policy() {
+        let backoff = Backoff::Exponential {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(10));
+        assert_eq!(backoff.delay(2), Duration::from_millis(20));
+        assert_eq!(backoff.delay(3), Duration::from_millis(40));
+        assert_eq!(backoff.delay(4), Duration::from_millis(50));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_millis(50));
+
+        let timeout = || Error::Timeout(None);
+        let policy = RetryPolicy::new()
+            .max_attempts(3)
+            .backoff(Backoff::Constant(Duration::from_millis(1)));
+        let mut attempts = 0;
+        let res: Result<(), _> = policy.run(|| {
+            attempts += 1;
+            Err(timeout())
+        });
+        assert!(res.is_err());
+        assert_eq!(attempts, 3);
+
+        // Succeeds on the second attempt
+        let mut attempts = 0;
+        policy
+            .run(|| {
+                attempts += 1;
+                if attempts < 2 {
+                    Err(timeout())
+                } else {
+                    Ok(())
+                }
+            })
+            .unwrap();
+        assert_eq!(attempts, 2);
+
+        // Errors not worth retrying are returned immediately
+        let mut attempts = 0;
+        let res: Result<(), _> = policy.run(|| {
+            attempts += 1;
+            Err(Error::NotAcknowledged(method::SIG))
+        });
+        assert!(res.is_err());
+        assert_eq!(attempts, 1);
+        let mut attempts = 0;
+        let res: Result<(), _> = policy.clone().retry_if(|_| true).run(|| {
+            attempts += 1;
+            Err(Error::NotAcknowledged(method::SIG))
+        });
+        assert!(res.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn connect_retries() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
+            (gen_keypair(), gen_keypair());
+        let closed_addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        // Bounded by the number of attempts
+        let config = RetryConfig::new()
+            .max_attempts(3)
+            .backoff(Backoff::Constant(Duration::from_millis(20)));
+        let start = Instant::now();
+        assert!(
+            connect_with_retries(closed_addr, &client_privkey, &server_pubkey, &config).is_err()
+        );
+        assert!(start.elapsed() >= Duration::from_millis(40));
+
+        // Bounded by the deadline
+        let config = RetryConfig::new()
+            .max_attempts(u32::MAX)
+            .backoff(Backoff::Constant(Duration::from_millis(20)))
+            .deadline(Duration::from_millis(100));
+        let start = Instant::now();
+        assert!(
+            connect_with_retries(closed_addr, &client_privkey, &server_pubkey, &config).is_err()
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // A failed handshake is retried too
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_thread = thread::spawn(move || {
+            let (other_pubkey, _) = gen_keypair();
+            assert!(KKTransport::accept(&listener, &server_privkey, &[other_pubkey]).is_err());
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap()
+        });
+        let config = RetryConfig::new().backoff(Backoff::Constant(Duration::from_millis(10)));
+        let transport =
+            connect_with_retries(addr, &client_privkey, &server_pubkey, &config).unwrap();
+        assert_eq!(transport.remote_static(), server_pubkey);
+        server_thread.join().unwrap();
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn cosigner_client() {
+        let spend_tx = fixtures::spend_tx();
+        let mut other_tx = spend_tx.clone();
+        other_tx.inner_tx_mut().global.unsigned_tx.lock_time += 1;
+
+        let cli_tx = spend_tx.clone();
+        let (signed, refused, mismatch) = with_server(
+            move |transport| {
+                let mut client = CosignerClient::new(transport);
+                (
+                    client.sign(cli_tx.clone()),
+                    client.sign(cli_tx.clone()),
+                    client.sign(cli_tx),
+                )
+            },
+            vec![
+                ResponseResult::SignResult(cosigner::SignResult {
+                    tx: Some(spend_tx.clone()),
+                }),
+                ResponseResult::SignResult(cosigner::SignResult { tx: None }),
+                ResponseResult::SignResult(cosigner::SignResult { tx: Some(other_tx) }),
+            ],
+        );
+        assert_eq!(signed.unwrap(), spend_tx);
+        assert!(matches!(refused, Err(Error::NotAcknowledged(method::SIGN))));
+        assert!(matches!(
+            mismatch,
+            Err(Error::Message(MessageError::TxidMismatch { .. }))
+        ));
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn cosigner_orchestrator() {
+        use crate::server::{
+            cosigner::{Cosigner, MemoryOutpointStore, PrivateKeySigner},
+            serve,
+        };
+        use bitcoin::secp256k1::{key::SecretKey as SecpKey, Secp256k1};
+
+        let secp = Secp256k1::new();
+        let (client_pubkey, client_privkey) = gen_keypair();
+        let privkeys: Vec<SecpKey> = (1..=3)
+            .map(|i| SecpKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let spawn_cosigner = |privkey: SecpKey| {
+            let (server_pubkey, server_privkey) = gen_keypair();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = thread::spawn(move || {
+                let mut transport =
+                    KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+                let cosigner =
+                    Cosigner::new(PrivateKeySigner::new(privkey), MemoryOutpointStore::new());
+                let _ = serve(&mut transport, &cosigner);
+            });
+            let client = CosignerClient::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            (client, server)
+        };
+
+        // The second one signs with another key than the one we know it by
+        let mut cosigners = Vec::new();
+        let mut servers = Vec::new();
+        for (i, privkey) in privkeys.iter().enumerate() {
+            let (client, server) = spawn_cosigner(if i == 1 {
+                SecpKey::from_slice(&[4; 32]).unwrap()
+            } else {
+                *privkey
+            });
+            let pubkey = bitcoin::secp256k1::PublicKey::from_secret_key(&secp, privkey);
+            cosigners.push((pubkey, client));
+            servers.push(server);
+        }
+        let pubkeys: Vec<_> = cosigners.iter().map(|(pubkey, _)| *pubkey).collect();
+        let partial_sigs = |res: &CosigningResult| -> Vec<bitcoin::secp256k1::PublicKey> {
+            res.spend_tx.inner_tx().inputs[0]
+                .partial_sigs
+                .keys()
+                .map(|pk| pk.key)
+                .collect()
+        };
+
+        // Stops at the first failure
+        let mut orchestrator = CosignerOrchestrator::new(cosigners, CosigningPolicy::Sequential);
+        let res = orchestrator.sign(fixtures::spend_tx());
+        assert!(!res.is_complete());
+        assert_eq!(partial_sigs(&res), vec![pubkeys[0]]);
+        assert_eq!(res.failures.len(), 1);
+        assert_eq!(res.failures[0].0, pubkeys[1]);
+        assert!(matches!(
+            res.failures[0].1,
+            Error::Message(MessageError::MissingSignature { input_index: 0, .. })
+        ));
+
+        // All the valid signatures are merged
+        let mut orchestrator =
+            CosignerOrchestrator::new(orchestrator.into_cosigners(), CosigningPolicy::Parallel);
+        let res = orchestrator.sign(fixtures::spend_tx());
+        let mut expected = vec![pubkeys[0], pubkeys[2]];
+        expected.sort_by_key(|pk| bitcoin::PublicKey {
+            compressed: true,
+            key: *pk,
+        });
+        assert_eq!(partial_sigs(&res), expected);
+        assert_eq!(res.failures.len(), 1);
+        assert_eq!(res.failures[0].0, pubkeys[1]);
+
+        drop(orchestrator);
+        for server in servers {
+            server.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn broadcast_to_watchtowers() {
+        let (pubkey, signature) = secp_sig();
+        let txid = Txid::default();
+        let msg = watchtower::Sig {
+            signatures: [(pubkey, signature)].iter().cloned().collect(),
+            txid,
+            deposit_outpoint: OutPoint::default(),
+        };
+
+        let (client_pubkey, client_privkey) = gen_keypair();
+        let mut transports = Vec::new();
+        let mut servers = Vec::new();
+        // The first one acks, the second one doesn't and the third one never answers
+        for ack in &[Some(true), Some(false), None] {
+            let (server_pubkey, server_privkey) = gen_keypair();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let ack = *ack;
+            servers.push(thread::spawn(move || {
+                let mut transport =
+                    KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+                transport
+                    .read_req(|_| {
+                        ack.map(|ack| ResponseResult::WtSig(watchtower::SigResult { ack, txid }))
+                    })
+                    .unwrap();
+                // Keep the connection open until the client is done
+                let _ = transport.read_req(|_| None);
+            }));
+            transports.push(KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap());
+        }
+
+        let responses: BroadcastResponses<watchtower::SigResult> = broadcast(
+            &mut transports,
+            &Request::from(msg),
+            Duration::from_millis(500),
+        );
+        assert_eq!(responses.acked(), vec![transports[0].remote_static()]);
+        assert!(!responses.all_acked());
+        assert!(responses.0[1].1.is_ok());
+        assert!(matches!(responses.0[2].1, Err(Error::Timeout(Some(_)))));
+        assert_eq!(responses.timed_out(), vec![transports[2].remote_static()]);
+        // The previous timeout is restored
+        assert_eq!(
+            transports[2].read_timeout().unwrap(),
+            Some(Duration::from_secs(20))
+        );
+
+        drop(transports);
+        for server in servers {
+            server.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn quorum_broadcast() {
+        let (pubkey, signature) = secp_sig();
+        let txid = Txid::default();
+        let msg = watchtower::Sig {
+            signatures: [(pubkey, signature)].iter().cloned().collect(),
+            txid,
+            deposit_outpoint: OutPoint::default(),
+        };
+
+        let (client_pubkey, client_privkey) = gen_keypair();
+        let mut transports = Vec::new();
+        let mut servers = Vec::new();
+        // Two of them ack, one doesn't and the last one never answers
+        for ack in &[Some(true), Some(true), Some(false), None] {
+            let (server_pubkey, server_privkey) = gen_keypair();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let ack = *ack;
+            servers.push(thread::spawn(move || {
+                let mut transport =
+                    KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+                // Until the client is done
+                while transport
+                    .read_req(|_| {
+                        ack.map(|ack| ResponseResult::WtSig(watchtower::SigResult { ack, txid }))
+                    })
+                    .is_ok()
+                {}
+            }));
+            transports.push(KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap());
+        }
+        let timeout = Duration::from_secs(10);
+
+        // Resolved without waiting for the silent one
+        let start = Instant::now();
+        let responses: QuorumResponses<watchtower::SigResult> =
+            QuorumBroadcast::new(2, timeout).send(&mut transports, &Request::from(msg.clone()));
+        assert!(start.elapsed() < timeout / 2);
+        assert!(responses.is_reached());
+        assert_eq!(
+            responses.acked(),
+            vec![transports[0].remote_static(), transports[1].remote_static()]
+        );
+        let remainder: Vec<_> = responses.remainder().map(|(peer, _)| *peer).collect();
+        assert_eq!(
+            remainder,
+            vec![transports[2].remote_static(), transports[3].remote_static()]
+        );
+        assert!(matches!(responses.outcomes[3].1, PeerOutcome::Cancelled));
+
+        // Resolved as soon as it can't be reached anymore
+        let start = Instant::now();
+        let responses: QuorumResponses<watchtower::SigResult> =
+            QuorumBroadcast::new(4, timeout).send(&mut transports, &Request::from(msg));
+        assert!(start.elapsed() < timeout / 2);
+        assert!(!responses.is_reached());
+        assert_eq!(responses.acked().len(), 2);
+        assert!(matches!(
+            responses.outcomes[2].1,
+            PeerOutcome::Responded(Ok(watchtower::SigResult { ack: false, .. }))
+        ));
+        assert!(matches!(responses.outcomes[3].1, PeerOutcome::Cancelled));
+
+        drop(transports);
+        for server in servers {
+            server.join().unwrap();
+        }
+    }
+
+    // Make sure we send what we think we send
+    #[test]
+    fn coordinator_client_requests() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_thread = thread::spawn(move || {
+            let mut client =
+                CoordinatorClient::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            client.get_spend_tx(OutPoint::default()).unwrap();
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        server_transport
+            .read_req(|params| {
+                assert_eq!(
+                    params,
+                    RequestParams::GetSpendTx(GetSpendTx {
+                        deposit_outpoint: OutPoint::default()
+                    })
+                );
+                Some(ResponseResult::SpendTx(SpendTx::not_found()))
+            })
+            .unwrap();
+
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn coordinator_client_sigs_cache() {
+        let (pubkey, signature) = secp_sig();
+        let txid = Txid::default();
+        let complete: SigSet = [(pubkey, signature)].iter().cloned().collect();
+
+        let sigs = complete.clone();
+        let (got, hits) = with_server(
+            move |transport| {
+                let mut client = CoordinatorClient::new(transport).with_sigs_cache(1);
+                let mut got = Vec::new();
+                // Incomplete, not cached
+                got.push(client.get_sigs(txid).unwrap());
+                // Complete, cached and served locally the second time
+                got.push(client.get_sigs(txid).unwrap());
+                got.push(client.get_sigs(txid).unwrap());
+                // Pushing a signature invalidates it
+                client
+                    .send_sig(Sig {
+                        pubkey,
+                        signature,
+                        id: txid,
+                    })
+                    .unwrap();
+                got.push(client.get_sigs(txid).unwrap());
+                (got, client.sigs_cache().unwrap().hits())
+            },
+            vec![
+                ResponseResult::Sigs(Sigs {
+                    signatures: SigSet::new(),
+                }),
+                ResponseResult::Sigs(Sigs {
+                    signatures: sigs.clone(),
+                }),
+                ResponseResult::Sig(SigResult::stored()),
+                ResponseResult::Sigs(Sigs { signatures: sigs }),
+            ],
+        );
+        assert_eq!(got[0].len(), 0);
+        assert_eq!(&got[1..], &[complete.clone(), complete.clone(), complete]);
+        assert_eq!(hits, 1);
+    }
+
+    #[test]
+    fn failover_coordinator_client() {
+        let (pubkey, signature) = secp_sig();
+        let sig = Sig {
+            pubkey,
+            signature,
+            id: Txid::default(),
+        };
+
+        let (client_pubkey, client_privkey) = gen_keypair();
+        // Nothing listens on the first address
+        let (down_pubkey, _) = gen_keypair();
+        let down_addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (server_pubkey, server_privkey) = gen_keypair();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+            transport
+                .read_req(|_| {
+                    Some(ResponseResult::Sigs(Sigs {
+                        signatures: SigSet::new(),
+                    }))
+                })
+                .unwrap();
+            transport
+                .read_req(|_| Some(ResponseResult::Sig(SigResult::refused())))
+                .unwrap();
+        });
+
+        let mut client = FailoverCoordinatorClient::new(
+            vec![(down_addr, down_pubkey), (addr, server_pubkey)],
+            client_privkey,
+        );
+        assert_eq!(client.active(), Some(down_pubkey));
+        assert_eq!(client.get_sigs(Txid::default()).unwrap().len(), 0);
+        assert_eq!(client.active(), Some(server_pubkey));
+
+        // Each of them is reported on
+        let outcomes = client.send_sig_to_all(sig);
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[0].0, down_pubkey);
+        assert!(matches!(outcomes[0].1, Err(Error::Transport(_))));
+        assert_eq!(outcomes[1].0, server_pubkey);
+        assert!(matches!(
+            outcomes[1].1,
+            Err(Error::NotAcknowledged(method::SIG))
+        ));
+        // A refusal isn't a reason to fail over
+        assert_eq!(client.active(), Some(server_pubkey));
+
+        server_thread.join().unwrap();
+        // Once they are all down, the last error is returned
+        assert!(is_connection_failure(
+            &client.get_sigs(Txid::default()).unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn latency_histogram() {
+        let mut hist = LatencyHistogram::default();
+        assert_eq!(hist.mean(), None);
+        assert_eq!(hist.quantile(0.5), None);
+
+        for ms in &[1, 3, 7, 40, 40, 40, 200, 900, 60_000] {
+            hist.record(Duration::from_millis(*ms));
+        }
+        assert_eq!(hist.count(), 9);
+        assert_eq!(hist.sum(), Duration::from_millis(61_231));
+        let buckets: Vec<_> = hist.buckets().collect();
+        assert_eq!(buckets.len(), LATENCY_BUCKETS.len() + 1);
+        assert_eq!(buckets[0], (Some(Duration::from_millis(5)), 2));
+        assert_eq!(buckets[1], (Some(Duration::from_millis(10)), 1));
+        assert_eq!(buckets[3], (Some(Duration::from_millis(50)), 3));
+        assert_eq!(buckets[LATENCY_BUCKETS.len()], (None, 1));
+        assert_eq!(hist.quantile(0.5), Some(Duration::from_millis(50)));
+        assert_eq!(hist.quantile(0.85), Some(Duration::from_secs(1)));
+        assert_eq!(hist.quantile(1.0), None);
+        assert_eq!(hist.quantile(0.0), Some(Duration::from_millis(5)));
+    }
+
+    #[test]
+    fn correlation_id() {
+        assert_eq!(super::correlation_id(), None);
+        let res = with_server(
+            |transport| {
+                let mut client = CoordinatorClient::new(transport);
+                with_correlation_id("vault-1", || {
+                    assert_eq!(super::correlation_id().as_deref(), Some("vault-1"));
+                    with_correlation_id("vault-2", || client.get_sigs(Txid::default()))
+                })
+            },
+            vec![ResponseResult::Sig(SigResult::stored())],
+        );
+        // The innermost id is kept
+        match res {
+            Err(Error::Correlated {
+                correlation_id,
+                error,
+            }) => {
+                assert_eq!(correlation_id, "vault-2");
+                assert!(matches!(*error, Error::Json(_)));
+            }
+            _ => panic!("unexpected result"),
+        }
+        assert_eq!(super::correlation_id(), None);
+        assert!(is_transient(&Error::Correlated {
+            correlation_id: "vault".to_string(),
+            error: Box::new(Error::Timeout(None)),
+        }));
+    }
+
+    #[test]
+    fn method_timeouts() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_thread = thread::spawn(move || {
+            let transport = KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            let read_timeout = transport.read_timeout().unwrap();
+            let mut client = CoordinatorClient::new(transport).with_method_timeouts(
+                MethodTimeouts::new()
+                    .with_default(Duration::from_millis(100))
+                    .with(method::GET_TIME, Duration::from_secs(5)),
+            );
+            // The slow answer is given the time of the method, the other ones the default
+            client.sync_time().unwrap();
+            assert!(matches!(
+                client.get_sigs(Txid::default()),
+                Err(Error::Timeout(Some(_)))
+            ));
+            // The read timeout of the connection is restored
+            assert_eq!(client.transport().read_timeout().unwrap(), read_timeout);
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        let slow_response = |resp: ResponseResult| {
+            move |_| {
+                thread::sleep(Duration::from_millis(300));
+                Some(resp)
+            }
+        };
+        server_transport
+            .read_req(slow_response(ResponseResult::ServerTime(ServerTime {
+                time: 1_600_000_000,
+            })))
+            .unwrap();
+        let _ = server_transport.read_req(slow_response(ResponseResult::Sigs(Sigs {
+            signatures: SigSet::new(),
+        })));
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn journaled_requests() {
+        let (pubkey, signature) = secp_sig();
+        let sig = Sig {
+            pubkey,
+            signature,
+            id: Txid::default(),
+        };
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let journal = Arc::new(crate::journal::MemoryJournal::new());
+        let cli_journal = journal.clone();
+        let cli_thread = thread::spawn(move || {
+            let transport = KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            let mut client = CoordinatorClient::new(transport).with_journal(cli_journal);
+            // A refusal is an answer
+            assert!(matches!(
+                client.send_sig(sig.clone()),
+                Err(Error::NotAcknowledged(method::SIG))
+            ));
+            assert!(client.send_sig(sig).is_err());
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        server_transport
+            .read_req(|_| Some(ResponseResult::Sig(SigResult::refused())))
+            .unwrap();
+        // The second one is read but never answered
+        server_transport.read_req(|_| None).unwrap();
+        drop(server_transport);
+        cli_thread.join().unwrap();
+
+        let pending = journal.pending().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert!(pending[0].is_sig());
+        assert!(matches!(
+            pending[0].request().unwrap().params(),
+            RequestParams::CoordSig(_)
+        ));
+    }
+}
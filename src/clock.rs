@@ -0,0 +1,209 @@
+//! Clock policy
+//!
+//! The timestamped fields of the messages (such as their expiry, see
+//! [Expiring](crate::message::Expiring)) are checked against the time given by a
+//! [ClockPolicy]. It tolerates some skew between the clocks of the peers (and the
+//! delays of a Tor circuit), and its time source can be replaced, for instance by a
+//! mock in tests.
+//!
+//! The skew to a peer can be measured (see [clock_offset]) and corrected for with
+//! [ClockPolicy::with_offset], for instance by a client before sending it expiring
+//! messages.
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Tells the current time
+pub trait TimeSource: Send + Sync {
+    /// The current time, in seconds since the UNIX epoch
+    fn now(&self) -> u64;
+}
+
+impl<F> TimeSource for F
+where
+    F: Fn() -> u64 + Send + Sync,
+{
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+/// The time of the system's clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// How the timestamps of the messages are checked: against the time of this source,
+/// tolerating up to this skew.
+///
+/// By default, the time of the system's clock is used and a skew of up to a minute is
+/// tolerated.
+#[derive(Clone)]
+pub struct ClockPolicy {
+    max_skew: Duration,
+    offset: i64,
+    source: Arc<dyn TimeSource>,
+}
+
+impl fmt::Debug for ClockPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClockPolicy")
+            .field("max_skew", &self.max_skew)
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
+
+impl Default for ClockPolicy {
+    fn default() -> Self {
+        Self {
+            max_skew: Duration::from_secs(60),
+            offset: 0,
+            source: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl ClockPolicy {
+    /// The default policy
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum skew tolerated between our clock and the peers' ones
+    pub fn with_max_skew(mut self, max_skew: Duration) -> Self {
+        self.max_skew = max_skew;
+        self
+    }
+
+    /// Tell the time with this source
+    pub fn with_time_source(mut self, source: Arc<dyn TimeSource>) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Shift the time of the source by this number of seconds, for instance the offset
+    /// of a peer's clock to tell the time by its clock
+    pub fn with_offset(mut self, offset: i64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// The maximum skew tolerated
+    pub fn max_skew(&self) -> Duration {
+        self.max_skew
+    }
+
+    /// The offset applied to the time of the source, in seconds
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    /// The current time, in seconds since the UNIX epoch
+    pub fn now(&self) -> u64 {
+        let now = self.source.now();
+        if self.offset < 0 {
+            now.saturating_sub(self.offset.unsigned_abs())
+        } else {
+            now.saturating_add(self.offset as u64)
+        }
+    }
+
+    /// Whether something expiring at this time (in seconds since the UNIX epoch) is
+    /// expired, even accounting for the skew
+    pub fn is_expired(&self, expires_at: u64) -> bool {
+        self.now() > expires_at.saturating_add(self.max_skew.as_secs())
+    }
+
+    /// Whether this timestamp (in seconds since the UNIX epoch) is not in the future
+    /// nor older than `max_age`, accounting for the skew
+    pub fn is_fresh(&self, timestamp: u64, max_age: Duration) -> bool {
+        let (now, skew) = (self.now(), self.max_skew.as_secs());
+        timestamp <= now.saturating_add(skew)
+            && timestamp
+                .saturating_add(max_age.as_secs())
+                .saturating_add(skew)
+                >= now
+    }
+}
+
+/// The offset of a peer's clock to ours, in seconds (positive if it is ahead), from the
+/// time it told (in seconds since the UNIX epoch) in answer to a request we sent at
+/// `sent_at` and got the response of at `received_at`.
+///
+/// The peer is assumed to have told its time halfway through the round trip, so the
+/// error is at most half of it (plus a second, as the peer's time is truncated).
+pub fn clock_offset(sent_at: SystemTime, received_at: SystemTime, peer_time: u64) -> i64 {
+    let millis = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i128)
+            .unwrap_or(0)
+    };
+    let (sent, received) = (millis(sent_at), millis(received_at).max(millis(sent_at)));
+    // Truncated to the second, as the peer's time
+    let midpoint = (sent + (received - sent) / 2) / 1_000;
+
+    (peer_time as i128 - midpoint) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn clock_policy() {
+        let time = Arc::new(AtomicU64::new(1_000));
+        let source_time = time.clone();
+        let policy = ClockPolicy::new()
+            .with_max_skew(Duration::from_secs(10))
+            .with_time_source(Arc::new(move || source_time.load(Ordering::SeqCst)));
+        assert_eq!(policy.now(), 1_000);
+
+        assert!(!policy.is_expired(1_000));
+        assert!(!policy.is_expired(990));
+        assert!(policy.is_expired(989));
+        time.store(u64::MAX, Ordering::SeqCst);
+        assert!(policy.is_expired(1_000));
+        assert!(!policy.is_expired(u64::MAX));
+
+        time.store(1_000, Ordering::SeqCst);
+        let max_age = Duration::from_secs(60);
+        assert!(policy.is_fresh(1_000, max_age));
+        assert!(policy.is_fresh(1_010, max_age));
+        assert!(!policy.is_fresh(1_011, max_age));
+        assert!(policy.is_fresh(930, max_age));
+        assert!(!policy.is_fresh(929, max_age));
+
+        assert!(SystemClock.now() > 1_600_000_000);
+        assert_eq!(ClockPolicy::default().max_skew(), Duration::from_secs(60));
+
+        let policy = policy.with_offset(-30);
+        assert_eq!(policy.offset(), -30);
+        assert_eq!(policy.now(), 970);
+        assert!(policy.is_expired(959));
+        assert_eq!(policy.with_offset(-2_000).now(), 0);
+    }
+
+    #[test]
+    fn peer_clock_offset() {
+        let at = |millis| UNIX_EPOCH + Duration::from_millis(millis);
+        // A round trip of 2 seconds, with the peer answering halfway through
+        assert_eq!(clock_offset(at(100_000), at(102_000), 101), 0);
+        assert_eq!(clock_offset(at(100_000), at(102_000), 131), 30);
+        assert_eq!(clock_offset(at(100_000), at(102_000), 41), -60);
+        // Whatever the order of the local times
+        assert_eq!(clock_offset(at(102_000), at(100_000), 131), 29);
+    }
+}
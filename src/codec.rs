@@ -0,0 +1,157 @@
+//! Wire encodings
+//!
+//! Messages are built and parsed as JSON throughout the crate. A [WireCodec] set on a
+//! connection (see [KKTransport::set_codec](crate::transport::KKTransport::set_codec))
+//! translates them to another encoding on the wire and back, so that an alternative
+//! encoding (CBOR, bincode, canonical JSON..) only needs to implement the translation,
+//! and the transport, clients and servers are unchanged.
+//!
+//! Both ends of a connection must use the same codec. Without one, the messages are
+//! sent as serialized, which is the same as using [Json].
+//!
+//! Messages may be padded with trailing whitespace (see
+//! [Padding](crate::padding::Padding)), which a codec must ignore when decoding.
+
+use crate::error::Error;
+
+use std::{borrow::Cow, fmt};
+
+/// Translates the JSON messages to the bytes sent on the wire, and back
+pub trait WireCodec: fmt::Debug + Send + Sync {
+    /// A name for this encoding
+    fn name(&self) -> &'static str;
+
+    /// The bytes to send on the wire for this JSON message
+    fn encode<'a>(&self, json: &'a [u8]) -> Result<Cow<'a, [u8]>, Error>;
+
+    /// The JSON message for these bytes read from the wire
+    fn decode<'a>(&self, wire: &'a [u8]) -> Result<Cow<'a, [u8]>, Error>;
+}
+
+/// The messages are sent as they are serialized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Json;
+
+impl WireCodec for Json {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn encode<'a>(&self, json: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+        Ok(Cow::Borrowed(json))
+    }
+
+    fn decode<'a>(&self, wire: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+        Ok(Cow::Borrowed(wire))
+    }
+}
+
+/// The messages are sent as canonical JSON: without whitespace and with the keys of the
+/// objects sorted, so that two ends serializing the same message send the same bytes (to
+/// sign or hash them). Anything JSON is accepted when decoding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CanonicalJson;
+
+impl WireCodec for CanonicalJson {
+    fn name(&self) -> &'static str {
+        "canonical_json"
+    }
+
+    fn encode<'a>(&self, json: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+        // The keys of the objects of a Value are sorted
+        let value: serde_json::Value = serde_json::from_slice(json)?;
+        Ok(Cow::Owned(serde_json::to_vec(&value)?))
+    }
+
+    fn decode<'a>(&self, wire: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+        Ok(Cow::Borrowed(wire))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::local_endpoints,
+        message::{
+            coordinator::{GetSigs, Sigs},
+            Request, ResponseResult,
+        },
+        transport::KKTransport,
+    };
+
+    use bitcoin::hashes::hex::{FromHex, ToHex};
+    use std::{sync::Arc, thread};
+
+    // Hex encodes the messages, to check they are not sent as JSON
+    #[derive(Debug)]
+    struct Hex;
+
+    impl WireCodec for Hex {
+        fn name(&self) -> &'static str {
+            "hex"
+        }
+
+        fn encode<'a>(&self, json: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+            Ok(Cow::Owned(json.to_hex().into_bytes()))
+        }
+
+        fn decode<'a>(&self, wire: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+            let hex = String::from_utf8_lossy(wire);
+            Vec::from_hex(hex.trim_end()).map(Cow::Owned).map_err(|e| {
+                Error::Transport(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    e.to_string(),
+                ))
+            })
+        }
+    }
+
+    #[test]
+    fn custom_codec() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            transport.set_codec(Some(Arc::new(Hex)));
+            let req = Request::from(GetSigs {
+                id: Default::default(),
+                if_none_match: None,
+            });
+            let sigs: Sigs = transport.send_req(&req).unwrap();
+            assert!(sigs.signatures.is_empty());
+            // Not understood by a peer not using the codec
+            assert!(transport.send_req::<Sigs>(&req).is_err());
+        });
+
+        let mut transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        transport.set_codec(Some(Arc::new(Hex)));
+        transport.set_padding(Some(Default::default()));
+        transport
+            .read_req(|_| {
+                Some(ResponseResult::Sigs(Sigs {
+                    signatures: Default::default(),
+                }))
+            })
+            .unwrap();
+        transport.set_codec(None);
+        assert!(transport.read_req(|_| None).is_err());
+        drop(transport);
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn canonical_json() {
+        let json = br#"{ "params": {"txid": "00", "pubkey": "02"}, "method": "sig" }"#;
+        assert_eq!(
+            CanonicalJson.encode(json).unwrap().as_ref(),
+            br#"{"method":"sig","params":{"pubkey":"02","txid":"00"}}"#
+        );
+        assert!(CanonicalJson.encode(b"{").is_err());
+        assert_eq!(CanonicalJson.decode(json).unwrap().as_ref(), json);
+        assert_eq!(Json.encode(json).unwrap().as_ref(), json);
+    }
+}
@@ -0,0 +1,983 @@
+//! Transport configuration
+//!
+//! The tunables of a connection (timeouts, the largest message accepted, keepalive,
+//! bandwidth limits, padding, the buffers to use, a [Prelude] to exchange, a proxy to
+//! connect through, the encoding on the wire and the limits checked on the messages read)
+//! are gathered in a [TransportConfig], given once to
+//! [KKTransport::connect_with_config], [KKTransport::connect_host_with_config] or
+//! [KKTransport::accept_with_config] rather than set one by one on the established
+//! connection.
+//!
+//! The proxy is a SOCKS5 proxy, such as the one of a Tor daemon. Only its `CONNECT`
+//! command is supported, without authentication except to isolate the streams of a Tor
+//! daemon.
+//!
+//! The time given to the peers to answer the requests of the clients and of the
+//! [ConnectionManager](crate::connections::ConnectionManager) may also be set per
+//! method, with [MethodTimeouts].
+//!
+//! Connecting and enacting the handshake through Tor, notably with onion services, is
+//! much slower and less reliable than over TCP: a [TorRetry] policy gives it more time,
+//! and retries the attempts failing because of a broken circuit on a new one.
+
+use crate::{
+    codec::WireCodec,
+    error::Error,
+    instrument::log_debug,
+    noise::NOISE_PLAINTEXT_MAX_SIZE,
+    padding::{CoverTraffic, Padding},
+    pool::BufferPool,
+    prelude::Prelude,
+    throttle::RateLimiter,
+    transport::KKTransport,
+    validation::ValidationConfig,
+};
+
+use std::{
+    cmp,
+    collections::BTreeMap,
+    error, fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTH: u8 = 0;
+const SOCKS_USERPASS_AUTH: u8 = 2;
+const SOCKS_USERPASS_VERSION: u8 = 1;
+const SOCKS_CONNECT: u8 = 1;
+const SOCKS_SUCCEEDED: u8 = 0;
+const SOCKS_ATYP_IPV4: u8 = 1;
+const SOCKS_ATYP_DOMAIN: u8 = 3;
+const SOCKS_ATYP_IPV6: u8 = 4;
+
+// The replies of a Tor daemon telling the circuit to the destination broke down or
+// could not be built: general failure, host unreachable, TTL expired, and with the
+// `ExtendedErrors` flag the onion service descriptor not found, the introduction or
+// rendezvous failing and the introduction timing out.
+const TOR_CIRCUIT_FAILURES: [u8; 7] = [0x01, 0x04, 0x06, 0xf0, 0xf2, 0xf3, 0xf7];
+
+/// How to retry connecting through Tor (see [TransportConfig::with_tor_retry]).
+///
+/// The attempts failing because of the circuit (the Tor daemon reporting it could not
+/// reach the destination, or the connection dropping during the handshake) are retried
+/// on a new circuit. Those timing out are retried on the same circuit a few times
+/// first. Any other failure, such as the proxy not running, the destination refusing
+/// the connection or the handshake failing, is not retried.
+///
+/// By default, each attempt may take 60 seconds, and up to 2 attempts are made on each
+/// of up to 3 circuits, 2 seconds apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TorRetry {
+    attempt_timeout: Duration,
+    circuits: u32,
+    attempts_per_circuit: u32,
+    delay: Duration,
+}
+
+impl Default for TorRetry {
+    fn default() -> Self {
+        Self {
+            attempt_timeout: Duration::from_secs(60),
+            circuits: 3,
+            attempts_per_circuit: 2,
+            delay: Duration::from_secs(2),
+        }
+    }
+}
+
+impl TorRetry {
+    /// The default policy
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long an attempt, connecting through the proxy and the handshake, may take.
+    /// This replaces the connect timeout of the [TransportConfig].
+    pub fn attempt_timeout(mut self, attempt_timeout: Duration) -> Self {
+        self.attempt_timeout = attempt_timeout;
+        self
+    }
+
+    /// The maximum number of circuits to try, including the first one
+    pub fn circuits(mut self, circuits: u32) -> Self {
+        self.circuits = cmp::max(circuits, 1);
+        self
+    }
+
+    /// The maximum number of attempts on each circuit
+    pub fn attempts_per_circuit(mut self, attempts: u32) -> Self {
+        self.attempts_per_circuit = cmp::max(attempts, 1);
+        self
+    }
+
+    /// How long to wait for between two attempts
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+// What to make of a failed attempt through Tor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TorFailure {
+    // Retry on a new circuit
+    Circuit,
+    // Retry on the same circuit
+    Timeout,
+    // Don't retry
+    Fatal,
+}
+
+impl TorFailure {
+    fn of(error: &Error) -> Self {
+        let io_error = match error {
+            Error::Timeout(_) => return TorFailure::Timeout,
+            Error::Disconnected(_) => return TorFailure::Circuit,
+            Error::Transport(e) => e,
+            _ => return TorFailure::Fatal,
+        };
+        if let Some(reply) = io_error
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<SocksReplyError>())
+        {
+            return if TOR_CIRCUIT_FAILURES.contains(&reply.code) {
+                TorFailure::Circuit
+            } else {
+                TorFailure::Fatal
+            };
+        }
+        match io_error.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => TorFailure::Timeout,
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => TorFailure::Circuit,
+            _ => TorFailure::Fatal,
+        }
+    }
+}
+
+// A SOCKS5 proxy could not connect to the destination
+#[derive(Debug)]
+struct SocksReplyError {
+    target: String,
+    code: u8,
+}
+
+impl fmt::Display for SocksReplyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SOCKS proxy could not connect to '{}' (reply '{}')",
+            self.target, self.code
+        )
+    }
+}
+
+impl error::Error for SocksReplyError {}
+
+/// The configuration of a connection.
+///
+/// By default, like [KKTransport::connect], connecting and the handshake must complete
+/// within 20 seconds, reads time out after 20 seconds and nothing else is set: writes
+/// don't time out, messages up to the Noise maximum are accepted, there is no keepalive,
+/// bandwidth limit or padding, the [global](BufferPool::global) buffers are used, no
+/// prelude is exchanged, the messages are sent as serialized, the ones read are checked
+/// against the limits of the thread reading them and the connection is direct.
+#[derive(Debug, Clone)]
+pub struct TransportConfig {
+    connect_timeout: Duration,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    max_message_size: usize,
+    cover: Option<CoverTraffic>,
+    padding: Option<Padding>,
+    inbound_limit: Option<RateLimiter>,
+    outbound_limit: Option<RateLimiter>,
+    buffer_pool: Option<Arc<BufferPool>>,
+    prelude: Option<Prelude>,
+    proxy: Option<SocketAddr>,
+    tor_retry: Option<TorRetry>,
+    codec: Option<Arc<dyn WireCodec>>,
+    first_id: Option<u32>,
+    validation: Option<ValidationConfig>,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(20),
+            read_timeout: Some(Duration::from_secs(20)),
+            write_timeout: None,
+            max_message_size: NOISE_PLAINTEXT_MAX_SIZE,
+            cover: None,
+            padding: None,
+            inbound_limit: None,
+            outbound_limit: None,
+            buffer_pool: None,
+            prelude: None,
+            proxy: None,
+            tor_retry: None,
+            codec: None,
+            first_id: None,
+            validation: None,
+        }
+    }
+}
+
+impl TransportConfig {
+    /// The default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long connecting (to the proxy and through it, if any) and the handshake may
+    /// take
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// The timeout of the reads once connected, `None` for blocking indefinitely
+    pub fn with_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// The timeout of the writes once connected, `None` for blocking indefinitely
+    pub fn with_write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Refuse to read messages larger than this size (see
+    /// [KKTransport::set_max_message_size])
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Send a dummy frame after this much idle time while waiting for a frame, so that
+    /// the NATs and proxies on the way don't drop the connection. This is a constant
+    /// [CoverTraffic], and replaces the one set if any.
+    pub fn with_keepalive(self, interval: Duration) -> Self {
+        self.with_cover_traffic(CoverTraffic::new(interval, interval))
+    }
+
+    /// Send dummy frames at these intervals (see [KKTransport::set_cover_traffic])
+    pub fn with_cover_traffic(mut self, cover: CoverTraffic) -> Self {
+        self.cover = Some(cover);
+        self
+    }
+
+    /// Pad the messages sent (see [KKTransport::set_padding])
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.padding = Some(padding);
+        self
+    }
+
+    /// Limit the rate of the bytes read and written (see
+    /// [KKTransport::set_rate_limits]). `None` doesn't limit it.
+    pub fn with_rate_limits(
+        mut self,
+        inbound: Option<RateLimiter>,
+        outbound: Option<RateLimiter>,
+    ) -> Self {
+        self.inbound_limit = inbound;
+        self.outbound_limit = outbound;
+        self
+    }
+
+    /// Take the buffers for the frames from this pool (see
+    /// [KKTransport::set_buffer_pool])
+    pub fn with_buffer_pool(mut self, pool: Arc<BufferPool>) -> Self {
+        self.buffer_pool = Some(pool);
+        self
+    }
+
+    /// Exchange this [Prelude] before the handshake (see
+    /// [KKTransport::connect_with_prelude] and [KKTransport::accept_with_prelude])
+    pub fn with_prelude(mut self, prelude: Prelude) -> Self {
+        self.prelude = Some(prelude);
+        self
+    }
+
+    /// Encode the messages on the wire with this codec (see [KKTransport::set_codec])
+    pub fn with_codec(mut self, codec: Arc<dyn WireCodec>) -> Self {
+        self.codec = Some(codec);
+        self
+    }
+
+    /// Number the requests from a counter starting at this id (see
+    /// [KKTransport::set_sequential_ids])
+    pub fn with_sequential_ids(mut self, first_id: u32) -> Self {
+        self.first_id = Some(first_id);
+        self
+    }
+
+    /// Check the messages read against the limits of this [ValidationConfig] rather
+    /// than against the ones of the thread reading them (see
+    /// [KKTransport::set_validation_config])
+    pub fn with_validation(mut self, validation: ValidationConfig) -> Self {
+        self.validation = Some(validation);
+        self
+    }
+
+    /// Connect through the SOCKS5 proxy at this address. Only used to connect, not to
+    /// accept connections.
+    pub fn with_proxy(mut self, proxy: SocketAddr) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Connect through the proxy as through a Tor daemon, retrying the failed attempts
+    /// according to this [TorRetry] policy. Only used with a proxy (see
+    /// [TransportConfig::with_proxy]).
+    pub fn with_tor_retry(mut self, retry: TorRetry) -> Self {
+        self.tor_retry = Some(retry);
+        self
+    }
+
+    pub(crate) fn connect_timeout(&self) -> Duration {
+        self.connect_timeout
+    }
+
+    pub(crate) fn prelude(&self) -> Option<&Prelude> {
+        self.prelude.as_ref()
+    }
+
+    pub(crate) fn proxy(&self) -> Option<SocketAddr> {
+        self.proxy
+    }
+
+    // Establish the connection to this address, through the proxy if any, with a read
+    // timeout for the handshake, and enact it
+    pub(crate) fn connect<F>(
+        &self,
+        addr: SocketAddr,
+        mut handshake: F,
+    ) -> Result<KKTransport, Error>
+    where
+        F: FnMut(TcpStream) -> Result<KKTransport, Error>,
+    {
+        match self.proxy {
+            Some(proxy) => {
+                self.connect_proxied(proxy, &addr.ip().to_string(), addr.port(), handshake)
+            }
+            None => {
+                let stream = TcpStream::connect_timeout(&addr, self.connect_timeout)?;
+                stream.set_read_timeout(Some(self.connect_timeout))?;
+                handshake(stream)
+            }
+        }
+    }
+
+    // Establish the connection to this host through this SOCKS5 proxy, with a read
+    // timeout for the handshake, and enact it. Through Tor, failed attempts are retried.
+    pub(crate) fn connect_proxied<F>(
+        &self,
+        proxy: SocketAddr,
+        host: &str,
+        port: u16,
+        mut handshake: F,
+    ) -> Result<KKTransport, Error>
+    where
+        F: FnMut(TcpStream) -> Result<KKTransport, Error>,
+    {
+        let retry = match self.tor_retry {
+            Some(retry) => retry,
+            None => {
+                let stream = socks5_stream(proxy, host, port, self.connect_timeout, None)?;
+                return handshake(stream);
+            }
+        };
+
+        let mut circuit = 0;
+        loop {
+            circuit += 1;
+            // A Tor daemon isolates the streams with different credentials on different
+            // circuits
+            let isolation = new_isolation_token();
+            let mut attempt = 0;
+
+            loop {
+                attempt += 1;
+                let error =
+                    match socks5_stream(proxy, host, port, retry.attempt_timeout, Some(&isolation))
+                        .and_then(&mut handshake)
+                    {
+                        Ok(transport) => return Ok(transport),
+                        Err(e) => e,
+                    };
+
+                let failure = TorFailure::of(&error);
+                let last_circuit = circuit >= retry.circuits;
+                let new_circuit = failure == TorFailure::Circuit
+                    || (failure == TorFailure::Timeout && attempt >= retry.attempts_per_circuit);
+                if failure == TorFailure::Fatal || (new_circuit && last_circuit) {
+                    return Err(error);
+                }
+                log_debug!(
+                    "Attempt {} on circuit {}/{} to '{}:{}' failed with '{}', retrying on {} circuit",
+                    attempt,
+                    circuit,
+                    retry.circuits,
+                    host,
+                    port,
+                    error,
+                    if new_circuit { "a new" } else { "the same" }
+                );
+                thread::sleep(retry.delay);
+                if new_circuit {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Set up an established connection
+    pub(crate) fn apply(&self, transport: &mut KKTransport) -> Result<(), Error> {
+        transport.set_read_timeout(self.read_timeout)?;
+        transport.set_write_timeout(self.write_timeout)?;
+        transport.set_max_message_size(self.max_message_size);
+        transport.set_cover_traffic(self.cover);
+        transport.set_padding(self.padding.clone());
+        transport.set_rate_limits(self.inbound_limit.clone(), self.outbound_limit.clone());
+        if let Some(pool) = &self.buffer_pool {
+            transport.set_buffer_pool(pool.clone());
+        }
+        transport.set_codec(self.codec.clone());
+        transport.set_sequential_ids(self.first_id);
+        transport.set_validation_config(self.validation.clone());
+        Ok(())
+    }
+}
+
+/// The time given to a peer to answer a request, per method (see
+/// [method](crate::message::method)). A `sign` request to a cosigning server checking
+/// the transaction thoroughly may take seconds to be answered, while a `get_sigs` one
+/// should take much less.
+///
+/// The requests for a method without a timeout of its own are given the default one,
+/// if set, otherwise the read timeout of the connection applies. The read timeout of
+/// the connection is restored once the response is read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MethodTimeouts {
+    default: Option<Duration>,
+    timeouts: BTreeMap<String, Duration>,
+}
+
+impl MethodTimeouts {
+    /// No timeout for any method
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the timeout of the methods without one of their own
+    pub fn with_default(mut self, timeout: Duration) -> Self {
+        self.default = Some(timeout);
+        self
+    }
+
+    /// Set the timeout of the requests for this method
+    pub fn with(mut self, method: &str, timeout: Duration) -> Self {
+        self.timeouts.insert(method.to_string(), timeout);
+        self
+    }
+
+    /// The timeout of the requests for this method, if any
+    pub fn get(&self, method: &str) -> Option<Duration> {
+        self.timeouts.get(method).copied().or(self.default)
+    }
+
+    // Run this exchange of a request for this method over this transport with the
+    // method's timeout, restoring the read timeout of the connection afterward
+    pub(crate) fn run<T>(
+        &self,
+        transport: &mut KKTransport,
+        method: &str,
+        exchange: impl FnOnce(&mut KKTransport) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let timeout = match self.get(method) {
+            Some(timeout) => timeout,
+            None => return exchange(transport),
+        };
+        let previous = transport.read_timeout()?;
+        transport.set_read_timeout(Some(timeout))?;
+        let res = exchange(transport);
+        transport.set_read_timeout(previous)?;
+        res
+    }
+}
+
+// A random token to isolate a stream from the others
+fn new_isolation_token() -> String {
+    let mut token = [0u8; 8];
+    getrandom::getrandom(&mut token).expect("Failed to get randomness");
+    token.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Connect to this host through the SOCKS5 proxy at this address, with these timeout
+// and isolation token
+fn socks5_stream(
+    proxy: SocketAddr,
+    host: &str,
+    port: u16,
+    timeout: Duration,
+    isolation: Option<&str>,
+) -> Result<TcpStream, Error> {
+    let mut stream = TcpStream::connect_timeout(&proxy, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    socks5_connect(&mut stream, host, port, isolation).map_err(Error::Transport)?;
+    stream.set_write_timeout(None)?;
+    Ok(stream)
+}
+
+fn socks_error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg)
+}
+
+// Ask the SOCKS5 proxy on the other end of this stream to connect to this host. With an
+// isolation token, it is given as credentials for a Tor daemon to use a circuit of its
+// own.
+fn socks5_connect<S: Read + Write>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    isolation: Option<&str>,
+) -> io::Result<()> {
+    let auth = if isolation.is_some() {
+        SOCKS_USERPASS_AUTH
+    } else {
+        SOCKS_NO_AUTH
+    };
+    stream.write_all(&[SOCKS_VERSION, 1, auth])?;
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method)?;
+    if method != [SOCKS_VERSION, auth] {
+        return Err(socks_error(format!(
+            "SOCKS proxy refused our authentication method: '{:x?}'",
+            method
+        )));
+    }
+    if let Some(token) = isolation {
+        // Username and password (RFC 1929), both the token
+        let mut auth_request = vec![SOCKS_USERPASS_VERSION, token.len() as u8];
+        auth_request.extend_from_slice(token.as_bytes());
+        auth_request.push(token.len() as u8);
+        auth_request.extend_from_slice(token.as_bytes());
+        stream.write_all(&auth_request)?;
+        let mut status = [0u8; 2];
+        stream.read_exact(&mut status)?;
+        if status[1] != 0 {
+            return Err(socks_error(format!(
+                "SOCKS proxy refused our credentials (status '{}')",
+                status[1]
+            )));
+        }
+    }
+
+    let mut request = vec![SOCKS_VERSION, SOCKS_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(SOCKS_ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(SOCKS_ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > u8::MAX as usize {
+                return Err(socks_error(format!("Hostname '{}' is too long", host)));
+            }
+            request.push(SOCKS_ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != SOCKS_VERSION || reply[1] != SOCKS_SUCCEEDED {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            SocksReplyError {
+                target: format!("{}:{}", host, port),
+                code: reply[1],
+            },
+        ));
+    }
+    // Skip the address the proxy bound
+    let addr_len = match reply[3] {
+        SOCKS_ATYP_IPV4 => 4,
+        SOCKS_ATYP_IPV6 => 16,
+        SOCKS_ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        atyp => {
+            return Err(socks_error(format!(
+                "SOCKS proxy replied with an unknown address type '{}'",
+                atyp
+            )))
+        }
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::MessageError, fixtures::local_endpoints};
+
+    use std::{net::TcpListener, thread};
+
+    #[test]
+    fn transport_config() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let server_thread = thread::spawn(move || {
+            let config = TransportConfig::new()
+                .with_read_timeout(None)
+                .with_max_message_size(100);
+            let mut transport = KKTransport::accept_with_config(
+                &listener,
+                &server_privkey,
+                &[client_pubkey],
+                &config,
+            )
+            .unwrap();
+            assert_eq!(transport.read_timeout().unwrap(), None);
+            assert_eq!(transport.pubread().unwrap(), vec![b'a'; 100]);
+            assert!(matches!(
+                transport.pubread(),
+                Err(Error::Message(MessageError::TooLargeMessage {
+                    size: 101,
+                    max_size: 100
+                }))
+            ));
+            // The connection is still usable
+            assert_eq!(transport.pubread().unwrap(), b"ok");
+        });
+
+        let config = TransportConfig::new()
+            .with_read_timeout(Some(Duration::from_secs(3)))
+            .with_keepalive(Duration::from_secs(1));
+        let mut transport =
+            KKTransport::connect_with_config(addr, &client_privkey, &server_pubkey, &config)
+                .unwrap();
+        assert_eq!(
+            transport.read_timeout().unwrap(),
+            Some(Duration::from_secs(3))
+        );
+        transport.pubwrite(&[b'a'; 100]).unwrap();
+        transport.pubwrite(&[b'a'; 101]).unwrap();
+        transport.pubwrite(b"ok").unwrap();
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn validation_config() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let approve_spend = crate::message::watchtower::ApproveSpend {
+            deposit_outpoints: vec![Default::default()],
+            spend_tx: bitcoin::Transaction {
+                version: 2,
+                lock_time: 0,
+                input: vec![Default::default()],
+                output: vec![Default::default()],
+            },
+        };
+
+        let server_thread = thread::spawn(move || {
+            let config = TransportConfig::new().with_validation(ValidationConfig {
+                max_tx_weight: 100,
+                ..ValidationConfig::default()
+            });
+            let mut transport = KKTransport::accept_with_config(
+                &listener,
+                &server_privkey,
+                &[client_pubkey],
+                &config,
+            )
+            .unwrap();
+            // Whatever the limits of this thread
+            let default = ValidationConfig::default();
+            assert!(matches!(
+                default.scope(|| transport.read_request()),
+                Err(Error::Json(_))
+            ));
+            transport.set_validation_config(None);
+            transport.read_request().unwrap();
+        });
+
+        let mut transport = KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        for _ in 0..2 {
+            let request = transport.request(approve_spend.clone());
+            transport.write_req(&request).unwrap();
+        }
+        server_thread.join().unwrap();
+    }
+
+    // A SOCKS5 proxy connecting to the server at this address, whatever the host asked
+    fn socks5_proxy(server_addr: SocketAddr) -> (SocketAddr, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            client.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [SOCKS_VERSION, 1, SOCKS_NO_AUTH]);
+            client.write_all(&[SOCKS_VERSION, SOCKS_NO_AUTH]).unwrap();
+
+            let mut request = [0u8; 5];
+            client.read_exact(&mut request).unwrap();
+            assert_eq!(
+                request[..4],
+                [SOCKS_VERSION, SOCKS_CONNECT, 0, SOCKS_ATYP_DOMAIN]
+            );
+            let mut host = vec![0u8; request[4] as usize + 2];
+            client.read_exact(&mut host).unwrap();
+            let port = u16::from_be_bytes([host[host.len() - 2], host[host.len() - 1]]);
+            host.truncate(host.len() - 2);
+            client
+                .write_all(&[
+                    SOCKS_VERSION,
+                    SOCKS_SUCCEEDED,
+                    0,
+                    SOCKS_ATYP_IPV4,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                ])
+                .unwrap();
+
+            forward(client, server_addr);
+            format!("{}:{}", String::from_utf8(host).unwrap(), port)
+        });
+        (addr, handle)
+    }
+
+    // Forward this connection to the server at this address until either end closes it
+    fn forward(client: TcpStream, server_addr: SocketAddr) {
+        let server = TcpStream::connect(server_addr).unwrap();
+        let (mut client_r, mut server_w) =
+            (client.try_clone().unwrap(), server.try_clone().unwrap());
+        let (mut server_r, mut client_w) = (server, client);
+        let forward = thread::spawn(move || io::copy(&mut client_r, &mut server_w));
+        let _ = io::copy(&mut server_r, &mut client_w);
+        let _ = forward.join();
+    }
+
+    // A Tor daemon answering the successive connection attempts with these replies, a
+    // `None` one succeeding but never reaching the server. Returns the isolation token
+    // of each attempt.
+    fn tor_proxy(
+        server_addr: SocketAddr,
+        replies: Vec<Option<u8>>,
+    ) -> (SocketAddr, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut isolations, mut hanging) = (Vec::new(), Vec::new());
+            for reply in replies {
+                let (mut client, _) = listener.accept().unwrap();
+                let mut greeting = [0u8; 3];
+                client.read_exact(&mut greeting).unwrap();
+                assert_eq!(greeting, [SOCKS_VERSION, 1, SOCKS_USERPASS_AUTH]);
+                client
+                    .write_all(&[SOCKS_VERSION, SOCKS_USERPASS_AUTH])
+                    .unwrap();
+                let mut len = [0u8; 2];
+                client.read_exact(&mut len).unwrap();
+                let mut username = vec![0u8; len[1] as usize];
+                client.read_exact(&mut username).unwrap();
+                client.read_exact(&mut len[..1]).unwrap();
+                let mut password = vec![0u8; len[0] as usize];
+                client.read_exact(&mut password).unwrap();
+                assert_eq!(username, password);
+                client.write_all(&[SOCKS_USERPASS_VERSION, 0]).unwrap();
+                isolations.push(String::from_utf8(username).unwrap());
+
+                let mut request = [0u8; 5];
+                client.read_exact(&mut request).unwrap();
+                let mut host = vec![0u8; request[4] as usize + 2];
+                client.read_exact(&mut host).unwrap();
+                let code = reply.unwrap_or(SOCKS_SUCCEEDED);
+                client
+                    .write_all(&[SOCKS_VERSION, code, 0, SOCKS_ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                    .unwrap();
+                match reply {
+                    None => hanging.push(client),
+                    Some(SOCKS_SUCCEEDED) => forward(client, server_addr),
+                    Some(_) => {}
+                }
+            }
+            isolations
+        });
+        (addr, handle)
+    }
+
+    struct MockStream {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn socks5_proxy_connection() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let (proxy_addr, proxy_thread) = socks5_proxy(addr);
+
+        let server_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+            assert_eq!(transport.pubread().unwrap(), b"through the proxy");
+        });
+
+        let config = TransportConfig::new().with_proxy(proxy_addr);
+        let mut transport = KKTransport::connect_host_with_config(
+            "coordinator.onion",
+            8383,
+            &client_privkey,
+            &server_pubkey,
+            &config,
+        )
+        .unwrap();
+        transport.pubwrite(b"through the proxy").unwrap();
+        server_thread.join().unwrap();
+        drop(transport);
+        assert_eq!(proxy_thread.join().unwrap(), "coordinator.onion:8383");
+
+        // A proxy failing to connect, or requiring authentication
+        let mut stream = MockStream {
+            input: io::Cursor::new(vec![SOCKS_VERSION, SOCKS_NO_AUTH, SOCKS_VERSION, 4, 0]),
+            output: Vec::new(),
+        };
+        assert!(socks5_connect(&mut stream, "127.0.0.1", 8383, None).is_err());
+        assert_eq!(
+            stream.output[3..],
+            [
+                SOCKS_VERSION,
+                SOCKS_CONNECT,
+                0,
+                SOCKS_ATYP_IPV4,
+                127,
+                0,
+                0,
+                1,
+                0x20,
+                0xbf
+            ]
+        );
+        let mut stream = MockStream {
+            input: io::Cursor::new(vec![SOCKS_VERSION, 2]),
+            output: Vec::new(),
+        };
+        assert!(socks5_connect(&mut stream, "127.0.0.1", 8383, None).is_err());
+    }
+
+    #[test]
+    fn tor_retry() {
+        let (
+            (client_pubkey, client_privkey),
+            (server_pubkey, server_privkey),
+            listener,
+            server_addr,
+        ) = local_endpoints();
+        let server_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+            assert_eq!(transport.pubread().unwrap(), b"through Tor");
+        });
+
+        // The circuit expires, then the connection on a new one times out and is
+        // retried on the same one
+        let (proxy_addr, proxy_thread) =
+            tor_proxy(server_addr, vec![Some(0x06), None, Some(SOCKS_SUCCEEDED)]);
+        let retry = TorRetry::new()
+            .attempt_timeout(Duration::from_millis(200))
+            .delay(Duration::from_millis(0));
+        let config = TransportConfig::new()
+            .with_proxy(proxy_addr)
+            .with_tor_retry(retry);
+        let mut transport = KKTransport::connect_host_with_config(
+            "coordinator.onion",
+            8383,
+            &client_privkey,
+            &server_pubkey,
+            &config,
+        )
+        .unwrap();
+        transport.pubwrite(b"through Tor").unwrap();
+        server_thread.join().unwrap();
+        drop(transport);
+        let isolations = proxy_thread.join().unwrap();
+        assert_ne!(isolations[0], isolations[1]);
+        assert_eq!(isolations[1], isolations[2]);
+
+        // The onion service refusing the connection is not retried
+        let (proxy_addr, proxy_thread) = tor_proxy(server_addr, vec![Some(0x05)]);
+        let config = TransportConfig::new()
+            .with_proxy(proxy_addr)
+            .with_tor_retry(retry);
+        let err = KKTransport::connect_host_with_config(
+            "coordinator.onion",
+            8383,
+            &client_privkey,
+            &server_pubkey,
+            &config,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("(reply '5')"));
+        assert_eq!(proxy_thread.join().unwrap().len(), 1);
+
+        // Nor are the attempts once out of circuits
+        let (proxy_addr, proxy_thread) = tor_proxy(server_addr, vec![Some(0xf2), Some(0xf3)]);
+        let config = TransportConfig::new()
+            .with_proxy(proxy_addr)
+            .with_tor_retry(retry.circuits(2));
+        KKTransport::connect_host_with_config(
+            "coordinator.onion",
+            8383,
+            &client_privkey,
+            &server_pubkey,
+            &config,
+        )
+        .unwrap_err();
+        assert_eq!(proxy_thread.join().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,570 @@
+//! Connection management
+//!
+//! A [ConnectionManager] maintains long-lived connections to a configured set of
+//! peers (the coordinator, the watchtowers, the cosigning servers..), reconnecting
+//! to them as needed.
+//!
+//! Requests can also be queued on it with a [Priority], and sent by
+//! [ConnectionManager::flush] from the most urgent: an emergency message queued behind
+//! a batch of signatures to upload is sent first.
+//!
+//! In recovery mode (see [ConnectionManager::with_recovery]), a session that breaks
+//! while waiting for a response is re-established and the request sent again.
+//!
+//! The time given to the peers to answer may be set per method, with
+//! [ConnectionManager::with_method_timeouts].
+
+use crate::{
+    client::is_connection_failure,
+    config::MethodTimeouts,
+    error::Error,
+    events::{self, ConnectionEvent},
+    instrument::{log_debug, log_warn},
+    message::{method::Peer, Request},
+    noise::{PublicKey, SecretKey},
+    transport::KKTransport,
+};
+
+use std::net::SocketAddr;
+
+/// How to reach a peer
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerConfig {
+    /// The role of this peer in the Revault network
+    pub role: Peer,
+    /// The address to connect to
+    pub addr: SocketAddr,
+    /// The static Noise public key of the peer
+    pub noise_pubkey: PublicKey,
+}
+
+/// How urgent a queued request is. Within a priority, requests are sent in the order
+/// they were queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// Bulk uploads, which may wait
+    Low,
+    /// Routine requests
+    #[default]
+    Normal,
+    /// Requests which must not wait, such as the ones of an emergency
+    High,
+}
+
+#[derive(Debug)]
+struct Queued {
+    noise_pubkey: PublicKey,
+    req: Request<'static>,
+    priority: Priority,
+}
+
+/// A queued request sent by [ConnectionManager::flush]
+#[derive(Debug)]
+pub struct Flushed {
+    /// The static Noise public key of the peer it was sent to
+    pub noise_pubkey: PublicKey,
+    /// The request
+    pub req: Request<'static>,
+    /// The result of its response
+    pub result: Result<serde_json::Value, Error>,
+}
+
+#[derive(Debug)]
+struct ManagedPeer {
+    config: PeerConfig,
+    transport: Option<KKTransport>,
+    // Whether we were connected to this peer before
+    connected: bool,
+}
+
+impl ManagedPeer {
+    // Get the connection to this peer, reconnecting to it if needed
+    fn transport(&mut self, my_noise_privkey: &SecretKey) -> Result<&mut KKTransport, Error> {
+        if let Some(transport) = &self.transport {
+            if transport.peer_closed() {
+                log_debug!("Connection to '{}' was closed", self.config.addr);
+                self.transport = None;
+            }
+        }
+
+        if self.transport.is_none() {
+            log_debug!("Connecting to '{}'", self.config.addr);
+            self.transport = Some(KKTransport::connect(
+                self.config.addr,
+                my_noise_privkey,
+                &self.config.noise_pubkey,
+            )?);
+            if self.connected {
+                events::notify(ConnectionEvent::Reconnected {
+                    peer: self.config.noise_pubkey,
+                });
+            }
+            self.connected = true;
+        }
+
+        Ok(self.transport.as_mut().expect("Just set"))
+    }
+}
+
+/// The status of the connection to a peer
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerStatus {
+    /// The role of this peer in the Revault network
+    pub role: Peer,
+    /// The static Noise public key of the peer
+    pub noise_pubkey: PublicKey,
+    /// Whether we are connected to this peer
+    pub connected: bool,
+}
+
+/// Maintains authenticated connections to a set of peers.
+///
+/// Connections are established lazily, when a peer is first accessed, or eagerly by
+/// [ConnectionManager::maintain]. A connection that was closed by the peer, or that
+/// failed while sending a request, is re-established the next time the peer is
+/// accessed.
+#[derive(Debug)]
+pub struct ConnectionManager {
+    my_noise_privkey: SecretKey,
+    peers: Vec<ManagedPeer>,
+    // From the most urgent
+    queue: Vec<Queued>,
+    recovery: bool,
+    timeouts: MethodTimeouts,
+}
+
+impl ConnectionManager {
+    /// A manager for connections to these peers, using our static Noise private key.
+    /// No connection is made until the peers are accessed.
+    pub fn new(my_noise_privkey: SecretKey, peers: Vec<PeerConfig>) -> Self {
+        Self {
+            my_noise_privkey,
+            peers: peers
+                .into_iter()
+                .map(|config| ManagedPeer {
+                    config,
+                    transport: None,
+                    connected: false,
+                })
+                .collect(),
+            queue: Vec::new(),
+            recovery: false,
+            timeouts: MethodTimeouts::default(),
+        }
+    }
+
+    /// When the session with a peer breaks while waiting for a response (a decryption
+    /// failure after the peer restarted or the nonces got out of sync, or a reset
+    /// connection), re-establish it and send the unacknowledged request again, once,
+    /// instead of failing. The request keeps its id, for a peer deduplicating the
+    /// requests (see [Dedup](crate::server::Dedup)) not to handle it twice.
+    pub fn with_recovery(mut self, recovery: bool) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// Give the peers this time to answer the requests of each method sent through
+    /// [ConnectionManager::send_req] and [ConnectionManager::flush]. Otherwise the read
+    /// timeout of the connections applies.
+    pub fn with_method_timeouts(mut self, timeouts: MethodTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Get the connection to the peer with this static Noise key, (re)connecting to it
+    /// if needed. Returns `None` if this peer is not managed.
+    pub fn get(&mut self, noise_pubkey: &PublicKey) -> Option<Result<&mut KKTransport, Error>> {
+        let my_noise_privkey = &self.my_noise_privkey;
+        self.peers
+            .iter_mut()
+            .find(|peer| &peer.config.noise_pubkey == noise_pubkey)
+            .map(|peer| peer.transport(my_noise_privkey))
+    }
+
+    /// Get the connections to all the peers with this role, (re)connecting to them if
+    /// needed. Peers we fail to connect to are logged and skipped.
+    pub fn by_role(&mut self, role: Peer) -> Vec<&mut KKTransport> {
+        let my_noise_privkey = &self.my_noise_privkey;
+        self.peers
+            .iter_mut()
+            .filter(|peer| peer.config.role == role)
+            .filter_map(|peer| {
+                let addr = peer.config.addr;
+                peer.transport(my_noise_privkey)
+                    .map_err(|e| log_warn!("Error connecting to '{}': '{}'", addr, e))
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Send a request to the peer with this static Noise key and return its response.
+    /// If the request fails, the connection is dropped, to be re-established next time.
+    pub fn send_req<T>(&mut self, noise_pubkey: &PublicKey, req: &Request) -> Result<T, Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let my_noise_privkey = &self.my_noise_privkey;
+        let peer = self
+            .peers
+            .iter_mut()
+            .find(|peer| &peer.config.noise_pubkey == noise_pubkey)
+            .ok_or_else(|| {
+                Error::Transport(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "Not a managed peer",
+                ))
+            })?;
+
+        let timeouts = &self.timeouts;
+        let send = |transport: &mut KKTransport| {
+            timeouts.run(transport, req.method(), |transport| transport.send_req(req))
+        };
+
+        let mut res = send(peer.transport(my_noise_privkey)?);
+        let broken = match &res {
+            Err(e @ Error::Disconnected(_)) | Err(e @ Error::Noise(_)) if self.recovery => {
+                log_debug!(
+                    "Session with '{}' broke: '{}'. Re-establishing it.",
+                    peer.config.addr,
+                    e
+                );
+                true
+            }
+            _ => false,
+        };
+        if broken {
+            peer.transport = None;
+            res = send(peer.transport(my_noise_privkey)?);
+        }
+        if let Err(Error::Transport(_)) | Err(Error::Disconnected(_)) | Err(Error::Noise(_)) = res {
+            peer.transport = None;
+        }
+        res
+    }
+
+    /// Queue a request for the peer with this static Noise key, to be sent by
+    /// [ConnectionManager::flush] after the ones queued with a higher or the same
+    /// priority.
+    pub fn enqueue(&mut self, noise_pubkey: PublicKey, req: Request<'static>, priority: Priority) {
+        let pos = self
+            .queue
+            .iter()
+            .position(|queued| queued.priority < priority)
+            .unwrap_or(self.queue.len());
+        self.queue.insert(
+            pos,
+            Queued {
+                noise_pubkey,
+                req,
+                priority,
+            },
+        );
+    }
+
+    /// The number of queued requests
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Send the queued requests, from the most urgent, and return their responses. A
+    /// request failing because the connection to its peer failed stays queued, and the
+    /// following ones for this peer are not sent until the next flush.
+    pub fn flush(&mut self) -> Vec<Flushed> {
+        let mut flushed = Vec::new();
+        let mut unreachable: Vec<PublicKey> = Vec::new();
+        let mut kept = Vec::new();
+
+        for queued in std::mem::take(&mut self.queue) {
+            if unreachable.contains(&queued.noise_pubkey) {
+                kept.push(queued);
+                continue;
+            }
+            let result = self.send_req(&queued.noise_pubkey, &queued.req);
+            if matches!(&result, Err(e) if is_connection_failure(e)) {
+                log_debug!(
+                    "Keeping request '{}' queued: '{}'",
+                    queued.req.id(),
+                    result.as_ref().unwrap_err()
+                );
+                unreachable.push(queued.noise_pubkey);
+                flushed.push(Flushed {
+                    noise_pubkey: queued.noise_pubkey,
+                    req: queued.req.clone(),
+                    result,
+                });
+                kept.push(queued);
+                continue;
+            }
+            flushed.push(Flushed {
+                noise_pubkey: queued.noise_pubkey,
+                req: queued.req,
+                result,
+            });
+        }
+        self.queue = kept;
+
+        flushed
+    }
+
+    /// Check the health of all the connections, and (re)connect to the peers we are not
+    /// connected to. Returns the status of each peer.
+    pub fn maintain(&mut self) -> Vec<PeerStatus> {
+        let my_noise_privkey = &self.my_noise_privkey;
+        self.peers
+            .iter_mut()
+            .map(|peer| {
+                let connected = match peer.transport(my_noise_privkey) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        log_warn!("Error connecting to '{}': '{}'", peer.config.addr, e);
+                        false
+                    }
+                };
+                PeerStatus {
+                    role: peer.config.role,
+                    noise_pubkey: peer.config.noise_pubkey,
+                    connected,
+                }
+            })
+            .collect()
+    }
+
+    /// Get the status of each peer, without trying to connect to them
+    pub fn statuses(&self) -> Vec<PeerStatus> {
+        self.peers
+            .iter()
+            .map(|peer| PeerStatus {
+                role: peer.config.role,
+                noise_pubkey: peer.config.noise_pubkey,
+                connected: peer
+                    .transport
+                    .as_ref()
+                    .map(|t| !t.peer_closed())
+                    .unwrap_or(false),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::{
+            coordinator::{GetSigs, Sigs},
+            ResponseResult, SigSet,
+        },
+        server::serve,
+    };
+
+    use crate::noise::gen_keypair;
+    use bitcoin::Txid;
+    use std::{net::TcpListener, thread, time::Duration};
+
+    #[test]
+    fn connection_manager() {
+        let (client_pubkey, client_privkey) = gen_keypair();
+        let (coord_pubkey, coord_privkey) = gen_keypair();
+        let (wt_pubkey, _) = gen_keypair();
+
+        // A coordinator serving a single connection at a time, twice
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let coord_addr = listener.local_addr().unwrap();
+        let coord_thread = thread::spawn(move || {
+            let handler = |_: &PublicKey, _| {
+                Some(ResponseResult::Sigs(Sigs {
+                    signatures: SigSet::new(),
+                }))
+            };
+            // Answer a single request then close the connection
+            let mut transport =
+                KKTransport::accept(&listener, &coord_privkey, &[client_pubkey]).unwrap();
+            transport.read_req(|p| handler(&client_pubkey, p)).unwrap();
+            drop(transport);
+            let mut transport =
+                KKTransport::accept(&listener, &coord_privkey, &[client_pubkey]).unwrap();
+            serve(&mut transport, &handler).unwrap();
+        });
+        // Nobody listens for the watchtower
+        let wt_addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let mut manager = ConnectionManager::new(
+            client_privkey,
+            vec![
+                PeerConfig {
+                    role: Peer::Coordinator,
+                    addr: coord_addr,
+                    noise_pubkey: coord_pubkey,
+                },
+                PeerConfig {
+                    role: Peer::Watchtower,
+                    addr: wt_addr,
+                    noise_pubkey: wt_pubkey,
+                },
+            ],
+        );
+        assert!(manager.statuses().iter().all(|status| !status.connected));
+
+        let req = Request::from(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        let _: Sigs = manager.send_req(&coord_pubkey, &req).unwrap();
+        // The coordinator closed the connection, it's re-established
+        thread::sleep(Duration::from_millis(100));
+        assert!(!manager.statuses()[0].connected);
+        let _: Sigs = manager.send_req(&coord_pubkey, &req).unwrap();
+        assert!(manager.statuses()[0].connected);
+
+        let statuses = manager.maintain();
+        assert!(statuses[0].connected);
+        assert_eq!(statuses[1].role, Peer::Watchtower);
+        assert!(!statuses[1].connected);
+        assert_eq!(manager.by_role(Peer::Coordinator).len(), 1);
+        assert!(manager.by_role(Peer::Watchtower).is_empty());
+        assert!(manager.get(&wt_pubkey).unwrap().is_err());
+        assert!(manager.get(&client_pubkey).is_none());
+
+        drop(manager);
+        coord_thread.join().unwrap();
+    }
+
+    #[test]
+    fn session_recovery() {
+        use std::io::Write;
+
+        let (client_pubkey, client_privkey) = gen_keypair();
+        let (coord_pubkey, coord_privkey) = gen_keypair();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let coord_addr = listener.local_addr().unwrap();
+
+        // A coordinator garbling its first response, for each client
+        let coord_thread = thread::spawn(move || {
+            let mut ids = Vec::new();
+            for _ in 0..2 {
+                let mut transport =
+                    KKTransport::accept(&listener, &coord_privkey, &[client_pubkey]).unwrap();
+                let req = transport.incoming().next().unwrap().unwrap();
+                ids.push(req.id);
+                let mut stream = transport.try_clone_stream().unwrap();
+                stream.write_all(&[0xff; 64]).unwrap();
+                drop(transport);
+            }
+            // The session is re-established and the request replayed
+            let mut transport =
+                KKTransport::accept(&listener, &coord_privkey, &[client_pubkey]).unwrap();
+            let req = transport.incoming().next().unwrap().unwrap();
+            ids.push(req.id);
+            transport
+                .respond(
+                    req.id,
+                    ResponseResult::Sigs(Sigs {
+                        signatures: SigSet::new(),
+                    }),
+                )
+                .unwrap();
+            ids
+        });
+
+        let peers = vec![PeerConfig {
+            role: Peer::Coordinator,
+            addr: coord_addr,
+            noise_pubkey: coord_pubkey,
+        }];
+        let req = Request::from(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        let mut manager = ConnectionManager::new(client_privkey.clone(), peers.clone());
+        assert!(matches!(
+            manager.send_req::<Sigs>(&coord_pubkey, &req),
+            Err(Error::Noise(_))
+        ));
+        let mut manager = ConnectionManager::new(client_privkey, peers).with_recovery(true);
+        let _: Sigs = manager.send_req(&coord_pubkey, &req).unwrap();
+
+        let ids = coord_thread.join().unwrap();
+        assert_eq!(ids, vec![req.id(); 3]);
+    }
+
+    #[test]
+    fn prioritized_queue() {
+        use crate::message::RequestParams;
+        use bitcoin::hashes::Hash;
+        use std::sync::{Arc, Mutex};
+
+        let (client_pubkey, client_privkey) = gen_keypair();
+        let (coord_pubkey, coord_privkey) = gen_keypair();
+        let (wt_pubkey, _) = gen_keypair();
+        let txid = |n| Txid::from_slice(&[n; 32]).unwrap();
+
+        // A coordinator recording the order of the requests
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let coord_addr = listener.local_addr().unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let coord_received = received.clone();
+        let coord_thread = thread::spawn(move || {
+            let handler = move |_: &PublicKey, params| {
+                if let RequestParams::GetSigs(get_sigs) = params {
+                    coord_received.lock().unwrap().push(get_sigs.id);
+                }
+                Some(ResponseResult::Sigs(Sigs {
+                    signatures: SigSet::new(),
+                }))
+            };
+            let mut transport =
+                KKTransport::accept(&listener, &coord_privkey, &[client_pubkey]).unwrap();
+            serve(&mut transport, &handler).unwrap();
+        });
+        let wt_addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let mut manager = ConnectionManager::new(
+            client_privkey,
+            vec![
+                PeerConfig {
+                    role: Peer::Coordinator,
+                    addr: coord_addr,
+                    noise_pubkey: coord_pubkey,
+                },
+                PeerConfig {
+                    role: Peer::Watchtower,
+                    addr: wt_addr,
+                    noise_pubkey: wt_pubkey,
+                },
+            ],
+        );
+        let get_sigs = |n| {
+            Request::from(GetSigs {
+                id: txid(n),
+                if_none_match: None,
+            })
+        };
+        manager.enqueue(coord_pubkey, get_sigs(1), Priority::Low);
+        manager.enqueue(wt_pubkey, get_sigs(2), Priority::Normal);
+        manager.enqueue(coord_pubkey, get_sigs(3), Priority::Normal);
+        manager.enqueue(coord_pubkey, get_sigs(4), Priority::High);
+        manager.enqueue(wt_pubkey, get_sigs(5), Priority::Low);
+        assert_eq!(manager.queued(), 5);
+
+        // The most urgent first, the unreachable watchtower's requests are kept
+        let flushed = manager.flush();
+        assert_eq!(flushed.len(), 4);
+        assert!(flushed[1].result.is_err());
+        assert_eq!(flushed[1].noise_pubkey, wt_pubkey);
+        assert!(flushed
+            .iter()
+            .filter(|f| f.noise_pubkey == coord_pubkey)
+            .all(|f| f.result.is_ok()));
+        assert_eq!(*received.lock().unwrap(), vec![txid(4), txid(3), txid(1)]);
+        assert_eq!(manager.queued(), 2);
+
+        drop(manager);
+        coord_thread.join().unwrap();
+    }
+}
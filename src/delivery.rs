@@ -0,0 +1,260 @@
+//! Delivery tracking
+//!
+//! A vault is only protected once all the watchtowers (and coordinators) it relies on
+//! acknowledged its revocation signatures. A [DeliveryTracker] records which peers
+//! the signatures of a revocation transaction were sent to and which of them
+//! acknowledged them, in order to report the deliveries that are not confirmed yet.
+//! The state is kept by the user through the [Storage] trait, so that it survives
+//! restarts when it is persisted.
+
+use crate::{
+    client::{Acknowledgement, BroadcastResponses},
+    message::watchtower,
+    noise::PublicKey,
+};
+
+use bitcoin::{OutPoint, Txid};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
+    fmt,
+    sync::Mutex,
+};
+
+/// The delivery of the signatures for a revocation transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    /// The vault the transaction revokes
+    pub deposit_outpoint: OutPoint,
+    /// The revocation transaction
+    pub txid: Txid,
+    /// The peers the signatures must be acknowledged by
+    pub expected: BTreeSet<PublicKey>,
+    /// The peers that acknowledged them
+    pub acked: BTreeSet<PublicKey>,
+}
+
+impl Delivery {
+    /// The peers that did not acknowledge the signatures yet
+    pub fn pending(&self) -> impl Iterator<Item = &PublicKey> {
+        self.expected.difference(&self.acked)
+    }
+
+    /// Whether all the expected peers acknowledged the signatures
+    pub fn is_confirmed(&self) -> bool {
+        self.pending().next().is_none()
+    }
+}
+
+/// The storage backend of a [DeliveryTracker]
+pub trait Storage {
+    /// The error returned by the storage backend
+    type Error: fmt::Display;
+
+    /// Store this delivery, replacing the one for the same transaction if any
+    fn store_delivery(&self, delivery: &Delivery) -> Result<(), Self::Error>;
+
+    /// Get the delivery for this transaction, if any
+    fn get_delivery(&self, txid: &Txid) -> Result<Option<Delivery>, Self::Error>;
+
+    /// Get all the deliveries stored
+    fn deliveries(&self) -> Result<Vec<Delivery>, Self::Error>;
+
+    /// Remove the delivery for this transaction
+    fn remove_delivery(&self, txid: &Txid) -> Result<(), Self::Error>;
+}
+
+/// A [Storage] backend keeping the deliveries in memory. It doesn't survive restarts.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    deliveries: Mutex<BTreeMap<Txid, Delivery>>,
+}
+
+impl MemoryStorage {
+    /// An empty storage
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    type Error = Infallible;
+
+    fn store_delivery(&self, delivery: &Delivery) -> Result<(), Self::Error> {
+        let mut deliveries = self.deliveries.lock().expect("Poisoned lock");
+        deliveries.insert(delivery.txid, delivery.clone());
+        Ok(())
+    }
+
+    fn get_delivery(&self, txid: &Txid) -> Result<Option<Delivery>, Self::Error> {
+        let deliveries = self.deliveries.lock().expect("Poisoned lock");
+        Ok(deliveries.get(txid).cloned())
+    }
+
+    fn deliveries(&self) -> Result<Vec<Delivery>, Self::Error> {
+        let deliveries = self.deliveries.lock().expect("Poisoned lock");
+        Ok(deliveries.values().cloned().collect())
+    }
+
+    fn remove_delivery(&self, txid: &Txid) -> Result<(), Self::Error> {
+        let mut deliveries = self.deliveries.lock().expect("Poisoned lock");
+        deliveries.remove(txid);
+        Ok(())
+    }
+}
+
+/// Tracks the acknowledgements of the revocation signatures by the peers, using this
+/// storage.
+#[derive(Debug)]
+pub struct DeliveryTracker<S> {
+    storage: S,
+}
+
+impl<S: Storage> DeliveryTracker<S> {
+    /// A tracker of the deliveries stored in this storage
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+
+    /// Expect these peers to acknowledge these signatures. Peers already expected for
+    /// this transaction are kept, along with their acknowledgement.
+    pub fn track(&self, sig: &watchtower::Sig, peers: &[PublicKey]) -> Result<(), S::Error> {
+        let mut delivery = self
+            .storage
+            .get_delivery(&sig.txid)?
+            .unwrap_or_else(|| Delivery {
+                deposit_outpoint: sig.deposit_outpoint,
+                txid: sig.txid,
+                expected: BTreeSet::new(),
+                acked: BTreeSet::new(),
+            });
+        delivery.expected.extend(peers.iter().copied());
+        self.storage.store_delivery(&delivery)
+    }
+
+    /// Record that this peer acknowledged the signatures for this transaction. Returns
+    /// whether the delivery is confirmed, or `None` if it isn't tracked.
+    pub fn record_ack(&self, txid: &Txid, peer: PublicKey) -> Result<Option<bool>, S::Error> {
+        self.record_acks(txid, std::iter::once(peer))
+    }
+
+    /// Record the acknowledgements of a [broadcast](crate::client::broadcast) of the
+    /// signatures for this transaction. Returns whether the delivery is confirmed, or
+    /// `None` if it isn't tracked.
+    pub fn record_responses<T: Acknowledgement>(
+        &self,
+        txid: &Txid,
+        responses: &BroadcastResponses<T>,
+    ) -> Result<Option<bool>, S::Error> {
+        self.record_acks(txid, responses.acked())
+    }
+
+    fn record_acks(
+        &self,
+        txid: &Txid,
+        peers: impl IntoIterator<Item = PublicKey>,
+    ) -> Result<Option<bool>, S::Error> {
+        let mut delivery = match self.storage.get_delivery(txid)? {
+            Some(delivery) => delivery,
+            None => return Ok(None),
+        };
+        // Only the expected peers count
+        let acked: Vec<_> = peers
+            .into_iter()
+            .filter(|peer| delivery.expected.contains(peer))
+            .collect();
+        delivery.acked.extend(acked);
+        self.storage.store_delivery(&delivery)?;
+
+        Ok(Some(delivery.is_confirmed()))
+    }
+
+    /// The delivery of the signatures for this transaction, if it is tracked
+    pub fn delivery(&self, txid: &Txid) -> Result<Option<Delivery>, S::Error> {
+        self.storage.get_delivery(txid)
+    }
+
+    /// The deliveries some peers did not acknowledge yet
+    pub fn unconfirmed(&self) -> Result<Vec<Delivery>, S::Error> {
+        Ok(self
+            .storage
+            .deliveries()?
+            .into_iter()
+            .filter(|delivery| !delivery.is_confirmed())
+            .collect())
+    }
+
+    /// Stop tracking the delivery of the signatures for this transaction
+    pub fn forget(&self, txid: &Txid) -> Result<(), S::Error> {
+        self.storage.remove_delivery(txid)
+    }
+
+    /// Get the underlying storage
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, message::watchtower::SigResult, noise::gen_keypair};
+
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn delivery_tracking() {
+        let (wt_a, wt_b, coordinator) = (gen_keypair().0, gen_keypair().0, gen_keypair().0);
+        let sig = |byte| watchtower::Sig {
+            signatures: crate::message::SigSet::new(),
+            txid: Txid::from_slice(&[byte; 32]).unwrap(),
+            deposit_outpoint: OutPoint::default(),
+        };
+        let (first, second) = (sig(1), sig(2));
+
+        let tracker = DeliveryTracker::new(MemoryStorage::new());
+        tracker.track(&first, &[wt_a, wt_b]).unwrap();
+        tracker.track(&second, &[wt_a]).unwrap();
+        assert_eq!(tracker.unconfirmed().unwrap().len(), 2);
+        assert_eq!(tracker.record_ack(&Txid::default(), wt_a).unwrap(), None);
+
+        // Only the expected peers count
+        assert_eq!(
+            tracker.record_ack(&second.txid, coordinator).unwrap(),
+            Some(false)
+        );
+        assert_eq!(tracker.record_ack(&second.txid, wt_a).unwrap(), Some(true));
+        let unconfirmed = tracker.unconfirmed().unwrap();
+        assert_eq!(unconfirmed.len(), 1);
+        assert_eq!(unconfirmed[0].txid, first.txid);
+        assert_eq!(unconfirmed[0].pending().count(), 2);
+
+        // From the responses to a broadcast
+        let responses = BroadcastResponses(vec![
+            (
+                wt_a,
+                Ok(SigResult {
+                    ack: true,
+                    txid: first.txid,
+                }),
+            ),
+            (wt_b, Err(Error::Timeout(None))),
+        ]);
+        assert_eq!(
+            tracker.record_responses(&first.txid, &responses).unwrap(),
+            Some(false)
+        );
+        let delivery = tracker.delivery(&first.txid).unwrap().unwrap();
+        assert_eq!(delivery.pending().collect::<Vec<_>>(), vec![&wt_b]);
+
+        // A new peer to deliver to is pending, the acks are kept
+        tracker.track(&second, &[coordinator]).unwrap();
+        let delivery = tracker.delivery(&second.txid).unwrap().unwrap();
+        assert_eq!(delivery.pending().collect::<Vec<_>>(), vec![&coordinator]);
+        assert!(delivery.acked.contains(&wt_a));
+
+        tracker.forget(&first.txid).unwrap();
+        tracker.forget(&second.txid).unwrap();
+        assert!(tracker.unconfirmed().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,144 @@
+//! Hostname resolution
+//!
+//! The hostnames given to [KKTransport::connect_host](crate::transport::KKTransport::connect_host)
+//! are resolved by the system by default. Deployments that must not leak them to the
+//! system's DNS (to resolve them through Tor, or an internal resolver instead) can set
+//! their own [Resolver] with [set_resolver].
+//!
+//! IP addresses are never resolved.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    sync::{Arc, RwLock},
+};
+
+/// Resolves a hostname to the addresses to try connecting to, in order
+pub trait Resolver: Send + Sync {
+    /// The addresses of this host, with this port
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+impl<F> Resolver for F
+where
+    F: Fn(&str, u16) -> io::Result<Vec<SocketAddr>> + Send + Sync,
+{
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        self(host, port)
+    }
+}
+
+/// Resolve hostnames with the system's resolver
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        Ok((host, port).to_socket_addrs()?.collect())
+    }
+}
+
+static RESOLVER: RwLock<Option<Arc<dyn Resolver>>> = RwLock::new(None);
+
+/// Resolve the hostnames with this resolver from now on, or with the
+/// [SystemResolver] again if `None`.
+pub fn set_resolver(resolver: Option<Arc<dyn Resolver>>) {
+    *RESOLVER.write().unwrap() = resolver;
+}
+
+/// The addresses of this host, using the resolver set if any. Fails if there is none.
+pub fn resolve(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    // IP addresses need no resolution
+    if let Ok(ip) = host
+        .trim_matches(|c| c == '[' || c == ']')
+        .parse::<IpAddr>()
+    {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+
+    let resolver = RESOLVER.read().unwrap().clone();
+    let addrs = match resolver {
+        Some(resolver) => resolver.resolve(host, port)?,
+        None => SystemResolver.resolve(host, port)?,
+    };
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No address for host '{}'", host),
+        ));
+    }
+
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::local_endpoints, transport::KKTransport};
+    use std::{sync::Mutex, thread};
+
+    #[test]
+    fn resolution_hook() {
+        let queried = Arc::new(Mutex::new(Vec::new()));
+        let resolver_queried = queried.clone();
+        set_resolver(Some(Arc::new(move |host: &str, port| {
+            resolver_queried.lock().unwrap().push(host.to_string());
+            match host {
+                "coordinator.revault" => Ok(vec![SocketAddr::from(([10, 0, 0, 1], port))]),
+                _ => Ok(vec![]),
+            }
+        })));
+
+        assert_eq!(
+            resolve("coordinator.revault", 8383).unwrap(),
+            vec![SocketAddr::from(([10, 0, 0, 1], 8383))]
+        );
+        assert_eq!(
+            resolve("unknown.revault", 8383).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        // IP addresses don't go through the resolver
+        assert_eq!(
+            resolve("127.0.0.1", 1).unwrap(),
+            vec![SocketAddr::from(([127, 0, 0, 1], 1))]
+        );
+        assert_eq!(
+            resolve("[::1]", 1).unwrap(),
+            vec!["[::1]:1".parse().unwrap()]
+        );
+        assert_eq!(
+            *queried.lock().unwrap(),
+            vec!["coordinator.revault", "unknown.revault"]
+        );
+
+        // The transport connects through it
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let port = addr.port();
+        set_resolver(Some(Arc::new(|_: &str, port| {
+            Ok(vec![
+                SocketAddr::from(([127, 0, 0, 1], 1)),
+                SocketAddr::from(([127, 0, 0, 1], port)),
+            ])
+        })));
+        let server_thread = thread::spawn(move || {
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap()
+        });
+        let client =
+            KKTransport::connect_host("coordinator.revault", port, &client_privkey, &server_pubkey)
+                .unwrap();
+        assert_eq!(client.remote_static(), server_pubkey);
+        server_thread.join().unwrap();
+        assert!(KKTransport::connect_host(
+            "coordinator.revault",
+            1,
+            &client_privkey,
+            &server_pubkey
+        )
+        .is_err());
+
+        set_resolver(None);
+        assert!(resolve("localhost", 1).is_ok());
+        assert_eq!(queried.lock().unwrap().len(), 2);
+    }
+}
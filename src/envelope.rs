@@ -0,0 +1,283 @@
+//! End-to-end encrypted signatures
+//!
+//! The coordinator relays the signatures of the revocation transactions between the
+//! stakeholders, and so learns all of them along with which vaults they are for. A
+//! [SealedSig] instead carries the [Sig] message sealed (a libsodium sealed box) to the
+//! Noise static key of each of the other stakeholders: the coordinator only stores
+//! opaque blobs, and serves each stakeholder those sealed to its key with
+//! [GetSealedSigs](crate::message::coordinator::GetSealedSigs).
+//!
+//! Sealed boxes are anonymous, the sender is authenticated by the Bitcoin signature
+//! inside, which the recipient checks against the transaction as for a plain [Sig].
+//!
+//! The signatures are sealed to the Noise static keys themselves, rather than to
+//! dedicated keys the stakeholders would need to exchange and configure besides them.
+//! Both protocols only use the key for X25519 exchanges the output of which is hashed
+//! into keys bound to the protocol: Noise mixes it into its handshake state with HKDF
+//! under the name of the protocol, a sealed box derives its key with HSalsa20 from an
+//! exchange with a fresh ephemeral key and a nonce committing to both public keys.
+//! Neither reveals the shared secret nor answers with anything depending on it but
+//! whether a message is authentic, so one can't be used against the other.
+//!
+//! A coordinator in blind storage mode does not even learn which transaction the
+//! blobs are for: they are stored with [StoreBlob] under a [BlobId] derived from the
+//! txid and the recipient with a [BlobKey] the stakeholders share, so that only they
+//! can compute it (see [store_sig]).
+
+use crate::{
+    error::{Error, NoiseError},
+    message::coordinator::{
+        BlobId, Blobs, RecipientKey, SealedBlob, SealedSig, SealedSigs, Sig, StoreBlob,
+    },
+    noise::{PublicKey, SecretKey},
+};
+
+use bitcoin::{
+    hashes::{hmac, sha256, Hash, HashEngine},
+    Txid,
+};
+use sodiumoxide::{crypto::sealedbox, randombytes};
+use std::fmt;
+
+/// The key a [SealedSig] is encrypted to for this Noise static public key
+pub fn recipient_key(pubkey: &PublicKey) -> RecipientKey {
+    RecipientKey(pubkey.0)
+}
+
+/// Seal this signature to each of these stakeholders' Noise static keys (see the
+/// [module](self) documentation for why these keys are reused)
+pub fn seal_sig(sig: &Sig, recipients: &[PublicKey]) -> SealedSig {
+    let payload = serde_json::to_vec(sig).expect("Sig serialization is infallible");
+    let sealed = recipients
+        .iter()
+        .map(|pubkey| {
+            (
+                recipient_key(pubkey),
+                SealedBlob(sealedbox::seal(&payload, pubkey)),
+            )
+        })
+        .collect();
+
+    SealedSig {
+        txid: sig.id,
+        sealed,
+    }
+}
+
+/// Open a signature sealed to our Noise static key. Fails with
+/// [NoiseError::Decryption] if it was not sealed to this key or was tampered with.
+pub fn open_sig(blob: &SealedBlob, pubkey: &PublicKey, privkey: &SecretKey) -> Result<Sig, Error> {
+    let payload = sealedbox::open(&blob.0, pubkey, privkey).map_err(|()| NoiseError::Decryption)?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+/// Open all the signatures of a [SealedSigs] response for the transaction `txid`,
+/// failing if any is not for this transaction (the coordinator may not swap them).
+pub fn open_sigs(
+    txid: &Txid,
+    sigs: &SealedSigs,
+    pubkey: &PublicKey,
+    privkey: &SecretKey,
+) -> Result<Vec<Sig>, Error> {
+    open_all(txid, &sigs.sealed, pubkey, privkey)
+}
+
+/// The secret the stakeholders share to derive the identifiers of their blobs in
+/// blind storage mode. It is part of their configuration, like the keys of each other,
+/// and must not be known to the coordinator.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BlobKey(pub [u8; 32]);
+
+impl BlobKey {
+    /// A new random key, to share with the other stakeholders
+    pub fn generate() -> Self {
+        let mut key = [0; 32];
+        randombytes::randombytes_into(&mut key);
+        Self(key)
+    }
+}
+
+impl fmt::Debug for BlobKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BlobKey(<redacted>)")
+    }
+}
+
+/// The identifier the signatures for this transaction sealed to this recipient are
+/// stored under in blind storage mode. It is a MAC with the key of the stakeholders,
+/// for the coordinator not to be able to tell the transactions from their txids.
+pub fn blob_id(key: &BlobKey, txid: &Txid, recipient: &PublicKey) -> BlobId {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(&key.0);
+    engine.input(b"revault_net blob id");
+    engine.input(&txid[..]);
+    engine.input(&recipient.0);
+    BlobId(hmac::Hmac::from_engine(engine).into_inner())
+}
+
+/// Seal this signature to each of these stakeholders' Noise static keys, as blobs
+/// to store on a coordinator in blind storage mode under identifiers derived with
+/// this key
+pub fn store_sig(key: &BlobKey, sig: &Sig, recipients: &[PublicKey]) -> Vec<StoreBlob> {
+    let sealed = seal_sig(sig, recipients);
+    recipients
+        .iter()
+        .map(|pubkey| StoreBlob {
+            blob_id: blob_id(key, &sig.id, pubkey),
+            blob: sealed.sealed[&recipient_key(pubkey)].clone(),
+        })
+        .collect()
+}
+
+/// Open all the signatures of a [Blobs] response to a request for the [blob_id] of
+/// the transaction `txid` and our key, failing if any is not for this transaction.
+pub fn open_blobs(
+    txid: &Txid,
+    blobs: &Blobs,
+    pubkey: &PublicKey,
+    privkey: &SecretKey,
+) -> Result<Vec<Sig>, Error> {
+    open_all(txid, &blobs.blobs, pubkey, privkey)
+}
+
+fn open_all(
+    txid: &Txid,
+    blobs: &[SealedBlob],
+    pubkey: &PublicKey,
+    privkey: &SecretKey,
+) -> Result<Vec<Sig>, Error> {
+    blobs
+        .iter()
+        .map(|blob| {
+            let sig = open_sig(blob, pubkey, privkey)?;
+            if sig.id != *txid {
+                return Err(crate::MessageError::TxidMismatch {
+                    expected: *txid,
+                    got: sig.id,
+                }
+                .into());
+            }
+            Ok(sig)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::noise::gen_keypair;
+
+    use bitcoin::{
+        hashes::Hash,
+        secp256k1::{Message, Secp256k1, SecretKey as BitcoinSecretKey},
+    };
+
+    fn sig(id: Txid) -> Sig {
+        let secp = Secp256k1::new();
+        let privkey = BitcoinSecretKey::from_slice(&[1; 32]).unwrap();
+        Sig {
+            pubkey: bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &privkey),
+            signature: secp.sign(&Message::from_slice(&[2; 32]).unwrap(), &privkey),
+            id,
+        }
+    }
+
+    #[test]
+    fn sealed_sigs() {
+        let (alice_pubkey, alice_privkey) = gen_keypair();
+        let (bob_pubkey, bob_privkey) = gen_keypair();
+        let (eve_pubkey, eve_privkey) = gen_keypair();
+        let txid = Txid::from_slice(&[3; 32]).unwrap();
+        let sig = sig(txid);
+
+        let sealed = seal_sig(&sig, &[alice_pubkey, bob_pubkey]);
+        assert_eq!(sealed.txid, txid);
+        assert_eq!(sealed.sealed.len(), 2);
+        let alice_blob = &sealed.sealed[&recipient_key(&alice_pubkey)];
+        let bob_blob = &sealed.sealed[&recipient_key(&bob_pubkey)];
+        assert_ne!(alice_blob, bob_blob);
+        assert_eq!(
+            open_sig(alice_blob, &alice_pubkey, &alice_privkey).unwrap(),
+            sig
+        );
+        assert_eq!(open_sig(bob_blob, &bob_pubkey, &bob_privkey).unwrap(), sig);
+
+        // Only the recipient can open it, and it can't be tampered with
+        assert!(matches!(
+            open_sig(alice_blob, &eve_pubkey, &eve_privkey),
+            Err(Error::Noise(NoiseError::Decryption))
+        ));
+        let mut tampered = alice_blob.clone();
+        *tampered.0.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            open_sig(&tampered, &alice_pubkey, &alice_privkey),
+            Err(Error::Noise(NoiseError::Decryption))
+        ));
+
+        // The coordinator can't serve a signature for another transaction
+        let sigs = SealedSigs {
+            sealed: vec![alice_blob.clone()],
+        };
+        assert_eq!(
+            open_sigs(&txid, &sigs, &alice_pubkey, &alice_privkey).unwrap(),
+            vec![sig]
+        );
+        let other_txid = Txid::from_slice(&[4; 32]).unwrap();
+        assert!(matches!(
+            open_sigs(&other_txid, &sigs, &alice_pubkey, &alice_privkey),
+            Err(Error::Message(crate::MessageError::TxidMismatch { .. }))
+        ));
+    }
+
+    #[test]
+    fn blind_storage() {
+        let (alice_pubkey, alice_privkey) = gen_keypair();
+        let (bob_pubkey, _) = gen_keypair();
+        let txid = Txid::from_slice(&[3; 32]).unwrap();
+        let other_txid = Txid::from_slice(&[4; 32]).unwrap();
+        let sig = sig(txid);
+        let key = BlobKey::generate();
+
+        // One identifier per transaction and recipient
+        let stored = store_sig(&key, &sig, &[alice_pubkey, bob_pubkey]);
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored[0].blob_id, blob_id(&key, &txid, &alice_pubkey));
+        assert_eq!(stored[1].blob_id, blob_id(&key, &txid, &bob_pubkey));
+        assert_ne!(stored[0].blob_id, stored[1].blob_id);
+        assert_ne!(stored[0].blob_id, blob_id(&key, &other_txid, &alice_pubkey));
+        assert_eq!(
+            blob_id(&BlobKey([7; 32]), &txid, &alice_pubkey),
+            blob_id(&BlobKey([7; 32]), &txid, &alice_pubkey)
+        );
+
+        // The coordinator knows the txids and the recipients, but not the key: the
+        // identifiers can't be computed from them alone
+        fn public_id<E: HashEngine>(engine: &mut E, txid: &Txid, recipient: &PublicKey) {
+            engine.input(b"revault_net blob id");
+            engine.input(&txid[..]);
+            engine.input(&recipient.0);
+        }
+        let mut engine = sha256::Hash::engine();
+        public_id(&mut engine, &txid, &alice_pubkey);
+        let digest = BlobId(sha256::Hash::from_engine(engine).into_inner());
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(&[0; 32]);
+        public_id(&mut engine, &txid, &alice_pubkey);
+        let without_key = BlobId(hmac::Hmac::from_engine(engine).into_inner());
+        assert!(stored
+            .iter()
+            .all(|stored| stored.blob_id != digest && stored.blob_id != without_key));
+        assert_ne!(
+            stored[0].blob_id,
+            blob_id(&BlobKey::generate(), &txid, &alice_pubkey)
+        );
+        assert!(!format!("{:?}", key).contains(&format!("{:?}", key.0)));
+
+        let blobs = Blobs {
+            blobs: vec![stored[0].blob.clone()],
+        };
+        assert_eq!(
+            open_blobs(&txid, &blobs, &alice_pubkey, &alice_privkey).unwrap(),
+            vec![sig]
+        );
+        assert!(open_blobs(&other_txid, &blobs, &alice_pubkey, &alice_privkey).is_err());
+    }
+}
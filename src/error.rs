@@ -1,10 +1,27 @@
 //! Revault_net error module
+//!
+//! All the failures of this library are reported as an [Error], whose variants are
+//! the classes of failure (Noise, transport, serialization, message content, ..). The
+//! underlying error is available through [std::error::Error::source]. The error enums
+//! are non-exhaustive, as new failure classes may be added.
 
-use std::{error, fmt};
+use std::{cmp, error, fmt};
 
-use crate::noise::{MAC_SIZE, NOISE_MESSAGE_MAX_SIZE, NOISE_PLAINTEXT_MAX_SIZE};
+use crate::message::ResponseError;
+#[cfg(feature = "transport")]
+use crate::{
+    message::Role,
+    noise::{PublicKey, MAC_SIZE, NOISE_MESSAGE_MAX_SIZE, NOISE_PLAINTEXT_MAX_SIZE},
+};
+#[cfg(feature = "transport")]
+use bitcoin::{hashes::hex::ToHex, Network};
+#[cfg(feature = "transport")]
+use std::ops::RangeInclusive;
 
+#[cfg(feature = "transport")]
+/// An error related to the Noise channel
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum NoiseError {
     /// Error from Snow's internals
     Snow(snow::error::Error),
@@ -16,14 +33,24 @@ pub enum NoiseError {
     BadHandshake,
     /// Remote static public key mismatch from passed keys
     MissingStaticKey,
+    /// A message could not be decrypted: it was tampered with, or is not for this
+    /// channel
+    Decryption,
+    /// The ephemeral key of the peer is a low order point or is its static key
+    InvalidEphemeralKey,
+    /// The ephemeral key of the peer was already used in a previous handshake: the
+    /// handshake is being replayed
+    ReusedEphemeralKey,
 }
 
+#[cfg(feature = "transport")]
 impl From<snow::error::Error> for NoiseError {
     fn from(error: snow::error::Error) -> Self {
         Self::Snow(error)
     }
 }
 
+#[cfg(feature = "transport")]
 impl fmt::Display for NoiseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -46,34 +73,696 @@ impl fmt::Display for NoiseError {
                 f,
                 "Missing sender's static public key to respond to handshake"
             ),
+            Self::Decryption => write!(f, "Failed to decrypt message"),
+            Self::InvalidEphemeralKey => write!(f, "Invalid ephemeral key in handshake"),
+            Self::ReusedEphemeralKey => write!(f, "Ephemeral key reused in handshake"),
         }
     }
 }
 
-impl error::Error for NoiseError {}
+#[cfg(feature = "transport")]
+impl error::Error for NoiseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Snow(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// An error related to the content of a message
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub enum MessageError {
+    /// A Spend transaction was not finalized
+    NotFinalized,
+    /// The number of deposit outpoints doesn't match the number of vaults spent
+    DepositOutpointsMismatch {
+        /// Number of deposit outpoints in the message
+        deposit_outpoints: usize,
+        /// Number of inputs of the Spend transaction
+        spend_inputs: usize,
+    },
+    /// A deposit outpoint is present more than once
+    DuplicateDepositOutpoint(bitcoin::OutPoint),
+    /// There are more deposit outpoints than the configured maximum
+    TooManyDepositOutpoints {
+        /// Number of deposit outpoints in the message, at least
+        count: usize,
+        /// The maximum allowed
+        max: usize,
+    },
+    /// There are more signatures than the configured maximum
+    TooManySignatures {
+        /// Number of signatures in the message, at least
+        count: usize,
+        /// The maximum allowed
+        max: usize,
+    },
+    /// A transaction has no input
+    NoInput,
+    /// A transaction has no output
+    NoOutput,
+    /// A transaction spends the same outpoint more than once
+    DuplicateInput(bitcoin::OutPoint),
+    /// A transaction is heavier than the configured maximum
+    TooHeavyTransaction {
+        /// The (estimated) weight of the transaction
+        weight: u64,
+        /// The maximum weight allowed
+        max_weight: u64,
+    },
+    /// A signature could not be parsed as DER
+    InvalidSignature(String),
+    /// A signature has a high S value
+    HighSSignature(bitcoin::secp256k1::Signature),
+    /// A signature is not canonically encoded
+    NonCanonicalSignature(String),
+    /// A public key could not be parsed
+    InvalidPublicKey(String),
+    /// A public key is not compressed
+    UncompressedPublicKey(String),
+    /// The message is not for the expected transaction
+    TxidMismatch {
+        /// Txid of the transaction the message was checked against
+        expected: bitcoin::Txid,
+        /// Txid in the message
+        got: bitcoin::Txid,
+    },
+    /// The message is not for the expected vault
+    DepositOutpointMismatch {
+        /// Deposit outpoint of the vault the message was checked against
+        expected: bitcoin::OutPoint,
+        /// Deposit outpoint in the message
+        got: bitcoin::OutPoint,
+    },
+    /// A signature is not valid for this public key
+    InvalidSignatureFor(bitcoin::secp256k1::PublicKey),
+    /// Could not compute the signature hash of a transaction input
+    #[cfg(feature = "revault_tx")]
+    InputSatisfaction(revault_tx::error::InputSatisfactionError),
+    /// Two different signatures were given for the same public key
+    ConflictingSignature(bitcoin::secp256k1::PublicKey),
+    /// A message was built without setting one of its mandatory fields
+    MissingField(&'static str),
+    /// The method of a request is not the one of its params
+    MethodMismatch {
+        /// The method of the request
+        method: String,
+        /// The method of its params
+        expected: &'static str,
+    },
+    /// A message sent in chunks is larger than the maximum allowed
+    TooLargeUpload {
+        /// The size of the message
+        size: u64,
+        /// The maximum size allowed
+        max_size: u64,
+    },
+    /// A download announces or sends more bytes than the maximum allowed
+    TooLargeDownload {
+        /// The size of the download, at least
+        size: u64,
+        /// The maximum size allowed
+        max_size: u64,
+    },
+    /// A message read is larger than the maximum allowed
+    TooLargeMessage {
+        /// The size of the message
+        size: u64,
+        /// The maximum size allowed
+        max_size: u64,
+    },
+    /// A request reuses the id of another request sent on the same connection
+    IdCollision(u32),
+    /// A chunk refers to an upload that was not started, or already completed
+    UnknownUpload(u32),
+    /// The chunks of an upload were not sent in order
+    UnexpectedChunk {
+        /// The index of the next chunk
+        expected: u32,
+        /// The index of the chunk received
+        got: u32,
+    },
+    /// The chunks of an upload don't add up to the size or digest it was started with
+    CorruptedUpload(u32),
+    /// The chunks of a download don't add up to the digest of the last one
+    CorruptedDownload,
+    /// A batch response doesn't have one result per request in the batch
+    BatchLengthMismatch {
+        /// The number of requests in the batch
+        expected: usize,
+        /// The number of results in the response
+        got: usize,
+    },
+    /// A transaction input is missing the signature of this public key
+    MissingSignature {
+        /// The key whose signature is missing
+        pubkey: bitcoin::secp256k1::PublicKey,
+        /// The index of the input
+        input_index: usize,
+    },
+    /// A message is of a revision of the protocol we don't know of
+    UnknownVersion(u32),
+    /// A field was given under both its name and its alias
+    AliasedField {
+        /// The name of the field
+        field: &'static str,
+        /// Its alias
+        alias: &'static str,
+    },
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::NotFinalized => write!(f, "Spend transaction is not finalized"),
+            Self::DepositOutpointsMismatch {
+                deposit_outpoints,
+                spend_inputs,
+            } => write!(
+                f,
+                "Got {} deposit outpoint(s) for a Spend transaction with {} input(s)",
+                deposit_outpoints, spend_inputs
+            ),
+            Self::DuplicateDepositOutpoint(ref outpoint) => write!(
+                f,
+                "Deposit outpoint '{}' is present more than once",
+                outpoint
+            ),
+            Self::TooManyDepositOutpoints { count, max } => write!(
+                f,
+                "Got {} deposit outpoints, above the maximum of {}",
+                count, max
+            ),
+            Self::TooManySignatures { count, max } => {
+                write!(f, "Got {} signatures, above the maximum of {}", count, max)
+            }
+            Self::NoInput => write!(f, "Transaction has no input"),
+            Self::NoOutput => write!(f, "Transaction has no output"),
+            Self::DuplicateInput(ref outpoint) => write!(
+                f,
+                "Transaction spends outpoint '{}' more than once",
+                outpoint
+            ),
+            Self::TooHeavyTransaction { weight, max_weight } => write!(
+                f,
+                "Transaction weight '{}' is above the maximum of '{}'",
+                weight, max_weight
+            ),
+            Self::InvalidSignature(ref sig) => write!(f, "Invalid DER signature: '{}'", sig),
+            Self::HighSSignature(ref sig) => write!(f, "Signature '{}' has a high S value", sig),
+            Self::NonCanonicalSignature(ref sig) => {
+                write!(f, "Signature '{}' is not canonically encoded", sig)
+            }
+            Self::InvalidPublicKey(ref pk) => write!(f, "Invalid public key: '{}'", pk),
+            Self::UncompressedPublicKey(ref pk) => {
+                write!(f, "Public key '{}' is not compressed", pk)
+            }
+            Self::TxidMismatch { expected, got } => write!(
+                f,
+                "Message is for transaction '{}' but expected transaction '{}'",
+                got, expected
+            ),
+            Self::DepositOutpointMismatch { expected, got } => write!(
+                f,
+                "Message is for vault '{}' but expected vault '{}'",
+                got, expected
+            ),
+            Self::InvalidSignatureFor(ref pk) => {
+                write!(f, "Invalid signature for public key '{}'", pk)
+            }
+            #[cfg(feature = "revault_tx")]
+            Self::InputSatisfaction(ref e) => write!(f, "Input satisfaction error: '{}'", e),
+            Self::ConflictingSignature(ref pk) => {
+                write!(f, "Conflicting signatures for public key '{}'", pk)
+            }
+            Self::MissingField(field) => write!(f, "Missing field '{}'", field),
+            Self::MethodMismatch {
+                ref method,
+                expected,
+            } => write!(
+                f,
+                "Request method '{}' does not match its params, expected '{}'",
+                method, expected
+            ),
+            Self::TooLargeUpload { size, max_size } => write!(
+                f,
+                "Upload of '{}' bytes is above the maximum of '{}'",
+                size, max_size
+            ),
+            Self::TooLargeDownload { size, max_size } => write!(
+                f,
+                "Download of at least '{}' bytes is above the maximum of '{}'",
+                size, max_size
+            ),
+            Self::TooLargeMessage { size, max_size } => write!(
+                f,
+                "Message of '{}' bytes is above the maximum of '{}'",
+                size, max_size
+            ),
+            Self::IdCollision(id) => write!(
+                f,
+                "Request id '{}' is already used by another request on this connection",
+                id
+            ),
+            Self::UnknownUpload(id) => write!(f, "Unknown upload '{}'", id),
+            Self::UnexpectedChunk { expected, got } => {
+                write!(f, "Got chunk '{}' but expected chunk '{}'", got, expected)
+            }
+            Self::CorruptedUpload(id) => write!(
+                f,
+                "Chunks of upload '{}' don't match its size or digest",
+                id
+            ),
+            Self::CorruptedDownload => write!(f, "Chunks of download don't match its digest"),
+            Self::BatchLengthMismatch { expected, got } => write!(
+                f,
+                "Got {} result(s) for a batch of {} request(s)",
+                got, expected
+            ),
+            Self::MissingSignature {
+                ref pubkey,
+                input_index,
+            } => write!(
+                f,
+                "Input '{}' is missing the signature of public key '{}'",
+                input_index, pubkey
+            ),
+            Self::UnknownVersion(version) => write!(f, "Unknown message revision '{}'", version),
+            Self::AliasedField { field, alias } => write!(
+                f,
+                "Field '{}' was given along with its alias '{}'",
+                field, alias
+            ),
+        }
+    }
+}
+
+impl error::Error for MessageError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "revault_tx")]
+            Self::InputSatisfaction(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "transport")]
+/// A peer is not allowed to make a request
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum AccessError {
+    /// The peer has no role
+    UnknownPeer(PublicKey),
+    /// None of the roles of the peer allows this method
+    MethodNotAllowed {
+        /// The method of the request
+        method: &'static str,
+        /// The roles of the peer
+        roles: Vec<Role>,
+    },
+}
+
+#[cfg(feature = "transport")]
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::UnknownPeer(ref pk) => {
+                write!(f, "Peer '{}' has no role", pk.0.to_hex())
+            }
+            Self::MethodNotAllowed { method, ref roles } => write!(
+                f,
+                "Method '{}' is not allowed for a peer with roles '{:?}'",
+                method, roles
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "transport")]
+impl error::Error for AccessError {}
+
+#[cfg(feature = "transport")]
+/// The peer is not the endpoint we expected, as told by its
+/// [prelude](crate::prelude::Prelude)
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum PreludeError {
+    /// The peer does not speak the Revault protocol (or not with a prelude)
+    NotRevault,
+    /// The peer is on a network we don't know of, identified by its magic
+    UnknownNetwork(u32),
+    /// The peer is on another network than ours
+    NetworkMismatch {
+        /// Our network
+        ours: Network,
+        /// The network of the peer
+        theirs: Network,
+    },
+    /// The peer supports none of the versions of the protocol we support
+    UnsupportedVersion {
+        /// The versions we support
+        ours: RangeInclusive<u8>,
+        /// The versions the peer supports
+        theirs: RangeInclusive<u8>,
+    },
+}
+
+#[cfg(feature = "transport")]
+impl fmt::Display for PreludeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::NotRevault => write!(f, "Peer is not a Revault endpoint"),
+            Self::UnknownNetwork(magic) => {
+                write!(f, "Peer is on an unknown network (magic '{:#x}')", magic)
+            }
+            Self::NetworkMismatch { ours, theirs } => write!(
+                f,
+                "Peer is on network '{}' but we are on '{}'",
+                theirs, ours
+            ),
+            Self::UnsupportedVersion {
+                ref ours,
+                ref theirs,
+            } => write!(
+                f,
+                "Peer supports protocol versions '{}' to '{}' but we support '{}' to '{}'",
+                theirs.start(),
+                theirs.end(),
+                ours.start(),
+                ours.end()
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "transport")]
+impl error::Error for PreludeError {}
+
+/// Where in a JSON message an error deserializing it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonLocation {
+    /// The byte offset of the error in the message
+    pub offset: usize,
+    /// The path to the value the error was found in, as in
+    /// `.params.signatures["02ab.."]` (`.` being the whole message)
+    pub path: String,
+}
+
+impl fmt::Display for JsonLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' (byte {})", self.path, self.offset)
+    }
+}
+
+/// A JSON serialization or deserialization error. For a message we read, it tells
+/// where in the message the error was found.
+///
+/// For the result of a response, the location is relative to the result.
+#[derive(Debug)]
+pub struct JsonError {
+    error: serde_json::Error,
+    location: Option<JsonLocation>,
+}
+
+impl JsonError {
+    // The error deserializing this message, located in it
+    pub(crate) fn locate(error: serde_json::Error, raw: &[u8]) -> Self {
+        // Serialization errors, and custom errors, don't have a position
+        let location = if error.line() > 0 {
+            let offset = json_error_offset(raw, &error);
+            Some(JsonLocation {
+                offset,
+                path: json_path(raw, offset),
+            })
+        } else {
+            None
+        };
+
+        Self { error, location }
+    }
+
+    /// The serde_json error
+    pub fn inner(&self) -> &serde_json::Error {
+        &self.error
+    }
+
+    /// Where the error was found in the message, if it was deserializing one
+    pub fn location(&self) -> Option<&JsonLocation> {
+        self.location.as_ref()
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location {
+            Some(ref location) => write!(f, "{} in {}", self.error, location),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+impl error::Error for JsonError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<serde_json::Error> for JsonError {
+    fn from(error: serde_json::Error) -> Self {
+        Self {
+            error,
+            location: None,
+        }
+    }
+}
+
+// Deserialize this message, locating the error in it if it fails
+pub(crate) fn from_json_slice<'a, T>(raw: &'a [u8]) -> Result<T, Error>
+where
+    T: serde::Deserialize<'a>,
+{
+    serde_json::from_slice(raw).map_err(|e| Error::Json(JsonError::locate(e, raw)))
+}
+
+// The byte offset in this message of the error deserializing it
+pub(crate) fn json_error_offset(raw: &[u8], error: &serde_json::Error) -> usize {
+    // The lines and columns are 1-based, the columns counted in bytes
+    let line_start = raw
+        .split(|b| *b == b'\n')
+        .take(error.line().saturating_sub(1))
+        .map(|line| line.len() + 1)
+        .sum::<usize>();
+    cmp::min(
+        line_start + error.column().saturating_sub(1),
+        raw.len().saturating_sub(1),
+    )
+}
+
+// The path to the value at this offset of this JSON message. Best effort: it is only
+// as accurate as the message is well-formed up to there.
+fn json_path(raw: &[u8], offset: usize) -> String {
+    enum Segment {
+        // The key of the current member of an object, once read
+        Key(Option<String>),
+        // The index of the current element of an array
+        Index(usize),
+    }
+    let mut stack = Vec::new();
+    let mut expecting_key = false;
+
+    let mut i = 0;
+    while i < cmp::min(offset, raw.len()) {
+        match raw[i] {
+            b'"' => {
+                // A key is read entirely, even if the error is within it
+                let mut end = i + 1;
+                let mut escaped = false;
+                while end < raw.len() && (escaped || raw[end] != b'"') {
+                    escaped = !escaped && raw[end] == b'\\';
+                    end += 1;
+                }
+                if expecting_key {
+                    let key = raw
+                        .get(i..=end)
+                        .and_then(|s| serde_json::from_slice(s).ok())
+                        .unwrap_or_else(|| String::from_utf8_lossy(&raw[i + 1..end]).into_owned());
+                    if let Some(Segment::Key(current)) = stack.last_mut() {
+                        *current = Some(key);
+                    }
+                    expecting_key = false;
+                }
+                i = end;
+            }
+            b'{' => {
+                stack.push(Segment::Key(None));
+                expecting_key = true;
+            }
+            b'[' => stack.push(Segment::Index(0)),
+            b'}' | b']' => {
+                stack.pop();
+            }
+            b',' => match stack.last_mut() {
+                Some(Segment::Key(current)) => {
+                    *current = None;
+                    expecting_key = true;
+                }
+                Some(Segment::Index(index)) => *index += 1,
+                None => {}
+            },
+            _ => {}
+        }
+        i += 1;
+    }
+
+    let mut path = String::new();
+    for segment in stack {
+        match segment {
+            Segment::Key(Some(key))
+                if key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+            {
+                path.push('.');
+                path.push_str(&key);
+            }
+            Segment::Key(Some(key)) => {
+                if path.is_empty() {
+                    path.push('.');
+                }
+                path.push('[');
+                path.push_str(&serde_json::Value::from(key).to_string());
+                path.push(']');
+            }
+            Segment::Key(None) => {}
+            Segment::Index(index) => {
+                if path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(&format!("[{}]", index));
+            }
+        }
+    }
+    if path.is_empty() {
+        path.push('.');
+    }
+
+    path
+}
 
 /// An error enum for revault_net functionality
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Noise protocol related error
+    #[cfg(feature = "transport")]
     Noise(NoiseError),
     /// Transport error
     Transport(std::io::Error),
-    /// JSON serialization / deserialization error.
-    Json(serde_json::Error),
+    /// The peer closed the connection, or it was reset
+    Disconnected(std::io::Error),
+    /// JSON serialization / deserialization error. For a message we read, it is
+    /// malformed, and the error tells where.
+    Json(JsonError),
+    /// Invalid bitcoin signature
+    Signature(bitcoin::secp256k1::Error),
+    /// Invalid message content
+    Message(MessageError),
+    /// The peer is not allowed to make this request
+    #[cfg(feature = "transport")]
+    Access(AccessError),
+    /// The peer is not the endpoint we expected
+    #[cfg(feature = "transport")]
+    Prelude(PreludeError),
+    /// The peer did not acknowledge our request for this method
+    NotAcknowledged(&'static str),
+    /// The peer responded to another request than ours
+    IdMismatch {
+        /// The id of our request
+        expected: u32,
+        /// The id of the response
+        got: u32,
+    },
+    /// A read from the peer did not complete in time. If it was for the response to a
+    /// request, the id of this request.
+    Timeout(Option<u32>),
+    /// The peer responded to our request with an error
+    Remote(ResponseError),
+    /// The connection was shut down by us while reading from it (see
+    /// [ShutdownHandle](crate::transport::ShutdownHandle))
+    Shutdown,
+    /// The request could not be recorded in the journal (see
+    /// [Journal](crate::journal::Journal)), it was not sent
+    #[cfg(feature = "transport")]
+    Journal(std::io::Error),
+    /// An error that happened during calls made with a correlation id (see
+    /// [with_correlation_id](crate::client::with_correlation_id))
+    Correlated {
+        /// The opaque identifier given to the calls
+        correlation_id: String,
+        /// The error itself
+        error: Box<Error>,
+    },
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            #[cfg(feature = "transport")]
             Error::Noise(ref e) => write!(f, "Noise Error: {}", e),
             Error::Transport(ref e) => write!(f, "Transport Error: {}", e),
             Error::Json(ref e) => write!(f, "Json error: '{}'", e),
+            Error::Signature(ref e) => write!(f, "Signature error: '{}'", e),
+            Error::Message(ref e) => write!(f, "Message error: '{}'", e),
+            #[cfg(feature = "transport")]
+            Error::Access(ref e) => write!(f, "Access error: '{}'", e),
+            #[cfg(feature = "transport")]
+            Error::Prelude(ref e) => write!(f, "Prelude error: '{}'", e),
+            Error::NotAcknowledged(method) => {
+                write!(f, "Peer did not acknowledge our '{}' request", method)
+            }
+            Error::IdMismatch { expected, got } => write!(
+                f,
+                "Got a response for request '{}' but expected request '{}'",
+                got, expected
+            ),
+            Error::Timeout(Some(id)) => write!(f, "Request '{}' timed out", id),
+            Error::Timeout(None) => write!(f, "Timed out reading from peer"),
+            Error::Disconnected(ref e) => write!(f, "Peer disconnected: '{}'", e),
+            Error::Remote(ref e) => write!(f, "Peer responded with an error: '{}'", e),
+            Error::Shutdown => write!(f, "Connection was shut down"),
+            #[cfg(feature = "transport")]
+            Error::Journal(ref e) => write!(f, "Journal error: '{}'", e),
+            Error::Correlated {
+                ref correlation_id,
+                ref error,
+            } => write!(f, "[{}] {}", correlation_id, error),
         }
     }
 }
 
-impl error::Error for Error {}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "transport")]
+            Error::Noise(e) => Some(e),
+            Error::Transport(e) => Some(e),
+            Error::Disconnected(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Signature(e) => Some(e),
+            Error::Message(e) => Some(e),
+            #[cfg(feature = "transport")]
+            Error::Access(e) => Some(e),
+            #[cfg(feature = "transport")]
+            Error::Prelude(e) => Some(e),
+            #[cfg(feature = "transport")]
+            Error::Journal(e) => Some(e),
+            Error::Correlated { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl From<std::io::Error> for Error {
     fn from(error: std::io::Error) -> Self {
@@ -81,14 +770,177 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl Error {
+    /// The error itself, without the correlation id it may have been given.
+    pub fn inner(&self) -> &Error {
+        match self {
+            Error::Correlated { error, .. } => error.inner(),
+            _ => self,
+        }
+    }
+
+    /// How long the peer asked us to wait before retrying the request, if it refused
+    /// it with such a hint (for instance as it was out of storage).
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self.inner() {
+            Error::Remote(e) => e.retry_after(),
+            _ => None,
+        }
+    }
+
+    /// Classify an error of the connection to a peer: a timeout, a disconnection,
+    /// or any other transport error.
+    pub fn from_stream(error: std::io::Error) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => Self::Timeout(None),
+            std::io::ErrorKind::UnexpectedEof
+            | std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::BrokenPipe => Self::Disconnected(error),
+            _ => Self::Transport(error),
+        }
+    }
+}
+
+#[cfg(feature = "transport")]
 impl From<NoiseError> for Error {
     fn from(error: NoiseError) -> Self {
         Self::Noise(error)
     }
 }
 
+impl From<MessageError> for Error {
+    fn from(error: MessageError) -> Self {
+        Self::Message(error)
+    }
+}
+
+#[cfg(feature = "transport")]
+impl From<AccessError> for Error {
+    fn from(error: AccessError) -> Self {
+        Self::Access(error)
+    }
+}
+
+#[cfg(feature = "transport")]
+impl From<PreludeError> for Error {
+    fn from(error: PreludeError) -> Self {
+        Self::Prelude(error)
+    }
+}
+
+impl From<bitcoin::secp256k1::Error> for Error {
+    fn from(error: bitcoin::secp256k1::Error) -> Self {
+        Self::Signature(error)
+    }
+}
+
 impl From<serde_json::Error> for Error {
     fn from(error: serde_json::Error) -> Self {
-        Self::Json(error)
+        Self::Json(error.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn error_sources() {
+        let err: Error = std::io::Error::new(std::io::ErrorKind::Other, "oops").into();
+        assert_eq!(err.source().unwrap().to_string(), "oops");
+
+        let err: Error = MessageError::NoInput.into();
+        let source = err.source().unwrap();
+        assert_eq!(source.to_string(), "Transaction has no input");
+        assert!(source.source().is_none());
+
+        #[cfg(feature = "transport")]
+        {
+            let err: Error = NoiseError::BadHandshake.into();
+            assert!(err.source().unwrap().source().is_none());
+        }
+
+        assert!(Error::Timeout(None).source().is_none());
+
+        let err = Error::from_stream(std::io::ErrorKind::UnexpectedEof.into());
+        assert!(matches!(err, Error::Disconnected(_)));
+        let err = Error::from_stream(std::io::ErrorKind::WouldBlock.into());
+        assert!(matches!(err, Error::Timeout(None)));
+        let err = Error::from_stream(std::io::ErrorKind::PermissionDenied.into());
+        assert!(matches!(err, Error::Transport(_)));
+
+        let err = Error::Correlated {
+            correlation_id: "vault".to_string(),
+            error: Box::new(Error::Timeout(Some(3))),
+        };
+        assert_eq!(err.to_string(), "[vault] Request '3' timed out");
+        assert!(matches!(err.inner(), Error::Timeout(Some(3))));
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn json_locations() {
+        use crate::message::coordinator::Sigs;
+        use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+
+        let secp = Secp256k1::new();
+        let privkeys: Vec<SecretKey> = (1..=3)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let signature = secp.sign(&Message::from_slice(&[1; 32]).unwrap(), &privkeys[0]);
+        let pubkeys: Vec<PublicKey> = privkeys
+            .iter()
+            .map(|privkey| PublicKey::from_secret_key(&secp, privkey))
+            .collect();
+        let sigs = Sigs {
+            signatures: pubkeys.iter().map(|pubkey| (*pubkey, signature)).collect(),
+        };
+
+        // Which of the signatures is invalid
+        let key = format!(r#""{}":""#, pubkeys[1]);
+        let ser = serde_json::to_string(&sigs)
+            .unwrap()
+            .replace(&key, &format!("{}zz", key));
+        let err = from_json_slice::<Sigs>(ser.as_bytes()).unwrap_err();
+        let err = match err {
+            Error::Json(e) => e,
+            e => panic!("Unexpected error {}", e),
+        };
+        assert!(err.inner().is_data());
+        let location = err.location().unwrap();
+        assert_eq!(location.path, format!(r#".signatures["{}"]"#, pubkeys[1]));
+        let key_start = ser.find(&key).unwrap();
+        assert!(location.offset > key_start + key.len());
+        let value_end = key_start + key.len() + ser[key_start + key.len()..].find('"').unwrap();
+        assert!(location.offset <= value_end);
+        assert!(err.to_string().ends_with(&format!(
+            r#"in '.signatures["{}"]' (byte {})"#,
+            pubkeys[1], location.offset
+        )));
+
+        // Within arrays, and for malformed messages
+        let raw = br#"{"a":[1, {"b c": [0, tru]}]}"#;
+        let err = JsonError::locate(
+            serde_json::from_slice::<serde_json::Value>(raw).unwrap_err(),
+            raw,
+        );
+        assert!(err.inner().is_syntax());
+        assert_eq!(err.location().unwrap().path, r#".a[1]["b c"][1]"#);
+        assert_eq!(raw[err.location().unwrap().offset], b']');
+        let raw = b"[1, 2";
+        let err = JsonError::locate(
+            serde_json::from_slice::<serde_json::Value>(raw).unwrap_err(),
+            raw,
+        );
+        assert_eq!(err.location().unwrap().path, ".[1]");
+
+        // Errors without a position are not located
+        let err = JsonError::locate(
+            serde_json::from_value::<u8>(serde_json::Value::Null).unwrap_err(),
+            b"null",
+        );
+        assert!(err.location().is_none());
     }
 }
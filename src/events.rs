@@ -0,0 +1,360 @@
+//! Connection lifecycle events
+//!
+//! A daemon may want to follow the state of its connections (to drive a health
+//! dashboard, or alert when a watchtower is unreachable) without polling them. The
+//! transport and the [ConnectionManager](crate::connections::ConnectionManager) report
+//! the [ConnectionEvent]s of all the connections to a [ConnectionObserver], which can be
+//! a closure.
+//!
+//! The messages of a peer a server drops are reported too, with a [DropReason] and
+//! where in the message the problem was found, so that an operator can tell exactly
+//! why some traffic is rejected.
+//!
+//! Like the [MetricsSink](crate::metrics::MetricsSink), the observer is global and can
+//! only be set once. Until it is, events are not reported.
+
+use crate::{
+    error::{self, Error},
+    noise::PublicKey,
+};
+
+use std::{cmp, fmt, sync::OnceLock, time::Duration};
+
+/// Which end of the handshake we are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HandshakeSide {
+    /// We connected to the peer
+    Initiator,
+    /// We accepted the connection of the peer
+    Responder,
+}
+
+impl HandshakeSide {
+    /// A label for this side
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Initiator => "initiator",
+            Self::Responder => "responder",
+        }
+    }
+}
+
+/// Why a message of a peer was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropReason {
+    /// It is not valid JSON
+    MalformedJson,
+    /// It is valid JSON, but not a valid message: an unknown method, a missing field,
+    /// or a field that failed the validation
+    InvalidMessage,
+    /// The peer is not allowed to call its method
+    Unauthorized,
+}
+
+impl DropReason {
+    /// A code for this reason, to filter the logs with
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::MalformedJson => "malformed_json",
+            Self::InvalidMessage => "invalid_message",
+            Self::Unauthorized => "unauthorized",
+        }
+    }
+
+    // Why a message that failed to parse is dropped
+    pub(crate) fn of_parse_error(error: &serde_json::Error) -> Self {
+        if error.is_data() {
+            Self::InvalidMessage
+        } else {
+            Self::MalformedJson
+        }
+    }
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+// How many bytes of the message to report on each side of a parsing error
+const CONTEXT_RADIUS: usize = 24;
+
+// The byte offset in this message of the error parsing it, and the part of the
+// message around it.
+pub(crate) fn parse_error_context(raw: &[u8], error: &serde_json::Error) -> (usize, String) {
+    let offset = error::json_error_offset(raw, error);
+    let start = offset.saturating_sub(CONTEXT_RADIUS);
+    let end = cmp::min(offset + CONTEXT_RADIUS, raw.len());
+
+    (
+        offset,
+        String::from_utf8_lossy(&raw[start..end]).into_owned(),
+    )
+}
+
+/// Something that happened to a connection
+#[derive(Debug)]
+pub enum ConnectionEvent<'a> {
+    /// We started a handshake. As an initiator, with the peer with this static Noise
+    /// public key (a responder only learns it once the handshake completes).
+    HandshakeStarted {
+        /// Our end of the handshake
+        side: HandshakeSide,
+        /// The peer, if known
+        peer: Option<PublicKey>,
+    },
+    /// A handshake completed, the peer being authenticated by this static Noise public
+    /// key
+    HandshakeCompleted {
+        /// Our end of the handshake
+        side: HandshakeSide,
+        /// The identity of the peer
+        peer: PublicKey,
+        /// How long the handshake took
+        duration: Duration,
+    },
+    /// A handshake failed
+    HandshakeFailed {
+        /// Our end of the handshake
+        side: HandshakeSide,
+        /// The peer, if known
+        peer: Option<PublicKey>,
+        /// Why it failed
+        error: &'a Error,
+    },
+    /// A read found the connection to this peer closed or broken
+    Disconnected {
+        /// The identity of the peer
+        peer: PublicKey,
+        /// The error of the read
+        error: &'a Error,
+    },
+    /// The [ConnectionManager](crate::connections::ConnectionManager) connected again to
+    /// this peer, after its previous connection was closed or broken
+    Reconnected {
+        /// The identity of the peer
+        peer: PublicKey,
+    },
+    /// A message of this peer was dropped
+    MessageDropped {
+        /// The identity of the peer
+        peer: PublicKey,
+        /// Why it was dropped
+        reason: DropReason,
+        /// Where, in bytes, the problem was found in the message, if it applies
+        offset: Option<usize>,
+        /// The part of the message around the offset. For an unauthorized request,
+        /// its method.
+        context: &'a str,
+    },
+}
+
+/// Receives the [ConnectionEvent]s. It is called inline by the transport and must not
+/// block.
+pub trait ConnectionObserver: Send + Sync {
+    /// This event happened
+    fn event(&self, event: &ConnectionEvent);
+}
+
+impl<F> ConnectionObserver for F
+where
+    F: Fn(&ConnectionEvent) + Send + Sync,
+{
+    fn event(&self, event: &ConnectionEvent) {
+        self(event)
+    }
+}
+
+static OBSERVER: OnceLock<Box<dyn ConnectionObserver>> = OnceLock::new();
+
+/// Set the global connection observer. Returns the observer back if one was already
+/// set.
+pub fn set_observer(
+    observer: Box<dyn ConnectionObserver>,
+) -> Result<(), Box<dyn ConnectionObserver>> {
+    OBSERVER.set(observer)
+}
+
+// Report this event to the global observer, if one was set
+pub(crate) fn notify(event: ConnectionEvent) {
+    if let Some(observer) = OBSERVER.get() {
+        observer.event(&event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connections::{ConnectionManager, PeerConfig},
+        fixtures::local_endpoints,
+        message::method::Peer,
+        noise::gen_keypair,
+        transport::KKTransport,
+    };
+
+    use std::{
+        net::TcpListener,
+        sync::{Mutex, Once},
+    };
+
+    // An event, as (name, side, peer)
+    type Recorded = (&'static str, Option<HandshakeSide>, Option<PublicKey>);
+
+    // The events of all the tests running concurrently
+    static EVENTS: Mutex<Vec<Recorded>> = Mutex::new(Vec::new());
+    // A dropped message, as (peer, reason, offset, context)
+    type Dropped = (PublicKey, DropReason, Option<usize>, String);
+
+    // The dropped messages of all the tests running concurrently
+    static DROPPED: Mutex<Vec<Dropped>> = Mutex::new(Vec::new());
+
+    fn recorded(name: &'static str, side: Option<HandshakeSide>, peer: Option<PublicKey>) -> bool {
+        EVENTS.lock().unwrap().contains(&(name, side, peer))
+    }
+
+    // Record the events of all the tests, the observer being set by the first one
+    fn record_events() {
+        static SET: Once = Once::new();
+        SET.call_once(|| {
+            set_observer(Box::new(|event: &ConnectionEvent| {
+                let record = match *event {
+                    ConnectionEvent::HandshakeStarted { side, peer } => {
+                        ("started", Some(side), peer)
+                    }
+                    ConnectionEvent::HandshakeCompleted { side, peer, .. } => {
+                        ("completed", Some(side), Some(peer))
+                    }
+                    ConnectionEvent::HandshakeFailed { side, peer, .. } => {
+                        ("failed", Some(side), peer)
+                    }
+                    ConnectionEvent::Disconnected { peer, .. } => {
+                        ("disconnected", None, Some(peer))
+                    }
+                    ConnectionEvent::Reconnected { peer } => ("reconnected", None, Some(peer)),
+                    ConnectionEvent::MessageDropped {
+                        peer,
+                        reason,
+                        offset,
+                        context,
+                    } => {
+                        DROPPED
+                            .lock()
+                            .unwrap()
+                            .push((peer, reason, offset, context.to_string()));
+                        (reason.code(), None, Some(peer))
+                    }
+                };
+                EVENTS.lock().unwrap().push(record);
+            }))
+            .unwrap_or_else(|_| panic!("Observer already set"));
+        });
+    }
+
+    #[test]
+    fn connection_events() {
+        record_events();
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let privkey = server_privkey.clone();
+        let server_thread = std::thread::spawn(move || {
+            // Dropped right away
+            KKTransport::accept(&listener, &privkey, &[client_pubkey]).unwrap();
+            assert!(KKTransport::accept(&listener, &privkey, &[client_pubkey]).is_err());
+        });
+        let mut transport = KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        assert!(recorded(
+            "started",
+            Some(HandshakeSide::Initiator),
+            Some(server_pubkey)
+        ));
+        assert!(recorded(
+            "completed",
+            Some(HandshakeSide::Initiator),
+            Some(server_pubkey)
+        ));
+        assert!(transport.pubread().is_err());
+        assert!(recorded("disconnected", None, Some(server_pubkey)));
+
+        let (other_pubkey, _) = gen_keypair();
+        assert!(KKTransport::connect(addr, &client_privkey, &other_pubkey).is_err());
+        assert!(recorded(
+            "failed",
+            Some(HandshakeSide::Initiator),
+            Some(other_pubkey)
+        ));
+        server_thread.join().unwrap();
+        assert!(recorded(
+            "completed",
+            Some(HandshakeSide::Responder),
+            Some(client_pubkey)
+        ));
+
+        // The manager reconnecting
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let peers = vec![PeerConfig {
+            role: Peer::Coordinator,
+            addr: listener.local_addr().unwrap(),
+            noise_pubkey: server_pubkey,
+        }];
+        let server_thread = std::thread::spawn(move || {
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap()
+        });
+        let mut manager = ConnectionManager::new(client_privkey, peers);
+        manager.get(&server_pubkey).unwrap().unwrap();
+        assert!(!recorded("reconnected", None, Some(server_pubkey)));
+        // Until it notices the connection was closed
+        for _ in 0..100 {
+            manager.get(&server_pubkey).unwrap().unwrap();
+            if recorded("reconnected", None, Some(server_pubkey)) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(recorded("reconnected", None, Some(server_pubkey)));
+        let _transport = server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn dropped_messages() {
+        record_events();
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let client_thread = std::thread::spawn(move || {
+            let mut transport =
+                KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            transport
+                .pubwrite(br#"{"method":"get_time","params":{}"#)
+                .unwrap();
+            transport
+                .pubwrite(br#"{"method":"get_sigs","params":{"id":"00"},"id":1}"#)
+                .unwrap();
+            transport
+        });
+        let mut transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        assert!(transport.read_request().is_err());
+        assert!(transport.read_request().is_err());
+        let _transport = client_thread.join().unwrap();
+
+        let dropped: Vec<_> = DROPPED
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(peer, ..)| peer == &client_pubkey)
+            .cloned()
+            .collect();
+        assert_eq!(dropped.len(), 2);
+        assert_eq!(dropped[0].1, DropReason::MalformedJson);
+        assert_eq!(dropped[0].2, Some(31));
+        assert!(dropped[0].3.ends_with(r#""params":{}"#));
+        assert_eq!(dropped[1].1, DropReason::InvalidMessage);
+        assert!(dropped[1].2.is_some());
+        assert!(recorded("invalid_message", None, Some(client_pubkey)));
+    }
+}
@@ -0,0 +1,603 @@
+//! C bindings
+//!
+//! A C ABI over the transport, so that hardware signing devices and C or C++ firmwares
+//! (of watchtowers for instance) can reuse this implementation of the protocol. The
+//! declarations are in `include/revault_net.h`. Build a library to link against with
+//! `cargo rustc --release --features ffi --crate-type staticlib` (or `cdylib`).
+//!
+//! Messages are passed as JSON strings: a request is a whole request object (`method`,
+//! `params` and `id`) and a response is the content of its `result`. The strings
+//! returned by the library must be freed with [revault_string_free], the transports
+//! and listeners with their own free function.
+//!
+//! All functions return [REVAULT_OK] or the class of the error they failed with. The
+//! description of the last error on a thread is given by [revault_last_error].
+
+use crate::{
+    error::Error,
+    message,
+    noise::{gen_keypair, PublicKey, SecretKey, KEY_SIZE},
+    transport::KKTransport,
+};
+
+use serde_json::value::RawValue;
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString},
+    io,
+    net::{SocketAddr, TcpListener},
+    os::raw::{c_char, c_int},
+    ptr, slice,
+    time::Duration,
+};
+
+/// The call succeeded
+pub const REVAULT_OK: c_int = 0;
+/// A pointer was null, or pointed to an invalid value
+pub const REVAULT_ERROR_INVALID_ARGUMENT: c_int = 1;
+/// No data was read before the timeout
+pub const REVAULT_ERROR_TIMEOUT: c_int = 2;
+/// The peer closed the connection
+pub const REVAULT_ERROR_DISCONNECTED: c_int = 3;
+/// The peer responded to the request with an error
+pub const REVAULT_ERROR_REMOTE: c_int = 4;
+/// Any other error (handshake, transport, malformed message, ..)
+pub const REVAULT_ERROR_OTHER: c_int = 5;
+
+/// An encrypted and authenticated connection to a peer
+#[derive(Debug)]
+pub struct RevaultTransport(KKTransport);
+
+/// A socket listening for incoming connections
+#[derive(Debug)]
+pub struct RevaultListener(TcpListener);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+// Why a call failed
+enum Failure {
+    InvalidArgument(&'static str),
+    Error(Error),
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        Self::Error(error)
+    }
+}
+
+impl From<serde_json::Error> for Failure {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Error(error.into())
+    }
+}
+
+fn status(error: &Error) -> c_int {
+    match error {
+        Error::Timeout(_) => REVAULT_ERROR_TIMEOUT,
+        Error::Disconnected(_) => REVAULT_ERROR_DISCONNECTED,
+        Error::Remote(_) => REVAULT_ERROR_REMOTE,
+        Error::Correlated { error, .. } => status(error),
+        _ => REVAULT_ERROR_OTHER,
+    }
+}
+
+fn set_last_error(description: String) {
+    let description = CString::new(description.replace('\0', "")).expect("Nul bytes were removed");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(description));
+}
+
+// Run the body of a call, recording its error if it failed
+fn run(call: impl FnOnce() -> Result<(), Failure>) -> c_int {
+    match call() {
+        Ok(()) => REVAULT_OK,
+        Err(Failure::InvalidArgument(name)) => {
+            set_last_error(format!("Invalid argument '{}'", name));
+            REVAULT_ERROR_INVALID_ARGUMENT
+        }
+        Err(Failure::Error(error)) => {
+            set_last_error(error.to_string());
+            status(&error)
+        }
+    }
+}
+
+// A reference to the value behind this pointer, unless it's null
+unsafe fn arg<'a, T>(ptr: *const T, name: &'static str) -> Result<&'a T, Failure> {
+    ptr.as_ref().ok_or(Failure::InvalidArgument(name))
+}
+
+unsafe fn arg_mut<'a, T>(ptr: *mut T, name: &'static str) -> Result<&'a mut T, Failure> {
+    ptr.as_mut().ok_or(Failure::InvalidArgument(name))
+}
+
+unsafe fn str_arg<'a>(ptr: *const c_char, name: &'static str) -> Result<&'a str, Failure> {
+    if ptr.is_null() {
+        return Err(Failure::InvalidArgument(name));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| Failure::InvalidArgument(name))
+}
+
+unsafe fn key_arg(ptr: *const u8, name: &'static str) -> Result<[u8; KEY_SIZE], Failure> {
+    if ptr.is_null() {
+        return Err(Failure::InvalidArgument(name));
+    }
+    let mut key = [0; KEY_SIZE];
+    key.copy_from_slice(slice::from_raw_parts(ptr, KEY_SIZE));
+    Ok(key)
+}
+
+unsafe fn addr_arg(ptr: *const c_char, name: &'static str) -> Result<SocketAddr, Failure> {
+    str_arg(ptr, name)?
+        .parse()
+        .map_err(|_| Failure::InvalidArgument(name))
+}
+
+// A string to be freed with revault_string_free
+fn c_string(s: String) -> *mut c_char {
+    CString::new(s)
+        .expect("Serialized JSON has no nul byte")
+        .into_raw()
+}
+
+/// Generate a static Noise keypair.
+///
+/// # Safety
+/// `pubkey` and `privkey` must be valid for writing 32 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn revault_keypair_generate(pubkey: *mut u8, privkey: *mut u8) -> c_int {
+    run(|| {
+        if pubkey.is_null() || privkey.is_null() {
+            return Err(Failure::InvalidArgument("pubkey or privkey"));
+        }
+        let (PublicKey(public), SecretKey(private)) = gen_keypair();
+        ptr::copy_nonoverlapping(public.as_ptr(), pubkey, KEY_SIZE);
+        ptr::copy_nonoverlapping(private.as_ptr(), privkey, KEY_SIZE);
+        Ok(())
+    })
+}
+
+/// Connect to the server at `addr` (such as `"127.0.0.1:8383"`) and enact the Noise
+/// handshake with our private key and its public key. On success, the new connection
+/// is written to `transport`.
+///
+/// # Safety
+/// `addr` must be a nul-terminated string, `privkey` and `their_pubkey` must be valid
+/// for reading 32 bytes and `transport` for writing a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn revault_transport_connect(
+    addr: *const c_char,
+    privkey: *const u8,
+    their_pubkey: *const u8,
+    transport: *mut *mut RevaultTransport,
+) -> c_int {
+    run(|| {
+        let addr = addr_arg(addr, "addr")?;
+        let privkey = SecretKey(key_arg(privkey, "privkey")?);
+        let their_pubkey = PublicKey(key_arg(their_pubkey, "their_pubkey")?);
+        let transport = arg_mut(transport, "transport")?;
+
+        let connected = KKTransport::connect(addr, &privkey, &their_pubkey)?;
+        *transport = Box::into_raw(Box::new(RevaultTransport(connected)));
+        Ok(())
+    })
+}
+
+/// Listen for connections on `addr` (such as `"0.0.0.0:8383"`). On success, the new
+/// listener is written to `listener`.
+///
+/// # Safety
+/// `addr` must be a nul-terminated string and `listener` valid for writing a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn revault_listener_bind(
+    addr: *const c_char,
+    listener: *mut *mut RevaultListener,
+) -> c_int {
+    run(|| {
+        let addr = addr_arg(addr, "addr")?;
+        let listener = arg_mut(listener, "listener")?;
+
+        let bound = TcpListener::bind(addr).map_err(Error::Transport)?;
+        *listener = Box::into_raw(Box::new(RevaultListener(bound)));
+        Ok(())
+    })
+}
+
+/// Accept a connection and enact the Noise handshake as a responder with our private
+/// key, the peer having one of the `n_pubkeys` public keys. On success, the new
+/// connection is written to `transport`.
+///
+/// # Safety
+/// `listener` must have been created by [revault_listener_bind], `privkey` must be valid
+/// for reading 32 bytes, `their_pubkeys` for reading `32 * n_pubkeys` bytes and
+/// `transport` for writing a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn revault_transport_accept(
+    listener: *const RevaultListener,
+    privkey: *const u8,
+    their_pubkeys: *const u8,
+    n_pubkeys: usize,
+    transport: *mut *mut RevaultTransport,
+) -> c_int {
+    run(|| {
+        let listener = arg(listener, "listener")?;
+        let privkey = SecretKey(key_arg(privkey, "privkey")?);
+        let their_pubkeys = (0..n_pubkeys)
+            .map(|i| key_arg(their_pubkeys.wrapping_add(i * KEY_SIZE), "their_pubkeys"))
+            .map(|key| key.map(PublicKey))
+            .collect::<Result<Vec<_>, _>>()?;
+        let transport = arg_mut(transport, "transport")?;
+
+        let accepted = KKTransport::accept(&listener.0, &privkey, &their_pubkeys)?;
+        *transport = Box::into_raw(Box::new(RevaultTransport(accepted)));
+        Ok(())
+    })
+}
+
+/// Set the timeout of the reads on this connection, in milliseconds. 0 means reads
+/// block indefinitely.
+///
+/// # Safety
+/// `transport` must have been created by [revault_transport_connect] or
+/// [revault_transport_accept].
+#[no_mangle]
+pub unsafe extern "C" fn revault_transport_set_read_timeout(
+    transport: *mut RevaultTransport,
+    timeout_ms: u64,
+) -> c_int {
+    run(|| {
+        let transport = arg_mut(transport, "transport")?;
+        let timeout = Some(Duration::from_millis(timeout_ms)).filter(|t| !t.is_zero());
+        Ok(transport.0.set_read_timeout(timeout)?)
+    })
+}
+
+/// Write the static public key of the peer of this connection to `pubkey`.
+///
+/// # Safety
+/// `transport` must have been created by [revault_transport_connect] or
+/// [revault_transport_accept], and `pubkey` must be valid for writing 32 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn revault_transport_remote_static(
+    transport: *const RevaultTransport,
+    pubkey: *mut u8,
+) -> c_int {
+    run(|| {
+        let transport = arg(transport, "transport")?;
+        if pubkey.is_null() {
+            return Err(Failure::InvalidArgument("pubkey"));
+        }
+        let PublicKey(remote) = transport.0.remote_static();
+        ptr::copy_nonoverlapping(remote.as_ptr(), pubkey, KEY_SIZE);
+        Ok(())
+    })
+}
+
+/// Send this JSON request and wait for its response. On success, the JSON result of
+/// the response is written to `result`. If the peer responded with an error,
+/// [REVAULT_ERROR_REMOTE] is returned and the error is described by
+/// [revault_last_error].
+///
+/// # Safety
+/// `transport` must have been created by [revault_transport_connect] or
+/// [revault_transport_accept], `request` must be a nul-terminated string and `result`
+/// valid for writing a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn revault_transport_send_request(
+    transport: *mut RevaultTransport,
+    request: *const c_char,
+    result: *mut *mut c_char,
+) -> c_int {
+    run(|| {
+        let transport = arg_mut(transport, "transport")?;
+        let request: message::Request = serde_json::from_str(str_arg(request, "request")?)?;
+        let result = arg_mut(result, "result")?;
+
+        let response: Box<RawValue> = transport.0.send_req(&request)?;
+        *result = c_string(response.get().to_string());
+        Ok(())
+    })
+}
+
+/// Wait for a request from the peer. On success, the JSON request is written to
+/// `request`.
+///
+/// # Safety
+/// `transport` must have been created by [revault_transport_connect] or
+/// [revault_transport_accept], and `request` must be valid for writing a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn revault_transport_read_request(
+    transport: *mut RevaultTransport,
+    request: *mut *mut c_char,
+) -> c_int {
+    run(|| {
+        let transport = arg_mut(transport, "transport")?;
+        let request = arg_mut(request, "request")?;
+
+        let incoming = transport
+            .0
+            .incoming()
+            .next()
+            .unwrap_or_else(|| Err(Error::Disconnected(io::ErrorKind::UnexpectedEof.into())))?;
+        *request = c_string(serde_json::to_string(&serde_json::json!({
+            "method": incoming.params.method(),
+            "params": incoming.params,
+            "id": incoming.id,
+        }))?);
+        Ok(())
+    })
+}
+
+/// Respond to the request with this id with this JSON result.
+///
+/// # Safety
+/// `transport` must have been created by [revault_transport_connect] or
+/// [revault_transport_accept], and `result` must be a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn revault_transport_respond(
+    transport: *mut RevaultTransport,
+    id: u32,
+    result: *const c_char,
+) -> c_int {
+    run(|| {
+        let transport = arg_mut(transport, "transport")?;
+        let result = RawValue::from_string(str_arg(result, "result")?.to_string())?;
+
+        Ok(transport.0._write_resp(&message::Response { result, id })?)
+    })
+}
+
+/// Respond to the request with this id with an error of this code and message.
+///
+/// # Safety
+/// `transport` must have been created by [revault_transport_connect] or
+/// [revault_transport_accept], and `message` must be a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn revault_transport_respond_error(
+    transport: *mut RevaultTransport,
+    id: u32,
+    code: i64,
+    message: *const c_char,
+) -> c_int {
+    run(|| {
+        let transport = arg_mut(transport, "transport")?;
+        let error = message::ResponseError::new(
+            message::ErrorCode::from_code(code),
+            str_arg(message, "message")?,
+        );
+
+        Ok(transport.0.respond_error(id, error)?)
+    })
+}
+
+/// Wait for a notification from the peer. On success, the JSON notification is
+/// written to `notification`.
+///
+/// # Safety
+/// `transport` must have been created by [revault_transport_connect] or
+/// [revault_transport_accept], and `notification` must be valid for writing a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn revault_transport_read_notification(
+    transport: *mut RevaultTransport,
+    notification: *mut *mut c_char,
+) -> c_int {
+    run(|| {
+        let transport = arg_mut(transport, "transport")?;
+        let notification = arg_mut(notification, "notification")?;
+
+        let notif: message::Notification = match transport.0.read_notification()? {
+            message::NotificationParams::NewSig(params) => params.into(),
+            message::NotificationParams::NewSpendTx(params) => params.into(),
+            #[cfg(feature = "revault_tx")]
+            message::NotificationParams::KeyRotation(params) => params.into(),
+        };
+        *notification = c_string(serde_json::to_string(&notif)?);
+        Ok(())
+    })
+}
+
+/// The description of the last error on this thread, or NULL if there was none. It
+/// is valid until the next call failing on this thread, and must not be freed.
+#[no_mangle]
+pub extern "C" fn revault_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map(|description| description.as_ptr())
+            .unwrap_or_else(ptr::null)
+    })
+}
+
+/// Free a string returned by the library. Does nothing if `s` is NULL.
+///
+/// # Safety
+/// `s` must have been returned by the library, and not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn revault_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Close and free a connection. Does nothing if `transport` is NULL.
+///
+/// # Safety
+/// `transport` must have been created by [revault_transport_connect] or
+/// [revault_transport_accept], and not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn revault_transport_free(transport: *mut RevaultTransport) {
+    if !transport.is_null() {
+        drop(Box::from_raw(transport));
+    }
+}
+
+/// Close and free a listener. Does nothing if `listener` is NULL.
+///
+/// # Safety
+/// `listener` must have been created by [revault_listener_bind], and not be freed
+/// already.
+#[no_mangle]
+pub unsafe extern "C" fn revault_listener_free(listener: *mut RevaultListener) {
+    if !listener.is_null() {
+        drop(Box::from_raw(listener));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    unsafe fn last_error() -> String {
+        CStr::from_ptr(revault_last_error())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    unsafe fn take_string(s: *mut c_char) -> String {
+        let owned = CStr::from_ptr(s).to_str().unwrap().to_string();
+        revault_string_free(s);
+        owned
+    }
+
+    #[test]
+    fn ffi_roundtrip() {
+        unsafe {
+            let (mut client_pub, mut client_priv) = ([0u8; KEY_SIZE], [0u8; KEY_SIZE]);
+            let (mut server_pub, mut server_priv) = ([0u8; KEY_SIZE], [0u8; KEY_SIZE]);
+            assert_eq!(
+                revault_keypair_generate(client_pub.as_mut_ptr(), client_priv.as_mut_ptr()),
+                REVAULT_OK
+            );
+            assert_eq!(
+                revault_keypair_generate(server_pub.as_mut_ptr(), server_priv.as_mut_ptr()),
+                REVAULT_OK
+            );
+
+            let mut listener = ptr::null_mut();
+            let addr = CString::new("127.0.0.1:0").unwrap();
+            assert_eq!(
+                revault_listener_bind(addr.as_ptr(), &mut listener),
+                REVAULT_OK
+            );
+            let addr = CString::new((*listener).0.local_addr().unwrap().to_string()).unwrap();
+
+            let listener = listener as usize;
+            let server_thread = thread::spawn(move || {
+                let mut transport = ptr::null_mut();
+                assert_eq!(
+                    revault_transport_accept(
+                        listener as *const RevaultListener,
+                        server_priv.as_ptr(),
+                        client_pub.as_ptr(),
+                        1,
+                        &mut transport,
+                    ),
+                    REVAULT_OK
+                );
+                let mut peer = [0u8; KEY_SIZE];
+                assert_eq!(
+                    revault_transport_remote_static(transport, peer.as_mut_ptr()),
+                    REVAULT_OK
+                );
+                assert_eq!(peer, client_pub);
+
+                // Respond to a first request, then fail the second one
+                let mut request = ptr::null_mut();
+                assert_eq!(
+                    revault_transport_read_request(transport, &mut request),
+                    REVAULT_OK
+                );
+                let request: serde_json::Value =
+                    serde_json::from_str(&take_string(request)).unwrap();
+                assert_eq!(request["method"], "get_sigs");
+                assert_eq!(request["id"], 4);
+                let result = CString::new(r#"{"signatures":{}}"#).unwrap();
+                assert_eq!(
+                    revault_transport_respond(transport, 4, result.as_ptr()),
+                    REVAULT_OK
+                );
+
+                let mut request = ptr::null_mut();
+                assert_eq!(
+                    revault_transport_read_request(transport, &mut request),
+                    REVAULT_OK
+                );
+                revault_string_free(request);
+                let message = CString::new("Not today").unwrap();
+                assert_eq!(
+                    revault_transport_respond_error(transport, 4, -32603, message.as_ptr()),
+                    REVAULT_OK
+                );
+
+                // The client is gone
+                let mut request = ptr::null_mut();
+                assert_eq!(
+                    revault_transport_read_request(transport, &mut request),
+                    REVAULT_ERROR_DISCONNECTED
+                );
+                assert!(request.is_null());
+                revault_transport_free(transport);
+                revault_listener_free(listener as *mut RevaultListener);
+            });
+
+            let mut transport = ptr::null_mut();
+            assert_eq!(
+                revault_transport_connect(
+                    addr.as_ptr(),
+                    client_priv.as_ptr(),
+                    server_pub.as_ptr(),
+                    &mut transport,
+                ),
+                REVAULT_OK
+            );
+            assert_eq!(
+                revault_transport_set_read_timeout(transport, 5_000),
+                REVAULT_OK
+            );
+            let request =
+                CString::new(include_str!("../contrib/test_vectors/get_sigs.json").trim_end())
+                    .unwrap();
+            let mut result = ptr::null_mut();
+            assert_eq!(
+                revault_transport_send_request(transport, request.as_ptr(), &mut result),
+                REVAULT_OK
+            );
+            assert_eq!(take_string(result), r#"{"signatures":{}}"#);
+
+            let mut result = ptr::null_mut();
+            assert_eq!(
+                revault_transport_send_request(transport, request.as_ptr(), &mut result),
+                REVAULT_ERROR_REMOTE
+            );
+            assert!(result.is_null());
+            assert!(last_error().contains("Not today"), "{}", last_error());
+            revault_transport_free(transport);
+            server_thread.join().unwrap();
+
+            // Invalid arguments are reported, not dereferenced
+            assert_eq!(
+                revault_transport_send_request(ptr::null_mut(), request.as_ptr(), &mut result),
+                REVAULT_ERROR_INVALID_ARGUMENT
+            );
+            assert_eq!(last_error(), "Invalid argument 'transport'");
+            let mut transport = ptr::null_mut();
+            let addr = CString::new("not an address").unwrap();
+            assert_eq!(
+                revault_transport_connect(
+                    addr.as_ptr(),
+                    client_priv.as_ptr(),
+                    server_pub.as_ptr(),
+                    &mut transport,
+                ),
+                REVAULT_ERROR_INVALID_ARGUMENT
+            );
+            assert!(transport.is_null());
+            revault_string_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Fixtures
+//!
+//! The data and the setups shared by the tests of this crate, its test doubles and its
+//! fuzzing harnesses, for them not to each carry a copy.
+
+#[cfg(all(test, feature = "transport"))]
+use crate::noise::{gen_keypair, PublicKey, SecretKey};
+#[cfg(test)]
+use bitcoin::secp256k1::{self, Message, Secp256k1, Signature};
+#[cfg(all(test, feature = "revault_tx"))]
+use revault_tx::transactions::{RevaultTransaction, SpendTransaction};
+#[cfg(all(test, feature = "transport"))]
+use std::net::{SocketAddr, TcpListener};
+
+/// A PSBT for a Spend transaction, the content of which doesn't matter
+#[cfg(feature = "revault_tx")]
+pub(crate) const SPEND_PSBT: &str = "cHNidP8BAGcCAAAAAY74R7yfKjYatj96vo5Ww2nRXnMLqJZ0sJtCZ0vUDJT1AAAAAADNVgAAAoDYAQAAAAAAIgAgrhve44jyE2BUeXInsUqYPSjeKfUi8+vcTiX9K649nlIBAAAAAAAAAAAAAAAAAAEBK6BK9QUAAAAAIgAgGOT4nZS2eDtYm83Cvrva0Ozxmrw4Wjin73s81+Z/MfEBAwQBAAAAAQX9YgJTIQJXWghCPRbOUhpx+hi93OfpK75maJRYRC38QR4f7+NtFiECM9/45YqHN25XccUBgRIDEcbyVEgt7j61+c9r3RZ7FzohAriewns/EcwKUVDvv1bxr790pkzQRzmqfV3dQ9mzBjaQU65kdqkUqOUtXIDgEzokTmljuXvjUVK6PKqIrGt2qRSxhJ72lPFm92bL1zs0fxxSxgvWIIisbJNrdqkUH5eaO3DdSZU5iyaVBAxs4jQpiiaIrGyTa3apFORRbu2KExrgnCCww5w9TraaoolAiKxsk2t2qRTdO8BPO/zd71a6yb+Cns88TZKG84isbJNrdqkU32Y5t5RL0rYBZZvHWmii6eTcgZ+IrGyTa3apFK83DFJxO+ke61QLvGNyYnmSwKrDiKxsk2t2qRQOTi7K/HfcXcC5iBLjCnMWcMWjIYisbJNYh2dYIQLR/ezgE85uXQeHPU/DkO9OMViCc8qtX1GT1B+pC3O4ASECx3y8Y+ejFiUsobbCiYlAU3h87Q7y+QhADwLFygARZXchAiQAGsW+t/RQ0AJ1axuUM9e58WBlzItzzI4xB8sPnMrsIQKnh96esMFOEyF0tbKBXWmAtff+mxSOoyQVefv/JN/vhSEDiQaTfG58TKdD2N4DbB+wCd3Sz04D4Psle+84rmIW51ghAzFWj+Qs+0gWprDMs3Aat9f5wMZuZaZth1AAtHbe2NbxIQL8522r0lMYLHkL+h2yus2uJP8y6N28+cwpWyaTFNnP+CECdjQgoJBQYwTi7KPMwt1RBcdP0KnnWdYNCSkUmtF972hYrwLOVrJoAAEBaVEhAldaCEI9Fs5SGnH6GL3c5+krvmZolFhELfxBHh/v420WIQIz3/jlioc3bldxxQGBEgMRxvJUSC3uPrX5z2vdFnsXOiECuJ7Cez8RzApRUO+/VvGvv3SmTNBHOap9Xd1D2bMGNpBTrgAA";
+
+/// The Spend transaction of [SPEND_PSBT]
+#[cfg(all(test, feature = "revault_tx"))]
+pub(crate) fn spend_tx() -> SpendTransaction {
+    SpendTransaction::from_psbt_str(SPEND_PSBT).expect("A valid PSBT")
+}
+
+/// The private key of the signatures of [secp_sig]
+#[cfg(test)]
+pub(crate) fn secp_privkey() -> secp256k1::SecretKey {
+    secp256k1::SecretKey::from_slice(&[1; 32]).expect("A valid key")
+}
+
+/// A signature of a dummy message, and the public key of its signer
+#[cfg(test)]
+pub(crate) fn secp_sig() -> (secp256k1::PublicKey, Signature) {
+    let (secp, privkey) = (Secp256k1::new(), secp_privkey());
+    let msg = Message::from_slice(&[1; 32]).expect("32 bytes");
+    (
+        secp256k1::PublicKey::from_secret_key(&secp, &privkey),
+        secp.sign(&msg, &privkey),
+    )
+}
+
+/// The Noise keys of a client and of a server, and a listener for the server on a free
+/// local port along with its address
+#[cfg(all(test, feature = "transport"))]
+pub(crate) fn local_endpoints() -> (
+    (PublicKey, SecretKey),
+    (PublicKey, SecretKey),
+    TcpListener,
+    SocketAddr,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Binding a free port");
+    let addr = listener.local_addr().expect("A bound listener");
+    (gen_keypair(), gen_keypair(), listener, addr)
+}
@@ -0,0 +1,176 @@
+//! One-shot exchanges
+//!
+//! Free functions performing a single exchange of the protocol over a [KKTransport]:
+//! creating the request, sending it and checking the response, as the clients of the
+//! [client](crate::client) module do. For callers that manage their connections
+//! themselves and need neither retries nor latency tracking.
+
+/// Exchanges with the coordinator
+pub mod coordinator {
+    use crate::{
+        error::Error,
+        message::{
+            coordinator::{
+                GetSigs, GetSpendTx, SetSpendResult, SetSpendTx, Sig, SigResult, Sigs, SpendTx,
+            },
+            method, SigSet,
+        },
+        transport::KKTransport,
+    };
+
+    use bitcoin::{OutPoint, Txid};
+
+    /// Share a signature for a transaction with the coordinator, and check it stored it
+    pub fn share_sig(transport: &mut KKTransport, sig: Sig) -> Result<(), Error> {
+        let resp: SigResult = transport.send_req(&transport.request(sig))?;
+        if !resp.ack {
+            return Err(Error::NotAcknowledged(method::SIG));
+        }
+
+        Ok(())
+    }
+
+    /// Get all the signatures the coordinator has for this transaction
+    pub fn fetch_sigs(transport: &mut KKTransport, txid: Txid) -> Result<SigSet, Error> {
+        let resp: Sigs = transport.send_req(&transport.request(GetSigs {
+            id: txid,
+            if_none_match: None,
+        }))?;
+        Ok(resp.signatures)
+    }
+
+    /// Store a Spend transaction on the coordinator, and check it stored it
+    pub fn store_spend_tx(transport: &mut KKTransport, msg: SetSpendTx) -> Result<(), Error> {
+        let resp: SetSpendResult = transport.send_req(&transport.request(msg))?;
+        if !resp.ack {
+            return Err(Error::NotAcknowledged(method::SET_SPEND_TX));
+        }
+
+        Ok(())
+    }
+
+    /// Get the Spend transaction the coordinator has for this vault, if any
+    pub fn fetch_spend_tx(
+        transport: &mut KKTransport,
+        deposit_outpoint: OutPoint,
+    ) -> Result<SpendTx, Error> {
+        transport.send_req(&transport.request(GetSpendTx { deposit_outpoint }))
+    }
+}
+
+/// Exchanges with a watchtower
+pub mod watchtower {
+    use crate::{
+        client::check_revocation_sigs_ack, error::Error, message::watchtower,
+        transport::KKTransport,
+    };
+
+    /// Share all the signatures for a revocation transaction of a vault with the
+    /// watchtower, and check it acknowledged them.
+    pub fn share_sigs(transport: &mut KKTransport, sig: watchtower::Sig) -> Result<(), Error> {
+        let txid = sig.txid;
+        let resp: watchtower::SigResult = transport.send_req(&transport.request(sig))?;
+        check_revocation_sigs_ack(txid, &resp)
+    }
+}
+
+/// Exchanges with a cosigning server
+#[cfg(feature = "revault_tx")]
+pub mod cosigner {
+    use crate::{client::check_signed_tx, error::Error, message::cosigner, transport::KKTransport};
+
+    use revault_tx::transactions::{RevaultTransaction, SpendTransaction};
+
+    /// Ask the cosigning server to sign this Spend transaction, and check it returned
+    /// it with its signatures.
+    pub fn request_signatures(
+        transport: &mut KKTransport,
+        spend_tx: SpendTransaction,
+    ) -> Result<SpendTransaction, Error> {
+        let txid = spend_tx.txid();
+        let resp: cosigner::SignResult =
+            transport.send_req(&transport.request(cosigner::SignRequest::new(spend_tx)))?;
+        check_signed_tx(txid, resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::{Error, MessageError},
+        fixtures::{local_endpoints, secp_sig},
+        message::{
+            coordinator::{SigResult, SpendTx},
+            method, ResponseResult, SigSet,
+        },
+        transport::KKTransport,
+    };
+
+    use bitcoin::{hashes::Hash, OutPoint, Txid};
+    use std::thread;
+
+    #[test]
+    fn one_shot_exchanges() {
+        let (pubkey, signature) = secp_sig();
+        let txid = Txid::default();
+        let signatures: SigSet = [(pubkey, signature)].iter().cloned().collect();
+        let revocation_sig = crate::message::watchtower::Sig {
+            signatures: signatures.clone(),
+            txid,
+            deposit_outpoint: OutPoint::default(),
+        };
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_sigs = signatures.clone();
+        let cli_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            let sig = crate::message::coordinator::Sig {
+                pubkey,
+                signature,
+                id: txid,
+            };
+            coordinator::share_sig(&mut transport, sig.clone()).unwrap();
+            assert!(matches!(
+                coordinator::share_sig(&mut transport, sig),
+                Err(Error::NotAcknowledged(method::SIG))
+            ));
+            assert_eq!(
+                coordinator::fetch_sigs(&mut transport, txid).unwrap(),
+                cli_sigs
+            );
+            assert!(
+                !coordinator::fetch_spend_tx(&mut transport, OutPoint::default())
+                    .unwrap()
+                    .is_found()
+            );
+
+            watchtower::share_sigs(&mut transport, revocation_sig.clone()).unwrap();
+            assert!(matches!(
+                watchtower::share_sigs(&mut transport, revocation_sig),
+                Err(Error::Message(MessageError::TxidMismatch { .. }))
+            ));
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        let responses = vec![
+            ResponseResult::Sig(SigResult::stored()),
+            ResponseResult::Sig(SigResult::refused()),
+            ResponseResult::Sigs(crate::message::coordinator::Sigs { signatures }),
+            ResponseResult::SpendTx(SpendTx::not_found()),
+            ResponseResult::WtSig(crate::message::watchtower::SigResult { ack: true, txid }),
+            ResponseResult::WtSig(crate::message::watchtower::SigResult {
+                ack: true,
+                txid: Txid::from_slice(&[1; 32]).unwrap(),
+            }),
+        ];
+        for resp in responses {
+            server_transport.read_req(|_| Some(resp)).unwrap();
+        }
+        cli_thread.join().unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+//! HTTP JSON-RPC adapter
+//!
+//! Monitoring tools and simple scripts may not implement Noise. [serve_http] serves
+//! the requests of a [RequestHandler] as plain HTTP JSON-RPC: each `POST` carries a
+//! request as its JSON body, and is answered with its response (or error response) as
+//! JSON. An [HttpClient] sends requests to such an endpoint.
+//!
+//! There being no Noise handshake, the peer is not authenticated: all the requests are
+//! made on behalf of the static public key the endpoint is configured with. The
+//! handler should restrict what this key may do (see
+//! [access](crate::server::access)), and the endpoint should only be reachable by
+//! trusted tools, for instance by listening on localhost.
+
+use crate::{
+    error::{from_json_slice, Error},
+    instrument::{log_debug, log_warn},
+    message::{ErrorCode, ErrorResponse, Request, Response, ResponseError},
+    noise::PublicKey,
+    server::{self, RequestHandler},
+    transport::parse_result,
+};
+
+use serde_json::value::RawValue;
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+// The maximum size of the body of a request or a response
+const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+// The maximum size of the status line and headers
+const MAX_HEADERS_SIZE: usize = 8 * 1024;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Read the start line and the headers of an HTTP message, and its body. Returns the
+// start line and the body.
+fn read_message<R: BufRead>(reader: &mut R) -> io::Result<(String, Vec<u8>)> {
+    let mut start_line = String::new();
+    reader.read_line(&mut start_line)?;
+    let mut content_length = None;
+    let mut headers_size = start_line.len();
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        headers_size += line.len();
+        if headers_size > MAX_HEADERS_SIZE {
+            return Err(invalid("Headers too large"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                content_length = Some(
+                    value
+                        .trim()
+                        .parse::<usize>()
+                        .map_err(|_| invalid("Invalid Content-Length"))?,
+                );
+            }
+        }
+    }
+
+    let content_length = content_length.ok_or_else(|| invalid("No Content-Length"))?;
+    if content_length > MAX_BODY_SIZE {
+        return Err(invalid("Body too large"));
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok((start_line.trim_end().to_string(), body))
+}
+
+fn write_response<W: Write>(writer: &mut W, status: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    )?;
+    writer.write_all(body)?;
+    writer.flush()
+}
+
+// The JSON-RPC response to this request body
+fn respond<H: RequestHandler + ?Sized>(handler: &H, peer: &PublicKey, body: &[u8]) -> Vec<u8> {
+    let error = |id, error: ResponseError| {
+        serde_json::to_vec(&ErrorResponse { error, id }).expect("Messages always serialize")
+    };
+
+    let req = match from_json_slice::<Request>(body) {
+        Ok(req) => req,
+        Err(e) => {
+            log_warn!("Ignoring invalid HTTP request: '{}'", e);
+            return error(0, ResponseError::from(&e));
+        }
+    };
+    let id = req.id();
+    match handler.reply(peer, id, req.params()) {
+        Some(Ok(result)) => {
+            serde_json::to_vec(&Response { result, id }).expect("Messages always serialize")
+        }
+        Some(Err(e)) => error(id, e),
+        None => error(
+            id,
+            ResponseError::new(ErrorCode::MethodNotFound, "Request not handled"),
+        ),
+    }
+}
+
+/// Serve the HTTP request on this connection with this handler, on behalf of the peer
+/// with this static public key. The connection is closed once responded to.
+pub fn handle_http<H: RequestHandler + ?Sized>(
+    stream: TcpStream,
+    handler: &H,
+    peer: &PublicKey,
+) -> Result<(), Error> {
+    stream.set_read_timeout(Some(Duration::from_secs(20)))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    let (start_line, body) = match read_message(&mut reader) {
+        Ok(message) => message,
+        Err(e) => {
+            write_response(&mut writer, "400 Bad Request", b"")?;
+            return Err(e.into());
+        }
+    };
+    if !start_line.starts_with("POST ") {
+        log_debug!("Refusing HTTP request '{}'", start_line);
+        write_response(&mut writer, "405 Method Not Allowed", b"")?;
+        return Ok(());
+    }
+
+    let response = respond(handler, peer, &body);
+    write_response(&mut writer, "200 OK", &response)?;
+    Ok(())
+}
+
+/// Accept HTTP connections on this listener, and serve each of them on its own thread
+/// with this handler on behalf of the peer with this static public key, see
+/// [handle_http]. A failed connection is logged and dropped. Only returns on a failure
+/// to spawn a thread.
+pub fn serve_http<H: RequestHandler + Send + Sync + 'static>(
+    listener: &TcpListener,
+    handler: Arc<H>,
+    peer: PublicKey,
+) -> Result<(), Error> {
+    loop {
+        let stream = server::accept_backing_off(listener, "HTTP connection");
+
+        let handler = handler.clone();
+        thread::Builder::new()
+            .name("revault_net http".to_string())
+            .spawn(move || {
+                if let Err(e) = handle_http(stream, handler.as_ref(), &peer) {
+                    log_warn!("Error serving HTTP connection: '{}'", e);
+                }
+            })?;
+    }
+}
+
+/// A client of an HTTP JSON-RPC endpoint, see [serve_http]
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    addr: SocketAddr,
+    timeout: Duration,
+}
+
+impl HttpClient {
+    /// A client of the endpoint at this address. Requests time out after 20 seconds.
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            timeout: Duration::from_secs(20),
+        }
+    }
+
+    /// Time out the requests after this duration instead
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Send a request to the endpoint, and return its response.
+    pub fn send_req<T>(&self, req: &Request) -> Result<T, Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let mut stream = TcpStream::connect_timeout(&self.addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        let body = serde_json::to_vec(req)?;
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.addr,
+            body.len()
+        )?;
+        stream.write_all(&body)?;
+        stream.flush()?;
+
+        let (status_line, body) = read_message(&mut BufReader::new(stream))?;
+        if status_line.split(' ').nth(1) != Some("200") {
+            return Err(invalid(&format!("Unexpected HTTP status '{}'", status_line)).into());
+        }
+        let (id, result) = match serde_json::from_slice::<Response<Box<RawValue>>>(&body) {
+            Ok(resp) => (resp.id, Ok(resp.result)),
+            Err(e) => match serde_json::from_slice::<ErrorResponse>(&body) {
+                Ok(resp) => (resp.id, Err(resp.error)),
+                Err(_) => return Err(e.into()),
+            },
+        };
+        // An invalid request is responded to with id 0
+        if id != req.id() && result.is_ok() {
+            return Err(Error::IdMismatch {
+                expected: req.id(),
+                got: id,
+            });
+        }
+
+        parse_result(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::{
+            coordinator::{GetSigs, Sigs},
+            RequestParams, ResponseResult, SigSet,
+        },
+        noise::gen_keypair,
+    };
+
+    use bitcoin::Txid;
+    use std::io::Read;
+
+    #[test]
+    fn http_gateway() {
+        let (monitoring_pubkey, _) = gen_keypair();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let handler = move |peer: &PublicKey, params| {
+                assert_eq!(peer, &monitoring_pubkey);
+                match params {
+                    RequestParams::GetSigs(_) => Some(ResponseResult::Sigs(Sigs {
+                        signatures: SigSet::new(),
+                    })),
+                    _ => None,
+                }
+            };
+            serve_http(&listener, Arc::new(handler), monitoring_pubkey).unwrap();
+        });
+
+        let client = HttpClient::new(addr);
+        let get_sigs = Request::from(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        let sigs: Sigs = client.send_req(&get_sigs).unwrap();
+        assert!(sigs.signatures.is_empty());
+
+        // Not handled
+        let get_spend = Request::from(crate::message::coordinator::GetSpendTx {
+            deposit_outpoint: Default::default(),
+        });
+        match client.send_req::<Sigs>(&get_spend) {
+            Err(Error::Remote(e)) => assert_eq!(e.code, ErrorCode::MethodNotFound),
+            res => panic!("Unexpected {:?}", res),
+        }
+
+        // Plain HTTP
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let error: ErrorResponse =
+            serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(error.id, 0);
+        assert_eq!(error.error.code, ErrorCode::InvalidParams);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+}
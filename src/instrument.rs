@@ -0,0 +1,230 @@
+//! Instrumentation of the handshakes and requests
+//!
+//! Behind the `tracing` feature, the handshakes and the requests are wrapped in spans
+//! and their outcome is reported as events along with their duration. Behind the `log`
+//! feature (enabled by default), the same events are emitted through the `log` facade.
+//! Peers are identified by a prefix of their static Noise public key, and no secret is
+//! ever part of an event. The [MetricsSink](crate::metrics::MetricsSink) and the
+//! [ConnectionObserver](crate::events::ConnectionObserver), if any, are called into as
+//! well.
+//!
+//! This module also provides the logging macros used throughout the crate, which are
+//! no-ops if the `log` feature is disabled.
+
+#[cfg(feature = "transport")]
+use crate::{
+    error::Error,
+    events::{self, ConnectionEvent, HandshakeSide},
+    metrics::{self, Direction, Side},
+    noise::PublicKey,
+    transport::KKTransport,
+};
+
+#[cfg(feature = "transport")]
+use std::time::Instant;
+
+// Define a macro forwarding to this macro of the log crate, or a no-op if the `log`
+// feature is disabled. The `$d` token is a `$`, for the inner macro to have its own
+// metavariables. It is formatted by hand, rustfmt indenting the inner macros further
+// on each run.
+#[rustfmt::skip]
+macro_rules! define_log_macro {
+    ($name:ident, $level:ident, $d:tt) => {
+        #[cfg(feature = "log")]
+        #[cfg_attr(not(feature = "transport"), allow(unused_macros))]
+        macro_rules! $name {
+            ($d($d arg:tt)+) => {
+                log::$level!($d($d arg)+)
+            };
+        }
+        #[cfg(not(feature = "log"))]
+        #[cfg_attr(not(feature = "transport"), allow(unused_macros))]
+        macro_rules! $name {
+            ($d($d arg:tt)+) => {{
+                let _ = format_args!($d($d arg)+);
+            }};
+        }
+        #[cfg_attr(not(feature = "transport"), allow(unused_imports))]
+        pub(crate) use $name;
+    };
+}
+
+define_log_macro!(log_error, error, $);
+define_log_macro!(log_warn, warn, $);
+define_log_macro!(log_debug, debug, $);
+define_log_macro!(log_trace, trace, $);
+
+// A short identifier for a peer, enough to correlate events
+#[cfg(all(feature = "transport", any(feature = "tracing", feature = "log")))]
+fn peer_prefix(pubkey: &PublicKey) -> String {
+    use bitcoin::hashes::hex::ToHex;
+    pubkey.0[..4].to_hex()
+}
+
+// Run a handshake as `side`, with this peer if we know it already
+#[cfg(feature = "transport")]
+pub(crate) fn handshake<F>(
+    side: HandshakeSide,
+    peer: Option<&PublicKey>,
+    f: F,
+) -> Result<KKTransport, Error>
+where
+    F: FnOnce() -> Result<KKTransport, Error>,
+{
+    events::notify(ConnectionEvent::HandshakeStarted {
+        side,
+        peer: peer.copied(),
+    });
+    #[cfg(feature = "tracing")]
+    let span = tracing::info_span!(
+        "handshake",
+        side = side.as_str(),
+        peer = tracing::field::Empty
+    );
+    #[cfg(feature = "tracing")]
+    let _entered = span.enter();
+
+    let start = Instant::now();
+    let res = f();
+    let duration = start.elapsed();
+
+    match &res {
+        Ok(transport) => events::notify(ConnectionEvent::HandshakeCompleted {
+            side,
+            peer: transport.remote_static(),
+            duration,
+        }),
+        Err(e) => {
+            if let Some(sink) = metrics::sink() {
+                sink.error(None, metrics::error_class(e));
+            }
+            events::notify(ConnectionEvent::HandshakeFailed {
+                side,
+                peer: peer.copied(),
+                error: e,
+            });
+        }
+    }
+    #[cfg(feature = "tracing")]
+    {
+        let duration_ms = duration.as_millis() as u64;
+        match &res {
+            Ok(transport) => {
+                span.record("peer", peer_prefix(&transport.remote_static()).as_str());
+                tracing::debug!(duration_ms, "Handshake completed");
+            }
+            Err(e) => tracing::warn!(duration_ms, error = %e, "Handshake failed"),
+        }
+    }
+    #[cfg(feature = "log")]
+    match &res {
+        Ok(transport) => log::debug!(
+            "Handshake as {} with '{}' completed in {}ms",
+            side.as_str(),
+            peer_prefix(&transport.remote_static()),
+            duration.as_millis()
+        ),
+        Err(e) => log::warn!(
+            "Handshake as {} failed after {}ms: '{}'",
+            side.as_str(),
+            duration.as_millis(),
+            e
+        ),
+    }
+    #[cfg(not(any(feature = "tracing", feature = "log")))]
+    let _ = duration;
+
+    res
+}
+
+// Process a request, on this side of the connection
+#[cfg(feature = "transport")]
+pub(crate) fn request<T, F>(
+    side: Side,
+    method: &str,
+    id: u32,
+    peer: &PublicKey,
+    f: F,
+) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error>,
+{
+    #[cfg(any(feature = "tracing", feature = "log"))]
+    let correlation_id = crate::client::correlation_id();
+    #[cfg(feature = "tracing")]
+    let span = tracing::info_span!(
+        "request",
+        side = side.as_str(),
+        method,
+        id,
+        peer = peer_prefix(peer).as_str(),
+        correlation_id = correlation_id.as_deref()
+    );
+    #[cfg(feature = "tracing")]
+    let _entered = span.enter();
+    #[cfg(not(any(feature = "tracing", feature = "log")))]
+    let _ = (id, peer);
+
+    let start = Instant::now();
+    let res = f();
+    let duration = start.elapsed();
+
+    if let Some(sink) = metrics::sink() {
+        sink.request(side, method, duration);
+        if let Err(e) = &res {
+            sink.error(Some((side, method)), metrics::error_class(e));
+        }
+    }
+    #[cfg(feature = "tracing")]
+    {
+        let duration_ms = duration.as_millis() as u64;
+        match &res {
+            Ok(_) => tracing::debug!(duration_ms, outcome = "ok", "Request processed"),
+            Err(e) => tracing::warn!(duration_ms, outcome = "error", error = %e, "Request failed"),
+        }
+    }
+    #[cfg(feature = "log")]
+    let correlation = correlation_id
+        .map(|id| format!("[{}] ", id))
+        .unwrap_or_default();
+    #[cfg(feature = "log")]
+    match &res {
+        Ok(_) => log::debug!(
+            "{}{} request '{}' ({}) with '{}' processed in {}ms",
+            correlation,
+            side.as_str(),
+            method,
+            id,
+            peer_prefix(peer),
+            duration.as_millis()
+        ),
+        Err(e) => log::warn!(
+            "{}{} request '{}' ({}) with '{}' failed after {}ms: '{}'",
+            correlation,
+            side.as_str(),
+            method,
+            id,
+            peer_prefix(peer),
+            duration.as_millis(),
+            e
+        ),
+    }
+
+    res
+}
+
+// A message of this size was read or written
+#[cfg(feature = "transport")]
+pub(crate) fn message(direction: Direction, size: usize) {
+    if let Some(sink) = metrics::sink() {
+        sink.message(direction, size);
+    }
+}
+
+// We closed a connection for this reason
+#[cfg(feature = "transport")]
+pub(crate) fn connection_closed(reason: &'static str) {
+    if let Some(sink) = metrics::sink() {
+        sink.connection_closed(reason);
+    }
+}
@@ -0,0 +1,154 @@
+//! Request journal
+//!
+//! A wallet crashing after sending a request but before reading its response can't
+//! tell whether the peer handled it. The clients of the [client](crate::client) module
+//! given a [Journal] (see for instance
+//! [CoordinatorClient::with_journal](crate::client::CoordinatorClient::with_journal))
+//! record each request in it before sending it, and mark it as answered once they read
+//! its response, whatever it is. On restart, the requests still
+//! [pending](Journal::pending) are the ones the peer may not have received, such as the
+//! signatures that may need to be shared again.
+//!
+//! An entry keeps the request as it was sent, with its id: a request sent again can be
+//! recognized by a peer deduplicating them (see [crate::server::Dedup]).
+
+use crate::{
+    error::{from_json_slice, Error},
+    message::{method, Request},
+};
+
+use serde::{Deserialize, Serialize};
+use std::{fmt, io, sync::Mutex};
+
+/// A request recorded in a [Journal]. It can be (de)serialized to be persisted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// The id of the request
+    pub id: u32,
+    /// The method of the request
+    pub method: String,
+    /// The request, serialized to JSON
+    pub request: String,
+}
+
+impl JournalEntry {
+    /// The entry recording this request
+    pub fn new(req: &Request) -> Result<Self, Error> {
+        Ok(Self {
+            id: req.id(),
+            method: req.method().to_string(),
+            request: serde_json::to_string(req)?,
+        })
+    }
+
+    /// The recorded request, to send it again
+    pub fn request(&self) -> Result<Request<'_>, Error> {
+        from_json_slice(self.request.as_bytes())
+    }
+
+    /// Whether the request shares signatures, with a coordinator or a watchtower
+    pub fn is_sig(&self) -> bool {
+        self.method == method::SIG
+    }
+}
+
+/// The storage of the requests sent by a client, written to before a request is sent.
+///
+/// A client fails a request it could not record, without sending it.
+pub trait Journal: fmt::Debug + Send + Sync {
+    /// Record this request, about to be sent
+    fn record(&self, entry: &JournalEntry) -> io::Result<()>;
+
+    /// Mark the request with this id as answered
+    fn mark_answered(&self, id: u32) -> io::Result<()>;
+
+    /// The requests recorded but not answered, from the oldest
+    fn pending(&self) -> io::Result<Vec<JournalEntry>>;
+}
+
+/// A [Journal] keeping the requests in memory. It doesn't survive restarts, but may
+/// be used to tell which requests a failed exchange left unanswered.
+#[derive(Debug, Default)]
+pub struct MemoryJournal {
+    entries: Mutex<Vec<JournalEntry>>,
+}
+
+impl MemoryJournal {
+    /// An empty journal
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Journal for MemoryJournal {
+    fn record(&self, entry: &JournalEntry) -> io::Result<()> {
+        let mut entries = self.entries.lock().expect("Poisoned lock");
+        // A request retried after a failure is recorded once
+        if !entries.iter().any(|e| e.id == entry.id) {
+            entries.push(entry.clone());
+        }
+        Ok(())
+    }
+
+    fn mark_answered(&self, id: u32) -> io::Result<()> {
+        let mut entries = self.entries.lock().expect("Poisoned lock");
+        entries.retain(|entry| entry.id != id);
+        Ok(())
+    }
+
+    fn pending(&self) -> io::Result<Vec<JournalEntry>> {
+        let entries = self.entries.lock().expect("Poisoned lock");
+        Ok(entries.clone())
+    }
+}
+
+// Whether this result of an exchange means the peer answered the request, even with an
+// error, as opposed to the response not being read
+pub(crate) fn is_answered<T>(res: &Result<T, Error>) -> bool {
+    match res {
+        Ok(_) => true,
+        Err(e) => matches!(
+            e.inner(),
+            Error::Remote(_) | Error::Json(_) | Error::Message(_)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::secp_sig;
+    use crate::message::coordinator::{GetSigs, Sig};
+
+    use bitcoin::Txid;
+
+    #[test]
+    fn memory_journal() {
+        let (pubkey, signature) = secp_sig();
+        let sig = Sig {
+            pubkey,
+            signature,
+            id: Txid::default(),
+        };
+        let sig_req: Request = sig.into();
+        let get_sigs_req: Request = GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        }
+        .into();
+
+        let journal = MemoryJournal::new();
+        let entry = JournalEntry::new(&sig_req).unwrap();
+        journal.record(&entry).unwrap();
+        journal.record(&entry).unwrap();
+        journal
+            .record(&JournalEntry::new(&get_sigs_req).unwrap())
+            .unwrap();
+        journal.mark_answered(get_sigs_req.id()).unwrap();
+
+        let pending = journal.pending().unwrap();
+        assert_eq!(pending, vec![entry]);
+        assert!(pending[0].is_sig());
+        assert_eq!(pending[0].request().unwrap(), sig_req);
+    }
+}
@@ -3,16 +3,125 @@
 //! Generalistic routines to work with Revault-specific network messages, server-client noise handshakes, and tor.
 
 #![warn(missing_docs)]
-#![forbid(unsafe_code)]
+#![cfg_attr(not(any(feature = "ffi", feature = "pipe")), forbid(unsafe_code))]
+#![cfg_attr(any(feature = "ffi", feature = "pipe"), deny(unsafe_code))]
+
+#[cfg(feature = "transport")]
+pub mod bridge;
+
+#[cfg(feature = "transport")]
+pub mod capabilities;
+
+#[cfg(feature = "transport")]
+pub mod capture;
+
+#[cfg(feature = "transport")]
+pub mod client;
+
+pub mod clock;
+
+#[cfg(feature = "transport")]
+pub mod codec;
+
+#[cfg(feature = "transport")]
+pub mod config;
+
+#[cfg(feature = "transport")]
+pub mod connections;
+
+#[cfg(feature = "transport")]
+pub mod delivery;
+
+#[cfg(feature = "transport")]
+pub mod dns;
+
+#[cfg(all(feature = "transport", not(target_arch = "wasm32")))]
+pub mod envelope;
+
+#[cfg(feature = "transport")]
+pub mod events;
+
+#[cfg(feature = "ffi")]
+#[allow(unsafe_code)]
+pub mod ffi;
+
+#[cfg(any(
+    test,
+    all(
+        feature = "revault_tx",
+        any(feature = "testing", feature = "arbitrary")
+    )
+))]
+mod fixtures;
+
+#[cfg(feature = "transport")]
+pub mod flows;
+
+#[cfg(feature = "transport")]
+pub mod http;
+
+#[cfg(feature = "transport")]
+pub mod journal;
 
 pub mod message;
 
+#[cfg(feature = "transport")]
+pub mod metrics;
+
+#[cfg(feature = "transport")]
 pub mod noise;
 
+#[cfg(feature = "transport")]
+pub mod outbox;
+
+#[cfg(feature = "transport")]
+pub mod padding;
+
+#[cfg(all(feature = "pipe", windows))]
+#[allow(unsafe_code)]
+pub mod pipe;
+
+#[cfg(feature = "transport")]
+pub mod pool;
+
+#[cfg(feature = "transport")]
+pub mod prelude;
+
+#[cfg(feature = "transport")]
+pub mod server;
+
+#[cfg(feature = "python")]
+pub mod python;
+
+#[cfg(feature = "transport")]
+pub mod quarantine;
+
+#[cfg(any(
+    all(test, feature = "transport", feature = "revault_tx"),
+    feature = "testing"
+))]
+pub mod testing;
+
+#[cfg(feature = "transport")]
+pub mod throttle;
+
+#[cfg(feature = "transport")]
 pub mod transport;
 
+pub mod validation;
+
+#[cfg(feature = "verify")]
+pub mod verify;
+
+#[cfg(all(feature = "websocket", target_arch = "wasm32"))]
+pub mod websocket;
+
 mod error;
-pub use error::Error;
+mod instrument;
+#[cfg(feature = "transport")]
+pub use error::{AccessError, NoiseError, PreludeError};
+pub use error::{Error, JsonError, JsonLocation, MessageError};
 
-pub use revault_tx::bitcoin;
+pub use bitcoin;
+#[cfg(all(feature = "transport", not(target_arch = "wasm32")))]
 pub use sodiumoxide;
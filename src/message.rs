@@ -6,23 +6,50 @@
 //! Please find the specification at
 //! https://github.com/re-vault/practical-revault/blob/master/messages.md
 
-use serde::{Deserialize, Serialize};
+use crate::{
+    clock::ClockPolicy,
+    error::{Error, MessageError},
+};
+
+use bitcoin::{
+    hashes::{sha256d, Hash, HashEngine},
+    secp256k1, Transaction,
+};
+use serde::{de, ser, Deserialize, Serialize};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
 
 /// A JSONRPC-like request, as specified in [practical-revault](https://github.com/revault/practical-revault/blob/master/messages.md)
+///
+/// Its method is checked to be the one of its params at deserialization.
 #[allow(missing_docs)]
-#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
-#[serde(untagged)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
+// The derived (de)serialization is wrapped by the implementations below
+#[serde(untagged, remote = "Self")]
 pub enum Request<'a> {
     WtSig {
         method: &'a str,
         params: watchtower::Sig,
         id: u32,
     },
+    WtSchnorrSig {
+        method: &'a str,
+        params: watchtower::SchnorrSig,
+        id: u32,
+    },
     SetSpendTx {
         method: &'a str,
         params: coordinator::SetSpendTx,
         id: u32,
     },
+    GetSpendTxChunk {
+        method: &'a str,
+        params: coordinator::GetSpendTxChunk,
+        id: u32,
+    },
     GetSpendTx {
         method: &'a str,
         params: coordinator::GetSpendTx,
@@ -33,16 +60,148 @@ pub enum Request<'a> {
         params: coordinator::Sig,
         id: u32,
     },
+    CoordSchnorrSig {
+        method: &'a str,
+        params: coordinator::SchnorrSig,
+        id: u32,
+    },
     GetSigs {
         method: &'a str,
         params: coordinator::GetSigs,
         id: u32,
     },
+    GetRevocationSigs {
+        method: &'a str,
+        params: coordinator::GetRevocationSigs,
+        id: u32,
+    },
+    // Its params parse as those of a GetSigs without a tag, told apart by method
+    HasSigs {
+        method: &'a str,
+        params: coordinator::HasSigs,
+        id: u32,
+    },
+    #[cfg(feature = "revault_tx")]
     Sign {
         method: &'a str,
         params: cosigner::SignRequest,
         id: u32,
     },
+    #[cfg(feature = "revault_tx")]
+    AckKeyRotation {
+        method: &'a str,
+        params: cosigner::AckKeyRotation,
+        id: u32,
+    },
+    #[cfg(feature = "revault_tx")]
+    BatchSign {
+        method: &'a str,
+        params: cosigner::BatchSignRequest,
+        id: u32,
+    },
+    MusigNonce {
+        method: &'a str,
+        params: coordinator::MusigNonce,
+        id: u32,
+    },
+    MusigPartialSig {
+        method: &'a str,
+        params: coordinator::MusigPartialSig,
+        id: u32,
+    },
+    SealedSig {
+        method: &'a str,
+        params: coordinator::SealedSig,
+        id: u32,
+    },
+    GetSealedSigs {
+        method: &'a str,
+        params: coordinator::GetSealedSigs,
+        id: u32,
+    },
+    StoreBlob {
+        method: &'a str,
+        params: coordinator::StoreBlob,
+        id: u32,
+    },
+    GetBlobs {
+        method: &'a str,
+        params: coordinator::GetBlobs,
+        id: u32,
+    },
+    SetSpendTxBegin {
+        method: &'a str,
+        params: coordinator::SetSpendTxBegin,
+        id: u32,
+    },
+    SetSpendTxChunk {
+        method: &'a str,
+        params: coordinator::SetSpendTxChunk,
+        id: u32,
+    },
+    SetSpendTxCommit {
+        method: &'a str,
+        params: coordinator::SetSpendTxCommit,
+        id: u32,
+    },
+    GetMusigSession {
+        method: &'a str,
+        params: coordinator::GetMusigSession,
+        id: u32,
+    },
+    Subscribe {
+        method: &'a str,
+        params: coordinator::Subscribe,
+        id: u32,
+    },
+    Unsubscribe {
+        method: &'a str,
+        params: coordinator::Unsubscribe,
+        id: u32,
+    },
+    Replicate {
+        method: &'a str,
+        params: replication::Replicate,
+        id: u32,
+    },
+    GetEntries {
+        method: &'a str,
+        params: replication::GetEntries,
+        id: u32,
+    },
+    WtSyncVaults {
+        method: &'a str,
+        params: watchtower::SyncVaults,
+        id: u32,
+    },
+    WtApproveSpend {
+        method: &'a str,
+        params: watchtower::ApproveSpend,
+        id: u32,
+    },
+    WtRecoverSigs {
+        method: &'a str,
+        params: watchtower::RecoverSigs,
+        id: u32,
+    },
+    // The requests whose params have no field must stay last, as those would match
+    // any params. They all parse as the first one and are told apart by method.
+    WtGetSpendPolicy {
+        method: &'a str,
+        params: watchtower::GetSpendPolicy,
+        id: u32,
+    },
+    GetTime {
+        method: &'a str,
+        params: coordinator::GetTime,
+        id: u32,
+    },
+    #[cfg(feature = "revault_tx")]
+    GetPubkey {
+        method: &'a str,
+        params: cosigner::GetPubkey,
+        id: u32,
+    },
 }
 
 impl<'a> Request<'a> {
@@ -50,11 +209,85 @@ impl<'a> Request<'a> {
     pub fn params(self) -> RequestParams {
         match self {
             Request::WtSig { params, .. } => RequestParams::WtSig(params),
+            Request::WtSchnorrSig { params, .. } => RequestParams::WtSchnorrSig(params),
             Request::SetSpendTx { params, .. } => RequestParams::SetSpendTx(params),
             Request::GetSpendTx { params, .. } => RequestParams::GetSpendTx(params),
             Request::CoordSig { params, .. } => RequestParams::CoordSig(params),
+            Request::CoordSchnorrSig { params, .. } => RequestParams::CoordSchnorrSig(params),
             Request::GetSigs { params, .. } => RequestParams::GetSigs(params),
+            Request::GetRevocationSigs { params, .. } => RequestParams::GetRevocationSigs(params),
+            Request::HasSigs { params, .. } => RequestParams::HasSigs(params),
+            #[cfg(feature = "revault_tx")]
             Request::Sign { params, .. } => RequestParams::Sign(params),
+            #[cfg(feature = "revault_tx")]
+            Request::AckKeyRotation { params, .. } => RequestParams::AckKeyRotation(params),
+            #[cfg(feature = "revault_tx")]
+            Request::BatchSign { params, .. } => RequestParams::BatchSign(params),
+            Request::MusigNonce { params, .. } => RequestParams::MusigNonce(params),
+            Request::MusigPartialSig { params, .. } => RequestParams::MusigPartialSig(params),
+            Request::GetMusigSession { params, .. } => RequestParams::GetMusigSession(params),
+            Request::Subscribe { params, .. } => RequestParams::Subscribe(params),
+            Request::Unsubscribe { params, .. } => RequestParams::Unsubscribe(params),
+            Request::Replicate { params, .. } => RequestParams::Replicate(params),
+            Request::GetEntries { params, .. } => RequestParams::GetEntries(params),
+            Request::WtSyncVaults { params, .. } => RequestParams::WtSyncVaults(params),
+            Request::WtApproveSpend { params, .. } => RequestParams::WtApproveSpend(params),
+            Request::WtRecoverSigs { params, .. } => RequestParams::WtRecoverSigs(params),
+            Request::WtGetSpendPolicy { params, .. } => RequestParams::WtGetSpendPolicy(params),
+            Request::GetTime { params, .. } => RequestParams::GetTime(params),
+            #[cfg(feature = "revault_tx")]
+            Request::GetPubkey { params, .. } => RequestParams::GetPubkey(params),
+            Request::SealedSig { params, .. } => RequestParams::SealedSig(params),
+            Request::GetSealedSigs { params, .. } => RequestParams::GetSealedSigs(params),
+            Request::StoreBlob { params, .. } => RequestParams::StoreBlob(params),
+            Request::GetBlobs { params, .. } => RequestParams::GetBlobs(params),
+            Request::SetSpendTxBegin { params, .. } => RequestParams::SetSpendTxBegin(params),
+            Request::SetSpendTxChunk { params, .. } => RequestParams::SetSpendTxChunk(params),
+            Request::SetSpendTxCommit { params, .. } => RequestParams::SetSpendTxCommit(params),
+            Request::GetSpendTxChunk { params, .. } => RequestParams::GetSpendTxChunk(params),
+        }
+    }
+
+    /// Get the method name of this request
+    pub fn method(&self) -> &str {
+        match self {
+            Request::WtSig { method, .. } => method,
+            Request::WtSchnorrSig { method, .. } => method,
+            Request::SetSpendTx { method, .. } => method,
+            Request::GetSpendTx { method, .. } => method,
+            Request::CoordSig { method, .. } => method,
+            Request::CoordSchnorrSig { method, .. } => method,
+            Request::GetSigs { method, .. } => method,
+            Request::GetRevocationSigs { method, .. } => method,
+            Request::HasSigs { method, .. } => method,
+            #[cfg(feature = "revault_tx")]
+            Request::Sign { method, .. } => method,
+            #[cfg(feature = "revault_tx")]
+            Request::AckKeyRotation { method, .. } => method,
+            #[cfg(feature = "revault_tx")]
+            Request::BatchSign { method, .. } => method,
+            Request::MusigNonce { method, .. } => method,
+            Request::MusigPartialSig { method, .. } => method,
+            Request::GetMusigSession { method, .. } => method,
+            Request::Subscribe { method, .. } => method,
+            Request::Unsubscribe { method, .. } => method,
+            Request::Replicate { method, .. } => method,
+            Request::GetEntries { method, .. } => method,
+            Request::WtSyncVaults { method, .. } => method,
+            Request::WtApproveSpend { method, .. } => method,
+            Request::WtRecoverSigs { method, .. } => method,
+            Request::WtGetSpendPolicy { method, .. } => method,
+            Request::GetTime { method, .. } => method,
+            #[cfg(feature = "revault_tx")]
+            Request::GetPubkey { method, .. } => method,
+            Request::SealedSig { method, .. } => method,
+            Request::GetSealedSigs { method, .. } => method,
+            Request::StoreBlob { method, .. } => method,
+            Request::GetBlobs { method, .. } => method,
+            Request::SetSpendTxBegin { method, .. } => method,
+            Request::SetSpendTxChunk { method, .. } => method,
+            Request::SetSpendTxCommit { method, .. } => method,
+            Request::GetSpendTxChunk { method, .. } => method,
         }
     }
 
@@ -62,504 +295,6525 @@ impl<'a> Request<'a> {
     pub fn id(&self) -> u32 {
         match self {
             Request::WtSig { id, .. } => *id,
+            Request::WtSchnorrSig { id, .. } => *id,
             Request::SetSpendTx { id, .. } => *id,
             Request::GetSpendTx { id, .. } => *id,
             Request::CoordSig { id, .. } => *id,
+            Request::CoordSchnorrSig { id, .. } => *id,
             Request::GetSigs { id, .. } => *id,
+            Request::GetRevocationSigs { id, .. } => *id,
+            Request::HasSigs { id, .. } => *id,
+            #[cfg(feature = "revault_tx")]
             Request::Sign { id, .. } => *id,
+            #[cfg(feature = "revault_tx")]
+            Request::AckKeyRotation { id, .. } => *id,
+            #[cfg(feature = "revault_tx")]
+            Request::BatchSign { id, .. } => *id,
+            Request::MusigNonce { id, .. } => *id,
+            Request::MusigPartialSig { id, .. } => *id,
+            Request::GetMusigSession { id, .. } => *id,
+            Request::Subscribe { id, .. } => *id,
+            Request::Unsubscribe { id, .. } => *id,
+            Request::Replicate { id, .. } => *id,
+            Request::GetEntries { id, .. } => *id,
+            Request::WtSyncVaults { id, .. } => *id,
+            Request::WtApproveSpend { id, .. } => *id,
+            Request::WtRecoverSigs { id, .. } => *id,
+            Request::WtGetSpendPolicy { id, .. } => *id,
+            Request::GetTime { id, .. } => *id,
+            #[cfg(feature = "revault_tx")]
+            Request::GetPubkey { id, .. } => *id,
+            Request::SealedSig { id, .. } => *id,
+            Request::GetSealedSigs { id, .. } => *id,
+            Request::StoreBlob { id, .. } => *id,
+            Request::GetBlobs { id, .. } => *id,
+            Request::SetSpendTxBegin { id, .. } => *id,
+            Request::SetSpendTxChunk { id, .. } => *id,
+            Request::SetSpendTxCommit { id, .. } => *id,
+            Request::GetSpendTxChunk { id, .. } => *id,
+        }
+    }
+
+    // The method of the params of this request
+    fn params_method(&self) -> &'static str {
+        match self {
+            Request::WtSig { .. }
+            | Request::WtSchnorrSig { .. }
+            | Request::CoordSig { .. }
+            | Request::CoordSchnorrSig { .. } => method::SIG,
+            Request::SetSpendTx { .. } => method::SET_SPEND_TX,
+            Request::GetSpendTx { .. } => method::GET_SPEND_TX,
+            Request::GetSigs { .. } => method::GET_SIGS,
+            Request::GetRevocationSigs { .. } => method::GET_REVOCATION_SIGS,
+            Request::HasSigs { .. } => method::HAS_SIGS,
+            #[cfg(feature = "revault_tx")]
+            Request::Sign { .. } => method::SIGN,
+            #[cfg(feature = "revault_tx")]
+            Request::AckKeyRotation { .. } => method::ACK_KEY_ROTATION,
+            #[cfg(feature = "revault_tx")]
+            Request::BatchSign { .. } => method::SIGN,
+            Request::MusigNonce { .. } => method::MUSIG_NONCE,
+            Request::MusigPartialSig { .. } => method::MUSIG_PARTIAL_SIG,
+            Request::GetMusigSession { .. } => method::GET_MUSIG_SESSION,
+            Request::Subscribe { .. } => method::SUBSCRIBE,
+            Request::Unsubscribe { .. } => method::UNSUBSCRIBE,
+            Request::Replicate { .. } => method::REPLICATE,
+            Request::GetEntries { .. } => method::GET_ENTRIES,
+            Request::WtSyncVaults { .. } => method::SYNC_VAULTS,
+            Request::WtApproveSpend { .. } => method::APPROVE_SPEND,
+            Request::WtRecoverSigs { .. } => method::RECOVER_SIGS,
+            Request::WtGetSpendPolicy { .. } => method::GET_SPEND_POLICY,
+            Request::GetTime { .. } => method::GET_TIME,
+            #[cfg(feature = "revault_tx")]
+            Request::GetPubkey { .. } => method::GET_PUBKEY,
+            Request::SealedSig { .. } => method::SEALED_SIG,
+            Request::GetSealedSigs { .. } => method::GET_SEALED_SIGS,
+            Request::StoreBlob { .. } => method::STORE_BLOB,
+            Request::GetBlobs { .. } => method::GET_BLOBS,
+            Request::SetSpendTxBegin { .. } => method::SET_SPEND_TX_BEGIN,
+            Request::SetSpendTxChunk { .. } => method::SET_SPEND_TX_CHUNK,
+            Request::SetSpendTxCommit { .. } => method::SET_SPEND_TX_COMMIT,
+            Request::GetSpendTxChunk { .. } => method::GET_SPEND_TX_CHUNK,
+        }
+    }
+}
+
+impl<'a> Request<'a> {
+    // The params without any field all parse as those of the first such request, get
+    // the request for the method instead.
+    fn with_fieldless_params(self) -> Self {
+        let (method, id) = match self {
+            Request::WtGetSpendPolicy { method, id, .. } => (method, id),
+            Request::GetTime { method, id, .. } => (method, id),
+            #[cfg(feature = "revault_tx")]
+            Request::GetPubkey { method, id, .. } => (method, id),
+            request => return request,
+        };
+        match method {
+            #[cfg(feature = "revault_tx")]
+            method::GET_PUBKEY => Request::GetPubkey {
+                method,
+                params: cosigner::GetPubkey {},
+                id,
+            },
+            method::GET_TIME => Request::GetTime {
+                method,
+                params: coordinator::GetTime {},
+                id,
+            },
+            // Including the unknown methods, for the method check to report them
+            _ => Request::WtGetSpendPolicy {
+                method,
+                params: watchtower::GetSpendPolicy {},
+                id,
+            },
+        }
+    }
+
+    // The params of some requests parse as those of another one: has_sigs as get_sigs
+    // without a tag, recover_sigs as get_spend_tx. Get the request for the method
+    // instead.
+    fn with_lookalike_params(self) -> Self {
+        match self {
+            Request::GetSigs {
+                method: method @ method::HAS_SIGS,
+                params:
+                    coordinator::GetSigs {
+                        id: txid,
+                        if_none_match: None,
+                    },
+                id,
+            } => Request::HasSigs {
+                method,
+                params: coordinator::HasSigs { id: txid },
+                id,
+            },
+            Request::GetSpendTx {
+                method: method @ method::RECOVER_SIGS,
+                params: coordinator::GetSpendTx { deposit_outpoint },
+                id,
+            } => Request::WtRecoverSigs {
+                method,
+                params: watchtower::RecoverSigs { deposit_outpoint },
+                id,
+            },
+            request => request,
+        }
+    }
+}
+
+impl Serialize for Request<'_> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Request::serialize(self, serializer)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Request<'a> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let request = Request::deserialize(deserializer)?
+            .with_fieldless_params()
+            .with_lookalike_params();
+        // The params are not tagged with their method, a request whose method doesn't
+        // match its params would otherwise be parsed as a request for another method.
+        let expected = request.params_method();
+        if request.method() != expected {
+            return Err(de::Error::custom(MessageError::MethodMismatch {
+                method: request.method().to_string(),
+                expected,
+            }));
+        }
+
+        Ok(request)
+    }
+}
+
+/// A one-line summary of the request: its method, id and the txid or outpoint it
+/// is about. Signatures and public keys are left out.
+impl std::fmt::Display for Request<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} (id {}) ", self.method(), self.id())?;
+        match self {
+            Request::WtSig { params, .. } => params.fmt(f),
+            Request::WtSchnorrSig { params, .. } => params.fmt(f),
+            Request::SetSpendTx { params, .. } => params.fmt(f),
+            Request::GetSpendTx { params, .. } => params.fmt(f),
+            Request::CoordSig { params, .. } => params.fmt(f),
+            Request::CoordSchnorrSig { params, .. } => params.fmt(f),
+            Request::GetSigs { params, .. } => params.fmt(f),
+            Request::GetRevocationSigs { params, .. } => params.fmt(f),
+            Request::HasSigs { params, .. } => params.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            Request::Sign { params, .. } => params.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            Request::AckKeyRotation { params, .. } => params.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            Request::BatchSign { params, .. } => params.fmt(f),
+            Request::MusigNonce { params, .. } => params.fmt(f),
+            Request::MusigPartialSig { params, .. } => params.fmt(f),
+            Request::GetMusigSession { params, .. } => params.fmt(f),
+            Request::Subscribe { params, .. } => params.fmt(f),
+            Request::Unsubscribe { params, .. } => params.fmt(f),
+            Request::Replicate { params, .. } => params.fmt(f),
+            Request::GetEntries { params, .. } => params.fmt(f),
+            Request::WtSyncVaults { params, .. } => params.fmt(f),
+            Request::WtApproveSpend { params, .. } => params.fmt(f),
+            Request::WtRecoverSigs { params, .. } => params.fmt(f),
+            Request::WtGetSpendPolicy { params, .. } => params.fmt(f),
+            Request::GetTime { params, .. } => params.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            Request::GetPubkey { params, .. } => params.fmt(f),
+            Request::SealedSig { params, .. } => params.fmt(f),
+            Request::GetSealedSigs { params, .. } => params.fmt(f),
+            Request::StoreBlob { params, .. } => params.fmt(f),
+            Request::GetBlobs { params, .. } => params.fmt(f),
+            Request::SetSpendTxBegin { params, .. } => params.fmt(f),
+            Request::SetSpendTxChunk { params, .. } => params.fmt(f),
+            Request::SetSpendTxCommit { params, .. } => params.fmt(f),
+            Request::GetSpendTxChunk { params, .. } => params.fmt(f),
         }
     }
 }
 
 /// All params types that can possibly be sent through a Request
 #[allow(missing_docs)]
-#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum RequestParams {
     WtSig(watchtower::Sig),
+    WtSchnorrSig(watchtower::SchnorrSig),
     SetSpendTx(coordinator::SetSpendTx),
+    GetSpendTxChunk(coordinator::GetSpendTxChunk),
     GetSpendTx(coordinator::GetSpendTx),
     CoordSig(coordinator::Sig),
+    CoordSchnorrSig(coordinator::SchnorrSig),
     GetSigs(coordinator::GetSigs),
+    GetRevocationSigs(coordinator::GetRevocationSigs),
+    HasSigs(coordinator::HasSigs),
+    #[cfg(feature = "revault_tx")]
     Sign(cosigner::SignRequest),
+    #[cfg(feature = "revault_tx")]
+    AckKeyRotation(cosigner::AckKeyRotation),
+    #[cfg(feature = "revault_tx")]
+    BatchSign(cosigner::BatchSignRequest),
+    MusigNonce(coordinator::MusigNonce),
+    MusigPartialSig(coordinator::MusigPartialSig),
+    SealedSig(coordinator::SealedSig),
+    GetSealedSigs(coordinator::GetSealedSigs),
+    StoreBlob(coordinator::StoreBlob),
+    GetBlobs(coordinator::GetBlobs),
+    SetSpendTxBegin(coordinator::SetSpendTxBegin),
+    SetSpendTxChunk(coordinator::SetSpendTxChunk),
+    SetSpendTxCommit(coordinator::SetSpendTxCommit),
+    GetMusigSession(coordinator::GetMusigSession),
+    Subscribe(coordinator::Subscribe),
+    Unsubscribe(coordinator::Unsubscribe),
+    Replicate(replication::Replicate),
+    GetEntries(replication::GetEntries),
+    WtSyncVaults(watchtower::SyncVaults),
+    WtApproveSpend(watchtower::ApproveSpend),
+    WtRecoverSigs(watchtower::RecoverSigs),
+    // Must stay last: they have no field, hence they would match any params
+    WtGetSpendPolicy(watchtower::GetSpendPolicy),
+    GetTime(coordinator::GetTime),
+    #[cfg(feature = "revault_tx")]
+    GetPubkey(cosigner::GetPubkey),
+}
+
+impl RequestParams {
+    /// Get the method name of a request with these parameters
+    pub fn method(&self) -> &'static str {
+        match self {
+            RequestParams::WtSig(_)
+            | RequestParams::WtSchnorrSig(_)
+            | RequestParams::CoordSig(_)
+            | RequestParams::CoordSchnorrSig(_) => method::SIG,
+            RequestParams::SetSpendTx(_) => method::SET_SPEND_TX,
+            RequestParams::GetSpendTx(_) => method::GET_SPEND_TX,
+            RequestParams::GetSigs(_) => method::GET_SIGS,
+            RequestParams::GetRevocationSigs(_) => method::GET_REVOCATION_SIGS,
+            RequestParams::HasSigs(_) => method::HAS_SIGS,
+            #[cfg(feature = "revault_tx")]
+            RequestParams::Sign(_) => method::SIGN,
+            #[cfg(feature = "revault_tx")]
+            RequestParams::AckKeyRotation(_) => method::ACK_KEY_ROTATION,
+            #[cfg(feature = "revault_tx")]
+            RequestParams::BatchSign(_) => method::SIGN,
+            RequestParams::MusigNonce(_) => method::MUSIG_NONCE,
+            RequestParams::MusigPartialSig(_) => method::MUSIG_PARTIAL_SIG,
+            RequestParams::GetMusigSession(_) => method::GET_MUSIG_SESSION,
+            RequestParams::Subscribe(_) => method::SUBSCRIBE,
+            RequestParams::Unsubscribe(_) => method::UNSUBSCRIBE,
+            RequestParams::Replicate(_) => method::REPLICATE,
+            RequestParams::GetEntries(_) => method::GET_ENTRIES,
+            RequestParams::WtSyncVaults(_) => method::SYNC_VAULTS,
+            RequestParams::WtApproveSpend(_) => method::APPROVE_SPEND,
+            RequestParams::WtRecoverSigs(_) => method::RECOVER_SIGS,
+            RequestParams::WtGetSpendPolicy(_) => method::GET_SPEND_POLICY,
+            RequestParams::GetTime(_) => method::GET_TIME,
+            #[cfg(feature = "revault_tx")]
+            RequestParams::GetPubkey(_) => method::GET_PUBKEY,
+            RequestParams::SealedSig(_) => method::SEALED_SIG,
+            RequestParams::GetSealedSigs(_) => method::GET_SEALED_SIGS,
+            RequestParams::StoreBlob(_) => method::STORE_BLOB,
+            RequestParams::GetBlobs(_) => method::GET_BLOBS,
+            RequestParams::SetSpendTxBegin(_) => method::SET_SPEND_TX_BEGIN,
+            RequestParams::SetSpendTxChunk(_) => method::SET_SPEND_TX_CHUNK,
+            RequestParams::SetSpendTxCommit(_) => method::SET_SPEND_TX_COMMIT,
+            RequestParams::GetSpendTxChunk(_) => method::GET_SPEND_TX_CHUNK,
+        }
+    }
+}
+
+impl std::fmt::Display for RequestParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RequestParams::WtSig(params) => params.fmt(f),
+            RequestParams::WtSchnorrSig(params) => params.fmt(f),
+            RequestParams::SetSpendTx(params) => params.fmt(f),
+            RequestParams::GetSpendTx(params) => params.fmt(f),
+            RequestParams::CoordSig(params) => params.fmt(f),
+            RequestParams::CoordSchnorrSig(params) => params.fmt(f),
+            RequestParams::GetSigs(params) => params.fmt(f),
+            RequestParams::GetRevocationSigs(params) => params.fmt(f),
+            RequestParams::HasSigs(params) => params.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            RequestParams::Sign(params) => params.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            RequestParams::AckKeyRotation(params) => params.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            RequestParams::BatchSign(params) => params.fmt(f),
+            RequestParams::MusigNonce(params) => params.fmt(f),
+            RequestParams::MusigPartialSig(params) => params.fmt(f),
+            RequestParams::GetMusigSession(params) => params.fmt(f),
+            RequestParams::Subscribe(params) => params.fmt(f),
+            RequestParams::Unsubscribe(params) => params.fmt(f),
+            RequestParams::Replicate(params) => params.fmt(f),
+            RequestParams::GetEntries(params) => params.fmt(f),
+            RequestParams::WtSyncVaults(params) => params.fmt(f),
+            RequestParams::WtApproveSpend(params) => params.fmt(f),
+            RequestParams::WtRecoverSigs(params) => params.fmt(f),
+            RequestParams::WtGetSpendPolicy(params) => params.fmt(f),
+            RequestParams::GetTime(params) => params.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            RequestParams::GetPubkey(params) => params.fmt(f),
+            RequestParams::SealedSig(params) => params.fmt(f),
+            RequestParams::GetSealedSigs(params) => params.fmt(f),
+            RequestParams::StoreBlob(params) => params.fmt(f),
+            RequestParams::GetBlobs(params) => params.fmt(f),
+            RequestParams::SetSpendTxBegin(params) => params.fmt(f),
+            RequestParams::SetSpendTxChunk(params) => params.fmt(f),
+            RequestParams::SetSpendTxCommit(params) => params.fmt(f),
+            RequestParams::GetSpendTxChunk(params) => params.fmt(f),
+        }
+    }
+}
+
+/// Generates the ids of the requests.
+///
+/// Ids are drawn from the CSPRNG by default ([RandomIds]). Tests can use a
+/// deterministic generator on a thread with `with_id_generator`, available with the
+/// `testing` or `arbitrary` feature. A connection can number its own requests with
+/// [KKTransport::set_sequential_ids](crate::transport::KKTransport::set_sequential_ids).
+pub trait IdGenerator {
+    /// The id of the next request
+    fn next_id(&mut self) -> u32;
+}
+
+/// Draw the ids from the randomness of the operating system (or of the browser).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomIds;
+
+impl IdGenerator for RandomIds {
+    fn next_id(&mut self) -> u32 {
+        // Ids are uniform in [0, u32::MAX)
+        loop {
+            let mut id = [0; 4];
+            getrandom::getrandom(&mut id).expect("Failed to get randomness from the OS");
+            let id = u32::from_le_bytes(id);
+            if id != u32::MAX {
+                return id;
+            }
+        }
+    }
+}
+
+/// Give sequential ids, starting from this one
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SequentialIds(pub u32);
+
+impl IdGenerator for SequentialIds {
+    fn next_id(&mut self) -> u32 {
+        let id = self.0;
+        self.0 = self.0.wrapping_add(1);
+        id
+    }
+}
+
+/// Draw the ids from a (non cryptographic) PRNG seeded with this value
+#[derive(Debug, Clone, Copy)]
+pub struct SeededIds(u64);
+
+impl SeededIds {
+    /// A generator seeded with this value
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    // The next output of the PRNG (splitmix64)
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl IdGenerator for SeededIds {
+    fn next_id(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+}
+
+thread_local! {
+    static ID_GENERATOR: RefCell<Option<Box<dyn IdGenerator>>> = const { RefCell::new(None) };
+}
+
+// Restores the previous generator when going out of scope, even on panic
+#[cfg(any(test, feature = "transport", feature = "arbitrary"))]
+struct IdGeneratorGuard(Option<Box<dyn IdGenerator>>);
+
+#[cfg(any(test, feature = "transport", feature = "arbitrary"))]
+impl Drop for IdGeneratorGuard {
+    fn drop(&mut self) {
+        ID_GENERATOR.with(|g| *g.borrow_mut() = self.0.take());
+    }
+}
+
+/// Run `f` with this generator giving the ids of all the requests created on the
+/// current thread. The CSPRNG is used outside of a scope.
+///
+/// Only meant for tests: predictable ids let a peer forge the responses to our
+/// requests.
+#[cfg(any(test, feature = "testing", feature = "arbitrary"))]
+pub fn with_id_generator<G, T, F>(generator: G, f: F) -> T
+where
+    G: IdGenerator + 'static,
+    F: FnOnce() -> T,
+{
+    scoped_id_generator(generator, f)
+}
+
+// Run `f` with this generator giving the ids of the requests it creates
+#[cfg(any(test, feature = "transport", feature = "arbitrary"))]
+pub(crate) fn scoped_id_generator<G, T, F>(generator: G, f: F) -> T
+where
+    G: IdGenerator + 'static,
+    F: FnOnce() -> T,
+{
+    let prev = ID_GENERATOR.with(|g| g.replace(Some(Box::new(generator))));
+    let _guard = IdGeneratorGuard(prev);
+    f()
+}
+
+// The id of a new request
+pub(crate) fn next_request_id() -> u32 {
+    ID_GENERATOR.with(|g| match g.borrow_mut().as_mut() {
+        Some(generator) => generator.next_id(),
+        None => RandomIds.next_id(),
+    })
 }
 
 // Implement From(param type) for a Request
 macro_rules! impl_to_request {
-    ($message_struct:ident, $message_name:literal, $enum_variant:ident) => {
+    ($message_struct:ty, $message_name:expr, $enum_variant:ident) => {
         impl From<$message_struct> for Request<'_> {
             fn from(params: $message_struct) -> Self {
                 Self::$enum_variant {
                     method: $message_name,
                     params,
-                    id: sodiumoxide::randombytes::randombytes_uniform(u32::MAX),
+                    id: $crate::message::next_request_id(),
                 }
             }
         }
     };
 }
 
+// Signatures don't implement Hash with all the secp256k1 versions we support, messages
+// carrying them hash their encoding instead.
+trait SigHash {
+    fn sig_hash<H: std::hash::Hasher>(&self, state: &mut H);
+}
+
+impl SigHash for secp256k1::Signature {
+    fn sig_hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::hash::Hash::hash(&self.serialize_compact(), state)
+    }
+}
+
+impl SigHash for secp256k1::schnorrsig::Signature {
+    fn sig_hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::hash::Hash::hash(self.as_ref(), state)
+    }
+}
+
+impl<K: std::hash::Hash, S: SigHash> SigHash for BTreeMap<K, S> {
+    fn sig_hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::hash::Hash::hash(&self.len(), state);
+        for (pubkey, signature) in self.iter() {
+            std::hash::Hash::hash(pubkey, state);
+            signature.sig_hash(state);
+        }
+    }
+}
+
+// Implement Hash for a message from its fields and its signature fields
+macro_rules! impl_hash_with_sigs {
+    ($message_struct:ty, [$($field:ident),*], [$($sig_field:ident),*]) => {
+        impl std::hash::Hash for $message_struct {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                $(std::hash::Hash::hash(&self.$field, state);)*
+                $($crate::message::SigHash::sig_hash(&self.$sig_field, state);)*
+            }
+        }
+    };
+}
+
+// PSBTs don't implement Hash, messages carrying one hash its serialization instead
+#[cfg(feature = "revault_tx")]
+fn psbt_bytes(tx: &revault_tx::transactions::SpendTransaction) -> Vec<u8> {
+    use revault_tx::transactions::RevaultTransaction;
+    tx.as_psbt_serialized()
+}
+
 /// All result types that can possibly be returned by a Response
 #[allow(missing_docs)]
-#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum ResponseResult {
     WtSig(watchtower::SigResult),
+    WtSpendPolicy(watchtower::SpendPolicy),
+    WtSpendApproval(watchtower::SpendApproval),
     Sigs(coordinator::Sigs),
+    RevocationSigs(coordinator::RevocationSigs),
+    SchnorrSigs(coordinator::SchnorrSigs),
+    NotModified(coordinator::NotModified),
+    ServerTime(coordinator::ServerTime),
+    SigsCount(coordinator::SigsCount),
+    #[cfg(feature = "revault_tx")]
+    BatchSignResult(cosigner::BatchSignResult),
+    #[cfg(feature = "revault_tx")]
+    AckKeyRotation(cosigner::AckKeyRotationResult),
+    #[cfg(feature = "revault_tx")]
+    CosignerPubkey(cosigner::GetPubkeyResult),
     Sig(coordinator::SigResult),
     SetSpend(coordinator::SetSpendResult),
     SpendTx(coordinator::SpendTx),
+    MusigSession(coordinator::MusigSession),
+    Subscribed(coordinator::SubscribeResult),
+    Unsubscribed(coordinator::UnsubscribeResult),
+    Replicated(replication::ReplicateResult),
+    Entries(replication::Entries),
+    WtSyncVaults(watchtower::SyncVaultsResult),
+    WtRecoveredSigs(watchtower::RecoveredSigs),
+    SealedSigs(coordinator::SealedSigs),
+    Blobs(coordinator::Blobs),
+    UploadStarted(coordinator::UploadStarted),
+    SpendTxChunk(coordinator::SpendTxChunk),
+    // Must stay last: its only field is optional, hence it would match any result
+    #[cfg(feature = "revault_tx")]
     SignResult(cosigner::SignResult),
 }
 
+impl std::fmt::Display for ResponseResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ResponseResult::WtSig(result) => result.fmt(f),
+            ResponseResult::WtSpendPolicy(result) => result.fmt(f),
+            ResponseResult::WtSpendApproval(result) => result.fmt(f),
+            ResponseResult::Sigs(result) => result.fmt(f),
+            ResponseResult::RevocationSigs(result) => result.fmt(f),
+            ResponseResult::SchnorrSigs(result) => result.fmt(f),
+            ResponseResult::NotModified(result) => result.fmt(f),
+            ResponseResult::ServerTime(result) => result.fmt(f),
+            ResponseResult::SigsCount(result) => result.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            ResponseResult::BatchSignResult(result) => result.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            ResponseResult::AckKeyRotation(result) => result.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            ResponseResult::CosignerPubkey(result) => result.fmt(f),
+            ResponseResult::Sig(result) => result.fmt(f),
+            ResponseResult::SetSpend(result) => result.fmt(f),
+            ResponseResult::SpendTx(result) => result.fmt(f),
+            ResponseResult::MusigSession(result) => result.fmt(f),
+            ResponseResult::Subscribed(result) => result.fmt(f),
+            ResponseResult::Unsubscribed(result) => result.fmt(f),
+            ResponseResult::Replicated(result) => result.fmt(f),
+            ResponseResult::Entries(result) => result.fmt(f),
+            ResponseResult::WtSyncVaults(result) => result.fmt(f),
+            ResponseResult::WtRecoveredSigs(result) => result.fmt(f),
+            ResponseResult::SealedSigs(result) => result.fmt(f),
+            ResponseResult::Blobs(result) => result.fmt(f),
+            ResponseResult::UploadStarted(result) => result.fmt(f),
+            ResponseResult::SpendTxChunk(result) => result.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            ResponseResult::SignResult(result) => result.fmt(f),
+        }
+    }
+}
+
 /// A JSONRPC-like response, as specified in [practical-revault](https://github.com/revault/practical-revault/blob/master/messages.md)
 #[allow(missing_docs)]
-#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
 pub struct Response<T> {
     pub result: T,
     pub id: u32,
 }
 
-/// Messages related to the communication with the Watchtower(s)
-pub mod watchtower {
-    use super::{Deserialize, Request, Serialize};
-    use bitcoin::{
-        hash_types::Txid,
-        secp256k1::{key::PublicKey, Signature},
-        OutPoint,
-    };
-    use std::collections::BTreeMap;
-    use std::convert::From;
-
-    /// Message from a stakeholder to share all signatures for a revocation
-    /// transaction with its watchtower.
-    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-    pub struct Sig {
-        /// A sufficient set of public keys and associated ALL|ANYONECANPAY
-        /// bitcoin ECDSA signatures to validate the revocation transaction
-        pub signatures: BTreeMap<PublicKey, Signature>,
-        /// Revocation transaction id
-        pub txid: Txid,
-        /// Deposit outpoint of this vault
-        pub deposit_outpoint: OutPoint,
-    }
-    impl_to_request!(Sig, "sig", WtSig);
-
-    /// Message from the watchtower to stakeholder to acknowledge that it has
-    /// sufficient signatures and fees to begin guarding the vault with the
-    /// revocation transaction
-    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-    pub struct SigResult {
-        /// Result of acknowledgement
-        pub ack: bool,
-        // FIXME: we don't need it anymore once we have ids in messages
-        /// Revocation transaction id
-        pub txid: Txid,
+impl<T: std::fmt::Display> std::fmt::Display for Response<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "response (id {}) {}", self.id, self.result)
     }
 }
 
-/// Messages related to the communication with the Coordinator
-pub mod coordinator {
-    use super::{Deserialize, Request, Serialize};
-    use bitcoin::{
-        hash_types::Txid,
-        secp256k1::{key::PublicKey, Signature},
-        OutPoint, Transaction,
-    };
-    use revault_tx::transactions::{RevaultTransaction, SpendTransaction};
-    use std::collections::BTreeMap;
-    use std::convert::From;
+/// The role of a participant in the Revault network, as a peer making requests to a
+/// server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// A stakeholder's wallet
+    Stakeholder,
+    /// A manager's wallet
+    Manager,
+    /// A stakeholder's watchtower
+    Watchtower,
+    /// Another coordinator, replicating our storage
+    Coordinator,
+}
 
-    mod serde_tx_hex {
-        use revault_tx::bitcoin::{
-            consensus::encode,
-            hashes::hex::{FromHex, ToHex},
-            Transaction,
-        };
-        use serde::{self, Deserialize, Deserializer, Serialize, Serializer};
+/// The code of an error responded to a request.
+///
+/// The messages specification does not define error codes, so these are the ones of
+/// [JSON-RPC 2.0](https://www.jsonrpc.org/specification#error_object) which it is
+/// modeled after, along with Revault-specific codes in the range reserved for servers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// The request is not valid JSON
+    ParseError,
+    /// The request is not a valid request object
+    InvalidRequest,
+    /// The method does not exist or is not handled by this server
+    MethodNotFound,
+    /// The parameters of the request are invalid
+    InvalidParams,
+    /// The server failed to process the request
+    InternalError,
+    /// The peer is not allowed to make this request
+    AccessDenied,
+    /// The request conflicts with data the server already has
+    Conflict,
+    /// The server has too many connections to serve this one
+    ServerBusy,
+    /// The peer exceeded its share of the server's storage
+    QuotaExceeded,
+    /// The server has reached its storage capacity, for all peers
+    StorageFull,
+    /// An error code we don't know about
+    Other(i64),
+}
 
-        pub fn serialize<S>(tx: &Transaction, serializer: S) -> Result<S::Ok, S::Error>
-        where
-            S: Serializer,
-        {
-            let hex_str = encode::serialize(tx).to_hex();
-            hex_str.serialize(serializer)
+impl ErrorCode {
+    /// Get the numeric value of this error code
+    pub fn code(&self) -> i64 {
+        match *self {
+            Self::ParseError => -32700,
+            Self::InvalidRequest => -32600,
+            Self::MethodNotFound => -32601,
+            Self::InvalidParams => -32602,
+            Self::InternalError => -32603,
+            Self::AccessDenied => -32001,
+            Self::Conflict => -32002,
+            Self::ServerBusy => -32003,
+            Self::QuotaExceeded => -32004,
+            Self::StorageFull => -32005,
+            Self::Other(code) => code,
         }
+    }
 
-        pub fn deserialize<'de, D>(deserializer: D) -> Result<Transaction, D::Error>
-        where
-            D: Deserializer<'de>,
-        {
-            let s = String::deserialize(deserializer)?;
-            let bytes = Vec::from_hex(&s).map_err(serde::de::Error::custom)?;
-            encode::deserialize::<Transaction>(&bytes).map_err(serde::de::Error::custom)
+    /// Get the error code with this numeric value
+    pub fn from_code(code: i64) -> Self {
+        match code {
+            -32700 => Self::ParseError,
+            -32600 => Self::InvalidRequest,
+            -32601 => Self::MethodNotFound,
+            -32602 => Self::InvalidParams,
+            -32603 => Self::InternalError,
+            -32001 => Self::AccessDenied,
+            -32002 => Self::Conflict,
+            -32003 => Self::ServerBusy,
+            -32004 => Self::QuotaExceeded,
+            -32005 => Self::StorageFull,
+            code => Self::Other(code),
         }
     }
+}
 
-    /// Sent by a wallet to retrieve all signatures for a specific transaction
-    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-    pub struct GetSigs {
-        /// Transaction id
-        pub id: Txid,
+impl From<&Error> for ErrorCode {
+    fn from(error: &Error) -> Self {
+        match error.inner() {
+            Error::Json(e) if e.inner().is_data() => Self::InvalidParams,
+            Error::Json(_) => Self::ParseError,
+            Error::Message(MessageError::ConflictingSignature(_)) => Self::Conflict,
+            Error::Message(_) | Error::Signature(_) => Self::InvalidParams,
+            #[cfg(feature = "transport")]
+            Error::Access(_) => Self::AccessDenied,
+            Error::Remote(e) => e.code,
+            _ => Self::InternalError,
+        }
     }
-    impl_to_request!(GetSigs, "get_sigs", GetSigs);
+}
 
-    /// Message response to get_sigs from sync server to wallet client with a
-    /// (potentially incomplete) mapping of each public key to each signature
-    /// required to verify this **usual** transaction
-    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-    pub struct Sigs {
-        /// Mapping of public keys to ECDSA signatures for the requested usual
-        /// transaction.
-        pub signatures: BTreeMap<PublicKey, Signature>,
+impl Serialize for ErrorCode {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(self.code())
     }
+}
 
-    /// Sent by a manager to advertise the spend transaction that will eventually
-    /// be used for a specific unvault.
-    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-    pub struct SetSpendTx {
-        /// Deposit outpoints of the vault this transaction is spending
-        pub deposit_outpoints: Vec<OutPoint>,
-        /// Fully signed spend transaction, as hex
-        #[serde(with = "serde_tx_hex")]
-        transaction: Transaction,
+impl<'de> Deserialize<'de> for ErrorCode {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        i64::deserialize(deserializer).map(Self::from_code)
     }
-    impl_to_request!(SetSpendTx, "set_spend_tx", SetSpendTx);
+}
 
-    impl SetSpendTx {
-        /// Create a SetSpendTx message out of a SpendTransaction.
-        ///
-        /// The SpendTransaction MUST have been finalized beforehand or it'll panic.
-        pub fn from_spend_tx(deposit_outpoints: Vec<OutPoint>, tx: SpendTransaction) -> Self {
-            assert!(tx.is_finalized());
-            let transaction = tx.into_psbt().extract_tx();
-            Self {
-                deposit_outpoints,
-                transaction,
-            }
+/// Additional information about an error, for the client to handle it
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
+pub struct ErrorData {
+    /// The number of seconds after which the request may succeed if retried
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+/// The error a request failed with
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
+pub struct ResponseError {
+    /// The class of error
+    pub code: ErrorCode,
+    /// A description of the error
+    pub message: String,
+    /// Additional information, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<ErrorData>,
+}
+
+impl ResponseError {
+    /// An error of this class, with no additional information
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
         }
+    }
 
-        /// Get the raw spend transaction
-        pub fn spend_tx(self) -> Transaction {
-            self.transaction
+    /// The peer exceeded its storage quota. It may retry after the given delay, for
+    /// instance once some of its data expired.
+    pub fn quota_exceeded(message: impl Into<String>, retry_after: Option<Duration>) -> Self {
+        Self::new(ErrorCode::QuotaExceeded, message).retry_hint(retry_after)
+    }
+
+    /// The server is at its storage capacity. Peers may retry after the given delay.
+    pub fn storage_full(message: impl Into<String>, retry_after: Option<Duration>) -> Self {
+        Self::new(ErrorCode::StorageFull, message).retry_hint(retry_after)
+    }
+
+    /// Hint the client to retry after this delay, rounded up to the second
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        self.data.get_or_insert_with(ErrorData::default).retry_after = Some(secs);
+        self
+    }
+
+    fn retry_hint(self, retry_after: Option<Duration>) -> Self {
+        match retry_after {
+            Some(retry_after) => self.with_retry_after(retry_after),
+            None => self,
         }
     }
 
-    /// Response to [SetSpendTx] by the coordinator, `ack` is `true` if it claims to have
-    /// succesfully stored the Spend tx.
-    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-    pub struct SetSpendResult {
-        /// Result of acknowledgement
-        pub ack: bool,
+    /// How long to wait before retrying the request, if the server said so
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.data
+            .as_ref()
+            .and_then(|data| data.retry_after)
+            .map(Duration::from_secs)
     }
 
-    /// Sent by a watchtower to the synchronisation server after an unvault
-    /// event to learn about the spend transaction.
-    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-    pub struct GetSpendTx {
-        /// Outpoint designing the deposit utxo that created the vault this
-        /// spend tx is spending.
-        pub deposit_outpoint: OutPoint,
+    /// Whether the server refused the request because it lacks resources, and the
+    /// client should back off rather than retry right away
+    pub fn is_capacity(&self) -> bool {
+        matches!(
+            self.code,
+            ErrorCode::ServerBusy | ErrorCode::QuotaExceeded | ErrorCode::StorageFull
+        )
     }
-    impl_to_request!(GetSpendTx, "get_spend_tx", GetSpendTx);
+}
 
-    /// The response to the [GetSpendTx] request.
-    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-    pub struct SpendTx {
-        /// The Bitcoin-serialized Spend transaction. The sync server isn't
-        /// creating it so there is no point to create it from_spend_tx().
-        #[serde(with = "serde_tx_hex")]
-        pub transaction: Transaction,
+impl From<&Error> for ResponseError {
+    fn from(error: &Error) -> Self {
+        Self::new(error.into(), error.to_string())
     }
+}
 
-    /// Message from a stakeholder client to sync server to share (at any time)
-    /// the signature for a revocation transaction with all participants.
-    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-    pub struct Sig {
-        /// Secp256k1 public key used to sign the transaction (hex)
-        pub pubkey: PublicKey,
-        /// Bitcoin ECDSA signature as hex
-        pub signature: Signature,
-        /// Txid of the transaction the signature applies to
-        pub id: Txid,
+impl std::fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code.code())
     }
-    impl_to_request!(Sig, "sig", CoordSig);
+}
 
-    /// Response to [SigResult] by the coordinator, `ack` is `true` if it claims to have
-    /// succesfully stored the Spend tx.
-    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-    pub struct SigResult {
-        /// Result of acknowledgement
-        pub ack: bool,
+/// A JSONRPC-like error response, sent instead of a [Response] to a request that
+/// could not be processed.
+#[allow(missing_docs)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
+pub struct ErrorResponse {
+    pub error: ResponseError,
+    pub id: u32,
+}
+
+impl std::fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "error (id {}) {}", self.id, self.error)
     }
 }
 
-/// Messages related to the communication with the Cosigning Server(s)
-pub mod cosigner {
-    use super::{Deserialize, Request, Serialize};
-    use revault_tx::transactions::SpendTransaction;
-    use std::convert::From;
+/// A JSONRPC-like notification, sent by a server without having been requested and
+/// hence without an id.
+#[allow(missing_docs)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Notification<'a> {
+    NewSig {
+        method: &'a str,
+        params: coordinator::NewSigEvent,
+    },
+    NewSpendTx {
+        method: &'a str,
+        params: coordinator::NewSpendTxEvent,
+    },
+    #[cfg(feature = "revault_tx")]
+    KeyRotation {
+        method: &'a str,
+        params: cosigner::KeyRotation,
+    },
+}
 
-    /// Message from a manager to a cosigning server who will soon attempt to
-    /// unvault and spend a vault utxo
-    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-    pub struct SignRequest {
-        /// The partially signed unvault transaction
-        pub tx: SpendTransaction,
+impl<'a> Notification<'a> {
+    /// Get the method name of this notification
+    pub fn method(&self) -> &str {
+        match self {
+            Notification::NewSig { method, .. } => method,
+            Notification::NewSpendTx { method, .. } => method,
+            #[cfg(feature = "revault_tx")]
+            Notification::KeyRotation { method, .. } => method,
+        }
     }
-    impl_to_request!(SignRequest, "sign", Sign);
 
-    /// Message returned from the cosigning server to the manager containing
-    /// the requested signature
-    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-    pub struct SignResult {
-        /// Cosigning server's signature for the unvault transaction
-        pub tx: Option<SpendTransaction>,
+    /// Get the parameters of this notification
+    pub fn params(self) -> NotificationParams {
+        match self {
+            Notification::NewSig { params, .. } => NotificationParams::NewSig(params),
+            Notification::NewSpendTx { params, .. } => NotificationParams::NewSpendTx(params),
+            #[cfg(feature = "revault_tx")]
+            Notification::KeyRotation { params, .. } => NotificationParams::KeyRotation(params),
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{Request, Response, ResponseResult};
-    use std::{collections::BTreeMap, str::FromStr};
-
-    use revault_tx::{
-        bitcoin::{
-            hash_types::Txid,
-            secp256k1::{
-                key::{PublicKey, SecretKey},
-                Secp256k1, Signature,
-            },
-            OutPoint,
-        },
-        transactions::{RevaultTransaction, SpendTransaction},
+/// A one-line summary of the notification, like for [Request]s
+impl std::fmt::Display for Notification<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ", self.method())?;
+        match self {
+            Notification::NewSig { params, .. } => params.fmt(f),
+            Notification::NewSpendTx { params, .. } => params.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            Notification::KeyRotation { params, .. } => params.fmt(f),
+        }
+    }
+}
+
+/// All params types that can possibly be sent through a Notification
+#[allow(missing_docs)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum NotificationParams {
+    NewSig(coordinator::NewSigEvent),
+    NewSpendTx(coordinator::NewSpendTxEvent),
+    #[cfg(feature = "revault_tx")]
+    KeyRotation(cosigner::KeyRotation),
+}
+
+impl std::fmt::Display for NotificationParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            NotificationParams::NewSig(params) => params.fmt(f),
+            NotificationParams::NewSpendTx(params) => params.fmt(f),
+            #[cfg(feature = "revault_tx")]
+            NotificationParams::KeyRotation(params) => params.fmt(f),
+        }
+    }
+}
+
+// Implement From(param type) for a Notification
+macro_rules! impl_to_notification {
+    ($message_struct:ty, $message_name:expr, $enum_variant:ident) => {
+        impl From<$message_struct> for Notification<'_> {
+            fn from(params: $message_struct) -> Self {
+                Self::$enum_variant {
+                    method: $message_name,
+                    params,
+                }
+            }
+        }
     };
+}
+
+/// The method names of the requests and notifications, and a registry of the params
+/// and result types expected for each of them.
+pub mod method {
+    /// Share signature(s) with a watchtower or the coordinator
+    pub const SIG: &str = "sig";
+    /// Get the signatures for a transaction from the coordinator
+    pub const GET_SIGS: &str = "get_sigs";
+    /// Get the signatures for all the revocation transactions of a vault from the
+    /// coordinator
+    pub const GET_REVOCATION_SIGS: &str = "get_revocation_sigs";
+    /// Get the number of signatures the coordinator has for a transaction, without
+    /// the signatures themselves
+    pub const HAS_SIGS: &str = "has_sigs";
+    /// Set the Spend transaction for some vaults on the coordinator
+    pub const SET_SPEND_TX: &str = "set_spend_tx";
+    /// Get the Spend transaction for a vault from the coordinator
+    pub const GET_SPEND_TX: &str = "get_spend_tx";
+    /// Ask a cosigning server to sign a Spend transaction
+    pub const SIGN: &str = "sign";
+    /// Share a MuSig2 public nonce with the coordinator
+    pub const MUSIG_NONCE: &str = "musig_nonce";
+    /// Share a MuSig2 partial signature with the coordinator
+    pub const MUSIG_PARTIAL_SIG: &str = "musig_partial_sig";
+    /// Get the state of a MuSig2 signing session from the coordinator
+    pub const GET_MUSIG_SESSION: &str = "get_musig_session";
+    /// Subscribe to coordinator notifications
+    pub const SUBSCRIBE: &str = "subscribe";
+    /// Cancel a subscription to coordinator notifications
+    pub const UNSUBSCRIBE: &str = "unsubscribe";
+    /// Push signatures and Spend transactions to a replica coordinator
+    pub const REPLICATE: &str = "replicate";
+    /// Get the signatures and Spend transactions stored by a coordinator, for a
+    /// replica to sync with it
+    pub const GET_ENTRIES: &str = "get_entries";
+    /// Reconcile the vaults guarded by two watchtowers of the same stakeholder
+    pub const SYNC_VAULTS: &str = "sync_vaults";
+    /// Get the spending policy a watchtower enforces
+    pub const GET_SPEND_POLICY: &str = "get_spend_policy";
+    /// Ask a watchtower to approve a Spend before unvaulting
+    pub const APPROVE_SPEND: &str = "approve_spend";
+    /// Get back the revocation signatures a watchtower holds for a vault
+    pub const RECOVER_SIGS: &str = "recover_sigs";
+    /// Acknowledge the new signing key announced by a cosigning server
+    pub const ACK_KEY_ROTATION: &str = "ack_key_rotation";
+    /// Get the signing key and policy of a cosigning server
+    pub const GET_PUBKEY: &str = "get_pubkey";
+    /// Share a signature encrypted to the other stakeholders through the coordinator
+    pub const SEALED_SIG: &str = "sealed_sig";
+    /// Get the encrypted signatures for a transaction from the coordinator
+    pub const GET_SEALED_SIGS: &str = "get_sealed_sigs";
+    /// Store an opaque blob on a coordinator in blind storage mode
+    pub const STORE_BLOB: &str = "store_blob";
+    /// Get the opaque blobs stored under an identifier from the coordinator
+    pub const GET_BLOBS: &str = "get_blobs";
+    /// Start the upload in chunks of a Spend transaction to the coordinator
+    pub const SET_SPEND_TX_BEGIN: &str = "set_spend_tx_begin";
+    /// Upload a chunk of a Spend transaction to the coordinator
+    pub const SET_SPEND_TX_CHUNK: &str = "set_spend_tx_chunk";
+    /// Complete the upload in chunks of a Spend transaction to the coordinator
+    pub const SET_SPEND_TX_COMMIT: &str = "set_spend_tx_commit";
+    /// Get a chunk of the Spend transaction for a vault from the coordinator
+    pub const GET_SPEND_TX_CHUNK: &str = "get_spend_tx_chunk";
+    /// Get the current time of the coordinator
+    pub const GET_TIME: &str = "get_time";
+    /// Notification of a new signature stored by the coordinator
+    pub const NEW_SIG: &str = "new_sig";
+    /// Notification of a new Spend transaction stored by the coordinator
+    pub const NEW_SPEND_TX: &str = "new_spend_tx";
+    /// Notification of a new signing key by a cosigning server
+    pub const KEY_ROTATION: &str = "key_rotation";
+
+    /// A participant to the Revault network
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Peer {
+        /// A stakeholder's or a manager's wallet
+        Wallet,
+        /// A stakeholder's watchtower
+        Watchtower,
+        /// The coordinator, or synchronisation server
+        Coordinator,
+        /// A manager's cosigning server
+        Cosigner,
+    }
+
+    /// The specification of a method as handled by a given peer. The types are the
+    /// paths of the message structs relative to the [message](crate::message) module.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MethodSpec {
+        /// The method name
+        pub method: &'static str,
+        /// The peer receiving this method
+        pub recipient: Peer,
+        /// The possible types of the params
+        pub params: &'static [&'static str],
+        /// The possible types of the result. Empty for notifications.
+        pub results: &'static [&'static str],
+    }
+
+    /// The specification of all the requests
+    pub const REQUESTS: &[MethodSpec] = &[
+        MethodSpec {
+            method: SIG,
+            recipient: Peer::Watchtower,
+            params: &["watchtower::Sig", "watchtower::SchnorrSig"],
+            results: &["watchtower::SigResult"],
+        },
+        MethodSpec {
+            method: SYNC_VAULTS,
+            recipient: Peer::Watchtower,
+            params: &["watchtower::SyncVaults"],
+            results: &["watchtower::SyncVaultsResult"],
+        },
+        MethodSpec {
+            method: GET_SPEND_POLICY,
+            recipient: Peer::Watchtower,
+            params: &["watchtower::GetSpendPolicy"],
+            results: &["watchtower::SpendPolicy"],
+        },
+        MethodSpec {
+            method: APPROVE_SPEND,
+            recipient: Peer::Watchtower,
+            params: &["watchtower::ApproveSpend"],
+            results: &["watchtower::SpendApproval"],
+        },
+        MethodSpec {
+            method: RECOVER_SIGS,
+            recipient: Peer::Watchtower,
+            params: &["watchtower::RecoverSigs"],
+            results: &["watchtower::RecoveredSigs"],
+        },
+        MethodSpec {
+            method: SIG,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::Sig", "coordinator::SchnorrSig"],
+            results: &["coordinator::SigResult"],
+        },
+        MethodSpec {
+            method: GET_SIGS,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::GetSigs"],
+            results: &[
+                "coordinator::Sigs",
+                "coordinator::SchnorrSigs",
+                "coordinator::NotModified",
+            ],
+        },
+        MethodSpec {
+            method: GET_REVOCATION_SIGS,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::GetRevocationSigs"],
+            results: &["coordinator::RevocationSigs"],
+        },
+        MethodSpec {
+            method: HAS_SIGS,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::HasSigs"],
+            results: &["coordinator::SigsCount"],
+        },
+        MethodSpec {
+            method: SET_SPEND_TX,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::SetSpendTx"],
+            results: &["coordinator::SetSpendResult"],
+        },
+        MethodSpec {
+            method: GET_SPEND_TX,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::GetSpendTx"],
+            results: &["coordinator::SpendTx"],
+        },
+        MethodSpec {
+            method: MUSIG_NONCE,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::MusigNonce"],
+            results: &["coordinator::SigResult"],
+        },
+        MethodSpec {
+            method: MUSIG_PARTIAL_SIG,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::MusigPartialSig"],
+            results: &["coordinator::SigResult"],
+        },
+        MethodSpec {
+            method: GET_MUSIG_SESSION,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::GetMusigSession"],
+            results: &["coordinator::MusigSession"],
+        },
+        MethodSpec {
+            method: SEALED_SIG,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::SealedSig"],
+            results: &["coordinator::SigResult"],
+        },
+        MethodSpec {
+            method: GET_SEALED_SIGS,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::GetSealedSigs"],
+            results: &["coordinator::SealedSigs"],
+        },
+        MethodSpec {
+            method: STORE_BLOB,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::StoreBlob"],
+            results: &["coordinator::SigResult"],
+        },
+        MethodSpec {
+            method: GET_BLOBS,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::GetBlobs"],
+            results: &["coordinator::Blobs"],
+        },
+        MethodSpec {
+            method: SET_SPEND_TX_BEGIN,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::SetSpendTxBegin"],
+            results: &["coordinator::UploadStarted"],
+        },
+        MethodSpec {
+            method: SET_SPEND_TX_CHUNK,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::SetSpendTxChunk"],
+            results: &["coordinator::SigResult"],
+        },
+        MethodSpec {
+            method: SET_SPEND_TX_COMMIT,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::SetSpendTxCommit"],
+            results: &["coordinator::SetSpendResult"],
+        },
+        MethodSpec {
+            method: GET_SPEND_TX_CHUNK,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::GetSpendTxChunk"],
+            results: &["coordinator::SpendTxChunk"],
+        },
+        MethodSpec {
+            method: GET_TIME,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::GetTime"],
+            results: &["coordinator::ServerTime"],
+        },
+        MethodSpec {
+            method: SUBSCRIBE,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::Subscribe"],
+            results: &["coordinator::SubscribeResult"],
+        },
+        MethodSpec {
+            method: UNSUBSCRIBE,
+            recipient: Peer::Coordinator,
+            params: &["coordinator::Unsubscribe"],
+            results: &["coordinator::UnsubscribeResult"],
+        },
+        MethodSpec {
+            method: REPLICATE,
+            recipient: Peer::Coordinator,
+            params: &["replication::Replicate"],
+            results: &["replication::ReplicateResult"],
+        },
+        MethodSpec {
+            method: GET_ENTRIES,
+            recipient: Peer::Coordinator,
+            params: &["replication::GetEntries"],
+            results: &["replication::Entries"],
+        },
+        MethodSpec {
+            method: SIGN,
+            recipient: Peer::Cosigner,
+            params: &["cosigner::SignRequest", "cosigner::BatchSignRequest"],
+            results: &["cosigner::SignResult", "cosigner::BatchSignResult"],
+        },
+        MethodSpec {
+            method: ACK_KEY_ROTATION,
+            recipient: Peer::Cosigner,
+            params: &["cosigner::AckKeyRotation"],
+            results: &["cosigner::AckKeyRotationResult"],
+        },
+        MethodSpec {
+            method: GET_PUBKEY,
+            recipient: Peer::Cosigner,
+            params: &["cosigner::GetPubkey"],
+            results: &["cosigner::GetPubkeyResult"],
+        },
+    ];
+
+    /// The specification of all the notifications
+    pub const NOTIFICATIONS: &[MethodSpec] = &[
+        MethodSpec {
+            method: NEW_SIG,
+            recipient: Peer::Wallet,
+            params: &["coordinator::NewSigEvent"],
+            results: &[],
+        },
+        MethodSpec {
+            method: NEW_SPEND_TX,
+            recipient: Peer::Wallet,
+            params: &["coordinator::NewSpendTxEvent"],
+            results: &[],
+        },
+        MethodSpec {
+            method: KEY_ROTATION,
+            recipient: Peer::Wallet,
+            params: &["cosigner::KeyRotation"],
+            results: &[],
+        },
+    ];
+
+    /// Get the specification of the request with this method name for this recipient
+    pub fn request_spec(method: &str, recipient: Peer) -> Option<&'static MethodSpec> {
+        REQUESTS
+            .iter()
+            .find(|spec| spec.method == method && spec.recipient == recipient)
+    }
+
+    /// Get the specification of the notification with this method name
+    pub fn notification_spec(method: &str) -> Option<&'static MethodSpec> {
+        NOTIFICATIONS.iter().find(|spec| spec.method == method)
+    }
+}
+
+/// The encoding of the raw transactions contained in the [SetSpendTx](coordinator::SetSpendTx),
+/// [SpendTx](coordinator::SpendTx) and [NewSpendTxEvent](coordinator::NewSpendTxEvent)
+/// messages. Note that the PSBTs (for instance in a [SignRequest](cosigner::SignRequest))
+/// are always encoded as base64.
+///
+/// Both encodings are always accepted at deserialization, hex being the one used by
+/// default for serialization. Base64 messages are about 33% smaller than hex ones but
+/// must only be sent to peers known to support them: the transport uses it with the
+/// peers which negotiated [Base64Tx](crate::capabilities::Capability::Base64Tx).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TxEncoding {
+    /// Hexadecimal, as specified
+    #[default]
+    Hex,
+    /// Standard base64, with padding
+    Base64,
+}
+
+thread_local! {
+    static TX_ENCODING: Cell<TxEncoding> = Cell::new(TxEncoding::default());
+}
+
+// Restores the previous encoding when going out of scope, even on panic
+struct TxEncodingGuard(TxEncoding);
+
+impl Drop for TxEncodingGuard {
+    fn drop(&mut self) {
+        TX_ENCODING.with(|e| e.set(self.0));
+    }
+}
+
+impl TxEncoding {
+    /// Run `f` with this encoding used for all the raw transactions serialized on the
+    /// current thread. Hex is used outside of a scope.
+    pub fn scope<T, F: FnOnce() -> T>(self, f: F) -> T {
+        let prev = TX_ENCODING.with(|e| e.replace(self));
+        let _guard = TxEncodingGuard(prev);
+        f()
+    }
+
+    /// Get the encoding in use on the current thread
+    pub fn current() -> Self {
+        TX_ENCODING.with(|e| e.get())
+    }
+}
+
+/// A raw transaction, encoded on the wire as those of the messages of this crate: as
+/// hex or base64 depending on the current [TxEncoding], and sanity checked against the
+/// current [ValidationConfig](crate::validation::ValidationConfig) (inputs, outputs,
+/// weight) when deserialized.
+///
+/// For the transactions of messages defined outside of this crate, so that they are
+/// encoded the same way.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct WireTransaction(#[serde(with = "coordinator::serde_tx")] pub Transaction);
+
+impl From<Transaction> for WireTransaction {
+    fn from(tx: Transaction) -> Self {
+        Self(tx)
+    }
+}
+
+impl From<WireTransaction> for Transaction {
+    fn from(tx: WireTransaction) -> Self {
+        tx.0
+    }
+}
+
+// A string borrowed from the input whenever possible, so that parsing the keys,
+// signatures and nonces of a message doesn't allocate. It is only owned when read
+// from a stream or when it contains escape sequences.
+struct CowStr<'de>(std::borrow::Cow<'de, str>);
+
+impl<'de> Deserialize<'de> for CowStr<'de> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct CowStrVisitor;
+
+        impl<'de> de::Visitor<'de> for CowStrVisitor {
+            type Value = CowStr<'de>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a string")
+            }
+
+            fn visit_borrowed_str<E: de::Error>(self, s: &'de str) -> Result<Self::Value, E> {
+                Ok(CowStr(s.into()))
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Self::Value, E> {
+                Ok(CowStr(s.to_string().into()))
+            }
+
+            fn visit_string<E: de::Error>(self, s: String) -> Result<Self::Value, E> {
+                Ok(CowStr(s.into()))
+            }
+        }
+
+        deserializer.deserialize_str(CowStrVisitor)
+    }
+}
+
+// Deserialize public keys, refusing uncompressed ones
+mod serde_pubkey {
+    use super::CowStr;
+    use crate::{error::MessageError, validation::parse_pubkey};
+
+    use bitcoin::secp256k1::key::PublicKey;
+    use serde::{de, Deserialize, Deserializer};
+    use std::{collections::BTreeMap, convert::Infallible, fmt, marker::PhantomData};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<PublicKey, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = CowStr::deserialize(deserializer)?;
+        parse_pubkey(&s.0).map_err(de::Error::custom)
+    }
+
+    // For maps keyed by public keys
+    pub fn deserialize_map<'de, D, V>(deserializer: D) -> Result<BTreeMap<PublicKey, V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: Deserialize<'de>,
+    {
+        deserialize_map_with(deserializer, usize::MAX, Ok::<V, Infallible>)
+    }
+
+    // For maps keyed by public keys of at most `max_len` entries, whose values are
+    // converted with `f`. The keys are parsed as we go rather than first collected as
+    // strings.
+    pub fn deserialize_map_with<'de, D, V, T, E, F>(
+        deserializer: D,
+        max_len: usize,
+        f: F,
+    ) -> Result<BTreeMap<PublicKey, T>, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: Deserialize<'de>,
+        E: fmt::Display,
+        F: Fn(V) -> Result<T, E>,
+    {
+        struct MapVisitor<V, F>(usize, F, PhantomData<V>);
+
+        impl<'de, V, T, E, F> de::Visitor<'de> for MapVisitor<V, F>
+        where
+            V: Deserialize<'de>,
+            E: fmt::Display,
+            F: Fn(V) -> Result<T, E>,
+        {
+            type Value = BTreeMap<PublicKey, T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map keyed by public keys")
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut keyed = BTreeMap::new();
+                while let Some((key, value)) = map.next_entry::<CowStr, V>()? {
+                    if keyed.len() == self.0 {
+                        return Err(de::Error::custom(MessageError::TooManySignatures {
+                            count: self.0 + 1,
+                            max: self.0,
+                        }));
+                    }
+                    keyed.insert(
+                        parse_pubkey(&key.0).map_err(de::Error::custom)?,
+                        (self.1)(value).map_err(de::Error::custom)?,
+                    );
+                }
+                Ok(keyed)
+            }
+        }
+
+        deserializer.deserialize_map(MapVisitor(max_len, f, PhantomData))
+    }
+}
+
+// Deserialize ECDSA signatures according to the current validation configuration
+mod serde_sig {
+    use super::CowStr;
+    use crate::validation::{parse_signature, ValidationConfig};
+
+    use bitcoin::secp256k1::{key::PublicKey, Signature};
+    use serde::{de, Deserialize, Deserializer};
+    use std::collections::BTreeMap;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Signature, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = CowStr::deserialize(deserializer)?;
+        parse_signature(&s.0, &ValidationConfig::current()).map_err(de::Error::custom)
+    }
+
+    pub fn deserialize_map<'de, D>(
+        deserializer: D,
+    ) -> Result<BTreeMap<PublicKey, Signature>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let config = ValidationConfig::current();
+        super::serde_pubkey::deserialize_map_with(
+            deserializer,
+            config.max_signatures,
+            |sig: CowStr| parse_signature(&sig.0, &config),
+        )
+    }
+}
+
+/// A set of ECDSA signatures for a transaction, at most one per public key.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct SigSet(BTreeMap<secp256k1::PublicKey, secp256k1::Signature>);
+
+impl std::hash::Hash for SigSet {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.sig_hash(state)
+    }
+}
+
+impl SigSet {
+    /// An empty set of signatures
+    pub fn new() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    /// Add a signature to the set. Returns `true` if it wasn't already present, and an
+    /// error if a different signature is already present for this public key.
+    pub fn insert(
+        &mut self,
+        pubkey: secp256k1::PublicKey,
+        signature: secp256k1::Signature,
+    ) -> Result<bool, MessageError> {
+        match self.0.get(&pubkey) {
+            Some(sig) if sig == &signature => Ok(false),
+            Some(_) => Err(MessageError::ConflictingSignature(pubkey)),
+            None => {
+                self.0.insert(pubkey, signature);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Add all the signatures from `other` to this set, returning the number of new
+    /// signatures. Nothing is merged if one of them conflicts with a signature of this
+    /// set.
+    pub fn merge(&mut self, other: &SigSet) -> Result<usize, MessageError> {
+        if let Some(pubkey) = self.conflicts(other).next() {
+            return Err(MessageError::ConflictingSignature(*pubkey));
+        }
+
+        let prev_len = self.0.len();
+        self.0
+            .extend(other.0.iter().map(|(pubkey, sig)| (*pubkey, *sig)));
+        Ok(self.0.len() - prev_len)
+    }
+
+    /// Get the public keys for which `other` contains a different signature than this set
+    pub fn conflicts<'a>(
+        &'a self,
+        other: &'a SigSet,
+    ) -> impl Iterator<Item = &'a secp256k1::PublicKey> + 'a {
+        other
+            .0
+            .iter()
+            .filter(move |(pubkey, sig)| matches!(self.0.get(pubkey), Some(s) if s != *sig))
+            .map(|(pubkey, _)| pubkey)
+    }
+
+    /// Get the signature for this public key, if any
+    pub fn get(&self, pubkey: &secp256k1::PublicKey) -> Option<&secp256k1::Signature> {
+        self.0.get(pubkey)
+    }
+
+    /// Whether this set contains a signature for this public key
+    pub fn contains_key(&self, pubkey: &secp256k1::PublicKey) -> bool {
+        self.0.contains_key(pubkey)
+    }
+
+    /// The number of signatures in this set
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether this set has no signature
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterate over the (public key, signature) pairs of this set
+    pub fn iter(&self) -> impl Iterator<Item = (&secp256k1::PublicKey, &secp256k1::Signature)> {
+        self.0.iter()
+    }
+
+    /// The number of signatures in this set made by one of the `expected` public keys
+    pub fn count_from(&self, expected: &[secp256k1::PublicKey]) -> usize {
+        expected
+            .iter()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter(|pubkey| self.0.contains_key(pubkey))
+            .count()
+    }
+
+    /// Whether this set contains at least `threshold` signatures from the `expected`
+    /// public keys
+    pub fn has_threshold(&self, expected: &[secp256k1::PublicKey], threshold: usize) -> bool {
+        self.count_from(expected) >= threshold
+    }
+
+    /// Check each signature of this set against the internal input of this Revault
+    /// transaction. See the [verify](crate::verify) module.
+    #[cfg(feature = "verify")]
+    pub fn verify<C: secp256k1::Verification, T: revault_tx::transactions::RevaultTransaction>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+        tx: &T,
+        sighash_type: bitcoin::SigHashType,
+    ) -> Result<(), Error> {
+        crate::verify::verify_sigset(secp, tx, sighash_type, self)
+    }
+
+    /// Get the underlying mapping of public keys to signatures
+    pub fn into_inner(self) -> BTreeMap<secp256k1::PublicKey, secp256k1::Signature> {
+        self.0
+    }
+}
+
+impl From<BTreeMap<secp256k1::PublicKey, secp256k1::Signature>> for SigSet {
+    fn from(map: BTreeMap<secp256k1::PublicKey, secp256k1::Signature>) -> Self {
+        Self(map)
+    }
+}
+
+impl std::iter::FromIterator<(secp256k1::PublicKey, secp256k1::Signature)> for SigSet {
+    fn from_iter<I: IntoIterator<Item = (secp256k1::PublicKey, secp256k1::Signature)>>(
+        iter: I,
+    ) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl Serialize for SigSet {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SigSet {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        serde_sig::deserialize_map(deserializer).map(Self)
+    }
+}
+
+/// The tag prefixed to the JSON serialization of a [SignedRequest] before hashing it, for
+/// its signature not to be valid for anything but a request of this protocol
+pub const SIGNED_REQUEST_TAG: &[u8] = b"revault_net signed request";
+
+/// A [Request] additionally signed by the sender's (stakeholder or manager) bitcoin key.
+///
+/// The signature commits to the JSON serialization of the request, such that a server may
+/// keep the envelope as a proof of who sent which signature or Spend transaction.
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize, Serialize)]
+pub struct SignedRequest<'a> {
+    /// The signed request
+    #[serde(borrow)]
+    pub request: Request<'a>,
+    /// The bitcoin public key of the sender
+    pub pubkey: secp256k1::PublicKey,
+    /// ECDSA signature of the request by `pubkey`
+    pub signature: secp256k1::Signature,
+}
+
+impl_hash_with_sigs!(SignedRequest<'_>, [request, pubkey], [signature]);
+
+impl<'a> SignedRequest<'a> {
+    /// Get the message committed to by the signature of this request, that is the double
+    /// SHA256 of its JSON serialization prefixed with [SIGNED_REQUEST_TAG].
+    ///
+    /// The request is serialized with its transactions as hex whatever the current
+    /// [TxEncoding], for the signature not to depend on how it was sent.
+    pub fn signature_msg(request: &Request<'a>) -> Result<secp256k1::Message, Error> {
+        let mut engine = sha256d::Hash::engine();
+        engine.input(SIGNED_REQUEST_TAG);
+        TxEncoding::Hex.scope(|| serde_json::to_writer(&mut engine, request))?;
+        let hash = sha256d::Hash::from_engine(engine);
+        Ok(secp256k1::Message::from_slice(&hash[..]).expect("32 bytes hash"))
+    }
+
+    /// Sign this request using the sender's bitcoin private key
+    pub fn sign<C: secp256k1::Signing>(
+        secp: &secp256k1::Secp256k1<C>,
+        request: Request<'a>,
+        privkey: &secp256k1::SecretKey,
+    ) -> Result<Self, Error> {
+        let msg = Self::signature_msg(&request)?;
+        let signature = secp.sign(&msg, privkey);
+        let pubkey = secp256k1::PublicKey::from_secret_key(secp, privkey);
+
+        Ok(Self {
+            request,
+            pubkey,
+            signature,
+        })
+    }
+
+    /// Check the signature of this request against the public key it contains
+    pub fn verify<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(), Error> {
+        let msg = Self::signature_msg(&self.request)?;
+        secp.verify(&msg, &self.signature, &self.pubkey)
+            .map_err(Error::Signature)
+    }
+
+    /// Check the signature of this request and that it was made by the given public key
+    pub fn verify_from<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+        expected_pubkey: &secp256k1::PublicKey,
+    ) -> Result<(), Error> {
+        if &self.pubkey != expected_pubkey {
+            return Err(Error::Signature(secp256k1::Error::IncorrectSignature));
+        }
+        self.verify(secp)
+    }
+}
+
+// The fields of the messages holding signatures, keys or transactions
+pub(crate) const SENSITIVE_FIELDS: &[&str] = &[
+    "signature",
+    "signatures",
+    "pubkey",
+    "transaction",
+    "tx",
+    "nonce",
+    "nonces",
+    "partial_sig",
+    "partial_sigs",
+];
+
+// Apply `replace` to the sensitive fields of this JSON message
+pub(crate) fn redact_json(value: &mut serde_json::Value, replace: &dyn Fn(&mut serde_json::Value)) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if SENSITIVE_FIELDS.contains(&key.as_str()) {
+                    replace(value);
+                } else {
+                    redact_json(value, replace);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| redact_json(value, replace)),
+        _ => {}
+    }
+}
+
+// The length of the fingerprints of the sensitive fields
+const FINGERPRINT_LEN: usize = 8;
+
+fn fingerprint_str(s: &str) -> String {
+    if s.len() <= FINGERPRINT_LEN {
+        return s.to_string();
+    }
+    let prefix: String = s.chars().take(FINGERPRINT_LEN).collect();
+    format!("{}..({} chars)", prefix, s.len())
+}
+
+// Truncate the strings in this value, including the keys of the maps (SigSets are maps
+// of public keys to signatures)
+fn fingerprint(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(s) => *s = fingerprint_str(s),
+        serde_json::Value::Array(values) => values.iter_mut().for_each(fingerprint),
+        serde_json::Value::Object(map) => {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    fingerprint(&mut value);
+                    (fingerprint_str(&key), value)
+                })
+                .collect();
+        }
+        _ => {}
+    }
+}
+
+/// A message that may expire: once expired, it is stale and must not be acted upon.
+/// Servers can refuse the expired requests with
+/// [RejectExpired](crate::server::expiry::RejectExpired).
+pub trait Expiring {
+    /// The time after which the message is expired, in seconds since the UNIX epoch.
+    /// `None` if it never expires.
+    fn expires_at(&self) -> Option<u64>;
+
+    /// Whether the message is expired at this time, in seconds since the UNIX epoch
+    fn is_expired(&self, now: u64) -> bool {
+        matches!(self.expires_at(), Some(expires_at) if now > expires_at)
+    }
+
+    /// Whether the message is expired under this clock policy, that is at the time of
+    /// its source even accounting for the skew it tolerates
+    fn is_expired_under(&self, clock: &ClockPolicy) -> bool {
+        matches!(self.expires_at(), Some(expires_at) if clock.is_expired(expires_at))
+    }
+}
+
+impl Expiring for RequestParams {
+    fn expires_at(&self) -> Option<u64> {
+        match self {
+            RequestParams::SetSpendTx(params) => params.expires_at(),
+            #[cfg(feature = "revault_tx")]
+            RequestParams::Sign(params) => params.expires_at(),
+            #[cfg(feature = "revault_tx")]
+            RequestParams::BatchSign(params) => params.expires_at(),
+            _ => None,
+        }
+    }
+}
+
+/// Debug or Display a message without dumping its signatures, public keys and
+/// transactions: they are truncated to short fingerprints.
+/// ```
+/// use revault_net::message::{coordinator::GetSigs, RedactedDebug};
+///
+/// let msg = GetSigs { id: Default::default(), if_none_match: None };
+/// println!("Sending '{}'", msg.redacted());
+/// ```
+pub trait RedactedDebug {
+    /// Get a wrapper formatting this message with the sensitive fields truncated
+    fn redacted(&self) -> Redacted<'_, Self>;
+}
+
+impl<T: Serialize> RedactedDebug for T {
+    fn redacted(&self) -> Redacted<'_, Self> {
+        Redacted(self)
+    }
+}
+
+/// A message formatted with its sensitive fields truncated. See [RedactedDebug].
+pub struct Redacted<'a, T: ?Sized>(&'a T);
+
+impl<T: Serialize + ?Sized> std::fmt::Display for Redacted<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut value = serde_json::to_value(self.0).map_err(|_| std::fmt::Error)?;
+        redact_json(&mut value, &fingerprint);
+        write!(f, "{}", value)
+    }
+}
+
+impl<T: Serialize + ?Sized> std::fmt::Debug for Redacted<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+/// Messages related to the communication with the Watchtower(s)
+pub mod watchtower {
+    use super::{method, Deserialize, Request, Serialize, SigSet};
+    use crate::{
+        error::MessageError,
+        validation::{check_signature, ValidationConfig},
+    };
+    use bitcoin::{
+        hash_types::Txid,
+        secp256k1::{key::PublicKey, schnorrsig, Signature},
+        OutPoint, Script, Transaction,
+    };
+    #[cfg(feature = "revault_tx")]
+    use revault_tx::transactions::RevaultTransaction;
+    use std::collections::BTreeMap;
+    use std::convert::From;
+    use std::fmt;
+
+    /// Message from a stakeholder to share all signatures for a revocation
+    /// transaction with its watchtower.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct Sig {
+        /// A sufficient set of public keys and associated ALL|ANYONECANPAY
+        /// bitcoin ECDSA signatures to validate the revocation transaction
+        pub signatures: SigSet,
+        /// Revocation transaction id
+        pub txid: Txid,
+        /// Deposit outpoint of this vault
+        pub deposit_outpoint: OutPoint,
+    }
+    impl_to_request!(Sig, method::SIG, WtSig);
+
+    /// A builder for [Sig] messages, checking each signature as it is added.
+    #[derive(Debug, Default, Clone)]
+    pub struct SigBuilder {
+        signatures: SigSet,
+        txid: Option<Txid>,
+        deposit_outpoint: Option<OutPoint>,
+    }
+
+    impl SigBuilder {
+        /// A builder with no field set
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Add the signature of a stakeholder for the revocation transaction
+        pub fn signature(
+            mut self,
+            pubkey: PublicKey,
+            signature: Signature,
+        ) -> Result<Self, MessageError> {
+            check_signature(&signature, &ValidationConfig::current())?;
+            self.signatures.insert(pubkey, signature)?;
+            Ok(self)
+        }
+
+        /// Set the id of the revocation transaction
+        pub fn txid(mut self, txid: Txid) -> Self {
+            self.txid = Some(txid);
+            self
+        }
+
+        /// Set the id of the revocation transaction from the transaction itself
+        #[cfg(feature = "revault_tx")]
+        pub fn tx<T: RevaultTransaction>(self, tx: &T) -> Self {
+            self.txid(tx.txid())
+        }
+
+        /// Set the deposit outpoint of the vault
+        pub fn deposit_outpoint(mut self, deposit_outpoint: OutPoint) -> Self {
+            self.deposit_outpoint = Some(deposit_outpoint);
+            self
+        }
+
+        /// Create the message, which must have at least one signature
+        pub fn build(self) -> Result<Sig, MessageError> {
+            if self.signatures.is_empty() {
+                return Err(MessageError::MissingField("signatures"));
+            }
+
+            Ok(Sig {
+                signatures: self.signatures,
+                txid: self.txid.ok_or(MessageError::MissingField("txid"))?,
+                deposit_outpoint: self
+                    .deposit_outpoint
+                    .ok_or(MessageError::MissingField("deposit_outpoint"))?,
+            })
+        }
+    }
+
+    /// The Taproot counterpart of [Sig], sharing BIP340 Schnorr signatures for
+    /// x-only public keys.
+    #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+    pub struct SchnorrSig {
+        /// A sufficient set of x-only public keys and associated
+        /// ALL|ANYONECANPAY Schnorr signatures to validate the revocation transaction
+        pub signatures: BTreeMap<schnorrsig::PublicKey, schnorrsig::Signature>,
+        /// Revocation transaction id
+        pub txid: Txid,
+        /// Deposit outpoint of this vault
+        pub deposit_outpoint: OutPoint,
+    }
+    impl_to_request!(SchnorrSig, method::SIG, WtSchnorrSig);
+    impl_hash_with_sigs!(SchnorrSig, [txid, deposit_outpoint], [signatures]);
+
+    /// Message from the watchtower to stakeholder to acknowledge that it has
+    /// sufficient signatures and fees to begin guarding the vault with the
+    /// revocation transaction
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SigResult {
+        /// Result of acknowledgement
+        pub ack: bool,
+        // FIXME: we don't need it anymore once we have ids in messages
+        /// Revocation transaction id
+        pub txid: Txid,
+    }
+
+    /// Sent by a watchtower to another watchtower of the same stakeholder, for
+    /// redundant watchtowers to reconcile their state after one of them was down.
+    /// Each shares the signatures of the other's vaults it is missing.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SyncVaults {
+        /// The revocation signatures the sender holds, for each transaction of each
+        /// vault it guards
+        pub vaults: Vec<Sig>,
+    }
+    impl_to_request!(SyncVaults, method::SYNC_VAULTS, WtSyncVaults);
+
+    /// Response to [SyncVaults]: the revocation signatures the responder holds and
+    /// the sender did not, including the ones for vaults it does not guard at all.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SyncVaultsResult {
+        /// The signatures missing from the sender, per revocation transaction
+        pub missing: Vec<Sig>,
+    }
+
+    /// Sent by a manager to a watchtower to learn the spending policy it enforces,
+    /// to check a Spend against it before unvaulting rather than having the
+    /// watchtower cancel it.
+    #[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct GetSpendPolicy {}
+    impl_to_request!(GetSpendPolicy, method::GET_SPEND_POLICY, WtGetSpendPolicy);
+
+    /// Response to [GetSpendPolicy]: the conditions under which the watchtower lets
+    /// an Unvault go through. A Spend breaking any of them gets canceled.
+    #[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SpendPolicy {
+        /// The maximum amount, in satoshis, the Spend transactions may pay out (change
+        /// excepted) over any 24 hours. `None` if not limited.
+        pub max_daily_amount: Option<u64>,
+        /// The scripts the Spend transactions may pay to besides change, empty if any
+        /// destination is allowed
+        pub allowed_destinations: Vec<Script>,
+        /// The relative timelock, in blocks, the Unvault outputs must have. `None` if
+        /// not checked.
+        pub unvault_csv: Option<u32>,
+    }
+
+    /// Sent by a manager to each watchtower before unvaulting, with the Spend
+    /// transaction it intends to broadcast. A watchtower approving it commits to not
+    /// canceling the Unvaults for this Spend.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct ApproveSpend {
+        /// The vaults spent, at most [max_deposit_outpoints](crate::validation::ValidationConfig::max_deposit_outpoints)
+        #[serde(deserialize_with = "super::coordinator::serde_deposit_outpoints::deserialize")]
+        pub deposit_outpoints: Vec<OutPoint>,
+        /// The Spend transaction
+        #[serde(with = "super::coordinator::serde_tx")]
+        pub spend_tx: Transaction,
+    }
+    impl_to_request!(ApproveSpend, method::APPROVE_SPEND, WtApproveSpend);
+
+    /// Sent by a stakeholder to its watchtower to get back the revocation signatures
+    /// it holds for a vault, for instance to rebuild its presigned transactions after
+    /// restoring its wallet from seed.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct RecoverSigs {
+        /// Deposit outpoint of the vault
+        pub deposit_outpoint: OutPoint,
+    }
+    impl_to_request!(RecoverSigs, method::RECOVER_SIGS, WtRecoverSigs);
+
+    /// Response to [RecoverSigs]: the signatures the watchtower holds for the vault,
+    /// per revocation transaction. Empty if it doesn't guard it.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct RecoveredSigs {
+        /// The signatures for each revocation transaction of the vault
+        pub sigs: Vec<Sig>,
+    }
+
+    /// Response to [ApproveSpend] by a watchtower
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SpendApproval {
+        /// Whether the watchtower approves the Spend
+        pub approved: bool,
+        /// The id of the Spend transaction
+        pub txid: Txid,
+        /// Why it was rejected, if it was and the watchtower tells
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub reason: Option<String>,
+    }
+
+    impl SpendApproval {
+        /// Approve this Spend transaction
+        pub fn approve(txid: Txid) -> Self {
+            Self {
+                approved: true,
+                txid,
+                reason: None,
+            }
+        }
+
+        /// Reject this Spend transaction, for this reason
+        pub fn reject(txid: Txid, reason: impl Into<String>) -> Self {
+            Self {
+                approved: false,
+                txid,
+                reason: Some(reason.into()),
+            }
+        }
+    }
+
+    impl SpendPolicy {
+        /// Whether the policy allows paying out this amount to this script, given the
+        /// amount already paid out in the past 24 hours
+        pub fn allows(&self, destination: &Script, amount: u64, spent_today: u64) -> bool {
+            let within_limit = self
+                .max_daily_amount
+                .map(|max| spent_today.saturating_add(amount) <= max)
+                .unwrap_or(true);
+            let allowed_destination = self.allowed_destinations.is_empty()
+                || self.allowed_destinations.contains(destination);
+
+            within_limit && allowed_destination
+        }
+    }
+
+    impl fmt::Display for Sig {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "txid={} deposit_outpoint={} signatures={}",
+                self.txid,
+                self.deposit_outpoint,
+                self.signatures.len()
+            )
+        }
+    }
+
+    impl fmt::Display for SchnorrSig {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "txid={} deposit_outpoint={} signatures={}",
+                self.txid,
+                self.deposit_outpoint,
+                self.signatures.len()
+            )
+        }
+    }
+
+    impl fmt::Display for SigResult {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "txid={} ack={}", self.txid, self.ack)
+        }
+    }
+
+    impl fmt::Display for SyncVaults {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "vaults={}", self.vaults.len())
+        }
+    }
+
+    impl fmt::Display for SyncVaultsResult {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "missing={}", self.missing.len())
+        }
+    }
+
+    impl fmt::Display for RecoverSigs {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "deposit_outpoint={}", self.deposit_outpoint)
+        }
+    }
+
+    impl fmt::Display for RecoveredSigs {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "sigs={}", self.sigs.len())
+        }
+    }
+
+    impl fmt::Display for ApproveSpend {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "txid={} vaults={}",
+                self.spend_tx.txid(),
+                self.deposit_outpoints.len()
+            )
+        }
+    }
+
+    impl fmt::Display for SpendApproval {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "txid={} approved={}", self.txid, self.approved)?;
+            if let Some(reason) = &self.reason {
+                write!(f, " reason='{}'", reason)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl fmt::Display for GetSpendPolicy {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "spend policy")
+        }
+    }
+
+    impl fmt::Display for SpendPolicy {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.max_daily_amount {
+                Some(amount) => write!(f, "max_daily_amount={}", amount)?,
+                None => write!(f, "max_daily_amount=none")?,
+            }
+            write!(f, " destinations={}", self.allowed_destinations.len())?;
+            if let Some(csv) = self.unvault_csv {
+                write!(f, " unvault_csv={}", csv)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Messages related to the communication with the Coordinator
+pub mod coordinator {
+    use super::{method, Deserialize, Notification, Request, Serialize, SigSet};
+    #[cfg(feature = "revault_tx")]
+    use crate::error::Error;
+    use crate::{
+        error::MessageError,
+        validation::{
+            aliased_field, check_deposit_outpoints_count, check_signature, check_transaction,
+            ValidationConfig,
+        },
+    };
+    use bitcoin::hashes::{
+        hex::{self, ToHex},
+        sha256, Hash, HashEngine,
+    };
+    use bitcoin::{
+        consensus::encode,
+        hash_types::Txid,
+        secp256k1::{key::PublicKey, schnorrsig, Signature},
+        OutPoint, Transaction,
+    };
+    #[cfg(feature = "revault_tx")]
+    use revault_tx::transactions::{RevaultTransaction, SpendTransaction};
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::convert::{From, TryFrom};
+    use std::{fmt, str};
+
+    // Raw transactions, as hex or base64 depending on the current TxEncoding
+    pub(super) mod serde_tx {
+        use super::super::TxEncoding;
+        use bitcoin::{
+            consensus::{encode, Decodable, Encodable},
+            Transaction,
+        };
+        use serde::{self, de, Deserializer, Serializer};
+        use std::{fmt, io, str};
+
+        // Batched Spend transactions can weigh a few hundred KBs: they are encoded and
+        // decoded on the fly, without any intermediary buffer.
+
+        const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+
+        fn fmt_error(_: fmt::Error) -> io::Error {
+            io::Error::new(io::ErrorKind::Other, "Formatter error")
+        }
+
+        // Write the bytes hex-encoded to a formatter
+        struct HexWriter<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+        impl io::Write for HexWriter<'_, '_> {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let mut hex = [0u8; 128];
+                for chunk in buf.chunks(hex.len() / 2) {
+                    for (i, byte) in chunk.iter().enumerate() {
+                        hex[2 * i] = HEX_CHARS[(byte >> 4) as usize];
+                        hex[2 * i + 1] = HEX_CHARS[(byte & 0x0f) as usize];
+                    }
+                    let hex = str::from_utf8(&hex[..chunk.len() * 2]).expect("Hex is ASCII");
+                    self.0.write_str(hex).map_err(fmt_error)?;
+                }
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // Write the (ASCII) output of the base64 encoder to a formatter
+        struct AsciiWriter<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+        impl io::Write for AsciiWriter<'_, '_> {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let s = str::from_utf8(buf)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                self.0.write_str(s).map_err(fmt_error)?;
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        struct TxDisplay<'a>(&'a Transaction, TxEncoding);
+
+        impl fmt::Display for TxDisplay<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self.1 {
+                    TxEncoding::Hex => self.0.consensus_encode(HexWriter(f)),
+                    TxEncoding::Base64 => {
+                        let mut writer =
+                            base64::write::EncoderWriter::new(AsciiWriter(f), base64::STANDARD);
+                        self.0
+                            .consensus_encode(&mut writer)
+                            .and_then(|len| writer.finish().map(|_| len))
+                    }
+                }
+                .map(|_| ())
+                .map_err(|_| fmt::Error)
+            }
+        }
+
+        // Read the bytes out of an hex string
+        struct HexReader<'a>(&'a [u8]);
+
+        fn hex_digit(c: u8) -> io::Result<u8> {
+            match c {
+                b'0'..=b'9' => Ok(c - b'0'),
+                b'a'..=b'f' => Ok(c - b'a' + 10),
+                b'A'..=b'F' => Ok(c - b'A' + 10),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid hex")),
+            }
+        }
+
+        impl io::Read for HexReader<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = buf.len().min(self.0.len() / 2);
+                for (byte, pair) in buf[..len].iter_mut().zip(self.0.chunks(2)) {
+                    *byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+                }
+                self.0 = &self.0[len * 2..];
+                Ok(len)
+            }
+        }
+
+        // Decode a transaction from this reader, which must not have any data left
+        fn decode<R: io::Read>(mut reader: R) -> Result<Transaction, encode::Error> {
+            let tx = Transaction::consensus_decode(&mut reader)?;
+            if reader.read(&mut [0u8; 1])? != 0 {
+                return Err(encode::Error::ParseFailed("data not consumed entirely"));
+            }
+            Ok(tx)
+        }
+
+        struct TxVisitor;
+
+        impl<'de> de::Visitor<'de> for TxVisitor {
+            type Value = Transaction;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a hex or base64 encoded transaction")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Transaction, E> {
+                // The version of a transaction can't be hex-encoded as a valid base64
+                // string and vice versa, so there is no ambiguity.
+                let is_hex = s.len() % 2 == 0 && s.bytes().all(|c| c.is_ascii_hexdigit());
+                let tx = if is_hex {
+                    decode(HexReader(s.as_bytes()))
+                } else {
+                    let mut bytes = s.as_bytes();
+                    decode(base64::read::DecoderReader::new(
+                        &mut bytes,
+                        base64::STANDARD,
+                    ))
+                }
+                .map_err(|e| match e {
+                    encode::Error::Io(ref io_err)
+                        if io_err.kind() == io::ErrorKind::InvalidData =>
+                    {
+                        E::custom("Transaction is neither hex nor base64 encoded")
+                    }
+                    e => E::custom(e),
+                })?;
+                crate::validation::check_transaction(&tx).map_err(E::custom)?;
+                Ok(tx)
+            }
+        }
+
+        pub fn serialize<S>(tx: &Transaction, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serializer.collect_str(&TxDisplay(tx, TxEncoding::current()))
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Transaction, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserializer.deserialize_str(TxVisitor)
+        }
+    }
+
+    // Same as serde_tx, for an optional transaction serialized as `null` if absent
+    mod serde_opt_tx {
+        use bitcoin::Transaction;
+        use serde::{self, Deserialize, Deserializer, Serialize, Serializer};
+
+        struct RawTxRef<'a>(&'a Transaction);
+
+        impl Serialize for RawTxRef<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                super::serde_tx::serialize(self.0, serializer)
+            }
+        }
+
+        #[derive(Deserialize)]
+        struct RawTx(#[serde(with = "super::serde_tx")] Transaction);
+
+        pub fn serialize<S>(tx: &Option<Transaction>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            tx.as_ref().map(RawTxRef).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Transaction>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Ok(Option::<RawTx>::deserialize(deserializer)?.map(|raw_tx| raw_tx.0))
+        }
+    }
+
+    // Deposit outpoints, refusing more than the configured maximum as soon as it's
+    // exceeded rather than after reading them all
+    pub(super) mod serde_deposit_outpoints {
+        use crate::{error::MessageError, validation::ValidationConfig};
+        use bitcoin::OutPoint;
+        use serde::{de, Deserializer};
+        use std::fmt;
+
+        struct OutpointsVisitor(usize);
+
+        impl<'de> de::Visitor<'de> for OutpointsVisitor {
+            type Value = Vec<OutPoint>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a list of at most {} outpoints", self.0)
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut outpoints = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(self.0));
+                while let Some(outpoint) = seq.next_element()? {
+                    if outpoints.len() == self.0 {
+                        return Err(de::Error::custom(MessageError::TooManyDepositOutpoints {
+                            count: self.0 + 1,
+                            max: self.0,
+                        }));
+                    }
+                    outpoints.push(outpoint);
+                }
+                Ok(outpoints)
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<OutPoint>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let max = ValidationConfig::current().max_deposit_outpoints;
+            deserializer.deserialize_seq(OutpointsVisitor(max))
+        }
+    }
+
+    /// Sent by a wallet to retrieve all signatures for a specific transaction
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct GetSigs {
+        /// Transaction id
+        pub id: Txid,
+        /// The [tag](Sigs::tag) of the signatures the wallet already has, for the
+        /// coordinator to respond [NotModified] if it has no other. Ignored by older
+        /// coordinators, which always respond with the [Sigs].
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub if_none_match: Option<SigsTag>,
+    }
+    impl_to_request!(GetSigs, method::GET_SIGS, GetSigs);
+
+    /// Message response to get_sigs from sync server to wallet client with a
+    /// (potentially incomplete) mapping of each public key to each signature
+    /// required to verify this **usual** transaction
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct Sigs {
+        /// Mapping of public keys to ECDSA signatures for the requested usual
+        /// transaction.
+        pub signatures: SigSet,
+    }
+
+    impl Sigs {
+        /// The tag identifying this set of signatures, to request them again only if
+        /// they changed (see [GetSigs::if_none_match])
+        pub fn tag(&self) -> SigsTag {
+            SigsTag::of(&self.signatures)
+        }
+    }
+
+    /// The kind of a revocation transaction of a vault
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum RevocationTxType {
+        /// The Cancel transaction, spending the Unvault back to a vault
+        Cancel,
+        /// The Emergency transaction, spending the deposit to the Emergency Deep Vault
+        Emergency,
+        /// The Unvault Emergency transaction, spending the Unvault to the Emergency
+        /// Deep Vault
+        UnvaultEmergency,
+    }
+
+    impl RevocationTxType {
+        /// All the revocation transactions of a vault
+        pub const ALL: [RevocationTxType; 3] = [
+            RevocationTxType::Cancel,
+            RevocationTxType::Emergency,
+            RevocationTxType::UnvaultEmergency,
+        ];
+    }
+
+    /// Sent by a wallet to retrieve the signatures for all the revocation transactions
+    /// of a vault at once, rather than with a [GetSigs] for each of them. Older
+    /// coordinators don't understand it.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct GetRevocationSigs {
+        /// The id of each revocation transaction
+        pub txids: BTreeMap<RevocationTxType, Txid>,
+    }
+    impl_to_request!(
+        GetRevocationSigs,
+        method::GET_REVOCATION_SIGS,
+        GetRevocationSigs
+    );
+
+    /// Response to [GetRevocationSigs] by the coordinator: the (potentially
+    /// incomplete) signatures for each of the transactions.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct RevocationSigs {
+        /// The signatures of each transaction, by type and id
+        pub signatures: BTreeMap<RevocationTxType, BTreeMap<Txid, SigSet>>,
+    }
+
+    impl RevocationSigs {
+        /// The signatures for the transaction of this type, if it was requested
+        pub fn get(&self, tx_type: RevocationTxType) -> Option<&SigSet> {
+            self.signatures
+                .get(&tx_type)
+                .and_then(|sigs| sigs.values().next())
+        }
+    }
+
+    /// Sent by a wallet to know whether the coordinator has signatures for a
+    /// transaction, and how many, without fetching them. Cheaper than a [GetSigs] to
+    /// poll the many transactions of a wallet. Older coordinators don't understand it.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct HasSigs {
+        /// Transaction id
+        pub id: Txid,
+    }
+    impl_to_request!(HasSigs, method::HAS_SIGS, HasSigs);
+
+    /// Response to [HasSigs] by the coordinator
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+    pub struct SigsCount {
+        /// The number of signatures stored for the transaction
+        pub count: u32,
+    }
+
+    impl SigsCount {
+        /// Whether any signature is stored for the transaction
+        pub fn has_sigs(&self) -> bool {
+            self.count > 0
+        }
+    }
+
+    /// The Taproot counterpart of [Sigs], a (potentially incomplete) mapping of
+    /// each x-only public key to its BIP340 Schnorr signature.
+    #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+    pub struct SchnorrSigs {
+        /// Mapping of x-only public keys to Schnorr signatures for the requested
+        /// usual transaction.
+        pub signatures: BTreeMap<schnorrsig::PublicKey, schnorrsig::Signature>,
+    }
+    impl_hash_with_sigs!(SchnorrSigs, [], [signatures]);
+
+    /// Sent by a manager to advertise the spend transaction that will eventually
+    /// be used for a specific unvault.
+    /// The deposit outpoints must be unique, there must be as many as the Spend
+    /// transaction has inputs (one per vault) and at most
+    /// [max_deposit_outpoints](crate::validation::ValidationConfig::max_deposit_outpoints),
+    /// which is checked at deserialization.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    #[serde(try_from = "UncheckedSetSpendTx")]
+    pub struct SetSpendTx {
+        /// Deposit outpoints of the vault this transaction is spending
+        pub deposit_outpoints: Vec<OutPoint>,
+        /// Fully signed spend transaction, as hex (or base64, see [TxEncoding](super::TxEncoding))
+        #[serde(with = "serde_tx")]
+        transaction: Transaction,
+        /// The time after which the Spend transaction must not be stored anymore, in
+        /// seconds since the UNIX epoch (see [Expiring](super::Expiring))
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub expires_at: Option<u64>,
+    }
+    impl_to_request!(SetSpendTx, method::SET_SPEND_TX, SetSpendTx);
+
+    // A SetSpendTx as read from the wire, before any sanity check
+    #[derive(Deserialize)]
+    struct UncheckedSetSpendTx {
+        #[serde(deserialize_with = "serde_deposit_outpoints::deserialize")]
+        deposit_outpoints: Vec<OutPoint>,
+        #[serde(with = "serde_tx")]
+        transaction: Transaction,
+        #[serde(default)]
+        expires_at: Option<u64>,
+    }
+
+    impl TryFrom<UncheckedSetSpendTx> for SetSpendTx {
+        type Error = MessageError;
+
+        fn try_from(unchecked: UncheckedSetSpendTx) -> Result<Self, Self::Error> {
+            SetSpendTx::check_deposit_outpoints(
+                &unchecked.deposit_outpoints,
+                &unchecked.transaction,
+            )?;
+            Ok(Self {
+                deposit_outpoints: unchecked.deposit_outpoints,
+                transaction: unchecked.transaction,
+                expires_at: unchecked.expires_at,
+            })
+        }
+    }
+
+    impl SetSpendTx {
+        // Check the deposit outpoints are coherent with the Spend transaction
+        fn check_deposit_outpoints(
+            deposit_outpoints: &[OutPoint],
+            transaction: &Transaction,
+        ) -> Result<(), MessageError> {
+            check_deposit_outpoints_count(deposit_outpoints.len())?;
+            if deposit_outpoints.len() != transaction.input.len() {
+                return Err(MessageError::DepositOutpointsMismatch {
+                    deposit_outpoints: deposit_outpoints.len(),
+                    spend_inputs: transaction.input.len(),
+                });
+            }
+
+            let mut seen = BTreeSet::new();
+            for outpoint in deposit_outpoints {
+                if !seen.insert(outpoint) {
+                    return Err(MessageError::DuplicateDepositOutpoint(*outpoint));
+                }
+            }
+
+            Ok(())
+        }
+
+        /// Create a SetSpendTx message out of a SpendTransaction.
+        ///
+        /// The SpendTransaction MUST have been finalized beforehand and `deposit_outpoints`
+        /// must contain one unique outpoint per input of the Spend.
+        #[cfg(feature = "revault_tx")]
+        pub fn from_spend_tx(
+            deposit_outpoints: Vec<OutPoint>,
+            tx: SpendTransaction,
+        ) -> Result<Self, Error> {
+            if !tx.is_finalized() {
+                return Err(MessageError::NotFinalized.into());
+            }
+            let transaction = tx.into_psbt().extract_tx();
+            Self::check_deposit_outpoints(&deposit_outpoints, &transaction)?;
+            Ok(Self {
+                deposit_outpoints,
+                transaction,
+                expires_at: None,
+            })
+        }
+
+        /// Create a SetSpendTx message out of a raw Spend transaction.
+        ///
+        /// The transaction MUST be fully signed, which can't be checked here, and
+        /// `deposit_outpoints` must contain one unique outpoint per input of it.
+        pub fn from_transaction(
+            deposit_outpoints: Vec<OutPoint>,
+            transaction: Transaction,
+        ) -> Result<Self, MessageError> {
+            check_transaction(&transaction)?;
+            Self::check_deposit_outpoints(&deposit_outpoints, &transaction)?;
+            Ok(Self {
+                deposit_outpoints,
+                transaction,
+                expires_at: None,
+            })
+        }
+
+        /// Set the time after which the Spend transaction must not be stored anymore,
+        /// in seconds since the UNIX epoch
+        pub fn with_expiry(mut self, expires_at: u64) -> Self {
+            self.expires_at = Some(expires_at);
+            self
+        }
+
+        /// Get the raw spend transaction
+        pub fn spend_tx(self) -> Transaction {
+            self.transaction
+        }
+
+        /// Split this message in chunks of at most `chunk_size` bytes (capped to
+        /// [MAX_CHUNK_SIZE]) to upload it with a [SetSpendTxBegin], returned along
+        /// with them.
+        pub fn to_chunks(&self, chunk_size: usize) -> (SetSpendTxBegin, Vec<ChunkData>) {
+            let chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE);
+            let serialized =
+                serde_json::to_vec(self).expect("SetSpendTx serialization is infallible");
+            let begin = SetSpendTxBegin {
+                total_size: serialized.len() as u32,
+                sha256: sha256::Hash::hash(&serialized),
+            };
+            let chunks = serialized
+                .chunks(chunk_size)
+                .map(|chunk| ChunkData(chunk.to_vec()))
+                .collect();
+
+            (begin, chunks)
+        }
+    }
+
+    impl super::Expiring for SetSpendTx {
+        fn expires_at(&self) -> Option<u64> {
+            self.expires_at
+        }
+    }
+
+    /// A builder for [SetSpendTx] messages, checking the deposit outpoints and the
+    /// Spend transaction as they are set.
+    #[derive(Debug, Default, Clone)]
+    pub struct SetSpendTxBuilder {
+        deposit_outpoints: Vec<OutPoint>,
+        transaction: Option<Transaction>,
+        expires_at: Option<u64>,
+    }
+
+    impl SetSpendTxBuilder {
+        /// A builder with no field set
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Add the deposit outpoint of a vault spent by the Spend transaction
+        pub fn deposit_outpoint(mut self, outpoint: OutPoint) -> Result<Self, MessageError> {
+            if self.deposit_outpoints.contains(&outpoint) {
+                return Err(MessageError::DuplicateDepositOutpoint(outpoint));
+            }
+            check_deposit_outpoints_count(self.deposit_outpoints.len() + 1)?;
+            self.deposit_outpoints.push(outpoint);
+            Ok(self)
+        }
+
+        /// Set the Spend transaction, which MUST have been finalized beforehand
+        #[cfg(feature = "revault_tx")]
+        pub fn spend_tx(self, tx: SpendTransaction) -> Result<Self, MessageError> {
+            if !tx.is_finalized() {
+                return Err(MessageError::NotFinalized);
+            }
+            self.transaction(tx.into_psbt().extract_tx())
+        }
+
+        /// Set the raw Spend transaction, which MUST be fully signed
+        pub fn transaction(mut self, transaction: Transaction) -> Result<Self, MessageError> {
+            check_transaction(&transaction)?;
+            self.transaction = Some(transaction);
+            Ok(self)
+        }
+
+        /// Set the time after which the Spend transaction must not be stored anymore,
+        /// in seconds since the UNIX epoch
+        pub fn expires_at(mut self, expires_at: u64) -> Self {
+            self.expires_at = Some(expires_at);
+            self
+        }
+
+        /// Create the message, which must have one deposit outpoint per input of the
+        /// Spend transaction
+        pub fn build(self) -> Result<SetSpendTx, MessageError> {
+            let transaction = self
+                .transaction
+                .ok_or(MessageError::MissingField("transaction"))?;
+            SetSpendTx::check_deposit_outpoints(&self.deposit_outpoints, &transaction)?;
+            Ok(SetSpendTx {
+                deposit_outpoints: self.deposit_outpoints,
+                transaction,
+                expires_at: self.expires_at,
+            })
+        }
+    }
+
+    /// Response to [SetSpendTx] by the coordinator, `ack` is `true` if it claims to have
+    /// succesfully stored the Spend tx.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SetSpendResult {
+        /// Result of acknowledgement
+        pub ack: bool,
+    }
+
+    /// Sent by a watchtower to the synchronisation server after an unvault
+    /// event to learn about the spend transaction.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct GetSpendTx {
+        /// Outpoint designing the deposit utxo that created the vault this
+        /// spend tx is spending.
+        pub deposit_outpoint: OutPoint,
+    }
+    impl_to_request!(GetSpendTx, method::GET_SPEND_TX, GetSpendTx);
+
+    /// The response to the [GetSpendTx] request.
+    /// If the coordinator has the PSBT of the Spend transaction, it may give it along
+    /// with the raw transaction so that the watchtower can learn about the amounts
+    /// of the spent coins. It must be for the same transaction, which is checked at
+    /// deserialization. Without the `revault_tx` feature, the PSBT is ignored.
+    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+    #[serde(try_from = "UncheckedSpendTx")]
+    pub struct SpendTx {
+        /// The Bitcoin-serialized Spend transaction. The sync server isn't
+        /// creating it so there is no point to create it from_spend_tx().
+        /// It is `null` if no Spend transaction was set for this outpoint.
+        #[serde(with = "serde_opt_tx")]
+        pub transaction: Option<Transaction>,
+        /// The Spend transaction as a PSBT, if the coordinator has it
+        #[cfg(feature = "revault_tx")]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        psbt: Option<SpendTransaction>,
+    }
+
+    impl Eq for SpendTx {}
+
+    impl std::hash::Hash for SpendTx {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            std::hash::Hash::hash(&self.transaction, state);
+            #[cfg(feature = "revault_tx")]
+            std::hash::Hash::hash(&self.psbt.as_ref().map(super::psbt_bytes), state);
+        }
+    }
+
+    // A SpendTx as read from the wire, before any sanity check
+    #[derive(Deserialize)]
+    struct UncheckedSpendTx {
+        #[serde(with = "serde_opt_tx")]
+        transaction: Option<Transaction>,
+        #[cfg(feature = "revault_tx")]
+        #[serde(default)]
+        psbt: Option<SpendTransaction>,
+    }
+
+    impl TryFrom<UncheckedSpendTx> for SpendTx {
+        type Error = MessageError;
+
+        fn try_from(unchecked: UncheckedSpendTx) -> Result<Self, Self::Error> {
+            #[cfg(feature = "revault_tx")]
+            match (&unchecked.transaction, &unchecked.psbt) {
+                (Some(transaction), Some(psbt)) => {
+                    let txid = transaction.txid();
+                    if psbt.txid() != txid {
+                        return Err(MessageError::TxidMismatch {
+                            expected: txid,
+                            got: psbt.txid(),
+                        });
+                    }
+                }
+                (None, Some(_)) => return Err(MessageError::MissingField("transaction")),
+                _ => {}
+            }
+
+            Ok(Self {
+                transaction: unchecked.transaction,
+                #[cfg(feature = "revault_tx")]
+                psbt: unchecked.psbt,
+            })
+        }
+    }
+
+    impl SpendTx {
+        /// The response for a Spend transaction that was set for this outpoint
+        pub fn found(transaction: Transaction) -> Self {
+            Self {
+                transaction: Some(transaction),
+                #[cfg(feature = "revault_tx")]
+                psbt: None,
+            }
+        }
+
+        /// The response for a Spend transaction that was set for this outpoint, for
+        /// which the coordinator has the PSBT. The PSBT MUST have been finalized
+        /// beforehand.
+        #[cfg(feature = "revault_tx")]
+        pub fn found_psbt(psbt: SpendTransaction) -> Result<Self, MessageError> {
+            if !psbt.is_finalized() {
+                return Err(MessageError::NotFinalized);
+            }
+
+            Ok(Self {
+                transaction: Some(psbt.clone().into_psbt().extract_tx()),
+                psbt: Some(psbt),
+            })
+        }
+
+        /// The response for an outpoint no Spend transaction was set for
+        pub fn not_found() -> Self {
+            Self {
+                transaction: None,
+                #[cfg(feature = "revault_tx")]
+                psbt: None,
+            }
+        }
+
+        /// Get the PSBT of the Spend transaction, if the coordinator gave it
+        #[cfg(feature = "revault_tx")]
+        pub fn psbt(&self) -> Option<&SpendTransaction> {
+            self.psbt.as_ref()
+        }
+
+        /// Whether a Spend transaction was set for the requested outpoint
+        pub fn is_found(&self) -> bool {
+            self.transaction.is_some()
+        }
+
+        /// Get the Spend transaction, if any was set for the requested outpoint
+        pub fn into_transaction(self) -> Option<Transaction> {
+            self.transaction
+        }
+    }
+
+    /// Message from a stakeholder client to sync server to share (at any time)
+    /// the signature for a revocation transaction with all participants.
+    #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+    #[serde(try_from = "UncheckedSig")]
+    pub struct Sig {
+        /// Secp256k1 public key used to sign the transaction (hex)
+        pub pubkey: PublicKey,
+        /// Bitcoin ECDSA signature as hex
+        pub signature: Signature,
+        /// Txid of the transaction the signature applies to. Being renamed to `txid`,
+        /// which is accepted too (see
+        /// [ValidationConfig::field_aliases](crate::validation::ValidationConfig::field_aliases)).
+        pub id: Txid,
+    }
+    impl_to_request!(Sig, method::SIG, CoordSig);
+    impl_hash_with_sigs!(Sig, [pubkey, id], [signature]);
+
+    // A Sig as read from the wire, its txid under either name
+    #[derive(Deserialize)]
+    struct UncheckedSig {
+        #[serde(deserialize_with = "super::serde_pubkey::deserialize")]
+        pubkey: PublicKey,
+        #[serde(deserialize_with = "super::serde_sig::deserialize")]
+        signature: Signature,
+        id: Option<Txid>,
+        txid: Option<Txid>,
+    }
+
+    impl TryFrom<UncheckedSig> for Sig {
+        type Error = MessageError;
+
+        fn try_from(unchecked: UncheckedSig) -> Result<Self, Self::Error> {
+            let config = ValidationConfig::current();
+            Ok(Self {
+                pubkey: unchecked.pubkey,
+                signature: unchecked.signature,
+                id: aliased_field(("id", unchecked.id), ("txid", unchecked.txid), &config)?,
+            })
+        }
+    }
+
+    /// A builder for [Sig] messages, checking the signature as it is set.
+    #[derive(Debug, Default, Clone)]
+    pub struct SigBuilder {
+        pubkey: Option<PublicKey>,
+        signature: Option<Signature>,
+        id: Option<Txid>,
+    }
+
+    impl SigBuilder {
+        /// A builder with no field set
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Set the public key of the signer
+        pub fn pubkey(mut self, pubkey: PublicKey) -> Self {
+            self.pubkey = Some(pubkey);
+            self
+        }
+
+        /// Set the signature
+        pub fn signature(mut self, signature: Signature) -> Result<Self, MessageError> {
+            check_signature(&signature, &ValidationConfig::current())?;
+            self.signature = Some(signature);
+            Ok(self)
+        }
+
+        /// Set the id of the signed transaction
+        pub fn id(mut self, id: Txid) -> Self {
+            self.id = Some(id);
+            self
+        }
+
+        /// Set the id of the signed transaction from the transaction itself
+        #[cfg(feature = "revault_tx")]
+        pub fn tx<T: RevaultTransaction>(self, tx: &T) -> Self {
+            self.id(tx.txid())
+        }
+
+        /// Create the message
+        pub fn build(self) -> Result<Sig, MessageError> {
+            Ok(Sig {
+                pubkey: self.pubkey.ok_or(MessageError::MissingField("pubkey"))?,
+                signature: self
+                    .signature
+                    .ok_or(MessageError::MissingField("signature"))?,
+                id: self.id.ok_or(MessageError::MissingField("id"))?,
+            })
+        }
+
+        /// Create the message, checking the signature is valid for this transaction
+        #[cfg(feature = "verify")]
+        pub fn build_verified<C: bitcoin::secp256k1::Verification, T: RevaultTransaction>(
+            self,
+            secp: &bitcoin::secp256k1::Secp256k1<C>,
+            tx: &T,
+            sighash_type: bitcoin::SigHashType,
+        ) -> Result<Sig, Error> {
+            let msg = self.build()?;
+            crate::verify::verify_coordinator_sig(secp, &msg, tx, sighash_type)?;
+            Ok(msg)
+        }
+    }
+
+    /// The Taproot counterpart of [Sig], sharing a BIP340 Schnorr signature for an
+    /// x-only public key.
+    #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+    pub struct SchnorrSig {
+        /// X-only public key used to sign the transaction (hex)
+        pub pubkey: schnorrsig::PublicKey,
+        /// BIP340 Schnorr signature as hex
+        pub signature: schnorrsig::Signature,
+        /// Txid of the transaction the signature applies to
+        pub id: Txid,
+    }
+    impl_to_request!(SchnorrSig, method::SIG, CoordSchnorrSig);
+    impl_hash_with_sigs!(SchnorrSig, [pubkey, id], [signature]);
+
+    // Implement hex (de)serialization for a fixed-size bytes newtype
+    macro_rules! impl_hex_array {
+        ($name:ident, $size:expr) => {
+            impl fmt::Display for $name {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "{}", self.0.to_hex())
+                }
+            }
+
+            impl str::FromStr for $name {
+                type Err = hex::Error;
+
+                fn from_str(s: &str) -> Result<Self, Self::Err> {
+                    let mut array = [0u8; $size];
+                    if crate::validation::hex_into(s, &mut array)? != $size {
+                        return Err(hex::Error::InvalidLength($size * 2, s.len()));
+                    }
+                    Ok(Self(array))
+                }
+            }
+
+            impl Serialize for $name {
+                fn serialize<S: serde::Serializer>(
+                    &self,
+                    serializer: S,
+                ) -> Result<S::Ok, S::Error> {
+                    serializer.collect_str(self)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $name {
+                fn deserialize<D: serde::Deserializer<'de>>(
+                    deserializer: D,
+                ) -> Result<Self, D::Error> {
+                    let s = super::CowStr::deserialize(deserializer)?;
+                    s.0.parse().map_err(serde::de::Error::custom)
+                }
+            }
+        };
+    }
+
+    /// A MuSig2 public nonce, that is two compressed points on the curve.
+    #[derive(Copy, Clone)]
+    pub struct MusigPubNonce(pub [u8; 66]);
+    impl_hex_array!(MusigPubNonce, 66);
+
+    impl fmt::Debug for MusigPubNonce {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "MusigPubNonce({})", self)
+        }
+    }
+
+    impl PartialEq for MusigPubNonce {
+        fn eq(&self, other: &Self) -> bool {
+            self.0[..] == other.0[..]
+        }
+    }
+
+    impl Eq for MusigPubNonce {}
+
+    impl std::hash::Hash for MusigPubNonce {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            std::hash::Hash::hash(&self.0[..], state)
+        }
+    }
+
+    /// A MuSig2 partial signature, that is a scalar.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+    pub struct MusigPartialSignature(pub [u8; 32]);
+    impl_hex_array!(MusigPartialSignature, 32);
+
+    /// Sent by a stakeholder to share (at any time) its MuSig2 public nonce for the
+    /// aggregated-key signing of a revocation transaction.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct MusigNonce {
+        /// Txid of the transaction to be signed
+        pub txid: Txid,
+        /// The stakeholder's individual public key, part of the aggregated key
+        #[serde(deserialize_with = "super::serde_pubkey::deserialize")]
+        pub pubkey: PublicKey,
+        /// The stakeholder's public nonce for this signing session
+        pub nonce: MusigPubNonce,
+    }
+    impl_to_request!(MusigNonce, method::MUSIG_NONCE, MusigNonce);
+
+    /// Sent by a stakeholder to share its MuSig2 partial signature for a revocation
+    /// transaction, once it got the public nonces of all the other participants.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct MusigPartialSig {
+        /// Txid of the transaction the partial signature applies to
+        pub txid: Txid,
+        /// The stakeholder's individual public key, part of the aggregated key
+        #[serde(deserialize_with = "super::serde_pubkey::deserialize")]
+        pub pubkey: PublicKey,
+        /// The stakeholder's partial signature
+        pub partial_sig: MusigPartialSignature,
+    }
+    impl_to_request!(MusigPartialSig, method::MUSIG_PARTIAL_SIG, MusigPartialSig);
+
+    /// Sent by a stakeholder to retrieve all the public nonces and partial signatures
+    /// shared so far for a MuSig2 signing session.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct GetMusigSession {
+        /// Txid of the transaction to be signed
+        pub txid: Txid,
+    }
+    impl_to_request!(GetMusigSession, method::GET_MUSIG_SESSION, GetMusigSession);
+
+    /// Response to [GetMusigSession] by the coordinator, containing the
+    /// (potentially incomplete) sets of public nonces and partial signatures per
+    /// participant. The uploads themselves are acknowledged with a [SigResult].
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct MusigSession {
+        /// Public nonces of the participants for this session
+        #[serde(deserialize_with = "super::serde_pubkey::deserialize_map")]
+        pub nonces: BTreeMap<PublicKey, MusigPubNonce>,
+        /// Partial signatures of the participants for this session
+        #[serde(deserialize_with = "super::serde_pubkey::deserialize_map")]
+        pub partial_sigs: BTreeMap<PublicKey, MusigPartialSignature>,
+    }
+
+    /// The maximum size of a [SealedBlob]
+    pub const MAX_SEALED_BLOB_SIZE: usize = 4096;
+
+    /// The Noise static public key of a stakeholder a [SealedSig] is encrypted to
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct RecipientKey(pub [u8; 32]);
+    impl_hex_array!(RecipientKey, 32);
+
+    // Hex (de)serialization of a bytes newtype of at most $max_size bytes, which are
+    // left out of its Debug representation
+    macro_rules! impl_hex_bytes {
+        ($name:ident, $max_size:expr) => {
+            impl fmt::Debug for $name {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "{}({} bytes)", stringify!($name), self.0.len())
+                }
+            }
+
+            impl fmt::Display for $name {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "{}", self.0.to_hex())
+                }
+            }
+
+            impl str::FromStr for $name {
+                type Err = hex::Error;
+
+                fn from_str(s: &str) -> Result<Self, Self::Err> {
+                    if s.len() > $max_size * 2 {
+                        return Err(hex::Error::InvalidLength($max_size * 2, s.len()));
+                    }
+                    hex::FromHex::from_hex(s).map(Self)
+                }
+            }
+
+            impl Serialize for $name {
+                fn serialize<S: serde::Serializer>(
+                    &self,
+                    serializer: S,
+                ) -> Result<S::Ok, S::Error> {
+                    serializer.collect_str(self)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $name {
+                fn deserialize<D: serde::Deserializer<'de>>(
+                    deserializer: D,
+                ) -> Result<Self, D::Error> {
+                    let s = super::CowStr::deserialize(deserializer)?;
+                    s.0.parse().map_err(serde::de::Error::custom)
+                }
+            }
+        };
+    }
+
+    /// A payload encrypted to a single recipient, opaque to the coordinator. See the
+    /// [envelope](crate::envelope) module to seal and open them.
+    #[derive(Clone, PartialEq, Eq, Hash)]
+    pub struct SealedBlob(pub Vec<u8>);
+    impl_hex_bytes!(SealedBlob, MAX_SEALED_BLOB_SIZE);
+
+    /// Sent by a stakeholder to share a signature for a revocation transaction
+    /// encrypted to each of the other stakeholders, so that the coordinator only stores
+    /// opaque blobs. It is acknowledged with a [SigResult].
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SealedSig {
+        /// Txid of the transaction the signature is for
+        pub txid: Txid,
+        /// The signature (a [Sig] message) sealed to each recipient
+        pub sealed: BTreeMap<RecipientKey, SealedBlob>,
+    }
+    impl_to_request!(SealedSig, method::SEALED_SIG, SealedSig);
+
+    /// Sent by a stakeholder to retrieve the signatures for a transaction sealed to it
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct GetSealedSigs {
+        /// Txid of the transaction
+        pub txid: Txid,
+        /// The key the signatures were sealed to
+        pub recipient: RecipientKey,
+    }
+    impl_to_request!(GetSealedSigs, method::GET_SEALED_SIGS, GetSealedSigs);
+
+    /// Response to [GetSealedSigs] by the coordinator, containing the signatures (as
+    /// yet) shared for this transaction and sealed to this recipient.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SealedSigs {
+        /// The sealed signatures, in no particular order
+        pub sealed: Vec<SealedBlob>,
+    }
+
+    /// Identifies a set of signatures for a transaction: it changes whenever a signature
+    /// is added. It is derived from the signatures themselves, hence computed the same
+    /// by the wallets and the coordinator without being sent along the [Sigs].
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct SigsTag(pub [u8; 32]);
+    impl_hex_array!(SigsTag, 32);
+
+    impl SigsTag {
+        /// The tag of this set of signatures
+        pub fn of(signatures: &SigSet) -> Self {
+            let mut engine = sha256::Hash::engine();
+            engine.input(b"revault_net sigs tag");
+            for (pubkey, signature) in signatures.iter() {
+                engine.input(&pubkey.serialize());
+                engine.input(&signature.serialize_compact());
+            }
+            Self(sha256::Hash::from_engine(engine).into_inner())
+        }
+    }
+
+    /// Response to a [GetSigs] with a tag matching the signatures the coordinator
+    /// has for this transaction: the wallet already has them all.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct NotModified {
+        /// The tag of the signatures, as sent in the request
+        pub not_modified: SigsTag,
+    }
+
+    /// The identifier blobs are stored under in blind storage mode. It is derived by
+    /// the stakeholders from what they store with a key they share (see the
+    /// [envelope](crate::envelope) module) and tells nothing to the coordinator.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct BlobId(pub [u8; 32]);
+    impl_hex_array!(BlobId, 32);
+
+    /// Sent by a stakeholder to a coordinator in blind storage mode, which stores
+    /// opaque blobs under opaque identifiers instead of signatures for transactions.
+    /// It is acknowledged with a [SigResult].
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct StoreBlob {
+        /// The identifier to store the blob under, along the others stored under it
+        pub blob_id: BlobId,
+        /// The blob to store
+        pub blob: SealedBlob,
+    }
+    impl_to_request!(StoreBlob, method::STORE_BLOB, StoreBlob);
+
+    /// Sent by a stakeholder to retrieve the blobs stored under an identifier
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct GetBlobs {
+        /// The identifier the blobs are stored under
+        pub blob_id: BlobId,
+    }
+    impl_to_request!(GetBlobs, method::GET_BLOBS, GetBlobs);
+
+    /// Response to [GetBlobs] by the coordinator, containing the blobs (as yet)
+    /// stored under this identifier.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct Blobs {
+        /// The blobs, in no particular order
+        pub blobs: Vec<SealedBlob>,
+    }
+
+    /// The maximum size of a chunk of a message sent in chunks
+    pub const MAX_CHUNK_SIZE: usize = 16 * 1024;
+
+    /// The maximum size of a Spend transaction reassembled from [SpendTxChunk]s, in bytes
+    pub const MAX_DOWNLOAD_SIZE: usize = 4 * 1024 * 1024;
+
+    /// A chunk of a message too large to be sent at once
+    #[derive(Clone, PartialEq, Eq, Hash)]
+    pub struct ChunkData(pub Vec<u8>);
+    impl_hex_bytes!(ChunkData, MAX_CHUNK_SIZE);
+
+    /// Sent by a manager to start uploading a [SetSpendTx] too large to be sent at
+    /// once, for instance for a Spend sweeping hundreds of vaults. Once answered with an
+    /// [UploadStarted], the serialized [SetSpendTx] is sent in order in
+    /// [SetSpendTxChunk]s, and the upload completed by a [SetSpendTxCommit].
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SetSpendTxBegin {
+        /// The size of the serialized [SetSpendTx], in bytes
+        pub total_size: u32,
+        /// The SHA256 of the serialized [SetSpendTx]
+        pub sha256: sha256::Hash,
+    }
+    impl_to_request!(SetSpendTxBegin, method::SET_SPEND_TX_BEGIN, SetSpendTxBegin);
+
+    /// Response to [SetSpendTxBegin] by the coordinator, with the identifier of the
+    /// upload that the chunks refer to.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct UploadStarted {
+        /// Identifier of this upload
+        pub upload_id: u32,
+    }
+
+    /// Sent by a manager to upload a chunk of a [SetSpendTx]. It is acknowledged with a
+    /// [SigResult].
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SetSpendTxChunk {
+        /// Identifier of the upload
+        pub upload_id: u32,
+        /// Index of this chunk, from 0
+        pub index: u32,
+        /// The bytes of this chunk
+        pub data: ChunkData,
+    }
+    impl_to_request!(SetSpendTxChunk, method::SET_SPEND_TX_CHUNK, SetSpendTxChunk);
+
+    /// Sent by a manager once all the chunks of a [SetSpendTx] were uploaded, to have
+    /// the coordinator check and store it. It is answered with a [SetSpendResult] as
+    /// for the whole [SetSpendTx].
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SetSpendTxCommit {
+        /// Identifier of the upload
+        pub upload_id: u32,
+    }
+    impl_to_request!(
+        SetSpendTxCommit,
+        method::SET_SPEND_TX_COMMIT,
+        SetSpendTxCommit
+    );
+
+    /// Sent by a watchtower to get the Spend transaction for a vault in chunks, when it
+    /// may be too large to be sent at once. The chunks are requested in order from 0,
+    /// until the last one which carries the digest of the whole transaction.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct GetSpendTxChunk {
+        /// Outpoint of the deposit of the vault
+        pub deposit_outpoint: OutPoint,
+        /// Index of the chunk, from 0
+        pub chunk_index: u32,
+    }
+    impl_to_request!(GetSpendTxChunk, method::GET_SPEND_TX_CHUNK, GetSpendTxChunk);
+
+    /// Sent by any participant to learn the current time of the coordinator, to measure
+    /// the skew of its clock (see [clock_offset](crate::clock::clock_offset)) before
+    /// sending it timestamped or expiring messages.
+    #[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct GetTime {}
+    impl_to_request!(GetTime, method::GET_TIME, GetTime);
+
+    /// Response to [GetTime] by the coordinator
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct ServerTime {
+        /// The time of the coordinator, in seconds since the UNIX epoch
+        pub time: u64,
+    }
+
+    /// Response to [GetSpendTxChunk] by the coordinator: a chunk of [MAX_CHUNK_SIZE]
+    /// bytes (or less for the last one) of the Bitcoin-serialized Spend transaction.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SpendTxChunk {
+        /// Index of this chunk
+        pub chunk_index: u32,
+        /// The number of chunks of the transaction, `0` if no Spend transaction was set
+        /// for this outpoint
+        pub chunk_count: u32,
+        /// The bytes of this chunk
+        pub data: ChunkData,
+        /// The SHA256 of the whole serialized transaction, only in the last chunk
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub sha256: Option<sha256::Hash>,
+    }
+
+    impl SpendTxChunk {
+        /// The chunk at this index of this Spend transaction, if any. Past the last
+        /// chunk, it has no data.
+        pub fn of(transaction: Option<&Transaction>, chunk_index: u32) -> Self {
+            let serialized = transaction.map(encode::serialize).unwrap_or_default();
+            let chunk_count = serialized.chunks(MAX_CHUNK_SIZE).count() as u32;
+            let data = serialized
+                .chunks(MAX_CHUNK_SIZE)
+                .nth(chunk_index as usize)
+                .unwrap_or_default()
+                .to_vec();
+            let sha256 = if chunk_index.checked_add(1) == Some(chunk_count) {
+                Some(sha256::Hash::hash(&serialized))
+            } else {
+                None
+            };
+
+            Self {
+                chunk_index,
+                chunk_count,
+                data: ChunkData(data),
+                sha256,
+            }
+        }
+    }
+
+    /// Reassembles a Spend transaction from its [SpendTxChunk]s
+    #[derive(Debug, Default, Clone)]
+    pub struct SpendTxChunks {
+        data: Vec<u8>,
+        next_index: u32,
+    }
+
+    impl SpendTxChunks {
+        /// No chunk received yet
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// The index of the next chunk to request
+        pub fn next_index(&self) -> u32 {
+            self.next_index
+        }
+
+        /// Append the next chunk. Returns the Spend transaction once the last chunk was
+        /// received and checked against its digest, `Some(None)` if there is none.
+        pub fn push(
+            &mut self,
+            chunk: SpendTxChunk,
+        ) -> Result<Option<Option<Transaction>>, MessageError> {
+            if chunk.chunk_index != self.next_index {
+                return Err(MessageError::UnexpectedChunk {
+                    expected: self.next_index,
+                    got: chunk.chunk_index,
+                });
+            }
+            if chunk.chunk_count == 0 {
+                return Ok(Some(None));
+            }
+            let max_chunks = (MAX_DOWNLOAD_SIZE + MAX_CHUNK_SIZE - 1) / MAX_CHUNK_SIZE;
+            if chunk.chunk_count as usize > max_chunks {
+                return Err(MessageError::TooLargeDownload {
+                    size: (u64::from(chunk.chunk_count) - 1) * MAX_CHUNK_SIZE as u64 + 1,
+                    max_size: MAX_DOWNLOAD_SIZE as u64,
+                });
+            }
+            let size = self.data.len() + chunk.data.0.len();
+            if size > MAX_DOWNLOAD_SIZE {
+                return Err(MessageError::TooLargeDownload {
+                    size: size as u64,
+                    max_size: MAX_DOWNLOAD_SIZE as u64,
+                });
+            }
+            self.data.extend_from_slice(&chunk.data.0);
+            self.next_index += 1;
+            if self.next_index < chunk.chunk_count {
+                return Ok(None);
+            }
+
+            match chunk.sha256 {
+                Some(sha256) if sha256 == sha256::Hash::hash(&self.data) => {}
+                _ => return Err(MessageError::CorruptedDownload),
+            }
+            let transaction =
+                encode::deserialize(&self.data).map_err(|_| MessageError::CorruptedDownload)?;
+            Ok(Some(Some(transaction)))
+        }
+    }
+
+    /// Response to [SigResult] by the coordinator, `ack` is `true` if it claims to have
+    /// succesfully stored the Spend tx.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SigResult {
+        /// Result of acknowledgement
+        pub ack: bool,
+        /// Whether the coordinator already had this exact signature, for a client
+        /// retrying after an ambiguous failure to tell a duplicate from a first store.
+        /// Not sent by older coordinators.
+        #[serde(default, skip_serializing_if = "is_false")]
+        pub already_known: bool,
+    }
+
+    impl SigResult {
+        /// The signature was stored
+        pub fn stored() -> Self {
+            Self {
+                ack: true,
+                already_known: false,
+            }
+        }
+
+        /// The signature was already stored
+        pub fn known() -> Self {
+            Self {
+                ack: true,
+                already_known: true,
+            }
+        }
+
+        /// The signature was refused
+        pub fn refused() -> Self {
+            Self {
+                ack: false,
+                already_known: false,
+            }
+        }
+    }
+
+    fn is_false(b: &bool) -> bool {
+        !b
+    }
+
+    /// Sent by a wallet to get notified of any new signature for the given transactions
+    /// and of any new Spend transaction for the given vaults instead of polling
+    /// [GetSigs] and [GetSpendTx].
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct Subscribe {
+        /// Transactions to watch for new signatures
+        pub txids: Vec<Txid>,
+        /// Deposit outpoints of the vaults to watch for a new Spend transaction
+        pub deposit_outpoints: Vec<OutPoint>,
+    }
+    impl_to_request!(Subscribe, method::SUBSCRIBE, Subscribe);
+
+    /// Response to [Subscribe] by the coordinator, with the identifier of the
+    /// subscription that will be referred to in notifications.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct SubscribeResult {
+        /// Identifier of this subscription
+        pub subscription_id: u32,
+    }
+
+    /// Sent by a wallet to stop receiving notifications for a subscription.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct Unsubscribe {
+        /// Identifier of the subscription, as returned in the [SubscribeResult]
+        pub subscription_id: u32,
+    }
+    impl_to_request!(Unsubscribe, method::UNSUBSCRIBE, Unsubscribe);
+
+    /// Response to [Unsubscribe] by the coordinator, `ack` is `false` if it did not know
+    /// about this subscription.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct UnsubscribeResult {
+        /// Result of acknowledgement
+        pub ack: bool,
+    }
+
+    /// Notification sent by the coordinator to a subscribed wallet when it
+    /// stores a new signature for a watched transaction.
+    #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+    pub struct NewSigEvent {
+        /// Subscription this notification is sent for
+        pub subscription_id: u32,
+        /// Secp256k1 public key used to sign the transaction (hex)
+        #[serde(deserialize_with = "super::serde_pubkey::deserialize")]
+        pub pubkey: PublicKey,
+        /// Bitcoin ECDSA signature as hex
+        #[serde(deserialize_with = "super::serde_sig::deserialize")]
+        pub signature: Signature,
+        /// Txid of the transaction the signature applies to
+        pub id: Txid,
+    }
+    impl_to_notification!(NewSigEvent, method::NEW_SIG, NewSig);
+    impl_hash_with_sigs!(NewSigEvent, [subscription_id, pubkey, id], [signature]);
+
+    /// Notification sent by the coordinator to a subscribed wallet when it
+    /// stores a new Spend transaction for a watched vault.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct NewSpendTxEvent {
+        /// Subscription this notification is sent for
+        pub subscription_id: u32,
+        /// Deposit outpoint of the watched vault this transaction is spending
+        pub deposit_outpoint: OutPoint,
+        /// The Bitcoin-serialized Spend transaction
+        #[serde(with = "serde_tx")]
+        pub transaction: Transaction,
+    }
+    impl_to_notification!(NewSpendTxEvent, method::NEW_SPEND_TX, NewSpendTx);
+
+    impl fmt::Display for GetSigs {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "txid={}", self.id)?;
+            if let Some(tag) = &self.if_none_match {
+                write!(f, " if_none_match={}", tag)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl fmt::Display for RevocationTxType {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                RevocationTxType::Cancel => write!(f, "cancel"),
+                RevocationTxType::Emergency => write!(f, "emergency"),
+                RevocationTxType::UnvaultEmergency => write!(f, "unvault_emergency"),
+            }
+        }
+    }
+
+    impl fmt::Display for GetRevocationSigs {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let txids: Vec<String> = self
+                .txids
+                .iter()
+                .map(|(tx_type, txid)| format!("{}={}", tx_type, txid))
+                .collect();
+            write!(f, "{}", txids.join(" "))
+        }
+    }
+
+    impl fmt::Display for RevocationSigs {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let counts: Vec<String> = self
+                .signatures
+                .iter()
+                .map(|(tx_type, sigs)| {
+                    let count: usize = sigs.values().map(|sigs| sigs.len()).sum();
+                    format!("{}={}", tx_type, count)
+                })
+                .collect();
+            write!(f, "signatures {}", counts.join(" "))
+        }
+    }
+
+    impl fmt::Display for HasSigs {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "txid={}", self.id)
+        }
+    }
+
+    impl fmt::Display for SigsCount {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "signatures={}", self.count)
+        }
+    }
+
+    impl fmt::Display for NotModified {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "not modified")
+        }
+    }
+
+    impl fmt::Display for GetTime {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "time")
+        }
+    }
+
+    impl fmt::Display for ServerTime {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "time={}", self.time)
+        }
+    }
+
+    impl fmt::Display for Sigs {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "signatures={}", self.signatures.len())
+        }
+    }
+
+    impl fmt::Display for SchnorrSigs {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "signatures={}", self.signatures.len())
+        }
+    }
+
+    impl fmt::Display for SetSpendTx {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "txid={} deposit_outpoints={}",
+                self.transaction.txid(),
+                self.deposit_outpoints.len()
+            )?;
+            if let Some(expires_at) = self.expires_at {
+                write!(f, " expires_at={}", expires_at)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl fmt::Display for SetSpendResult {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "ack={}", self.ack)
+        }
+    }
+
+    impl fmt::Display for GetSpendTx {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "deposit_outpoint={}", self.deposit_outpoint)
+        }
+    }
+
+    impl fmt::Display for SpendTx {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.transaction {
+                Some(ref tx) => write!(f, "txid={}", tx.txid()),
+                None => write!(f, "not found"),
+            }
+        }
+    }
+
+    impl fmt::Display for Sig {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "txid={}", self.id)
+        }
+    }
+
+    impl fmt::Display for SchnorrSig {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "txid={}", self.id)
+        }
+    }
+
+    impl fmt::Display for SigResult {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "ack={}", self.ack)?;
+            if self.already_known {
+                write!(f, " already_known")?;
+            }
+            Ok(())
+        }
+    }
+
+    impl fmt::Display for MusigNonce {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "txid={}", self.txid)
+        }
+    }
+
+    impl fmt::Display for MusigPartialSig {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "txid={}", self.txid)
+        }
+    }
+
+    impl fmt::Display for GetMusigSession {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "txid={}", self.txid)
+        }
+    }
+
+    impl fmt::Display for MusigSession {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "nonces={} partial_sigs={}",
+                self.nonces.len(),
+                self.partial_sigs.len()
+            )
+        }
+    }
+
+    impl fmt::Display for SealedSig {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "txid={} recipients={}", self.txid, self.sealed.len())
+        }
+    }
+
+    impl fmt::Display for GetSealedSigs {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "txid={}", self.txid)
+        }
+    }
+
+    impl fmt::Display for SealedSigs {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "sealed={}", self.sealed.len())
+        }
+    }
+
+    impl fmt::Display for StoreBlob {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "blob_id={} size={}", self.blob_id, self.blob.0.len())
+        }
+    }
+
+    impl fmt::Display for GetBlobs {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "blob_id={}", self.blob_id)
+        }
+    }
+
+    impl fmt::Display for SetSpendTxBegin {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "size={} sha256={}", self.total_size, self.sha256)
+        }
+    }
+
+    impl fmt::Display for UploadStarted {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "upload_id={}", self.upload_id)
+        }
+    }
+
+    impl fmt::Display for SetSpendTxChunk {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "upload_id={} index={} size={}",
+                self.upload_id,
+                self.index,
+                self.data.0.len()
+            )
+        }
+    }
+
+    impl fmt::Display for SetSpendTxCommit {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "upload_id={}", self.upload_id)
+        }
+    }
+
+    impl fmt::Display for GetSpendTxChunk {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "deposit_outpoint={} index={}",
+                self.deposit_outpoint, self.chunk_index
+            )
+        }
+    }
+
+    impl fmt::Display for SpendTxChunk {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "index={}/{} size={}",
+                self.chunk_index,
+                self.chunk_count,
+                self.data.0.len()
+            )
+        }
+    }
+
+    impl fmt::Display for Blobs {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "blobs={}", self.blobs.len())
+        }
+    }
+
+    impl fmt::Display for Subscribe {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "txids={} deposit_outpoints={}",
+                self.txids.len(),
+                self.deposit_outpoints.len()
+            )
+        }
+    }
+
+    impl fmt::Display for SubscribeResult {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "subscription_id={}", self.subscription_id)
+        }
+    }
+
+    impl fmt::Display for Unsubscribe {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "subscription_id={}", self.subscription_id)
+        }
+    }
+
+    impl fmt::Display for UnsubscribeResult {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "ack={}", self.ack)
+        }
+    }
+
+    impl fmt::Display for NewSigEvent {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "subscription_id={} txid={}",
+                self.subscription_id, self.id
+            )
+        }
+    }
+
+    impl fmt::Display for NewSpendTxEvent {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "subscription_id={} deposit_outpoint={} txid={}",
+                self.subscription_id,
+                self.deposit_outpoint,
+                self.transaction.txid()
+            )
+        }
+    }
+}
+
+/// Messages exchanged by redundant coordinators to replicate the signatures and the
+/// Spend transactions they store.
+///
+/// A coordinator pushes what it stores to its replicas with [Replicate] as it stores
+/// it. A replica that was down syncs with [GetEntries]: from the start for a full
+/// sync, or from the cursor of its last sync for an incremental one.
+pub mod replication {
+    use super::{coordinator, method, Deserialize, Request, Serialize, SigHash};
+
+    use bitcoin::{
+        hash_types::Txid,
+        secp256k1::{key::PublicKey, Signature},
+    };
+    use std::{fmt, hash};
+
+    /// A piece of data stored by a coordinator
+    #[allow(missing_docs)]
+    #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+    #[serde(untagged)]
+    pub enum Entry {
+        /// The signature of a participant for a transaction
+        Sig {
+            txid: Txid,
+            #[serde(deserialize_with = "super::serde_pubkey::deserialize")]
+            pubkey: PublicKey,
+            #[serde(deserialize_with = "super::serde_sig::deserialize")]
+            signature: Signature,
+        },
+        /// The Spend transaction of some vaults
+        SpendTx(coordinator::SetSpendTx),
+    }
+
+    impl hash::Hash for Entry {
+        fn hash<H: hash::Hasher>(&self, state: &mut H) {
+            match self {
+                Entry::Sig {
+                    txid,
+                    pubkey,
+                    signature,
+                } => {
+                    txid.hash(state);
+                    pubkey.hash(state);
+                    signature.sig_hash(state);
+                }
+                Entry::SpendTx(spend_tx) => spend_tx.hash(state),
+            }
+        }
+    }
+
+    /// Sent by a coordinator to its replicas to share the entries it just stored
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct Replicate {
+        /// The new entries
+        pub entries: Vec<Entry>,
+    }
+    impl_to_request!(Replicate, method::REPLICATE, Replicate);
+
+    /// Response to [Replicate] by the replica
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct ReplicateResult {
+        /// How many of the entries the replica did not have yet
+        pub new_entries: u32,
+    }
+
+    /// Sent by a replica to get the entries a coordinator stored since its last sync
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct GetEntries {
+        /// The cursor returned by the last sync, 0 for a full sync
+        pub since: u64,
+    }
+    impl_to_request!(GetEntries, method::GET_ENTRIES, GetEntries);
+
+    /// Response to [GetEntries], with the entries in the order they were stored. The
+    /// coordinator may return only part of them: the replica syncs until it gets none.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct Entries {
+        /// The entries stored since the requested cursor
+        pub entries: Vec<Entry>,
+        /// The cursor to sync from next time
+        pub cursor: u64,
+    }
+
+    impl fmt::Display for Replicate {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "entries={}", self.entries.len())
+        }
+    }
+
+    impl fmt::Display for ReplicateResult {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "new_entries={}", self.new_entries)
+        }
+    }
+
+    impl fmt::Display for GetEntries {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "since={}", self.since)
+        }
+    }
+
+    impl fmt::Display for Entries {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "entries={} cursor={}", self.entries.len(), self.cursor)
+        }
+    }
+}
+
+/// Messages related to the communication with the Cosigning Server(s)
+#[cfg(feature = "revault_tx")]
+pub mod cosigner {
+    use super::{method, Deserialize, Notification, Request, Serialize};
+    use crate::{
+        error::MessageError,
+        validation::{check_transaction_structure, ValidationConfig},
+    };
+    use bitcoin::{
+        hashes::{sha256, Hash, HashEngine},
+        secp256k1::{self, key::PublicKey, Signature},
+        SigHashType,
+    };
+    use revault_tx::transactions::{RevaultTransaction, SpendTransaction};
+    use std::convert::{From, TryFrom};
+    use std::fmt;
+
+    /// Message from a manager to a cosigning server who will soon attempt to
+    /// unvault and spend a vault utxo
+    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+    #[serde(try_from = "UncheckedSignRequest")]
+    pub struct SignRequest {
+        /// The partially signed unvault transaction
+        pub tx: SpendTransaction,
+        /// The time after which the transaction must not be signed anymore, in seconds
+        /// since the UNIX epoch (see [Expiring](super::Expiring))
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub expires_at: Option<u64>,
+    }
+    impl_to_request!(SignRequest, method::SIGN, Sign);
+
+    impl Eq for SignRequest {}
+
+    impl std::hash::Hash for SignRequest {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            std::hash::Hash::hash(&super::psbt_bytes(&self.tx), state);
+            std::hash::Hash::hash(&self.expires_at, state)
+        }
+    }
+
+    impl SignRequest {
+        /// A request to sign this transaction, not expiring
+        pub fn new(tx: SpendTransaction) -> Self {
+            Self {
+                tx,
+                expires_at: None,
+            }
+        }
+
+        /// Set the time after which the transaction must not be signed anymore, in
+        /// seconds since the UNIX epoch
+        pub fn with_expiry(mut self, expires_at: u64) -> Self {
+            self.expires_at = Some(expires_at);
+            self
+        }
+    }
+
+    impl super::Expiring for SignRequest {
+        fn expires_at(&self) -> Option<u64> {
+            self.expires_at
+        }
+    }
+
+    // A SignRequest as read from the wire, before any sanity check
+    #[derive(Deserialize)]
+    struct UncheckedSignRequest {
+        tx: SpendTransaction,
+        #[serde(default)]
+        expires_at: Option<u64>,
+    }
+
+    impl TryFrom<UncheckedSignRequest> for SignRequest {
+        type Error = MessageError;
+
+        fn try_from(unchecked: UncheckedSignRequest) -> Result<Self, Self::Error> {
+            // The PSBT isn't signed yet, so check the weight it will eventually have
+            check_transaction_structure(
+                &unchecked.tx.inner_tx().global.unsigned_tx,
+                unchecked.tx.max_weight(),
+                &ValidationConfig::current(),
+            )?;
+            Ok(Self {
+                tx: unchecked.tx,
+                expires_at: unchecked.expires_at,
+            })
+        }
+    }
+
+    /// Message from a manager to a cosigning server to sign several Spend transactions
+    /// at once, for instance when spending vaults from several unvaults. Each
+    /// transaction is signed or refused on its own.
+    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+    #[serde(try_from = "UncheckedBatchSignRequest")]
+    pub struct BatchSignRequest {
+        /// The partially signed Spend transactions
+        pub txs: Vec<SpendTransaction>,
+        /// The time after which none of the transactions must be signed anymore, in
+        /// seconds since the UNIX epoch (see [Expiring](super::Expiring))
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub expires_at: Option<u64>,
+    }
+    impl_to_request!(BatchSignRequest, method::SIGN, BatchSign);
+
+    impl Eq for BatchSignRequest {}
+
+    impl std::hash::Hash for BatchSignRequest {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            for tx in &self.txs {
+                std::hash::Hash::hash(&super::psbt_bytes(tx), state);
+            }
+            std::hash::Hash::hash(&self.expires_at, state)
+        }
+    }
+
+    impl BatchSignRequest {
+        /// A request to sign these transactions, not expiring
+        pub fn new(txs: Vec<SpendTransaction>) -> Self {
+            Self {
+                txs,
+                expires_at: None,
+            }
+        }
+
+        /// Set the time after which the transactions must not be signed anymore, in
+        /// seconds since the UNIX epoch
+        pub fn with_expiry(mut self, expires_at: u64) -> Self {
+            self.expires_at = Some(expires_at);
+            self
+        }
+
+        /// The request to sign each of the transactions on its own
+        pub fn requests(&self) -> impl Iterator<Item = SignRequest> + '_ {
+            self.txs.iter().map(move |tx| SignRequest {
+                tx: tx.clone(),
+                expires_at: self.expires_at,
+            })
+        }
+    }
+
+    impl super::Expiring for BatchSignRequest {
+        fn expires_at(&self) -> Option<u64> {
+            self.expires_at
+        }
+    }
+
+    // A BatchSignRequest as read from the wire, before any sanity check
+    #[derive(Deserialize)]
+    struct UncheckedBatchSignRequest {
+        txs: Vec<SpendTransaction>,
+        #[serde(default)]
+        expires_at: Option<u64>,
+    }
+
+    impl TryFrom<UncheckedBatchSignRequest> for BatchSignRequest {
+        type Error = MessageError;
+
+        fn try_from(unchecked: UncheckedBatchSignRequest) -> Result<Self, Self::Error> {
+            let config = ValidationConfig::current();
+            for tx in &unchecked.txs {
+                check_transaction_structure(
+                    &tx.inner_tx().global.unsigned_tx,
+                    tx.max_weight(),
+                    &config,
+                )?;
+            }
+            Ok(Self {
+                txs: unchecked.txs,
+                expires_at: unchecked.expires_at,
+            })
+        }
+    }
+
+    /// Message returned from the cosigning server to the manager containing
+    /// the requested signature
+    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+    pub struct SignResult {
+        /// Cosigning server's signature for the unvault transaction
+        pub tx: Option<SpendTransaction>,
+    }
+
+    impl Eq for SignResult {}
+
+    impl std::hash::Hash for SignResult {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            std::hash::Hash::hash(&self.tx.as_ref().map(super::psbt_bytes), state)
+        }
+    }
+
+    /// Message returned from the cosigning server to the manager for a
+    /// [BatchSignRequest], with a result per transaction in the order of the request
+    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+    pub struct BatchSignResult {
+        /// Cosigning server's signature for each Spend transaction, `None` for those
+        /// it refused to sign
+        pub txs: Vec<Option<SpendTransaction>>,
+    }
+
+    impl Eq for BatchSignResult {}
+
+    impl std::hash::Hash for BatchSignResult {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            for tx in &self.txs {
+                std::hash::Hash::hash(&tx.as_ref().map(super::psbt_bytes), state);
+            }
+        }
+    }
+
+    /// Notification from a cosigning server to the managers that it is going to sign
+    /// with a new key, signed by its current one. The managers check it against the
+    /// key they know the cosigning server by before acknowledging it with an
+    /// [AckKeyRotation].
+    #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+    pub struct KeyRotation {
+        /// The current signing key of the cosigning server (hex)
+        #[serde(deserialize_with = "super::serde_pubkey::deserialize")]
+        pub old_pubkey: PublicKey,
+        /// The signing key it is rotating to (hex)
+        #[serde(deserialize_with = "super::serde_pubkey::deserialize")]
+        pub new_pubkey: PublicKey,
+        /// The signature of the rotation by the current key (hex)
+        #[serde(deserialize_with = "super::serde_sig::deserialize")]
+        pub signature: Signature,
+    }
+    impl_to_notification!(KeyRotation, method::KEY_ROTATION, KeyRotation);
+    impl_hash_with_sigs!(KeyRotation, [old_pubkey, new_pubkey], [signature]);
+
+    impl KeyRotation {
+        /// The announcement of the rotation from this current key to this new one
+        pub fn new<C: secp256k1::Signing>(
+            secp: &secp256k1::Secp256k1<C>,
+            old_privkey: &secp256k1::SecretKey,
+            new_pubkey: PublicKey,
+        ) -> Self {
+            let old_pubkey = PublicKey::from_secret_key(secp, old_privkey);
+            let signature = secp.sign(&Self::message(&old_pubkey, &new_pubkey), old_privkey);
+            Self {
+                old_pubkey,
+                new_pubkey,
+                signature,
+            }
+        }
+
+        // What the current key signs: a tagged hash of both keys
+        fn message(old_pubkey: &PublicKey, new_pubkey: &PublicKey) -> secp256k1::Message {
+            let mut engine = sha256::Hash::engine();
+            engine.input(b"revault_net cosigner key rotation");
+            engine.input(&old_pubkey.serialize());
+            engine.input(&new_pubkey.serialize());
+            secp256k1::Message::from_slice(&sha256::Hash::from_engine(engine)[..])
+                .expect("sha256 is 32 bytes")
+        }
+
+        /// Check this rotation is from this current key of the cosigning server and
+        /// signed by it.
+        pub fn verify<C: secp256k1::Verification>(
+            &self,
+            secp: &secp256k1::Secp256k1<C>,
+            current_pubkey: &PublicKey,
+        ) -> Result<(), MessageError> {
+            if self.old_pubkey != *current_pubkey {
+                return Err(MessageError::InvalidSignatureFor(*current_pubkey));
+            }
+            secp.verify(
+                &Self::message(&self.old_pubkey, &self.new_pubkey),
+                &self.signature,
+                &self.old_pubkey,
+            )
+            .map_err(|_| MessageError::InvalidSignatureFor(self.old_pubkey))
+        }
+    }
+
+    /// Message from a manager to a cosigning server acknowledging it will now expect
+    /// signatures from this key
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct AckKeyRotation {
+        /// The new signing key announced in the [KeyRotation] (hex)
+        #[serde(deserialize_with = "super::serde_pubkey::deserialize")]
+        pub new_pubkey: PublicKey,
+    }
+    impl_to_request!(AckKeyRotation, method::ACK_KEY_ROTATION, AckKeyRotation);
+
+    /// Message returned from the cosigning server to a manager acknowledging its
+    /// [AckKeyRotation]
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct AckKeyRotationResult {
+        /// Whether this is the key the cosigning server is rotating to
+        pub ack: bool,
+        /// The key acknowledged (hex)
+        #[serde(deserialize_with = "super::serde_pubkey::deserialize")]
+        pub new_pubkey: PublicKey,
+    }
+
+    /// Message from a manager to a cosigning server to learn the key it signs with
+    /// and how, for instance to check its configuration at startup
+    #[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct GetPubkey {}
+    impl_to_request!(GetPubkey, method::GET_PUBKEY, GetPubkey);
+
+    /// How a cosigning server prevents a Spend transaction from being replaced by
+    /// another one once signed
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum AntiReplay {
+        /// It never signs two different Spend transactions spending the same outpoint
+        OncePerOutpoint,
+        /// It signs any Spend transaction
+        Unenforced,
+    }
+
+    /// Response to [GetPubkey]
+    #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+    pub struct GetPubkeyResult {
+        /// The key the cosigning server currently signs with (hex)
+        #[serde(deserialize_with = "super::serde_pubkey::deserialize")]
+        pub pubkey: PublicKey,
+        /// The signature hash types of its signatures
+        pub sighash_types: Vec<SigHashType>,
+        /// How it prevents replays
+        pub anti_replay: AntiReplay,
+    }
+
+    // SigHashType doesn't implement Hash
+    impl std::hash::Hash for GetPubkeyResult {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            std::hash::Hash::hash(&self.pubkey, state);
+            for sighash_type in &self.sighash_types {
+                std::hash::Hash::hash(&sighash_type.as_u32(), state);
+            }
+            std::hash::Hash::hash(&self.anti_replay, state)
+        }
+    }
+
+    impl GetPubkeyResult {
+        /// Whether this cosigning server can be used by a manager expecting it to sign
+        /// with this key: it must sign with `SIGHASH_ALL` and once per outpoint.
+        pub fn is_compatible(&self, expected_pubkey: &PublicKey) -> bool {
+            self.pubkey == *expected_pubkey
+                && self.sighash_types.contains(&SigHashType::All)
+                && self.anti_replay == AntiReplay::OncePerOutpoint
+        }
+    }
+
+    impl fmt::Display for GetPubkey {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "signing key")
+        }
+    }
+
+    impl fmt::Display for GetPubkeyResult {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "pubkey={} anti_replay={:?}",
+                self.pubkey, self.anti_replay
+            )
+        }
+    }
+
+    impl fmt::Display for KeyRotation {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "old_pubkey={} new_pubkey={}",
+                self.old_pubkey, self.new_pubkey
+            )
+        }
+    }
+
+    impl fmt::Display for AckKeyRotation {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "new_pubkey={}", self.new_pubkey)
+        }
+    }
+
+    impl fmt::Display for AckKeyRotationResult {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "ack={} new_pubkey={}", self.ack, self.new_pubkey)
+        }
+    }
+
+    impl fmt::Display for SignRequest {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "txid={}", self.tx.txid())?;
+            if let Some(expires_at) = self.expires_at {
+                write!(f, " expires_at={}", expires_at)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl fmt::Display for BatchSignRequest {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "txids=[")?;
+            for (i, tx) in self.txs.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ",")?;
+                }
+                write!(f, "{}", tx.txid())?;
+            }
+            write!(f, "]")?;
+            if let Some(expires_at) = self.expires_at {
+                write!(f, " expires_at={}", expires_at)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl fmt::Display for BatchSignResult {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let signed = self.txs.iter().filter(|tx| tx.is_some()).count();
+            write!(f, "signed={}/{}", signed, self.txs.len())
+        }
+    }
+
+    impl fmt::Display for SignResult {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.tx {
+                Some(ref tx) => write!(f, "txid={}", tx.txid()),
+                None => write!(f, "not signed"),
+            }
+        }
+    }
+}
+
+pub mod v1;
+pub mod v2;
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        with_id_generator, ErrorCode, ErrorResponse, Expiring, Notification, NotificationParams,
+        RedactedDebug, Request, RequestParams, Response, ResponseError, ResponseResult, SeededIds,
+        SequentialIds, SigSet, SignedRequest, TxEncoding,
+    };
+    use crate::fixtures::secp_sig;
+    use std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        str::FromStr,
+        time::Duration,
+    };
+
+    #[cfg(feature = "revault_tx")]
+    use crate::fixtures::{secp_privkey, spend_tx, SPEND_PSBT};
+    use bitcoin::{
+        consensus::encode,
+        hash_types::Txid,
+        hashes::{hex::FromHex, sha256d, Hash},
+        secp256k1::{
+            key::{PublicKey, SecretKey},
+            schnorrsig, Message, Secp256k1, Signature,
+        },
+        OutPoint, Script, Transaction, TxIn,
+    };
+    #[cfg(feature = "revault_tx")]
+    use revault_tx::transactions::{RevaultTransaction, SpendTransaction};
+
+    use super::coordinator;
+    #[cfg(feature = "revault_tx")]
+    use super::cosigner;
+    use super::method;
+    use super::replication;
+    use super::watchtower;
+    use crate::error::{Error, MessageError};
+
+    fn get_dummy_pubkey() -> PublicKey {
+        let secp_ctx = Secp256k1::new();
+        PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap())
+    }
+
+    fn get_dummy_sig() -> Signature {
+        // from https://github.com/rust-bitcoin/rust-secp256k1/blob/master/src/lib.rs
+        Signature::from_compact(&[
+            0xdc, 0x4d, 0xc2, 0x64, 0xa9, 0xfe, 0xf1, 0x7a, 0x3f, 0x25, 0x34, 0x49, 0xcf, 0x8c,
+            0x39, 0x7a, 0xb6, 0xf1, 0x6f, 0xb3, 0xd6, 0x3d, 0x86, 0x94, 0x0b, 0x55, 0x86, 0x82,
+            0x3d, 0xfd, 0x02, 0xae, 0x3b, 0x46, 0x1b, 0xb4, 0x33, 0x6b, 0x5e, 0xcb, 0xae, 0xfd,
+            0x66, 0x27, 0xaa, 0x92, 0x2e, 0xfc, 0x04, 0x8f, 0xec, 0x0c, 0x88, 0x1c, 0x10, 0xc4,
+            0xc9, 0x42, 0x8f, 0xca, 0x69, 0xc1, 0x32, 0xa2,
+        ])
+        .expect("compact signatures are 64 bytes; DER signatures are 68-72 bytes")
+    }
+
+    // A finalized Spend transaction with 4 inputs
+    #[cfg(feature = "revault_tx")]
+    fn get_dummy_signed_spend_tx() -> SpendTransaction {
+        SpendTransaction::from_psbt_str("cHNidP8BAOICAAAABCqeuW7WKzo1iD/mMt74WOi4DJRupF8Ys2QTjf4U3NcOAAAAAABe0AAAOjPsA68jDPWuRjwrZF8AN1O/sG2oB7AriUKJMsrPqiMBAAAAAF7QAAAdmwWqMhBuu2zxKu+hEVxUG2GEeql4I6BL5Ld3QL/K/AAAAAAAXtAAAOEKg+2uhHsUgQDxZt3WVCjfgjKELfnCbE7VhDEwBNxxAAAAAABe0AAAAgBvAgAAAAAAIgAgKjuiJEE1EeX8hEfJEB1Hfi+V23ETrp/KCx74SqwSLGBc9sMAAAAAAAAAAAAAAAEBK4iUAwAAAAAAIgAgRAzbIqFTxU8vRmZJTINVkIFqQsv6nWgsBrqsPSo3yg4BCP2IAQUASDBFAiEAo2IX4SPeqXGdu8cEB13BkfCDk1N+kf8mMOrwx6uJZ3gCIHYEspD4EUjt+PM8D4T5qtE5GjUT56aH9yEmf8SCR63eAUcwRAIgVdpttzz0rxS/gpSTPcG3OIQcLWrTcSFc6vthcBrBTZQCIDYm952TZ644IEETblK7N434NrFql7ccFTM7+jUj+9unAUgwRQIhALKhtFWbyicZtKuqfBcjKfl7GY1e2i2UTSS2hMtCKRIyAiA410YD546ONeAq2+CPk86Q1dQHUIRj+OQl3dmKvo/aFwGrIQPazx7E2MqqusRekjfgnWmq3OG4lF3MR3b+c/ufTDH3pKxRh2R2qRRZT2zQxRaHYRlox31j9A8EIu4mroisa3apFH7IHjHORqjFOYgmE+5URE+rT+iiiKxsk1KHZ1IhAr+ZWb/U4iUT5Vu1kF7zoqKfn5JK2wDGJ/0dkrZ/+c+UIQL+mr8QPqouEYAyh3QmEVU4Dv9BaheeYbCkvpmryviNm1KvA17QALJoAAEBKyBSDgAAAAAAIgAgRAzbIqFTxU8vRmZJTINVkIFqQsv6nWgsBrqsPSo3yg4BCP2GAQUARzBEAiAZR0TO1PRje6KzUb0lYmMuk6DjnMCHcCUU/Ct/otpMCgIgcAgD7H5oGx6jG2RjcRkS3HC617v1C58+BjyUKowb/nIBRzBEAiAhYwZTODb8zAjwfNjt5wL37yg1OZQ9wQuTV2iS7YByFwIgGb008oD3RXgzE3exXLDzGE0wst24ft15oLxj2xeqcmsBRzBEAiA6JMEwOeGlq92NItxEA2tBW5akps9EkUX1vMiaSM8yrwIgUsaiU94sOOQf/5zxb0hpp44HU17FgGov8/mFy3mT++IBqyED2s8exNjKqrrEXpI34J1pqtzhuJRdzEd2/nP7n0wx96SsUYdkdqkUWU9s0MUWh2EZaMd9Y/QPBCLuJq6IrGt2qRR+yB4xzkaoxTmIJhPuVERPq0/oooisbJNSh2dSIQK/mVm/1OIlE+VbtZBe86Kin5+SStsAxif9HZK2f/nPlCEC/pq/ED6qLhGAMod0JhFVOA7/QWoXnmGwpL6Zq8r4jZtSrwNe0ACyaAABAStEygEAAAAAACIAIEQM2yKhU8VPL0ZmSUyDVZCBakLL+p1oLAa6rD0qN8oOAQj9iAEFAEgwRQIhAL6mDIPbQZc8Y51CzTUl7+grFUVr+6CpBPt3zLio4FTLAiBkmNSnd8VvlD84jrDx12Xug5XRwueBSG0N1PBwCtyPCQFHMEQCIFLryPMdlr0XLySRzYWw75tKofJAjhhXgc1XpVDXtPRjAiBp+eeNA5Zl1aU8E3UtFxnlZ5KMRlIZpkqn7lvIlXi0rQFIMEUCIQCym/dSaqtfrTb3fs1ig1KvwS0AwyoHR62R3WGq52fk0gIgI/DAQO6EyvZT1UHYtfGsZHLlIZkFYRLZnTpznle/qsUBqyED2s8exNjKqrrEXpI34J1pqtzhuJRdzEd2/nP7n0wx96SsUYdkdqkUWU9s0MUWh2EZaMd9Y/QPBCLuJq6IrGt2qRR+yB4xzkaoxTmIJhPuVERPq0/oooisbJNSh2dSIQK/mVm/1OIlE+VbtZBe86Kin5+SStsAxif9HZK2f/nPlCEC/pq/ED6qLhGAMod0JhFVOA7/QWoXnmGwpL6Zq8r4jZtSrwNe0ACyaAABASuQArMAAAAAACIAIEQM2yKhU8VPL0ZmSUyDVZCBakLL+p1oLAa6rD0qN8oOAQj9iQEFAEgwRQIhAK8fSyw0VbBElw6L9iyedbSz6HtbrHrzs+M6EB4+6+1yAiBMN3s3ZKff7Msvgq8yfrI9v0CK5IKEoacgb0PcBKCzlwFIMEUCIQDyIe5RXWOu8PJ1Rbc2Nn0NGuPORDO4gYaGWH3swEixzAIgU2/ft0cNzSjbgT0O/MKss2Sk0e7OevzclRBSWZP3SHQBSDBFAiEA+spp4ejHuWnwymZqNYaTtrrFC5wCw3ItwtJ6DMxmRWMCIAbOYDm/yuiijXSz1YTDdyO0Zpg6TAzLY1kd90GFhQpRAashA9rPHsTYyqq6xF6SN+Cdaarc4biUXcxHdv5z+59MMfekrFGHZHapFFlPbNDFFodhGWjHfWP0DwQi7iauiKxrdqkUfsgeMc5GqMU5iCYT7lRET6tP6KKIrGyTUodnUiECv5lZv9TiJRPlW7WQXvOiop+fkkrbAMYn/R2Stn/5z5QhAv6avxA+qi4RgDKHdCYRVTgO/0FqF55hsKS+mavK+I2bUq8DXtAAsmgAAQElIQPazx7E2MqqusRekjfgnWmq3OG4lF3MR3b+c/ufTDH3pKxRhwAA").unwrap()
+    }
+
+    // One deposit outpoint per input of the dummy Spend
+    #[cfg(feature = "revault_tx")]
+    fn get_dummy_deposit_outpoints() -> Vec<OutPoint> {
+        (0..4)
+            .map(|i| {
+                OutPoint::from_str(&format!(
+                    "6e4977728e7100db80c30751f27cf834b7a1e02d083a4338874e48d1f3694446:{}",
+                    i
+                ))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    // The unsigned transaction of the dummy Spend
+    fn get_dummy_raw_tx() -> Transaction {
+        let raw_tx = "02000000018ef847bc9f2a361ab63f7abe8e56c369d15e730ba89674b09b42674bd40c94f50000000000cd5600000280d8010000000000220020ae1bdee388f2136054797227b14a983d28de29f522f3ebdc4e25fd2bae3d9e5201000000000000000000000000";
+        encode::deserialize(&Vec::<u8>::from_hex(raw_tx).unwrap()).unwrap()
+    }
+
+    macro_rules! roundtrip {
+        ($msg:ident) => {
+            let serialized_msg = serde_json::to_string(&$msg).unwrap();
+            let deserialized_msg = serde_json::from_str(&serialized_msg).unwrap();
+            assert_eq!($msg, deserialized_msg);
+            assert_eq!(
+                serialized_msg,
+                String::from_utf8_lossy(&serde_json::to_vec(&$msg).unwrap())
+            );
+        };
+    }
+
+    macro_rules! assert_str_ser {
+        ($msg:ident, $str:expr) => {
+            let ser = serde_json::to_string(&$msg).unwrap();
+            assert_eq!(ser, $str);
+        };
+    }
+
+    // Check the method of the request created from these params is specified as
+    // accepting them
+    fn assert_request_spec<'a, T: Into<Request<'a>>>(params: T, recipient: method::Peer) {
+        let type_name = std::any::type_name::<T>();
+        let req: Request = params.into();
+        let spec = method::request_spec(req.method(), recipient).unwrap();
+        assert!(spec
+            .params
+            .iter()
+            .any(|p| type_name.ends_with(&format!("message::{}", p))));
+        assert_eq!(req.method(), req.clone().params().method());
+    }
+
+    #[test]
+    fn proto_schema() {
+        let schema = include_str!("../proto/revault_net.proto");
+        // coordinator::GetSigs is CoordinatorGetSigs
+        let proto_name = |path: &str| {
+            path.split("::")
+                .map(|part| {
+                    let mut chars = part.chars();
+                    let first = chars.next().unwrap().to_ascii_uppercase();
+                    std::iter::once(first).chain(chars).collect::<String>()
+                })
+                .collect::<String>()
+        };
+        let rpc_name = |method: &str| proto_name(&method.replace('_', "::"));
+
+        for spec in method::REQUESTS.iter().chain(method::NOTIFICATIONS) {
+            for path in spec.params.iter().chain(spec.results) {
+                let message = format!("message {} {{", proto_name(path));
+                assert!(schema.contains(&message), "Missing '{}'", message);
+            }
+            if spec.results.is_empty() {
+                continue;
+            }
+            let service = format!("service {:?} {{", spec.recipient);
+            let service = &schema[schema.find(&service).expect("Missing service")..];
+            let service = &service[..service.find("\n}").unwrap()];
+            let rpc = format!("rpc {}(", rpc_name(spec.method));
+            assert!(service.contains(&rpc), "Missing '{}'", rpc);
+        }
+    }
+
+    #[test]
+    fn method_registry() {
+        let txid = Txid::default();
+        let pubkey = get_dummy_pubkey();
+        let signature = get_dummy_sig();
+        let keypair = get_dummy_schnorr_keypair();
+        let deposit_outpoint = OutPoint::default();
+
+        assert_request_spec(
+            watchtower::Sig {
+                signatures: [(pubkey, signature)].iter().cloned().collect(),
+                txid,
+                deposit_outpoint,
+            },
+            method::Peer::Watchtower,
+        );
+        assert_request_spec(
+            watchtower::SchnorrSig {
+                signatures: BTreeMap::new(),
+                txid,
+                deposit_outpoint,
+            },
+            method::Peer::Watchtower,
+        );
+        assert_request_spec(
+            watchtower::SyncVaults { vaults: vec![] },
+            method::Peer::Watchtower,
+        );
+        assert_request_spec(
+            coordinator::Sig {
+                pubkey,
+                signature,
+                id: txid,
+            },
+            method::Peer::Coordinator,
+        );
+        assert_request_spec(
+            coordinator::SchnorrSig {
+                pubkey: schnorrsig::PublicKey::from_keypair(&Secp256k1::new(), &keypair),
+                signature: get_dummy_schnorr_sig(),
+                id: txid,
+            },
+            method::Peer::Coordinator,
+        );
+        assert_request_spec(
+            coordinator::GetSigs {
+                id: txid,
+                if_none_match: None,
+            },
+            method::Peer::Coordinator,
+        );
+        assert_request_spec(
+            coordinator::GetSpendTx { deposit_outpoint },
+            method::Peer::Coordinator,
+        );
+        assert_request_spec(
+            coordinator::GetMusigSession { txid },
+            method::Peer::Coordinator,
+        );
+        assert_request_spec(
+            coordinator::Subscribe {
+                txids: vec![txid],
+                deposit_outpoints: vec![],
+            },
+            method::Peer::Coordinator,
+        );
+        assert_request_spec(
+            coordinator::Unsubscribe { subscription_id: 1 },
+            method::Peer::Coordinator,
+        );
+        assert_request_spec(
+            replication::Replicate { entries: vec![] },
+            method::Peer::Coordinator,
+        );
+        assert_request_spec(
+            replication::GetEntries { since: 0 },
+            method::Peer::Coordinator,
+        );
+        #[cfg(feature = "revault_tx")]
+        assert_request_spec(
+            cosigner::SignRequest::new(spend_tx()),
+            method::Peer::Cosigner,
+        );
+
+        // Each method is specified once per recipient
+        for spec in method::REQUESTS {
+            assert_eq!(
+                method::request_spec(spec.method, spec.recipient),
+                Some(spec)
+            );
+        }
+        assert!(method::request_spec(method::SIGN, method::Peer::Coordinator).is_none());
+
+        let notif = Notification::from(coordinator::NewSigEvent {
+            subscription_id: 1,
+            pubkey,
+            signature,
+            id: txid,
+        });
+        let spec = method::notification_spec(notif.method()).unwrap();
+        assert_eq!(spec.params, &["coordinator::NewSigEvent"]);
+        assert!(spec.results.is_empty());
+    }
+
+    #[test]
+    fn sigset() {
+        let secp = Secp256k1::new();
+        let keys: Vec<PublicKey> = (1..4)
+            .map(|i| PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[i; 32]).unwrap()))
+            .collect();
+        let sig = get_dummy_sig();
+        let other_sig = secp_sig().1;
+
+        let mut sigset = SigSet::new();
+        assert!(sigset.is_empty());
+        assert!(sigset.insert(keys[0], sig).unwrap());
+        // Inserting the same signature twice is fine, but a different one isn't
+        assert!(!sigset.insert(keys[0], sig).unwrap());
+        assert_eq!(
+            sigset.insert(keys[0], other_sig).unwrap_err().to_string(),
+            format!("Conflicting signatures for public key '{}'", keys[0])
+        );
+        assert_eq!(sigset.len(), 1);
+        assert_eq!(sigset.get(&keys[0]), Some(&sig));
+
+        let mut other: SigSet = [(keys[0], sig), (keys[1], other_sig)]
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(sigset.conflicts(&other).count(), 0);
+        assert_eq!(sigset.merge(&other).unwrap(), 1);
+        assert_eq!(sigset.merge(&other).unwrap(), 0);
+        assert!(sigset.contains_key(&keys[1]));
+
+        // A conflicting merge leaves the set untouched
+        other = [(keys[1], sig), (keys[2], sig)].iter().cloned().collect();
+        assert_eq!(sigset.conflicts(&other).collect::<Vec<_>>(), vec![&keys[1]]);
+        sigset.merge(&other).unwrap_err();
+        assert_eq!(sigset.len(), 2);
+
+        assert_eq!(sigset.count_from(&keys), 2);
+        assert_eq!(sigset.count_from(&[keys[0], keys[0]]), 1);
+        assert_eq!(sigset.count_from(&keys[2..]), 0);
+        assert!(sigset.has_threshold(&keys, 2));
+        assert!(!sigset.has_threshold(&keys, 3));
+
+        roundtrip!(sigset);
+        assert_eq!(sigset.into_inner().len(), 2);
+    }
+
+    #[test]
+    fn sig_builders() {
+        let pubkey = get_dummy_pubkey();
+        let sig = get_dummy_sig();
+        let mut high_s_sig = Signature::from_str("3046022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae022100c4b9e44bcc94a134510299d8556dd102b61ef0da272c8f76f68fcec266750e9f").unwrap();
+        let txid = Txid::default();
+        let deposit_outpoint = OutPoint::from_str(
+            "3694ef9e8fcd78e9b8165a41e6f5e2b5f10bcd92c6d6e42b3325a850df56cd83:0",
+        )
+        .unwrap();
+
+        let msg = coordinator::SigBuilder::new()
+            .pubkey(pubkey)
+            .signature(sig)
+            .unwrap()
+            .id(txid)
+            .build()
+            .unwrap();
+        assert_eq!(
+            msg,
+            coordinator::Sig {
+                pubkey,
+                signature: sig,
+                id: txid
+            }
+        );
+        assert_eq!(
+            coordinator::SigBuilder::new()
+                .signature(high_s_sig)
+                .unwrap_err(),
+            MessageError::HighSSignature(high_s_sig)
+        );
+        assert_eq!(
+            coordinator::SigBuilder::new()
+                .pubkey(pubkey)
+                .id(txid)
+                .build()
+                .unwrap_err(),
+            MessageError::MissingField("signature")
+        );
+
+        let builder = watchtower::SigBuilder::new()
+            .signature(pubkey, sig)
+            .unwrap()
+            .txid(txid);
+        assert_eq!(
+            builder.clone().build().unwrap_err(),
+            MessageError::MissingField("deposit_outpoint")
+        );
+        assert_eq!(
+            builder.clone().signature(pubkey, high_s_sig).unwrap_err(),
+            MessageError::HighSSignature(high_s_sig)
+        );
+        // Once normalized, it's the very same signature
+        high_s_sig.normalize_s();
+        builder.clone().signature(pubkey, high_s_sig).unwrap();
+        let other_sig = secp_sig().1;
+        assert_eq!(
+            builder.clone().signature(pubkey, other_sig).unwrap_err(),
+            MessageError::ConflictingSignature(pubkey)
+        );
+        assert_eq!(
+            builder.deposit_outpoint(deposit_outpoint).build().unwrap(),
+            watchtower::Sig {
+                signatures: [(pubkey, sig)].iter().cloned().collect(),
+                txid,
+                deposit_outpoint,
+            }
+        );
+        assert_eq!(
+            watchtower::SigBuilder::new()
+                .txid(txid)
+                .deposit_outpoint(deposit_outpoint)
+                .build()
+                .unwrap_err(),
+            MessageError::MissingField("signatures")
+        );
+    }
+
+    #[test]
+    fn serde_watchtower_sig() {
+        let pubkey: PublicKey = get_dummy_pubkey();
+        let sig: Signature = get_dummy_sig();
+        let signatures: SigSet = [(pubkey, sig)].iter().cloned().collect();
+        let txid = Txid::default();
+        let deposit_outpoint = OutPoint::from_str(
+            "3694ef9e8fcd78e9b8165a41e6f5e2b5f10bcd92c6d6e42b3325a850df56cd83:0",
+        )
+        .unwrap();
+        let msg = watchtower::Sig {
+            signatures,
+            txid,
+            deposit_outpoint,
+        };
+        let req = Request::from(msg);
+        roundtrip!(req);
+        assert_str_ser!(
+            req,
+            format!("{{\"method\":\"sig\",\"params\":{{\"signatures\":{{\"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c\":\"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2\"}},\"txid\":\"0000000000000000000000000000000000000000000000000000000000000000\",\"deposit_outpoint\":\"3694ef9e8fcd78e9b8165a41e6f5e2b5f10bcd92c6d6e42b3325a850df56cd83:0\"}},\"id\":{}}}", req.id())
+            );
+    }
+
+    #[test]
+    fn serde_watchtower_sig_ack() {
+        let ack = true;
+        let txid = Txid::default();
+        let msg = Response {
+            result: ResponseResult::WtSig(watchtower::SigResult { ack, txid }),
+            id: 1946,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(
+            msg,
+            r#"{"result":{"ack":true,"txid":"0000000000000000000000000000000000000000000000000000000000000000"},"id":1946}"#
+        );
+    }
+
+    #[test]
+    fn serde_watchtower_sync_vaults() {
+        let sig = watchtower::Sig {
+            signatures: [(get_dummy_pubkey(), get_dummy_sig())]
+                .iter()
+                .cloned()
+                .collect(),
+            txid: Txid::default(),
+            deposit_outpoint: OutPoint::default(),
+        };
+        let msg = watchtower::SyncVaults {
+            vaults: vec![sig.clone()],
+        };
+        let req = Request::from(msg.clone());
+        roundtrip!(req);
+        assert_eq!(req.method(), method::SYNC_VAULTS);
+        assert_eq!(req.params(), RequestParams::WtSyncVaults(msg));
+
+        let msg = Response {
+            result: ResponseResult::WtSyncVaults(watchtower::SyncVaultsResult { missing: vec![] }),
+            id: 1946,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(msg, r#"{"result":{"missing":[]},"id":1946}"#);
+        let msg = Response {
+            result: ResponseResult::WtSyncVaults(watchtower::SyncVaultsResult {
+                missing: vec![sig],
+            }),
+            id: 1946,
+        };
+        roundtrip!(msg);
+    }
+
+    #[test]
+    fn serde_watchtower_spend_policy() {
+        let req = Request::from(watchtower::GetSpendPolicy {});
+        roundtrip!(req);
+        assert_eq!(req.method(), method::GET_SPEND_POLICY);
+        assert_str_ser!(
+            req,
+            format!(
+                r#"{{"method":"get_spend_policy","params":{{}},"id":{}}}"#,
+                req.id()
+            )
+        );
+        // Its params don't capture the ones of other requests
+        let req = Request::from(coordinator::GetSpendTx {
+            deposit_outpoint: OutPoint::default(),
+        });
+        roundtrip!(req);
+
+        let destination = Script::from(vec![0x00, 0x14]);
+        let policy = watchtower::SpendPolicy {
+            max_daily_amount: Some(1_000_000),
+            allowed_destinations: vec![destination.clone()],
+            unvault_csv: Some(144),
+        };
+        let msg = Response {
+            result: ResponseResult::WtSpendPolicy(policy.clone()),
+            id: 3,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(
+            msg,
+            r#"{"result":{"max_daily_amount":1000000,"allowed_destinations":["0014"],"unvault_csv":144},"id":3}"#
+        );
+        assert!(policy.allows(&destination, 600_000, 400_000));
+        assert!(!policy.allows(&destination, 600_000, 400_001));
+        assert!(!policy.allows(&Script::new(), 1, 0));
+        assert!(watchtower::SpendPolicy::default().allows(&Script::new(), u64::MAX, u64::MAX));
+    }
+
+    #[test]
+    fn serde_watchtower_approve_spend() {
+        let spend_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![Default::default()],
+        };
+        let msg = watchtower::ApproveSpend {
+            deposit_outpoints: vec![OutPoint::default()],
+            spend_tx: spend_tx.clone(),
+        };
+        let req = Request::from(msg.clone());
+        roundtrip!(req);
+        assert_eq!(req.method(), method::APPROVE_SPEND);
+        assert_eq!(req.params(), RequestParams::WtApproveSpend(msg));
+
+        let txid = spend_tx.txid();
+        let msg = Response {
+            result: ResponseResult::WtSpendApproval(watchtower::SpendApproval::approve(txid)),
+            id: 4,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(
+            msg,
+            format!(
+                r#"{{"result":{{"approved":true,"txid":"{}"}},"id":4}}"#,
+                txid
+            )
+        );
+        let msg = Response {
+            result: ResponseResult::WtSpendApproval(watchtower::SpendApproval::reject(
+                txid,
+                "Daily limit exceeded",
+            )),
+            id: 5,
+        };
+        roundtrip!(msg);
+    }
+
+    #[test]
+    fn serde_watchtower_get_spend_tx() {
+        let msg = coordinator::GetSpendTx {
+            deposit_outpoint: OutPoint::from_str(
+                "6a276a96807dd45ceed9cbd6fd48b5edf185623b23339a1643e19e8dcbf2e474:0",
+            )
+            .unwrap(),
+        };
+        let req = Request::from(msg);
+        roundtrip!(req);
+        assert_str_ser!(
+            req,
+            format!("{{\"method\":\"get_spend_tx\",\"params\":{{\"deposit_outpoint\":\"6a276a96807dd45ceed9cbd6fd48b5edf185623b23339a1643e19e8dcbf2e474:0\"}},\"id\":{}}}", req.id()
+        ));
+
+        // Response
+        let msg = Response {
+            result: ResponseResult::SpendTx(coordinator::SpendTx::found(get_dummy_raw_tx())),
+            id: 0,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(
+            msg,
+            r#"{"result":{"transaction":"02000000018ef847bc9f2a361ab63f7abe8e56c369d15e730ba89674b09b42674bd40c94f50000000000cd5600000280d8010000000000220020ae1bdee388f2136054797227b14a983d28de29f522f3ebdc4e25fd2bae3d9e5201000000000000000000000000"},"id":0}"#
+        );
+
+        // Response when no Spend transaction was set
+        let msg = Response {
+            result: ResponseResult::SpendTx(coordinator::SpendTx::not_found()),
+            id: 0,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(msg, r#"{"result":{"transaction":null},"id":0}"#);
+        let resp: Response<coordinator::SpendTx> =
+            serde_json::from_str(r#"{"result":{"transaction":null},"id":0}"#).unwrap();
+        assert!(!resp.result.is_found());
+        assert_eq!(resp.result.into_transaction(), None);
+        // The field must be explicitly set
+        serde_json::from_str::<coordinator::SpendTx>("{}").unwrap_err();
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn serde_spend_tx_psbt() {
+        let signed_spend_tx = get_dummy_signed_spend_tx();
+        coordinator::SpendTx::found_psbt(spend_tx()).unwrap_err();
+        let msg = Response {
+            result: ResponseResult::SpendTx(
+                coordinator::SpendTx::found_psbt(signed_spend_tx.clone()).unwrap(),
+            ),
+            id: 0,
+        };
+        roundtrip!(msg);
+        let ser_msg = serde_json::to_string(&msg).unwrap();
+        assert!(ser_msg.contains(&format!(
+            r#","psbt":"{}"}}"#,
+            signed_spend_tx.as_psbt_string()
+        )));
+        let resp: Response<coordinator::SpendTx> = serde_json::from_str(&ser_msg).unwrap();
+        assert_eq!(resp.result.psbt(), Some(&signed_spend_tx));
+        assert_eq!(
+            resp.result.into_transaction(),
+            Some(signed_spend_tx.clone().into_psbt().extract_tx())
+        );
+        // The PSBT must be for the same transaction
+        let mismatch = format!(
+            r#"{{"transaction":"{}","psbt":"{}"}}"#,
+            encode::serialize_hex(&get_dummy_raw_tx()),
+            signed_spend_tx.as_psbt_string()
+        );
+        assert!(serde_json::from_str::<coordinator::SpendTx>(&mismatch)
+            .unwrap_err()
+            .to_string()
+            .contains("Message is for transaction"));
+        assert!(serde_json::from_str::<coordinator::SpendTx>(&format!(
+            r#"{{"transaction":null,"psbt":"{}"}}"#,
+            signed_spend_tx.as_psbt_string()
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn serde_server_sig() {
+        let pubkey = get_dummy_pubkey();
+        let signature = get_dummy_sig();
+        let id = Txid::default();
+
+        let msg = coordinator::Sig {
+            pubkey,
+            signature,
+            id,
+        };
+        let req = Request::from(msg);
+        roundtrip!(req);
+        assert_str_ser!(
+            req,
+            format!("{{\"method\":\"sig\",\"params\":{{\"pubkey\":\"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c\",\"signature\":\"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2\",\"id\":\"0000000000000000000000000000000000000000000000000000000000000000\"}},\"id\":{}}}", req.id()
+        ));
+
+        let resp = Response {
+            result: ResponseResult::Sig(coordinator::SigResult::stored()),
+            id: 0,
+        };
+        assert_str_ser!(resp, r#"{"result":{"ack":true},"id":0}"#);
+        let resp = Response {
+            result: ResponseResult::Sig(coordinator::SigResult::refused()),
+            id: 988364,
+        };
+        assert_str_ser!(resp, r#"{"result":{"ack":false},"id":988364}"#);
+    }
+
+    #[test]
+    fn serde_server_sig_already_known() {
+        let resp = Response {
+            result: ResponseResult::Sig(coordinator::SigResult::known()),
+            id: 1,
+        };
+        roundtrip!(resp);
+        assert_str_ser!(
+            resp,
+            r#"{"result":{"ack":true,"already_known":true},"id":1}"#
+        );
+        // Older coordinators don't tell
+        let result: coordinator::SigResult = serde_json::from_str(r#"{"ack":true}"#).unwrap();
+        assert_eq!(result, coordinator::SigResult::stored());
+    }
+
+    #[test]
+    fn serde_server_get_sigs() {
+        let id = Txid::default();
+        let msg = coordinator::GetSigs {
+            id,
+            if_none_match: None,
+        };
+        let req = Request::from(msg);
+        roundtrip!(req);
+        assert_str_ser!(
+            req,
+            format!("{{\"method\":\"get_sigs\",\"params\":{{\"id\":\"0000000000000000000000000000000000000000000000000000000000000000\"}},\"id\":{}}}", req.id()
+        ));
+    }
+
+    #[test]
+    fn serde_server_get_sigs_if_none_match() {
+        let id = Txid::default();
+        // Conditional on the signatures having changed
+        let sigs = coordinator::Sigs {
+            signatures: SigSet::new(),
+        };
+        let tag = sigs.tag();
+        let req = Request::from(coordinator::GetSigs {
+            id,
+            if_none_match: Some(tag),
+        });
+        roundtrip!(req);
+        let resp = Response {
+            result: ResponseResult::NotModified(coordinator::NotModified { not_modified: tag }),
+            id: req.id(),
+        };
+        roundtrip!(resp);
+        let mut signatures = SigSet::new();
+        signatures
+            .insert(get_dummy_pubkey(), get_dummy_sig())
+            .unwrap();
+        assert_ne!(coordinator::SigsTag::of(&signatures), tag);
+    }
+
+    #[test]
+    fn serde_method_mismatch() {
+        let params = r#"{"id":"0000000000000000000000000000000000000000000000000000000000000000"}"#;
+        let req_str = format!(r#"{{"method":"get_sigs","params":{},"id":1}}"#, params);
+        let req: Request = serde_json::from_str(&req_str).unwrap();
+        assert_eq!(req.method(), method::GET_SIGS);
+
+        let err = serde_json::from_str::<Request>(&format!(
+            r#"{{"method":"sig","params":{},"id":1}}"#,
+            params
+        ))
+        .unwrap_err();
+        assert!(err.is_data());
+        assert!(err
+            .to_string()
+            .contains("Request method 'sig' does not match its params, expected 'get_sigs'"));
+        assert_eq!(ErrorCode::from(&Error::from(err)), ErrorCode::InvalidParams);
+    }
+
+    #[test]
+    fn serde_server_sigs() {
+        let pubkey: PublicKey = get_dummy_pubkey();
+        let sig = get_dummy_sig();
+        let signatures = [(pubkey, sig)].iter().cloned().collect();
+
+        // With signatures
+        let msg = Response {
+            result: ResponseResult::Sigs(coordinator::Sigs { signatures }),
+            id: 0,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(
+            msg,
+            r#"{"result":{"signatures":{"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c":"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2"}},"id":0}"#
+        );
+
+        // Without signatures
+        let signatures = SigSet::new();
+        let msg = Response {
+            result: ResponseResult::Sigs(coordinator::Sigs { signatures }),
+            id: 2234,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(msg, r#"{"result":{"signatures":{}},"id":2234}"#);
+    }
+
+    #[test]
+    fn serde_server_revocation_sigs() {
+        let txids = coordinator::RevocationTxType::ALL
+            .iter()
+            .enumerate()
+            .map(|(i, tx_type)| (*tx_type, Txid::from_hash(Hash::from_inner([i as u8; 32]))))
+            .collect();
+        let req = Request::from(coordinator::GetRevocationSigs { txids });
+        roundtrip!(req);
+        assert_eq!(req.method(), method::GET_REVOCATION_SIGS);
+        let de: Request = serde_json::from_str(
+            r#"{"method":"get_revocation_sigs","params":{"txids":{"cancel":"0000000000000000000000000000000000000000000000000000000000000000"}},"id":4}"#,
+        )
+        .unwrap();
+        assert!(matches!(de.params(), RequestParams::GetRevocationSigs(_)));
+        // It doesn't share the method of the requests for a single transaction
+        serde_json::from_str::<Request>(
+            r#"{"method":"get_sigs","params":{"txids":{"cancel":"0000000000000000000000000000000000000000000000000000000000000000"}},"id":4}"#,
+        )
+        .unwrap_err();
+        let de: super::v2::Request = serde_json::from_str(
+            r#"{"method":"get_revocation_sigs","params":{"txids":{"cancel":"0000000000000000000000000000000000000000000000000000000000000000"}},"id":4}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            de.params(),
+            super::v2::RequestParams::GetRevocationSigs(_)
+        ));
+
+        let txid = Txid::default();
+        let signatures: SigSet = [(get_dummy_pubkey(), get_dummy_sig())]
+            .iter()
+            .cloned()
+            .collect();
+        let sigs = coordinator::RevocationSigs {
+            signatures: [(
+                coordinator::RevocationTxType::Cancel,
+                [(txid, signatures.clone())].iter().cloned().collect(),
+            )]
+            .iter()
+            .cloned()
+            .collect(),
+        };
+        assert_eq!(
+            sigs.get(coordinator::RevocationTxType::Cancel),
+            Some(&signatures)
+        );
+        assert_eq!(sigs.get(coordinator::RevocationTxType::Emergency), None);
+        let msg = Response {
+            result: ResponseResult::RevocationSigs(sigs),
+            id: 4,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(
+            msg,
+            r#"{"result":{"signatures":{"cancel":{"0000000000000000000000000000000000000000000000000000000000000000":{"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c":"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2"}}}},"id":4}"#
+        );
+    }
+
+    #[test]
+    fn serde_recover_sigs() {
+        let deposit_outpoint = OutPoint::from_str(
+            "6e4977728e7ab3bfc11e36cd1b1a4e6a8ff1da8e75060d11e7399f9a683b8273:1",
+        )
+        .unwrap();
+        let req = Request::from(watchtower::RecoverSigs { deposit_outpoint });
+        roundtrip!(req);
+        assert_eq!(req.method(), method::RECOVER_SIGS);
+        // Its params are those of a get_spend_tx
+        let raw = format!(
+            r#"{{"method":"recover_sigs","params":{{"deposit_outpoint":"{}"}},"id":3}}"#,
+            deposit_outpoint
+        );
+        let de: Request = serde_json::from_str(&raw).unwrap();
+        assert_eq!(
+            de.params(),
+            RequestParams::WtRecoverSigs(watchtower::RecoverSigs { deposit_outpoint })
+        );
+        let get_spend_tx = raw.replace("recover_sigs", "get_spend_tx");
+        let de: Request = serde_json::from_str(&get_spend_tx).unwrap();
+        assert!(matches!(de.params(), RequestParams::GetSpendTx(_)));
+        let de: super::v2::Request = serde_json::from_str(&raw).unwrap();
+        assert!(matches!(
+            de.params(),
+            super::v2::RequestParams::WtRecoverSigs(_)
+        ));
+
+        let (pubkey, signature) = secp_sig();
+        let msg = Response {
+            result: ResponseResult::WtRecoveredSigs(watchtower::RecoveredSigs {
+                sigs: vec![watchtower::Sig {
+                    signatures: [(pubkey, signature)].iter().cloned().collect(),
+                    txid: deposit_outpoint.txid,
+                    deposit_outpoint,
+                }],
+            }),
+            id: 3,
+        };
+        roundtrip!(msg);
+        let msg = Response {
+            result: ResponseResult::WtRecoveredSigs(watchtower::RecoveredSigs { sigs: vec![] }),
+            id: 3,
+        };
+        roundtrip!(msg);
+    }
+
+    #[test]
+    fn serde_server_time() {
+        let req = Request::from(coordinator::GetTime {});
+        roundtrip!(req);
+        assert_eq!(req.method(), method::GET_TIME);
+        let de: Request =
+            serde_json::from_str(r#"{"method":"get_time","params":{},"id":3}"#).unwrap();
+        assert_eq!(de.id(), 3);
+        assert_eq!(de.params(), RequestParams::GetTime(coordinator::GetTime {}));
+        let de: super::v2::Request =
+            serde_json::from_str(r#"{"method":"get_time","params":{},"id":3}"#).unwrap();
+        assert!(matches!(de.params(), super::v2::RequestParams::GetTime(_)));
+
+        let msg = Response {
+            result: ResponseResult::ServerTime(coordinator::ServerTime {
+                time: 1_650_000_000,
+            }),
+            id: 3,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(msg, r#"{"result":{"time":1650000000},"id":3}"#);
+    }
+
+    #[test]
+    fn serde_has_sigs() {
+        let txid =
+            Txid::from_str("6e4977728e7ab3bfc11e36cd1b1a4e6a8ff1da8e75060d11e7399f9a683b8273")
+                .unwrap();
+        let req = Request::from(coordinator::HasSigs { id: txid });
+        roundtrip!(req);
+        assert_eq!(req.method(), method::HAS_SIGS);
+        // Its params are those of a get_sigs without a tag
+        let raw = format!(
+            r#"{{"method":"has_sigs","params":{{"id":"{}"}},"id":3}}"#,
+            txid
+        );
+        let de: Request = serde_json::from_str(&raw).unwrap();
+        assert_eq!(
+            de.params(),
+            RequestParams::HasSigs(coordinator::HasSigs { id: txid })
+        );
+        let get_sigs = raw.replace("has_sigs", "get_sigs");
+        let de: Request = serde_json::from_str(&get_sigs).unwrap();
+        assert!(matches!(de.params(), RequestParams::GetSigs(_)));
+        let raw = format!(
+            r#"{{"method":"has_sigs","params":{{"id":"{}","if_none_match":"{}"}},"id":3}}"#,
+            txid,
+            "00".repeat(32)
+        );
+        assert!(serde_json::from_str::<Request>(&raw).is_err());
+        let raw = format!(
+            r#"{{"method":"has_sigs","params":{{"txid":"{}"}},"id":3}}"#,
+            txid
+        );
+        let de: super::v2::Request = serde_json::from_str(&raw).unwrap();
+        assert_eq!(
+            de.params(),
+            super::v2::RequestParams::HasSigs(super::v2::coordinator::HasSigs { txid })
+        );
+
+        let msg = Response {
+            result: ResponseResult::SigsCount(coordinator::SigsCount { count: 2 }),
+            id: 3,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(msg, r#"{"result":{"count":2},"id":3}"#);
+    }
+
+    #[test]
+    fn serde_server_request_spend_raw() {
+        let vector = include_str!("../contrib/test_vectors/set_spend_tx.json");
+        let msg = match serde_json::from_str::<Request>(vector).unwrap().params() {
+            RequestParams::SetSpendTx(msg) => msg,
+            _ => panic!("Not a set_spend_tx request"),
+        };
+        let deposit_outpoints = msg.deposit_outpoints.clone();
+        let transaction = msg.clone().spend_tx();
+
+        // The same message can be created out of the raw Spend transaction
+        let raw_msg = coordinator::SetSpendTx::from_transaction(
+            deposit_outpoints.clone(),
+            transaction.clone(),
+        )
+        .unwrap();
+        assert_eq!(raw_msg, msg);
+        let builder = deposit_outpoints
+            .iter()
+            .try_fold(
+                coordinator::SetSpendTxBuilder::new(),
+                |builder, outpoint| builder.deposit_outpoint(*outpoint),
+            )
+            .unwrap();
+        let built_msg = builder
+            .clone()
+            .transaction(transaction.clone())
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(built_msg, msg);
+        let base64_msg = TxEncoding::Base64.scope(|| serde_json::to_string(&raw_msg).unwrap());
+        assert_eq!(
+            serde_json::from_str::<coordinator::SetSpendTx>(&base64_msg).unwrap(),
+            msg
+        );
+
+        // It is sanity checked all the same
+        assert_eq!(
+            coordinator::SetSpendTx::from_transaction(
+                deposit_outpoints[1..].to_vec(),
+                transaction.clone()
+            )
+            .unwrap_err(),
+            MessageError::DepositOutpointsMismatch {
+                deposit_outpoints: deposit_outpoints.len() - 1,
+                spend_inputs: deposit_outpoints.len(),
+            }
+        );
+        let mut no_output = transaction;
+        no_output.output.clear();
+        assert_eq!(
+            builder.transaction(no_output).unwrap_err(),
+            MessageError::NoOutput
+        );
+    }
+
+    #[test]
+    fn serde_expiry() {
+        let msg = coordinator::SetSpendTx::from_transaction(
+            vec![OutPoint::default()],
+            get_dummy_raw_tx(),
+        )
+        .unwrap();
+        // Not set, not serialized
+        let ser = serde_json::to_string(&msg).unwrap();
+        assert!(!ser.contains("expires_at"));
+        assert_eq!(msg.expires_at(), None);
+        assert!(!msg.is_expired(u64::MAX));
+
+        let expiring = msg.with_expiry(1_620_000_000);
+        let ser = serde_json::to_value(&expiring).unwrap();
+        assert_eq!(ser["expires_at"], 1_620_000_000);
+        let de: coordinator::SetSpendTx = serde_json::from_value(ser).unwrap();
+        assert_eq!(de, expiring);
+        assert!(de.is_expired(1_620_000_001));
+        assert!(de.to_string().ends_with(" expires_at=1620000000"));
+
+        #[cfg(feature = "revault_tx")]
+        {
+            let msg = cosigner::SignRequest::new(spend_tx()).with_expiry(42);
+            let ser = serde_json::to_string(&msg).unwrap();
+            let de: cosigner::SignRequest = serde_json::from_str(&ser).unwrap();
+            assert_eq!(de, msg);
+            assert_eq!(RequestParams::Sign(de).expires_at(), Some(42));
+            let msg = cosigner::BatchSignRequest::new(vec![spend_tx()]).with_expiry(42);
+            let ser = serde_json::to_string(&msg).unwrap();
+            let de: cosigner::BatchSignRequest = serde_json::from_str(&ser).unwrap();
+            assert_eq!(de, msg);
+            assert_eq!(RequestParams::BatchSign(de).expires_at(), Some(42));
+        }
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn serde_server_request_spend() {
+        let deposit_outpoints = get_dummy_deposit_outpoints();
+        let signed_spend_tx = get_dummy_signed_spend_tx();
+        let msg = coordinator::SetSpendTx::from_spend_tx(
+            deposit_outpoints.clone(),
+            signed_spend_tx.clone(),
+        )
+        .unwrap();
+        let req = Request::from(msg);
+        roundtrip!(req);
+        assert_str_ser!(
+            req,
+            format!("{{\"method\":\"set_spend_tx\",\"params\":{{\"deposit_outpoints\":[\"6e4977728e7100db80c30751f27cf834b7a1e02d083a4338874e48d1f3694446:0\",\"6e4977728e7100db80c30751f27cf834b7a1e02d083a4338874e48d1f3694446:1\",\"6e4977728e7100db80c30751f27cf834b7a1e02d083a4338874e48d1f3694446:2\",\"6e4977728e7100db80c30751f27cf834b7a1e02d083a4338874e48d1f3694446:3\"],\"transaction\":\"020000000001042a9eb96ed62b3a35883fe632def858e8b80c946ea45f18b364138dfe14dcd70e00000000005ed000003a33ec03af230cf5ae463c2b645f003753bfb06da807b02b89428932cacfaa2301000000005ed000001d9b05aa32106ebb6cf12aefa1115c541b61847aa97823a04be4b77740bfcafc00000000005ed00000e10a83edae847b148100f166ddd65428df8232842df9c26c4ed584313004dc7100000000005ed0000002006f0200000000002200202a3ba224413511e5fc8447c9101d477e2f95db7113ae9fca0b1ef84aac122c605cf6c30000000000000500483045022100a36217e123dea9719dbbc704075dc191f08393537e91ff2630eaf0c7ab89677802207604b290f81148edf8f33c0f84f9aad1391a3513e7a687f721267fc48247adde01473044022055da6db73cf4af14bf8294933dc1b738841c2d6ad371215ceafb61701ac14d9402203626f79d9367ae382041136e52bb378df836b16a97b71c15333bfa3523fbdba701483045022100b2a1b4559bca2719b4abaa7c172329f97b198d5eda2d944d24b684cb42291232022038d74603e78e8e35e02adbe08f93ce90d5d407508463f8e425ddd98abe8fda1701ab2103dacf1ec4d8caaabac45e9237e09d69aadce1b8945dcc4776fe73fb9f4c31f7a4ac51876476a914594f6cd0c51687611968c77d63f40f0422ee26ae88ac6b76a9147ec81e31ce46a8c539882613ee54444fab4fe8a288ac6c93528767522102bf9959bfd4e22513e55bb5905ef3a2a29f9f924adb00c627fd1d92b67ff9cf942102fe9abf103eaa2e1180328774261155380eff416a179e61b0a4be99abcaf88d9b52af035ed000b26805004730440220194744ced4f4637ba2b351bd2562632e93a0e39cc087702514fc2b7fa2da4c0a0220700803ec7e681b1ea31b6463711912dc70bad7bbf50b9f3e063c942a8c1bfe72014730440220216306533836fccc08f07cd8ede702f7ef283539943dc10b93576892ed807217022019bd34f280f74578331377b15cb0f3184d30b2ddb87edd79a0bc63db17aa726b0147304402203a24c13039e1a5abdd8d22dc44036b415b96a4a6cf449145f5bcc89a48cf32af022052c6a253de2c38e41fff9cf16f4869a78e07535ec5806a2ff3f985cb7993fbe201ab2103dacf1ec4d8caaabac45e9237e09d69aadce1b8945dcc4776fe73fb9f4c31f7a4ac51876476a914594f6cd0c51687611968c77d63f40f0422ee26ae88ac6b76a9147ec81e31ce46a8c539882613ee54444fab4fe8a288ac6c93528767522102bf9959bfd4e22513e55bb5905ef3a2a29f9f924adb00c627fd1d92b67ff9cf942102fe9abf103eaa2e1180328774261155380eff416a179e61b0a4be99abcaf88d9b52af035ed000b2680500483045022100bea60c83db41973c639d42cd3525efe82b15456bfba0a904fb77ccb8a8e054cb02206498d4a777c56f943f388eb0f1d765ee8395d1c2e781486d0dd4f0700adc8f0901473044022052ebc8f31d96bd172f2491cd85b0ef9b4aa1f2408e185781cd57a550d7b4f463022069f9e78d039665d5a53c13752d1719e567928c465219a64aa7ee5bc89578b4ad01483045022100b29bf7526aab5fad36f77ecd628352afc12d00c32a0747ad91dd61aae767e4d2022023f0c040ee84caf653d541d8b5f1ac6472e52199056112d99d3a739e57bfaac501ab2103dacf1ec4d8caaabac45e9237e09d69aadce1b8945dcc4776fe73fb9f4c31f7a4ac51876476a914594f6cd0c51687611968c77d63f40f0422ee26ae88ac6b76a9147ec81e31ce46a8c539882613ee54444fab4fe8a288ac6c93528767522102bf9959bfd4e22513e55bb5905ef3a2a29f9f924adb00c627fd1d92b67ff9cf942102fe9abf103eaa2e1180328774261155380eff416a179e61b0a4be99abcaf88d9b52af035ed000b2680500483045022100af1f4b2c3455b044970e8bf62c9e75b4b3e87b5bac7af3b3e33a101e3eebed7202204c377b3764a7dfeccb2f82af327eb23dbf408ae48284a1a7206f43dc04a0b39701483045022100f221ee515d63aef0f27545b736367d0d1ae3ce4433b8818686587decc048b1cc0220536fdfb7470dcd28db813d0efcc2acb364a4d1eece7afcdc9510525993f7487401483045022100faca69e1e8c7b969f0ca666a358693b6bac50b9c02c3722dc2d27a0ccc664563022006ce6039bfcae8a28d74b3d584c37723b466983a4c0ccb63591df74185850a5101ab2103dacf1ec4d8caaabac45e9237e09d69aadce1b8945dcc4776fe73fb9f4c31f7a4ac51876476a914594f6cd0c51687611968c77d63f40f0422ee26ae88ac6b76a9147ec81e31ce46a8c539882613ee54444fab4fe8a288ac6c93528767522102bf9959bfd4e22513e55bb5905ef3a2a29f9f924adb00c627fd1d92b67ff9cf942102fe9abf103eaa2e1180328774261155380eff416a179e61b0a4be99abcaf88d9b52af035ed000b26800000000\"}},\"id\":{}}}", req.id()
+        ));
+
+        let response = Response {
+            result: ResponseResult::SetSpend(coordinator::SetSpendResult { ack: true }),
+            id: 0,
+        };
+        assert_str_ser!(response, r#"{"result":{"ack":true},"id":0}"#);
+        let response = Response {
+            result: ResponseResult::SetSpend(coordinator::SetSpendResult { ack: false }),
+            id: u32::MAX,
+        };
+        assert_str_ser!(response, r#"{"result":{"ack":false},"id":4294967295}"#);
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn set_spend_tx_base64() {
+        let deposit_outpoints = get_dummy_deposit_outpoints();
+        let signed_spend_tx = get_dummy_signed_spend_tx();
+        let req = Request::from(
+            coordinator::SetSpendTx::from_spend_tx(
+                deposit_outpoints.clone(),
+                signed_spend_tx.clone(),
+            )
+            .unwrap(),
+        );
+        let ser_req = serde_json::to_string(&req).unwrap();
+
+        // The transaction may also be encoded as base64, which is always accepted
+        let base64_req = TxEncoding::Base64.scope(|| serde_json::to_string(&req).unwrap());
+        assert!(base64_req.contains("\"transaction\":\"AgAAAAABBCqeuW7WKzo1iD"));
+        assert!(base64_req.len() < ser_req.len() * 3 / 4);
+        assert_eq!(serde_json::from_str::<Request>(&base64_req).unwrap(), req);
+        assert_eq!(TxEncoding::current(), TxEncoding::Hex);
+        assert!(serde_json::from_str::<Request>(
+            &base64_req.replace("\"transaction\":\"AgAAAAAB", "\"transaction\":\"AgAA!AAB")
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn set_spend_tx_deposit_outpoints() {
+        let deposit_outpoints = get_dummy_deposit_outpoints();
+        let signed_spend_tx = get_dummy_signed_spend_tx();
+        let req = Request::from(
+            coordinator::SetSpendTx::from_spend_tx(
+                deposit_outpoints.clone(),
+                signed_spend_tx.clone(),
+            )
+            .unwrap(),
+        );
+        let ser_req = serde_json::to_string(&req).unwrap();
+
+        // The deposit outpoints must match the Spend inputs
+        assert_eq!(
+            coordinator::SetSpendTx::from_spend_tx(
+                deposit_outpoints[1..].to_vec(),
+                signed_spend_tx.clone()
+            )
+            .unwrap_err()
+            .to_string(),
+            "Message error: 'Got 3 deposit outpoint(s) for a Spend transaction with 4 input(s)'"
+        );
+        assert!(serde_json::from_str::<Request>(
+            &ser_req.replace(&format!("\"{}\",", deposit_outpoints[0]), "")
+        )
+        .is_err());
+        let mut dup_outpoints = deposit_outpoints.clone();
+        dup_outpoints[3] = dup_outpoints[0];
+        assert_eq!(
+            coordinator::SetSpendTx::from_spend_tx(dup_outpoints, signed_spend_tx.clone())
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Message error: 'Deposit outpoint '{}' is present more than once'",
+                deposit_outpoints[0]
+            )
+        );
+        assert!(serde_json::from_str::<Request>(&ser_req.replace(
+            &deposit_outpoints[3].to_string(),
+            &deposit_outpoints[0].to_string()
+        ))
+        .is_err());
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn set_spend_tx_max_deposit_outpoints() {
+        let deposit_outpoints = get_dummy_deposit_outpoints();
+        let signed_spend_tx = get_dummy_signed_spend_tx();
+        let req = Request::from(
+            coordinator::SetSpendTx::from_spend_tx(
+                deposit_outpoints.clone(),
+                signed_spend_tx.clone(),
+            )
+            .unwrap(),
+        );
+        let ser_req = serde_json::to_string(&req).unwrap();
+        let strict = crate::validation::ValidationConfig {
+            max_deposit_outpoints: 3,
+            ..Default::default()
+        };
+
+        // As many deposit outpoints as allowed at most
+        let too_many = MessageError::TooManyDepositOutpoints { count: 4, max: 3 };
+        let ser_msg = serde_json::to_string(
+            &coordinator::SetSpendTx::from_spend_tx(
+                deposit_outpoints.clone(),
+                signed_spend_tx.clone(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let err = strict
+            .scope(|| serde_json::from_str::<coordinator::SetSpendTx>(&ser_msg))
+            .unwrap_err();
+        assert!(err.to_string().contains(&too_many.to_string()));
+        assert!(strict
+            .scope(|| serde_json::from_str::<Request>(&ser_req))
+            .is_err());
+        assert!(matches!(
+            strict.scope(|| coordinator::SetSpendTx::from_spend_tx(
+                deposit_outpoints.clone(),
+                signed_spend_tx.clone()
+            )),
+            Err(Error::Message(MessageError::TooManyDepositOutpoints {
+                count: 4,
+                max: 3
+            }))
+        ));
+        assert_eq!(
+            strict
+                .scope(|| deposit_outpoints.iter().try_fold(
+                    coordinator::SetSpendTxBuilder::new(),
+                    |builder, outpoint| builder.deposit_outpoint(*outpoint),
+                ))
+                .unwrap_err(),
+            too_many
+        );
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn approve_spend_max_deposit_outpoints() {
+        let strict = crate::validation::ValidationConfig {
+            max_deposit_outpoints: 3,
+            ..Default::default()
+        };
+
+        // One deposit outpoint more than the strict configuration allows
+        let approve_spend = serde_json::to_string(&watchtower::ApproveSpend {
+            deposit_outpoints: get_dummy_deposit_outpoints(),
+            spend_tx: get_dummy_signed_spend_tx().into_psbt().extract_tx(),
+        })
+        .unwrap();
+        assert!(strict
+            .scope(|| serde_json::from_str::<watchtower::ApproveSpend>(&approve_spend))
+            .is_err());
+        serde_json::from_str::<watchtower::ApproveSpend>(&approve_spend).unwrap();
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn set_spend_tx_builder() {
+        let deposit_outpoints = get_dummy_deposit_outpoints();
+        let signed_spend_tx = get_dummy_signed_spend_tx();
+        let req = Request::from(
+            coordinator::SetSpendTx::from_spend_tx(
+                deposit_outpoints.clone(),
+                signed_spend_tx.clone(),
+            )
+            .unwrap(),
+        );
+
+        // The same checks are performed by the builder, as the fields are set
+        let builder = deposit_outpoints
+            .iter()
+            .try_fold(
+                coordinator::SetSpendTxBuilder::new(),
+                |builder, outpoint| builder.deposit_outpoint(*outpoint),
+            )
+            .unwrap();
+        assert_eq!(
+            builder
+                .clone()
+                .deposit_outpoint(deposit_outpoints[0])
+                .unwrap_err(),
+            MessageError::DuplicateDepositOutpoint(deposit_outpoints[0])
+        );
+        assert_eq!(
+            builder.clone().build().unwrap_err(),
+            MessageError::MissingField("transaction")
+        );
+        assert_eq!(
+            builder.clone().spend_tx(spend_tx()).unwrap_err(),
+            MessageError::NotFinalized
+        );
+        let built_msg = builder
+            .spend_tx(signed_spend_tx.clone())
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(Request::from(built_msg).params(), req.params());
+        assert_eq!(
+            coordinator::SetSpendTxBuilder::new()
+                .deposit_outpoint(deposit_outpoints[0])
+                .unwrap()
+                .spend_tx(signed_spend_tx.clone())
+                .unwrap()
+                .build()
+                .unwrap_err(),
+            MessageError::DepositOutpointsMismatch {
+                deposit_outpoints: 1,
+                spend_inputs: 4
+            }
+        );
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn set_spend_tx_unfinalized() {
+        // An unfinalized Spend transaction is refused
+        assert_eq!(
+            coordinator::SetSpendTx::from_spend_tx(get_dummy_deposit_outpoints(), spend_tx())
+                .unwrap_err()
+                .to_string(),
+            "Message error: 'Spend transaction is not finalized'"
+        );
+    }
+
+    #[test]
+    fn borrowed_strings() {
+        let s: super::CowStr = serde_json::from_str(r#""0a0b""#).unwrap();
+        assert!(matches!(s.0, std::borrow::Cow::Borrowed("0a0b")));
+        // Escape sequences need to be unescaped in an owned string
+        let s: super::CowStr = serde_json::from_str(r#""\u0030a0b""#).unwrap();
+        assert!(matches!(s.0, std::borrow::Cow::Owned(ref s) if s == "0a0b"));
+
+        // Keys and signatures are parsed the same either way
+        let sigs = r#"{"signatures":{"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c":"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2"}}"#;
+        let borrowed: coordinator::Sigs = serde_json::from_str(sigs).unwrap();
+        let escaped: coordinator::Sigs =
+            serde_json::from_str(&sigs.replacen("035b", "\\u0030\\u0033\\u0035b", 1)).unwrap();
+        let read: coordinator::Sigs = serde_json::from_reader(sigs.as_bytes()).unwrap();
+        assert_eq!(borrowed, escaped);
+        assert_eq!(borrowed, read);
+        assert_eq!(borrowed.signatures.len(), 1);
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn serde_raw_tx() {
+        use super::WireTransaction;
+        use bitcoin::{consensus::encode, hashes::hex::ToHex};
+
+        let tx = get_dummy_signed_spend_tx().into_psbt().extract_tx();
+        let bytes = encode::serialize(&tx);
+        let msg = coordinator::SpendTx::found(tx.clone());
+
+        // Same as the encoding of the whole serialized transaction
+        let hex_msg = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            hex_msg,
+            format!(r#"{{"transaction":"{}"}}"#, bytes.to_hex())
+        );
+        let base64_msg = TxEncoding::Base64.scope(|| serde_json::to_string(&msg).unwrap());
+        assert_eq!(
+            base64_msg,
+            format!(r#"{{"transaction":"{}"}}"#, base64::encode(&bytes))
+        );
+        for ser in [
+            &hex_msg,
+            &base64_msg,
+            &hex_msg.to_uppercase().replace("TRANSACTION", "transaction"),
+        ] {
+            let de: coordinator::SpendTx = serde_json::from_str(ser).unwrap();
+            assert_eq!(de, msg);
+            let de: coordinator::SpendTx = serde_json::from_reader(ser.as_bytes()).unwrap();
+            assert_eq!(de, msg);
+        }
+
+        // The same encoding is available to other messages
+        let wire_tx = WireTransaction::from(tx.clone());
+        assert_eq!(
+            serde_json::to_string(&wire_tx).unwrap(),
+            format!(r#""{}""#, bytes.to_hex())
+        );
+        assert_eq!(
+            TxEncoding::Base64.scope(|| serde_json::to_string(&wire_tx).unwrap()),
+            format!(r#""{}""#, base64::encode(&bytes))
+        );
+        let de: WireTransaction =
+            serde_json::from_str(&format!(r#""{}""#, base64::encode(&bytes))).unwrap();
+        assert_eq!(Transaction::from(de), tx);
+        let no_input = Transaction {
+            input: Vec::new(),
+            ..tx.clone()
+        };
+        let ser = serde_json::to_string(&WireTransaction(no_input)).unwrap();
+        assert!(serde_json::from_str::<WireTransaction>(&ser).is_err());
+
+        // Trailing data, truncated transaction and invalid encoding
+        let err = |ser: String| {
+            serde_json::from_str::<coordinator::SpendTx>(&ser)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(err(hex_msg.replace("\"}", "00\"}")).contains("data not consumed entirely"));
+        let hex_tx = bytes.to_hex();
+        let truncated = format!(r#"{{"transaction":"{}"}}"#, &hex_tx[..hex_tx.len() - 4]);
+        assert!(err(truncated).contains("failed to fill whole buffer"));
+        assert!(err(r#"{"transaction":"02!!"}"#.to_string())
+            .contains("Transaction is neither hex nor base64 encoded"));
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn serde_cosigner_sign() {
+        let tx = spend_tx();
+        let msg = cosigner::SignRequest::new(tx);
+        let req = Request::from(msg);
+        roundtrip!(req);
+        assert_str_ser!(
+            req,
+            format!(
+                "{{\"method\":\"sign\",\"params\":{{\"tx\":\"{}\"}},\"id\":{}}}",
+                SPEND_PSBT,
+                req.id()
+            )
+        );
+
+        let msg = Response {
+            result: ResponseResult::SignResult(cosigner::SignResult { tx: None }),
+            id: 975687,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(msg, r#"{"result":{"tx":null},"id":975687}"#);
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn serde_cosigner_get_pubkey() {
+        let req = Request::from(cosigner::GetPubkey {});
+        roundtrip!(req);
+        assert_eq!(req.method(), method::GET_PUBKEY);
+        assert_eq!(
+            req.params(),
+            RequestParams::GetPubkey(cosigner::GetPubkey {})
+        );
+
+        // Requests without params are told apart by their method
+        let de: Request =
+            serde_json::from_str(r#"{"method":"get_pubkey","params":{},"id":1}"#).unwrap();
+        assert!(matches!(de.params(), RequestParams::GetPubkey(_)));
+        let de: Request =
+            serde_json::from_str(r#"{"method":"get_spend_policy","params":{},"id":1}"#).unwrap();
+        assert!(matches!(de.params(), RequestParams::WtGetSpendPolicy(_)));
+        let err = serde_json::from_str::<Request>(r#"{"method":"unknown","params":{},"id":1}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("get_spend_policy"), "{}", err);
+        let de: super::v2::Request =
+            serde_json::from_str(r#"{"method":"get_pubkey","params":{},"id":1}"#).unwrap();
+        assert!(matches!(
+            de.params(),
+            super::v2::RequestParams::GetPubkey(_)
+        ));
+
+        let pubkey = get_dummy_pubkey();
+        let info = cosigner::GetPubkeyResult {
+            pubkey,
+            sighash_types: vec![bitcoin::SigHashType::All],
+            anti_replay: cosigner::AntiReplay::OncePerOutpoint,
+        };
+        let msg = Response {
+            result: ResponseResult::CosignerPubkey(info.clone()),
+            id: 7,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(
+            msg,
+            r#"{"result":{"pubkey":"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c","sighash_types":["SIGHASH_ALL"],"anti_replay":"once_per_outpoint"},"id":7}"#
+        );
+        assert!(info.is_compatible(&pubkey));
+        let other_pubkey = secp_sig().0;
+        assert!(!info.is_compatible(&other_pubkey));
+        let unenforced = cosigner::GetPubkeyResult {
+            anti_replay: cosigner::AntiReplay::Unenforced,
+            ..info.clone()
+        };
+        assert!(!unenforced.is_compatible(&pubkey));
+        let anyonecanpay = cosigner::GetPubkeyResult {
+            sighash_types: vec![bitcoin::SigHashType::AllPlusAnyoneCanPay],
+            ..info
+        };
+        assert!(!anyonecanpay.is_compatible(&pubkey));
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn cosigner_key_rotation() {
+        let secp = Secp256k1::new();
+        let (old_privkey, old_pubkey) = (secp_privkey(), secp_sig().0);
+        let new_pubkey = get_dummy_pubkey();
+
+        let msg = cosigner::KeyRotation::new(&secp, &old_privkey, new_pubkey);
+        assert_eq!(msg.old_pubkey, old_pubkey);
+        msg.verify(&secp, &old_pubkey).unwrap();
+        let notif = Notification::from(msg.clone());
+        roundtrip!(notif);
+        assert_eq!(notif.method(), method::KEY_ROTATION);
+        assert_eq!(notif.params(), NotificationParams::KeyRotation(msg.clone()));
+
+        // It must be from the key we know, and for this new key
+        assert_eq!(
+            msg.verify(&secp, &new_pubkey),
+            Err(MessageError::InvalidSignatureFor(new_pubkey))
+        );
+        let mut tampered = msg.clone();
+        tampered.new_pubkey = old_pubkey;
+        assert_eq!(
+            tampered.verify(&secp, &old_pubkey),
+            Err(MessageError::InvalidSignatureFor(old_pubkey))
+        );
+        let forged = cosigner::KeyRotation {
+            old_pubkey,
+            ..cosigner::KeyRotation::new(
+                &secp,
+                &SecretKey::from_slice(&[2; 32]).unwrap(),
+                new_pubkey,
+            )
+        };
+        assert!(forged.verify(&secp, &old_pubkey).is_err());
+
+        let msg = cosigner::AckKeyRotation { new_pubkey };
+        let req = Request::from(msg.clone());
+        roundtrip!(req);
+        assert_eq!(req.method(), method::ACK_KEY_ROTATION);
+        assert_eq!(req.params(), RequestParams::AckKeyRotation(msg));
+
+        let msg = Response {
+            result: ResponseResult::AckKeyRotation(cosigner::AckKeyRotationResult {
+                ack: true,
+                new_pubkey,
+            }),
+            id: 12,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(
+            msg,
+            r#"{"result":{"ack":true,"new_pubkey":"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c"},"id":12}"#
+        );
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn serde_cosigner_batch_sign() {
+        let tx = spend_tx();
+        let msg = cosigner::BatchSignRequest::new(vec![tx.clone(), tx.clone()]).with_expiry(42);
+        let req = Request::from(msg.clone());
+        roundtrip!(req);
+        assert_eq!(req.method(), method::SIGN);
+        assert_eq!(req.clone().params().expires_at(), Some(42));
+        assert_eq!(req.params(), RequestParams::BatchSign(msg.clone()));
+        // A single transaction is still a plain sign request
+        let single = Request::from(cosigner::SignRequest::new(tx.clone()));
+        let ser = serde_json::to_string(&single).unwrap();
+        let de: Request = serde_json::from_str(&ser).unwrap();
+        assert!(matches!(de.params(), RequestParams::Sign(_)));
+        assert_eq!(
+            msg.requests().map(|req| req.tx).collect::<Vec<_>>(),
+            vec![tx.clone(), tx.clone()]
+        );
+        assert!(msg.to_string().starts_with("txids=["));
+
+        let msg = Response {
+            result: ResponseResult::BatchSignResult(cosigner::BatchSignResult {
+                txs: vec![None, Some(tx)],
+            }),
+            id: 975687,
+        };
+        roundtrip!(msg);
+        assert_eq!(msg.result.to_string(), "signed=1/2");
+        let de: Response<ResponseResult> =
+            serde_json::from_str(r#"{"result":{"txs":[null]},"id":1}"#).unwrap();
+        assert_eq!(
+            de.result,
+            ResponseResult::BatchSignResult(cosigner::BatchSignResult { txs: vec![None] })
+        );
+    }
+
+    #[test]
+    fn signed_request() {
+        let secp = Secp256k1::new();
+        let privkey = SecretKey::from_slice(&[42; 32]).unwrap();
+        let msg = coordinator::Sig {
+            pubkey: get_dummy_pubkey(),
+            signature: get_dummy_sig(),
+            id: Txid::default(),
+        };
+        let req = Request::from(msg);
+
+        let signed_req = SignedRequest::sign(&secp, req.clone(), &privkey).unwrap();
+        assert_eq!(signed_req.pubkey, get_dummy_pubkey());
+        signed_req.verify(&secp).unwrap();
+        signed_req.verify_from(&secp, &get_dummy_pubkey()).unwrap();
+        roundtrip!(signed_req);
+        let ser = serde_json::to_string(&signed_req).unwrap();
+        let deser: SignedRequest = serde_json::from_str(&ser).unwrap();
+        deser.verify(&secp).unwrap();
 
-    use super::coordinator;
-    use super::cosigner;
-    use super::watchtower;
+        // Another key isn't accepted
+        let other_pubkey =
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[43; 32]).unwrap());
+        signed_req
+            .verify_from(&secp, &other_pubkey)
+            .expect_err("Not the expected signer");
 
-    fn get_dummy_pubkey() -> PublicKey {
-        let secp_ctx = Secp256k1::new();
-        PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap())
-    }
+        // Any change to the request invalidates the signature
+        let mut tampered_req = signed_req.clone();
+        tampered_req.request = Request::from(coordinator::GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        tampered_req.verify(&secp).expect_err("Tampered request");
+        let mut tampered_req = signed_req;
+        tampered_req.pubkey = other_pubkey;
+        tampered_req.verify(&secp).expect_err("Tampered pubkey");
 
-    fn get_dummy_sig() -> Signature {
-        // from https://github.com/rust-bitcoin/rust-secp256k1/blob/master/src/lib.rs
-        Signature::from_compact(&[
-            0xdc, 0x4d, 0xc2, 0x64, 0xa9, 0xfe, 0xf1, 0x7a, 0x3f, 0x25, 0x34, 0x49, 0xcf, 0x8c,
-            0x39, 0x7a, 0xb6, 0xf1, 0x6f, 0xb3, 0xd6, 0x3d, 0x86, 0x94, 0x0b, 0x55, 0x86, 0x82,
-            0x3d, 0xfd, 0x02, 0xae, 0x3b, 0x46, 0x1b, 0xb4, 0x33, 0x6b, 0x5e, 0xcb, 0xae, 0xfd,
-            0x66, 0x27, 0xaa, 0x92, 0x2e, 0xfc, 0x04, 0x8f, 0xec, 0x0c, 0x88, 0x1c, 0x10, 0xc4,
-            0xc9, 0x42, 0x8f, 0xca, 0x69, 0xc1, 0x32, 0xa2,
-        ])
-        .expect("compact signatures are 64 bytes; DER signatures are 68-72 bytes")
+        // The signature commits to the tag, not to the bare request
+        let untagged = sha256d::Hash::hash(&serde_json::to_vec(&req).unwrap());
+        let untagged_sig = secp.sign(&Message::from_slice(&untagged[..]).unwrap(), &privkey);
+        let untagged_req = SignedRequest {
+            request: req,
+            pubkey: get_dummy_pubkey(),
+            signature: untagged_sig,
+        };
+        untagged_req.verify(&secp).expect_err("Untagged signature");
     }
 
-    fn get_dummy_spend_tx() -> SpendTransaction {
-        let psbt_base64 = "cHNidP8BAGcCAAAAAY74R7yfKjYatj96vo5Ww2nRXnMLqJZ0sJtCZ0vUDJT1AAAAAADNVgAAAoDYAQAAAAAAIgAgrhve44jyE2BUeXInsUqYPSjeKfUi8+vcTiX9K649nlIBAAAAAAAAAAAAAAAAAAEBK6BK9QUAAAAAIgAgGOT4nZS2eDtYm83Cvrva0Ozxmrw4Wjin73s81+Z/MfEBAwQBAAAAAQX9YgJTIQJXWghCPRbOUhpx+hi93OfpK75maJRYRC38QR4f7+NtFiECM9/45YqHN25XccUBgRIDEcbyVEgt7j61+c9r3RZ7FzohAriewns/EcwKUVDvv1bxr790pkzQRzmqfV3dQ9mzBjaQU65kdqkUqOUtXIDgEzokTmljuXvjUVK6PKqIrGt2qRSxhJ72lPFm92bL1zs0fxxSxgvWIIisbJNrdqkUH5eaO3DdSZU5iyaVBAxs4jQpiiaIrGyTa3apFORRbu2KExrgnCCww5w9TraaoolAiKxsk2t2qRTdO8BPO/zd71a6yb+Cns88TZKG84isbJNrdqkU32Y5t5RL0rYBZZvHWmii6eTcgZ+IrGyTa3apFK83DFJxO+ke61QLvGNyYnmSwKrDiKxsk2t2qRQOTi7K/HfcXcC5iBLjCnMWcMWjIYisbJNYh2dYIQLR/ezgE85uXQeHPU/DkO9OMViCc8qtX1GT1B+pC3O4ASECx3y8Y+ejFiUsobbCiYlAU3h87Q7y+QhADwLFygARZXchAiQAGsW+t/RQ0AJ1axuUM9e58WBlzItzzI4xB8sPnMrsIQKnh96esMFOEyF0tbKBXWmAtff+mxSOoyQVefv/JN/vhSEDiQaTfG58TKdD2N4DbB+wCd3Sz04D4Psle+84rmIW51ghAzFWj+Qs+0gWprDMs3Aat9f5wMZuZaZth1AAtHbe2NbxIQL8522r0lMYLHkL+h2yus2uJP8y6N28+cwpWyaTFNnP+CECdjQgoJBQYwTi7KPMwt1RBcdP0KnnWdYNCSkUmtF972hYrwLOVrJoAAEBaVEhAldaCEI9Fs5SGnH6GL3c5+krvmZolFhELfxBHh/v420WIQIz3/jlioc3bldxxQGBEgMRxvJUSC3uPrX5z2vdFnsXOiECuJ7Cez8RzApRUO+/VvGvv3SmTNBHOap9Xd1D2bMGNpBTrgAA";
-        serde_json::from_str(&serde_json::to_string(&psbt_base64).unwrap()).unwrap()
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn signed_request_tx_encoding() {
+        let secp = Secp256k1::new();
+        let privkey = SecretKey::from_slice(&[42; 32]).unwrap();
+        let spend_tx = get_dummy_signed_spend_tx();
+        let deposit_outpoints = spend_tx
+            .clone()
+            .into_psbt()
+            .extract_tx()
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect();
+        let req = Request::from(
+            coordinator::SetSpendTx::from_spend_tx(deposit_outpoints, spend_tx).unwrap(),
+        );
+
+        // Signed and sent as base64, it is verified by a peer reading hex
+        let ser = TxEncoding::Base64.scope(|| {
+            let signed_req = SignedRequest::sign(&secp, req.clone(), &privkey).unwrap();
+            signed_req.verify(&secp).unwrap();
+            serde_json::to_string(&signed_req).unwrap()
+        });
+        let deser: SignedRequest = TxEncoding::Base64.scope(|| serde_json::from_str(&ser).unwrap());
+        deser.verify(&secp).unwrap();
+        assert_eq!(
+            SignedRequest::signature_msg(&req).unwrap(),
+            TxEncoding::Base64.scope(|| SignedRequest::signature_msg(&req).unwrap())
+        );
     }
 
-    macro_rules! roundtrip {
-        ($msg:ident) => {
-            let serialized_msg = serde_json::to_string(&$msg).unwrap();
-            let deserialized_msg = serde_json::from_str(&serialized_msg).unwrap();
-            assert_eq!($msg, deserialized_msg);
-            assert_eq!(
-                serialized_msg,
-                String::from_utf8_lossy(&serde_json::to_vec(&$msg).unwrap())
-            );
-        };
+    fn get_dummy_schnorr_keypair() -> schnorrsig::KeyPair {
+        let secp_ctx = Secp256k1::new();
+        schnorrsig::KeyPair::from_seckey_slice(&secp_ctx, &[42; 32]).unwrap()
     }
 
-    macro_rules! assert_str_ser {
-        ($msg:ident, $str:expr) => {
-            let ser = serde_json::to_string(&$msg).unwrap();
-            assert_eq!(ser, $str);
-        };
+    fn get_dummy_schnorr_sig() -> schnorrsig::Signature {
+        let secp_ctx = Secp256k1::new();
+        let msg = Message::from_slice(&[1; 32]).unwrap();
+        secp_ctx.schnorrsig_sign_no_aux_rand(&msg, &get_dummy_schnorr_keypair())
     }
 
     #[test]
-    fn serde_watchtower_sig() {
-        let pubkey: PublicKey = get_dummy_pubkey();
-        let sig: Signature = get_dummy_sig();
-        let signatures: BTreeMap<PublicKey, Signature> = [(pubkey, sig)].iter().cloned().collect();
-        let txid = Txid::default();
+    fn serde_schnorr_sigs() {
+        let secp_ctx = Secp256k1::new();
+        let pubkey = schnorrsig::PublicKey::from_keypair(&secp_ctx, &get_dummy_schnorr_keypair());
+        let sig = get_dummy_schnorr_sig();
+        let signatures: BTreeMap<schnorrsig::PublicKey, schnorrsig::Signature> =
+            [(pubkey, sig)].iter().cloned().collect();
         let deposit_outpoint = OutPoint::from_str(
             "3694ef9e8fcd78e9b8165a41e6f5e2b5f10bcd92c6d6e42b3325a850df56cd83:0",
         )
         .unwrap();
-        let msg = watchtower::Sig {
-            signatures,
-            txid,
+
+        // The watchtower message
+        let msg = watchtower::SchnorrSig {
+            signatures: signatures.clone(),
+            txid: Txid::default(),
             deposit_outpoint,
         };
-        let req = Request::from(msg);
+        let req = Request::from(msg.clone());
         roundtrip!(req);
-        assert_str_ser!(
-            req,
-            format!("{{\"method\":\"sig\",\"params\":{{\"signatures\":{{\"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c\":\"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2\"}},\"txid\":\"0000000000000000000000000000000000000000000000000000000000000000\",\"deposit_outpoint\":\"3694ef9e8fcd78e9b8165a41e6f5e2b5f10bcd92c6d6e42b3325a850df56cd83:0\"}},\"id\":{}}}", req.id())
-            );
-    }
+        let ser = serde_json::to_string(&req).unwrap();
+        assert_eq!(
+            ser,
+            format!("{{\"method\":\"sig\",\"params\":{{\"signatures\":{{\"{}\":\"{}\"}},\"txid\":\"0000000000000000000000000000000000000000000000000000000000000000\",\"deposit_outpoint\":\"3694ef9e8fcd78e9b8165a41e6f5e2b5f10bcd92c6d6e42b3325a850df56cd83:0\"}},\"id\":{}}}", pubkey, sig, req.id())
+        );
+        // It's not mistaken for an ECDSA one
+        assert_eq!(req.params(), RequestParams::WtSchnorrSig(msg));
 
-    #[test]
-    fn serde_watchtower_sig_ack() {
-        let ack = true;
-        let txid = Txid::default();
+        // The coordinator messages
+        let msg = coordinator::SchnorrSig {
+            pubkey,
+            signature: sig,
+            id: Txid::default(),
+        };
+        let req = Request::from(msg.clone());
+        roundtrip!(req);
+        assert_eq!(req.params(), RequestParams::CoordSchnorrSig(msg));
         let msg = Response {
-            result: ResponseResult::WtSig(watchtower::SigResult { ack, txid }),
-            id: 1946,
+            result: ResponseResult::SchnorrSigs(coordinator::SchnorrSigs { signatures }),
+            id: 0,
         };
         roundtrip!(msg);
-        assert_str_ser!(
-            msg,
-            r#"{"result":{"ack":true,"txid":"0000000000000000000000000000000000000000000000000000000000000000"},"id":1946}"#
-        );
     }
 
     #[test]
-    fn serde_watchtower_get_spend_tx() {
-        let msg = coordinator::GetSpendTx {
-            deposit_outpoint: OutPoint::from_str(
-                "6a276a96807dd45ceed9cbd6fd48b5edf185623b23339a1643e19e8dcbf2e474:0",
-            )
-            .unwrap(),
+    fn serde_musig() {
+        let pubkey = get_dummy_pubkey();
+        let txid = Txid::default();
+
+        let nonce = coordinator::MusigPubNonce([3; 66]);
+        let msg = coordinator::MusigNonce {
+            txid,
+            pubkey,
+            nonce,
         };
-        let req = Request::from(msg);
+        let req = Request::from(msg.clone());
         roundtrip!(req);
         assert_str_ser!(
             req,
-            format!("{{\"method\":\"get_spend_tx\",\"params\":{{\"deposit_outpoint\":\"6a276a96807dd45ceed9cbd6fd48b5edf185623b23339a1643e19e8dcbf2e474:0\"}},\"id\":{}}}", req.id()
+            format!("{{\"method\":\"musig_nonce\",\"params\":{{\"txid\":\"0000000000000000000000000000000000000000000000000000000000000000\",\"pubkey\":\"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c\",\"nonce\":\"{}\"}},\"id\":{}}}", "03".repeat(66), req.id()
         ));
+        assert_eq!(req.params(), RequestParams::MusigNonce(msg));
+
+        let partial_sig = coordinator::MusigPartialSignature([7; 32]);
+        let msg = coordinator::MusigPartialSig {
+            txid,
+            pubkey,
+            partial_sig,
+        };
+        let req = Request::from(msg.clone());
+        roundtrip!(req);
+        assert_eq!(req.params(), RequestParams::MusigPartialSig(msg));
+
+        let msg = coordinator::GetMusigSession { txid };
+        let req = Request::from(msg.clone());
+        roundtrip!(req);
+        assert_eq!(req.params(), RequestParams::GetMusigSession(msg));
 
-        // Response
         let msg = Response {
-            result: ResponseResult::SpendTx(coordinator::SpendTx {
-                transaction: get_dummy_spend_tx().into_psbt().extract_tx(),
+            result: ResponseResult::MusigSession(coordinator::MusigSession {
+                nonces: [(pubkey, nonce)].iter().cloned().collect(),
+                partial_sigs: [(pubkey, partial_sig)].iter().cloned().collect(),
             }),
             id: 0,
         };
-        eprintln!("{}", get_dummy_spend_tx().hex());
         roundtrip!(msg);
-        assert_str_ser!(
-            msg,
-            r#"{"result":{"transaction":"02000000018ef847bc9f2a361ab63f7abe8e56c369d15e730ba89674b09b42674bd40c94f50000000000cd5600000280d8010000000000220020ae1bdee388f2136054797227b14a983d28de29f522f3ebdc4e25fd2bae3d9e5201000000000000000000000000"},"id":0}"#
-        );
+
+        // Invalid sizes are refused
+        serde_json::from_str::<coordinator::MusigPubNonce>(&format!("\"{}\"", "03".repeat(65)))
+            .unwrap_err();
+        serde_json::from_str::<coordinator::MusigPartialSignature>("\"0707\"").unwrap_err();
     }
 
     #[test]
-    fn serde_server_sig() {
-        let pubkey = get_dummy_pubkey();
-        let signature = get_dummy_sig();
-        let id = Txid::default();
+    fn serde_subscriptions() {
+        let txid = Txid::default();
+        let deposit_outpoint = OutPoint::from_str(
+            "6a276a96807dd45ceed9cbd6fd48b5edf185623b23339a1643e19e8dcbf2e474:0",
+        )
+        .unwrap();
 
-        let msg = coordinator::Sig {
-            pubkey,
-            signature,
-            id,
+        let msg = coordinator::Subscribe {
+            txids: vec![txid],
+            deposit_outpoints: vec![deposit_outpoint],
         };
-        let req = Request::from(msg);
+        let req = Request::from(msg.clone());
         roundtrip!(req);
         assert_str_ser!(
             req,
-            format!("{{\"method\":\"sig\",\"params\":{{\"pubkey\":\"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c\",\"signature\":\"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2\",\"id\":\"0000000000000000000000000000000000000000000000000000000000000000\"}},\"id\":{}}}", req.id()
+            format!("{{\"method\":\"subscribe\",\"params\":{{\"txids\":[\"0000000000000000000000000000000000000000000000000000000000000000\"],\"deposit_outpoints\":[\"6a276a96807dd45ceed9cbd6fd48b5edf185623b23339a1643e19e8dcbf2e474:0\"]}},\"id\":{}}}", req.id()
         ));
-
-        let resp = Response {
-            result: ResponseResult::Sig(coordinator::SigResult { ack: true }),
+        assert_eq!(req.params(), RequestParams::Subscribe(msg));
+        let msg = Response {
+            result: ResponseResult::Subscribed(coordinator::SubscribeResult {
+                subscription_id: 12,
+            }),
             id: 0,
         };
-        assert_str_ser!(resp, r#"{"result":{"ack":true},"id":0}"#);
-        let resp = Response {
-            result: ResponseResult::Sig(coordinator::SigResult { ack: false }),
-            id: 988364,
-        };
-        assert_str_ser!(resp, r#"{"result":{"ack":false},"id":988364}"#);
-    }
+        roundtrip!(msg);
+        assert_str_ser!(msg, r#"{"result":{"subscription_id":12},"id":0}"#);
 
-    #[test]
-    fn serde_server_get_sigs() {
-        let id = Txid::default();
-        let msg = coordinator::GetSigs { id };
-        let req = Request::from(msg);
+        let msg = coordinator::Unsubscribe {
+            subscription_id: 12,
+        };
+        let req = Request::from(msg.clone());
         roundtrip!(req);
+        assert_eq!(req.params(), RequestParams::Unsubscribe(msg));
+
+        let msg = coordinator::NewSigEvent {
+            subscription_id: 12,
+            pubkey: get_dummy_pubkey(),
+            signature: get_dummy_sig(),
+            id: txid,
+        };
+        let notif = Notification::from(msg.clone());
+        roundtrip!(notif);
         assert_str_ser!(
-            req,
-            format!("{{\"method\":\"get_sigs\",\"params\":{{\"id\":\"0000000000000000000000000000000000000000000000000000000000000000\"}},\"id\":{}}}", req.id()
-        ));
+            notif,
+            r#"{"method":"new_sig","params":{"subscription_id":12,"pubkey":"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c","signature":"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2","id":"0000000000000000000000000000000000000000000000000000000000000000"}}"#
+        );
+        assert_eq!(notif.params(), NotificationParams::NewSig(msg));
+
+        let msg = coordinator::NewSpendTxEvent {
+            subscription_id: 12,
+            deposit_outpoint,
+            transaction: get_dummy_raw_tx(),
+        };
+        let notif = Notification::from(msg.clone());
+        roundtrip!(notif);
+        assert_eq!(notif.params(), NotificationParams::NewSpendTx(msg));
     }
 
     #[test]
-    fn serde_server_sigs() {
-        let pubkey: PublicKey = get_dummy_pubkey();
-        let sig = get_dummy_sig();
-        let signatures = [(pubkey, sig)].iter().cloned().collect();
+    fn serde_replication() {
+        let txid = Txid::default();
+        let deposit_outpoint = OutPoint::from_str(
+            "6a276a96807dd45ceed9cbd6fd48b5edf185623b23339a1643e19e8dcbf2e474:0",
+        )
+        .unwrap();
+        let sig_entry = replication::Entry::Sig {
+            txid,
+            pubkey: get_dummy_pubkey(),
+            signature: get_dummy_sig(),
+        };
+        let spend_entry = replication::Entry::SpendTx(
+            coordinator::SetSpendTx::from_transaction(vec![deposit_outpoint], get_dummy_raw_tx())
+                .unwrap(),
+        );
 
-        // With signatures
+        let msg = replication::Replicate {
+            entries: vec![sig_entry.clone(), spend_entry.clone()],
+        };
+        let req = Request::from(msg.clone());
+        roundtrip!(req);
+        assert_eq!(req.params(), RequestParams::Replicate(msg));
         let msg = Response {
-            result: ResponseResult::Sigs(coordinator::Sigs { signatures }),
+            result: ResponseResult::Replicated(replication::ReplicateResult { new_entries: 1 }),
+            id: 0,
+        };
+        roundtrip!(msg);
+        assert_str_ser!(msg, r#"{"result":{"new_entries":1},"id":0}"#);
+
+        let msg = replication::GetEntries { since: 0 };
+        let req = Request::from(msg.clone());
+        roundtrip!(req);
+        assert_str_ser!(
+            req,
+            format!(
+                r#"{{"method":"get_entries","params":{{"since":0}},"id":{}}}"#,
+                req.id()
+            )
+        );
+        assert_eq!(req.params(), RequestParams::GetEntries(msg));
+        let msg = Response {
+            result: ResponseResult::Entries(replication::Entries {
+                entries: vec![sig_entry],
+                cursor: 1,
+            }),
             id: 0,
         };
         roundtrip!(msg);
         assert_str_ser!(
             msg,
-            r#"{"result":{"signatures":{"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c":"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2"}},"id":0}"#
+            r#"{"result":{"entries":[{"txid":"0000000000000000000000000000000000000000000000000000000000000000","pubkey":"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c","signature":"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2"}],"cursor":1},"id":0}"#
         );
-
-        // Without signatures
-        let signatures = BTreeMap::new();
         let msg = Response {
-            result: ResponseResult::Sigs(coordinator::Sigs { signatures }),
-            id: 2234,
+            result: ResponseResult::Entries(replication::Entries {
+                entries: vec![spend_entry],
+                cursor: 2,
+            }),
+            id: 0,
         };
         roundtrip!(msg);
-        assert_str_ser!(msg, r#"{"result":{"signatures":{}},"id":2234}"#);
     }
 
     #[test]
-    fn serde_server_request_spend() {
-        let deposit_outpoints = vec![OutPoint::from_str(
-            "6e4977728e7100db80c30751f27cf834b7a1e02d083a4338874e48d1f3694446:0",
+    fn chunked_spend_tx() {
+        let mut transaction = get_dummy_raw_tx();
+        let input = transaction.input[0].clone();
+        transaction.input = (0..600)
+            .map(|vout| TxIn {
+                previous_output: OutPoint {
+                    vout,
+                    ..input.previous_output
+                },
+                ..input.clone()
+            })
+            .collect();
+        let first = coordinator::SpendTxChunk::of(Some(&transaction), 0);
+        assert!(first.chunk_count > 1);
+        assert_eq!(first.data.0.len(), coordinator::MAX_CHUNK_SIZE);
+        assert_eq!(first.sha256, None);
+
+        let mut chunks = coordinator::SpendTxChunks::new();
+        let mut reassembled = None;
+        for index in 0..first.chunk_count {
+            let chunk = coordinator::SpendTxChunk::of(Some(&transaction), index);
+            roundtrip!(chunk);
+            assert_eq!(chunks.next_index(), index);
+            reassembled = chunks.push(chunk).unwrap();
+        }
+        assert_eq!(reassembled, Some(Some(transaction.clone())));
+
+        // Out of order, or tampered with
+        let mut chunks = coordinator::SpendTxChunks::new();
+        let second = coordinator::SpendTxChunk::of(Some(&transaction), 1);
+        assert_eq!(
+            chunks.push(second.clone()),
+            Err(MessageError::UnexpectedChunk {
+                expected: 0,
+                got: 1
+            })
+        );
+        let mut tampered = first;
+        tampered.data.0[0] ^= 1;
+        chunks.push(tampered).unwrap();
+        assert_eq!(chunks.push(second), Err(MessageError::CorruptedDownload));
+
+        // No Spend transaction
+        let none = coordinator::SpendTxChunk::of(None, 0);
+        assert_eq!(none.chunk_count, 0);
+        assert_eq!(coordinator::SpendTxChunks::new().push(none), Ok(Some(None)));
+
+        // Past the last index, or announcing an unbounded download
+        let past = coordinator::SpendTxChunk::of(Some(&transaction), u32::MAX);
+        assert!(past.data.0.is_empty());
+        assert_eq!(past.sha256, None);
+        let endless = coordinator::SpendTxChunk {
+            chunk_index: 0,
+            chunk_count: u32::MAX,
+            data: coordinator::ChunkData(vec![0; coordinator::MAX_CHUNK_SIZE]),
+            sha256: None,
+        };
+        assert!(matches!(
+            coordinator::SpendTxChunks::new().push(endless),
+            Err(MessageError::TooLargeDownload { .. })
+        ));
+    }
+
+    #[test]
+    fn serde_error_response() {
+        let resp = ErrorResponse {
+            error: ResponseError::new(ErrorCode::AccessDenied, "Not allowed"),
+            id: 42,
+        };
+        roundtrip!(resp);
+        assert_str_ser!(
+            resp,
+            r#"{"error":{"code":-32001,"message":"Not allowed"},"id":42}"#
+        );
+        // An error response is not a response
+        assert!(serde_json::from_str::<Response<serde_json::Value>>(
+            &serde_json::to_string(&resp).unwrap()
         )
-        .unwrap()];
-        let signed_spend_tx = SpendTransaction::from_psbt_str("cHNidP8BAOICAAAABCqeuW7WKzo1iD/mMt74WOi4DJRupF8Ys2QTjf4U3NcOAAAAAABe0AAAOjPsA68jDPWuRjwrZF8AN1O/sG2oB7AriUKJMsrPqiMBAAAAAF7QAAAdmwWqMhBuu2zxKu+hEVxUG2GEeql4I6BL5Ld3QL/K/AAAAAAAXtAAAOEKg+2uhHsUgQDxZt3WVCjfgjKELfnCbE7VhDEwBNxxAAAAAABe0AAAAgBvAgAAAAAAIgAgKjuiJEE1EeX8hEfJEB1Hfi+V23ETrp/KCx74SqwSLGBc9sMAAAAAAAAAAAAAAAEBK4iUAwAAAAAAIgAgRAzbIqFTxU8vRmZJTINVkIFqQsv6nWgsBrqsPSo3yg4BCP2IAQUASDBFAiEAo2IX4SPeqXGdu8cEB13BkfCDk1N+kf8mMOrwx6uJZ3gCIHYEspD4EUjt+PM8D4T5qtE5GjUT56aH9yEmf8SCR63eAUcwRAIgVdpttzz0rxS/gpSTPcG3OIQcLWrTcSFc6vthcBrBTZQCIDYm952TZ644IEETblK7N434NrFql7ccFTM7+jUj+9unAUgwRQIhALKhtFWbyicZtKuqfBcjKfl7GY1e2i2UTSS2hMtCKRIyAiA410YD546ONeAq2+CPk86Q1dQHUIRj+OQl3dmKvo/aFwGrIQPazx7E2MqqusRekjfgnWmq3OG4lF3MR3b+c/ufTDH3pKxRh2R2qRRZT2zQxRaHYRlox31j9A8EIu4mroisa3apFH7IHjHORqjFOYgmE+5URE+rT+iiiKxsk1KHZ1IhAr+ZWb/U4iUT5Vu1kF7zoqKfn5JK2wDGJ/0dkrZ/+c+UIQL+mr8QPqouEYAyh3QmEVU4Dv9BaheeYbCkvpmryviNm1KvA17QALJoAAEBKyBSDgAAAAAAIgAgRAzbIqFTxU8vRmZJTINVkIFqQsv6nWgsBrqsPSo3yg4BCP2GAQUARzBEAiAZR0TO1PRje6KzUb0lYmMuk6DjnMCHcCUU/Ct/otpMCgIgcAgD7H5oGx6jG2RjcRkS3HC617v1C58+BjyUKowb/nIBRzBEAiAhYwZTODb8zAjwfNjt5wL37yg1OZQ9wQuTV2iS7YByFwIgGb008oD3RXgzE3exXLDzGE0wst24ft15oLxj2xeqcmsBRzBEAiA6JMEwOeGlq92NItxEA2tBW5akps9EkUX1vMiaSM8yrwIgUsaiU94sOOQf/5zxb0hpp44HU17FgGov8/mFy3mT++IBqyED2s8exNjKqrrEXpI34J1pqtzhuJRdzEd2/nP7n0wx96SsUYdkdqkUWU9s0MUWh2EZaMd9Y/QPBCLuJq6IrGt2qRR+yB4xzkaoxTmIJhPuVERPq0/oooisbJNSh2dSIQK/mVm/1OIlE+VbtZBe86Kin5+SStsAxif9HZK2f/nPlCEC/pq/ED6qLhGAMod0JhFVOA7/QWoXnmGwpL6Zq8r4jZtSrwNe0ACyaAABAStEygEAAAAAACIAIEQM2yKhU8VPL0ZmSUyDVZCBakLL+p1oLAa6rD0qN8oOAQj9iAEFAEgwRQIhAL6mDIPbQZc8Y51CzTUl7+grFUVr+6CpBPt3zLio4FTLAiBkmNSnd8VvlD84jrDx12Xug5XRwueBSG0N1PBwCtyPCQFHMEQCIFLryPMdlr0XLySRzYWw75tKofJAjhhXgc1XpVDXtPRjAiBp+eeNA5Zl1aU8E3UtFxnlZ5KMRlIZpkqn7lvIlXi0rQFIMEUCIQCym/dSaqtfrTb3fs1ig1KvwS0AwyoHR62R3WGq52fk0gIgI/DAQO6EyvZT1UHYtfGsZHLlIZkFYRLZnTpznle/qsUBqyED2s8exNjKqrrEXpI34J1pqtzhuJRdzEd2/nP7n0wx96SsUYdkdqkUWU9s0MUWh2EZaMd9Y/QPBCLuJq6IrGt2qRR+yB4xzkaoxTmIJhPuVERPq0/oooisbJNSh2dSIQK/mVm/1OIlE+VbtZBe86Kin5+SStsAxif9HZK2f/nPlCEC/pq/ED6qLhGAMod0JhFVOA7/QWoXnmGwpL6Zq8r4jZtSrwNe0ACyaAABASuQArMAAAAAACIAIEQM2yKhU8VPL0ZmSUyDVZCBakLL+p1oLAa6rD0qN8oOAQj9iQEFAEgwRQIhAK8fSyw0VbBElw6L9iyedbSz6HtbrHrzs+M6EB4+6+1yAiBMN3s3ZKff7Msvgq8yfrI9v0CK5IKEoacgb0PcBKCzlwFIMEUCIQDyIe5RXWOu8PJ1Rbc2Nn0NGuPORDO4gYaGWH3swEixzAIgU2/ft0cNzSjbgT0O/MKss2Sk0e7OevzclRBSWZP3SHQBSDBFAiEA+spp4ejHuWnwymZqNYaTtrrFC5wCw3ItwtJ6DMxmRWMCIAbOYDm/yuiijXSz1YTDdyO0Zpg6TAzLY1kd90GFhQpRAashA9rPHsTYyqq6xF6SN+Cdaarc4biUXcxHdv5z+59MMfekrFGHZHapFFlPbNDFFodhGWjHfWP0DwQi7iauiKxrdqkUfsgeMc5GqMU5iCYT7lRET6tP6KKIrGyTUodnUiECv5lZv9TiJRPlW7WQXvOiop+fkkrbAMYn/R2Stn/5z5QhAv6avxA+qi4RgDKHdCYRVTgO/0FqF55hsKS+mavK+I2bUq8DXtAAsmgAAQElIQPazx7E2MqqusRekjfgnWmq3OG4lF3MR3b+c/ufTDH3pKxRhwAA").unwrap();
-        let msg = coordinator::SetSpendTx::from_spend_tx(deposit_outpoints, signed_spend_tx);
-        let req = Request::from(msg);
-        roundtrip!(req);
+        .is_err());
+
+        // Unknown codes are preserved
+        let resp: ErrorResponse =
+            serde_json::from_str(r#"{"error":{"code":12,"message":""},"id":1}"#).unwrap();
+        assert_eq!(resp.error.code, ErrorCode::Other(12));
+        roundtrip!(resp);
+        for code in &[
+            ErrorCode::ParseError,
+            ErrorCode::InvalidRequest,
+            ErrorCode::MethodNotFound,
+            ErrorCode::InvalidParams,
+            ErrorCode::InternalError,
+            ErrorCode::AccessDenied,
+            ErrorCode::Conflict,
+            ErrorCode::ServerBusy,
+            ErrorCode::QuotaExceeded,
+            ErrorCode::StorageFull,
+        ] {
+            assert_eq!(ErrorCode::from_code(code.code()), *code);
+        }
+
+        let json_err = serde_json::from_str::<coordinator::GetSigs>("{").unwrap_err();
+        assert_eq!(
+            ErrorCode::from(&Error::from(json_err)),
+            ErrorCode::ParseError
+        );
+        let json_err = serde_json::from_str::<coordinator::GetSigs>("{}").unwrap_err();
+        assert_eq!(
+            ErrorCode::from(&Error::from(json_err)),
+            ErrorCode::InvalidParams
+        );
+        let err = Error::from(MessageError::ConflictingSignature(get_dummy_pubkey()));
+        assert_eq!(ResponseError::from(&err).code, ErrorCode::Conflict);
+        assert_eq!(err.retry_after(), None);
+
+        // Capacity errors may hint at when to retry
+        let resp = ErrorResponse {
+            error: ResponseError::quota_exceeded(
+                "Too many Spend",
+                Some(Duration::from_millis(1_500)),
+            ),
+            id: 3,
+        };
+        roundtrip!(resp);
         assert_str_ser!(
-            req,
-            format!("{{\"method\":\"set_spend_tx\",\"params\":{{\"deposit_outpoints\":[\"6e4977728e7100db80c30751f27cf834b7a1e02d083a4338874e48d1f3694446:0\"],\"transaction\":\"020000000001042a9eb96ed62b3a35883fe632def858e8b80c946ea45f18b364138dfe14dcd70e00000000005ed000003a33ec03af230cf5ae463c2b645f003753bfb06da807b02b89428932cacfaa2301000000005ed000001d9b05aa32106ebb6cf12aefa1115c541b61847aa97823a04be4b77740bfcafc00000000005ed00000e10a83edae847b148100f166ddd65428df8232842df9c26c4ed584313004dc7100000000005ed0000002006f0200000000002200202a3ba224413511e5fc8447c9101d477e2f95db7113ae9fca0b1ef84aac122c605cf6c30000000000000500483045022100a36217e123dea9719dbbc704075dc191f08393537e91ff2630eaf0c7ab89677802207604b290f81148edf8f33c0f84f9aad1391a3513e7a687f721267fc48247adde01473044022055da6db73cf4af14bf8294933dc1b738841c2d6ad371215ceafb61701ac14d9402203626f79d9367ae382041136e52bb378df836b16a97b71c15333bfa3523fbdba701483045022100b2a1b4559bca2719b4abaa7c172329f97b198d5eda2d944d24b684cb42291232022038d74603e78e8e35e02adbe08f93ce90d5d407508463f8e425ddd98abe8fda1701ab2103dacf1ec4d8caaabac45e9237e09d69aadce1b8945dcc4776fe73fb9f4c31f7a4ac51876476a914594f6cd0c51687611968c77d63f40f0422ee26ae88ac6b76a9147ec81e31ce46a8c539882613ee54444fab4fe8a288ac6c93528767522102bf9959bfd4e22513e55bb5905ef3a2a29f9f924adb00c627fd1d92b67ff9cf942102fe9abf103eaa2e1180328774261155380eff416a179e61b0a4be99abcaf88d9b52af035ed000b26805004730440220194744ced4f4637ba2b351bd2562632e93a0e39cc087702514fc2b7fa2da4c0a0220700803ec7e681b1ea31b6463711912dc70bad7bbf50b9f3e063c942a8c1bfe72014730440220216306533836fccc08f07cd8ede702f7ef283539943dc10b93576892ed807217022019bd34f280f74578331377b15cb0f3184d30b2ddb87edd79a0bc63db17aa726b0147304402203a24c13039e1a5abdd8d22dc44036b415b96a4a6cf449145f5bcc89a48cf32af022052c6a253de2c38e41fff9cf16f4869a78e07535ec5806a2ff3f985cb7993fbe201ab2103dacf1ec4d8caaabac45e9237e09d69aadce1b8945dcc4776fe73fb9f4c31f7a4ac51876476a914594f6cd0c51687611968c77d63f40f0422ee26ae88ac6b76a9147ec81e31ce46a8c539882613ee54444fab4fe8a288ac6c93528767522102bf9959bfd4e22513e55bb5905ef3a2a29f9f924adb00c627fd1d92b67ff9cf942102fe9abf103eaa2e1180328774261155380eff416a179e61b0a4be99abcaf88d9b52af035ed000b2680500483045022100bea60c83db41973c639d42cd3525efe82b15456bfba0a904fb77ccb8a8e054cb02206498d4a777c56f943f388eb0f1d765ee8395d1c2e781486d0dd4f0700adc8f0901473044022052ebc8f31d96bd172f2491cd85b0ef9b4aa1f2408e185781cd57a550d7b4f463022069f9e78d039665d5a53c13752d1719e567928c465219a64aa7ee5bc89578b4ad01483045022100b29bf7526aab5fad36f77ecd628352afc12d00c32a0747ad91dd61aae767e4d2022023f0c040ee84caf653d541d8b5f1ac6472e52199056112d99d3a739e57bfaac501ab2103dacf1ec4d8caaabac45e9237e09d69aadce1b8945dcc4776fe73fb9f4c31f7a4ac51876476a914594f6cd0c51687611968c77d63f40f0422ee26ae88ac6b76a9147ec81e31ce46a8c539882613ee54444fab4fe8a288ac6c93528767522102bf9959bfd4e22513e55bb5905ef3a2a29f9f924adb00c627fd1d92b67ff9cf942102fe9abf103eaa2e1180328774261155380eff416a179e61b0a4be99abcaf88d9b52af035ed000b2680500483045022100af1f4b2c3455b044970e8bf62c9e75b4b3e87b5bac7af3b3e33a101e3eebed7202204c377b3764a7dfeccb2f82af327eb23dbf408ae48284a1a7206f43dc04a0b39701483045022100f221ee515d63aef0f27545b736367d0d1ae3ce4433b8818686587decc048b1cc0220536fdfb7470dcd28db813d0efcc2acb364a4d1eece7afcdc9510525993f7487401483045022100faca69e1e8c7b969f0ca666a358693b6bac50b9c02c3722dc2d27a0ccc664563022006ce6039bfcae8a28d74b3d584c37723b466983a4c0ccb63591df74185850a5101ab2103dacf1ec4d8caaabac45e9237e09d69aadce1b8945dcc4776fe73fb9f4c31f7a4ac51876476a914594f6cd0c51687611968c77d63f40f0422ee26ae88ac6b76a9147ec81e31ce46a8c539882613ee54444fab4fe8a288ac6c93528767522102bf9959bfd4e22513e55bb5905ef3a2a29f9f924adb00c627fd1d92b67ff9cf942102fe9abf103eaa2e1180328774261155380eff416a179e61b0a4be99abcaf88d9b52af035ed000b26800000000\"}},\"id\":{}}}", req.id()
-        ));
+            resp,
+            r#"{"error":{"code":-32004,"message":"Too many Spend","data":{"retry_after":2}},"id":3}"#
+        );
+        assert!(resp.error.is_capacity());
+        let err = Error::Remote(resp.error);
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(2)));
+        let full = ResponseError::storage_full("Full", None);
+        assert_eq!(full.code, ErrorCode::StorageFull);
+        assert_eq!(full.data, None);
+        assert!(!ResponseError::new(ErrorCode::Conflict, "").is_capacity());
+        assert_eq!(
+            ErrorCode::from(&Error::Timeout(None)),
+            ErrorCode::InternalError
+        );
+    }
 
-        let response = Response {
-            result: ResponseResult::SetSpend(coordinator::SetSpendResult { ack: true }),
-            id: 0,
+    #[test]
+    fn redacted_debug() {
+        let msg = watchtower::Sig {
+            signatures: [(get_dummy_pubkey(), get_dummy_sig())]
+                .iter()
+                .cloned()
+                .collect(),
+            txid: Txid::default(),
+            deposit_outpoint: OutPoint::from_str(
+                "3694ef9e8fcd78e9b8165a41e6f5e2b5f10bcd92c6d6e42b3325a850df56cd83:0",
+            )
+            .unwrap(),
         };
-        assert_str_ser!(response, r#"{"result":{"ack":true},"id":0}"#);
-        let response = Response {
-            result: ResponseResult::SetSpend(coordinator::SetSpendResult { ack: false }),
-            id: u32::MAX,
+        let req: Request = msg.into();
+        assert_eq!(
+            req.redacted().to_string(),
+            format!("{{\"id\":{},\"method\":\"sig\",\"params\":{{\"deposit_outpoint\":\"3694ef9e8fcd78e9b8165a41e6f5e2b5f10bcd92c6d6e42b3325a850df56cd83:0\",\"signatures\":{{\"035be5e9..(66 chars)\":\"30450221..(142 chars)\"}},\"txid\":\"0000000000000000000000000000000000000000000000000000000000000000\"}}}}", req.id())
+        );
+        assert_eq!(format!("{:?}", req.redacted()), req.redacted().to_string());
+
+        #[cfg(feature = "revault_tx")]
+        {
+            let msg = cosigner::SignRequest::new(spend_tx());
+            assert!(msg.redacted().to_string().len() < 64);
+        }
+    }
+
+    #[test]
+    fn id_generators() {
+        let get_sigs = || -> Request {
+            coordinator::GetSigs {
+                id: Txid::default(),
+                if_none_match: None,
+            }
+            .into()
         };
-        assert_str_ser!(response, r#"{"result":{"ack":false},"id":4294967295}"#);
+
+        let ids = with_id_generator(SequentialIds(41), || {
+            let ids = [get_sigs().id(), get_sigs().id()];
+            // Scopes nest
+            let inner = with_id_generator(SequentialIds(0), || get_sigs().id());
+            assert_eq!(inner, 0);
+            let req = get_sigs();
+            assert_str_ser!(
+                req,
+                r#"{"method":"get_sigs","params":{"id":"0000000000000000000000000000000000000000000000000000000000000000"},"id":43}"#
+            );
+            ids
+        });
+        assert_eq!(ids, [41, 42]);
+
+        let seeded = || with_id_generator(SeededIds::new(7), || [get_sigs().id(), get_sigs().id()]);
+        assert_eq!(seeded(), seeded());
+        assert_ne!(seeded()[0], seeded()[1]);
     }
 
     #[test]
-    fn serde_cosigner_sign() {
-        let tx = get_dummy_spend_tx();
-        let msg = cosigner::SignRequest { tx };
-        let req = Request::from(msg);
-        roundtrip!(req);
-        assert_str_ser!(
-            req,
-            format!("{{\"method\":\"sign\",\"params\":{{\"tx\":\"cHNidP8BAGcCAAAAAY74R7yfKjYatj96vo5Ww2nRXnMLqJZ0sJtCZ0vUDJT1AAAAAADNVgAAAoDYAQAAAAAAIgAgrhve44jyE2BUeXInsUqYPSjeKfUi8+vcTiX9K649nlIBAAAAAAAAAAAAAAAAAAEBK6BK9QUAAAAAIgAgGOT4nZS2eDtYm83Cvrva0Ozxmrw4Wjin73s81+Z/MfEBAwQBAAAAAQX9YgJTIQJXWghCPRbOUhpx+hi93OfpK75maJRYRC38QR4f7+NtFiECM9/45YqHN25XccUBgRIDEcbyVEgt7j61+c9r3RZ7FzohAriewns/EcwKUVDvv1bxr790pkzQRzmqfV3dQ9mzBjaQU65kdqkUqOUtXIDgEzokTmljuXvjUVK6PKqIrGt2qRSxhJ72lPFm92bL1zs0fxxSxgvWIIisbJNrdqkUH5eaO3DdSZU5iyaVBAxs4jQpiiaIrGyTa3apFORRbu2KExrgnCCww5w9TraaoolAiKxsk2t2qRTdO8BPO/zd71a6yb+Cns88TZKG84isbJNrdqkU32Y5t5RL0rYBZZvHWmii6eTcgZ+IrGyTa3apFK83DFJxO+ke61QLvGNyYnmSwKrDiKxsk2t2qRQOTi7K/HfcXcC5iBLjCnMWcMWjIYisbJNYh2dYIQLR/ezgE85uXQeHPU/DkO9OMViCc8qtX1GT1B+pC3O4ASECx3y8Y+ejFiUsobbCiYlAU3h87Q7y+QhADwLFygARZXchAiQAGsW+t/RQ0AJ1axuUM9e58WBlzItzzI4xB8sPnMrsIQKnh96esMFOEyF0tbKBXWmAtff+mxSOoyQVefv/JN/vhSEDiQaTfG58TKdD2N4DbB+wCd3Sz04D4Psle+84rmIW51ghAzFWj+Qs+0gWprDMs3Aat9f5wMZuZaZth1AAtHbe2NbxIQL8522r0lMYLHkL+h2yus2uJP8y6N28+cwpWyaTFNnP+CECdjQgoJBQYwTi7KPMwt1RBcdP0KnnWdYNCSkUmtF972hYrwLOVrJoAAEBaVEhAldaCEI9Fs5SGnH6GL3c5+krvmZolFhELfxBHh/v420WIQIz3/jlioc3bldxxQGBEgMRxvJUSC3uPrX5z2vdFnsXOiECuJ7Cez8RzApRUO+/VvGvv3SmTNBHOap9Xd1D2bMGNpBTrgAA\"}},\"id\":{}}}", req.id()
-        ));
+    fn display_summaries() {
+        let txid =
+            Txid::from_str("cafebabe00000000000000000000000000000000000000000000000000000000")
+                .unwrap();
+        let deposit_outpoint = OutPoint { txid, vout: 1 };
+        let mut signatures = SigSet::new();
+        signatures
+            .insert(get_dummy_pubkey(), get_dummy_sig())
+            .unwrap();
 
-        let msg = Response {
-            result: ResponseResult::SignResult(cosigner::SignResult { tx: None }),
-            id: 975687,
+        let req: Request = with_id_generator(SequentialIds(7), || {
+            watchtower::Sig {
+                signatures: signatures.clone(),
+                txid,
+                deposit_outpoint,
+            }
+            .into()
+        });
+        assert_eq!(
+            req.to_string(),
+            format!(
+                "sig (id 7) txid={} deposit_outpoint={}:1 signatures=1",
+                txid, txid
+            )
+        );
+        assert_eq!(
+            req.params().to_string(),
+            format!("txid={} deposit_outpoint={}:1 signatures=1", txid, txid)
+        );
+
+        let resp = Response {
+            result: ResponseResult::Sigs(coordinator::Sigs { signatures }),
+            id: 7,
         };
-        roundtrip!(msg);
-        assert_str_ser!(msg, r#"{"result":{"tx":null},"id":975687}"#);
+        assert_eq!(resp.to_string(), "response (id 7) signatures=1");
+        let resp = Response {
+            result: coordinator::SpendTx::not_found(),
+            id: 8,
+        };
+        assert_eq!(resp.to_string(), "response (id 8) not found");
+        let err = ErrorResponse {
+            error: ResponseError::new(ErrorCode::MethodNotFound, "Unknown method"),
+            id: 9,
+        };
+        assert_eq!(err.to_string(), "error (id 9) Unknown method (code -32601)");
+
+        let notif: Notification = coordinator::NewSpendTxEvent {
+            subscription_id: 3,
+            deposit_outpoint,
+            transaction: get_dummy_raw_tx(),
+        }
+        .into();
+        assert_eq!(
+            notif.to_string(),
+            format!(
+                "new_spend_tx subscription_id=3 deposit_outpoint={}:1 txid={}",
+                txid,
+                get_dummy_raw_tx().txid()
+            )
+        );
+    }
+
+    #[test]
+    fn hashable_messages() {
+        let sig = coordinator::Sig {
+            pubkey: get_dummy_pubkey(),
+            signature: get_dummy_sig(),
+            id: Txid::default(),
+        };
+        let req: Request = with_id_generator(SequentialIds(0), || sig.clone().into());
+
+        // Duplicate requests are deduplicated
+        let mut seen = HashSet::new();
+        assert!(seen.insert(req.clone()));
+        assert!(!seen.insert(req.clone()));
+        let other_req: Request = with_id_generator(SequentialIds(1), || sig.clone().into());
+        assert!(seen.insert(other_req));
+        assert_eq!(seen.len(), 2);
+
+        // And params can be used as cache keys
+        let mut cache = HashMap::new();
+        cache.insert(req.clone().params(), "cached");
+        assert_eq!(cache.get(&req.params()), Some(&"cached"));
+        let mut sigset = SigSet::new();
+        sigset.insert(sig.pubkey, sig.signature).unwrap();
+        let sigsets: HashSet<_> = [sigset.clone(), sigset, SigSet::new()]
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(sigsets.len(), 2);
+
+        #[cfg(feature = "revault_tx")]
+        {
+            let sign_req = cosigner::SignRequest::new(spend_tx());
+            let signed = cosigner::SignRequest::new(get_dummy_signed_spend_tx());
+            let set: HashSet<_> = [sign_req.clone(), sign_req, signed]
+                .iter()
+                .cloned()
+                .collect();
+            assert_eq!(set.len(), 2);
+        }
     }
 }
@@ -0,0 +1,1127 @@
+//! Arbitrary messages
+//!
+//! [Arbitrary] implementations for all the message types, for this crate and the
+//! servers using it to property-test (or fuzz) their handling of messages. The
+//! generated messages are valid: they pass the sanity checks performed at
+//! deserialization.
+
+use crate::fixtures::SPEND_PSBT;
+
+use super::{
+    coordinator, cosigner, replication, watchtower, with_id_generator, ErrorCode, ErrorData,
+    ErrorResponse, Notification, NotificationParams, Request, RequestParams, Response,
+    ResponseError, ResponseResult, SequentialIds, SigSet,
+};
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+use bitcoin::{
+    hash_types::Txid,
+    hashes::Hash,
+    secp256k1::{self, schnorrsig, All, Secp256k1},
+    OutPoint, Script, SigHashType, Transaction, TxIn, TxOut,
+};
+use revault_tx::transactions::{RevaultTransaction, SpendTransaction};
+use std::collections::{BTreeMap, BTreeSet};
+
+thread_local! {
+    static SECP: Secp256k1<All> = Secp256k1::new();
+}
+
+// Any 32 bytes, as long as it's a valid secret key
+fn seckey_bytes(u: &mut Unstructured) -> Result<[u8; 32]> {
+    let bytes: [u8; 32] = u.arbitrary()?;
+    Ok(if secp256k1::SecretKey::from_slice(&bytes).is_ok() {
+        bytes
+    } else {
+        [1; 32]
+    })
+}
+
+fn txid(u: &mut Unstructured) -> Result<Txid> {
+    Ok(Txid::from_inner(u.arbitrary()?))
+}
+
+fn txids(u: &mut Unstructured) -> Result<Vec<Txid>> {
+    u.arbitrary_iter::<[u8; 32]>()?
+        .map(|bytes| bytes.map(Txid::from_inner))
+        .collect()
+}
+
+fn outpoint(u: &mut Unstructured) -> Result<OutPoint> {
+    Ok(OutPoint {
+        txid: txid(u)?,
+        vout: u.arbitrary()?,
+    })
+}
+
+// This number of unique outpoints
+fn unique_outpoints(u: &mut Unstructured, len: usize) -> Result<Vec<OutPoint>> {
+    let mut outpoints = BTreeSet::new();
+    while outpoints.len() < len {
+        let mut outpoint = outpoint(u)?;
+        // Don't loop forever once the data is exhausted
+        outpoint.vout = outpoint.vout.wrapping_add(outpoints.len() as u32);
+        outpoints.insert(outpoint);
+    }
+    Ok(outpoints.into_iter().collect())
+}
+
+// Up to `max` bytes, less if the data is exhausted
+fn bytes(u: &mut Unstructured, max: usize) -> Result<Vec<u8>> {
+    let len = u.int_in_range(0..=max)?.min(u.len());
+    Ok(u.bytes(len)?.to_vec())
+}
+
+fn pubkey(u: &mut Unstructured) -> Result<secp256k1::PublicKey> {
+    let seckey = secp256k1::SecretKey::from_slice(&seckey_bytes(u)?).expect("Valid secret key");
+    Ok(SECP.with(|secp| secp256k1::PublicKey::from_secret_key(secp, &seckey)))
+}
+
+// A (low-S, as produced by libsecp) signature of an arbitrary message
+fn signature(u: &mut Unstructured) -> Result<secp256k1::Signature> {
+    let seckey = secp256k1::SecretKey::from_slice(&seckey_bytes(u)?).expect("Valid secret key");
+    let msg = secp256k1::Message::from_slice(&u.arbitrary::<[u8; 32]>()?).expect("32 bytes");
+    Ok(SECP.with(|secp| secp.sign(&msg, &seckey)))
+}
+
+fn schnorr_pubkey(u: &mut Unstructured) -> Result<schnorrsig::PublicKey> {
+    let bytes = seckey_bytes(u)?;
+    Ok(SECP.with(|secp| {
+        let keypair = schnorrsig::KeyPair::from_seckey_slice(secp, &bytes).expect("Valid key");
+        schnorrsig::PublicKey::from_keypair(secp, &keypair)
+    }))
+}
+
+fn schnorr_signature(u: &mut Unstructured) -> Result<schnorrsig::Signature> {
+    let bytes = seckey_bytes(u)?;
+    let msg = secp256k1::Message::from_slice(&u.arbitrary::<[u8; 32]>()?).expect("32 bytes");
+    Ok(SECP.with(|secp| {
+        let keypair = schnorrsig::KeyPair::from_seckey_slice(secp, &bytes).expect("Valid key");
+        secp.schnorrsig_sign_no_aux_rand(&msg, &keypair)
+    }))
+}
+
+fn schnorr_sigs(
+    u: &mut Unstructured,
+) -> Result<BTreeMap<schnorrsig::PublicKey, schnorrsig::Signature>> {
+    let len = u.int_in_range(0..=3)?;
+    (0..len)
+        .map(|_| Ok((schnorr_pubkey(u)?, schnorr_signature(u)?)))
+        .collect()
+}
+
+fn script(u: &mut Unstructured) -> Result<Script> {
+    Ok(bytes(u, 40)?.into())
+}
+
+// A transaction that passes the sanity checks: a few unique inputs, a few outputs and
+// small scripts and witnesses so that it is far from the weight limit.
+fn transaction(u: &mut Unstructured) -> Result<Transaction> {
+    let n_inputs = u.int_in_range(1..=3)?;
+    let input = unique_outpoints(u, n_inputs)?
+        .into_iter()
+        .map(|previous_output| {
+            let n_items = u.int_in_range(0..=2)?;
+            let witness = (0..n_items).map(|_| bytes(u, 73)).collect::<Result<_>>()?;
+            Ok(TxIn {
+                previous_output,
+                script_sig: script(u)?,
+                sequence: u.arbitrary()?,
+                witness,
+            })
+        })
+        .collect::<Result<_>>()?;
+    let n_outputs = u.int_in_range(1..=3)?;
+    let output = (0..n_outputs)
+        .map(|_| {
+            Ok(TxOut {
+                value: u.int_in_range(0..=21_000_000 * 100_000_000)?,
+                script_pubkey: script(u)?,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(Transaction {
+        version: u.int_in_range(1..=2)?,
+        lock_time: u.arbitrary()?,
+        input,
+        output,
+    })
+}
+
+fn spend_tx(u: &mut Unstructured) -> Result<SpendTransaction> {
+    let mut tx = SpendTransaction::from_psbt_str(SPEND_PSBT).expect("Valid PSBT");
+    tx.inner_tx_mut().global.unsigned_tx.lock_time = u.int_in_range(0..=499_999_999)?;
+    Ok(tx)
+}
+
+impl<'a> Arbitrary<'a> for SigSet {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut sigs = SigSet::new();
+        for _ in 0..u.int_in_range(0..=3)? {
+            // Keys are drawn independently, a duplicate one is just skipped
+            let _ = sigs.insert(pubkey(u)?, signature(u)?);
+        }
+        Ok(sigs)
+    }
+}
+
+impl<'a> Arbitrary<'a> for watchtower::Sig {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            signatures: u.arbitrary()?,
+            txid: txid(u)?,
+            deposit_outpoint: outpoint(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for watchtower::SchnorrSig {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            signatures: schnorr_sigs(u)?,
+            txid: txid(u)?,
+            deposit_outpoint: outpoint(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for watchtower::SigResult {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            ack: u.arbitrary()?,
+            txid: txid(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for watchtower::SyncVaults {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            vaults: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for watchtower::GetSpendPolicy {
+    fn arbitrary(_: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {})
+    }
+}
+
+impl<'a> Arbitrary<'a> for watchtower::SpendPolicy {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let destinations: Vec<Vec<u8>> = u.arbitrary()?;
+        Ok(Self {
+            max_daily_amount: u.arbitrary()?,
+            allowed_destinations: destinations.into_iter().map(Script::from).collect(),
+            unvault_csv: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for watchtower::ApproveSpend {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            deposit_outpoints: (0..u.int_in_range(1..=3)?)
+                .map(|_| outpoint(u))
+                .collect::<Result<_>>()?,
+            spend_tx: transaction(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for watchtower::SpendApproval {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            approved: u.arbitrary()?,
+            txid: txid(u)?,
+            reason: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for watchtower::SyncVaultsResult {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            missing: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for watchtower::RecoverSigs {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            deposit_outpoint: outpoint(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for watchtower::RecoveredSigs {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            sigs: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::GetSigs {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            id: txid(u)?,
+            if_none_match: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::RevocationTxType {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(*u.choose(&coordinator::RevocationTxType::ALL)?)
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::GetRevocationSigs {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut txids = BTreeMap::new();
+        for _ in 0..u.int_in_range(0..=3)? {
+            txids.insert(u.arbitrary()?, txid(u)?);
+        }
+        Ok(Self { txids })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::RevocationSigs {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut signatures = BTreeMap::new();
+        for _ in 0..u.int_in_range(0..=3)? {
+            let sigs: BTreeMap<Txid, SigSet> =
+                [(txid(u)?, u.arbitrary()?)].iter().cloned().collect();
+            signatures.insert(u.arbitrary()?, sigs);
+        }
+        Ok(Self { signatures })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::HasSigs {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self { id: txid(u)? })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SigsCount {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            count: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SigsTag {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self(u.arbitrary()?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::NotModified {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            not_modified: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::GetTime {
+    fn arbitrary(_: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {})
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::ServerTime {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            time: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::Sigs {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            signatures: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SchnorrSigs {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            signatures: schnorr_sigs(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SetSpendTx {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let transaction = transaction(u)?;
+        let deposit_outpoints = unique_outpoints(u, transaction.input.len())?;
+        let msg = Self::from_transaction(deposit_outpoints, transaction).expect("Valid SetSpendTx");
+        Ok(match u.arbitrary()? {
+            Some(expires_at) => msg.with_expiry(expires_at),
+            None => msg,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SetSpendResult {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            ack: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::GetSpendTx {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            deposit_outpoint: outpoint(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SpendTx {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(if u.arbitrary()? {
+            Self::found(transaction(u)?)
+        } else {
+            Self::not_found()
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::Sig {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            pubkey: pubkey(u)?,
+            signature: signature(u)?,
+            id: txid(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SchnorrSig {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            pubkey: schnorr_pubkey(u)?,
+            signature: schnorr_signature(u)?,
+            id: txid(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::MusigPubNonce {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut nonce = [0; 66];
+        u.fill_buffer(&mut nonce)?;
+        Ok(Self(nonce))
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::MusigPartialSignature {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self(u.arbitrary()?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::MusigNonce {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            txid: txid(u)?,
+            pubkey: pubkey(u)?,
+            nonce: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::MusigPartialSig {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            txid: txid(u)?,
+            pubkey: pubkey(u)?,
+            partial_sig: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::GetMusigSession {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self { txid: txid(u)? })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::RecipientKey {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self(u.arbitrary()?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SealedBlob {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let bytes: &[u8] = u.arbitrary()?;
+        let len = bytes.len().min(coordinator::MAX_SEALED_BLOB_SIZE);
+        Ok(Self(bytes[..len].to_vec()))
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SealedSig {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let n_recipients = u.int_in_range(0..=3)?;
+        Ok(Self {
+            txid: txid(u)?,
+            sealed: (0..n_recipients)
+                .map(|_| Ok((u.arbitrary()?, u.arbitrary()?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::GetSealedSigs {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            txid: txid(u)?,
+            recipient: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SealedSigs {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let n_sealed = u.int_in_range(0..=3)?;
+        Ok(Self {
+            sealed: (0..n_sealed)
+                .map(|_| u.arbitrary())
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::BlobId {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self(u.arbitrary()?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::StoreBlob {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            blob_id: u.arbitrary()?,
+            blob: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::GetBlobs {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            blob_id: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::Blobs {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let n_blobs = u.int_in_range(0..=3)?;
+        Ok(Self {
+            blobs: (0..n_blobs).map(|_| u.arbitrary()).collect::<Result<_>>()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::ChunkData {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let bytes: &[u8] = u.arbitrary()?;
+        let len = bytes.len().min(coordinator::MAX_CHUNK_SIZE);
+        Ok(Self(bytes[..len].to_vec()))
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SetSpendTxBegin {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            total_size: u.arbitrary()?,
+            sha256: bitcoin::hashes::sha256::Hash::from_inner(u.arbitrary()?),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::UploadStarted {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            upload_id: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SetSpendTxChunk {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            upload_id: u.arbitrary()?,
+            index: u.arbitrary()?,
+            data: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SetSpendTxCommit {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            upload_id: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::GetSpendTxChunk {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            deposit_outpoint: outpoint(u)?,
+            chunk_index: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SpendTxChunk {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let sha256: Option<[u8; 32]> = u.arbitrary()?;
+        Ok(Self {
+            chunk_index: u.arbitrary()?,
+            chunk_count: u.arbitrary()?,
+            data: u.arbitrary()?,
+            sha256: sha256.map(bitcoin::hashes::sha256::Hash::from_inner),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::MusigSession {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let (n_nonces, n_partial_sigs) = (u.int_in_range(0..=3)?, u.int_in_range(0..=3)?);
+        Ok(Self {
+            nonces: (0..n_nonces)
+                .map(|_| Ok((pubkey(u)?, u.arbitrary()?)))
+                .collect::<Result<_>>()?,
+            partial_sigs: (0..n_partial_sigs)
+                .map(|_| Ok((pubkey(u)?, u.arbitrary()?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SigResult {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            ack: u.arbitrary()?,
+            already_known: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::Subscribe {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            txids: txids(u)?,
+            deposit_outpoints: u
+                .arbitrary_iter::<([u8; 32], u32)>()?
+                .map(|res| res.map(|(txid, vout)| OutPoint::new(Txid::from_inner(txid), vout)))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::SubscribeResult {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            subscription_id: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::Unsubscribe {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            subscription_id: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::UnsubscribeResult {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            ack: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::NewSigEvent {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            subscription_id: u.arbitrary()?,
+            pubkey: pubkey(u)?,
+            signature: signature(u)?,
+            id: txid(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for coordinator::NewSpendTxEvent {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            subscription_id: u.arbitrary()?,
+            deposit_outpoint: outpoint(u)?,
+            transaction: transaction(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for replication::Entry {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(if u.arbitrary()? {
+            Self::Sig {
+                txid: txid(u)?,
+                pubkey: pubkey(u)?,
+                signature: signature(u)?,
+            }
+        } else {
+            Self::SpendTx(u.arbitrary()?)
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for replication::Replicate {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            entries: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for replication::ReplicateResult {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            new_entries: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for replication::GetEntries {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            since: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for replication::Entries {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            entries: u.arbitrary()?,
+            cursor: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for cosigner::SignRequest {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            tx: spend_tx(u)?,
+            expires_at: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for cosigner::SignResult {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            tx: if u.arbitrary()? {
+                Some(spend_tx(u)?)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for cosigner::BatchSignRequest {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let len = u.int_in_range(0..=3)?;
+        Ok(Self {
+            txs: (0..len).map(|_| spend_tx(u)).collect::<Result<_>>()?,
+            expires_at: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for cosigner::BatchSignResult {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let len = u.int_in_range(0..=3)?;
+        let mut txs = Vec::with_capacity(len);
+        for _ in 0..len {
+            txs.push(if u.arbitrary()? {
+                Some(spend_tx(u)?)
+            } else {
+                None
+            });
+        }
+        Ok(Self { txs })
+    }
+}
+
+impl<'a> Arbitrary<'a> for cosigner::KeyRotation {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let old_privkey =
+            secp256k1::SecretKey::from_slice(&seckey_bytes(u)?).expect("Valid secret key");
+        let new_pubkey = pubkey(u)?;
+        Ok(SECP.with(|secp| Self::new(secp, &old_privkey, new_pubkey)))
+    }
+}
+
+impl<'a> Arbitrary<'a> for cosigner::AckKeyRotation {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            new_pubkey: pubkey(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for cosigner::AckKeyRotationResult {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            ack: u.arbitrary()?,
+            new_pubkey: pubkey(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for cosigner::GetPubkey {
+    fn arbitrary(_: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {})
+    }
+}
+
+impl<'a> Arbitrary<'a> for cosigner::GetPubkeyResult {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let sighash_types = [
+            SigHashType::All,
+            SigHashType::None,
+            SigHashType::Single,
+            SigHashType::AllPlusAnyoneCanPay,
+            SigHashType::NonePlusAnyoneCanPay,
+            SigHashType::SinglePlusAnyoneCanPay,
+        ];
+        let n_types = u.int_in_range(0..=3)?;
+        Ok(Self {
+            pubkey: pubkey(u)?,
+            sighash_types: (0..n_types)
+                .map(|_| u.choose(&sighash_types).copied())
+                .collect::<Result<_>>()?,
+            anti_replay: if u.arbitrary()? {
+                cosigner::AntiReplay::OncePerOutpoint
+            } else {
+                cosigner::AntiReplay::Unenforced
+            },
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for RequestParams {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=32)? {
+            0 => Self::WtSig(u.arbitrary()?),
+            1 => Self::WtSchnorrSig(u.arbitrary()?),
+            2 => Self::SetSpendTx(u.arbitrary()?),
+            3 => Self::GetSpendTx(u.arbitrary()?),
+            4 => Self::CoordSig(u.arbitrary()?),
+            5 => Self::CoordSchnorrSig(u.arbitrary()?),
+            6 => Self::GetSigs(u.arbitrary()?),
+            7 => Self::Sign(u.arbitrary()?),
+            8 => Self::MusigNonce(u.arbitrary()?),
+            9 => Self::MusigPartialSig(u.arbitrary()?),
+            10 => Self::GetMusigSession(u.arbitrary()?),
+            11 => Self::Subscribe(u.arbitrary()?),
+            12 => Self::Unsubscribe(u.arbitrary()?),
+            13 => Self::Replicate(u.arbitrary()?),
+            14 => Self::GetEntries(u.arbitrary()?),
+            15 => Self::SealedSig(u.arbitrary()?),
+            16 => Self::GetSealedSigs(u.arbitrary()?),
+            17 => Self::StoreBlob(u.arbitrary()?),
+            18 => Self::GetBlobs(u.arbitrary()?),
+            19 => Self::SetSpendTxBegin(u.arbitrary()?),
+            20 => Self::SetSpendTxChunk(u.arbitrary()?),
+            21 => Self::SetSpendTxCommit(u.arbitrary()?),
+            22 => Self::GetSpendTxChunk(u.arbitrary()?),
+            23 => Self::WtSyncVaults(u.arbitrary()?),
+            24 => Self::WtApproveSpend(u.arbitrary()?),
+            25 => Self::BatchSign(u.arbitrary()?),
+            26 => Self::AckKeyRotation(u.arbitrary()?),
+            27 => Self::GetPubkey(u.arbitrary()?),
+            28 => Self::GetTime(u.arbitrary()?),
+            29 => Self::GetRevocationSigs(u.arbitrary()?),
+            30 => Self::HasSigs(u.arbitrary()?),
+            31 => Self::WtRecoverSigs(u.arbitrary()?),
+            _ => Self::WtGetSpendPolicy(u.arbitrary()?),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Request<'_> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let params: RequestParams = u.arbitrary()?;
+        let id: u32 = u.arbitrary()?;
+        Ok(with_id_generator(SequentialIds(id), || match params {
+            RequestParams::WtSig(p) => p.into(),
+            RequestParams::WtSchnorrSig(p) => p.into(),
+            RequestParams::SetSpendTx(p) => p.into(),
+            RequestParams::GetSpendTx(p) => p.into(),
+            RequestParams::CoordSig(p) => p.into(),
+            RequestParams::CoordSchnorrSig(p) => p.into(),
+            RequestParams::GetSigs(p) => p.into(),
+            RequestParams::GetRevocationSigs(p) => p.into(),
+            RequestParams::HasSigs(p) => p.into(),
+            RequestParams::Sign(p) => p.into(),
+            RequestParams::BatchSign(p) => p.into(),
+            RequestParams::AckKeyRotation(p) => p.into(),
+            RequestParams::GetPubkey(p) => p.into(),
+            RequestParams::MusigNonce(p) => p.into(),
+            RequestParams::MusigPartialSig(p) => p.into(),
+            RequestParams::GetMusigSession(p) => p.into(),
+            RequestParams::Subscribe(p) => p.into(),
+            RequestParams::Unsubscribe(p) => p.into(),
+            RequestParams::Replicate(p) => p.into(),
+            RequestParams::GetEntries(p) => p.into(),
+            RequestParams::WtSyncVaults(p) => p.into(),
+            RequestParams::SealedSig(p) => p.into(),
+            RequestParams::GetSealedSigs(p) => p.into(),
+            RequestParams::StoreBlob(p) => p.into(),
+            RequestParams::GetBlobs(p) => p.into(),
+            RequestParams::SetSpendTxBegin(p) => p.into(),
+            RequestParams::SetSpendTxChunk(p) => p.into(),
+            RequestParams::SetSpendTxCommit(p) => p.into(),
+            RequestParams::GetSpendTxChunk(p) => p.into(),
+            RequestParams::WtGetSpendPolicy(p) => p.into(),
+            RequestParams::GetTime(p) => p.into(),
+            RequestParams::WtApproveSpend(p) => p.into(),
+            RequestParams::WtRecoverSigs(p) => p.into(),
+        }))
+    }
+}
+
+impl<'a> Arbitrary<'a> for ResponseResult {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=26)? {
+            0 => Self::WtSig(u.arbitrary()?),
+            1 => Self::Sigs(u.arbitrary()?),
+            2 => Self::SchnorrSigs(u.arbitrary()?),
+            3 => Self::Sig(u.arbitrary()?),
+            4 => Self::SetSpend(u.arbitrary()?),
+            5 => Self::SpendTx(u.arbitrary()?),
+            6 => Self::MusigSession(u.arbitrary()?),
+            7 => Self::Subscribed(u.arbitrary()?),
+            8 => Self::Unsubscribed(u.arbitrary()?),
+            9 => Self::Replicated(u.arbitrary()?),
+            10 => Self::Entries(u.arbitrary()?),
+            11 => Self::WtSyncVaults(u.arbitrary()?),
+            12 => Self::SealedSigs(u.arbitrary()?),
+            13 => Self::Blobs(u.arbitrary()?),
+            14 => Self::UploadStarted(u.arbitrary()?),
+            15 => Self::SpendTxChunk(u.arbitrary()?),
+            16 => Self::NotModified(u.arbitrary()?),
+            17 => Self::WtSpendPolicy(u.arbitrary()?),
+            18 => Self::WtSpendApproval(u.arbitrary()?),
+            19 => Self::BatchSignResult(u.arbitrary()?),
+            20 => Self::AckKeyRotation(u.arbitrary()?),
+            21 => Self::CosignerPubkey(u.arbitrary()?),
+            22 => Self::ServerTime(u.arbitrary()?),
+            23 => Self::RevocationSigs(u.arbitrary()?),
+            24 => Self::SigsCount(u.arbitrary()?),
+            25 => Self::WtRecoveredSigs(u.arbitrary()?),
+            _ => Self::SignResult(u.arbitrary()?),
+        })
+    }
+}
+
+impl<'a, T: Arbitrary<'a>> Arbitrary<'a> for Response<T> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            result: u.arbitrary()?,
+            id: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for ErrorCode {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self::from_code(u.arbitrary()?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for ErrorData {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            retry_after: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for ResponseError {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            code: u.arbitrary()?,
+            message: u.arbitrary()?,
+            data: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for ErrorResponse {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            error: u.arbitrary()?,
+            id: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for NotificationParams {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=2)? {
+            0 => Self::NewSig(u.arbitrary()?),
+            1 => Self::NewSpendTx(u.arbitrary()?),
+            _ => Self::KeyRotation(u.arbitrary()?),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Notification<'_> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.arbitrary()? {
+            NotificationParams::NewSig(p) => p.into(),
+            NotificationParams::NewSpendTx(p) => p.into(),
+            NotificationParams::KeyRotation(p) => p.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{de::DeserializeOwned, Serialize};
+    use std::fmt::Debug;
+
+    // A few hundred inputs of various lengths out of a fixed seed
+    fn inputs() -> impl Iterator<Item = Vec<u8>> {
+        let mut rng = super::super::SeededIds::new(0);
+        (0..300).map(move |i| {
+            (0..i * 7)
+                .map(|_| rng.next_u64() as u8)
+                .collect::<Vec<u8>>()
+        })
+    }
+
+    fn roundtrip<T>()
+    where
+        T: for<'a> Arbitrary<'a> + Serialize + DeserializeOwned + PartialEq + Debug,
+    {
+        for data in inputs() {
+            let msg: T = Unstructured::new(&data).arbitrary().unwrap();
+            let ser = serde_json::to_string(&msg).unwrap();
+            let de: T = serde_json::from_str(&ser)
+                .unwrap_or_else(|e| panic!("Deserializing '{}': {}", ser, e));
+            assert_eq!(msg, de);
+        }
+    }
+
+    #[test]
+    fn arbitrary_messages() {
+        roundtrip::<watchtower::Sig>();
+        roundtrip::<watchtower::SchnorrSig>();
+        roundtrip::<watchtower::SigResult>();
+        roundtrip::<watchtower::SyncVaults>();
+        roundtrip::<watchtower::SyncVaultsResult>();
+        roundtrip::<watchtower::GetSpendPolicy>();
+        roundtrip::<watchtower::SpendPolicy>();
+        roundtrip::<watchtower::ApproveSpend>();
+        roundtrip::<watchtower::SpendApproval>();
+        roundtrip::<watchtower::RecoverSigs>();
+        roundtrip::<watchtower::RecoveredSigs>();
+        roundtrip::<coordinator::GetSigs>();
+        roundtrip::<coordinator::Sigs>();
+        roundtrip::<coordinator::GetRevocationSigs>();
+        roundtrip::<coordinator::RevocationSigs>();
+        roundtrip::<coordinator::HasSigs>();
+        roundtrip::<coordinator::SigsCount>();
+        roundtrip::<coordinator::SchnorrSigs>();
+        roundtrip::<coordinator::SetSpendTx>();
+        roundtrip::<coordinator::SetSpendResult>();
+        roundtrip::<coordinator::GetSpendTx>();
+        roundtrip::<coordinator::SpendTx>();
+        roundtrip::<coordinator::Sig>();
+        roundtrip::<coordinator::SchnorrSig>();
+        roundtrip::<coordinator::MusigNonce>();
+        roundtrip::<coordinator::MusigPartialSig>();
+        roundtrip::<coordinator::GetMusigSession>();
+        roundtrip::<coordinator::MusigSession>();
+        roundtrip::<coordinator::SealedSig>();
+        roundtrip::<coordinator::GetSealedSigs>();
+        roundtrip::<coordinator::SealedSigs>();
+        roundtrip::<coordinator::StoreBlob>();
+        roundtrip::<coordinator::GetBlobs>();
+        roundtrip::<coordinator::Blobs>();
+        roundtrip::<coordinator::SetSpendTxBegin>();
+        roundtrip::<coordinator::UploadStarted>();
+        roundtrip::<coordinator::SetSpendTxChunk>();
+        roundtrip::<coordinator::SetSpendTxCommit>();
+        roundtrip::<coordinator::GetSpendTxChunk>();
+        roundtrip::<coordinator::SpendTxChunk>();
+        roundtrip::<coordinator::NotModified>();
+        roundtrip::<coordinator::GetTime>();
+        roundtrip::<coordinator::ServerTime>();
+        roundtrip::<coordinator::SigResult>();
+        roundtrip::<coordinator::Subscribe>();
+        roundtrip::<coordinator::SubscribeResult>();
+        roundtrip::<coordinator::Unsubscribe>();
+        roundtrip::<coordinator::UnsubscribeResult>();
+        roundtrip::<coordinator::NewSigEvent>();
+        roundtrip::<coordinator::NewSpendTxEvent>();
+        roundtrip::<replication::Replicate>();
+        roundtrip::<replication::ReplicateResult>();
+        roundtrip::<replication::GetEntries>();
+        roundtrip::<replication::Entries>();
+        roundtrip::<cosigner::SignRequest>();
+        roundtrip::<cosigner::SignResult>();
+        roundtrip::<cosigner::BatchSignRequest>();
+        roundtrip::<cosigner::BatchSignResult>();
+        roundtrip::<cosigner::KeyRotation>();
+        roundtrip::<cosigner::AckKeyRotation>();
+        roundtrip::<cosigner::AckKeyRotationResult>();
+        roundtrip::<cosigner::GetPubkey>();
+        roundtrip::<cosigner::GetPubkeyResult>();
+        roundtrip::<ErrorResponse>();
+
+        // The untagged enums may deserialize to another variant with the same fields
+        // (an empty set of ECDSA signatures is also an empty set of Schnorr ones), so
+        // only check they are valid.
+        for data in inputs() {
+            let mut u = Unstructured::new(&data);
+            let req: Request = u.arbitrary().unwrap();
+            let ser = serde_json::to_string(&req).unwrap();
+            let de: Request = serde_json::from_str(&ser).unwrap();
+            assert_eq!((de.method(), de.id()), (req.method(), req.id()));
+
+            let resp: Response<ResponseResult> = u.arbitrary().unwrap();
+            let ser = serde_json::to_string(&resp).unwrap();
+            serde_json::from_str::<Response<ResponseResult>>(&ser).unwrap();
+
+            let notif: Notification = u.arbitrary().unwrap();
+            let ser = serde_json::to_string(&notif).unwrap();
+            let de: Notification = serde_json::from_str(&ser).unwrap();
+            assert_eq!(de.method(), notif.method());
+        }
+    }
+}
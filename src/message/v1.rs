@@ -0,0 +1,15 @@
+//! The current revision of the messages
+//!
+//! These are the messages of the parent module, under a versioned path for the
+//! daemons handling more than one revision. See [v2](super::v2).
+
+#[cfg(feature = "revault_tx")]
+pub use super::cosigner;
+pub use super::{
+    coordinator, replication, watchtower, ErrorResponse, Notification, NotificationParams, Request,
+    RequestParams, Response, ResponseError, ResponseResult,
+};
+
+/// The revision of these messages, assumed for the requests without a `version` (see
+/// [v2::Request::from_versioned_slice](super::v2::Request::from_versioned_slice))
+pub const VERSION: u32 = 1;
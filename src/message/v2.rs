@@ -0,0 +1,1370 @@
+//! The next revision of the messages
+//!
+//! It names `txid` the transaction ids [v1] names `id`, which are easily
+//! mistaken for the id of the request. The other messages are the ones of v1.
+//!
+//! Every message converts from and into its v1 counterpart, for the daemons to
+//! support both revisions during the migration. A request may also tell its revision
+//! in the `version` field of its params, for a server to parse the requests of any
+//! revision and only ever handle v2 ones (see [Request::from_versioned_slice]). The
+//! requests read by a [KKTransport](crate::transport::KKTransport) are parsed this way
+//! too, and handed to the handlers as v1 ones.
+
+use super::{de, method, ser, v1, Deserialize, Serialize};
+use crate::error::{from_json_slice, Error, JsonError, MessageError};
+
+use serde_json::value::RawValue;
+
+/// The revision of these messages, as given by the `version` of the params of a
+/// request
+pub const VERSION: u32 = 2;
+
+#[cfg(feature = "revault_tx")]
+pub use super::cosigner;
+pub use super::{replication, watchtower, ErrorResponse, Response, ResponseError, ResponseResult};
+
+/// A JSONRPC-like request, as [v1::Request] with the v2 messages.
+#[allow(missing_docs)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
+// The derived (de)serialization is wrapped by the implementations below
+#[serde(untagged, remote = "Self")]
+pub enum Request<'a> {
+    WtSig {
+        method: &'a str,
+        params: watchtower::Sig,
+        id: u32,
+    },
+    WtSchnorrSig {
+        method: &'a str,
+        params: watchtower::SchnorrSig,
+        id: u32,
+    },
+    SetSpendTx {
+        method: &'a str,
+        params: coordinator::SetSpendTx,
+        id: u32,
+    },
+    GetSpendTxChunk {
+        method: &'a str,
+        params: coordinator::GetSpendTxChunk,
+        id: u32,
+    },
+    GetSpendTx {
+        method: &'a str,
+        params: coordinator::GetSpendTx,
+        id: u32,
+    },
+    CoordSig {
+        method: &'a str,
+        params: coordinator::Sig,
+        id: u32,
+    },
+    CoordSchnorrSig {
+        method: &'a str,
+        params: coordinator::SchnorrSig,
+        id: u32,
+    },
+    GetSigs {
+        method: &'a str,
+        params: coordinator::GetSigs,
+        id: u32,
+    },
+    GetRevocationSigs {
+        method: &'a str,
+        params: coordinator::GetRevocationSigs,
+        id: u32,
+    },
+    HasSigs {
+        method: &'a str,
+        params: coordinator::HasSigs,
+        id: u32,
+    },
+    #[cfg(feature = "revault_tx")]
+    Sign {
+        method: &'a str,
+        params: cosigner::SignRequest,
+        id: u32,
+    },
+    #[cfg(feature = "revault_tx")]
+    AckKeyRotation {
+        method: &'a str,
+        params: cosigner::AckKeyRotation,
+        id: u32,
+    },
+    #[cfg(feature = "revault_tx")]
+    BatchSign {
+        method: &'a str,
+        params: cosigner::BatchSignRequest,
+        id: u32,
+    },
+    MusigNonce {
+        method: &'a str,
+        params: coordinator::MusigNonce,
+        id: u32,
+    },
+    MusigPartialSig {
+        method: &'a str,
+        params: coordinator::MusigPartialSig,
+        id: u32,
+    },
+    SealedSig {
+        method: &'a str,
+        params: coordinator::SealedSig,
+        id: u32,
+    },
+    GetSealedSigs {
+        method: &'a str,
+        params: coordinator::GetSealedSigs,
+        id: u32,
+    },
+    StoreBlob {
+        method: &'a str,
+        params: coordinator::StoreBlob,
+        id: u32,
+    },
+    GetBlobs {
+        method: &'a str,
+        params: coordinator::GetBlobs,
+        id: u32,
+    },
+    SetSpendTxBegin {
+        method: &'a str,
+        params: coordinator::SetSpendTxBegin,
+        id: u32,
+    },
+    SetSpendTxChunk {
+        method: &'a str,
+        params: coordinator::SetSpendTxChunk,
+        id: u32,
+    },
+    SetSpendTxCommit {
+        method: &'a str,
+        params: coordinator::SetSpendTxCommit,
+        id: u32,
+    },
+    GetMusigSession {
+        method: &'a str,
+        params: coordinator::GetMusigSession,
+        id: u32,
+    },
+    Subscribe {
+        method: &'a str,
+        params: coordinator::Subscribe,
+        id: u32,
+    },
+    Unsubscribe {
+        method: &'a str,
+        params: coordinator::Unsubscribe,
+        id: u32,
+    },
+    Replicate {
+        method: &'a str,
+        params: replication::Replicate,
+        id: u32,
+    },
+    GetEntries {
+        method: &'a str,
+        params: replication::GetEntries,
+        id: u32,
+    },
+    WtSyncVaults {
+        method: &'a str,
+        params: watchtower::SyncVaults,
+        id: u32,
+    },
+    WtApproveSpend {
+        method: &'a str,
+        params: watchtower::ApproveSpend,
+        id: u32,
+    },
+    WtRecoverSigs {
+        method: &'a str,
+        params: watchtower::RecoverSigs,
+        id: u32,
+    },
+    // Last, as for v1
+    WtGetSpendPolicy {
+        method: &'a str,
+        params: watchtower::GetSpendPolicy,
+        id: u32,
+    },
+    GetTime {
+        method: &'a str,
+        params: coordinator::GetTime,
+        id: u32,
+    },
+    #[cfg(feature = "revault_tx")]
+    GetPubkey {
+        method: &'a str,
+        params: cosigner::GetPubkey,
+        id: u32,
+    },
+}
+
+impl<'a> Request<'a> {
+    /// Get the parameters of this request
+    pub fn params(self) -> RequestParams {
+        match self {
+            Request::WtSig { params, .. } => RequestParams::WtSig(params),
+            Request::WtSchnorrSig { params, .. } => RequestParams::WtSchnorrSig(params),
+            Request::SetSpendTx { params, .. } => RequestParams::SetSpendTx(params),
+            Request::GetSpendTx { params, .. } => RequestParams::GetSpendTx(params),
+            Request::CoordSig { params, .. } => RequestParams::CoordSig(params),
+            Request::CoordSchnorrSig { params, .. } => RequestParams::CoordSchnorrSig(params),
+            Request::GetSigs { params, .. } => RequestParams::GetSigs(params),
+            #[cfg(feature = "revault_tx")]
+            Request::Sign { params, .. } => RequestParams::Sign(params),
+            #[cfg(feature = "revault_tx")]
+            Request::AckKeyRotation { params, .. } => RequestParams::AckKeyRotation(params),
+            #[cfg(feature = "revault_tx")]
+            Request::BatchSign { params, .. } => RequestParams::BatchSign(params),
+            Request::MusigNonce { params, .. } => RequestParams::MusigNonce(params),
+            Request::MusigPartialSig { params, .. } => RequestParams::MusigPartialSig(params),
+            Request::GetMusigSession { params, .. } => RequestParams::GetMusigSession(params),
+            Request::Subscribe { params, .. } => RequestParams::Subscribe(params),
+            Request::Unsubscribe { params, .. } => RequestParams::Unsubscribe(params),
+            Request::Replicate { params, .. } => RequestParams::Replicate(params),
+            Request::GetEntries { params, .. } => RequestParams::GetEntries(params),
+            Request::WtSyncVaults { params, .. } => RequestParams::WtSyncVaults(params),
+            Request::WtApproveSpend { params, .. } => RequestParams::WtApproveSpend(params),
+            Request::WtRecoverSigs { params, .. } => RequestParams::WtRecoverSigs(params),
+            Request::WtGetSpendPolicy { params, .. } => RequestParams::WtGetSpendPolicy(params),
+            Request::GetTime { params, .. } => RequestParams::GetTime(params),
+            Request::GetRevocationSigs { params, .. } => RequestParams::GetRevocationSigs(params),
+            Request::HasSigs { params, .. } => RequestParams::HasSigs(params),
+            #[cfg(feature = "revault_tx")]
+            Request::GetPubkey { params, .. } => RequestParams::GetPubkey(params),
+            Request::SealedSig { params, .. } => RequestParams::SealedSig(params),
+            Request::GetSealedSigs { params, .. } => RequestParams::GetSealedSigs(params),
+            Request::StoreBlob { params, .. } => RequestParams::StoreBlob(params),
+            Request::GetBlobs { params, .. } => RequestParams::GetBlobs(params),
+            Request::SetSpendTxBegin { params, .. } => RequestParams::SetSpendTxBegin(params),
+            Request::SetSpendTxChunk { params, .. } => RequestParams::SetSpendTxChunk(params),
+            Request::SetSpendTxCommit { params, .. } => RequestParams::SetSpendTxCommit(params),
+            Request::GetSpendTxChunk { params, .. } => RequestParams::GetSpendTxChunk(params),
+        }
+    }
+
+    /// Get the method name of this request
+    pub fn method(&self) -> &str {
+        match self {
+            Request::WtSig { method, .. } => method,
+            Request::WtSchnorrSig { method, .. } => method,
+            Request::SetSpendTx { method, .. } => method,
+            Request::GetSpendTx { method, .. } => method,
+            Request::CoordSig { method, .. } => method,
+            Request::CoordSchnorrSig { method, .. } => method,
+            Request::GetSigs { method, .. } => method,
+            #[cfg(feature = "revault_tx")]
+            Request::Sign { method, .. } => method,
+            #[cfg(feature = "revault_tx")]
+            Request::AckKeyRotation { method, .. } => method,
+            #[cfg(feature = "revault_tx")]
+            Request::BatchSign { method, .. } => method,
+            Request::MusigNonce { method, .. } => method,
+            Request::MusigPartialSig { method, .. } => method,
+            Request::GetMusigSession { method, .. } => method,
+            Request::Subscribe { method, .. } => method,
+            Request::Unsubscribe { method, .. } => method,
+            Request::Replicate { method, .. } => method,
+            Request::GetEntries { method, .. } => method,
+            Request::WtSyncVaults { method, .. } => method,
+            Request::WtApproveSpend { method, .. } => method,
+            Request::WtRecoverSigs { method, .. } => method,
+            Request::WtGetSpendPolicy { method, .. } => method,
+            Request::GetTime { method, .. } => method,
+            Request::GetRevocationSigs { method, .. } => method,
+            Request::HasSigs { method, .. } => method,
+            #[cfg(feature = "revault_tx")]
+            Request::GetPubkey { method, .. } => method,
+            Request::SealedSig { method, .. } => method,
+            Request::GetSealedSigs { method, .. } => method,
+            Request::StoreBlob { method, .. } => method,
+            Request::GetBlobs { method, .. } => method,
+            Request::SetSpendTxBegin { method, .. } => method,
+            Request::SetSpendTxChunk { method, .. } => method,
+            Request::SetSpendTxCommit { method, .. } => method,
+            Request::GetSpendTxChunk { method, .. } => method,
+        }
+    }
+
+    /// Get the id of this request
+    pub fn id(&self) -> u32 {
+        match self {
+            Request::WtSig { id, .. } => *id,
+            Request::WtSchnorrSig { id, .. } => *id,
+            Request::SetSpendTx { id, .. } => *id,
+            Request::GetSpendTx { id, .. } => *id,
+            Request::CoordSig { id, .. } => *id,
+            Request::CoordSchnorrSig { id, .. } => *id,
+            Request::GetSigs { id, .. } => *id,
+            #[cfg(feature = "revault_tx")]
+            Request::Sign { id, .. } => *id,
+            #[cfg(feature = "revault_tx")]
+            Request::AckKeyRotation { id, .. } => *id,
+            #[cfg(feature = "revault_tx")]
+            Request::BatchSign { id, .. } => *id,
+            Request::MusigNonce { id, .. } => *id,
+            Request::MusigPartialSig { id, .. } => *id,
+            Request::GetMusigSession { id, .. } => *id,
+            Request::Subscribe { id, .. } => *id,
+            Request::Unsubscribe { id, .. } => *id,
+            Request::Replicate { id, .. } => *id,
+            Request::GetEntries { id, .. } => *id,
+            Request::WtSyncVaults { id, .. } => *id,
+            Request::WtApproveSpend { id, .. } => *id,
+            Request::WtRecoverSigs { id, .. } => *id,
+            Request::WtGetSpendPolicy { id, .. } => *id,
+            Request::GetTime { id, .. } => *id,
+            Request::GetRevocationSigs { id, .. } => *id,
+            Request::HasSigs { id, .. } => *id,
+            #[cfg(feature = "revault_tx")]
+            Request::GetPubkey { id, .. } => *id,
+            Request::SealedSig { id, .. } => *id,
+            Request::GetSealedSigs { id, .. } => *id,
+            Request::StoreBlob { id, .. } => *id,
+            Request::GetBlobs { id, .. } => *id,
+            Request::SetSpendTxBegin { id, .. } => *id,
+            Request::SetSpendTxChunk { id, .. } => *id,
+            Request::SetSpendTxCommit { id, .. } => *id,
+            Request::GetSpendTxChunk { id, .. } => *id,
+        }
+    }
+}
+
+impl<'a> Request<'a> {
+    // As for v1, get the request for the method of fieldless params
+    fn with_fieldless_params(self) -> Self {
+        let (method, id) = match self {
+            Request::WtGetSpendPolicy { method, id, .. } => (method, id),
+            Request::GetTime { method, id, .. } => (method, id),
+            #[cfg(feature = "revault_tx")]
+            Request::GetPubkey { method, id, .. } => (method, id),
+            request => return request,
+        };
+        match method {
+            #[cfg(feature = "revault_tx")]
+            method::GET_PUBKEY => Request::GetPubkey {
+                method,
+                params: cosigner::GetPubkey {},
+                id,
+            },
+            method::GET_TIME => Request::GetTime {
+                method,
+                params: coordinator::GetTime {},
+                id,
+            },
+            _ => Request::WtGetSpendPolicy {
+                method,
+                params: watchtower::GetSpendPolicy {},
+                id,
+            },
+        }
+    }
+
+    // As for v1, get the request for the method of lookalike params
+    fn with_lookalike_params(self) -> Self {
+        match self {
+            Request::GetSigs {
+                method: method @ method::HAS_SIGS,
+                params:
+                    coordinator::GetSigs {
+                        txid,
+                        if_none_match: None,
+                    },
+                id,
+            } => Request::HasSigs {
+                method,
+                params: coordinator::HasSigs { txid },
+                id,
+            },
+            Request::GetSpendTx {
+                method: method @ method::RECOVER_SIGS,
+                params: coordinator::GetSpendTx { deposit_outpoint },
+                id,
+            } => Request::WtRecoverSigs {
+                method,
+                params: watchtower::RecoverSigs { deposit_outpoint },
+                id,
+            },
+            request => request,
+        }
+    }
+}
+
+impl Serialize for Request<'_> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Request::serialize(self, serializer)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Request<'a> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let request = Request::deserialize(deserializer)?
+            .with_fieldless_params()
+            .with_lookalike_params();
+        let expected = match request {
+            Request::WtSig { .. } => method::SIG,
+            Request::WtSchnorrSig { .. } => method::SIG,
+            Request::SetSpendTx { .. } => method::SET_SPEND_TX,
+            Request::GetSpendTx { .. } => method::GET_SPEND_TX,
+            Request::CoordSig { .. } => method::SIG,
+            Request::CoordSchnorrSig { .. } => method::SIG,
+            Request::GetSigs { .. } => method::GET_SIGS,
+            #[cfg(feature = "revault_tx")]
+            Request::Sign { .. } => method::SIGN,
+            #[cfg(feature = "revault_tx")]
+            Request::AckKeyRotation { .. } => method::ACK_KEY_ROTATION,
+            #[cfg(feature = "revault_tx")]
+            Request::BatchSign { .. } => method::SIGN,
+            Request::MusigNonce { .. } => method::MUSIG_NONCE,
+            Request::MusigPartialSig { .. } => method::MUSIG_PARTIAL_SIG,
+            Request::GetMusigSession { .. } => method::GET_MUSIG_SESSION,
+            Request::Subscribe { .. } => method::SUBSCRIBE,
+            Request::Unsubscribe { .. } => method::UNSUBSCRIBE,
+            Request::Replicate { .. } => method::REPLICATE,
+            Request::GetEntries { .. } => method::GET_ENTRIES,
+            Request::WtSyncVaults { .. } => method::SYNC_VAULTS,
+            Request::WtApproveSpend { .. } => method::APPROVE_SPEND,
+            Request::WtRecoverSigs { .. } => method::RECOVER_SIGS,
+            Request::WtGetSpendPolicy { .. } => method::GET_SPEND_POLICY,
+            Request::GetTime { .. } => method::GET_TIME,
+            Request::GetRevocationSigs { .. } => method::GET_REVOCATION_SIGS,
+            Request::HasSigs { .. } => method::HAS_SIGS,
+            #[cfg(feature = "revault_tx")]
+            Request::GetPubkey { .. } => method::GET_PUBKEY,
+            Request::SealedSig { .. } => method::SEALED_SIG,
+            Request::GetSealedSigs { .. } => method::GET_SEALED_SIGS,
+            Request::StoreBlob { .. } => method::STORE_BLOB,
+            Request::GetBlobs { .. } => method::GET_BLOBS,
+            Request::SetSpendTxBegin { .. } => method::SET_SPEND_TX_BEGIN,
+            Request::SetSpendTxChunk { .. } => method::SET_SPEND_TX_CHUNK,
+            Request::SetSpendTxCommit { .. } => method::SET_SPEND_TX_COMMIT,
+            Request::GetSpendTxChunk { .. } => method::GET_SPEND_TX_CHUNK,
+        };
+        if request.method() != expected {
+            return Err(de::Error::custom(MessageError::MethodMismatch {
+                method: request.method().to_string(),
+                expected,
+            }));
+        }
+
+        Ok(request)
+    }
+}
+
+// The revision of a request, peeked at before parsing it
+#[derive(Deserialize)]
+struct VersionPeek<'a> {
+    #[serde(borrow)]
+    params: &'a RawValue,
+}
+
+#[derive(Deserialize)]
+struct ParamsVersion {
+    version: Option<u32>,
+}
+
+// The revision of a serialized request: the `version` field of its params, if any,
+// otherwise v1
+pub(crate) fn request_version(raw: &[u8]) -> Result<u32, serde_json::Error> {
+    let params = serde_json::from_slice::<VersionPeek>(raw)?.params;
+    // Params that are not an object can't tell
+    Ok(serde_json::from_str::<ParamsVersion>(params.get())
+        .ok()
+        .and_then(|params| params.version)
+        .unwrap_or(v1::VERSION))
+}
+
+impl<'a> Request<'a> {
+    /// Parse a request of any revision, upgrading it to v2 if it is an older one. Its
+    /// revision is the `version` field of its params, if any, otherwise it is a v1
+    /// request.
+    pub fn from_versioned_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let version = request_version(raw).map_err(|e| Error::Json(JsonError::locate(e, raw)))?;
+
+        match version {
+            v1::VERSION => Ok(from_json_slice::<v1::Request>(raw)?.into()),
+            VERSION => from_json_slice(raw),
+            version => Err(MessageError::UnknownVersion(version).into()),
+        }
+    }
+
+    /// Serialize this request with its revision as the `version` of its params, to be
+    /// parsed with [Request::from_versioned_slice].
+    pub fn to_versioned_vec(&self) -> Vec<u8> {
+        let mut value = serde_json::to_value(self).expect("Messages always serialize");
+        if let Some(params) = value.get_mut("params").and_then(|p| p.as_object_mut()) {
+            params.insert("version".to_string(), VERSION.into());
+        }
+        serde_json::to_vec(&value).expect("Messages always serialize")
+    }
+}
+
+impl<'a> From<v1::Request<'a>> for Request<'a> {
+    fn from(request: v1::Request<'a>) -> Self {
+        match request {
+            v1::Request::WtSig { method, params, id } => Request::WtSig { method, params, id },
+            v1::Request::WtSchnorrSig { method, params, id } => {
+                Request::WtSchnorrSig { method, params, id }
+            }
+            v1::Request::SetSpendTx { method, params, id } => {
+                Request::SetSpendTx { method, params, id }
+            }
+            v1::Request::GetSpendTx { method, params, id } => {
+                Request::GetSpendTx { method, params, id }
+            }
+            v1::Request::CoordSig { method, params, id } => Request::CoordSig {
+                method,
+                params: params.into(),
+                id,
+            },
+            v1::Request::CoordSchnorrSig { method, params, id } => Request::CoordSchnorrSig {
+                method,
+                params: params.into(),
+                id,
+            },
+            v1::Request::GetSigs { method, params, id } => Request::GetSigs {
+                method,
+                params: params.into(),
+                id,
+            },
+            #[cfg(feature = "revault_tx")]
+            v1::Request::Sign { method, params, id } => Request::Sign { method, params, id },
+            #[cfg(feature = "revault_tx")]
+            v1::Request::AckKeyRotation { method, params, id } => {
+                Request::AckKeyRotation { method, params, id }
+            }
+            #[cfg(feature = "revault_tx")]
+            v1::Request::BatchSign { method, params, id } => {
+                Request::BatchSign { method, params, id }
+            }
+            v1::Request::MusigNonce { method, params, id } => {
+                Request::MusigNonce { method, params, id }
+            }
+            v1::Request::MusigPartialSig { method, params, id } => {
+                Request::MusigPartialSig { method, params, id }
+            }
+            v1::Request::GetMusigSession { method, params, id } => {
+                Request::GetMusigSession { method, params, id }
+            }
+            v1::Request::Subscribe { method, params, id } => {
+                Request::Subscribe { method, params, id }
+            }
+            v1::Request::Unsubscribe { method, params, id } => {
+                Request::Unsubscribe { method, params, id }
+            }
+            v1::Request::Replicate { method, params, id } => {
+                Request::Replicate { method, params, id }
+            }
+            v1::Request::GetEntries { method, params, id } => {
+                Request::GetEntries { method, params, id }
+            }
+            v1::Request::WtSyncVaults { method, params, id } => {
+                Request::WtSyncVaults { method, params, id }
+            }
+            v1::Request::WtApproveSpend { method, params, id } => {
+                Request::WtApproveSpend { method, params, id }
+            }
+            v1::Request::WtRecoverSigs { method, params, id } => {
+                Request::WtRecoverSigs { method, params, id }
+            }
+            v1::Request::WtGetSpendPolicy { method, params, id } => {
+                Request::WtGetSpendPolicy { method, params, id }
+            }
+            v1::Request::GetTime { method, params, id } => Request::GetTime { method, params, id },
+            v1::Request::GetRevocationSigs { method, params, id } => {
+                Request::GetRevocationSigs { method, params, id }
+            }
+            v1::Request::HasSigs { method, params, id } => Request::HasSigs {
+                method,
+                params: params.into(),
+                id,
+            },
+            #[cfg(feature = "revault_tx")]
+            v1::Request::GetPubkey { method, params, id } => {
+                Request::GetPubkey { method, params, id }
+            }
+            v1::Request::SealedSig { method, params, id } => {
+                Request::SealedSig { method, params, id }
+            }
+            v1::Request::GetSealedSigs { method, params, id } => {
+                Request::GetSealedSigs { method, params, id }
+            }
+            v1::Request::StoreBlob { method, params, id } => {
+                Request::StoreBlob { method, params, id }
+            }
+            v1::Request::GetBlobs { method, params, id } => {
+                Request::GetBlobs { method, params, id }
+            }
+            v1::Request::SetSpendTxBegin { method, params, id } => {
+                Request::SetSpendTxBegin { method, params, id }
+            }
+            v1::Request::SetSpendTxChunk { method, params, id } => {
+                Request::SetSpendTxChunk { method, params, id }
+            }
+            v1::Request::SetSpendTxCommit { method, params, id } => {
+                Request::SetSpendTxCommit { method, params, id }
+            }
+            v1::Request::GetSpendTxChunk { method, params, id } => {
+                Request::GetSpendTxChunk { method, params, id }
+            }
+        }
+    }
+}
+
+impl<'a> From<Request<'a>> for v1::Request<'a> {
+    fn from(request: Request<'a>) -> Self {
+        match request {
+            Request::WtSig { method, params, id } => v1::Request::WtSig { method, params, id },
+            Request::WtSchnorrSig { method, params, id } => {
+                v1::Request::WtSchnorrSig { method, params, id }
+            }
+            Request::SetSpendTx { method, params, id } => {
+                v1::Request::SetSpendTx { method, params, id }
+            }
+            Request::GetSpendTx { method, params, id } => {
+                v1::Request::GetSpendTx { method, params, id }
+            }
+            Request::CoordSig { method, params, id } => v1::Request::CoordSig {
+                method,
+                params: params.into(),
+                id,
+            },
+            Request::CoordSchnorrSig { method, params, id } => v1::Request::CoordSchnorrSig {
+                method,
+                params: params.into(),
+                id,
+            },
+            Request::GetSigs { method, params, id } => v1::Request::GetSigs {
+                method,
+                params: params.into(),
+                id,
+            },
+            #[cfg(feature = "revault_tx")]
+            Request::Sign { method, params, id } => v1::Request::Sign { method, params, id },
+            #[cfg(feature = "revault_tx")]
+            Request::AckKeyRotation { method, params, id } => {
+                v1::Request::AckKeyRotation { method, params, id }
+            }
+            #[cfg(feature = "revault_tx")]
+            Request::BatchSign { method, params, id } => {
+                v1::Request::BatchSign { method, params, id }
+            }
+            Request::MusigNonce { method, params, id } => {
+                v1::Request::MusigNonce { method, params, id }
+            }
+            Request::MusigPartialSig { method, params, id } => {
+                v1::Request::MusigPartialSig { method, params, id }
+            }
+            Request::GetMusigSession { method, params, id } => {
+                v1::Request::GetMusigSession { method, params, id }
+            }
+            Request::Subscribe { method, params, id } => {
+                v1::Request::Subscribe { method, params, id }
+            }
+            Request::Unsubscribe { method, params, id } => {
+                v1::Request::Unsubscribe { method, params, id }
+            }
+            Request::Replicate { method, params, id } => {
+                v1::Request::Replicate { method, params, id }
+            }
+            Request::GetEntries { method, params, id } => {
+                v1::Request::GetEntries { method, params, id }
+            }
+            Request::WtSyncVaults { method, params, id } => {
+                v1::Request::WtSyncVaults { method, params, id }
+            }
+            Request::WtApproveSpend { method, params, id } => {
+                v1::Request::WtApproveSpend { method, params, id }
+            }
+            Request::WtRecoverSigs { method, params, id } => {
+                v1::Request::WtRecoverSigs { method, params, id }
+            }
+            Request::WtGetSpendPolicy { method, params, id } => {
+                v1::Request::WtGetSpendPolicy { method, params, id }
+            }
+            Request::GetTime { method, params, id } => v1::Request::GetTime { method, params, id },
+            Request::GetRevocationSigs { method, params, id } => {
+                v1::Request::GetRevocationSigs { method, params, id }
+            }
+            Request::HasSigs { method, params, id } => v1::Request::HasSigs {
+                method,
+                params: params.into(),
+                id,
+            },
+            #[cfg(feature = "revault_tx")]
+            Request::GetPubkey { method, params, id } => {
+                v1::Request::GetPubkey { method, params, id }
+            }
+            Request::SealedSig { method, params, id } => {
+                v1::Request::SealedSig { method, params, id }
+            }
+            Request::GetSealedSigs { method, params, id } => {
+                v1::Request::GetSealedSigs { method, params, id }
+            }
+            Request::StoreBlob { method, params, id } => {
+                v1::Request::StoreBlob { method, params, id }
+            }
+            Request::GetBlobs { method, params, id } => {
+                v1::Request::GetBlobs { method, params, id }
+            }
+            Request::SetSpendTxBegin { method, params, id } => {
+                v1::Request::SetSpendTxBegin { method, params, id }
+            }
+            Request::SetSpendTxChunk { method, params, id } => {
+                v1::Request::SetSpendTxChunk { method, params, id }
+            }
+            Request::SetSpendTxCommit { method, params, id } => {
+                v1::Request::SetSpendTxCommit { method, params, id }
+            }
+            Request::GetSpendTxChunk { method, params, id } => {
+                v1::Request::GetSpendTxChunk { method, params, id }
+            }
+        }
+    }
+}
+
+/// All params types that can possibly be sent through a v2 [Request]
+#[allow(missing_docs)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum RequestParams {
+    WtSig(watchtower::Sig),
+    WtSchnorrSig(watchtower::SchnorrSig),
+    SetSpendTx(coordinator::SetSpendTx),
+    GetSpendTxChunk(coordinator::GetSpendTxChunk),
+    GetSpendTx(coordinator::GetSpendTx),
+    CoordSig(coordinator::Sig),
+    CoordSchnorrSig(coordinator::SchnorrSig),
+    GetSigs(coordinator::GetSigs),
+    #[cfg(feature = "revault_tx")]
+    Sign(cosigner::SignRequest),
+    #[cfg(feature = "revault_tx")]
+    AckKeyRotation(cosigner::AckKeyRotation),
+    #[cfg(feature = "revault_tx")]
+    BatchSign(cosigner::BatchSignRequest),
+    MusigNonce(coordinator::MusigNonce),
+    MusigPartialSig(coordinator::MusigPartialSig),
+    SealedSig(coordinator::SealedSig),
+    GetSealedSigs(coordinator::GetSealedSigs),
+    StoreBlob(coordinator::StoreBlob),
+    GetBlobs(coordinator::GetBlobs),
+    SetSpendTxBegin(coordinator::SetSpendTxBegin),
+    SetSpendTxChunk(coordinator::SetSpendTxChunk),
+    SetSpendTxCommit(coordinator::SetSpendTxCommit),
+    GetMusigSession(coordinator::GetMusigSession),
+    Subscribe(coordinator::Subscribe),
+    Unsubscribe(coordinator::Unsubscribe),
+    Replicate(replication::Replicate),
+    GetEntries(replication::GetEntries),
+    WtSyncVaults(watchtower::SyncVaults),
+    WtApproveSpend(watchtower::ApproveSpend),
+    WtRecoverSigs(watchtower::RecoverSigs),
+    WtGetSpendPolicy(watchtower::GetSpendPolicy),
+    GetTime(coordinator::GetTime),
+    GetRevocationSigs(coordinator::GetRevocationSigs),
+    HasSigs(coordinator::HasSigs),
+    #[cfg(feature = "revault_tx")]
+    GetPubkey(cosigner::GetPubkey),
+}
+
+impl From<v1::RequestParams> for RequestParams {
+    fn from(params: v1::RequestParams) -> Self {
+        match params {
+            v1::RequestParams::WtSig(params) => RequestParams::WtSig(params),
+            v1::RequestParams::WtSchnorrSig(params) => RequestParams::WtSchnorrSig(params),
+            v1::RequestParams::SetSpendTx(params) => RequestParams::SetSpendTx(params),
+            v1::RequestParams::GetSpendTx(params) => RequestParams::GetSpendTx(params),
+            v1::RequestParams::CoordSig(params) => RequestParams::CoordSig(params.into()),
+            v1::RequestParams::CoordSchnorrSig(params) => {
+                RequestParams::CoordSchnorrSig(params.into())
+            }
+            v1::RequestParams::GetSigs(params) => RequestParams::GetSigs(params.into()),
+            #[cfg(feature = "revault_tx")]
+            v1::RequestParams::Sign(params) => RequestParams::Sign(params),
+            #[cfg(feature = "revault_tx")]
+            v1::RequestParams::AckKeyRotation(params) => RequestParams::AckKeyRotation(params),
+            #[cfg(feature = "revault_tx")]
+            v1::RequestParams::BatchSign(params) => RequestParams::BatchSign(params),
+            v1::RequestParams::MusigNonce(params) => RequestParams::MusigNonce(params),
+            v1::RequestParams::MusigPartialSig(params) => RequestParams::MusigPartialSig(params),
+            v1::RequestParams::GetMusigSession(params) => RequestParams::GetMusigSession(params),
+            v1::RequestParams::Subscribe(params) => RequestParams::Subscribe(params),
+            v1::RequestParams::Unsubscribe(params) => RequestParams::Unsubscribe(params),
+            v1::RequestParams::Replicate(params) => RequestParams::Replicate(params),
+            v1::RequestParams::GetEntries(params) => RequestParams::GetEntries(params),
+            v1::RequestParams::WtSyncVaults(params) => RequestParams::WtSyncVaults(params),
+            v1::RequestParams::WtApproveSpend(params) => RequestParams::WtApproveSpend(params),
+            v1::RequestParams::WtRecoverSigs(params) => RequestParams::WtRecoverSigs(params),
+            v1::RequestParams::WtGetSpendPolicy(params) => RequestParams::WtGetSpendPolicy(params),
+            v1::RequestParams::GetTime(params) => RequestParams::GetTime(params),
+            v1::RequestParams::GetRevocationSigs(params) => {
+                RequestParams::GetRevocationSigs(params)
+            }
+            v1::RequestParams::HasSigs(params) => RequestParams::HasSigs(params.into()),
+            #[cfg(feature = "revault_tx")]
+            v1::RequestParams::GetPubkey(params) => RequestParams::GetPubkey(params),
+            v1::RequestParams::SealedSig(params) => RequestParams::SealedSig(params),
+            v1::RequestParams::GetSealedSigs(params) => RequestParams::GetSealedSigs(params),
+            v1::RequestParams::StoreBlob(params) => RequestParams::StoreBlob(params),
+            v1::RequestParams::GetBlobs(params) => RequestParams::GetBlobs(params),
+            v1::RequestParams::SetSpendTxBegin(params) => RequestParams::SetSpendTxBegin(params),
+            v1::RequestParams::SetSpendTxChunk(params) => RequestParams::SetSpendTxChunk(params),
+            v1::RequestParams::SetSpendTxCommit(params) => RequestParams::SetSpendTxCommit(params),
+            v1::RequestParams::GetSpendTxChunk(params) => RequestParams::GetSpendTxChunk(params),
+        }
+    }
+}
+
+impl From<RequestParams> for v1::RequestParams {
+    fn from(params: RequestParams) -> Self {
+        match params {
+            RequestParams::WtSig(params) => v1::RequestParams::WtSig(params),
+            RequestParams::WtSchnorrSig(params) => v1::RequestParams::WtSchnorrSig(params),
+            RequestParams::SetSpendTx(params) => v1::RequestParams::SetSpendTx(params),
+            RequestParams::GetSpendTx(params) => v1::RequestParams::GetSpendTx(params),
+            RequestParams::CoordSig(params) => v1::RequestParams::CoordSig(params.into()),
+            RequestParams::CoordSchnorrSig(params) => {
+                v1::RequestParams::CoordSchnorrSig(params.into())
+            }
+            RequestParams::GetSigs(params) => v1::RequestParams::GetSigs(params.into()),
+            #[cfg(feature = "revault_tx")]
+            RequestParams::Sign(params) => v1::RequestParams::Sign(params),
+            #[cfg(feature = "revault_tx")]
+            RequestParams::AckKeyRotation(params) => v1::RequestParams::AckKeyRotation(params),
+            #[cfg(feature = "revault_tx")]
+            RequestParams::BatchSign(params) => v1::RequestParams::BatchSign(params),
+            RequestParams::MusigNonce(params) => v1::RequestParams::MusigNonce(params),
+            RequestParams::MusigPartialSig(params) => v1::RequestParams::MusigPartialSig(params),
+            RequestParams::GetMusigSession(params) => v1::RequestParams::GetMusigSession(params),
+            RequestParams::Subscribe(params) => v1::RequestParams::Subscribe(params),
+            RequestParams::Unsubscribe(params) => v1::RequestParams::Unsubscribe(params),
+            RequestParams::Replicate(params) => v1::RequestParams::Replicate(params),
+            RequestParams::GetEntries(params) => v1::RequestParams::GetEntries(params),
+            RequestParams::WtSyncVaults(params) => v1::RequestParams::WtSyncVaults(params),
+            RequestParams::WtApproveSpend(params) => v1::RequestParams::WtApproveSpend(params),
+            RequestParams::WtRecoverSigs(params) => v1::RequestParams::WtRecoverSigs(params),
+            RequestParams::WtGetSpendPolicy(params) => v1::RequestParams::WtGetSpendPolicy(params),
+            RequestParams::GetTime(params) => v1::RequestParams::GetTime(params),
+            RequestParams::GetRevocationSigs(params) => {
+                v1::RequestParams::GetRevocationSigs(params)
+            }
+            RequestParams::HasSigs(params) => v1::RequestParams::HasSigs(params.into()),
+            #[cfg(feature = "revault_tx")]
+            RequestParams::GetPubkey(params) => v1::RequestParams::GetPubkey(params),
+            RequestParams::SealedSig(params) => v1::RequestParams::SealedSig(params),
+            RequestParams::GetSealedSigs(params) => v1::RequestParams::GetSealedSigs(params),
+            RequestParams::StoreBlob(params) => v1::RequestParams::StoreBlob(params),
+            RequestParams::GetBlobs(params) => v1::RequestParams::GetBlobs(params),
+            RequestParams::SetSpendTxBegin(params) => v1::RequestParams::SetSpendTxBegin(params),
+            RequestParams::SetSpendTxChunk(params) => v1::RequestParams::SetSpendTxChunk(params),
+            RequestParams::SetSpendTxCommit(params) => v1::RequestParams::SetSpendTxCommit(params),
+            RequestParams::GetSpendTxChunk(params) => v1::RequestParams::GetSpendTxChunk(params),
+        }
+    }
+}
+
+/// A JSONRPC-like notification, as [v1::Notification] with the v2 messages.
+#[allow(missing_docs)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Notification<'a> {
+    NewSig {
+        method: &'a str,
+        params: coordinator::NewSigEvent,
+    },
+    NewSpendTx {
+        method: &'a str,
+        params: coordinator::NewSpendTxEvent,
+    },
+    #[cfg(feature = "revault_tx")]
+    KeyRotation {
+        method: &'a str,
+        params: cosigner::KeyRotation,
+    },
+}
+
+impl<'a> Notification<'a> {
+    /// Get the method name of this notification
+    pub fn method(&self) -> &str {
+        match self {
+            Notification::NewSig { method, .. } => method,
+            Notification::NewSpendTx { method, .. } => method,
+            #[cfg(feature = "revault_tx")]
+            Notification::KeyRotation { method, .. } => method,
+        }
+    }
+
+    /// Get the parameters of this notification
+    pub fn params(self) -> NotificationParams {
+        match self {
+            Notification::NewSig { params, .. } => NotificationParams::NewSig(params),
+            Notification::NewSpendTx { params, .. } => NotificationParams::NewSpendTx(params),
+            #[cfg(feature = "revault_tx")]
+            Notification::KeyRotation { params, .. } => NotificationParams::KeyRotation(params),
+        }
+    }
+}
+
+impl<'a> From<v1::Notification<'a>> for Notification<'a> {
+    fn from(notification: v1::Notification<'a>) -> Self {
+        match notification {
+            v1::Notification::NewSig { method, params } => Notification::NewSig {
+                method,
+                params: params.into(),
+            },
+            v1::Notification::NewSpendTx { method, params } => {
+                Notification::NewSpendTx { method, params }
+            }
+            #[cfg(feature = "revault_tx")]
+            v1::Notification::KeyRotation { method, params } =>
+            {
+                #[cfg(feature = "revault_tx")]
+                Notification::KeyRotation { method, params }
+            }
+        }
+    }
+}
+
+impl<'a> From<Notification<'a>> for v1::Notification<'a> {
+    fn from(notification: Notification<'a>) -> Self {
+        match notification {
+            Notification::NewSig { method, params } => v1::Notification::NewSig {
+                method,
+                params: params.into(),
+            },
+            Notification::NewSpendTx { method, params } => {
+                v1::Notification::NewSpendTx { method, params }
+            }
+            #[cfg(feature = "revault_tx")]
+            Notification::KeyRotation { method, params } =>
+            {
+                #[cfg(feature = "revault_tx")]
+                v1::Notification::KeyRotation { method, params }
+            }
+        }
+    }
+}
+
+/// All params types that can possibly be sent through a v2 [Notification]
+#[allow(missing_docs)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum NotificationParams {
+    NewSig(coordinator::NewSigEvent),
+    NewSpendTx(coordinator::NewSpendTxEvent),
+    #[cfg(feature = "revault_tx")]
+    KeyRotation(cosigner::KeyRotation),
+}
+
+impl From<v1::NotificationParams> for NotificationParams {
+    fn from(params: v1::NotificationParams) -> Self {
+        match params {
+            v1::NotificationParams::NewSig(params) => NotificationParams::NewSig(params.into()),
+            v1::NotificationParams::NewSpendTx(params) => NotificationParams::NewSpendTx(params),
+            #[cfg(feature = "revault_tx")]
+            v1::NotificationParams::KeyRotation(params) => NotificationParams::KeyRotation(params),
+        }
+    }
+}
+
+impl From<NotificationParams> for v1::NotificationParams {
+    fn from(params: NotificationParams) -> Self {
+        match params {
+            NotificationParams::NewSig(params) => v1::NotificationParams::NewSig(params.into()),
+            NotificationParams::NewSpendTx(params) => v1::NotificationParams::NewSpendTx(params),
+            #[cfg(feature = "revault_tx")]
+            NotificationParams::KeyRotation(params) => v1::NotificationParams::KeyRotation(params),
+        }
+    }
+}
+
+// The v1 messages that v2 keeps
+impl_to_request!(watchtower::Sig, method::SIG, WtSig);
+impl_to_request!(watchtower::SchnorrSig, method::SIG, WtSchnorrSig);
+impl_to_request!(coordinator::SetSpendTx, method::SET_SPEND_TX, SetSpendTx);
+impl_to_request!(coordinator::GetSpendTx, method::GET_SPEND_TX, GetSpendTx);
+#[cfg(feature = "revault_tx")]
+impl_to_request!(cosigner::SignRequest, method::SIGN, Sign);
+#[cfg(feature = "revault_tx")]
+impl_to_request!(cosigner::BatchSignRequest, method::SIGN, BatchSign);
+#[cfg(feature = "revault_tx")]
+impl_to_request!(
+    cosigner::AckKeyRotation,
+    method::ACK_KEY_ROTATION,
+    AckKeyRotation
+);
+#[cfg(feature = "revault_tx")]
+impl_to_request!(cosigner::GetPubkey, method::GET_PUBKEY, GetPubkey);
+#[cfg(feature = "revault_tx")]
+impl_to_notification!(cosigner::KeyRotation, method::KEY_ROTATION, KeyRotation);
+impl_to_request!(coordinator::MusigNonce, method::MUSIG_NONCE, MusigNonce);
+impl_to_request!(
+    coordinator::MusigPartialSig,
+    method::MUSIG_PARTIAL_SIG,
+    MusigPartialSig
+);
+impl_to_request!(
+    coordinator::GetMusigSession,
+    method::GET_MUSIG_SESSION,
+    GetMusigSession
+);
+impl_to_request!(coordinator::Subscribe, method::SUBSCRIBE, Subscribe);
+impl_to_request!(coordinator::Unsubscribe, method::UNSUBSCRIBE, Unsubscribe);
+impl_to_request!(replication::Replicate, method::REPLICATE, Replicate);
+impl_to_request!(replication::GetEntries, method::GET_ENTRIES, GetEntries);
+impl_to_request!(watchtower::SyncVaults, method::SYNC_VAULTS, WtSyncVaults);
+impl_to_request!(
+    watchtower::GetSpendPolicy,
+    method::GET_SPEND_POLICY,
+    WtGetSpendPolicy
+);
+impl_to_request!(
+    watchtower::ApproveSpend,
+    method::APPROVE_SPEND,
+    WtApproveSpend
+);
+impl_to_request!(watchtower::RecoverSigs, method::RECOVER_SIGS, WtRecoverSigs);
+impl_to_request!(coordinator::SealedSig, method::SEALED_SIG, SealedSig);
+impl_to_request!(coordinator::StoreBlob, method::STORE_BLOB, StoreBlob);
+impl_to_request!(coordinator::GetBlobs, method::GET_BLOBS, GetBlobs);
+impl_to_request!(
+    coordinator::SetSpendTxBegin,
+    method::SET_SPEND_TX_BEGIN,
+    SetSpendTxBegin
+);
+impl_to_request!(
+    coordinator::SetSpendTxChunk,
+    method::SET_SPEND_TX_CHUNK,
+    SetSpendTxChunk
+);
+impl_to_request!(
+    coordinator::SetSpendTxCommit,
+    method::SET_SPEND_TX_COMMIT,
+    SetSpendTxCommit
+);
+impl_to_request!(
+    coordinator::GetSpendTxChunk,
+    method::GET_SPEND_TX_CHUNK,
+    GetSpendTxChunk
+);
+impl_to_request!(
+    coordinator::GetSealedSigs,
+    method::GET_SEALED_SIGS,
+    GetSealedSigs
+);
+impl_to_request!(coordinator::GetTime, method::GET_TIME, GetTime);
+impl_to_request!(
+    coordinator::GetRevocationSigs,
+    method::GET_REVOCATION_SIGS,
+    GetRevocationSigs
+);
+
+/// Messages related to the communication with the Coordinator. The messages v2
+/// doesn't change are re-exported from v1.
+pub mod coordinator {
+    use super::{method, Deserialize, Notification, Request, Serialize};
+    use crate::message::v1;
+
+    use bitcoin::{
+        hash_types::Txid,
+        secp256k1::{key::PublicKey, schnorrsig, Signature},
+    };
+
+    pub use crate::message::coordinator::*;
+
+    /// Sent by a wallet to retrieve all signatures for a specific transaction
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct GetSigs {
+        /// Transaction id
+        pub txid: Txid,
+        /// See [v1::coordinator::GetSigs::if_none_match]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub if_none_match: Option<SigsTag>,
+    }
+    impl_to_request!(GetSigs, method::GET_SIGS, GetSigs);
+
+    impl From<v1::coordinator::GetSigs> for GetSigs {
+        fn from(msg: v1::coordinator::GetSigs) -> Self {
+            Self {
+                txid: msg.id,
+                if_none_match: msg.if_none_match,
+            }
+        }
+    }
+
+    impl From<GetSigs> for v1::coordinator::GetSigs {
+        fn from(msg: GetSigs) -> Self {
+            Self {
+                id: msg.txid,
+                if_none_match: msg.if_none_match,
+            }
+        }
+    }
+
+    /// Sent by a wallet to know how many signatures the coordinator has for a
+    /// transaction, as [v1::coordinator::HasSigs]
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+    pub struct HasSigs {
+        /// Transaction id
+        pub txid: Txid,
+    }
+    impl_to_request!(HasSigs, method::HAS_SIGS, HasSigs);
+
+    impl From<v1::coordinator::HasSigs> for HasSigs {
+        fn from(msg: v1::coordinator::HasSigs) -> Self {
+            Self { txid: msg.id }
+        }
+    }
+
+    impl From<HasSigs> for v1::coordinator::HasSigs {
+        fn from(msg: HasSigs) -> Self {
+            Self { id: msg.txid }
+        }
+    }
+
+    /// Message from a stakeholder client to sync server to share (at any time)
+    /// the signature for a revocation transaction with all participants.
+    #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+    pub struct Sig {
+        /// Secp256k1 public key used to sign the transaction (hex)
+        #[serde(deserialize_with = "crate::message::serde_pubkey::deserialize")]
+        pub pubkey: PublicKey,
+        /// Bitcoin ECDSA signature as hex
+        #[serde(deserialize_with = "crate::message::serde_sig::deserialize")]
+        pub signature: Signature,
+        /// Txid of the transaction the signature applies to
+        pub txid: Txid,
+    }
+    impl_to_request!(Sig, method::SIG, CoordSig);
+    impl_hash_with_sigs!(Sig, [pubkey, txid], [signature]);
+
+    impl From<v1::coordinator::Sig> for Sig {
+        fn from(msg: v1::coordinator::Sig) -> Self {
+            Self {
+                pubkey: msg.pubkey,
+                signature: msg.signature,
+                txid: msg.id,
+            }
+        }
+    }
+
+    impl From<Sig> for v1::coordinator::Sig {
+        fn from(msg: Sig) -> Self {
+            Self {
+                pubkey: msg.pubkey,
+                signature: msg.signature,
+                id: msg.txid,
+            }
+        }
+    }
+
+    /// The Taproot counterpart of [Sig], sharing a BIP340 Schnorr signature for an
+    /// x-only public key.
+    #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+    pub struct SchnorrSig {
+        /// X-only public key used to sign the transaction (hex)
+        pub pubkey: schnorrsig::PublicKey,
+        /// BIP340 Schnorr signature as hex
+        pub signature: schnorrsig::Signature,
+        /// Txid of the transaction the signature applies to
+        pub txid: Txid,
+    }
+    impl_to_request!(SchnorrSig, method::SIG, CoordSchnorrSig);
+    impl_hash_with_sigs!(SchnorrSig, [pubkey, txid], [signature]);
+
+    impl From<v1::coordinator::SchnorrSig> for SchnorrSig {
+        fn from(msg: v1::coordinator::SchnorrSig) -> Self {
+            Self {
+                pubkey: msg.pubkey,
+                signature: msg.signature,
+                txid: msg.id,
+            }
+        }
+    }
+
+    impl From<SchnorrSig> for v1::coordinator::SchnorrSig {
+        fn from(msg: SchnorrSig) -> Self {
+            Self {
+                pubkey: msg.pubkey,
+                signature: msg.signature,
+                id: msg.txid,
+            }
+        }
+    }
+
+    /// Notification sent by the coordinator to a subscribed wallet when it
+    /// stores a new signature for a watched transaction.
+    #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+    pub struct NewSigEvent {
+        /// Subscription this notification is sent for
+        pub subscription_id: u32,
+        /// Secp256k1 public key used to sign the transaction (hex)
+        #[serde(deserialize_with = "crate::message::serde_pubkey::deserialize")]
+        pub pubkey: PublicKey,
+        /// Bitcoin ECDSA signature as hex
+        #[serde(deserialize_with = "crate::message::serde_sig::deserialize")]
+        pub signature: Signature,
+        /// Txid of the transaction the signature applies to
+        pub txid: Txid,
+    }
+    impl_to_notification!(NewSigEvent, method::NEW_SIG, NewSig);
+    impl_to_notification!(NewSpendTxEvent, method::NEW_SPEND_TX, NewSpendTx);
+    impl_hash_with_sigs!(NewSigEvent, [subscription_id, pubkey, txid], [signature]);
+
+    impl From<v1::coordinator::NewSigEvent> for NewSigEvent {
+        fn from(msg: v1::coordinator::NewSigEvent) -> Self {
+            Self {
+                subscription_id: msg.subscription_id,
+                pubkey: msg.pubkey,
+                signature: msg.signature,
+                txid: msg.id,
+            }
+        }
+    }
+
+    impl From<NewSigEvent> for v1::coordinator::NewSigEvent {
+        fn from(msg: NewSigEvent) -> Self {
+            Self {
+                subscription_id: msg.subscription_id,
+                pubkey: msg.pubkey,
+                signature: msg.signature,
+                id: msg.txid,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{coordinator, v1, Notification, Request, RequestParams};
+    use crate::{
+        error::{Error, MessageError},
+        message::{with_id_generator, SequentialIds},
+    };
+
+    use bitcoin::{
+        hash_types::Txid,
+        secp256k1::{
+            key::{PublicKey, SecretKey},
+            Message, Secp256k1,
+        },
+    };
+
+    #[test]
+    fn v1_conversions() {
+        let txid = Txid::default();
+        let req: Request = with_id_generator(SequentialIds(3), || {
+            coordinator::GetSigs {
+                txid,
+                if_none_match: None,
+            }
+            .into()
+        });
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            r#"{"method":"get_sigs","params":{"txid":"0000000000000000000000000000000000000000000000000000000000000000"},"id":3}"#
+        );
+        let req_str = serde_json::to_string(&req).unwrap();
+        let parsed: Request = serde_json::from_str(&req_str).unwrap();
+        assert_eq!(parsed, req);
+
+        // Converting to v1 renames the field, the method and id are untouched
+        let v1_req: v1::Request = req.clone().into();
+        assert_eq!(
+            serde_json::to_string(&v1_req).unwrap(),
+            r#"{"method":"get_sigs","params":{"id":"0000000000000000000000000000000000000000000000000000000000000000"},"id":3}"#
+        );
+        assert_eq!(Request::from(v1_req.clone()), req);
+        assert_eq!(
+            RequestParams::from(v1_req.params()),
+            RequestParams::GetSigs(coordinator::GetSigs {
+                txid,
+                if_none_match: None
+            })
+        );
+
+        // A v1 wire message isn't a v2 one
+        let v1_str = r#"{"method":"get_sigs","params":{"id":"0000000000000000000000000000000000000000000000000000000000000000"},"id":3}"#;
+        assert!(serde_json::from_str::<Request>(v1_str).is_err());
+
+        // The messages v2 doesn't change convert as is
+        let subscribe = coordinator::Subscribe {
+            txids: vec![txid],
+            deposit_outpoints: vec![],
+        };
+        let req: Request = subscribe.clone().into();
+        let v1_req: v1::Request = req.into();
+        assert_eq!(
+            v1_req.params(),
+            v1::RequestParams::Subscribe(subscribe.clone())
+        );
+
+        let secp = Secp256k1::new();
+        let seckey = SecretKey::from_slice(&[1; 32]).unwrap();
+        let sig = coordinator::NewSigEvent {
+            subscription_id: 1,
+            pubkey: PublicKey::from_secret_key(&secp, &seckey),
+            signature: secp.sign(&Message::from_slice(&[2; 32]).unwrap(), &seckey),
+            txid,
+        };
+        let notif: Notification = sig.clone().into();
+        let v1_notif: v1::Notification = notif.clone().into();
+        assert_eq!(Notification::from(v1_notif.clone()), notif);
+        let v1_sig: v1::coordinator::NewSigEvent = match v1_notif.params() {
+            v1::NotificationParams::NewSig(sig) => sig,
+            _ => unreachable!(),
+        };
+        assert_eq!(v1_sig.id, txid);
+        let v2_sig: coordinator::NewSigEvent = v1_sig.into();
+        assert_eq!(v2_sig, sig);
+    }
+
+    #[test]
+    fn versioned_requests() {
+        let txid = Txid::default();
+        let req: Request = with_id_generator(SequentialIds(3), || {
+            coordinator::GetSigs {
+                txid,
+                if_none_match: None,
+            }
+            .into()
+        });
+
+        // The v1 requests are upgraded, with or without a version
+        let v1_str = r#"{"method":"get_sigs","params":{"id":"0000000000000000000000000000000000000000000000000000000000000000"},"id":3}"#;
+        assert_eq!(
+            Request::from_versioned_slice(v1_str.as_bytes()).unwrap(),
+            req
+        );
+        let v1_str = v1_str.replace(r#"{"id""#, r#"{"version":1,"id""#);
+        assert_eq!(
+            Request::from_versioned_slice(v1_str.as_bytes()).unwrap(),
+            req
+        );
+
+        // The v2 ones must tell their version
+        let ser = req.to_versioned_vec();
+        assert_eq!(
+            String::from_utf8(ser.clone()).unwrap(),
+            r#"{"id":3,"method":"get_sigs","params":{"txid":"0000000000000000000000000000000000000000000000000000000000000000","version":2}}"#
+        );
+        assert_eq!(Request::from_versioned_slice(&ser).unwrap(), req);
+        let unversioned = serde_json::to_vec(&req).unwrap();
+        assert!(Request::from_versioned_slice(&unversioned).is_err());
+        let req: Request = coordinator::GetTime {}.into();
+        assert_eq!(
+            Request::from_versioned_slice(&req.to_versioned_vec()).unwrap(),
+            req
+        );
+
+        let future = String::from_utf8(ser)
+            .unwrap()
+            .replace(r#""version":2"#, r#""version":3"#);
+        assert!(matches!(
+            Request::from_versioned_slice(future.as_bytes()),
+            Err(Error::Message(MessageError::UnknownVersion(3)))
+        ));
+    }
+}
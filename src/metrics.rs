@@ -0,0 +1,187 @@
+//! Metrics
+//!
+//! The transport and the dispatcher report the messages they exchange, the requests
+//! they process and the errors they encounter to a [MetricsSink]. This way a daemon
+//! can export them (to Prometheus for instance) without this crate depending on any
+//! metrics library.
+//!
+//! Like a logger, the sink is global and can only be set once. Until it is, nothing is
+//! recorded.
+
+use crate::error::Error;
+
+use std::{sync::OnceLock, time::Duration};
+
+/// In which direction a message went over the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Read from the other end of the channel
+    Inbound,
+    /// Written to the other end of the channel
+    Outbound,
+}
+
+/// On which side of the connection a request was processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    /// We sent the request and waited for the response
+    Client,
+    /// We received the request and processed it
+    Server,
+}
+
+impl Side {
+    /// A label for this side
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Client => "client",
+            Self::Server => "server",
+        }
+    }
+}
+
+/// Receives the metrics recorded by this crate.
+///
+/// All methods default to doing nothing, so that a sink only needs to implement the
+/// ones it is interested in. They are called inline by the transport and must not
+/// block.
+pub trait MetricsSink: Send + Sync {
+    /// A message of `size` bytes (the plaintext, before encryption) was exchanged.
+    fn message(&self, _direction: Direction, _size: usize) {}
+
+    /// A request for `method` was processed in `latency`, successfully or not. The
+    /// [correlation id](crate::client::correlation_id) of the request, if any, can be
+    /// queried from there.
+    fn request(&self, _side: Side, _method: &str, _latency: Duration) {}
+
+    /// An error of this [class](error_class) happened. `request` is the side and the
+    /// method of the request it happened during, if any (it's `None` for handshakes).
+    fn error(&self, _request: Option<(Side, &str)>, _class: &'static str) {}
+
+    /// We closed a connection for this reason (`stale` for a connection closed by a
+    /// [Watchdog](crate::server::watchdog::Watchdog)).
+    fn connection_closed(&self, _reason: &'static str) {}
+}
+
+static SINK: OnceLock<Box<dyn MetricsSink>> = OnceLock::new();
+
+/// Set the global metrics sink. Returns the sink back if one was already set.
+pub fn set_sink(sink: Box<dyn MetricsSink>) -> Result<(), Box<dyn MetricsSink>> {
+    SINK.set(sink)
+}
+
+// The global metrics sink, if one was set
+pub(crate) fn sink() -> Option<&'static dyn MetricsSink> {
+    SINK.get().map(|s| s.as_ref())
+}
+
+/// A low cardinality label for this error, suitable to be used in metrics.
+pub fn error_class(error: &Error) -> &'static str {
+    match error {
+        Error::Noise(_) => "noise",
+        Error::Transport(_) => "transport",
+        Error::Disconnected(_) => "disconnected",
+        Error::Json(_) => "json",
+        Error::Signature(_) => "signature",
+        Error::Message(_) => "message",
+        Error::Access(_) => "access",
+        Error::Prelude(_) => "prelude",
+        Error::NotAcknowledged(_) => "not_acknowledged",
+        Error::IdMismatch { .. } => "id_mismatch",
+        Error::Timeout(_) => "timeout",
+        Error::Remote(_) => "remote",
+        Error::Shutdown => "shutdown",
+        Error::Journal(_) => "journal",
+        Error::Correlated { error, .. } => error_class(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::local_endpoints,
+        message::{
+            coordinator::{GetSigs, Sigs},
+            Request, ResponseResult,
+        },
+        transport::KKTransport,
+    };
+    use bitcoin::Txid;
+    use std::{
+        collections::HashMap,
+        str::FromStr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        thread,
+    };
+
+    #[derive(Default)]
+    struct CountingSink {
+        inbound: AtomicUsize,
+        outbound: AtomicUsize,
+        requests: Mutex<HashMap<(Side, String), usize>>,
+    }
+
+    impl MetricsSink for &'static CountingSink {
+        fn message(&self, direction: Direction, _size: usize) {
+            match direction {
+                Direction::Inbound => self.inbound.fetch_add(1, Ordering::SeqCst),
+                Direction::Outbound => self.outbound.fetch_add(1, Ordering::SeqCst),
+            };
+        }
+
+        fn request(&self, side: Side, method: &str, _latency: Duration) {
+            *self
+                .requests
+                .lock()
+                .unwrap()
+                .entry((side, method.to_string()))
+                .or_default() += 1;
+        }
+    }
+
+    #[test]
+    fn metrics_sink() {
+        let sink: &'static CountingSink = Box::leak(Box::default());
+        assert!(set_sink(Box::new(sink)).is_ok());
+        assert!(set_sink(Box::new(sink)).is_err());
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let server_thread = thread::spawn(move || {
+            let mut server_transport =
+                KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+            server_transport
+                .read_req(|_| {
+                    Some(ResponseResult::Sigs(Sigs {
+                        signatures: Default::default(),
+                    }))
+                })
+                .unwrap();
+        });
+
+        let mut client_transport =
+            KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        let txid =
+            Txid::from_str("cafedeadbeefcafedeadbeefcafedeadbeefcafedeadbeefcafedeadbeefcafe")
+                .unwrap();
+        let req: Request = GetSigs {
+            id: txid,
+            if_none_match: None,
+        }
+        .into();
+        let _: Sigs = client_transport.send_req(&req).unwrap();
+        server_thread.join().unwrap();
+
+        // Other tests may run concurrently and be recorded too
+        assert!(sink.inbound.load(Ordering::SeqCst) >= 2);
+        assert!(sink.outbound.load(Ordering::SeqCst) >= 2);
+        let requests = sink.requests.lock().unwrap();
+        assert!(requests[&(Side::Client, "get_sigs".to_string())] >= 1);
+        assert!(requests[&(Side::Server, "get_sigs".to_string())] >= 1);
+    }
+}
@@ -6,15 +6,54 @@
 
 use crate::error::NoiseError;
 
-use std::convert::TryInto;
+use std::{
+    collections::{HashSet, VecDeque},
+    convert::TryInto,
+    sync::{Mutex, OnceLock},
+};
 
-use snow::{resolvers::SodiumResolver, Builder, HandshakeState, TransportState};
+use snow::{Builder, HandshakeState, TransportState};
+
+#[cfg(any(target_arch = "wasm32", test))]
+mod resolver;
+#[cfg(target_arch = "wasm32")]
+use resolver::RustResolver as Resolver;
+#[cfg(not(target_arch = "wasm32"))]
+use snow::resolvers::SodiumResolver as Resolver;
 
 /// The static public key used to enact Noise authenticated and encrypted channels
+#[cfg(not(target_arch = "wasm32"))]
 pub use sodiumoxide::crypto::box_::curve25519xsalsa20poly1305::PublicKey;
 /// The static secret key used to enact Noise authenticated and encrypted channels
+#[cfg(not(target_arch = "wasm32"))]
 pub use sodiumoxide::crypto::box_::curve25519xsalsa20poly1305::SecretKey;
 
+/// The static public key used to enact Noise authenticated and encrypted channels
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PublicKey(pub [u8; KEY_SIZE]);
+
+/// The static secret key used to enact Noise authenticated and encrypted channels
+#[cfg(target_arch = "wasm32")]
+#[derive(Clone)]
+pub struct SecretKey(pub [u8; KEY_SIZE]);
+
+#[cfg(target_arch = "wasm32")]
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "SecretKey(****)")
+    }
+}
+
+/// Generate a new static keypair, from the randomness of the operating system
+pub fn gen_keypair() -> (PublicKey, SecretKey) {
+    let mut privkey = [0; KEY_SIZE];
+    getrandom::getrandom(&mut privkey).expect("Failed to get randomness from the OS");
+    let pubkey = x25519_dalek::x25519(privkey, x25519_dalek::X25519_BASEPOINT_BYTES);
+
+    (PublicKey(pubkey), SecretKey(privkey))
+}
+
 /// The size of a key, either public or private, on the Curve25519
 pub const KEY_SIZE: usize = 32;
 /// Size of the poly1305 MAC
@@ -32,9 +71,93 @@ pub const NOISE_PLAINTEXT_MAX_SIZE: usize =
 pub const KK_MSG_1_SIZE: usize = KEY_SIZE + HANDSHAKE_MESSAGE.len() + MAC_SIZE;
 /// e, ee, se
 pub const KK_MSG_2_SIZE: usize = KEY_SIZE + MAC_SIZE;
+/// Size of the hash of a completed handshake (SHA256)
+pub const HANDSHAKE_HASH_SIZE: usize = 32;
 /// Sent for versioning and identification during handshake
 pub const HANDSHAKE_MESSAGE: &[u8] = b"practical_revault_0";
 
+/// The number of ephemeral keys remembered by default, see
+/// [set_ephemeral_cache_capacity]
+pub const DEFAULT_EPHEMERAL_CACHE_CAPACITY: usize = 10_000;
+
+// The ephemeral keys of the last handshakes, oldest first
+struct EphemeralCache {
+    capacity: usize,
+    keys: HashSet<[u8; KEY_SIZE]>,
+    order: VecDeque<[u8; KEY_SIZE]>,
+}
+
+impl EphemeralCache {
+    // Remember this key, returns false if it already was
+    fn insert(&mut self, key: [u8; KEY_SIZE]) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        if !self.keys.insert(key) {
+            return false;
+        }
+        self.order.push_back(key);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+static EPHEMERALS: OnceLock<Mutex<EphemeralCache>> = OnceLock::new();
+
+fn ephemerals() -> &'static Mutex<EphemeralCache> {
+    EPHEMERALS.get_or_init(|| {
+        Mutex::new(EphemeralCache {
+            capacity: DEFAULT_EPHEMERAL_CACHE_CAPACITY,
+            keys: HashSet::new(),
+            order: VecDeque::new(),
+        })
+    })
+}
+
+/// Set how many of the ephemeral keys of the peers are remembered, for the handshakes
+/// reusing one of them to be refused with [NoiseError::ReusedEphemeralKey]. The cache
+/// is global to the process. 0 disables the check.
+pub fn set_ephemeral_cache_capacity(capacity: usize) {
+    let mut cache = ephemerals().lock().unwrap_or_else(|e| e.into_inner());
+    cache.capacity = capacity;
+    while cache.order.len() > capacity {
+        if let Some(oldest) = cache.order.pop_front() {
+            cache.keys.remove(&oldest);
+        }
+    }
+}
+
+// Whether this is a point of low order (including the identity) on Curve25519. A
+// clamped scalar being a multiple of the cofactor, multiplying such a point by any of
+// them gives the identity.
+fn is_low_order(point: &[u8; KEY_SIZE]) -> bool {
+    x25519_dalek::x25519([1; KEY_SIZE], *point) == [0; KEY_SIZE]
+}
+
+// Check the ephemeral key of the peer once its static key is known: it must not be a
+// low order point, nor be its static key, nor have been seen before.
+fn check_ephemeral(ephemeral: &[u8], state: &HandshakeState) -> Result<(), NoiseError> {
+    let ephemeral: [u8; KEY_SIZE] = ephemeral[..KEY_SIZE]
+        .try_into()
+        .expect("Handshake messages start with the ephemeral key");
+    if is_low_order(&ephemeral) || state.get_remote_static() == Some(&ephemeral[..]) {
+        return Err(NoiseError::InvalidEphemeralKey);
+    }
+    if !ephemerals()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(ephemeral)
+    {
+        return Err(NoiseError::ReusedEphemeralKey);
+    }
+
+    Ok(())
+}
+
 /// First round of the KK handshake
 #[derive(Debug)]
 pub struct KKHandshakeActOne {
@@ -55,7 +178,7 @@ impl KKHandshakeActOne {
             "Noise_KK_25519_ChaChaPoly_SHA256"
                 .parse()
                 .expect("Valid params"),
-            Box::new(SodiumResolver::default()),
+            Box::new(Resolver),
         );
         let mut state = builder
             .local_private_key(&my_privkey.0)
@@ -82,7 +205,7 @@ impl KKHandshakeActOne {
                 "Noise_KK_25519_ChaChaPoly_SHA256"
                     .parse()
                     .expect("Valid params"),
-                Box::new(SodiumResolver::default()),
+                Box::new(Resolver),
             );
             let mut state = builder
                 .local_private_key(&my_privkey.0)
@@ -96,6 +219,7 @@ impl KKHandshakeActOne {
             if &msg[..HANDSHAKE_MESSAGE.len()] != HANDSHAKE_MESSAGE {
                 return Err(NoiseError::BadHandshake);
             }
+            check_ephemeral(&message.0, &state)?;
 
             return Ok(KKHandshakeActOne { state });
         }
@@ -123,6 +247,7 @@ impl KKHandshakeActTwo {
         // In handshake mode we don't actually care about the message
         let mut _m = [0u8; KK_MSG_2_SIZE];
         handshake.state.read_message(&message.0, &mut _m)?;
+        check_ephemeral(&message.0, &handshake.state)?;
 
         Ok(KKHandshakeActTwo {
             state: handshake.state,
@@ -159,9 +284,18 @@ pub struct NoiseEncryptedMessage(pub Vec<u8>);
 #[derive(Debug)]
 pub struct KKChannel {
     transport_state: TransportState,
+    handshake_hash: [u8; HANDSHAKE_HASH_SIZE],
+}
+
+// A failure to decrypt a message means it was tampered with (or the channel desynced)
+fn decryption_error(e: snow::error::Error) -> NoiseError {
+    match e {
+        snow::error::Error::Decrypt => NoiseError::Decryption,
+        e => NoiseError::Snow(e),
+    }
 }
 
-fn encrypted_msg_size(plaintext_size: usize) -> usize {
+pub(crate) const fn encrypted_msg_size(plaintext_size: usize) -> usize {
     // Length prefix + MAC    ||   Message + MAC
     NOISE_MESSAGE_HEADER_SIZE + plaintext_size + MAC_SIZE
 }
@@ -169,9 +303,17 @@ fn encrypted_msg_size(plaintext_size: usize) -> usize {
 impl KKChannel {
     /// Constructs the KK Noise channel from a final stage KK handshake
     pub fn from_handshake(state: KKHandshakeActTwo) -> Result<KKChannel, NoiseError> {
+        let handshake_hash = state
+            .state
+            .get_handshake_hash()
+            .try_into()
+            .expect("We use SHA256");
         let transport_state = state.state.into_transport_mode()?;
 
-        Ok(KKChannel { transport_state })
+        Ok(KKChannel {
+            transport_state,
+            handshake_hash,
+        })
     }
 
     /// Use the channel to encrypt a message shorter than [NOISE_PLAINTEXT_MAX_SIZE].
@@ -179,28 +321,61 @@ impl KKChannel {
     /// incremental reads.
     /// On success, returns the ciphertext.
     pub fn encrypt_message(&mut self, message: &[u8]) -> Result<NoiseEncryptedMessage, NoiseError> {
+        let mut output = Vec::new();
+        self.encrypt_message_into(message, &mut output)?;
+        Ok(NoiseEncryptedMessage(output))
+    }
+
+    /// Same as [encrypt_message](Self::encrypt_message), but write the ciphertext to
+    /// this buffer (replacing its content) instead of allocating a new one.
+    pub fn encrypt_message_into(
+        &mut self,
+        message: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<(), NoiseError> {
+        if message.len() > NOISE_PLAINTEXT_MAX_SIZE {
+            return Err(NoiseError::TooLargePlaintext(message.len()));
+        }
+        output.clear();
+        output.resize(encrypted_msg_size(message.len()), 0);
+        self.encrypt_message_to_slice(message, output)?;
+
+        Ok(())
+    }
+
+    // Same as encrypt_message_into, to a slice large enough for the ciphertext.
+    // Returns the size of the ciphertext.
+    pub(crate) fn encrypt_message_to_slice(
+        &mut self,
+        message: &[u8],
+        output: &mut [u8],
+    ) -> Result<usize, NoiseError> {
         if message.len() > NOISE_PLAINTEXT_MAX_SIZE {
             return Err(NoiseError::TooLargePlaintext(message.len()));
         }
-        let mut output = vec![0u8; encrypted_msg_size(message.len())];
+        let size = encrypted_msg_size(message.len());
+        assert!(output.len() >= size, "Output buffer too small");
 
         let message_len: u16 = (MAC_SIZE + message.len())
             .try_into()
             .expect("We just checked it was < NOISE_PLAINTEXT_MAX_SIZE");
-        let prefix = message_len.to_be_bytes().to_vec();
-        self.transport_state
-            .write_message(&prefix, &mut output[..NOISE_MESSAGE_HEADER_SIZE])?;
+        self.transport_state.write_message(
+            &message_len.to_be_bytes(),
+            &mut output[..NOISE_MESSAGE_HEADER_SIZE],
+        )?;
 
         self.transport_state
-            .write_message(&message, &mut output[NOISE_MESSAGE_HEADER_SIZE..])?;
+            .write_message(message, &mut output[NOISE_MESSAGE_HEADER_SIZE..size])?;
 
-        Ok(NoiseEncryptedMessage(output))
+        Ok(size)
     }
 
     /// Get the size of the message following this header
     pub fn decrypt_header(&mut self, header: &NoiseEncryptedHeader) -> Result<u16, NoiseError> {
         let mut buf = [0u8; NOISE_MESSAGE_HEADER_SIZE];
-        self.transport_state.read_message(&header.0, &mut buf)?;
+        self.transport_state
+            .read_message(&header.0, &mut buf)
+            .map_err(decryption_error)?;
 
         let len_be: [u8; 2] = buf[..NOISE_MESSAGE_HEADER_SIZE - MAC_SIZE]
             .try_into()
@@ -213,19 +388,39 @@ impl KKChannel {
         &mut self,
         message: &NoiseEncryptedMessage,
     ) -> Result<Vec<u8>, NoiseError> {
+        let mut plaintext = Vec::new();
+        self.decrypt_message_into(message, &mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    /// Same as [decrypt_message](Self::decrypt_message), but write the plaintext to
+    /// this buffer (replacing its content) instead of allocating a new one.
+    pub fn decrypt_message_into(
+        &mut self,
+        message: &NoiseEncryptedMessage,
+        plaintext: &mut Vec<u8>,
+    ) -> Result<(), NoiseError> {
         // TODO: could be in NoiseEncryptedMessage's constructor?
         if message.0.len() < MAC_SIZE || message.0.len() > NOISE_MESSAGE_MAX_SIZE {
             return Err(NoiseError::InvalidCiphertextSize(message.0.len()));
         }
-        let mut plaintext = vec![0u8; message.0.len()];
+        plaintext.clear();
+        plaintext.resize(message.0.len(), 0);
 
         self.transport_state
-            .read_message(&message.0, &mut plaintext)?;
+            .read_message(&message.0, plaintext)
+            .map_err(decryption_error)?;
 
         // We read the MAC, but caller doesn't care about it
-        // FIXME: add a test for invalid MAC getting refused
         plaintext.truncate(plaintext.len() - MAC_SIZE);
-        Ok(plaintext)
+        Ok(())
+    }
+
+    /// Get the hash of the handshake which settled this channel. It is the same for
+    /// both ends and unique to the session, so signing it along with a message binds
+    /// the message to the session: it can't be replayed over another one.
+    pub fn handshake_hash(&self) -> [u8; HANDSHAKE_HASH_SIZE] {
+        self.handshake_hash
     }
 
     /// Get the static public key of the peer
@@ -244,14 +439,35 @@ impl KKChannel {
 }
 
 #[cfg(test)]
-pub mod tests {
-    use crate::noise::{
-        KKChannel, KKHandshakeActOne, KKHandshakeActTwo, KKMessageActOne, KKMessageActTwo,
-        NoiseEncryptedHeader, NoiseEncryptedMessage, KK_MSG_1_SIZE, KK_MSG_2_SIZE, MAC_SIZE,
-        NOISE_MESSAGE_HEADER_SIZE, NOISE_MESSAGE_MAX_SIZE, NOISE_PLAINTEXT_MAX_SIZE,
+mod tests {
+    use crate::{
+        error::NoiseError,
+        noise::{
+            encrypted_msg_size, gen_keypair, is_low_order, EphemeralCache, KKChannel,
+            KKHandshakeActOne, KKHandshakeActTwo, KKMessageActOne, KKMessageActTwo,
+            NoiseEncryptedHeader, NoiseEncryptedMessage, KEY_SIZE, KK_MSG_1_SIZE, KK_MSG_2_SIZE,
+            MAC_SIZE, NOISE_MESSAGE_HEADER_SIZE, NOISE_MESSAGE_MAX_SIZE, NOISE_PLAINTEXT_MAX_SIZE,
+        },
     };
-    use sodiumoxide::crypto::box_::curve25519xsalsa20poly1305::gen_keypair;
-    use std::convert::TryInto;
+    use std::{collections::HashSet, collections::VecDeque, convert::TryInto};
+
+    // The ends of a new channel, the initiator's first
+    fn channels() -> (KKChannel, KKChannel) {
+        let (initiator_pubkey, initiator_privkey) = gen_keypair();
+        let (responder_pubkey, responder_privkey) = gen_keypair();
+
+        let (cli_act_1, msg_1) =
+            KKHandshakeActOne::initiator(&initiator_privkey, &responder_pubkey).unwrap();
+        let serv_act_1 =
+            KKHandshakeActOne::responder(&responder_privkey, &[initiator_pubkey], &msg_1).unwrap();
+        let (serv_act_2, msg_2) = KKHandshakeActTwo::responder(serv_act_1).unwrap();
+        let cli_act_2 = KKHandshakeActTwo::initiator(cli_act_1, &msg_2).unwrap();
+
+        (
+            KKChannel::from_handshake(cli_act_2).unwrap(),
+            KKChannel::from_handshake(serv_act_2).unwrap(),
+        )
+    }
 
     #[test]
     fn test_bidirectional_roundtrip() {
@@ -274,7 +490,7 @@ pub mod tests {
 
         // client encrypts message for server
         let msg = "Hello".as_bytes();
-        let encrypted_msg = client_channel.encrypt_message(&msg).unwrap();
+        let encrypted_msg = client_channel.encrypt_message(msg).unwrap();
         assert_eq!(
             encrypted_msg.0.len(),
             msg.len() + NOISE_MESSAGE_HEADER_SIZE + MAC_SIZE
@@ -295,7 +511,7 @@ pub mod tests {
 
         // server encrypts message for client
         let msg = "Goodbye".as_bytes();
-        let encrypted_msg = server_channel.encrypt_message(&msg).unwrap();
+        let encrypted_msg = server_channel.encrypt_message(msg).unwrap();
         let (header, body) = (
             &encrypted_msg.0[..NOISE_MESSAGE_HEADER_SIZE],
             &encrypted_msg.0[NOISE_MESSAGE_HEADER_SIZE..],
@@ -310,6 +526,61 @@ pub mod tests {
         assert_eq!(msg.to_vec(), decrypted_msg);
     }
 
+    #[test]
+    fn test_roundtrip_into_buffers() {
+        let (mut client_channel, mut server_channel) = channels();
+
+        // Messages can be encrypted and decrypted into existing buffers
+        let msg = "Goodbye".as_bytes();
+        let mut encrypted_msg = vec![1; 100];
+        server_channel
+            .encrypt_message_into(msg, &mut encrypted_msg)
+            .unwrap();
+        assert_eq!(encrypted_msg.len(), encrypted_msg_size(msg.len()));
+        let (header, body) = encrypted_msg.split_at(NOISE_MESSAGE_HEADER_SIZE);
+        client_channel
+            .decrypt_header(&NoiseEncryptedHeader(header.try_into().unwrap()))
+            .unwrap();
+        let mut decrypted_msg = vec![1; 100];
+        client_channel
+            .decrypt_message_into(&NoiseEncryptedMessage(body.to_vec()), &mut decrypted_msg)
+            .unwrap();
+        assert_eq!(msg.to_vec(), decrypted_msg);
+    }
+
+    #[test]
+    fn test_invalid_mac() {
+        let msg = "Goodbye".as_bytes();
+
+        // A tampered header, message or MAC can't be decrypted
+        for tampered in &[
+            0,
+            NOISE_MESSAGE_HEADER_SIZE - 1,
+            NOISE_MESSAGE_HEADER_SIZE,
+            encrypted_msg_size(msg.len()) - 1,
+        ] {
+            let (mut client_channel, mut server_channel) = channels();
+            let mut encrypted_msg = server_channel.encrypt_message(msg).unwrap();
+            encrypted_msg.0[*tampered] ^= 1;
+            let (header, body) = encrypted_msg.0.split_at(NOISE_MESSAGE_HEADER_SIZE);
+            let header =
+                client_channel.decrypt_header(&NoiseEncryptedHeader(header.try_into().unwrap()));
+            if *tampered < NOISE_MESSAGE_HEADER_SIZE {
+                assert!(matches!(header, Err(NoiseError::Decryption)));
+                continue;
+            }
+            header.unwrap();
+            let mut decrypted_msg = Vec::new();
+            assert!(matches!(
+                client_channel.decrypt_message_into(
+                    &NoiseEncryptedMessage(body.to_vec()),
+                    &mut decrypted_msg
+                ),
+                Err(NoiseError::Decryption)
+            ));
+        }
+    }
+
     #[test]
     fn test_message_size_limit() {
         let (initiator_pubkey, initiator_privkey) = gen_keypair();
@@ -349,6 +620,25 @@ pub mod tests {
             .expect_err("Encrypted message with no header");
     }
 
+    #[test]
+    fn handshake_hash_matches() {
+        // Both ends of a session share its hash, other sessions don't
+        let (client_channel, server_channel) = channels();
+        let (other_client_channel, other_server_channel) = channels();
+        assert_eq!(
+            client_channel.handshake_hash(),
+            server_channel.handshake_hash()
+        );
+        assert_eq!(
+            other_client_channel.handshake_hash(),
+            other_server_channel.handshake_hash()
+        );
+        assert_ne!(
+            client_channel.handshake_hash(),
+            other_server_channel.handshake_hash()
+        );
+    }
+
     #[test]
     fn test_bad_messages() {
         let (initiator_pubkey, initiator_privkey) = gen_keypair();
@@ -366,4 +656,65 @@ pub mod tests {
         let bad_msg = KKMessageActTwo([1u8; KK_MSG_2_SIZE]);
         KKHandshakeActTwo::initiator(cli_act_1, &bad_msg).expect_err("So is this one.");
     }
+
+    #[test]
+    fn ephemeral_key_checks() {
+        let (initiator_pubkey, initiator_privkey) = gen_keypair();
+        let (responder_pubkey, responder_privkey) = gen_keypair();
+
+        // A replayed first act is refused
+        let (_, msg_1) = KKHandshakeActOne::initiator(&initiator_privkey, &responder_pubkey)
+            .expect("The first act is valid.");
+        KKHandshakeActOne::responder(&responder_privkey, &[initiator_pubkey], &msg_1).unwrap();
+        assert!(matches!(
+            KKHandshakeActOne::responder(&responder_privkey, &[initiator_pubkey], &msg_1),
+            Err(NoiseError::ReusedEphemeralKey)
+        ));
+
+        // And so is a replayed second act
+        let (cli_act_1, msg_1) =
+            KKHandshakeActOne::initiator(&initiator_privkey, &responder_pubkey).unwrap();
+        let serv_act_1 =
+            KKHandshakeActOne::responder(&responder_privkey, &[initiator_pubkey], &msg_1).unwrap();
+        let (_, msg_2) = KKHandshakeActTwo::responder(serv_act_1).unwrap();
+        KKHandshakeActTwo::initiator(cli_act_1, &msg_2).unwrap();
+        let (cli_act_1, _) =
+            KKHandshakeActOne::initiator(&initiator_privkey, &responder_pubkey).unwrap();
+        assert!(KKHandshakeActTwo::initiator(cli_act_1, &msg_2).is_err());
+
+        // The identity and the other low order points
+        let mut one = [0; KEY_SIZE];
+        one[0] = 1;
+        let mut p_minus_one = [0xff; KEY_SIZE];
+        p_minus_one[0] = 0xec;
+        p_minus_one[31] = 0x7f;
+        for point in &[[0; KEY_SIZE], one, p_minus_one] {
+            assert!(is_low_order(point));
+        }
+        assert!(!is_low_order(&gen_keypair().0 .0));
+
+        // The oldest keys are forgotten
+        let mut cache = EphemeralCache {
+            capacity: 2,
+            keys: HashSet::new(),
+            order: VecDeque::new(),
+        };
+        assert!(cache.insert([1; KEY_SIZE]));
+        assert!(!cache.insert([1; KEY_SIZE]));
+        assert!(cache.insert([2; KEY_SIZE]));
+        assert!(cache.insert([3; KEY_SIZE]));
+        assert!(cache.insert([1; KEY_SIZE]));
+        cache.capacity = 0;
+        assert!(cache.insert([3; KEY_SIZE]));
+    }
+
+    #[test]
+    fn keypair_generation() {
+        use sodiumoxide::crypto::scalarmult::curve25519::{scalarmult_base, Scalar};
+
+        // The public key is the one libsodium derives, and keys are random
+        let (pubkey, privkey) = gen_keypair();
+        assert_eq!(scalarmult_base(&Scalar(privkey.0)).0, pubkey.0);
+        assert_ne!(gen_keypair().0, pubkey);
+    }
 }
@@ -0,0 +1,302 @@
+//! Noise crypto in Rust
+//!
+//! libsodium can't be built for browsers, so on wasm32 targets snow is given the
+//! primitives of the Noise_KK_25519_ChaChaPoly_SHA256 protocol implemented in Rust. It
+//! behaves as the libsodium resolver, to which it is compared in the tests.
+
+use bitcoin::hashes::{sha256, Hash as _, HashEngine};
+use chacha20poly1305::{
+    aead::{AeadInPlace, KeyInit},
+    ChaCha20Poly1305,
+};
+use snow::{
+    params::{CipherChoice, DHChoice, HashChoice},
+    resolvers::CryptoResolver,
+    types::{Cipher, Dh, Hash, Random},
+};
+
+use std::num::NonZeroU32;
+
+use super::{KEY_SIZE, MAC_SIZE};
+
+/// A snow resolver for the primitives of our Noise protocol, in Rust
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RustResolver;
+
+impl CryptoResolver for RustResolver {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        Some(Box::new(OsRng))
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        match choice {
+            DHChoice::Curve25519 => Some(Box::new(Dh25519::default())),
+            _ => None,
+        }
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<dyn Hash>> {
+        match choice {
+            HashChoice::SHA256 => Some(Box::new(Sha256::default())),
+            _ => None,
+        }
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+        match choice {
+            CipherChoice::ChaChaPoly => Some(Box::new(ChaChaPoly::default())),
+            _ => None,
+        }
+    }
+}
+
+// The randomness of the operating system (or of the browser)
+struct OsRng;
+
+impl rand_core::RngCore for OsRng {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest)
+            .expect("Failed to get randomness from the OS")
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        getrandom::getrandom(dest).map_err(|e| {
+            NonZeroU32::new(e.code().get())
+                .expect("getrandom error codes are not 0")
+                .into()
+        })
+    }
+}
+
+impl rand_core::CryptoRng for OsRng {}
+
+impl Random for OsRng {}
+
+#[derive(Default)]
+struct Dh25519 {
+    privkey: [u8; KEY_SIZE],
+    pubkey: [u8; KEY_SIZE],
+}
+
+impl Dh for Dh25519 {
+    fn name(&self) -> &'static str {
+        "25519"
+    }
+
+    fn pub_len(&self) -> usize {
+        KEY_SIZE
+    }
+
+    fn priv_len(&self) -> usize {
+        KEY_SIZE
+    }
+
+    fn set(&mut self, privkey: &[u8]) {
+        self.privkey.copy_from_slice(&privkey[..KEY_SIZE]);
+        self.pubkey = x25519_dalek::x25519(self.privkey, x25519_dalek::X25519_BASEPOINT_BYTES);
+    }
+
+    fn generate(&mut self, rng: &mut dyn Random) {
+        let mut privkey = [0u8; KEY_SIZE];
+        rng.fill_bytes(&mut privkey);
+        self.set(&privkey);
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    fn privkey(&self) -> &[u8] {
+        &self.privkey
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> {
+        let mut their_pubkey = [0u8; KEY_SIZE];
+        their_pubkey.copy_from_slice(pubkey.get(..KEY_SIZE).ok_or(())?);
+        let shared_secret = x25519_dalek::x25519(self.privkey, their_pubkey);
+        // Like libsodium, refuse the low order points
+        if shared_secret == [0; KEY_SIZE] {
+            return Err(());
+        }
+        out[..KEY_SIZE].copy_from_slice(&shared_secret);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct ChaChaPoly {
+    key: [u8; KEY_SIZE],
+}
+
+impl ChaChaPoly {
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(&self.key.into())
+    }
+}
+
+// The 96 bits nonce of the IETF variant, from the Noise 64 bits one
+fn ietf_nonce(nonce: u64) -> chacha20poly1305::Nonce {
+    let mut nonce_bytes = [0u8; 12];
+    nonce_bytes[4..].copy_from_slice(&nonce.to_le_bytes());
+    nonce_bytes.into()
+}
+
+impl Cipher for ChaChaPoly {
+    fn name(&self) -> &'static str {
+        "ChaChaPoly"
+    }
+
+    fn set(&mut self, key: &[u8]) {
+        self.key.copy_from_slice(&key[..KEY_SIZE]);
+    }
+
+    fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        let (msg, mac) = out[..plaintext.len() + MAC_SIZE].split_at_mut(plaintext.len());
+        msg.copy_from_slice(plaintext);
+        let tag = self
+            .cipher()
+            .encrypt_in_place_detached(&ietf_nonce(nonce), authtext, msg)
+            .expect("Noise messages are far below the ChaCha20 limit");
+        mac.copy_from_slice(&tag);
+
+        plaintext.len() + MAC_SIZE
+    }
+
+    fn decrypt(
+        &self,
+        nonce: u64,
+        authtext: &[u8],
+        ciphertext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, ()> {
+        let msg_len = ciphertext.len().checked_sub(MAC_SIZE).ok_or(())?;
+        let (ciphertext, mac) = ciphertext.split_at(msg_len);
+        let msg = &mut out[..msg_len];
+        msg.copy_from_slice(ciphertext);
+        self.cipher()
+            .decrypt_in_place_detached(&ietf_nonce(nonce), authtext, msg, mac.into())
+            .map_err(|_| ())?;
+
+        Ok(msg_len)
+    }
+}
+
+#[derive(Default)]
+struct Sha256(sha256::HashEngine);
+
+impl Hash for Sha256 {
+    fn name(&self) -> &'static str {
+        "SHA256"
+    }
+
+    fn block_len(&self) -> usize {
+        sha256::HashEngine::BLOCK_SIZE
+    }
+
+    fn hash_len(&self) -> usize {
+        sha256::Hash::LEN
+    }
+
+    fn reset(&mut self) {
+        self.0 = sha256::HashEngine::default();
+    }
+
+    fn input(&mut self, data: &[u8]) {
+        self.0.input(data);
+    }
+
+    fn result(&mut self, out: &mut [u8]) {
+        let engine = std::mem::take(&mut self.0);
+        out[..sha256::Hash::LEN].copy_from_slice(&sha256::Hash::from_engine(engine)[..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snow::{resolvers::SodiumResolver, Builder};
+
+    const PARAMS: &str = "Noise_KK_25519_ChaChaPoly_SHA256";
+
+    fn builder(resolver: Box<dyn CryptoResolver + Send>) -> Builder<'static> {
+        Builder::with_resolver(PARAMS.parse().unwrap(), resolver)
+    }
+
+    // Run a handshake and exchange messages between an initiator and a responder
+    // using these resolvers
+    fn exchange(
+        initiator: Box<dyn CryptoResolver + Send>,
+        responder: Box<dyn CryptoResolver + Send>,
+    ) {
+        let init_keys = builder(Box::new(RustResolver)).generate_keypair().unwrap();
+        let resp_keys = builder(Box::new(SodiumResolver))
+            .generate_keypair()
+            .unwrap();
+
+        let mut init = builder(initiator)
+            .local_private_key(&init_keys.private)
+            .remote_public_key(&resp_keys.public)
+            .build_initiator()
+            .unwrap();
+        let mut resp = builder(responder)
+            .local_private_key(&resp_keys.private)
+            .remote_public_key(&init_keys.public)
+            .build_responder()
+            .unwrap();
+
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+        let len = init
+            .write_message(b"practical_revault_0", &mut msg)
+            .unwrap();
+        let read = resp.read_message(&msg[..len], &mut buf).unwrap();
+        assert_eq!(&buf[..read], b"practical_revault_0");
+        let len = resp.write_message(&[], &mut msg).unwrap();
+        init.read_message(&msg[..len], &mut buf).unwrap();
+        assert_eq!(init.get_handshake_hash(), resp.get_handshake_hash());
+
+        let (mut init, mut resp) = (
+            init.into_transport_mode().unwrap(),
+            resp.into_transport_mode().unwrap(),
+        );
+        for plaintext in [&b""[..], b"a", &[42; 600][..]].iter() {
+            let len = init.write_message(plaintext, &mut msg).unwrap();
+            let read = resp.read_message(&msg[..len], &mut buf).unwrap();
+            assert_eq!(&buf[..read], *plaintext);
+            let len = resp.write_message(plaintext, &mut msg).unwrap();
+            let read = init.read_message(&msg[..len], &mut buf).unwrap();
+            assert_eq!(&buf[..read], *plaintext);
+        }
+
+        // Tampered messages are refused
+        let len = init.write_message(b"Hello", &mut msg).unwrap();
+        msg[0] ^= 1;
+        resp.read_message(&msg[..len], &mut buf).unwrap_err();
+    }
+
+    #[test]
+    fn libsodium_compatibility() {
+        exchange(Box::new(RustResolver), Box::new(SodiumResolver));
+        exchange(Box::new(SodiumResolver), Box::new(RustResolver));
+        exchange(Box::new(RustResolver), Box::new(RustResolver));
+
+        // The public key derived from a private key is the same
+        let keys = builder(Box::new(SodiumResolver))
+            .generate_keypair()
+            .unwrap();
+        let mut dh = Dh25519::default();
+        dh.set(&keys.private);
+        assert_eq!(dh.pubkey(), &keys.public[..]);
+
+        // Low order points are refused
+        let mut out = [0u8; KEY_SIZE];
+        dh.dh(&[0; KEY_SIZE], &mut out).unwrap_err();
+    }
+}
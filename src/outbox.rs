@@ -0,0 +1,349 @@
+//! Outbox
+//!
+//! The signatures and Spend transactions to share with the coordinator can be queued
+//! in an [Outbox] while it is unreachable, and flushed in order once it is reachable
+//! again. The queue is kept by the user through the [Storage] trait, so that it
+//! survives restarts when it is persisted.
+//!
+//! Each entry keeps the id of its request: a request sent again after a restart can
+//! be recognized by the coordinator (see [crate::server::Dedup]).
+
+use crate::{
+    client::{is_connection_failure, CoordinatorClient},
+    error::Error,
+    instrument::{log_debug, log_warn},
+    message::{
+        coordinator::{SchnorrSig, SetSpendResult, SetSpendTx, Sig, SigResult},
+        method, next_request_id, Request,
+    },
+};
+
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, convert::Infallible, fmt, sync::Mutex};
+
+/// The message of a queued request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutboxMessage {
+    /// A signature for a transaction
+    Sig(Sig),
+    /// A Schnorr signature for a transaction
+    SchnorrSig(SchnorrSig),
+    /// A Spend transaction to store
+    SetSpendTx(SetSpendTx),
+}
+
+/// A request queued in an [Outbox]. It can be (de)serialized to be persisted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    /// The id of the request, kept across attempts
+    pub id: u32,
+    /// The message to send
+    pub message: OutboxMessage,
+}
+
+impl OutboxEntry {
+    /// The request to send for this entry
+    pub fn request(&self) -> Request<'static> {
+        match self.message.clone() {
+            OutboxMessage::Sig(params) => Request::CoordSig {
+                method: method::SIG,
+                params,
+                id: self.id,
+            },
+            OutboxMessage::SchnorrSig(params) => Request::CoordSchnorrSig {
+                method: method::SIG,
+                params,
+                id: self.id,
+            },
+            OutboxMessage::SetSpendTx(params) => Request::SetSpendTx {
+                method: method::SET_SPEND_TX,
+                params,
+                id: self.id,
+            },
+        }
+    }
+}
+
+/// The storage backend of an [Outbox], a FIFO queue of entries.
+pub trait Storage {
+    /// The error returned by the storage backend
+    type Error: fmt::Display;
+
+    /// Append this entry at the back of the queue
+    fn push(&self, entry: &OutboxEntry) -> Result<(), Self::Error>;
+
+    /// The entry at the front of the queue, if any
+    fn front(&self) -> Result<Option<OutboxEntry>, Self::Error>;
+
+    /// Remove the entry at the front of the queue
+    fn pop(&self) -> Result<(), Self::Error>;
+
+    /// The number of entries in the queue
+    fn len(&self) -> Result<usize, Self::Error>;
+
+    /// Whether the queue is empty
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        self.len().map(|len| len == 0)
+    }
+}
+
+/// A [Storage] backend keeping the queue in memory. It doesn't survive restarts.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: Mutex<VecDeque<OutboxEntry>>,
+}
+
+impl MemoryStorage {
+    /// An empty queue
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    type Error = Infallible;
+
+    fn push(&self, entry: &OutboxEntry) -> Result<(), Self::Error> {
+        let mut entries = self.entries.lock().expect("Poisoned lock");
+        entries.push_back(entry.clone());
+        Ok(())
+    }
+
+    fn front(&self) -> Result<Option<OutboxEntry>, Self::Error> {
+        let entries = self.entries.lock().expect("Poisoned lock");
+        Ok(entries.front().cloned())
+    }
+
+    fn pop(&self) -> Result<(), Self::Error> {
+        let mut entries = self.entries.lock().expect("Poisoned lock");
+        entries.pop_front();
+        Ok(())
+    }
+
+    fn len(&self) -> Result<usize, Self::Error> {
+        let entries = self.entries.lock().expect("Poisoned lock");
+        Ok(entries.len())
+    }
+}
+
+/// The outcome of flushing an [Outbox]
+#[derive(Debug, Default)]
+pub struct FlushReport {
+    /// The number of entries the coordinator acknowledged
+    pub sent: usize,
+    /// The entries the coordinator refused. They were removed from the queue, as
+    /// sending them again would not change its answer.
+    pub rejected: Vec<(OutboxEntry, Error)>,
+    /// The connection failure the flush stopped at, if any. The entry it happened
+    /// for is left at the front of the queue.
+    pub interrupted: Option<Error>,
+}
+
+/// A queue of the requests to send to the coordinator, in order.
+#[derive(Debug)]
+pub struct Outbox<S> {
+    storage: S,
+}
+
+impl<S: Storage> Outbox<S> {
+    /// An outbox queueing the requests in this storage. The entries it already contains
+    /// are sent on the next flush.
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+
+    /// Queue a message at the back of the outbox
+    pub fn queue(&self, message: OutboxMessage) -> Result<(), S::Error> {
+        self.storage.push(&OutboxEntry {
+            id: next_request_id(),
+            message,
+        })
+    }
+
+    /// Queue a signature at the back of the outbox
+    pub fn queue_sig(&self, sig: Sig) -> Result<(), S::Error> {
+        self.queue(OutboxMessage::Sig(sig))
+    }
+
+    /// Queue a Schnorr signature at the back of the outbox
+    pub fn queue_schnorr_sig(&self, sig: SchnorrSig) -> Result<(), S::Error> {
+        self.queue(OutboxMessage::SchnorrSig(sig))
+    }
+
+    /// Queue a Spend transaction at the back of the outbox
+    pub fn queue_spend_tx(&self, msg: SetSpendTx) -> Result<(), S::Error> {
+        self.queue(OutboxMessage::SetSpendTx(msg))
+    }
+
+    /// The number of requests waiting to be sent
+    pub fn len(&self) -> Result<usize, S::Error> {
+        self.storage.len()
+    }
+
+    /// Whether no request is waiting to be sent
+    pub fn is_empty(&self) -> Result<bool, S::Error> {
+        self.storage.is_empty()
+    }
+
+    /// Send the queued requests to the coordinator, in order, until the queue is empty
+    /// or the connection fails. Only a storage error is returned as an error.
+    pub fn flush(&self, client: &mut CoordinatorClient) -> Result<FlushReport, S::Error> {
+        let mut report = FlushReport::default();
+
+        while let Some(entry) = self.storage.front()? {
+            let req = entry.request();
+            let res = match &entry.message {
+                OutboxMessage::Sig(sig) => {
+                    if let Some(cache) = client.sigs_cache() {
+                        cache.invalidate(&sig.id);
+                    }
+                    client.send_acked_req::<SigResult>(&req, method::SIG)
+                }
+                OutboxMessage::SchnorrSig(_) => {
+                    client.send_acked_req::<SigResult>(&req, method::SIG)
+                }
+                OutboxMessage::SetSpendTx(_) => {
+                    client.send_acked_req::<SetSpendResult>(&req, method::SET_SPEND_TX)
+                }
+            };
+            match res {
+                Ok(()) => report.sent += 1,
+                Err(e) if is_connection_failure(&e) => {
+                    log_debug!("Outbox flush interrupted: '{}'", e);
+                    report.interrupted = Some(e);
+                    break;
+                }
+                Err(e) => {
+                    log_warn!("Coordinator refused queued request {}: '{}'", entry.id, e);
+                    report.rejected.push((entry, e));
+                }
+            }
+            self.storage.pop()?;
+        }
+
+        Ok(report)
+    }
+
+    /// Get the underlying storage
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{local_endpoints, secp_sig},
+        message::{RequestParams, ResponseResult},
+        noise::{PublicKey, SecretKey},
+        transport::KKTransport,
+    };
+
+    use bitcoin::{hashes::Hash, Txid};
+    use std::{net::TcpListener, thread};
+
+    // Accept a client and answer its `sig` requests with these acks, returning the ids
+    // of all the requests read (`None`: read it but drop the connection).
+    fn coordinator(
+        listener: TcpListener,
+        acks: Vec<Option<bool>>,
+        client_pubkey: PublicKey,
+        privkey: SecretKey,
+    ) -> thread::JoinHandle<Vec<u32>> {
+        thread::spawn(move || {
+            let mut transport = KKTransport::accept(&listener, &privkey, &[client_pubkey]).unwrap();
+            let mut ids = Vec::new();
+            let mut incoming = transport.incoming();
+            for ack in acks {
+                let req = incoming.next().unwrap().unwrap();
+                assert!(matches!(req.params, RequestParams::CoordSig(_)));
+                ids.push(req.id);
+                match ack {
+                    Some(ack) => incoming
+                        .respond(
+                            req.id,
+                            ResponseResult::Sig(SigResult {
+                                ack,
+                                already_known: false,
+                            }),
+                        )
+                        .unwrap(),
+                    None => break,
+                }
+            }
+            ids
+        })
+    }
+
+    #[test]
+    fn outbox_flush() {
+        let (pubkey, signature) = secp_sig();
+        let sig = |byte| Sig {
+            pubkey,
+            signature,
+            id: Txid::from_slice(&[byte; 32]).unwrap(),
+        };
+
+        let outbox = Outbox::new(MemoryStorage::new());
+        for byte in 0..3 {
+            outbox.queue_sig(sig(byte)).unwrap();
+        }
+        assert_eq!(outbox.len().unwrap(), 3);
+
+        // The first one is refused, the second one acked, and the connection is lost
+        // while sending the third one.
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let server = coordinator(
+            listener,
+            vec![Some(false), Some(true), None],
+            client_pubkey,
+            server_privkey.clone(),
+        );
+        let mut client = CoordinatorClient::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        let report = outbox.flush(&mut client).unwrap();
+        let ids = server.join().unwrap();
+        assert_eq!(report.sent, 1);
+        assert_eq!(report.rejected.len(), 1);
+        assert_eq!(report.rejected[0].0.message, OutboxMessage::Sig(sig(0)));
+        assert!(matches!(
+            report.rejected[0].1,
+            Error::NotAcknowledged(method::SIG)
+        ));
+        assert!(is_connection_failure(report.interrupted.as_ref().unwrap()));
+        assert_eq!(outbox.len().unwrap(), 1);
+
+        // After a restart, the one left is sent again with the same id
+        let outbox = Outbox::new(outbox.storage);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = coordinator(listener, vec![Some(true)], client_pubkey, server_privkey);
+        let mut client = CoordinatorClient::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        let report = outbox.flush(&mut client).unwrap();
+        assert_eq!(report.sent, 1);
+        assert!(report.rejected.is_empty() && report.interrupted.is_none());
+        assert_eq!(server.join().unwrap(), vec![ids[2]]);
+        assert!(outbox.is_empty().unwrap());
+    }
+
+    #[test]
+    fn outbox_entry_serde() {
+        let (pubkey, signature) = secp_sig();
+        let entry = OutboxEntry {
+            id: 42,
+            message: OutboxMessage::Sig(Sig {
+                pubkey,
+                signature,
+                id: Txid::default(),
+            }),
+        };
+        let ser = serde_json::to_string(&entry).unwrap();
+        assert_eq!(serde_json::from_str::<OutboxEntry>(&ser).unwrap(), entry);
+
+        let req = entry.request();
+        assert_eq!(req.id(), 42);
+        assert_eq!(req.method(), method::SIG);
+    }
+}
@@ -0,0 +1,128 @@
+//! Message padding and cover traffic
+//!
+//! Noise hides the content of the frames but not their length, from which a passive
+//! observer of a link can tell a `sig` from a `set_spend_tx`. With a [Padding] set (see
+//! [set_padding](crate::transport::KKTransport::set_padding)), a transport pads each
+//! message it sends to the smallest of the padding's bucket sizes that fits it, so
+//! the observer only learns in which bucket each message falls.
+//!
+//! Messages are padded with JSON whitespace, which the peers ignore when parsing
+//! them: it needs no support from the peer.
+//!
+//! The timing of the frames is still visible, for instance the burst of revocation
+//! signatures exchanged when a vault is created. With [CoverTraffic] set (see
+//! [set_cover_traffic](crate::transport::KKTransport::set_cover_traffic)), a transport
+//! waiting for a frame sends dummy frames at random intervals while the connection is
+//! idle. A dummy frame is a message of whitespace only, padded like the others, and
+//! is dropped by the peers' transports (this version or later ones) when read.
+
+use crate::noise::NOISE_PLAINTEXT_MAX_SIZE;
+
+use std::time::Duration;
+
+/// The sizes messages are padded to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Padding {
+    buckets: Vec<usize>,
+}
+
+impl Padding {
+    /// Pad messages to the smallest of these sizes that fits them. Messages larger
+    /// than all are left as is, and sizes are capped to the maximum size of a message.
+    /// A single size (for instance [NOISE_PLAINTEXT_MAX_SIZE]) makes all the messages
+    /// the same size, at the cost of bandwidth.
+    pub fn new(mut buckets: Vec<usize>) -> Self {
+        for size in buckets.iter_mut() {
+            *size = (*size).min(NOISE_PLAINTEXT_MAX_SIZE);
+        }
+        buckets.sort_unstable();
+        buckets.dedup();
+        Self { buckets }
+    }
+
+    /// The size a message of this size is padded to
+    pub fn padded_len(&self, len: usize) -> usize {
+        self.buckets
+            .iter()
+            .copied()
+            .find(|size| *size >= len)
+            .unwrap_or(len)
+    }
+}
+
+impl Default for Padding {
+    /// Buckets by powers of 4 from 512 bytes: a signature, a few signatures, a
+    /// transaction, large transactions, and up to the maximum size of a message.
+    fn default() -> Self {
+        Self::new(vec![512, 2048, 8192, 32768, NOISE_PLAINTEXT_MAX_SIZE])
+    }
+}
+
+/// The intervals at which to send dummy frames on an idle connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoverTraffic {
+    min_interval: Duration,
+    max_interval: Duration,
+}
+
+impl CoverTraffic {
+    /// Send a dummy frame after a random interval between these bounds without any
+    /// frame sent, repeatedly.
+    pub fn new(min_interval: Duration, max_interval: Duration) -> Self {
+        assert!(min_interval <= max_interval, "Invalid interval bounds");
+        Self {
+            min_interval,
+            max_interval,
+        }
+    }
+
+    /// The interval until the next dummy frame
+    pub fn next_interval(&self) -> Duration {
+        let mut random = [0u8; 8];
+        getrandom::getrandom(&mut random).expect("Failed to get randomness from the OS");
+        let spread = (self.max_interval - self.min_interval).as_nanos() as u64;
+        let offset = u64::from_le_bytes(random)
+            .checked_rem(spread + 1)
+            .unwrap_or(0);
+
+        self.min_interval + Duration::from_nanos(offset)
+    }
+}
+
+// Whether this message is a dummy frame
+pub(crate) fn is_cover(msg: &[u8]) -> bool {
+    msg.iter().all(|b| b.is_ascii_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padding_buckets() {
+        let padding = Padding::default();
+        assert_eq!(padding.padded_len(0), 512);
+        assert_eq!(padding.padded_len(512), 512);
+        assert_eq!(padding.padded_len(513), 2048);
+        assert_eq!(padding.padded_len(40_000), NOISE_PLAINTEXT_MAX_SIZE);
+
+        let padding = Padding::new(vec![1_000_000, 100, 100]);
+        assert_eq!(padding.padded_len(50), 100);
+        assert_eq!(padding.padded_len(101), NOISE_PLAINTEXT_MAX_SIZE);
+        assert_eq!(Padding::new(vec![]).padded_len(50), 50);
+    }
+
+    #[test]
+    fn cover_intervals() {
+        let cover = CoverTraffic::new(Duration::from_secs(10), Duration::from_secs(20));
+        for _ in 0..100 {
+            let interval = cover.next_interval();
+            assert!(interval >= Duration::from_secs(10) && interval <= Duration::from_secs(20));
+        }
+        let cover = CoverTraffic::new(Duration::from_secs(1), Duration::from_secs(1));
+        assert_eq!(cover.next_interval(), Duration::from_secs(1));
+
+        assert!(is_cover(b"   "));
+        assert!(!is_cover(b" {} "));
+    }
+}
@@ -0,0 +1,503 @@
+//! Named pipes transport, for Windows
+//!
+//! The Revault components running on the same Windows host can talk over a named pipe
+//! (such as `\\.\pipe\revault_coordinator`) rather than over a loopback TCP port. A
+//! [PipeTransport] enacts the same Noise KK channel as a
+//! [KKTransport](crate::transport::KKTransport) over it, and exchanges the same
+//! messages. Remote clients are rejected: a named pipe is only for local components.
+//!
+//! The pipes are blocking, and their operations don't time out.
+
+use crate::{
+    error::{Error, JsonError},
+    instrument::{log_trace, log_warn},
+    message,
+    noise::{
+        KKChannel, KKHandshakeActOne, KKHandshakeActTwo, KKMessageActOne, KKMessageActTwo,
+        NoiseEncryptedHeader, NoiseEncryptedMessage, PublicKey, SecretKey, HANDSHAKE_HASH_SIZE,
+        KK_MSG_1_SIZE, KK_MSG_2_SIZE, NOISE_MESSAGE_HEADER_SIZE,
+    },
+    server::RequestHandler,
+    transport::{parse_result, IncomingRequest, RawResult},
+};
+
+use serde::de::IgnoredAny;
+use serde_json::value::RawValue;
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    ptr,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use windows_sys::Win32::{
+    Foundation::{
+        CloseHandle, ERROR_BROKEN_PIPE, ERROR_PIPE_BUSY, ERROR_PIPE_CONNECTED, GENERIC_READ,
+        GENERIC_WRITE, HANDLE, INVALID_HANDLE_VALUE,
+    },
+    Storage::FileSystem::{
+        CreateFileW, FlushFileBuffers, ReadFile, WriteFile, FILE_FLAGS_AND_ATTRIBUTES,
+        FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_SHARE_NONE, OPEN_EXISTING, PIPE_ACCESS_DUPLEX,
+        SECURITY_IDENTIFICATION, SECURITY_SQOS_PRESENT,
+    },
+    System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, WaitNamedPipeW, NMPWAIT_USE_DEFAULT_WAIT,
+        PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES,
+        PIPE_WAIT,
+    },
+};
+
+// The size of the buffers of each pipe instance, enough for most messages
+const PIPE_BUFFER_SIZE: u32 = 64 * 1024;
+
+// The longest we wait for before accepting connections again after a failure
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+// The name of a pipe as a null-terminated wide string
+fn wide_name(name: &str) -> io::Result<Vec<u16>> {
+    if name.contains('\0') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Pipe name contains a null character",
+        ));
+    }
+    Ok(name.encode_utf16().chain(Some(0)).collect())
+}
+
+/// One end of a connected named pipe
+#[derive(Debug)]
+pub struct PipeStream {
+    handle: HANDLE,
+}
+
+// The handle of a pipe can be used from any thread, only one at a time
+unsafe impl Send for PipeStream {}
+
+impl PipeStream {
+    /// Connect to the named pipe with this name, waiting for an instance of it to be
+    /// available if they are all busy.
+    pub fn connect(name: &str) -> io::Result<PipeStream> {
+        let name = wide_name(name)?;
+        loop {
+            // The server may only identify us, not impersonate us
+            let handle = unsafe {
+                CreateFileW(
+                    name.as_ptr(),
+                    GENERIC_READ | GENERIC_WRITE,
+                    FILE_SHARE_NONE,
+                    ptr::null(),
+                    OPEN_EXISTING,
+                    SECURITY_SQOS_PRESENT | SECURITY_IDENTIFICATION,
+                    ptr::null_mut(),
+                )
+            };
+            if handle != INVALID_HANDLE_VALUE {
+                return Ok(PipeStream { handle });
+            }
+
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() != Some(ERROR_PIPE_BUSY as i32) {
+                return Err(error);
+            }
+            if unsafe { WaitNamedPipeW(name.as_ptr(), NMPWAIT_USE_DEFAULT_WAIT) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+}
+
+impl Read for PipeStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        let mut read = 0;
+        if unsafe {
+            ReadFile(
+                self.handle,
+                buf.as_mut_ptr(),
+                len,
+                &mut read,
+                ptr::null_mut(),
+            )
+        } == 0
+        {
+            let error = io::Error::last_os_error();
+            // The other end closed the pipe
+            if error.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) {
+                return Ok(0);
+            }
+            return Err(error);
+        }
+        Ok(read as usize)
+    }
+}
+
+impl Write for PipeStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        let mut written = 0;
+        if unsafe {
+            WriteFile(
+                self.handle,
+                buf.as_ptr(),
+                len,
+                &mut written,
+                ptr::null_mut(),
+            )
+        } == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(written as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if unsafe { FlushFileBuffers(self.handle) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PipeStream {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.handle) };
+    }
+}
+
+// Create an instance of the named pipe with this name, for the next client to connect
+fn create_instance(name: &[u16], first: bool) -> io::Result<PipeStream> {
+    let open_mode: FILE_FLAGS_AND_ATTRIBUTES = if first {
+        // Fail if another process already created a pipe with this name
+        PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE
+    } else {
+        PIPE_ACCESS_DUPLEX
+    };
+    let handle = unsafe {
+        CreateNamedPipeW(
+            name.as_ptr(),
+            open_mode,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            PIPE_BUFFER_SIZE,
+            PIPE_BUFFER_SIZE,
+            0,
+            ptr::null(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(PipeStream { handle })
+}
+
+/// A named pipe server, accepting the connections of the local clients
+#[derive(Debug)]
+pub struct PipeListener {
+    name: Vec<u16>,
+    // The instance the next client will connect to
+    next: Mutex<PipeStream>,
+}
+
+impl PipeListener {
+    /// Create the named pipe with this name. Fails if it already exists, for another
+    /// process not to be able to serve our clients.
+    pub fn bind(name: &str) -> io::Result<PipeListener> {
+        let name = wide_name(name)?;
+        let next = Mutex::new(create_instance(&name, true)?);
+        Ok(PipeListener { name, next })
+    }
+
+    /// Wait for a client to connect
+    pub fn accept(&self) -> io::Result<PipeStream> {
+        let mut next = self.next.lock().expect("Pipe listener lock poisoned");
+        if unsafe { ConnectNamedPipe(next.handle, ptr::null_mut()) } == 0 {
+            let error = io::Error::last_os_error();
+            // The client connected between the creation of the instance and now
+            if error.raw_os_error() != Some(ERROR_PIPE_CONNECTED as i32) {
+                return Err(error);
+            }
+        }
+        let instance = create_instance(&self.name, false)?;
+        Ok(std::mem::replace(&mut *next, instance))
+    }
+}
+
+/// Wrapper type for a named pipe and KKChannel that automatically enforces
+/// authenticated and encrypted channels when communicating
+#[derive(Debug)]
+pub struct PipeTransport {
+    stream: PipeStream,
+    channel: KKChannel,
+    // Notifications we read while waiting for a response
+    notifications: VecDeque<Vec<u8>>,
+}
+
+impl PipeTransport {
+    /// Connect to the server listening on the named pipe with this name, and enact the
+    /// Noise handshake with given private key.
+    pub fn connect(
+        name: &str,
+        my_noise_privkey: &SecretKey,
+        their_noise_pubkey: &PublicKey,
+    ) -> Result<PipeTransport, Error> {
+        let mut stream = PipeStream::connect(name).map_err(Error::Transport)?;
+        let (cli_act_1, msg_1) =
+            KKHandshakeActOne::initiator(my_noise_privkey, their_noise_pubkey)?;
+
+        // write msg_1 to stream (e, es, ss)
+        stream.write_all(&msg_1.0).map_err(Error::from_stream)?;
+
+        // read msg_2 from stream (e, ee, se)
+        let mut msg_2 = [0u8; KK_MSG_2_SIZE];
+        stream.read_exact(&mut msg_2).map_err(Error::from_stream)?;
+        let cli_act_2 = KKHandshakeActTwo::initiator(cli_act_1, &KKMessageActTwo(msg_2))?;
+        let channel = KKChannel::from_handshake(cli_act_2)?;
+
+        Ok(PipeTransport::new(stream, channel))
+    }
+
+    /// Accept a connection on this named pipe and perform the Noise KK handshake as a
+    /// responder, with our private key and a set of possible public keys for them.
+    pub fn accept(
+        listener: &PipeListener,
+        my_noise_privkey: &SecretKey,
+        their_possible_pubkeys: &[PublicKey],
+    ) -> Result<PipeTransport, Error> {
+        let stream = listener.accept().map_err(Error::Transport)?;
+        Self::accept_stream(stream, my_noise_privkey, their_possible_pubkeys)
+    }
+
+    /// Perform the Noise KK handshake as a responder on an accepted connection
+    pub fn accept_stream(
+        mut stream: PipeStream,
+        my_noise_privkey: &SecretKey,
+        their_possible_pubkeys: &[PublicKey],
+    ) -> Result<PipeTransport, Error> {
+        // read msg_1 from stream
+        let mut msg_1 = [0u8; KK_MSG_1_SIZE];
+        stream.read_exact(&mut msg_1).map_err(Error::from_stream)?;
+        let serv_act_1 = KKHandshakeActOne::responder(
+            my_noise_privkey,
+            their_possible_pubkeys,
+            &KKMessageActOne(msg_1),
+        )?;
+        let (serv_act_2, msg_2) = KKHandshakeActTwo::responder(serv_act_1)?;
+        let channel = KKChannel::from_handshake(serv_act_2)?;
+
+        // write msg_2 to stream
+        stream.write_all(&msg_2.0).map_err(Error::from_stream)?;
+
+        Ok(PipeTransport::new(stream, channel))
+    }
+
+    fn new(stream: PipeStream, channel: KKChannel) -> Self {
+        Self {
+            stream,
+            channel,
+            notifications: VecDeque::new(),
+        }
+    }
+
+    // Read and decrypt a message from the communication channel
+    fn read(&mut self) -> Result<Vec<u8>, Error> {
+        let mut header = [0u8; NOISE_MESSAGE_HEADER_SIZE];
+        self.stream
+            .read_exact(&mut header)
+            .map_err(Error::from_stream)?;
+        let msg_len = self.channel.decrypt_header(&NoiseEncryptedHeader(header))?;
+
+        let mut body = vec![0u8; msg_len as usize];
+        self.stream
+            .read_exact(&mut body)
+            .map_err(Error::from_stream)?;
+        Ok(self.channel.decrypt_message(&NoiseEncryptedMessage(body))?)
+    }
+
+    // Read a raw message from the communication channel
+    fn read_message(&mut self) -> Result<Vec<u8>, Error> {
+        match self.notifications.pop_front() {
+            Some(msg) => Ok(msg),
+            None => self.read(),
+        }
+    }
+
+    // Encrypt and write a message to the communication channel
+    fn write(&mut self, msg: &[u8]) -> Result<(), Error> {
+        let encrypted_msg = self.channel.encrypt_message(msg)?;
+        self.stream
+            .write_all(&encrypted_msg.0)
+            .map_err(Error::from_stream)
+    }
+
+    // Read the next response (or error response), queuing the notifications
+    fn read_response(&mut self) -> Result<message::Response<RawResult>, Error> {
+        loop {
+            let raw_resp = self.read()?;
+            log_trace!("Read response: '{}'", String::from_utf8_lossy(&raw_resp));
+            match serde_json::from_slice::<message::Response<Box<RawValue>>>(&raw_resp) {
+                Ok(resp) => {
+                    return Ok(message::Response {
+                        result: Ok(resp.result),
+                        id: resp.id,
+                    })
+                }
+                Err(e) => {
+                    if let Ok(resp) = serde_json::from_slice::<message::ErrorResponse>(&raw_resp) {
+                        return Ok(message::Response {
+                            result: Err(resp.error),
+                            id: resp.id,
+                        });
+                    } else if serde_json::from_slice::<message::Notification>(&raw_resp).is_ok() {
+                        log_trace!("Got a notification. Queuing it and continuing to read.");
+                        self.notifications.push_back(raw_resp);
+                    } else {
+                        return Err(Error::Json(JsonError::locate(e, &raw_resp)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send a request to the other end of the encrypted channel, and return their response.
+    pub fn send_req<T>(&mut self, req: &message::Request<'_>) -> Result<T, Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.write(&serde_json::to_vec(req)?)?;
+
+        loop {
+            let resp = self.read_response()?;
+            if resp.id == req.id() {
+                return parse_result(resp.result);
+            } else {
+                log_trace!("Reponse was not for us. Continuing to read.");
+            }
+        }
+    }
+
+    /// Read a notification from the other end of the encrypted channel. Notifications
+    /// that were received while waiting for a response in [PipeTransport::send_req] are
+    /// returned first.
+    pub fn read_notification(&mut self) -> Result<message::NotificationParams, Error> {
+        loop {
+            let raw_notif = self.read_message()?;
+            log_trace!(
+                "Read notification: '{}'",
+                String::from_utf8_lossy(&raw_notif)
+            );
+            match serde_json::from_slice::<message::Notification>(&raw_notif) {
+                Ok(notif) => return Ok(notif.params()),
+                Err(e) => {
+                    if serde_json::from_slice::<message::Response<IgnoredAny>>(&raw_notif).is_err()
+                    {
+                        return Err(e.into());
+                    }
+                    log_trace!("Got a response to no pending request. Dropping it.");
+                }
+            }
+        }
+    }
+
+    /// Read the next request from the other end of the encrypted channel, to respond to
+    /// it with [PipeTransport::respond] (or [PipeTransport::respond_error]) using its id.
+    pub fn read_request(&mut self) -> Result<IncomingRequest, Error> {
+        let raw_req = self.read()?;
+        log_trace!("Read request: '{}'", String::from_utf8_lossy(&raw_req));
+        serde_json::from_slice::<message::Request>(&raw_req)
+            .map(|req| IncomingRequest {
+                id: req.id(),
+                params: req.params(),
+            })
+            .map_err(|e| Error::Json(JsonError::locate(e, &raw_req)))
+    }
+
+    /// Respond to the request with this id.
+    pub fn respond(&mut self, id: u32, result: message::ResponseResult) -> Result<(), Error> {
+        self.write(&serde_json::to_vec(&message::Response { result, id })?)
+    }
+
+    /// Respond to the request with this id with an error.
+    pub fn respond_error(&mut self, id: u32, error: message::ResponseError) -> Result<(), Error> {
+        self.write(&serde_json::to_vec(&message::ErrorResponse { error, id })?)
+    }
+
+    /// Send a notification to the other end of the encrypted channel.
+    pub fn send_notification(&mut self, notif: &message::Notification) -> Result<(), Error> {
+        self.write(&serde_json::to_vec(notif)?)
+    }
+
+    /// Get the static public key of the peer
+    pub fn remote_static(&self) -> PublicKey {
+        self.channel.remote_static()
+    }
+
+    /// Get the hash of the handshake of this session, for channel binding (see
+    /// [KKChannel::handshake_hash])
+    pub fn handshake_hash(&self) -> [u8; HANDSHAKE_HASH_SIZE] {
+        self.channel.handshake_hash()
+    }
+}
+
+/// Answer the requests read from this connection with this handler, until the peer
+/// disconnects. Invalid requests are ignored.
+pub fn serve<H: RequestHandler + ?Sized>(
+    transport: &mut PipeTransport,
+    handler: &H,
+) -> Result<(), Error> {
+    let peer = transport.remote_static();
+
+    loop {
+        match transport.read_request() {
+            Ok(IncomingRequest { id, params }) => match handler.reply(&peer, id, params) {
+                Some(Ok(result)) => transport.respond(id, result)?,
+                Some(Err(error)) => transport.respond_error(id, error)?,
+                None => {}
+            },
+            Err(Error::Json(e)) => log_warn!("Ignoring invalid request: '{}'", e),
+            Err(Error::Disconnected(_)) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Accept the connections of the peers with these keys on this named pipe, and serve
+/// each of them with this handler on its own thread. Only returns on a failure to
+/// spawn a thread.
+pub fn listen<H: RequestHandler + Send + Sync + 'static>(
+    listener: &PipeListener,
+    my_noise_privkey: &SecretKey,
+    their_possible_pubkeys: &[PublicKey],
+    handler: Arc<H>,
+) -> Result<(), Error> {
+    let mut backoff = Duration::from_millis(10);
+    loop {
+        // Failures to accept don't go away at once, don't spin on them
+        let stream = match listener.accept() {
+            Ok(stream) => stream,
+            Err(e) => {
+                log_warn!("Error accepting pipe connection: '{}'", e);
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        backoff = Duration::from_millis(10);
+
+        let (handler, my_noise_privkey) = (handler.clone(), my_noise_privkey.clone());
+        let their_possible_pubkeys = their_possible_pubkeys.to_vec();
+        thread::Builder::new()
+            .name("revault_net pipe".to_string())
+            .spawn(move || {
+                let res = PipeTransport::accept_stream(
+                    stream,
+                    &my_noise_privkey,
+                    &their_possible_pubkeys,
+                )
+                .and_then(|mut transport| serve(&mut transport, handler.as_ref()));
+                if let Err(e) = res {
+                    log_warn!("Error serving pipe connection: '{}'", e);
+                }
+            })?;
+    }
+}
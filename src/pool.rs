@@ -0,0 +1,110 @@
+//! Frame buffer pooling
+//!
+//! Reading or writing a frame on a [KKTransport](crate::transport::KKTransport) needs
+//! a buffer for its ciphertext and one for the serialized message. Rather than
+//! allocating them for each frame, transports take them from a [BufferPool] and give
+//! them back once the frame is read or written, so that a server handling many
+//! connections reuses the same few buffers.
+//!
+//! All transports share a [global](BufferPool::global) pool by default, a different
+//! one can be set per transport with
+//! [set_buffer_pool](crate::transport::KKTransport::set_buffer_pool).
+
+use crate::noise::NOISE_MESSAGE_MAX_SIZE;
+
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// The number of buffers kept by the global pool
+pub const GLOBAL_POOL_SIZE: usize = 64;
+
+static GLOBAL: OnceLock<Arc<BufferPool>> = OnceLock::new();
+
+/// A bounded set of reusable buffers.
+#[derive(Debug)]
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_buffers: usize,
+}
+
+impl BufferPool {
+    /// A pool keeping up to this number of buffers. Buffers given back while it is
+    /// full are freed.
+    pub fn new(max_buffers: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+            max_buffers,
+        }
+    }
+
+    /// The pool used by the transports unless set otherwise, keeping up to
+    /// [GLOBAL_POOL_SIZE] buffers.
+    pub fn global() -> Arc<Self> {
+        GLOBAL
+            .get_or_init(|| Arc::new(Self::new(GLOBAL_POOL_SIZE)))
+            .clone()
+    }
+
+    /// Take an empty buffer from the pool, or allocate a new one if there is none.
+    pub fn get(&self) -> Vec<u8> {
+        self.buffers.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    /// Give back a buffer to the pool. Its content is discarded.
+    pub fn put(&self, mut buffer: Vec<u8>) {
+        // Frames can't be larger, so this is most likely not a buffer of ours
+        if buffer.capacity() > NOISE_MESSAGE_MAX_SIZE {
+            return;
+        }
+        buffer.clear();
+
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < self.max_buffers {
+            buffers.push(buffer);
+        }
+    }
+
+    /// The number of buffers currently in the pool
+    pub fn len(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+
+    /// Whether the pool has no buffer to give, that is whether the next [get](Self::get)
+    /// will allocate
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_pool() {
+        let pool = BufferPool::new(2);
+        assert!(pool.is_empty());
+        let mut buf = pool.get();
+        buf.extend_from_slice(&[1; 100]);
+        let ptr = buf.as_ptr();
+        pool.put(buf);
+        assert_eq!(pool.len(), 1);
+
+        // The same allocation is handed back, emptied
+        let buf = pool.get();
+        assert!(buf.is_empty());
+        assert!(buf.capacity() >= 100);
+        assert_eq!(buf.as_ptr(), ptr);
+        assert!(pool.is_empty());
+
+        // Bounded, in number and size of buffers
+        pool.put(buf);
+        pool.put(Vec::with_capacity(10));
+        pool.put(Vec::with_capacity(10));
+        assert_eq!(pool.len(), 2);
+        let pool = BufferPool::new(2);
+        pool.put(Vec::with_capacity(NOISE_MESSAGE_MAX_SIZE + 1));
+        assert!(pool.is_empty());
+
+        assert!(Arc::ptr_eq(&BufferPool::global(), &BufferPool::global()));
+    }
+}
@@ -0,0 +1,300 @@
+//! Connection prelude
+//!
+//! A Noise handshake with the wrong endpoint (a testnet coordinator for a mainnet
+//! wallet, or another service altogether on this port) just fails with an obscure
+//! handshake error, or hangs. A [Prelude] is a few bytes exchanged in clear before the
+//! handshake, telling the protocol, network and versions supported by each end, so
+//! that such a misconfiguration fails immediately with a [PreludeError]. Both ends
+//! then speak the highest version they support, and if there is none the error lists
+//! the versions of each end.
+//!
+//! The prelude is opt-in (see
+//! [KKTransport::connect_with_prelude](crate::transport::KKTransport::connect_with_prelude)
+//! and [KKTransport::accept_with_prelude](crate::transport::KKTransport::accept_with_prelude)):
+//! both ends must use it, as a peer that does not would take it for the start of the
+//! handshake.
+
+use crate::{error::Error, PreludeError};
+
+use bitcoin::Network;
+use std::{
+    io::{Read, Write},
+    ops::RangeInclusive,
+};
+
+/// The magic bytes starting a prelude
+pub const PRELUDE_MAGIC: [u8; 4] = *b"RVLT";
+/// The size of a prelude: the magic, the network magic and the lowest and highest
+/// versions supported
+pub const PRELUDE_SIZE: usize = 10;
+/// The lowest version of the protocol supported by this library, the
+/// [v1](crate::message::v1) messages
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+/// The highest version of the protocol supported by this library, the
+/// [v2](crate::message::v2) messages
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// What an end of the connection tells about itself before the handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prelude {
+    /// The Bitcoin network the end is operating on
+    pub network: Network,
+    /// The lowest version of the protocol the end supports
+    pub min_version: u8,
+    /// The highest version of the protocol the end supports
+    pub max_version: u8,
+}
+
+impl Prelude {
+    /// The prelude for this network, supporting the versions from
+    /// [MIN_PROTOCOL_VERSION] to [PROTOCOL_VERSION]
+    pub fn new(network: Network) -> Self {
+        Self {
+            network,
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+        }
+    }
+
+    /// Only support the versions of the protocol in this range, for instance once a
+    /// deployment dropped the older ones
+    pub fn with_versions(mut self, versions: RangeInclusive<u8>) -> Self {
+        self.min_version = *versions.start();
+        self.max_version = *versions.end();
+        self
+    }
+
+    /// The versions of the protocol supported
+    pub fn versions(&self) -> RangeInclusive<u8> {
+        self.min_version..=self.max_version
+    }
+
+    /// The prelude as written on the wire
+    pub fn to_bytes(&self) -> [u8; PRELUDE_SIZE] {
+        let mut bytes = [0u8; PRELUDE_SIZE];
+        bytes[..4].copy_from_slice(&PRELUDE_MAGIC);
+        bytes[4..8].copy_from_slice(&self.network.magic().to_le_bytes());
+        bytes[8] = self.min_version;
+        bytes[9] = self.max_version;
+        bytes
+    }
+
+    /// Parse a prelude read from the wire
+    pub fn from_bytes(bytes: &[u8; PRELUDE_SIZE]) -> Result<Self, PreludeError> {
+        if bytes[..4] != PRELUDE_MAGIC {
+            return Err(PreludeError::NotRevault);
+        }
+        let mut magic = [0u8; 4];
+        magic.copy_from_slice(&bytes[4..8]);
+        let magic = u32::from_le_bytes(magic);
+        let network = Network::from_magic(magic).ok_or(PreludeError::UnknownNetwork(magic))?;
+
+        Ok(Self {
+            network,
+            min_version: bytes[8],
+            max_version: bytes[9],
+        })
+    }
+
+    /// Check the prelude of the peer is compatible with ours, and return the version
+    /// of the protocol to speak: the highest one supported by both ends.
+    pub fn negotiate(&self, theirs: &Prelude) -> Result<u8, PreludeError> {
+        if self.network != theirs.network {
+            return Err(PreludeError::NetworkMismatch {
+                ours: self.network,
+                theirs: theirs.network,
+            });
+        }
+        let version = self.max_version.min(theirs.max_version);
+        if version < self.min_version.max(theirs.min_version) {
+            return Err(PreludeError::UnsupportedVersion {
+                ours: self.versions(),
+                theirs: theirs.versions(),
+            });
+        }
+
+        Ok(version)
+    }
+
+    /// Exchange the preludes as the initiator of the connection: write ours, then read
+    /// the one of the peer. Returns the version of the protocol to speak.
+    pub fn initiate<S: Read + Write>(&self, stream: &mut S) -> Result<u8, Error> {
+        stream.write_all(&self.to_bytes())?;
+        let theirs = read_prelude(stream)?;
+        Ok(self.negotiate(&theirs)?)
+    }
+
+    /// Exchange the preludes as the responder of the connection: read the one of the
+    /// peer and answer with ours. The peer is answered even if incompatible so that it
+    /// may report why (for instance the versions we support), unless it did not send a
+    /// prelude at all. Returns the version of the protocol to speak.
+    pub fn respond<S: Read + Write>(&self, stream: &mut S) -> Result<u8, Error> {
+        let theirs = read_prelude(stream);
+        if !matches!(theirs, Err(Error::Prelude(PreludeError::NotRevault))) {
+            stream.write_all(&self.to_bytes())?;
+        }
+        Ok(self.negotiate(&theirs?)?)
+    }
+}
+
+fn read_prelude<S: Read>(stream: &mut S) -> Result<Prelude, Error> {
+    let mut bytes = [0u8; PRELUDE_SIZE];
+    stream.read_exact(&mut bytes).map_err(Error::from_stream)?;
+    Ok(Prelude::from_bytes(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::local_endpoints,
+        message::{
+            coordinator::{GetSigs, Sigs},
+            ResponseResult, SigSet,
+        },
+        transport::KKTransport,
+    };
+
+    use std::{net::TcpStream, thread};
+
+    #[test]
+    fn prelude_exchange() {
+        for network in &[
+            Network::Bitcoin,
+            Network::Testnet,
+            Network::Signet,
+            Network::Regtest,
+        ] {
+            let prelude = Prelude::new(*network);
+            assert_eq!(Prelude::from_bytes(&prelude.to_bytes()).unwrap(), prelude);
+        }
+        let mut bytes = Prelude::new(Network::Bitcoin).to_bytes();
+        bytes[4] ^= 1;
+        assert!(matches!(
+            Prelude::from_bytes(&bytes),
+            Err(PreludeError::UnknownNetwork(_))
+        ));
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let server = thread::spawn(move || {
+            let prelude = Prelude::new(Network::Bitcoin);
+            let mut transport = KKTransport::accept_with_prelude(
+                &listener,
+                &server_privkey,
+                &[client_pubkey],
+                &prelude,
+            )
+            .unwrap();
+            assert_eq!(transport.protocol_version(), Some(PROTOCOL_VERSION));
+            transport
+                .read_req(|_| {
+                    Some(ResponseResult::Sigs(Sigs {
+                        signatures: SigSet::new(),
+                    }))
+                })
+                .unwrap();
+
+            // A testnet wallet
+            match KKTransport::accept_with_prelude(
+                &listener,
+                &server_privkey,
+                &[client_pubkey],
+                &prelude,
+            ) {
+                Err(Error::Prelude(PreludeError::NetworkMismatch { ours, theirs })) => {
+                    assert_eq!((ours, theirs), (Network::Bitcoin, Network::Testnet))
+                }
+                res => panic!("Unexpected {:?}", res),
+            }
+
+            // A wallet too recent
+            match KKTransport::accept_with_prelude(
+                &listener,
+                &server_privkey,
+                &[client_pubkey],
+                &prelude,
+            ) {
+                Err(Error::Prelude(PreludeError::UnsupportedVersion { theirs, .. })) => {
+                    assert_eq!(theirs, 3..=4)
+                }
+                res => panic!("Unexpected {:?}", res),
+            }
+
+            // Something else
+            match KKTransport::accept_with_prelude(
+                &listener,
+                &server_privkey,
+                &[client_pubkey],
+                &prelude,
+            ) {
+                Err(Error::Prelude(PreludeError::NotRevault)) => {}
+                res => panic!("Unexpected {:?}", res),
+            }
+        });
+
+        let mut transport = KKTransport::connect_with_prelude(
+            addr,
+            &client_privkey,
+            &server_pubkey,
+            &Prelude::new(Network::Bitcoin),
+        )
+        .unwrap();
+        let get_sigs = GetSigs {
+            id: Default::default(),
+            if_none_match: None,
+        };
+        transport.send_req::<Sigs>(&get_sigs.into()).unwrap();
+        assert_eq!(transport.protocol_version(), Some(PROTOCOL_VERSION));
+
+        match KKTransport::connect_with_prelude(
+            addr,
+            &client_privkey,
+            &server_pubkey,
+            &Prelude::new(Network::Testnet),
+        ) {
+            Err(Error::Prelude(PreludeError::NetworkMismatch { ours, theirs })) => {
+                assert_eq!((ours, theirs), (Network::Testnet, Network::Bitcoin))
+            }
+            res => panic!("Unexpected {:?}", res),
+        }
+
+        // The server tells the versions it supports
+        match KKTransport::connect_with_prelude(
+            addr,
+            &client_privkey,
+            &server_pubkey,
+            &Prelude::new(Network::Bitcoin).with_versions(3..=4),
+        ) {
+            Err(Error::Prelude(PreludeError::UnsupportedVersion { ours, theirs })) => {
+                assert_eq!((ours, theirs), (3..=4, 1..=2))
+            }
+            res => panic!("Unexpected {:?}", res),
+        }
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        server.join().unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(stream.read_to_end(&mut buf).unwrap_or(0), 0);
+
+        // A newer wallet
+        let theirs = Prelude::new(Network::Regtest).with_versions(2..=4);
+        assert_eq!(Prelude::new(Network::Regtest).negotiate(&theirs), Ok(2));
+        let theirs = theirs.with_versions(3..=4);
+        let err = Prelude::new(Network::Regtest)
+            .negotiate(&theirs)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            PreludeError::UnsupportedVersion {
+                ours: MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION,
+                theirs: 3..=4,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Peer supports protocol versions '3' to '4' but we support '1' to '2'"
+        );
+    }
+}
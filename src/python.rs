@@ -0,0 +1,358 @@
+//! Python bindings
+//!
+//! A `revault_net` Python module over the transport, so that test suites and
+//! integration scripts can drive coordinators, watchtowers and cosigners with this
+//! implementation of the protocol. Build it with
+//! `cargo rustc --release --features python,pyo3/extension-module --crate-type cdylib`
+//! and rename the library to `revault_net.so`.
+//!
+//! The params and results of the messages are passed as dicts of their JSON fields.
+//! They are checked against the message types: params that don't match the method
+//! raise a `ValueError` before anything is sent, and so do malformed results. Errors
+//! from the transport raise a `TimeoutError`, a `ConnectionError` or a `RevaultError`,
+//! and error responses from the peer a `RemoteError`.
+
+use crate::{
+    error::Error,
+    message,
+    noise::{gen_keypair as noise_gen_keypair, PublicKey, SecretKey, KEY_SIZE},
+    transport::KKTransport,
+};
+
+use pyo3::{
+    create_exception,
+    exceptions::{PyConnectionError, PyException, PyTimeoutError, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+use serde_json::value::RawValue;
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::Mutex,
+    time::Duration,
+};
+
+create_exception!(
+    revault_net,
+    RevaultError,
+    PyException,
+    "An error communicating with a peer."
+);
+create_exception!(
+    revault_net,
+    RemoteError,
+    RevaultError,
+    "The peer responded to the request with an error. The args are its code and message."
+);
+
+fn py_err(error: Error) -> PyErr {
+    match error {
+        Error::Timeout(_) => PyTimeoutError::new_err(error.to_string()),
+        Error::Disconnected(_) => PyConnectionError::new_err(error.to_string()),
+        Error::Remote(error) => RemoteError::new_err((error.code.code(), error.message)),
+        Error::Correlated { error, .. } => py_err(*error),
+        Error::Json(_) | Error::Message(_) => PyValueError::new_err(error.to_string()),
+        _ => RevaultError::new_err(error.to_string()),
+    }
+}
+
+fn value_err(error: impl ToString) -> PyErr {
+    PyValueError::new_err(error.to_string())
+}
+
+fn key(bytes: &[u8]) -> PyResult<[u8; KEY_SIZE]> {
+    let mut key = [0; KEY_SIZE];
+    if bytes.len() != KEY_SIZE {
+        return Err(value_err(format!("Keys are {} bytes", KEY_SIZE)));
+    }
+    key.copy_from_slice(bytes);
+    Ok(key)
+}
+
+fn parse_addr(addr: &str) -> PyResult<SocketAddr> {
+    addr.parse().map_err(value_err)
+}
+
+// The JSON serialization of a Python object, and back
+fn dumps(obj: &Bound<'_, PyAny>) -> PyResult<String> {
+    let json = obj.py().import("json")?;
+    json.call_method1("dumps", (obj,))?.extract()
+}
+
+fn loads(py: Python<'_>, json: &str) -> PyResult<PyObject> {
+    Ok(py.import("json")?.call_method1("loads", (json,))?.unbind())
+}
+
+/// Generate a static Noise keypair, as a tuple of the public and private keys.
+#[pyfunction]
+fn gen_keypair(py: Python<'_>) -> (Bound<'_, PyBytes>, Bound<'_, PyBytes>) {
+    let (PublicKey(pubkey), SecretKey(privkey)) = noise_gen_keypair();
+    (PyBytes::new(py, &pubkey), PyBytes::new(py, &privkey))
+}
+
+/// An encrypted and authenticated connection to a peer
+#[pyclass(module = "revault_net")]
+#[derive(Debug)]
+pub struct Transport {
+    // Python objects must be Sync, the methods take it mutably anyways
+    inner: Mutex<KKTransport>,
+}
+
+#[pymethods]
+impl Transport {
+    /// Connect to the server at `addr` (such as `"127.0.0.1:8383"`) and enact the
+    /// Noise handshake with our private key and its public key.
+    #[staticmethod]
+    fn connect(py: Python<'_>, addr: &str, privkey: &[u8], their_pubkey: &[u8]) -> PyResult<Self> {
+        let addr = parse_addr(addr)?;
+        let (privkey, their_pubkey) = (SecretKey(key(privkey)?), PublicKey(key(their_pubkey)?));
+        let inner = py
+            .allow_threads(|| KKTransport::connect(addr, &privkey, &their_pubkey))
+            .map_err(py_err)?;
+        Ok(Self {
+            inner: Mutex::new(inner),
+        })
+    }
+
+    /// Send a request for this method with these params, and return the result of its
+    /// response.
+    fn send(
+        &mut self,
+        py: Python<'_>,
+        method: &str,
+        params: &Bound<'_, PyAny>,
+    ) -> PyResult<PyObject> {
+        let params: Box<RawValue> = serde_json::from_str(&dumps(params)?).map_err(value_err)?;
+        let checked: message::RequestParams =
+            serde_json::from_str(params.get()).map_err(value_err)?;
+        if checked.method() != method {
+            return Err(value_err(format!(
+                "Params are the ones of a '{}' request, not '{}'",
+                checked.method(),
+                method
+            )));
+        }
+        let raw_req = serde_json::json!({
+            "method": method,
+            "params": params,
+            "id": message::next_request_id(),
+        })
+        .to_string();
+        let req: message::Request = serde_json::from_str(&raw_req).map_err(value_err)?;
+
+        let inner = self.inner.get_mut().expect("Never poisoned");
+        let result: Box<RawValue> = py.allow_threads(|| inner.send_req(&req)).map_err(py_err)?;
+        serde_json::from_str::<message::ResponseResult>(result.get()).map_err(value_err)?;
+        loads(py, result.get())
+    }
+
+    /// Wait for a request from the peer, and return its id, method and params.
+    fn read_request(&mut self, py: Python<'_>) -> PyResult<(u32, &'static str, PyObject)> {
+        let inner = self.inner.get_mut().expect("Never poisoned");
+        let incoming = py
+            .allow_threads(|| inner.incoming().next())
+            .ok_or_else(|| PyConnectionError::new_err("The peer closed the connection"))?
+            .map_err(py_err)?;
+        let params = serde_json::to_string(&incoming.params).map_err(value_err)?;
+
+        Ok((incoming.id, incoming.params.method(), loads(py, &params)?))
+    }
+
+    /// Respond to the request with this id with this result.
+    fn respond(&mut self, py: Python<'_>, id: u32, result: &Bound<'_, PyAny>) -> PyResult<()> {
+        let result: Box<RawValue> = serde_json::from_str(&dumps(result)?).map_err(value_err)?;
+        serde_json::from_str::<message::ResponseResult>(result.get()).map_err(value_err)?;
+
+        let inner = self.inner.get_mut().expect("Never poisoned");
+        py.allow_threads(|| inner._write_resp(&message::Response { result, id }))
+            .map_err(py_err)
+    }
+
+    /// Respond to the request with this id with an error of this code and message.
+    fn respond_error(
+        &mut self,
+        py: Python<'_>,
+        id: u32,
+        code: i64,
+        message: String,
+    ) -> PyResult<()> {
+        let error = message::ResponseError::new(message::ErrorCode::from_code(code), message);
+        let inner = self.inner.get_mut().expect("Never poisoned");
+        py.allow_threads(|| inner.respond_error(id, error))
+            .map_err(py_err)
+    }
+
+    /// Wait for a notification from the peer, and return its method and params.
+    fn read_notification(&mut self, py: Python<'_>) -> PyResult<(&'static str, PyObject)> {
+        let inner = self.inner.get_mut().expect("Never poisoned");
+        let params = py
+            .allow_threads(|| inner.read_notification())
+            .map_err(py_err)?;
+        let method = match params {
+            message::NotificationParams::NewSig(_) => message::method::NEW_SIG,
+            message::NotificationParams::NewSpendTx(_) => message::method::NEW_SPEND_TX,
+            #[cfg(feature = "revault_tx")]
+            message::NotificationParams::KeyRotation(_) => message::method::KEY_ROTATION,
+        };
+        let params = serde_json::to_string(&params).map_err(value_err)?;
+
+        Ok((method, loads(py, &params)?))
+    }
+
+    /// Set the timeout of the reads, in seconds. `None` means reads block indefinitely.
+    #[pyo3(signature = (timeout))]
+    fn set_read_timeout(&mut self, timeout: Option<f64>) -> PyResult<()> {
+        let timeout = timeout
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(value_err)?;
+        self.inner
+            .get_mut()
+            .expect("Never poisoned")
+            .set_read_timeout(timeout)
+            .map_err(py_err)
+    }
+
+    /// The static public key of the peer
+    fn remote_static<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        let inner = self.inner.lock().expect("Never poisoned");
+        PyBytes::new(py, &inner.remote_static().0)
+    }
+}
+
+/// A socket listening for incoming connections
+#[pyclass(module = "revault_net")]
+#[derive(Debug)]
+pub struct Listener {
+    inner: TcpListener,
+}
+
+#[pymethods]
+impl Listener {
+    /// Listen for connections on `addr` (such as `"0.0.0.0:8383"`).
+    #[new]
+    fn new(addr: &str) -> PyResult<Self> {
+        let inner =
+            TcpListener::bind(parse_addr(addr)?).map_err(|e| py_err(Error::Transport(e)))?;
+        Ok(Self { inner })
+    }
+
+    /// Accept a connection and enact the Noise handshake as a responder with our
+    /// private key, the peer having one of these public keys.
+    fn accept(
+        &self,
+        py: Python<'_>,
+        privkey: &[u8],
+        their_pubkeys: Vec<Vec<u8>>,
+    ) -> PyResult<Transport> {
+        let privkey = SecretKey(key(privkey)?);
+        let their_pubkeys = their_pubkeys
+            .iter()
+            .map(|pubkey| key(pubkey).map(PublicKey))
+            .collect::<PyResult<Vec<_>>>()?;
+        let listener = &self.inner;
+        let inner = py
+            .allow_threads(|| KKTransport::accept(listener, &privkey, &their_pubkeys))
+            .map_err(py_err)?;
+        Ok(Transport {
+            inner: Mutex::new(inner),
+        })
+    }
+
+    /// The address the socket is bound to, as `"ip:port"`
+    #[getter]
+    fn local_addr(&self) -> PyResult<String> {
+        self.inner
+            .local_addr()
+            .map(|addr| addr.to_string())
+            .map_err(|e| py_err(Error::Transport(e)))
+    }
+}
+
+/// The `revault_net` Python module
+#[pymodule]
+pub fn revault_net(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(gen_keypair, module)?)?;
+    module.add_class::<Transport>()?;
+    module.add_class::<Listener>()?;
+    module.add("RevaultError", module.py().get_type::<RevaultError>())?;
+    module.add("RemoteError", module.py().get_type::<RemoteError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn python_roundtrip() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "revault_net").unwrap();
+            revault_net(&module).unwrap();
+            // As if it was imported from the built library
+            py.import("sys")
+                .unwrap()
+                .getattr("modules")
+                .unwrap()
+                .set_item("revault_net", module)
+                .unwrap();
+            let code = CString::new(
+                r#"
+import threading
+from revault_net import Listener, Transport, RemoteError, gen_keypair
+
+(client_pub, client_priv), (server_pub, server_priv) = gen_keypair(), gen_keypair()
+listener = Listener("127.0.0.1:0")
+
+errors = []
+
+def serve():
+    try:
+        handle()
+    except Exception as e:
+        errors.append(e)
+
+def handle():
+    transport = listener.accept(server_priv, [client_pub])
+    assert transport.remote_static() == client_pub
+    id, method, params = transport.read_request()
+    assert (method, params) == ("get_sigs", {"id": "00" * 32}), (method, params)
+    transport.respond(id, {"signatures": {}})
+    id, _, _ = transport.read_request()
+    transport.respond_error(id, -32603, "Not today")
+    try:
+        transport.read_request()
+        assert False
+    except ConnectionError:
+        pass
+
+server = threading.Thread(target=serve)
+server.start()
+client = Transport.connect(listener.local_addr, client_priv, server_pub)
+client.set_read_timeout(5)
+assert client.send("get_sigs", {"id": "00" * 32}) == {"signatures": {}}
+try:
+    client.send("get_sigs", {"id": "00" * 32})
+    assert False
+except RemoteError as e:
+    assert e.args[1] == "Not today", e.args
+
+# Malformed params never make it to the wire
+for method, params in [("get_sigs", {"id": "not a txid"}), ("get_spend_tx", {"id": "00" * 32})]:
+    try:
+        client.send(method, params)
+        assert False
+    except ValueError:
+        pass
+del client
+server.join()
+assert not errors, errors
+"#,
+            )
+            .unwrap();
+            py.run(&code, None, None).unwrap();
+        });
+    }
+}
@@ -0,0 +1,230 @@
+//! Quarantine of the rejected messages
+//!
+//! A debugging facility to keep the last messages a server rejected (malformed, failing
+//! the validation, or for a method the peer is not allowed to call), in order to
+//! diagnose interoperability issues between implementations without capturing all the
+//! traffic. It is opt-in, per transport with
+//! [set_quarantine](crate::transport::KKTransport::set_quarantine), and per access
+//! policy with [with_quarantine](crate::server::access::PeerPolicy::with_quarantine).
+//!
+//! The memory it uses is bounded: only the `capacity` most recent messages are kept,
+//! each truncated to [MAX_QUARANTINED_SIZE] bytes. Their secrets are redacted: the
+//! signatures, public keys and transactions of the valid JSON ones, and any long hex
+//! string of the others.
+
+use crate::{capture::REDACTED, events::DropReason, message, noise::PublicKey};
+
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The maximum size of a quarantined message, longer ones are truncated
+pub const MAX_QUARANTINED_SIZE: usize = 4096;
+
+// The length from which a hex string in a message that is not JSON is redacted. Long
+// enough to reveal the txids, short enough to redact a compressed public key.
+const MIN_REDACTED_HEX_LEN: usize = 65;
+
+/// A message that was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuarantinedMessage {
+    /// When it was rejected, in milliseconds since the UNIX epoch
+    pub timestamp: u128,
+    /// The peer that sent it
+    pub peer: PublicKey,
+    /// Why it was rejected
+    pub reason: DropReason,
+    /// The message, redacted and maybe truncated
+    pub payload: String,
+    /// Whether the payload was truncated
+    pub truncated: bool,
+}
+
+/// The last messages rejected
+#[derive(Debug)]
+pub struct Quarantine {
+    capacity: usize,
+    messages: Mutex<VecDeque<QuarantinedMessage>>,
+}
+
+impl Quarantine {
+    /// Keep the `capacity` most recent rejected messages
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            messages: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    // Record a message of this peer rejected for this reason
+    pub(crate) fn record(&self, peer: PublicKey, reason: DropReason, msg: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let (payload, truncated) = redacted_payload(msg);
+
+        let mut messages = self.messages.lock().unwrap_or_else(|e| e.into_inner());
+        if messages.len() == self.capacity {
+            messages.pop_front();
+        }
+        messages.push_back(QuarantinedMessage {
+            timestamp,
+            peer,
+            reason,
+            payload,
+            truncated,
+        });
+    }
+
+    /// The quarantined messages, from the oldest to the most recent
+    pub fn messages(&self) -> Vec<QuarantinedMessage> {
+        let messages = self.messages.lock().unwrap_or_else(|e| e.into_inner());
+        messages.iter().cloned().collect()
+    }
+
+    /// Get the quarantined messages, from the oldest to the most recent, and forget
+    /// about them
+    pub fn take(&self) -> Vec<QuarantinedMessage> {
+        let mut messages = self.messages.lock().unwrap_or_else(|e| e.into_inner());
+        messages.drain(..).collect()
+    }
+}
+
+// The message with its secrets redacted, truncated if too large
+fn redacted_payload(msg: &[u8]) -> (String, bool) {
+    let mut payload = match serde_json::from_slice::<serde_json::Value>(msg) {
+        Ok(mut value) => {
+            message::redact_json(&mut value, &|value| *value = REDACTED.into());
+            value.to_string()
+        }
+        Err(_) => redact_hex(&String::from_utf8_lossy(msg)),
+    };
+
+    let truncated = payload.len() > MAX_QUARANTINED_SIZE;
+    if truncated {
+        let mut end = MAX_QUARANTINED_SIZE;
+        while !payload.is_char_boundary(end) {
+            end -= 1;
+        }
+        payload.truncate(end);
+    }
+
+    (payload, truncated)
+}
+
+// Replace the long hex strings, which may be signatures, public keys or transactions
+fn redact_hex(msg: &str) -> String {
+    let mut redacted = String::with_capacity(msg.len());
+    let mut hex_run = String::new();
+    let flush = |redacted: &mut String, hex_run: &mut String| {
+        if hex_run.len() >= MIN_REDACTED_HEX_LEN {
+            redacted.push_str(REDACTED);
+        } else {
+            redacted.push_str(hex_run);
+        }
+        hex_run.clear();
+    };
+
+    for c in msg.chars() {
+        if c.is_ascii_hexdigit() {
+            hex_run.push(c);
+        } else {
+            flush(&mut redacted, &mut hex_run);
+            redacted.push(c);
+        }
+    }
+    flush(&mut redacted, &mut hex_run);
+
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::local_endpoints, noise::gen_keypair, transport::KKTransport};
+
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn quarantine() {
+        let (peer, _) = gen_keypair();
+        let quarantine = Quarantine::new(2);
+        let pubkey = "035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c";
+        let txid = "0000000000000000000000000000000000000000000000000000000000000000";
+
+        // Valid JSON
+        let msg = format!(
+            r#"{{"method":"sig","params":{{"pubkey":"{}","id":"{}"}},"id":12}}"#,
+            pubkey, txid
+        );
+        quarantine.record(peer, DropReason::InvalidMessage, msg.as_bytes());
+        // Not JSON
+        let msg = format!(
+            r#"{{"method":"sig","params":{{"pubkey":"{}","id":"{}""#,
+            pubkey, txid
+        );
+        quarantine.record(peer, DropReason::MalformedJson, msg.as_bytes());
+        let messages = quarantine.messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0].payload,
+            format!(
+                r#"{{"id":12,"method":"sig","params":{{"id":"{}","pubkey":"<redacted>"}}}}"#,
+                txid
+            )
+        );
+        assert_eq!(messages[0].reason, DropReason::InvalidMessage);
+        assert_eq!(
+            messages[1].payload,
+            format!(
+                r#"{{"method":"sig","params":{{"pubkey":"<redacted>","id":"{}""#,
+                txid
+            )
+        );
+        assert!(!messages.iter().any(|msg| msg.truncated));
+
+        // Only the most recent ones are kept, truncated
+        quarantine.record(
+            peer,
+            DropReason::MalformedJson,
+            &[b'x'; MAX_QUARANTINED_SIZE + 1],
+        );
+        let messages = quarantine.take();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].reason, DropReason::MalformedJson);
+        assert!(messages[1].truncated);
+        assert_eq!(messages[1].payload.len(), MAX_QUARANTINED_SIZE);
+        assert!(quarantine.messages().is_empty());
+    }
+
+    #[test]
+    fn quarantined_requests() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let client_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            transport.pubwrite(br#"{"method":"get_time""#).unwrap();
+            transport
+        });
+
+        let quarantine = Arc::new(Quarantine::new(8));
+        let mut transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        transport.set_quarantine(Some(quarantine.clone()));
+        assert!(transport.read_request().is_err());
+        let _transport = client_thread.join().unwrap();
+
+        let messages = quarantine.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].peer, client_pubkey);
+        assert_eq!(messages[0].reason, DropReason::MalformedJson);
+        assert_eq!(messages[0].payload, r#"{"method":"get_time""#);
+    }
+}
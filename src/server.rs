@@ -0,0 +1,1327 @@
+//! Server utilities
+//!
+//! This module provides the skeleton of a Revault server: a [RequestHandler] trait to
+//! implement the logic of the server and a [serve] loop reading the requests from a
+//! connection, dispatching them to the handler and writing back the responses, as well
+//! as a [listen] loop serving each incoming connection on its own thread (or on a
+//! bounded pool of threads with [listen_bounded], or up to a maximum number of
+//! connections with [listen_limited]). Stale connections can be closed by a
+//! [Watchdog](watchdog::Watchdog) with [listen_watched]. The connections accepted on
+//! several addresses at once can be merged by a
+//! [MultiListener](endpoints::MultiListener).
+//! Cross-cutting concerns (logging, metrics, authorization, ..) can be implemented as
+//! a [Middleware] wrapped around the handler by a [Dispatcher], and requests sent again
+//! by a client can be handled only once by a [Dedup] handler. The requests handled can
+//! be recorded to an audit log (see [audit]).
+//!
+//! The submodules implement the message flow of each server of the Revault network
+//! on top of user-provided backends, an access control middleware, a rate limiting
+//! one, one refusing the expired requests, the scoring of the misbehaving peers and a
+//! gateway for the clients connecting over a WebSocket.
+
+use crate::{
+    error::Error,
+    events::DropReason,
+    instrument::{self, log_debug, log_error, log_warn},
+    message::{ErrorCode, RequestParams, ResponseError, ResponseResult},
+    metrics::Side,
+    noise::{PublicKey, SecretKey},
+    server::{
+        scoring::{PeerScores, Violation},
+        watchdog::{WatchGuard, Watchdog},
+    },
+    transport::{IncomingRequest, KKTransport},
+};
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    io,
+    net::{TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+pub mod access;
+pub mod audit;
+pub mod coordinator;
+#[cfg(feature = "revault_tx")]
+pub mod cosigner;
+pub mod endpoints;
+pub mod expiry;
+pub mod ratelimit;
+pub mod scoring;
+pub mod upload;
+pub mod watchdog;
+pub mod watchtower;
+pub mod websocket;
+
+/// The answer of a [RequestHandler] to a request: the result to respond with, or the
+/// error to tell the peer the request failed with.
+pub type Reply = Result<ResponseResult, ResponseError>;
+
+/// The logic of a server, handling the requests it receives.
+pub trait RequestHandler {
+    /// Handle a request sent by the peer with this static Noise public key. Returns
+    /// the result to respond with, or `None` to not respond.
+    fn handle(&self, peer: &PublicKey, params: RequestParams) -> Option<ResponseResult>;
+
+    /// Handle a request along with its id. A request sent again by a client (for
+    /// instance after a timeout) has the same id. By default the id is ignored, see
+    /// [Dedup] for a handler using it.
+    fn handle_request(
+        &self,
+        peer: &PublicKey,
+        id: u32,
+        params: RequestParams,
+    ) -> Option<ResponseResult> {
+        let _ = id;
+        self.handle(peer, params)
+    }
+
+    /// Handle a request along with its id, and answer it with a result or an error.
+    /// Returns `None` to not respond. By default the result of
+    /// [handle_request](Self::handle_request), handlers able to fail override it to tell
+    /// the peer why its request was not answered.
+    fn reply(&self, peer: &PublicKey, id: u32, params: RequestParams) -> Option<Reply> {
+        self.handle_request(peer, id, params).map(Ok)
+    }
+}
+
+impl<F> RequestHandler for F
+where
+    F: Fn(&PublicKey, RequestParams) -> Option<ResponseResult>,
+{
+    fn handle(&self, peer: &PublicKey, params: RequestParams) -> Option<ResponseResult> {
+        self(peer, params)
+    }
+}
+
+/// What to do with a request after a [Middleware::before] hook
+#[derive(Debug)]
+pub enum Flow {
+    /// Pass these (possibly modified) params to the next middleware, and eventually
+    /// to the handler
+    Continue(RequestParams),
+    /// Don't handle this request, and respond with this result (if any)
+    Stop(Option<ResponseResult>),
+    /// Don't handle this request, and respond with this error
+    Refuse(ResponseError),
+}
+
+/// A hook around the handling of requests.
+pub trait Middleware {
+    /// Called before the request is handled. By default, let it through untouched.
+    fn before(&self, _peer: &PublicKey, params: RequestParams) -> Flow {
+        Flow::Continue(params)
+    }
+
+    /// Called with the result of a request before it is sent back to the peer.
+    /// By default, leave it untouched.
+    fn after(&self, _peer: &PublicKey, result: Option<ResponseResult>) -> Option<ResponseResult> {
+        result
+    }
+}
+
+/// A [RequestHandler] wrapped in an ordered chain of [Middleware]s.
+///
+/// The `before` hooks are called in the order middlewares were added, and the `after`
+/// hooks in the reverse order. If a middleware stops a request, only the `after`
+/// hooks of the middlewares before it are called. The `after` hooks are not called
+/// for the requests answered with an error.
+pub struct Dispatcher<H> {
+    handler: H,
+    middlewares: Vec<Box<dyn Middleware + Send + Sync>>,
+}
+
+impl<H: RequestHandler> Dispatcher<H> {
+    /// A dispatcher to this handler with no middleware
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            middlewares: Vec::new(),
+        }
+    }
+
+    /// Add a middleware at the end of the chain
+    pub fn with<M: Middleware + Send + Sync + 'static>(mut self, middleware: M) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+}
+
+impl<H: RequestHandler> Dispatcher<H> {
+    // Run the middlewares around this call to the handler
+    fn dispatch<F>(&self, peer: &PublicKey, params: RequestParams, handle: F) -> Option<Reply>
+    where
+        F: FnOnce(RequestParams) -> Option<Reply>,
+    {
+        let mut flow = Flow::Continue(params);
+        // The number of middlewares that let the request through
+        let mut passed = 0;
+
+        for middleware in self.middlewares.iter() {
+            let params = match flow {
+                Flow::Continue(params) => params,
+                Flow::Stop(_) | Flow::Refuse(_) => break,
+            };
+            flow = middleware.before(peer, params);
+            if let Flow::Continue(_) = flow {
+                passed += 1;
+            }
+        }
+        let mut result = match flow {
+            Flow::Continue(params) => match handle(params) {
+                Some(Ok(result)) => Some(result),
+                Some(Err(error)) => return Some(Err(error)),
+                None => None,
+            },
+            Flow::Stop(result) => result,
+            Flow::Refuse(error) => return Some(Err(error)),
+        };
+
+        for middleware in self.middlewares[..passed].iter().rev() {
+            result = middleware.after(peer, result);
+        }
+
+        result.map(Ok)
+    }
+}
+
+impl<H: RequestHandler> RequestHandler for Dispatcher<H> {
+    fn handle(&self, peer: &PublicKey, params: RequestParams) -> Option<ResponseResult> {
+        self.dispatch(peer, params, |params| {
+            self.handler.handle(peer, params).map(Ok)
+        })
+        .and_then(Result::ok)
+    }
+
+    fn handle_request(
+        &self,
+        peer: &PublicKey,
+        id: u32,
+        params: RequestParams,
+    ) -> Option<ResponseResult> {
+        self.reply(peer, id, params).and_then(Result::ok)
+    }
+
+    fn reply(&self, peer: &PublicKey, id: u32, params: RequestParams) -> Option<Reply> {
+        self.dispatch(peer, params, |params| self.handler.reply(peer, id, params))
+    }
+}
+
+// A request, identified by its sender, its id and a digest of its parameters
+type RequestKey = (PublicKey, u32, u64);
+
+fn request_key(peer: &PublicKey, id: u32, params: &RequestParams) -> RequestKey {
+    let mut hasher = DefaultHasher::new();
+    params.hash(&mut hasher);
+    (*peer, id, hasher.finish())
+}
+
+#[derive(Debug, Default)]
+struct HandledRequests {
+    // The most recent requests and the result we responded with, from the oldest
+    results: VecDeque<(RequestKey, Option<Reply>)>,
+    // The requests being handled
+    in_flight: HashSet<RequestKey>,
+}
+
+// Marks a request as being handled until dropped, even if the handler panicked
+struct InFlight<'a> {
+    handled: &'a Mutex<HandledRequests>,
+    done: &'a Condvar,
+    key: RequestKey,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut handled = self.handled.lock().unwrap_or_else(|e| e.into_inner());
+        handled.in_flight.remove(&self.key);
+        self.done.notify_all();
+    }
+}
+
+/// A [RequestHandler] handling a request sent again by the same peer with the same id
+/// and parameters only once, responding to the duplicates with the result of the first
+/// one. This avoids storing or logging twice a request retried after an ambiguous
+/// failure (it was sent, but we sent no response or the client did not receive it).
+/// A duplicate received while the first one is being handled waits for its result,
+/// other requests are handled concurrently.
+///
+/// Only the `capacity` most recent requests are remembered.
+pub struct Dedup<H> {
+    handler: H,
+    capacity: usize,
+    handled: Mutex<HandledRequests>,
+    // Notified when a request is done being handled
+    done: Condvar,
+}
+
+impl<H: RequestHandler> Dedup<H> {
+    /// Deduplicate the last `capacity` requests to this handler
+    pub fn new(handler: H, capacity: usize) -> Self {
+        Self {
+            handler,
+            capacity,
+            handled: Mutex::new(HandledRequests::default()),
+            done: Condvar::new(),
+        }
+    }
+}
+
+impl<H: RequestHandler> RequestHandler for Dedup<H> {
+    fn handle(&self, peer: &PublicKey, params: RequestParams) -> Option<ResponseResult> {
+        self.handler.handle(peer, params)
+    }
+
+    fn handle_request(
+        &self,
+        peer: &PublicKey,
+        id: u32,
+        params: RequestParams,
+    ) -> Option<ResponseResult> {
+        self.reply(peer, id, params).and_then(Result::ok)
+    }
+
+    fn reply(&self, peer: &PublicKey, id: u32, params: RequestParams) -> Option<Reply> {
+        let key = request_key(peer, id, &params);
+        let mut handled = self.handled.lock().expect("Dedup lock poisoned");
+        loop {
+            if let Some((_, result)) = handled.results.iter().find(|(k, _)| k == &key) {
+                log_debug!(
+                    "Request '{}' was already handled, not handling it again",
+                    id
+                );
+                return result.clone();
+            }
+            if !handled.in_flight.contains(&key) {
+                break;
+            }
+            // A duplicate is being handled, wait for its result
+            handled = self.done.wait(handled).expect("Dedup lock poisoned");
+        }
+        handled.in_flight.insert(key);
+        drop(handled);
+        let _in_flight = InFlight {
+            handled: &self.handled,
+            done: &self.done,
+            key,
+        };
+
+        let result = self.handler.reply(peer, id, params);
+        if self.capacity > 0 {
+            let mut handled = self.handled.lock().expect("Dedup lock poisoned");
+            if handled.results.len() == self.capacity {
+                handled.results.pop_front();
+            }
+            handled.results.push_back((key, result.clone()));
+        }
+
+        result
+    }
+}
+
+/// Serve the requests of the peer on this connection with this handler, until the
+/// peer closes the connection. Invalid requests are logged and ignored; a Noise or
+/// transport error is returned.
+pub fn serve<H: RequestHandler + ?Sized>(
+    transport: &mut KKTransport,
+    handler: &H,
+) -> Result<(), Error> {
+    serve_inner(transport, handler, None, None)
+}
+
+/// Like [serve], but count the invalid requests of the peer as violations in these
+/// scores. The connection is closed once the peer is banned.
+pub fn serve_scored<H: RequestHandler + ?Sized>(
+    transport: &mut KKTransport,
+    handler: &H,
+    scores: &PeerScores,
+) -> Result<(), Error> {
+    serve_inner(transport, handler, Some(scores), None)
+}
+
+fn serve_inner<H: RequestHandler + ?Sized>(
+    transport: &mut KKTransport,
+    handler: &H,
+    scores: Option<&PeerScores>,
+    watch: Option<&WatchGuard>,
+) -> Result<(), Error> {
+    let peer = transport.remote_static();
+
+    let mut incoming = transport.incoming();
+    while let Some(req) = incoming.next() {
+        if let Some(watch) = watch {
+            watch.touch();
+        }
+        match req {
+            Ok(IncomingRequest { id, params }) => {
+                instrument::request(Side::Server, params.method(), id, &peer, || {
+                    match handler.reply(&peer, id, params) {
+                        Some(Ok(result)) => incoming.respond(id, result)?,
+                        Some(Err(error)) => incoming.respond_error(id, error)?,
+                        None => {}
+                    }
+                    Ok(())
+                })?;
+            }
+            Err(Error::Json(e)) => {
+                log_warn!(
+                    "Ignoring invalid request ({}): '{}'",
+                    DropReason::of_parse_error(e.inner()),
+                    e
+                );
+                if let Some(scores) = scores {
+                    if scores.record(&peer, Violation::MalformedMessage) {
+                        log_debug!("Closing the connection of a banned peer");
+                        return Ok(());
+                    }
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    log_debug!("Peer closed the connection");
+    Ok(())
+}
+
+/// How long a peer has to complete its handshake with a server, unless configured
+/// otherwise through [ConnectionLimits].
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accept the connections of these peers on this listener, and serve each of them
+/// on its own thread with this handler. The handshake is performed on this thread too,
+/// and must complete within [HANDSHAKE_TIMEOUT]. A failed handshake is logged and the
+/// connection dropped. Only returns on a failure to spawn a thread.
+pub fn listen<H: RequestHandler + Send + Sync + 'static>(
+    listener: &TcpListener,
+    my_noise_privkey: &SecretKey,
+    their_possible_pubkeys: &[PublicKey],
+    handler: Arc<H>,
+) -> Result<(), Error> {
+    listen_inner(
+        listener,
+        my_noise_privkey,
+        their_possible_pubkeys,
+        handler,
+        None,
+        None,
+    )
+}
+
+/// Like [listen], but refuse the handshakes of the peers banned in these scores and
+/// serve the connections with [serve_scored].
+pub fn listen_scored<H: RequestHandler + Send + Sync + 'static>(
+    listener: &TcpListener,
+    my_noise_privkey: &SecretKey,
+    their_possible_pubkeys: &[PublicKey],
+    handler: Arc<H>,
+    scores: Arc<PeerScores>,
+) -> Result<(), Error> {
+    listen_inner(
+        listener,
+        my_noise_privkey,
+        their_possible_pubkeys,
+        handler,
+        Some(scores),
+        None,
+    )
+}
+
+/// Like [listen], but watch the connections with this watchdog. The connections silent
+/// for longer than its threshold are closed when it reaps them, see
+/// [Watchdog::spawn](watchdog::Watchdog::spawn).
+pub fn listen_watched<H: RequestHandler + Send + Sync + 'static>(
+    listener: &TcpListener,
+    my_noise_privkey: &SecretKey,
+    their_possible_pubkeys: &[PublicKey],
+    handler: Arc<H>,
+    watchdog: Arc<Watchdog>,
+) -> Result<(), Error> {
+    listen_inner(
+        listener,
+        my_noise_privkey,
+        their_possible_pubkeys,
+        handler,
+        None,
+        Some(watchdog),
+    )
+}
+
+// The longest we wait for before accepting connections again after a failure
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+// Accept a connection, waiting for longer and longer after each failure. Running out of
+// file descriptors fails every accept until a connection is closed: we would otherwise
+// spin on them.
+pub(crate) fn accept_backing_off(listener: &TcpListener, kind: &str) -> TcpStream {
+    let mut backoff = Duration::from_millis(10);
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => return stream,
+            Err(e) => {
+                log_warn!("Error accepting {}: '{}'", kind, e);
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+            }
+        }
+    }
+}
+
+fn listen_inner<H: RequestHandler + Send + Sync + 'static>(
+    listener: &TcpListener,
+    my_noise_privkey: &SecretKey,
+    their_possible_pubkeys: &[PublicKey],
+    handler: Arc<H>,
+    scores: Option<Arc<PeerScores>>,
+    watchdog: Option<Arc<Watchdog>>,
+) -> Result<(), Error> {
+    loop {
+        let stream = accept_backing_off(listener, "connection");
+        // A banned peer is not among the keys we expect, its handshake fails
+        let allowed = match &scores {
+            Some(scores) => scores.allowed(their_possible_pubkeys),
+            None => their_possible_pubkeys.to_vec(),
+        };
+
+        let (handler, scores, watchdog) = (handler.clone(), scores.clone(), watchdog.clone());
+        let my_noise_privkey = my_noise_privkey.clone();
+        thread::Builder::new()
+            .name("revault_net connection".to_string())
+            .spawn(move || {
+                let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+                let res = KKTransport::accept_stream_before(
+                    stream,
+                    &my_noise_privkey,
+                    &allowed,
+                    Some(deadline),
+                );
+                let mut transport = match res {
+                    Ok(transport) => transport,
+                    Err(e) => {
+                        log_warn!("Error accepting connection: '{}'", e);
+                        return;
+                    }
+                };
+
+                let watch = match watchdog.as_ref().map(|watchdog| watchdog.watch(&transport)) {
+                    Some(Ok(watch)) => Some(watch),
+                    Some(Err(e)) => {
+                        log_warn!("Error watching connection: '{}'", e);
+                        return;
+                    }
+                    None => None,
+                };
+
+                let res = serve_inner(
+                    &mut transport,
+                    handler.as_ref(),
+                    scores.as_deref(),
+                    watch.as_ref(),
+                );
+                if let Err(e) = res {
+                    log_warn!("Error serving connection: '{}'", e);
+                }
+            })?;
+    }
+}
+
+/// What to do with a new connection when all the workers of a pool are busy and its
+/// queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wait for a slot in the queue before accepting more connections
+    Block,
+    /// Drop the connection
+    Drop,
+}
+
+/// The configuration of the pool of threads serving the connections, see [listen_bounded].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// The number of threads serving connections
+    pub workers: usize,
+    /// The number of connections waiting for a thread to be available
+    pub queue_len: usize,
+    /// What to do with a connection when the queue is full
+    pub overflow: Overflow,
+    /// How long a peer has to complete its handshake once a thread picked up its
+    /// connection
+    pub handshake_timeout: Duration,
+    /// How long a peer may stay without sending a request before its connection is
+    /// closed, for an idle peer not to hold a thread forever. `None` if not limited.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            queue_len: 16,
+            overflow: Overflow::Drop,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            idle_timeout: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// Like [listen], but serve the connections with a bounded pool of threads instead of
+/// a thread per connection. Connections accepted while all the threads are busy are
+/// queued, up to the configured length, and their handshake is performed by the thread
+/// serving them. A panic of the handler only drops the connection it was serving, the
+/// thread goes on serving the next ones. Only returns on a failure to spawn a thread, or
+/// if all of them stopped nonetheless.
+pub fn listen_bounded<H: RequestHandler + Send + Sync + 'static>(
+    listener: &TcpListener,
+    my_noise_privkey: &SecretKey,
+    their_possible_pubkeys: &[PublicKey],
+    handler: Arc<H>,
+    config: &PoolConfig,
+) -> Result<(), Error> {
+    let (sender, receiver) = mpsc::sync_channel::<TcpStream>(config.queue_len);
+    let receiver = Arc::new(Mutex::new(receiver));
+    let their_possible_pubkeys = Arc::new(their_possible_pubkeys.to_vec());
+
+    for i in 0..config.workers {
+        let (handler, receiver, pubkeys) = (
+            handler.clone(),
+            receiver.clone(),
+            their_possible_pubkeys.clone(),
+        );
+        let (my_noise_privkey, config) = (my_noise_privkey.clone(), config.clone());
+        thread::Builder::new()
+            .name(format!("revault_net worker {}", i))
+            .spawn(move || loop {
+                let stream = receiver.lock().expect("Pool lock poisoned").recv();
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    // The listener is gone
+                    Err(_) => return,
+                };
+                let deadline = Instant::now() + config.handshake_timeout;
+                // Don't lose the thread with the connection if the handler panics
+                let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let res = KKTransport::accept_stream_before(
+                        stream,
+                        &my_noise_privkey,
+                        &pubkeys,
+                        Some(deadline),
+                    );
+                    res.and_then(|mut transport| {
+                        transport.set_read_timeout(config.idle_timeout)?;
+                        serve(&mut transport, handler.as_ref())
+                    })
+                }));
+                match res {
+                    Ok(Ok(())) => {}
+                    Ok(Err(Error::Timeout(_))) => log_debug!("Closing idle connection"),
+                    Ok(Err(e)) => log_warn!("Error serving connection: '{}'", e),
+                    Err(_) => log_error!("Handler panicked, dropping the connection"),
+                }
+            })?;
+    }
+
+    loop {
+        let stream = accept_backing_off(listener, "connection");
+
+        let disconnected = match config.overflow {
+            Overflow::Block => sender.send(stream).is_err(),
+            Overflow::Drop => match sender.try_send(stream) {
+                Ok(()) => false,
+                Err(mpsc::TrySendError::Full(stream)) => {
+                    log_warn!(
+                        "Too many connections, dropping connection from '{:?}'",
+                        stream.peer_addr()
+                    );
+                    false
+                }
+                Err(mpsc::TrySendError::Disconnected(_)) => true,
+            },
+        };
+        if disconnected {
+            log_error!("All the threads of the pool stopped");
+            return Err(Error::Transport(io::Error::new(
+                io::ErrorKind::Other,
+                "All the threads of the pool stopped",
+            )));
+        }
+    }
+}
+
+/// The limits on the connections of a listener, see [listen_limited].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// The number of connections served at once
+    pub max_sessions: usize,
+    /// The number of handshakes in progress at once
+    pub max_handshakes: usize,
+    /// How long a peer has to complete its handshake
+    pub handshake_timeout: Duration,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_sessions: 64,
+            max_handshakes: 16,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+        }
+    }
+}
+
+// One of a limited number of slots, released when dropped
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn acquire(count: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                if n < max {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()
+            .map(|_| Self(count.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Respond to the first request of a peer we can't serve, for it to know why, if it
+// sends one before the timeout
+fn refuse_busy(transport: &mut KKTransport, timeout: Duration) -> Result<(), Error> {
+    transport.set_read_timeout(Some(timeout))?;
+    let id = match transport.incoming().next() {
+        Some(Ok(IncomingRequest { id, .. })) => id,
+        Some(Err(e)) => return Err(e),
+        None => return Ok(()),
+    };
+    transport.respond_error(
+        id,
+        ResponseError::new(ErrorCode::ServerBusy, "Too many connections"),
+    )
+}
+
+/// Like [listen], but serve at most the configured number of connections at once and
+/// perform at most the configured number of handshakes at once, each on its own
+/// thread. A connection accepted while the handshakes are at capacity is closed; a
+/// peer connecting while the sessions are at capacity has its first request responded
+/// to with a [ServerBusy](ErrorCode::ServerBusy) error before being disconnected, if it
+/// sends it within the handshake timeout. Only returns on a failure to spawn a thread.
+pub fn listen_limited<H: RequestHandler + Send + Sync + 'static>(
+    listener: &TcpListener,
+    my_noise_privkey: &SecretKey,
+    their_possible_pubkeys: &[PublicKey],
+    handler: Arc<H>,
+    limits: &ConnectionLimits,
+) -> Result<(), Error> {
+    let (sessions, handshakes) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let their_possible_pubkeys = Arc::new(their_possible_pubkeys.to_vec());
+
+    loop {
+        let stream = accept_backing_off(listener, "connection");
+        let handshake = match Slot::acquire(&handshakes, limits.max_handshakes) {
+            Some(slot) => slot,
+            None => {
+                log_warn!("Too many handshakes in progress, dropping connection");
+                continue;
+            }
+        };
+
+        let (handler, sessions, pubkeys) = (
+            handler.clone(),
+            sessions.clone(),
+            their_possible_pubkeys.clone(),
+        );
+        let (my_noise_privkey, limits) = (my_noise_privkey.clone(), limits.clone());
+        thread::Builder::new()
+            .name("revault_net connection".to_string())
+            .spawn(move || {
+                let deadline = Instant::now() + limits.handshake_timeout;
+                let res = KKTransport::accept_stream_before(
+                    stream,
+                    &my_noise_privkey,
+                    &pubkeys,
+                    Some(deadline),
+                );
+                // Waiting for the request of a peer we refuse is not part of the handshake
+                drop(handshake);
+                let mut transport = match res {
+                    Ok(transport) => transport,
+                    Err(e) => {
+                        log_warn!("Error accepting connection: '{}'", e);
+                        return;
+                    }
+                };
+
+                let res = match Slot::acquire(&sessions, limits.max_sessions) {
+                    Some(_session) => serve(&mut transport, handler.as_ref()),
+                    None => {
+                        log_warn!(
+                            "Too many connections, refusing connection from '{:?}'",
+                            transport.remote_static()
+                        );
+                        refuse_busy(&mut transport, limits.handshake_timeout)
+                    }
+                };
+                if let Err(e) = res {
+                    log_warn!("Error serving connection: '{}'", e);
+                }
+            })?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::local_endpoints;
+    use crate::message::{
+        coordinator::{GetSigs, Sigs},
+        Request, SigSet,
+    };
+
+    use crate::noise::gen_keypair;
+    use bitcoin::{hashes::Hash, Txid};
+    use std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    #[test]
+    fn serve_requests() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let get_sigs = || {
+            Request::from(GetSigs {
+                id: Txid::default(),
+                if_none_match: None,
+            })
+        };
+        let cli_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            for _ in 0..2 {
+                let resp: Sigs = transport.send_req(&get_sigs()).unwrap();
+                assert!(resp.signatures.is_empty());
+            }
+            // Garbage is ignored
+            transport.pubwrite(b"{}").unwrap();
+            let resp: Sigs = transport.send_req(&get_sigs()).unwrap();
+            assert!(resp.signatures.is_empty());
+        });
+
+        let mut transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        let handler = |peer: &PublicKey, params| {
+            assert_eq!(peer, &client_pubkey);
+            match params {
+                RequestParams::GetSigs(GetSigs { .. }) => Some(ResponseResult::Sigs(Sigs {
+                    signatures: SigSet::new(),
+                })),
+                _ => None,
+            }
+        };
+        serve(&mut transport, &handler).unwrap();
+
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn scored_listener() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        // A single malformed message gets the peer banned
+        let scores = Arc::new(PeerScores::new(scoring::ScoringConfig {
+            ban_threshold: Violation::MalformedMessage.points(),
+            ..scoring::ScoringConfig::default()
+        }));
+        let server_scores = scores.clone();
+        thread::spawn(move || {
+            let handler = |_: &PublicKey, _| {
+                Some(ResponseResult::Sigs(Sigs {
+                    signatures: SigSet::new(),
+                }))
+            };
+            listen_scored(
+                &listener,
+                &server_privkey,
+                &[client_pubkey],
+                Arc::new(handler),
+                server_scores,
+            )
+            .unwrap();
+        });
+
+        let get_sigs = Request::from(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        let mut transport = KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        let _: Sigs = transport.send_req(&get_sigs).unwrap();
+        transport.pubwrite(b"{}").unwrap();
+        // The connection is closed and new handshakes are refused
+        assert!(transport.send_req::<Sigs>(&get_sigs).is_err());
+        assert!(scores.is_banned(&client_pubkey));
+        assert!(KKTransport::connect(addr, &client_privkey, &server_pubkey)
+            .and_then(|mut transport| transport.send_req::<Sigs>(&get_sigs))
+            .is_err());
+
+        scores.unban(&client_pubkey);
+        let mut transport = KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        let _: Sigs = transport.send_req(&get_sigs).unwrap();
+    }
+
+    #[test]
+    fn dedup_requests() {
+        let (peer, _) = gen_keypair();
+        let (other_peer, _) = gen_keypair();
+        let calls = Arc::new(Mutex::new(0));
+
+        let handler_calls = calls.clone();
+        let handler = move |_: &PublicKey, _| {
+            *handler_calls.lock().unwrap() += 1;
+            Some(ResponseResult::Sigs(Sigs {
+                signatures: SigSet::new(),
+            }))
+        };
+        let dedup = Dedup::new(handler, 2);
+        let params = RequestParams::GetSigs(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+
+        assert!(dedup.handle_request(&peer, 1, params.clone()).is_some());
+        assert!(dedup.handle_request(&peer, 1, params.clone()).is_some());
+        assert_eq!(*calls.lock().unwrap(), 1);
+        // Same id, another peer
+        dedup.handle_request(&other_peer, 1, params.clone());
+        assert_eq!(*calls.lock().unwrap(), 2);
+        // Only the 2 most recent requests are remembered
+        dedup.handle_request(&peer, 2, params.clone());
+        dedup.handle_request(&peer, 1, params.clone());
+        assert_eq!(*calls.lock().unwrap(), 4);
+        // Without an id, requests are not deduplicated
+        dedup.handle(&peer, params);
+        assert_eq!(*calls.lock().unwrap(), 5);
+        // Nor another request with the same id
+        let other_params = RequestParams::GetSigs(GetSigs {
+            id: Txid::from_slice(&[1; 32]).unwrap(),
+            if_none_match: None,
+        });
+        dedup.handle_request(&peer, 1, other_params);
+        assert_eq!(*calls.lock().unwrap(), 6);
+    }
+
+    #[test]
+    fn dedup_concurrent_requests() {
+        let (peer, _) = gen_keypair();
+        let slow_params = RequestParams::GetSigs(GetSigs {
+            id: Txid::from_slice(&[1; 32]).unwrap(),
+            if_none_match: None,
+        });
+        let (started, wait_started) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+        let (started, wait_release) = (Mutex::new(started), Mutex::new(wait_release));
+        let calls = Arc::new(Mutex::new(0));
+
+        let (handler_calls, slow) = (calls.clone(), slow_params.clone());
+        let handler = move |_: &PublicKey, params| {
+            if params == slow {
+                *handler_calls.lock().unwrap() += 1;
+                started.lock().unwrap().send(()).unwrap();
+                wait_release.lock().unwrap().recv().unwrap();
+            }
+            Some(ResponseResult::Sigs(Sigs {
+                signatures: SigSet::new(),
+            }))
+        };
+        let dedup = Arc::new(Dedup::new(handler, 8));
+
+        let request = |params: RequestParams| {
+            let dedup = dedup.clone();
+            thread::spawn(move || dedup.handle_request(&peer, 1, params))
+        };
+        let first = request(slow_params.clone());
+        wait_started.recv().unwrap();
+        let duplicate = request(slow_params);
+
+        // Other requests are handled while the first one is
+        let params = RequestParams::GetSigs(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        assert!(dedup.handle_request(&peer, 2, params).is_some());
+
+        // The duplicate waits for its result
+        release.send(()).unwrap();
+        assert_eq!(first.join().unwrap(), duplicate.join().unwrap());
+        assert_eq!(*calls.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn bounded_pool() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        thread::spawn(move || {
+            let handler = |_: &PublicKey, _| {
+                Some(ResponseResult::Sigs(Sigs {
+                    signatures: SigSet::new(),
+                }))
+            };
+            let config = PoolConfig {
+                workers: 1,
+                queue_len: 0,
+                overflow: Overflow::Drop,
+                idle_timeout: Some(Duration::from_millis(500)),
+                ..PoolConfig::default()
+            };
+            listen_bounded(
+                &listener,
+                &server_privkey,
+                &[client_pubkey],
+                Arc::new(handler),
+                &config,
+            )
+            .unwrap();
+        });
+
+        let get_sigs = || {
+            Request::from(GetSigs {
+                id: Txid::default(),
+                if_none_match: None,
+            })
+        };
+        let connect = || KKTransport::connect(addr, &client_privkey, &server_pubkey);
+        // A connection is dropped until the worker is back to waiting for one
+        let served = || {
+            for _ in 0..100 {
+                if let Ok(mut transport) = connect() {
+                    if transport.send_req::<Sigs>(&get_sigs()).is_ok() {
+                        return transport;
+                    }
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            panic!("The worker never became available");
+        };
+
+        // The single worker serves the first connection, the second one is dropped
+        let first = served();
+        assert!(connect()
+            .and_then(|mut second| second.send_req::<Sigs>(&get_sigs()))
+            .is_err());
+
+        // Once the first connection is closed, the worker is available again
+        drop(first);
+        let mut third = served();
+
+        // An idle connection is closed, and frees the worker
+        thread::sleep(Duration::from_millis(700));
+        assert!(third.send_req::<Sigs>(&get_sigs()).is_err());
+        served();
+    }
+
+    // A panicking handler doesn't take the worker down with the connection
+    #[test]
+    fn bounded_pool_handler_panic() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        thread::spawn(move || {
+            let panicked = std::sync::atomic::AtomicBool::new(false);
+            let handler = move |_: &PublicKey, _| {
+                if !panicked.swap(true, Ordering::SeqCst) {
+                    panic!("Handler bug");
+                }
+                Some(ResponseResult::Sigs(Sigs {
+                    signatures: SigSet::new(),
+                }))
+            };
+            let config = PoolConfig {
+                workers: 1,
+                overflow: Overflow::Block,
+                ..PoolConfig::default()
+            };
+            listen_bounded(
+                &listener,
+                &server_privkey,
+                &[client_pubkey],
+                Arc::new(handler),
+                &config,
+            )
+            .unwrap();
+        });
+
+        let get_sigs = Request::from(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        let mut first = KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        assert!(first.send_req::<Sigs>(&get_sigs).is_err());
+        let mut second = KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        second.send_req::<Sigs>(&get_sigs).unwrap();
+    }
+
+    #[test]
+    fn limited_listener() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        thread::spawn(move || {
+            let handler = |_: &PublicKey, _| {
+                Some(ResponseResult::Sigs(Sigs {
+                    signatures: SigSet::new(),
+                }))
+            };
+            let limits = ConnectionLimits {
+                max_sessions: 1,
+                max_handshakes: 1,
+                handshake_timeout: Duration::from_secs(10),
+            };
+            listen_limited(
+                &listener,
+                &server_privkey,
+                &[client_pubkey],
+                Arc::new(handler),
+                &limits,
+            )
+            .unwrap();
+        });
+
+        let get_sigs = || {
+            Request::from(GetSigs {
+                id: Txid::default(),
+                if_none_match: None,
+            })
+        };
+        let connect = || KKTransport::connect(addr, &client_privkey, &server_pubkey);
+        // The slot of a closed connection is released once the server noticed it was
+        let served = || {
+            for _ in 0..100 {
+                if let Ok(mut transport) = connect() {
+                    if transport.send_req::<Sigs>(&get_sigs()).is_ok() {
+                        return transport;
+                    }
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            panic!("The slot was never released");
+        };
+
+        // The second session is told the server is busy
+        let mut first = connect().unwrap();
+        let _: Sigs = first.send_req(&get_sigs()).unwrap();
+        let mut second = connect().unwrap();
+        match second.send_req::<Sigs>(&get_sigs()) {
+            Err(Error::Remote(e)) => assert_eq!(e.code, ErrorCode::ServerBusy),
+            res => panic!("Unexpected {:?}", res),
+        }
+        // A refused peer not sending its request doesn't hold the handshake slot, once
+        // its handshake is done
+        let _silent = connect().unwrap();
+        let mut third = (0..100)
+            .find_map(|_| {
+                thread::sleep(Duration::from_millis(20));
+                connect().ok()
+            })
+            .expect("The handshake slot was never released");
+        match third.send_req::<Sigs>(&get_sigs()) {
+            Err(Error::Remote(e)) => assert_eq!(e.code, ErrorCode::ServerBusy),
+            res => panic!("Unexpected {:?}", res),
+        }
+        let _: Sigs = first.send_req(&get_sigs()).unwrap();
+        drop(first);
+        drop(served());
+
+        // A pending handshake prevents others. The connections are accepted in order,
+        // the pending one is being handshaken when the next one is accepted.
+        let pending = std::net::TcpStream::connect(addr).unwrap();
+        assert!(connect()
+            .and_then(|mut transport| transport.send_req::<Sigs>(&get_sigs()))
+            .is_err());
+        drop(pending);
+        drop(served());
+    }
+
+    #[test]
+    fn silent_peers() {
+        use std::{
+            io::{self, Read, Write},
+            net::TcpStream,
+        };
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
+            (gen_keypair(), gen_keypair());
+        let handler = |_: &PublicKey, _| {
+            Some(ResponseResult::Sigs(Sigs {
+                signatures: SigSet::new(),
+            }))
+        };
+        let (listener, limited_listener) = (
+            TcpListener::bind("127.0.0.1:0").unwrap(),
+            TcpListener::bind("127.0.0.1:0").unwrap(),
+        );
+        let (addr, limited_addr) = (
+            listener.local_addr().unwrap(),
+            limited_listener.local_addr().unwrap(),
+        );
+        let privkey = server_privkey.clone();
+        thread::spawn(move || {
+            listen(&listener, &privkey, &[client_pubkey], Arc::new(handler)).unwrap();
+        });
+        thread::spawn(move || {
+            let limits = ConnectionLimits {
+                handshake_timeout: Duration::from_millis(200),
+                ..ConnectionLimits::default()
+            };
+            listen_limited(
+                &limited_listener,
+                &server_privkey,
+                &[client_pubkey],
+                Arc::new(handler),
+                &limits,
+            )
+            .unwrap();
+        });
+
+        // A peer not sending its handshake doesn't prevent the others from connecting
+        let _silent = TcpStream::connect(addr).unwrap();
+        let get_sigs = Request::from(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        let _: Sigs = KKTransport::connect(addr, &client_privkey, &server_pubkey)
+            .unwrap()
+            .send_req(&get_sigs)
+            .unwrap();
+
+        // A peer sending its handshake too slowly is disconnected once it timed out
+        let mut slow = TcpStream::connect(limited_addr).unwrap();
+        slow.set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        let start = Instant::now();
+        loop {
+            assert!(start.elapsed() < Duration::from_secs(2), "Not disconnected");
+            if slow.write_all(&[0]).is_err() {
+                break;
+            }
+            match slow.read(&mut [0; 1]) {
+                Ok(0) => break,
+                Err(e)
+                    if e.kind() != io::ErrorKind::WouldBlock
+                        && e.kind() != io::ErrorKind::TimedOut =>
+                {
+                    break
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Records the order in which the hooks are called
+    struct Recorder(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl Middleware for Recorder {
+        fn before(&self, _: &PublicKey, params: RequestParams) -> Flow {
+            self.1.lock().unwrap().push(format!("before {}", self.0));
+            Flow::Continue(params)
+        }
+
+        fn after(&self, _: &PublicKey, result: Option<ResponseResult>) -> Option<ResponseResult> {
+            self.1.lock().unwrap().push(format!("after {}", self.0));
+            result
+        }
+    }
+
+    // Only lets a given peer through
+    struct Auth(PublicKey);
+
+    impl Middleware for Auth {
+        fn before(&self, peer: &PublicKey, params: RequestParams) -> Flow {
+            if peer == &self.0 {
+                Flow::Continue(params)
+            } else {
+                Flow::Stop(None)
+            }
+        }
+    }
+
+    // Rewrites the txid of get_sigs requests
+    struct Rewrite;
+
+    impl Middleware for Rewrite {
+        fn before(&self, _: &PublicKey, params: RequestParams) -> Flow {
+            match params {
+                RequestParams::GetSigs(_) => Flow::Continue(RequestParams::GetSigs(GetSigs {
+                    id: Txid::from_slice(&[1; 32]).unwrap(),
+                    if_none_match: None,
+                })),
+                p => Flow::Continue(p),
+            }
+        }
+    }
+
+    #[test]
+    fn middlewares() {
+        let (peer, _) = gen_keypair();
+        let (other_peer, _) = gen_keypair();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let handler_log = log.clone();
+        let handler = move |_: &PublicKey, params| {
+            handler_log.lock().unwrap().push("handler".to_string());
+            assert_eq!(
+                params,
+                RequestParams::GetSigs(GetSigs {
+                    id: Txid::from_slice(&[1; 32]).unwrap(),
+                    if_none_match: None
+                })
+            );
+            Some(ResponseResult::Sigs(Sigs {
+                signatures: SigSet::new(),
+            }))
+        };
+        let dispatcher = Dispatcher::new(handler)
+            .with(Recorder("first", log.clone()))
+            .with(Auth(peer))
+            .with(Rewrite)
+            .with(Recorder("second", log.clone()));
+
+        let params = RequestParams::GetSigs(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        assert!(dispatcher.handle(&peer, params.clone()).is_some());
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "before first",
+                "before second",
+                "handler",
+                "after second",
+                "after first"
+            ]
+        );
+
+        log.lock().unwrap().clear();
+        assert!(dispatcher.handle(&other_peer, params).is_none());
+        assert_eq!(*log.lock().unwrap(), vec!["before first", "after first"]);
+    }
+}
@@ -0,0 +1,243 @@
+//! Access control
+//!
+//! A [PeerPolicy] maps the static Noise public keys of the peers to their [Role]s, and
+//! the roles to the methods they are allowed to call. Used as a [Middleware], it refuses
+//! the requests a peer is not allowed to make with an
+//! [AccessDenied](ErrorCode::AccessDenied) error.
+
+use crate::{
+    error::AccessError,
+    events::{self, ConnectionEvent, DropReason},
+    instrument::log_warn,
+    message::{method, ErrorCode, RequestParams, ResponseError},
+    noise::PublicKey,
+    quarantine::Quarantine,
+    server::{Flow, Middleware},
+};
+
+use std::{collections::HashMap, sync::Arc};
+
+pub use crate::message::Role;
+
+/// The roles of the peers and the methods each role is allowed to call. Peers without
+/// a role are not allowed to call any method.
+#[derive(Debug, Clone, Default)]
+pub struct PeerPolicy {
+    roles: HashMap<PublicKey, Vec<Role>>,
+    allowed: HashMap<Role, Vec<&'static str>>,
+    quarantine: Option<Arc<Quarantine>>,
+}
+
+impl PeerPolicy {
+    /// A policy allowing nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A policy with the permissions of the Revault protocol: only stakeholders share
+    /// signatures (and fetch the sealed ones, blobs and recovered ones), only managers set Spend
+    /// transactions and get them cosigned, and everyone may fetch them (and the time).
+    /// Other coordinators only replicate the stored entries, and watchtowers sync their
+    /// vaults with each other.
+    pub fn revault() -> Self {
+        let read = [
+            method::GET_SIGS,
+            method::GET_REVOCATION_SIGS,
+            method::HAS_SIGS,
+            method::GET_SPEND_TX,
+            method::GET_SPEND_TX_CHUNK,
+            method::SUBSCRIBE,
+            method::UNSUBSCRIBE,
+            method::GET_TIME,
+        ];
+        let manager = [
+            method::SET_SPEND_TX,
+            method::SET_SPEND_TX_BEGIN,
+            method::SET_SPEND_TX_CHUNK,
+            method::SET_SPEND_TX_COMMIT,
+            method::SIGN,
+            method::MUSIG_NONCE,
+            method::MUSIG_PARTIAL_SIG,
+            method::GET_MUSIG_SESSION,
+            method::GET_SPEND_POLICY,
+            method::APPROVE_SPEND,
+            method::ACK_KEY_ROTATION,
+            method::GET_PUBKEY,
+        ];
+
+        let policy = read.iter().fold(Self::new(), |policy, method| {
+            policy
+                .allow(Role::Stakeholder, method)
+                .allow(Role::Manager, method)
+                .allow(Role::Watchtower, method)
+        });
+        manager
+            .iter()
+            .fold(policy, |policy, method| policy.allow(Role::Manager, method))
+            .allow(Role::Stakeholder, method::SIG)
+            .allow(Role::Stakeholder, method::SEALED_SIG)
+            .allow(Role::Stakeholder, method::GET_SEALED_SIGS)
+            .allow(Role::Stakeholder, method::STORE_BLOB)
+            .allow(Role::Stakeholder, method::GET_BLOBS)
+            .allow(Role::Stakeholder, method::RECOVER_SIGS)
+            .allow(Role::Coordinator, method::REPLICATE)
+            .allow(Role::Coordinator, method::GET_ENTRIES)
+            .allow(Role::Watchtower, method::SYNC_VAULTS)
+    }
+
+    /// Give this role to the peer with this static Noise public key. A peer may have
+    /// multiple roles, for instance a stakeholder which is also a manager.
+    pub fn with_peer(mut self, noise_pubkey: PublicKey, role: Role) -> Self {
+        let roles = self.roles.entry(noise_pubkey).or_default();
+        if !roles.contains(&role) {
+            roles.push(role);
+        }
+        self
+    }
+
+    /// Allow the peers with this role to call this method
+    pub fn allow(mut self, role: Role, method: &'static str) -> Self {
+        let methods = self.allowed.entry(role).or_default();
+        if !methods.contains(&method) {
+            methods.push(method);
+        }
+        self
+    }
+
+    /// Keep a redacted copy of the requests rejected by the policy in this [Quarantine].
+    /// For debugging purposes.
+    pub fn with_quarantine(mut self, quarantine: Arc<Quarantine>) -> Self {
+        self.quarantine = Some(quarantine);
+        self
+    }
+
+    /// Get the roles of the peer with this static Noise public key
+    pub fn roles(&self, noise_pubkey: &PublicKey) -> &[Role] {
+        self.roles
+            .get(noise_pubkey)
+            .map(|roles| roles.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Check whether a peer is allowed to call this method
+    pub fn check(&self, noise_pubkey: &PublicKey, method: &'static str) -> Result<(), AccessError> {
+        let roles = self
+            .roles
+            .get(noise_pubkey)
+            .ok_or(AccessError::UnknownPeer(*noise_pubkey))?;
+
+        let allowed = roles.iter().any(|role| {
+            self.allowed
+                .get(role)
+                .map(|methods| methods.contains(&method))
+                .unwrap_or(false)
+        });
+        if !allowed {
+            return Err(AccessError::MethodNotAllowed {
+                method,
+                roles: roles.clone(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Middleware for PeerPolicy {
+    fn before(&self, peer: &PublicKey, params: RequestParams) -> Flow {
+        match self.check(peer, params.method()) {
+            Ok(()) => Flow::Continue(params),
+            Err(e) => {
+                log_warn!("Rejecting request ({}): '{}'", DropReason::Unauthorized, e);
+                events::notify(ConnectionEvent::MessageDropped {
+                    peer: *peer,
+                    reason: DropReason::Unauthorized,
+                    offset: None,
+                    context: params.method(),
+                });
+                if let Some(quarantine) = &self.quarantine {
+                    let msg = serde_json::json!({ "method": params.method(), "params": params });
+                    quarantine.record(*peer, DropReason::Unauthorized, msg.to_string().as_bytes());
+                }
+                Flow::Refuse(ResponseError::new(ErrorCode::AccessDenied, e.to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::{
+            coordinator::{GetSigs, SetSpendResult, Sigs},
+            ResponseResult, SigSet,
+        },
+        server::{Dispatcher, RequestHandler},
+    };
+
+    use crate::noise::gen_keypair;
+    use bitcoin::Txid;
+
+    #[test]
+    fn peer_policy() {
+        let (stakeholder, _) = gen_keypair();
+        let (manager, _) = gen_keypair();
+        let (stakeman, _) = gen_keypair();
+        let (stranger, _) = gen_keypair();
+        let (replica, _) = gen_keypair();
+        let policy = PeerPolicy::revault()
+            .with_peer(stakeholder, Role::Stakeholder)
+            .with_peer(manager, Role::Manager)
+            .with_peer(stakeman, Role::Stakeholder)
+            .with_peer(stakeman, Role::Manager)
+            .with_peer(replica, Role::Coordinator);
+        assert_eq!(policy.roles(&stakeman), &[Role::Stakeholder, Role::Manager]);
+        assert!(policy.roles(&stranger).is_empty());
+
+        policy.check(&stakeholder, method::SIG).unwrap();
+        policy.check(&stakeholder, method::GET_SPEND_TX).unwrap();
+        policy
+            .check(&stakeholder, method::GET_REVOCATION_SIGS)
+            .unwrap();
+        assert_eq!(
+            policy.check(&stakeholder, method::SET_SPEND_TX),
+            Err(AccessError::MethodNotAllowed {
+                method: method::SET_SPEND_TX,
+                roles: vec![Role::Stakeholder]
+            })
+        );
+        policy.check(&manager, method::SET_SPEND_TX).unwrap();
+        policy.check(&manager, method::SIG).unwrap_err();
+        policy.check(&stakeman, method::SIG).unwrap();
+        policy.check(&stakeman, method::SET_SPEND_TX).unwrap();
+        assert_eq!(
+            policy.check(&stranger, method::GET_SIGS),
+            Err(AccessError::UnknownPeer(stranger))
+        );
+        policy.check(&manager, "not_a_method").unwrap_err();
+        policy.check(&replica, method::REPLICATE).unwrap();
+        policy.check(&replica, method::GET_ENTRIES).unwrap();
+        policy.check(&replica, method::SIG).unwrap_err();
+        policy.check(&stakeholder, method::REPLICATE).unwrap_err();
+        policy.check(&stakeholder, method::SYNC_VAULTS).unwrap_err();
+
+        // As a middleware
+        let handler = |_: &PublicKey, params| match params {
+            RequestParams::GetSigs(_) => Some(ResponseResult::Sigs(Sigs {
+                signatures: SigSet::new(),
+            })),
+            _ => Some(ResponseResult::SetSpend(SetSpendResult { ack: true })),
+        };
+        let dispatcher = Dispatcher::new(handler).with(policy);
+        let params = RequestParams::GetSigs(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        assert!(dispatcher.handle(&stakeholder, params.clone()).is_some());
+        assert!(dispatcher.handle(&stranger, params.clone()).is_none());
+        // The peer is told why
+        let error = dispatcher.reply(&stranger, 1, params).unwrap().unwrap_err();
+        assert_eq!(error.code, ErrorCode::AccessDenied);
+    }
+}
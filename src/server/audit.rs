@@ -0,0 +1,375 @@
+//! Audit log
+//!
+//! An [AuditLog] records every request handled by a server, along with its response,
+//! to an append-only file so that an operator can prove after the fact what was
+//! stored and when. A handler is audited by wrapping it into an [Audited] one.
+//!
+//! Each request is written on its own line: the time it was handled (in milliseconds
+//! since the UNIX epoch), the static Noise public key of the peer, the method, and
+//! the SHA256 of the JSON encoding of the params and of the result or error responded
+//! (`-` if it wasn't responded to):
+//! ```text
+//! 1620000000000 <peer> get_sigs <params hash> <result hash>
+//! ```
+//! If the log is chained, each line ends with the SHA256 of the previous line's chain
+//! hash and of the line itself (the first one chaining from 32 zero bytes). A line
+//! can't be changed, removed or inserted without breaking the chain, see
+//! [verify_chain].
+
+use crate::{
+    instrument::log_warn,
+    message::{RequestParams, ResponseResult},
+    noise::PublicKey,
+    server::{Reply, RequestHandler},
+};
+
+use bitcoin::hashes::{hex::ToHex, sha256, Hash, HashEngine};
+use serde::Serialize;
+use std::{
+    cmp, fmt,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+struct Writer {
+    writer: Box<dyn Write + Send>,
+    // The chain hash of the last line
+    last: sha256::Hash,
+}
+
+/// Where to record the handled requests
+pub struct AuditLog {
+    writer: Mutex<Writer>,
+    chained: bool,
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AuditLog")
+            .field("chained", &self.chained)
+            .finish()
+    }
+}
+
+// The SHA256 of the JSON encoding of this value
+fn json_hash<T: Serialize>(value: &T) -> sha256::Hash {
+    let json = serde_json::to_vec(value).expect("Messages always serialize");
+    sha256::Hash::hash(&json)
+}
+
+// The chain hash of a line given the one of the previous line
+fn chain_hash(prev: &sha256::Hash, line: &str) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&prev[..]);
+    engine.input(line.as_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+impl AuditLog {
+    /// Record the requests to this writer, not chaining them.
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(Writer {
+                writer: Box::new(writer),
+                last: sha256::Hash::default(),
+            }),
+            chained: false,
+        }
+    }
+
+    /// Record the requests to this file, appending to it if it already exists. If
+    /// `chained`, the chain continues from its last line: an error of kind
+    /// [io::ErrorKind::InvalidData] is returned if this line doesn't end with a chain
+    /// hash, rather than starting a new chain amid the file.
+    pub fn to_file<P: AsRef<Path>>(path: P, chained: bool) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let last = if chained {
+            match last_line(&mut file)? {
+                Some(line) => line
+                    .rsplit(' ')
+                    .next()
+                    .and_then(|hash| hash.parse().ok())
+                    .filter(|_| line.split(' ').count() == 6)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "The last line of the audit log is not chained",
+                        )
+                    })?,
+                None => sha256::Hash::default(),
+            }
+        } else {
+            sha256::Hash::default()
+        };
+        let log = Self::new(file).chained(chained);
+        log.writer.lock().expect("Audit log lock poisoned").last = last;
+        Ok(log)
+    }
+
+    /// Chain each line to the previous one. To continue the chain of an existing
+    /// file, see [AuditLog::to_file].
+    pub fn chained(mut self, chained: bool) -> Self {
+        self.chained = chained;
+        self
+    }
+
+    /// Record a request of this peer with these params, hashed, responded to with this
+    /// result or error.
+    pub fn record(
+        &self,
+        peer: &PublicKey,
+        method: &str,
+        params_hash: sha256::Hash,
+        reply: Option<&Reply>,
+    ) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let result_hash = match reply {
+            Some(Ok(result)) => json_hash(result).to_hex(),
+            Some(Err(error)) => json_hash(error).to_hex(),
+            None => "-".to_string(),
+        };
+        let mut line = format!(
+            "{} {} {} {} {}",
+            timestamp,
+            peer.0.to_hex(),
+            method,
+            params_hash,
+            result_hash
+        );
+
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if self.chained {
+            let hash = chain_hash(&writer.last, &line);
+            line = format!("{} {}", line, hash);
+            writer.last = hash;
+        }
+        if let Err(e) = writeln!(writer.writer, "{}", line) {
+            log_warn!("Error writing to the audit log: '{}'", e);
+        }
+    }
+}
+
+// The last line of this file, reading only as much of its end as needed
+fn last_line(file: &mut File) -> io::Result<Option<String>> {
+    let mut start = file.seek(SeekFrom::End(0))?;
+    let mut tail = Vec::new();
+
+    loop {
+        let chunk_size = cmp::min(start, 512);
+        start -= chunk_size;
+        let mut chunk = vec![0; chunk_size as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+
+        let content = tail.strip_suffix(b"\n").unwrap_or(&tail);
+        if let Some(newline) = content.iter().rposition(|b| *b == b'\n') {
+            return Ok(Some(
+                String::from_utf8_lossy(&content[newline + 1..]).into_owned(),
+            ));
+        }
+        if start == 0 {
+            return Ok(
+                Some(String::from_utf8_lossy(content).into_owned()).filter(|line| !line.is_empty())
+            );
+        }
+    }
+}
+
+/// Check the chain of the lines of a chained audit log. Returns the number of lines
+/// checked, or an error of kind [io::ErrorKind::InvalidData] at the first line
+/// breaking the chain.
+pub fn verify_chain<R: BufRead>(reader: R) -> io::Result<usize> {
+    let mut last = sha256::Hash::default();
+    let mut count = 0;
+
+    for line in reader.lines() {
+        let line = line?;
+        count += 1;
+        let broken = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Audit log chain broken at line {}", count),
+            )
+        };
+        let (entry, hash) = line.rsplit_once(' ').ok_or_else(broken)?;
+        let hash: sha256::Hash = hash.parse().map_err(|_| broken())?;
+        if hash != chain_hash(&last, entry) {
+            return Err(broken());
+        }
+        last = hash;
+    }
+
+    Ok(count)
+}
+
+/// A [RequestHandler] recording the requests it handles, and their result, to an
+/// [AuditLog].
+#[derive(Debug)]
+pub struct Audited<H> {
+    handler: H,
+    log: Arc<AuditLog>,
+}
+
+impl<H: RequestHandler> Audited<H> {
+    /// Record the requests handled by this handler to this log
+    pub fn new(handler: H, log: Arc<AuditLog>) -> Self {
+        Self { handler, log }
+    }
+
+    fn audit<F>(&self, peer: &PublicKey, params: RequestParams, handle: F) -> Option<Reply>
+    where
+        F: FnOnce(RequestParams) -> Option<Reply>,
+    {
+        let (method, params_hash) = (params.method(), json_hash(&params));
+        let result = handle(params);
+        self.log.record(peer, method, params_hash, result.as_ref());
+        result
+    }
+}
+
+impl<H: RequestHandler> RequestHandler for Audited<H> {
+    fn handle(&self, peer: &PublicKey, params: RequestParams) -> Option<ResponseResult> {
+        self.audit(peer, params, |params| {
+            self.handler.handle(peer, params).map(Ok)
+        })
+        .and_then(Result::ok)
+    }
+
+    fn handle_request(
+        &self,
+        peer: &PublicKey,
+        id: u32,
+        params: RequestParams,
+    ) -> Option<ResponseResult> {
+        self.reply(peer, id, params).and_then(Result::ok)
+    }
+
+    fn reply(&self, peer: &PublicKey, id: u32, params: RequestParams) -> Option<Reply> {
+        self.audit(peer, params, |params| self.handler.reply(peer, id, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::{
+            coordinator::{GetSigs, Sigs},
+            ErrorCode, ResponseError, SigSet,
+        },
+        noise::gen_keypair,
+    };
+
+    use bitcoin::Txid;
+    use std::fs;
+
+    #[test]
+    fn audit_log() {
+        let path = std::env::temp_dir().join(format!(
+            "revault_net_audit_{}_{}",
+            std::process::id(),
+            gen_keypair().0 .0.to_hex()
+        ));
+        let (peer, _) = gen_keypair();
+        let handler = |_: &PublicKey, params| match params {
+            RequestParams::GetSigs(_) => Some(ResponseResult::Sigs(Sigs {
+                signatures: SigSet::new(),
+            })),
+            _ => None,
+        };
+        let params = RequestParams::GetSigs(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+
+        let log = Arc::new(AuditLog::to_file(&path, true).unwrap());
+        let audited = Audited::new(handler, log);
+        assert!(audited.handle(&peer, params.clone()).is_some());
+        assert!(audited.handle_request(&peer, 1, params.clone()).is_some());
+        drop(audited);
+        // The chain continues across restarts
+        let log = Arc::new(AuditLog::to_file(&path, true).unwrap());
+        Audited::new(handler, log).handle(&peer, params.clone());
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let fields: Vec<&str> = lines[0].split(' ').collect();
+        assert_eq!(fields.len(), 6);
+        fields[0].parse::<u128>().unwrap();
+        assert_eq!(fields[1], peer.0.to_hex());
+        assert_eq!(fields[2], "get_sigs");
+        assert_eq!(fields[3], json_hash(&params).to_hex());
+        assert_eq!(
+            fields[4],
+            json_hash(&ResponseResult::Sigs(Sigs {
+                signatures: SigSet::new()
+            }))
+            .to_hex()
+        );
+        assert_eq!(verify_chain(content.as_bytes()).unwrap(), 3);
+
+        // Tampering with a line breaks the chain
+        let tampered = content.replacen("get_sigs", "sig", 1);
+        let err = verify_chain(tampered.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let removed: String = lines[1..].iter().map(|l| format!("{}\n", l)).collect();
+        assert!(verify_chain(removed.as_bytes()).is_err());
+
+        // A chain is not continued from a line which isn't chained
+        let log = AuditLog::to_file(&path, false).unwrap();
+        log.record(&peer, "get_sigs", json_hash(&params), None);
+        drop(log);
+        let err = AuditLog::to_file(&path, true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+
+        // Not chained, and not responded to
+        #[derive(Clone, Default)]
+        struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+        impl Write for SharedBuf {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let buf = SharedBuf::default();
+        let audited = Audited::new(
+            |_: &PublicKey, _| None,
+            Arc::new(AuditLog::new(buf.clone())),
+        );
+        assert!(audited.handle(&peer, params.clone()).is_none());
+        let content = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let fields: Vec<&str> = content.trim_end().split(' ').collect();
+        assert_eq!(fields.len(), 5);
+        assert_eq!(fields[4], "-");
+
+        // Errors are recorded as well
+        let buf = SharedBuf::default();
+        let log = AuditLog::new(buf.clone());
+        let error = ResponseError::new(ErrorCode::InternalError, "Storage error");
+        log.record(
+            &peer,
+            "get_sigs",
+            json_hash(&params),
+            Some(&Err(error.clone())),
+        );
+        let content = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let fields: Vec<&str> = content.trim_end().split(' ').collect();
+        assert_eq!(fields[4], json_hash(&error).to_hex());
+    }
+}
@@ -0,0 +1,850 @@
+//! Coordinator server
+//!
+//! The message flow of the coordinator, storing the signatures and the Spend
+//! transactions shared by the participants and serving them back. The actual storage
+//! is provided by the user through the [Storage] trait. Spend transactions too large
+//! to be sent at once are reassembled from their chunks (see [upload](super::upload)),
+//! and can be served in chunks too. It tells its time to let the participants measure
+//! the skew of their clocks. It tells how many signatures it has for a transaction,
+//! for the participants to poll it cheaply. It relays the signatures sealed to each
+//! stakeholder (see [envelope](crate::envelope)) without being able to read them.
+
+use crate::{
+    clock::ClockPolicy,
+    instrument::{log_debug, log_error, log_warn},
+    message::{
+        coordinator::{
+            GetRevocationSigs, GetSealedSigs, GetSigs, GetSpendTx, NotModified, RecipientKey,
+            RevocationSigs, SealedBlob, SealedSig, SealedSigs, ServerTime, SetSpendResult,
+            SetSpendTx, SetSpendTxCommit, Sig, SigResult, Sigs, SigsCount, SpendTx, SpendTxChunk,
+        },
+        ErrorCode, Expiring, RequestParams, ResponseError, ResponseResult, SigSet,
+    },
+    noise::PublicKey,
+    server::{upload::Uploads, Reply, RequestHandler},
+};
+
+use bitcoin::{
+    secp256k1::{key::PublicKey as SecpPublicKey, Signature},
+    OutPoint, Transaction, Txid,
+};
+
+use std::{
+    collections::BTreeMap,
+    convert::{Infallible, TryInto},
+    fmt,
+    sync::Mutex,
+};
+
+/// What storing a signature or a Spend transaction did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stored {
+    /// It was stored
+    New,
+    /// The exact same one was already stored
+    Known,
+    /// A different one was already stored, and was kept
+    Conflict,
+}
+
+/// The storage backend of a coordinator.
+///
+/// Checking what is already stored and storing must be a single atomic operation, for
+/// two concurrent requests storing different data to not both be acknowledged.
+pub trait Storage {
+    /// The error returned by the storage backend
+    type Error: fmt::Display;
+
+    /// Store the signature of a participant for this transaction, unless one is already
+    /// stored for this public key: there is at most one signature per public key for a
+    /// given transaction.
+    fn store_sig(
+        &self,
+        txid: Txid,
+        pubkey: SecpPublicKey,
+        signature: Signature,
+    ) -> Result<Stored, Self::Error>;
+
+    /// Get all the signatures stored for this transaction
+    fn get_sigs(&self, txid: &Txid) -> Result<SigSet, Self::Error>;
+
+    /// The number of signatures stored for this transaction. Backends able to count
+    /// them without loading them should override it.
+    fn count_sigs(&self, txid: &Txid) -> Result<usize, Self::Error> {
+        self.get_sigs(txid).map(|sigs| sigs.len())
+    }
+
+    /// Store the Spend transaction for these vaults, unless a different one is stored
+    /// for any of them (in which case it is not stored for any).
+    fn store_spend_tx(
+        &self,
+        deposit_outpoints: &[OutPoint],
+        transaction: Transaction,
+    ) -> Result<Stored, Self::Error>;
+
+    /// Get the Spend transaction stored for this vault, if any
+    fn get_spend_tx(&self, deposit_outpoint: &OutPoint)
+        -> Result<Option<Transaction>, Self::Error>;
+
+    /// Store the blobs of this signature sealed to each recipient, along the ones
+    /// already stored for this transaction and recipient. A blob identical to a stored
+    /// one is not stored again, and there is no conflicting blob: the coordinator can't
+    /// tell what they contain.
+    fn store_sealed_sig(&self, sealed_sig: SealedSig) -> Result<Stored, Self::Error>;
+
+    /// Get the blobs stored for this transaction sealed to this recipient
+    fn get_sealed_sigs(
+        &self,
+        txid: &Txid,
+        recipient: &RecipientKey,
+    ) -> Result<Vec<SealedBlob>, Self::Error>;
+}
+
+/// A [Storage] backend keeping everything in memory, mostly useful for testing.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    sigs: Mutex<BTreeMap<Txid, SigSet>>,
+    spend_txs: Mutex<BTreeMap<OutPoint, Transaction>>,
+    sealed_sigs: Mutex<BTreeMap<(Txid, RecipientKey), Vec<SealedBlob>>>,
+}
+
+impl MemoryStorage {
+    /// An empty storage
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    type Error = Infallible;
+
+    fn store_sig(
+        &self,
+        txid: Txid,
+        pubkey: SecpPublicKey,
+        signature: Signature,
+    ) -> Result<Stored, Self::Error> {
+        let mut sigs = self.sigs.lock().expect("Poisoned lock");
+        Ok(
+            match sigs.entry(txid).or_default().insert(pubkey, signature) {
+                Ok(true) => Stored::New,
+                Ok(false) => Stored::Known,
+                Err(_) => Stored::Conflict,
+            },
+        )
+    }
+
+    fn get_sigs(&self, txid: &Txid) -> Result<SigSet, Self::Error> {
+        let sigs = self.sigs.lock().expect("Poisoned lock");
+        Ok(sigs.get(txid).cloned().unwrap_or_default())
+    }
+
+    fn count_sigs(&self, txid: &Txid) -> Result<usize, Self::Error> {
+        let sigs = self.sigs.lock().expect("Poisoned lock");
+        Ok(sigs.get(txid).map(|sigs| sigs.len()).unwrap_or(0))
+    }
+
+    fn store_spend_tx(
+        &self,
+        deposit_outpoints: &[OutPoint],
+        transaction: Transaction,
+    ) -> Result<Stored, Self::Error> {
+        let mut spend_txs = self.spend_txs.lock().expect("Poisoned lock");
+        let stored: Vec<_> = deposit_outpoints
+            .iter()
+            .map(|outpoint| spend_txs.get(outpoint))
+            .collect();
+        if stored.iter().flatten().any(|tx| *tx != &transaction) {
+            return Ok(Stored::Conflict);
+        }
+        if stored.iter().all(Option::is_some) {
+            return Ok(Stored::Known);
+        }
+        for outpoint in deposit_outpoints {
+            spend_txs.insert(*outpoint, transaction.clone());
+        }
+        Ok(Stored::New)
+    }
+
+    fn get_spend_tx(
+        &self,
+        deposit_outpoint: &OutPoint,
+    ) -> Result<Option<Transaction>, Self::Error> {
+        let spend_txs = self.spend_txs.lock().expect("Poisoned lock");
+        Ok(spend_txs.get(deposit_outpoint).cloned())
+    }
+
+    fn store_sealed_sig(&self, sealed_sig: SealedSig) -> Result<Stored, Self::Error> {
+        let mut sealed_sigs = self.sealed_sigs.lock().expect("Poisoned lock");
+        let mut stored = Stored::Known;
+        for (recipient, blob) in sealed_sig.sealed {
+            let blobs = sealed_sigs.entry((sealed_sig.txid, recipient)).or_default();
+            if !blobs.contains(&blob) {
+                blobs.push(blob);
+                stored = Stored::New;
+            }
+        }
+        Ok(stored)
+    }
+
+    fn get_sealed_sigs(
+        &self,
+        txid: &Txid,
+        recipient: &RecipientKey,
+    ) -> Result<Vec<SealedBlob>, Self::Error> {
+        let sealed_sigs = self.sealed_sigs.lock().expect("Poisoned lock");
+        Ok(sealed_sigs
+            .get(&(*txid, *recipient))
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// A coordinator, handling the requests of the participants using this storage.
+///
+/// A signature conflicting with an already stored one for the same public key is not
+/// acknowledged, neither is a Spend transaction conflicting with the one stored for
+/// one of its vaults, nor an invalid chunk or upload. Requests that can't be answered
+/// because of a storage error, or an upload that can't be started, are logged and
+/// answered with an error. So are the requests for the methods of other servers.
+#[derive(Debug)]
+pub struct Coordinator<S> {
+    storage: S,
+    uploads: Uploads,
+    clock: ClockPolicy,
+}
+
+impl<S: Storage> Coordinator<S> {
+    /// A coordinator using this storage backend
+    pub fn new(storage: S) -> Self {
+        Self {
+            storage,
+            uploads: Uploads::new(),
+            clock: ClockPolicy::new(),
+        }
+    }
+
+    /// Reassemble the chunked uploads with these limits
+    pub fn with_uploads(mut self, uploads: Uploads) -> Self {
+        self.uploads = uploads;
+        self
+    }
+
+    /// Tell the time of this clock policy to the participants, and refuse the expired
+    /// Spend transactions uploaded in chunks under it (see
+    /// [RejectExpired](crate::server::expiry::RejectExpired) for the others).
+    pub fn with_clock(mut self, clock: ClockPolicy) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Get the storage backend of this coordinator
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    fn handle_sig(&self, sig: Sig) -> Result<SigResult, S::Error> {
+        let Sig {
+            pubkey,
+            signature,
+            id,
+        } = sig;
+
+        Ok(match self.storage.store_sig(id, pubkey, signature)? {
+            Stored::New => SigResult::stored(),
+            Stored::Known => SigResult::known(),
+            Stored::Conflict => {
+                log_debug!(
+                    "Refusing conflicting signature for '{}' by '{}'",
+                    id,
+                    pubkey
+                );
+                SigResult::refused()
+            }
+        })
+    }
+
+    fn handle_get_sigs(&self, get_sigs: GetSigs) -> Result<ResponseResult, S::Error> {
+        let sigs = Sigs {
+            signatures: self.storage.get_sigs(&get_sigs.id)?,
+        };
+        match get_sigs.if_none_match {
+            Some(tag) if tag == sigs.tag() => Ok(ResponseResult::NotModified(NotModified {
+                not_modified: tag,
+            })),
+            _ => Ok(ResponseResult::Sigs(sigs)),
+        }
+    }
+
+    fn handle_get_revocation_sigs(
+        &self,
+        get_sigs: GetRevocationSigs,
+    ) -> Result<RevocationSigs, S::Error> {
+        let mut signatures = BTreeMap::new();
+        for (tx_type, txid) in get_sigs.txids {
+            let sigs = self.storage.get_sigs(&txid)?;
+            signatures.insert(tx_type, [(txid, sigs)].iter().cloned().collect());
+        }
+        Ok(RevocationSigs { signatures })
+    }
+
+    fn handle_set_spend_tx(&self, set_spend_tx: SetSpendTx) -> Result<SetSpendResult, S::Error> {
+        let deposit_outpoints = set_spend_tx.deposit_outpoints.clone();
+        let stored = self
+            .storage
+            .store_spend_tx(&deposit_outpoints, set_spend_tx.spend_tx())?;
+        if stored == Stored::Conflict {
+            log_debug!("Refusing Spend conflicting with a stored one");
+        }
+        Ok(SetSpendResult {
+            ack: stored != Stored::Conflict,
+        })
+    }
+
+    fn handle_set_spend_tx_commit(
+        &self,
+        peer: &PublicKey,
+        commit: SetSpendTxCommit,
+    ) -> Result<SetSpendResult, S::Error> {
+        match self.uploads.commit(peer, &commit) {
+            // The expiry of a chunked upload is only known once reassembled, after it
+            // went through the middlewares
+            Ok(set_spend_tx) if set_spend_tx.is_expired_under(&self.clock) => {
+                log_debug!("Refusing expired upload '{}'", commit.upload_id);
+                Ok(SetSpendResult { ack: false })
+            }
+            Ok(set_spend_tx) => self.handle_set_spend_tx(set_spend_tx),
+            Err(e) => {
+                log_debug!("Refusing upload '{}': '{}'", commit.upload_id, e);
+                Ok(SetSpendResult { ack: false })
+            }
+        }
+    }
+
+    fn handle_sealed_sig(&self, sealed_sig: SealedSig) -> Result<SigResult, S::Error> {
+        Ok(match self.storage.store_sealed_sig(sealed_sig)? {
+            Stored::New => SigResult::stored(),
+            Stored::Known => SigResult::known(),
+            Stored::Conflict => SigResult::refused(),
+        })
+    }
+
+    fn handle_get_spend_tx(&self, get_spend_tx: GetSpendTx) -> Result<SpendTx, S::Error> {
+        Ok(
+            match self.storage.get_spend_tx(&get_spend_tx.deposit_outpoint)? {
+                Some(tx) => SpendTx::found(tx),
+                None => SpendTx::not_found(),
+            },
+        )
+    }
+}
+
+impl<S: Storage> RequestHandler for Coordinator<S> {
+    fn handle(&self, peer: &PublicKey, params: RequestParams) -> Option<ResponseResult> {
+        self.reply(peer, 0, params).and_then(Result::ok)
+    }
+
+    fn reply(&self, peer: &PublicKey, _id: u32, params: RequestParams) -> Option<Reply> {
+        let result = match params {
+            RequestParams::CoordSig(sig) => self.handle_sig(sig).map(ResponseResult::Sig),
+            RequestParams::GetSigs(get_sigs) => self.handle_get_sigs(get_sigs),
+            RequestParams::GetRevocationSigs(get_sigs) => self
+                .handle_get_revocation_sigs(get_sigs)
+                .map(ResponseResult::RevocationSigs),
+            RequestParams::HasSigs(has_sigs) => {
+                self.storage.count_sigs(&has_sigs.id).map(|count| {
+                    ResponseResult::SigsCount(SigsCount {
+                        count: count.try_into().unwrap_or(u32::MAX),
+                    })
+                })
+            }
+            RequestParams::SetSpendTx(set_spend_tx) => self
+                .handle_set_spend_tx(set_spend_tx)
+                .map(ResponseResult::SetSpend),
+            RequestParams::GetSpendTx(get_spend_tx) => self
+                .handle_get_spend_tx(get_spend_tx)
+                .map(ResponseResult::SpendTx),
+            RequestParams::SetSpendTxBegin(begin) => match self.uploads.begin(peer, begin) {
+                Ok(started) => Ok(ResponseResult::UploadStarted(started)),
+                Err(e) => {
+                    log_warn!("Refusing to start upload: '{}'", e);
+                    return Some(Err(ResponseError::new(
+                        ErrorCode::InvalidParams,
+                        e.to_string(),
+                    )));
+                }
+            },
+            RequestParams::SetSpendTxChunk(chunk) => {
+                let upload_id = chunk.upload_id;
+                let ack = match self.uploads.chunk(peer, chunk) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        log_debug!("Refusing chunk of upload '{}': '{}'", upload_id, e);
+                        false
+                    }
+                };
+                Ok(ResponseResult::Sig(SigResult {
+                    ack,
+                    already_known: false,
+                }))
+            }
+            RequestParams::SetSpendTxCommit(commit) => self
+                .handle_set_spend_tx_commit(peer, commit)
+                .map(ResponseResult::SetSpend),
+            RequestParams::GetSpendTxChunk(get_chunk) => self
+                .storage
+                .get_spend_tx(&get_chunk.deposit_outpoint)
+                .map(|tx| {
+                    ResponseResult::SpendTxChunk(SpendTxChunk::of(
+                        tx.as_ref(),
+                        get_chunk.chunk_index,
+                    ))
+                }),
+            RequestParams::GetTime(_) => Ok(ResponseResult::ServerTime(ServerTime {
+                time: self.clock.now(),
+            })),
+            RequestParams::SealedSig(sealed_sig) => {
+                self.handle_sealed_sig(sealed_sig).map(ResponseResult::Sig)
+            }
+            RequestParams::GetSealedSigs(GetSealedSigs { txid, recipient }) => self
+                .storage
+                .get_sealed_sigs(&txid, &recipient)
+                .map(|sealed| ResponseResult::SealedSigs(SealedSigs { sealed })),
+            params => {
+                log_warn!(
+                    "Refusing request not handled by the coordinator: {:?}",
+                    params
+                );
+                return Some(Err(ResponseError::new(
+                    ErrorCode::MethodNotFound,
+                    format!("'{}' is not a coordinator method", params.method()),
+                )));
+            }
+        };
+
+        match result {
+            Ok(result) => Some(Ok(result)),
+            Err(e) => {
+                log_error!("Storage error: '{}'", e);
+                Some(Err(ResponseError::new(
+                    ErrorCode::InternalError,
+                    "Storage error",
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::CoordinatorClient,
+        envelope,
+        fixtures::{local_endpoints, secp_privkey, secp_sig},
+        message::coordinator::{RevocationTxType, SigsTag, MAX_CHUNK_SIZE},
+        server::serve,
+        transport::KKTransport,
+    };
+
+    use crate::noise::gen_keypair;
+    use bitcoin::{
+        hashes::Hash,
+        secp256k1::{Message, Secp256k1},
+    };
+    use std::thread;
+
+    #[test]
+    fn coordinator_flow() {
+        let secp = Secp256k1::new();
+        let (pubkey, signature) = secp_sig();
+        let other_signature = secp.sign(&Message::from_slice(&[2; 32]).unwrap(), &secp_privkey());
+        let txid = Txid::from_slice(&[3; 32]).unwrap();
+        let sig = Sig {
+            pubkey,
+            signature,
+            id: txid,
+        };
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_thread = thread::spawn(move || {
+            let mut client =
+                CoordinatorClient::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            assert!(client.get_sigs(txid).unwrap().is_empty());
+            assert!(!client.resend_sig(sig.clone()).unwrap());
+            // Sending it twice is fine, but not a different signature for the same key
+            assert!(client.resend_sig(sig.clone()).unwrap());
+            client
+                .send_sig(Sig {
+                    signature: other_signature,
+                    ..sig
+                })
+                .unwrap_err();
+            let sigs = client.get_sigs(txid).unwrap();
+            assert_eq!(sigs.len(), 1);
+            assert_eq!(sigs.get(&pubkey), Some(&signature));
+            // All the revocation transactions at once
+            let other_txid = Txid::from_slice(&[4; 32]).unwrap();
+            let txids = [
+                (RevocationTxType::Cancel, txid),
+                (RevocationTxType::Emergency, other_txid),
+            ]
+            .iter()
+            .cloned()
+            .collect();
+            let revocation_sigs = client.get_revocation_sigs(txids).unwrap();
+            assert_eq!(revocation_sigs.get(RevocationTxType::Cancel), Some(&sigs));
+            assert!(revocation_sigs
+                .get(RevocationTxType::Emergency)
+                .unwrap()
+                .is_empty());
+            assert_eq!(
+                revocation_sigs.get(RevocationTxType::UnvaultEmergency),
+                None
+            );
+
+            // Not sent again if they didn't change
+            let tag = SigsTag::of(&sigs);
+            assert_eq!(client.get_sigs_if_changed(txid, tag).unwrap(), None);
+            let other_tag = SigsTag::of(&SigSet::new());
+            assert_eq!(
+                client.get_sigs_if_changed(txid, other_tag).unwrap(),
+                Some(sigs)
+            );
+            // Or only counted
+            assert_eq!(client.count_sigs(txid).unwrap(), 1);
+            assert_eq!(client.count_sigs(other_txid).unwrap(), 0);
+
+            assert!(!client.get_spend_tx(OutPoint::default()).unwrap().is_found());
+        });
+
+        let mut transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        let coordinator = Coordinator::new(MemoryStorage::new());
+        serve(&mut transport, &coordinator).unwrap();
+        cli_thread.join().unwrap();
+
+        assert_eq!(coordinator.storage().get_sigs(&txid).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn coordinator_time() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        // The coordinator's clock is an hour ahead
+        let cli_thread = thread::spawn(move || {
+            let mut client =
+                CoordinatorClient::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            assert_eq!(client.clock_offset(), None);
+            let offset = client.sync_time().unwrap();
+            assert!((3_599..=3_601).contains(&offset), "{}", offset);
+            assert_eq!(client.clock_offset(), Some(offset));
+        });
+        let mut transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        let coordinator = Coordinator::new(MemoryStorage::new())
+            .with_clock(ClockPolicy::new().with_offset(3_600));
+        serve(&mut transport, &coordinator).unwrap();
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn coordinator_chunked_spend_tx() {
+        let deposit_outpoints: Vec<OutPoint> = (0..500)
+            .map(|vout| OutPoint {
+                vout,
+                ..OutPoint::default()
+            })
+            .collect();
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: deposit_outpoints
+                .iter()
+                .map(|outpoint| bitcoin::TxIn {
+                    previous_output: *outpoint,
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![Default::default()],
+        };
+        let set_spend_tx =
+            SetSpendTx::from_transaction(deposit_outpoints.clone(), transaction.clone()).unwrap();
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let coordinator = Coordinator::new(MemoryStorage::new());
+
+        // It's uploaded in chunks
+        let privkey = client_privkey.clone();
+        let cli_thread = thread::spawn(move || {
+            let mut client = CoordinatorClient::connect(addr, &privkey, &server_pubkey).unwrap();
+            client
+                .set_spend_tx_chunked(&set_spend_tx, MAX_CHUNK_SIZE)
+                .unwrap();
+        });
+        let mut transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        serve(&mut transport, &coordinator).unwrap();
+        cli_thread.join().unwrap();
+        for deposit_outpoint in &deposit_outpoints {
+            assert_eq!(
+                coordinator
+                    .storage()
+                    .get_spend_tx(deposit_outpoint)
+                    .unwrap(),
+                Some(transaction.clone())
+            );
+        }
+
+        // And downloaded in chunks
+        let (deposit_outpoint, tx) = (deposit_outpoints[0], transaction.clone());
+        let cli_thread = thread::spawn(move || {
+            let mut client =
+                CoordinatorClient::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            assert_eq!(
+                client.get_spend_tx_chunked(deposit_outpoint).unwrap(),
+                Some(tx)
+            );
+            let unknown_outpoint = OutPoint {
+                vout: 1_000,
+                ..OutPoint::default()
+            };
+            assert_eq!(client.get_spend_tx_chunked(unknown_outpoint).unwrap(), None);
+        });
+        let mut transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        serve(&mut transport, &coordinator).unwrap();
+        cli_thread.join().unwrap();
+
+        // A commit of an unknown upload is not acknowledged
+        let (peer, _) = gen_keypair();
+        assert_eq!(
+            coordinator.handle(
+                &peer,
+                RequestParams::SetSpendTxCommit(SetSpendTxCommit { upload_id: 0 })
+            ),
+            Some(ResponseResult::SetSpend(SetSpendResult { ack: false }))
+        );
+
+        // Nor is an expired one, once reassembled
+        let expired = SetSpendTx::from_transaction(deposit_outpoints, transaction)
+            .unwrap()
+            .with_expiry(1);
+        let (begin, chunks) = expired.to_chunks(MAX_CHUNK_SIZE);
+        let upload_id = match coordinator.handle(&peer, RequestParams::SetSpendTxBegin(begin)) {
+            Some(ResponseResult::UploadStarted(started)) => started.upload_id,
+            res => panic!("Unexpected {:?}", res),
+        };
+        for (index, data) in chunks.into_iter().enumerate() {
+            let chunk = crate::message::coordinator::SetSpendTxChunk {
+                upload_id,
+                index: index as u32,
+                data,
+            };
+            coordinator.handle(&peer, RequestParams::SetSpendTxChunk(chunk));
+        }
+        assert_eq!(
+            coordinator.handle(
+                &peer,
+                RequestParams::SetSpendTxCommit(SetSpendTxCommit { upload_id })
+            ),
+            Some(ResponseResult::SetSpend(SetSpendResult { ack: false }))
+        );
+    }
+
+    #[test]
+    fn coordinator_spend_txs() {
+        let coordinator = Coordinator::new(MemoryStorage::new());
+        let (peer, _) = gen_keypair();
+        let deposit_outpoints: Vec<OutPoint> = (0..2)
+            .map(|vout| OutPoint {
+                vout,
+                ..OutPoint::default()
+            })
+            .collect();
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: deposit_outpoints
+                .iter()
+                .map(|outpoint| bitcoin::TxIn {
+                    previous_output: *outpoint,
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![Default::default()],
+        };
+        let set_spend_tx: SetSpendTx = serde_json::from_value(serde_json::json!({
+            "deposit_outpoints": deposit_outpoints,
+            "transaction": bitcoin::consensus::encode::serialize_hex(&transaction),
+        }))
+        .unwrap();
+
+        assert_eq!(
+            coordinator.handle(&peer, RequestParams::SetSpendTx(set_spend_tx.clone())),
+            Some(ResponseResult::SetSpend(SetSpendResult { ack: true }))
+        );
+        // The same one can be sent again, but it is not overwritten by another version
+        assert_eq!(
+            coordinator.handle(&peer, RequestParams::SetSpendTx(set_spend_tx)),
+            Some(ResponseResult::SetSpend(SetSpendResult { ack: true }))
+        );
+        let other_transaction = Transaction {
+            lock_time: 1,
+            ..transaction.clone()
+        };
+        let other_set_spend_tx =
+            SetSpendTx::from_transaction(deposit_outpoints.clone(), other_transaction).unwrap();
+        assert_eq!(
+            coordinator.handle(&peer, RequestParams::SetSpendTx(other_set_spend_tx)),
+            Some(ResponseResult::SetSpend(SetSpendResult { ack: false }))
+        );
+        for deposit_outpoint in deposit_outpoints {
+            assert_eq!(
+                coordinator.handle(
+                    &peer,
+                    RequestParams::GetSpendTx(GetSpendTx { deposit_outpoint })
+                ),
+                Some(ResponseResult::SpendTx(SpendTx::found(transaction.clone())))
+            );
+        }
+
+        // Requests not for the coordinator are refused
+        let unsubscribe = RequestParams::Unsubscribe(crate::message::coordinator::Unsubscribe {
+            subscription_id: 0,
+        });
+        assert_eq!(coordinator.handle(&peer, unsubscribe.clone()), None);
+        assert_eq!(
+            coordinator
+                .reply(&peer, 1, unsubscribe)
+                .unwrap()
+                .unwrap_err()
+                .code,
+            ErrorCode::MethodNotFound
+        );
+    }
+
+    #[test]
+    fn coordinator_sealed_sigs() {
+        let (pubkey, signature) = secp_sig();
+        let txid = Txid::from_slice(&[3; 32]).unwrap();
+        let sig = Sig {
+            pubkey,
+            signature,
+            id: txid,
+        };
+        let (peer, _) = gen_keypair();
+        let (alice_pubkey, alice_privkey) = gen_keypair();
+        let (bob_pubkey, _) = gen_keypair();
+        let coordinator = Coordinator::new(MemoryStorage::new());
+
+        let sealed_sig = envelope::seal_sig(&sig, &[alice_pubkey, bob_pubkey]);
+        assert_eq!(
+            coordinator.handle(&peer, RequestParams::SealedSig(sealed_sig.clone())),
+            Some(ResponseResult::Sig(SigResult::stored()))
+        );
+        assert_eq!(
+            coordinator.handle(&peer, RequestParams::SealedSig(sealed_sig)),
+            Some(ResponseResult::Sig(SigResult::known()))
+        );
+
+        // Each recipient gets the blob sealed to it, none for another transaction
+        let get_sealed_sigs = |txid, pubkey| match coordinator.handle(
+            &peer,
+            RequestParams::GetSealedSigs(GetSealedSigs {
+                txid,
+                recipient: envelope::recipient_key(pubkey),
+            }),
+        ) {
+            Some(ResponseResult::SealedSigs(sealed_sigs)) => sealed_sigs,
+            res => panic!("Unexpected {:?}", res),
+        };
+        let sealed_sigs = get_sealed_sigs(txid, &alice_pubkey);
+        assert_eq!(sealed_sigs.sealed.len(), 1);
+        assert_eq!(
+            envelope::open_sigs(&txid, &sealed_sigs, &alice_pubkey, &alice_privkey).unwrap(),
+            vec![sig]
+        );
+        assert_eq!(get_sealed_sigs(txid, &bob_pubkey).sealed.len(), 1);
+        let other_txid = Txid::from_slice(&[4; 32]).unwrap();
+        assert!(get_sealed_sigs(other_txid, &alice_pubkey).sealed.is_empty());
+    }
+
+    #[test]
+    fn coordinator_storage() {
+        let secp = Secp256k1::new();
+        let (pubkey, privkey) = (secp_sig().0, secp_privkey());
+        let txid = Txid::from_slice(&[3; 32]).unwrap();
+
+        // Of concurrent conflicting signatures, a single one is stored
+        let storage = std::sync::Arc::new(MemoryStorage::new());
+        let threads: Vec<_> = (1..9u8)
+            .map(|i| {
+                let storage = storage.clone();
+                let signature = secp.sign(&Message::from_slice(&[i; 32]).unwrap(), &privkey);
+                thread::spawn(move || storage.store_sig(txid, pubkey, signature).unwrap())
+            })
+            .collect();
+        let stored: Vec<Stored> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert_eq!(stored.iter().filter(|s| **s == Stored::New).count(), 1);
+        assert_eq!(stored.iter().filter(|s| **s == Stored::Conflict).count(), 7);
+
+        // A storage failure is answered with an error
+        struct Failing;
+        impl Storage for Failing {
+            type Error = &'static str;
+
+            fn store_sig(
+                &self,
+                _: Txid,
+                _: SecpPublicKey,
+                _: Signature,
+            ) -> Result<Stored, Self::Error> {
+                Err("Disk full")
+            }
+
+            fn get_sigs(&self, _: &Txid) -> Result<SigSet, Self::Error> {
+                Err("Disk full")
+            }
+
+            fn store_spend_tx(
+                &self,
+                _: &[OutPoint],
+                _: Transaction,
+            ) -> Result<Stored, Self::Error> {
+                Err("Disk full")
+            }
+
+            fn get_spend_tx(&self, _: &OutPoint) -> Result<Option<Transaction>, Self::Error> {
+                Err("Disk full")
+            }
+
+            fn store_sealed_sig(&self, _: SealedSig) -> Result<Stored, Self::Error> {
+                Err("Disk full")
+            }
+
+            fn get_sealed_sigs(
+                &self,
+                _: &Txid,
+                _: &RecipientKey,
+            ) -> Result<Vec<SealedBlob>, Self::Error> {
+                Err("Disk full")
+            }
+        }
+        let (peer, _) = gen_keypair();
+        let coordinator = Coordinator::new(Failing);
+        let reply = coordinator.reply(
+            &peer,
+            1,
+            RequestParams::GetSigs(GetSigs {
+                id: txid,
+                if_none_match: None,
+            }),
+        );
+        assert_eq!(reply.unwrap().unwrap_err().code, ErrorCode::InternalError);
+    }
+}
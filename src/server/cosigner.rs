@@ -0,0 +1,455 @@
+//! Cosigning server
+//!
+//! The message flow of a cosigning server, signing the Spend transactions of the
+//! managers. A cosigning server must never sign two different Spend transactions
+//! spending the same vault, which is enforced here using a user-provided
+//! [OutpointStore]. The actual signing is performed by a [SigningBackend].
+
+use crate::{
+    instrument::{log_error, log_warn},
+    message::{cosigner, ErrorCode, RequestParams, ResponseError, ResponseResult},
+    noise::PublicKey,
+    server::{Reply, RequestHandler},
+};
+
+use revault_tx::{
+    bitcoin::{
+        secp256k1::{self, key::SecretKey},
+        OutPoint, PublicKey as BitcoinPubKey, SigHashType, Txid,
+    },
+    error::InputSatisfactionError,
+    transactions::{RevaultTransaction, SpendTransaction},
+};
+
+use std::{collections::BTreeMap, convert::Infallible, fmt, sync::Mutex};
+
+/// The signing backend of a cosigning server.
+pub trait SigningBackend {
+    /// The error returned by the backend
+    type Error: fmt::Display;
+
+    /// Add our signature to all the inputs of this Spend transaction
+    fn sign(&self, spend_tx: &mut SpendTransaction) -> Result<(), Self::Error>;
+
+    /// The public key we sign with, if it can be told to the managers. The
+    /// `get_pubkey` requests are refused otherwise.
+    fn pubkey(&self) -> Option<secp256k1::PublicKey> {
+        None
+    }
+
+    /// The signature hash types of our signatures
+    fn sighash_types(&self) -> Vec<SigHashType> {
+        vec![SigHashType::All]
+    }
+}
+
+/// A [SigningBackend] signing all the inputs of the Spend transactions with a single
+/// private key, with `SIGHASH_ALL`.
+#[derive(Debug)]
+pub struct PrivateKeySigner {
+    privkey: SecretKey,
+    pubkey: BitcoinPubKey,
+    secp: secp256k1::Secp256k1<secp256k1::All>,
+}
+
+impl PrivateKeySigner {
+    /// A signer using this private key
+    pub fn new(privkey: SecretKey) -> Self {
+        let secp = secp256k1::Secp256k1::new();
+        let pubkey = BitcoinPubKey {
+            compressed: true,
+            key: secp256k1::PublicKey::from_secret_key(&secp, &privkey),
+        };
+
+        Self {
+            privkey,
+            pubkey,
+            secp,
+        }
+    }
+}
+
+impl SigningBackend for PrivateKeySigner {
+    type Error = InputSatisfactionError;
+
+    fn sign(&self, spend_tx: &mut SpendTransaction) -> Result<(), Self::Error> {
+        for i in 0..spend_tx.inner_tx().inputs.len() {
+            let sighash = spend_tx.signature_hash_internal_input(i, SigHashType::All)?;
+            let msg = secp256k1::Message::from_slice(&sighash).expect("sighash is 32 bytes");
+            let sig = self.secp.sign(&msg, &self.privkey);
+            spend_tx.add_signature(i, self.pubkey, (sig, SigHashType::All))?;
+        }
+
+        Ok(())
+    }
+
+    fn pubkey(&self) -> Option<secp256k1::PublicKey> {
+        Some(self.pubkey.key)
+    }
+}
+
+/// The storage of the outpoints a cosigning server already signed a Spend transaction
+/// for.
+pub trait OutpointStore {
+    /// The error returned by the store
+    type Error: fmt::Display;
+
+    /// Mark these outpoints as spent by the transaction with this txid, unless one of
+    /// them is already marked as spent by another transaction. Returns `false` in this
+    /// case, and `true` if the transaction can be signed.
+    /// This MUST be atomic: concurrent calls must not both succeed for the same
+    /// outpoint and different transactions.
+    fn mark_spent(&self, outpoints: &[OutPoint], txid: Txid) -> Result<bool, Self::Error>;
+}
+
+/// An [OutpointStore] keeping the outpoints in memory, mostly useful for testing.
+#[derive(Debug, Default)]
+pub struct MemoryOutpointStore {
+    spent: Mutex<BTreeMap<OutPoint, Txid>>,
+}
+
+impl MemoryOutpointStore {
+    /// An empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl OutpointStore for MemoryOutpointStore {
+    type Error = Infallible;
+
+    fn mark_spent(&self, outpoints: &[OutPoint], txid: Txid) -> Result<bool, Self::Error> {
+        let mut spent = self.spent.lock().expect("Poisoned lock");
+        if outpoints
+            .iter()
+            .any(|outpoint| matches!(spent.get(outpoint), Some(spent_by) if spent_by != &txid))
+        {
+            return Ok(false);
+        }
+
+        for outpoint in outpoints {
+            spent.insert(*outpoint, txid);
+        }
+        Ok(true)
+    }
+}
+
+/// A cosigning server, handling the `sign` requests of the managers.
+///
+/// A Spend transaction is only signed if none of the outpoints it spends was signed
+/// for another transaction before. Signing the same transaction again is fine.
+/// The manager gets a `null` transaction otherwise, and also if the backends failed
+/// (after logging the error).
+#[derive(Debug)]
+pub struct Cosigner<B, S> {
+    backend: B,
+    store: S,
+    key_rotation: Option<cosigner::KeyRotation>,
+    rotation_acks: Mutex<Vec<PublicKey>>,
+}
+
+impl<B: SigningBackend, S: OutpointStore> Cosigner<B, S> {
+    /// A cosigning server signing with this backend and using this outpoint store
+    pub fn new(backend: B, store: S) -> Self {
+        Self {
+            backend,
+            store,
+            key_rotation: None,
+            rotation_acks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Accept the managers' acknowledgements of this rotation of our signing key,
+    /// once announced to them (see [KeyRotation](cosigner::KeyRotation)).
+    pub fn with_key_rotation(mut self, key_rotation: cosigner::KeyRotation) -> Self {
+        self.key_rotation = Some(key_rotation);
+        self
+    }
+
+    /// The static Noise public keys of the managers which acknowledged the rotation
+    /// of our signing key so far
+    pub fn key_rotation_acks(&self) -> Vec<PublicKey> {
+        self.rotation_acks.lock().expect("Poisoned lock").clone()
+    }
+
+    /// Get the signing backend of this cosigning server
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Get the outpoint store of this cosigning server
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    fn handle_sign(&self, mut spend_tx: SpendTransaction) -> Option<SpendTransaction> {
+        let txid = spend_tx.txid();
+        let outpoints: Vec<OutPoint> = spend_tx
+            .inner_tx()
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect();
+
+        match self.store.mark_spent(&outpoints, txid) {
+            Ok(true) => {}
+            Ok(false) => {
+                log_warn!(
+                    "Refusing to sign '{}': one of its outpoints was already signed for",
+                    txid
+                );
+                return None;
+            }
+            Err(e) => {
+                log_error!("Outpoint store error: '{}'", e);
+                return None;
+            }
+        }
+
+        if let Err(e) = self.backend.sign(&mut spend_tx) {
+            log_error!("Error signing '{}': '{}'", txid, e);
+            return None;
+        }
+
+        Some(spend_tx)
+    }
+}
+
+impl<B: SigningBackend, S: OutpointStore> RequestHandler for Cosigner<B, S> {
+    fn handle(&self, peer: &PublicKey, params: RequestParams) -> Option<ResponseResult> {
+        self.reply(peer, 0, params).and_then(Result::ok)
+    }
+
+    fn reply(&self, peer: &PublicKey, _id: u32, params: RequestParams) -> Option<Reply> {
+        let result = match params {
+            RequestParams::Sign(cosigner::SignRequest { tx, .. }) => {
+                ResponseResult::SignResult(cosigner::SignResult {
+                    tx: self.handle_sign(tx),
+                })
+            }
+            // Each transaction on its own, in order: of two spending the same outpoint
+            // only the first one is signed
+            RequestParams::BatchSign(req) => {
+                ResponseResult::BatchSignResult(cosigner::BatchSignResult {
+                    txs: req.txs.into_iter().map(|tx| self.handle_sign(tx)).collect(),
+                })
+            }
+            // We never sign twice for the same outpoint, whatever the backend
+            RequestParams::GetPubkey(_) => match self.backend.pubkey() {
+                Some(pubkey) => ResponseResult::CosignerPubkey(cosigner::GetPubkeyResult {
+                    pubkey,
+                    sighash_types: self.backend.sighash_types(),
+                    anti_replay: cosigner::AntiReplay::OncePerOutpoint,
+                }),
+                None => {
+                    log_warn!(
+                        "Refusing 'get_pubkey' request: the signing backend has no public key"
+                    );
+                    return Some(Err(ResponseError::new(
+                        ErrorCode::MethodNotFound,
+                        "The signing key of this cosigner is not disclosed",
+                    )));
+                }
+            },
+            RequestParams::AckKeyRotation(cosigner::AckKeyRotation { new_pubkey }) => {
+                let ack = self
+                    .key_rotation
+                    .as_ref()
+                    .map(|rotation| rotation.new_pubkey == new_pubkey)
+                    .unwrap_or(false);
+                if ack {
+                    let mut acks = self.rotation_acks.lock().expect("Poisoned lock");
+                    if !acks.contains(peer) {
+                        acks.push(*peer);
+                    }
+                } else {
+                    log_warn!("Ignoring acknowledgement of unknown key '{}'", new_pubkey);
+                }
+                ResponseResult::AckKeyRotation(cosigner::AckKeyRotationResult { ack, new_pubkey })
+            }
+            params => {
+                log_warn!("Refusing request not handled by the cosigner: {:?}", params);
+                return Some(Err(ResponseError::new(
+                    ErrorCode::MethodNotFound,
+                    format!("'{}' is not a cosigner method", params.method()),
+                )));
+            }
+        };
+
+        Some(Ok(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::CosignerClient,
+        error::Error,
+        fixtures::{local_endpoints, secp_privkey, secp_sig, spend_tx},
+        noise::gen_keypair,
+        server::serve,
+        transport::KKTransport,
+    };
+
+    use bitcoin::hashes::Hash;
+    use std::thread;
+
+    #[test]
+    fn sign_once_per_outpoint() {
+        let spend_tx = spend_tx();
+        // Another transaction spending the same outpoint
+        let mut other_tx = spend_tx.clone();
+        other_tx.inner_tx_mut().global.unsigned_tx.lock_time += 1;
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_tx = spend_tx.clone();
+        let cli_thread = thread::spawn(move || {
+            let mut client =
+                CosignerClient::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            let signed_tx = client.sign(cli_tx.clone()).unwrap();
+            assert_eq!(signed_tx.inner_tx().inputs[0].partial_sigs.len(), 1);
+            // Signing the same transaction again is fine
+            assert_eq!(client.sign(cli_tx).unwrap(), signed_tx);
+            assert!(matches!(
+                client.sign(other_tx),
+                Err(Error::NotAcknowledged(_))
+            ));
+        });
+
+        let mut transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        let cosigner = Cosigner::new(
+            PrivateKeySigner::new(secp_privkey()),
+            MemoryOutpointStore::new(),
+        );
+        serve(&mut transport, &cosigner).unwrap();
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn sign_batch() {
+        let spend_tx = spend_tx();
+        let mut other_tx = spend_tx.clone();
+        other_tx.inner_tx_mut().global.unsigned_tx.lock_time += 1;
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_tx = spend_tx.clone();
+        let cli_thread = thread::spawn(move || {
+            let mut client =
+                CosignerClient::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            // The second one spends the same outpoint as the first one, it's refused
+            let signed = client
+                .sign_batch(vec![cli_tx.clone(), other_tx, cli_tx])
+                .unwrap();
+            assert_eq!(signed.len(), 3);
+            let signed_tx = signed[0].clone().unwrap();
+            assert_eq!(signed_tx.inner_tx().inputs[0].partial_sigs.len(), 1);
+            assert!(signed[1].is_none());
+            assert_eq!(signed[2], Some(signed_tx));
+            assert_eq!(client.sign_batch(vec![]).unwrap(), vec![]);
+        });
+
+        let mut transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        let cosigner = Cosigner::new(
+            PrivateKeySigner::new(secp_privkey()),
+            MemoryOutpointStore::new(),
+        );
+        serve(&mut transport, &cosigner).unwrap();
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn key_rotation() {
+        let secp = secp256k1::Secp256k1::new();
+        let (old_privkey, old_pubkey) = (secp_privkey(), secp_sig().0);
+        let new_privkey = SecretKey::from_slice(&[2; 32]).unwrap();
+        let new_pubkey = secp256k1::PublicKey::from_secret_key(&secp, &new_privkey);
+        let rotation = cosigner::KeyRotation::new(&secp, &old_privkey, new_pubkey);
+        // Announcing a rotation to another key than the one acknowledged
+        let other_rotation = cosigner::KeyRotation::new(&secp, &old_privkey, old_pubkey);
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_rotation = rotation.clone();
+        let cli_thread = thread::spawn(move || {
+            let mut client =
+                CosignerClient::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            // Not from the key we know the cosigning server by
+            assert!(matches!(
+                client.ack_key_rotation(&cli_rotation, &new_pubkey),
+                Err(Error::Message(_))
+            ));
+            assert!(matches!(
+                client.ack_key_rotation(&other_rotation, &old_pubkey),
+                Err(Error::NotAcknowledged(_))
+            ));
+            client.ack_key_rotation(&cli_rotation, &old_pubkey).unwrap();
+            let info = client.get_pubkey().unwrap();
+            assert!(info.is_compatible(&old_pubkey));
+        });
+
+        let mut transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        let cosigner = Cosigner::new(
+            PrivateKeySigner::new(old_privkey),
+            MemoryOutpointStore::new(),
+        )
+        .with_key_rotation(rotation);
+        serve(&mut transport, &cosigner).unwrap();
+        cli_thread.join().unwrap();
+        assert_eq!(cosigner.key_rotation_acks(), vec![client_pubkey]);
+    }
+
+    // A backend which doesn't disclose its key
+    struct HiddenKeySigner;
+
+    impl SigningBackend for HiddenKeySigner {
+        type Error = Infallible;
+
+        fn sign(&self, _: &mut SpendTransaction) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn refused_requests() {
+        let (peer, _) = gen_keypair();
+        let cosigner = Cosigner::new(HiddenKeySigner, MemoryOutpointStore::new());
+        let reply = cosigner.reply(&peer, 0, RequestParams::GetPubkey(cosigner::GetPubkey {}));
+        assert_eq!(reply.unwrap().unwrap_err().code, ErrorCode::MethodNotFound);
+
+        // Nor do we answer the requests meant to a coordinator
+        let params = RequestParams::GetTime(crate::message::coordinator::GetTime {});
+        let reply = cosigner.reply(&peer, 0, params.clone());
+        assert_eq!(reply.unwrap().unwrap_err().code, ErrorCode::MethodNotFound);
+        assert_eq!(cosigner.handle(&peer, params), None);
+    }
+
+    #[test]
+    fn memory_outpoint_store() {
+        let store = MemoryOutpointStore::new();
+        let (a, b) = (
+            OutPoint::default(),
+            OutPoint {
+                vout: 1,
+                ..OutPoint::default()
+            },
+        );
+        let (txid_1, txid_2) = (Txid::default(), Txid::from_slice(&[1; 32]).unwrap());
+
+        assert!(store.mark_spent(&[a], txid_1).unwrap());
+        assert!(store.mark_spent(&[a], txid_1).unwrap());
+        // Nothing is marked if one of the outpoints is already spent
+        assert!(!store.mark_spent(&[b, a], txid_2).unwrap());
+        assert!(store.mark_spent(&[b], txid_1).unwrap());
+    }
+}
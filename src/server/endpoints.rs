@@ -0,0 +1,152 @@
+//! Multiple listening endpoints
+//!
+//! A server may be reached on several addresses: a LAN address, localhost for the
+//! components running on the same machine, and a port forwarded by a Tor hidden
+//! service. A [MultiListener] accepts the connections on all of them at once and
+//! merges them into a single stream of [Session]s, each telling which of the endpoints
+//! it arrived on (for instance to only allow some peers through Tor).
+
+use crate::{
+    error::Error,
+    instrument::log_warn,
+    noise::{PublicKey, SecretKey},
+    transport::KKTransport,
+};
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::mpsc,
+    thread,
+};
+
+/// A connection accepted by a [MultiListener]
+#[derive(Debug)]
+pub struct Session {
+    /// The connection, once the handshake completed
+    pub transport: KKTransport,
+    /// The local address of the listener it was accepted on
+    pub endpoint: SocketAddr,
+}
+
+/// Accepts the connections of a set of peers on several listeners, as a single
+/// iterator over the [Session]s.
+///
+/// Each listener is accepted on by its own thread, which performs the handshakes. A
+/// failed handshake is logged and the connection dropped. Once the listener is
+/// dropped, the threads exit after their next accepted connection.
+#[derive(Debug)]
+pub struct MultiListener {
+    endpoints: Vec<SocketAddr>,
+    sessions: mpsc::Receiver<Session>,
+}
+
+impl MultiListener {
+    /// Bind all these addresses and accept the connections of these peers on them
+    pub fn bind(
+        addrs: &[SocketAddr],
+        my_noise_privkey: &SecretKey,
+        their_possible_pubkeys: &[PublicKey],
+    ) -> Result<Self, Error> {
+        let listeners = addrs
+            .iter()
+            .map(TcpListener::bind)
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(listeners, my_noise_privkey, their_possible_pubkeys)
+    }
+
+    /// Accept the connections of these peers on all these listeners
+    pub fn new(
+        listeners: Vec<TcpListener>,
+        my_noise_privkey: &SecretKey,
+        their_possible_pubkeys: &[PublicKey],
+    ) -> Result<Self, Error> {
+        let (sender, sessions) = mpsc::channel();
+        let mut endpoints = Vec::with_capacity(listeners.len());
+
+        for listener in listeners {
+            let endpoint = listener.local_addr()?;
+            endpoints.push(endpoint);
+            let (sender, privkey, pubkeys) = (
+                sender.clone(),
+                my_noise_privkey.clone(),
+                their_possible_pubkeys.to_vec(),
+            );
+            thread::Builder::new()
+                .name(format!("revault_net listener {}", endpoint))
+                .spawn(move || loop {
+                    let transport = match KKTransport::accept(&listener, &privkey, &pubkeys) {
+                        Ok(transport) => transport,
+                        Err(e) => {
+                            log_warn!("Error accepting connection on '{}': '{}'", endpoint, e);
+                            continue;
+                        }
+                    };
+                    if sender
+                        .send(Session {
+                            transport,
+                            endpoint,
+                        })
+                        .is_err()
+                    {
+                        return;
+                    }
+                })?;
+        }
+
+        Ok(Self {
+            endpoints,
+            sessions,
+        })
+    }
+
+    /// The local addresses of the listeners
+    pub fn endpoints(&self) -> &[SocketAddr] {
+        &self.endpoints
+    }
+}
+
+impl Iterator for MultiListener {
+    type Item = Session;
+
+    /// Wait for the next connection accepted on any of the listeners
+    fn next(&mut self) -> Option<Session> {
+        self.sessions.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::noise::gen_keypair;
+
+    #[test]
+    fn multiple_endpoints() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
+            (gen_keypair(), gen_keypair());
+        let addrs: Vec<SocketAddr> = vec![
+            "127.0.0.1:0".parse().unwrap(),
+            "127.0.0.1:0".parse().unwrap(),
+        ];
+        let mut listener = MultiListener::bind(&addrs, &server_privkey, &[client_pubkey]).unwrap();
+        let endpoints = listener.endpoints().to_vec();
+        assert_eq!(endpoints.len(), 2);
+        assert_ne!(endpoints[0], endpoints[1]);
+
+        for endpoint in endpoints.iter().rev() {
+            let _transport =
+                KKTransport::connect(*endpoint, &client_privkey, &server_pubkey).unwrap();
+            let session = listener.next().unwrap();
+            assert_eq!(session.endpoint, *endpoint);
+            assert_eq!(session.transport.remote_static(), client_pubkey);
+        }
+
+        // Failed handshakes are not reported
+        let (other_pubkey, other_privkey) = gen_keypair();
+        assert!(KKTransport::connect(endpoints[0], &other_privkey, &server_pubkey).is_err());
+        let _transport =
+            KKTransport::connect(endpoints[1], &client_privkey, &server_pubkey).unwrap();
+        let session = listener.next().unwrap();
+        assert_eq!(session.endpoint, endpoints[1]);
+        assert_ne!(session.transport.remote_static(), other_pubkey);
+    }
+}
@@ -0,0 +1,151 @@
+//! Message expiry
+//!
+//! Some requests carry the time after which they must not be acted upon anymore (see
+//! [Expiring]), so that a request delayed for hours can't act on a stale intention.
+//! Used as a [Middleware], [RejectExpired] refuses them once they are expired under its
+//! [ClockPolicy].
+
+use crate::{
+    clock::ClockPolicy,
+    instrument::log_warn,
+    message::{coordinator::SetSpendResult, Expiring, RequestParams, ResponseResult},
+    noise::PublicKey,
+    server::{Flow, Middleware},
+};
+
+#[cfg(feature = "revault_tx")]
+use crate::message::cosigner::{BatchSignResult, SignResult};
+
+/// Refuses the expired requests: a Spend transaction to store is not acknowledged, a
+/// Spend transaction to sign is not signed. Requests without an expiry are let through.
+#[derive(Debug, Clone, Default)]
+pub struct RejectExpired {
+    clock: ClockPolicy,
+}
+
+impl RejectExpired {
+    /// A middleware refusing the expired requests under the default clock policy
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tell whether the requests are expired under this clock policy
+    pub fn with_clock(mut self, clock: ClockPolicy) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The clock policy the requests are checked under
+    pub fn clock(&self) -> &ClockPolicy {
+        &self.clock
+    }
+
+    /// The response refusing these params if they are expired under our clock policy
+    pub fn refusal(&self, params: &RequestParams) -> Option<ResponseResult> {
+        if !params.is_expired_under(&self.clock) {
+            return None;
+        }
+
+        match params {
+            RequestParams::SetSpendTx(_) => {
+                Some(ResponseResult::SetSpend(SetSpendResult { ack: false }))
+            }
+            #[cfg(feature = "revault_tx")]
+            RequestParams::Sign(_) => Some(ResponseResult::SignResult(SignResult { tx: None })),
+            #[cfg(feature = "revault_tx")]
+            RequestParams::BatchSign(req) => {
+                Some(ResponseResult::BatchSignResult(BatchSignResult {
+                    txs: vec![None; req.txs.len()],
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Middleware for RejectExpired {
+    fn before(&self, peer: &PublicKey, params: RequestParams) -> Flow {
+        match self.refusal(&params) {
+            Some(refusal) => {
+                log_warn!(
+                    "Refusing expired '{}' request from '{:?}'",
+                    params.method(),
+                    peer
+                );
+                Flow::Stop(Some(refusal))
+            }
+            None => Flow::Continue(params),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::coordinator::{GetSigs, SetSpendTx},
+        noise::gen_keypair,
+        server::{Dispatcher, RequestHandler},
+    };
+
+    use bitcoin::{consensus::encode, hashes::hex::FromHex, OutPoint, Transaction, Txid};
+    use std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    #[test]
+    fn reject_expired() {
+        let raw_tx = "02000000018ef847bc9f2a361ab63f7abe8e56c369d15e730ba89674b09b42674bd40c94f50000000000cd5600000280d8010000000000220020ae1bdee388f2136054797227b14a983d28de29f522f3ebdc4e25fd2bae3d9e5201000000000000000000000000";
+        let transaction: Transaction =
+            encode::deserialize(&Vec::<u8>::from_hex(raw_tx).unwrap()).unwrap();
+        let set_spend = SetSpendTx::from_transaction(vec![OutPoint::default()], transaction)
+            .unwrap()
+            .with_expiry(1_000);
+        let params = RequestParams::SetSpendTx(set_spend.clone());
+        assert_eq!(params.expires_at(), Some(1_000));
+        assert!(!params.is_expired(1_000));
+        assert!(params.is_expired(1_001));
+
+        // Under a mocked clock, tolerating some skew
+        let time = Arc::new(AtomicU64::new(1_000));
+        let clock_time = time.clone();
+        let reject = RejectExpired::new().with_clock(
+            ClockPolicy::new()
+                .with_max_skew(Duration::from_secs(30))
+                .with_time_source(Arc::new(move || clock_time.load(Ordering::SeqCst))),
+        );
+        assert_eq!(reject.refusal(&params), None);
+        time.store(1_030, Ordering::SeqCst);
+        assert_eq!(reject.refusal(&params), None);
+        time.store(1_031, Ordering::SeqCst);
+        assert_eq!(
+            reject.refusal(&params),
+            Some(ResponseResult::SetSpend(SetSpendResult { ack: false }))
+        );
+        let get_sigs = RequestParams::GetSigs(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        time.store(u64::MAX, Ordering::SeqCst);
+        assert_eq!(reject.refusal(&get_sigs), None);
+
+        // As a middleware, with the system's clock
+        let (peer, _) = gen_keypair();
+        let handler =
+            |_: &PublicKey, _| Some(ResponseResult::SetSpend(SetSpendResult { ack: true }));
+        let dispatcher = Dispatcher::new(handler).with(RejectExpired::new());
+        assert_eq!(
+            dispatcher.handle(&peer, params),
+            Some(ResponseResult::SetSpend(SetSpendResult { ack: false }))
+        );
+        let not_expired = RequestParams::SetSpendTx(set_spend.with_expiry(u64::MAX));
+        assert_eq!(
+            dispatcher.handle(&peer, not_expired),
+            Some(ResponseResult::SetSpend(SetSpendResult { ack: true }))
+        );
+    }
+}
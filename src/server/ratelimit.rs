@@ -0,0 +1,248 @@
+//! Rate limiting
+//!
+//! A [RateLimiter] limits the rate of the requests of each peer, identified by its
+//! static Noise public key, using a token bucket per peer and method. Used as a
+//! [Middleware], it refuses the requests of a peer exceeding its limit with a
+//! [QuotaExceeded](crate::message::ErrorCode::QuotaExceeded) error, telling it when to retry, so that a
+//! single (authenticated) peer can't starve the server.
+
+use crate::{
+    instrument::log_warn,
+    message::{RequestParams, ResponseError},
+    noise::PublicKey,
+    server::{Flow, Middleware},
+};
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The rate requests are allowed at: up to `burst` requests at once, then `per_second`
+/// requests per second on average.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// The number of requests allowed at once
+    pub burst: u32,
+    /// The number of requests allowed per second after a burst
+    pub per_second: f64,
+}
+
+impl RateLimit {
+    /// Up to `burst` requests at once, then `per_second` per second
+    pub fn new(burst: u32, per_second: f64) -> Self {
+        Self { burst, per_second }
+    }
+}
+
+// The requests a peer may still make for a method
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn full(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            last_refill: now,
+        }
+    }
+
+    // Take a token if there is one left, after refilling the bucket for the time elapsed.
+    // Otherwise get the time until there is one, if it ever refills.
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> Result<(), Option<Duration>> {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * limit.per_second).min(limit.burst as f64);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else if limit.per_second > 0.0 && limit.burst > 0 {
+            Err(Some(Duration::from_secs_f64(
+                (1.0 - self.tokens) / limit.per_second,
+            )))
+        } else {
+            Err(None)
+        }
+    }
+}
+
+/// Limits the rate of the requests of each peer, per method.
+///
+/// Each method is limited by the limit set for it if any, otherwise by the default one.
+/// Methods without a limit are not limited.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    default: Option<RateLimit>,
+    per_method: HashMap<&'static str, RateLimit>,
+    buckets: Mutex<HashMap<(PublicKey, &'static str), Bucket>>,
+}
+
+impl RateLimiter {
+    /// A limiter applying this limit to all the methods
+    pub fn new(default: RateLimit) -> Self {
+        Self {
+            default: Some(default),
+            ..Self::default()
+        }
+    }
+
+    /// A limiter only limiting the methods it is given a limit for
+    pub fn per_method() -> Self {
+        Self::default()
+    }
+
+    /// Limit the requests for this method with this limit instead of the default one
+    pub fn with_method(mut self, method: &'static str, limit: RateLimit) -> Self {
+        self.per_method.insert(method, limit);
+        self
+    }
+
+    /// The limit applied to the requests for this method, if any
+    pub fn limit(&self, method: &str) -> Option<&RateLimit> {
+        self.per_method.get(method).or(self.default.as_ref())
+    }
+
+    /// Check whether the peer with this static Noise public key may make a request for
+    /// this method now, counting it if so.
+    pub fn check(&self, peer: &PublicKey, method: &'static str) -> bool {
+        self.check_at(peer, method, Instant::now())
+    }
+
+    fn check_at(&self, peer: &PublicKey, method: &'static str, now: Instant) -> bool {
+        self.take_at(peer, method, now).is_ok()
+    }
+
+    // Count a request if the peer may make it now, otherwise get when it may retry
+    fn take_at(
+        &self,
+        peer: &PublicKey,
+        method: &'static str,
+        now: Instant,
+    ) -> Result<(), Option<Duration>> {
+        let limit = match self.limit(method) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned");
+        buckets
+            .entry((*peer, method))
+            .or_insert_with(|| Bucket::full(limit, now))
+            .take(limit, now)
+    }
+
+    /// Forget the requests made by the peers more than `idle` ago, for peers that
+    /// disconnected not to be kept in memory.
+    pub fn prune(&self, idle: Duration) {
+        let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned");
+        buckets.retain(|_, bucket| bucket.last_refill.elapsed() < idle);
+    }
+}
+
+impl Middleware for RateLimiter {
+    fn before(&self, peer: &PublicKey, params: RequestParams) -> Flow {
+        let method = params.method();
+        match self.take_at(peer, method, Instant::now()) {
+            Ok(()) => Flow::Continue(params),
+            Err(retry_after) => {
+                log_warn!(
+                    "Rejecting '{}' request from '{:?}': rate limit exceeded",
+                    method,
+                    peer
+                );
+                Flow::Refuse(ResponseError::quota_exceeded(
+                    format!("Rate limit exceeded for '{}'", method),
+                    retry_after,
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::{
+            coordinator::{GetSigs, Sigs},
+            method, ErrorCode, ResponseResult, SigSet,
+        },
+        noise::gen_keypair,
+        server::{Dispatcher, RequestHandler},
+    };
+
+    use bitcoin::Txid;
+
+    #[test]
+    fn rate_limiter() {
+        let (peer_a, _) = gen_keypair();
+        let (peer_b, _) = gen_keypair();
+        let limiter = RateLimiter::new(RateLimit::new(2, 1.0))
+            .with_method(method::GET_SIGS, RateLimit::new(1, 0.5));
+        assert_eq!(limiter.limit(method::SIG), Some(&RateLimit::new(2, 1.0)));
+
+        let start = Instant::now();
+        // A burst, then nothing until the bucket refills
+        assert!(limiter.check_at(&peer_a, method::SIG, start));
+        assert!(limiter.check_at(&peer_a, method::SIG, start));
+        assert!(!limiter.check_at(&peer_a, method::SIG, start));
+        assert!(limiter.check_at(&peer_a, method::SIG, start + Duration::from_secs(1)));
+        assert!(!limiter.check_at(&peer_a, method::SIG, start + Duration::from_secs(1)));
+        // It never holds more than a burst
+        let later = start + Duration::from_secs(60);
+        assert!(limiter.check_at(&peer_a, method::SIG, later));
+        assert!(limiter.check_at(&peer_a, method::SIG, later));
+        assert!(!limiter.check_at(&peer_a, method::SIG, later));
+
+        // Per method and per peer
+        assert!(limiter.check_at(&peer_a, method::GET_SIGS, start));
+        assert!(!limiter.check_at(&peer_a, method::GET_SIGS, start + Duration::from_secs(1)));
+        assert!(limiter.check_at(&peer_a, method::GET_SIGS, start + Duration::from_secs(2)));
+        assert!(limiter.check_at(&peer_b, method::SIG, start));
+        assert!(limiter.check_at(&peer_b, method::GET_SIGS, start));
+
+        // Only the methods given a limit are limited
+        let limiter =
+            RateLimiter::per_method().with_method(method::GET_SIGS, RateLimit::new(0, 0.0));
+        assert!(!limiter.check(&peer_a, method::GET_SIGS));
+        assert!((0..100).all(|_| limiter.check(&peer_a, method::SIG)));
+        limiter.prune(Duration::from_secs(0));
+        assert!(limiter.buckets.lock().unwrap().is_empty());
+
+        // As a middleware
+        let handler = |_: &PublicKey, _| {
+            Some(ResponseResult::Sigs(Sigs {
+                signatures: SigSet::new(),
+            }))
+        };
+        let dispatcher = Dispatcher::new(handler).with(RateLimiter::new(RateLimit::new(1, 0.0)));
+        let params = RequestParams::GetSigs(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        assert!(dispatcher.handle(&peer_a, params.clone()).is_some());
+        assert!(dispatcher.handle(&peer_a, params.clone()).is_none());
+        assert!(dispatcher.handle(&peer_b, params.clone()).is_some());
+
+        // The peers are told why, and when to retry if the limit ever allows it
+        let err = dispatcher
+            .reply(&peer_a, 0, params.clone())
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::QuotaExceeded);
+        assert_eq!(err.retry_after(), None);
+        let dispatcher = Dispatcher::new(handler).with(RateLimiter::new(RateLimit::new(1, 0.5)));
+        assert!(dispatcher
+            .reply(&peer_a, 0, params.clone())
+            .unwrap()
+            .is_ok());
+        let err = dispatcher.reply(&peer_a, 1, params).unwrap().unwrap_err();
+        assert_eq!(err.code, ErrorCode::QuotaExceeded);
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(2)));
+    }
+}
@@ -0,0 +1,313 @@
+//! Misbehavior scoring
+//!
+//! [PeerScores] accumulates points for the protocol violations of each peer, identified
+//! by its static Noise public key. A peer reaching the threshold is banned for a while:
+//! its handshakes are refused by [listen_scored](crate::server::listen_scored), and its
+//! requests refused with an [AccessDenied](ErrorCode::AccessDenied) error when used as a
+//! [Middleware]. An operator can be notified of the
+//! bans with a hook.
+//!
+//! The malformed messages are recorded by [listen_scored](crate::server::listen_scored),
+//! the violations only the application can detect through [PeerScores::record].
+
+use crate::{
+    instrument::log_warn,
+    message::{ErrorCode, RequestParams, ResponseError},
+    noise::PublicKey,
+    server::{Flow, Middleware},
+};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// A protocol violation by a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// It sent a message we could not parse
+    MalformedMessage,
+    /// Another violation, worth this number of points. For the ones only the
+    /// application can tell, such as an invalid signature of a transaction.
+    Other(u32),
+}
+
+impl Violation {
+    /// The number of points this violation is worth
+    pub fn points(&self) -> u32 {
+        match *self {
+            Self::MalformedMessage => 20,
+            Self::Other(points) => points,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MalformedMessage => write!(f, "malformed message"),
+            Self::Other(points) => write!(f, "violation ({} points)", points),
+        }
+    }
+}
+
+/// When a peer is banned, see [PeerScores].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScoringConfig {
+    /// The number of points a peer is banned at
+    pub ban_threshold: u32,
+    /// How long the points of a peer are kept for, from its first violation
+    pub window: Duration,
+    /// How long a peer is banned for
+    pub ban_duration: Duration,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        Self {
+            ban_threshold: 100,
+            window: Duration::from_secs(10 * 60),
+            ban_duration: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+// The points of a peer since its first violation in the window, and its ban if any
+#[derive(Debug, Clone, Copy)]
+struct Score {
+    points: u32,
+    since: Instant,
+    banned_until: Option<Instant>,
+}
+
+type BanHook = Box<dyn Fn(&PublicKey, &Violation, Duration) + Send + Sync>;
+
+/// The misbehavior scores of the peers, banning the ones reaching the threshold.
+///
+/// Points expire after the configured window. A ban resets the points of the peer.
+pub struct PeerScores {
+    config: ScoringConfig,
+    scores: Mutex<HashMap<PublicKey, Score>>,
+    on_ban: Option<BanHook>,
+}
+
+impl fmt::Debug for PeerScores {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PeerScores")
+            .field("config", &self.config)
+            .field("scores", &self.scores)
+            .finish()
+    }
+}
+
+impl Default for PeerScores {
+    fn default() -> Self {
+        Self::new(ScoringConfig::default())
+    }
+}
+
+impl PeerScores {
+    /// Scores banning peers according to this configuration
+    pub fn new(config: ScoringConfig) -> Self {
+        Self {
+            config,
+            scores: Mutex::new(HashMap::new()),
+            on_ban: None,
+        }
+    }
+
+    /// Call this hook when a peer is banned, with the violation that got it banned and
+    /// the duration of the ban. It is called with the scores locked.
+    pub fn on_ban<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&PublicKey, &Violation, Duration) + Send + Sync + 'static,
+    {
+        self.on_ban = Some(Box::new(hook));
+        self
+    }
+
+    /// Count a violation by this peer. Returns whether it is banned.
+    pub fn record(&self, peer: &PublicKey, violation: Violation) -> bool {
+        self.record_at(peer, violation, Instant::now())
+    }
+
+    fn record_at(&self, peer: &PublicKey, violation: Violation, now: Instant) -> bool {
+        let mut scores = self.scores.lock().expect("Scores lock poisoned");
+        let score = scores.entry(*peer).or_insert(Score {
+            points: 0,
+            since: now,
+            banned_until: None,
+        });
+        if matches!(score.banned_until, Some(until) if until > now) {
+            return true;
+        }
+        if now.saturating_duration_since(score.since) >= self.config.window {
+            score.points = 0;
+            score.since = now;
+        }
+
+        score.points = score.points.saturating_add(violation.points());
+        if score.points < self.config.ban_threshold {
+            return false;
+        }
+
+        log_warn!(
+            "Banning '{:?}' for {:?} after a {}",
+            peer,
+            self.config.ban_duration,
+            violation
+        );
+        score.points = 0;
+        score.since = now;
+        score.banned_until = Some(now + self.config.ban_duration);
+        if let Some(hook) = &self.on_ban {
+            hook(peer, &violation, self.config.ban_duration);
+        }
+
+        true
+    }
+
+    /// The points of this peer, if it isn't banned
+    pub fn score(&self, peer: &PublicKey) -> u32 {
+        self.score_at(peer, Instant::now())
+    }
+
+    fn score_at(&self, peer: &PublicKey, now: Instant) -> u32 {
+        let scores = self.scores.lock().expect("Scores lock poisoned");
+        scores
+            .get(peer)
+            .filter(|score| now.saturating_duration_since(score.since) < self.config.window)
+            .map(|score| score.points)
+            .unwrap_or(0)
+    }
+
+    /// Whether this peer is currently banned
+    pub fn is_banned(&self, peer: &PublicKey) -> bool {
+        self.is_banned_at(peer, Instant::now())
+    }
+
+    fn is_banned_at(&self, peer: &PublicKey, now: Instant) -> bool {
+        self.ban_left_at(peer, now).is_some()
+    }
+
+    // The time left until the ban of this peer is lifted, if it is banned
+    fn ban_left_at(&self, peer: &PublicKey, now: Instant) -> Option<Duration> {
+        let scores = self.scores.lock().expect("Scores lock poisoned");
+        scores
+            .get(peer)
+            .and_then(|score| score.banned_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// Lift the ban of this peer and forget its points
+    pub fn unban(&self, peer: &PublicKey) {
+        self.scores
+            .lock()
+            .expect("Scores lock poisoned")
+            .remove(peer);
+    }
+
+    /// These public keys, without the ones of the banned peers
+    pub fn allowed(&self, pubkeys: &[PublicKey]) -> Vec<PublicKey> {
+        let now = Instant::now();
+        pubkeys
+            .iter()
+            .filter(|pubkey| !self.is_banned_at(pubkey, now))
+            .copied()
+            .collect()
+    }
+}
+
+impl Middleware for PeerScores {
+    fn before(&self, peer: &PublicKey, params: RequestParams) -> Flow {
+        if let Some(ban_left) = self.ban_left_at(peer, Instant::now()) {
+            log_warn!(
+                "Rejecting '{}' request from banned peer '{:?}'",
+                params.method(),
+                peer
+            );
+            return Flow::Refuse(
+                ResponseError::new(ErrorCode::AccessDenied, "Banned for misbehaving")
+                    .with_retry_after(ban_left),
+            );
+        }
+
+        Flow::Continue(params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::noise::gen_keypair;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn peer_scores() {
+        let (peer, _) = gen_keypair();
+        let (other_peer, _) = gen_keypair();
+        let (third_peer, _) = gen_keypair();
+        let bans = Arc::new(Mutex::new(Vec::new()));
+        let hook_bans = bans.clone();
+        let scores = PeerScores::new(ScoringConfig {
+            ban_threshold: 100,
+            window: Duration::from_secs(60),
+            ban_duration: Duration::from_secs(600),
+        })
+        .on_ban(move |peer, violation, duration| {
+            hook_bans
+                .lock()
+                .unwrap()
+                .push((*peer, *violation, duration));
+        });
+
+        let start = Instant::now();
+        assert!(!scores.record_at(&peer, Violation::MalformedMessage, start));
+        assert!(!scores.record_at(&peer, Violation::Other(50), start));
+        assert_eq!(scores.score_at(&peer, start), 70);
+        // The points expire
+        let later = start + Duration::from_secs(60);
+        assert_eq!(scores.score_at(&peer, later), 0);
+        assert!(!scores.record_at(&peer, Violation::Other(50), later));
+        assert!(!scores.is_banned_at(&peer, later));
+        assert!(bans.lock().unwrap().is_empty());
+
+        assert!(scores.record_at(&peer, Violation::Other(50), later));
+        assert!(scores.is_banned_at(&peer, later));
+        assert!(!scores.is_banned_at(&other_peer, later));
+        assert_eq!(
+            *bans.lock().unwrap(),
+            vec![(peer, Violation::Other(50), Duration::from_secs(600))]
+        );
+        // Violations while banned don't extend the ban
+        assert!(scores.record_at(&peer, Violation::Other(1000), later));
+        assert_eq!(bans.lock().unwrap().len(), 1);
+        let after_ban = later + Duration::from_secs(600);
+        assert!(!scores.is_banned_at(&peer, after_ban));
+        assert_eq!(scores.score_at(&peer, after_ban), 0);
+
+        assert!(scores.record(&other_peer, Violation::Other(100)));
+        assert_eq!(scores.allowed(&[other_peer, third_peer]), vec![third_peer]);
+        let params = RequestParams::GetSigs(crate::message::coordinator::GetSigs {
+            id: bitcoin::Txid::default(),
+            if_none_match: None,
+        });
+        match scores.before(&other_peer, params.clone()) {
+            Flow::Refuse(err) => {
+                assert_eq!(err.code, ErrorCode::AccessDenied);
+                assert_eq!(err.retry_after(), Some(Duration::from_secs(600)));
+            }
+            _ => panic!("A banned peer's request was not refused"),
+        }
+        assert!(matches!(
+            scores.before(&third_peer, params),
+            Flow::Continue(_)
+        ));
+        scores.unban(&other_peer);
+        assert!(!scores.is_banned(&other_peer));
+    }
+}
@@ -0,0 +1,294 @@
+//! Reassembly of chunked uploads
+//!
+//! A [SetSpendTx] for a Spend sweeping hundreds of vaults may be too large to be sent
+//! in a single message. It is then uploaded in chunks (see
+//! [SetSpendTx::to_chunks]): a [SetSpendTxBegin] announcing its size and digest, the
+//! [SetSpendTxChunk]s in order and a [SetSpendTxCommit]. [Uploads] keeps track of the
+//! uploads in progress and reassembles them, for a coordinator to store the
+//! [SetSpendTx] once committed.
+
+use crate::{
+    error::{from_json_slice, Error, MessageError},
+    message::coordinator::{
+        SetSpendTx, SetSpendTxBegin, SetSpendTxChunk, SetSpendTxCommit, UploadStarted,
+    },
+    noise::PublicKey,
+};
+
+use bitcoin::hashes::{sha256, Hash};
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// The default maximum size of a message uploaded in chunks
+pub const MAX_UPLOAD_SIZE: u32 = 4 * 1024 * 1024;
+/// The default maximum number of uploads in progress per peer
+pub const MAX_PENDING_UPLOADS: usize = 4;
+
+#[derive(Debug)]
+struct Upload {
+    peer: PublicKey,
+    begin: SetSpendTxBegin,
+    data: Vec<u8>,
+    next_index: u32,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: u32,
+    // By id, hence from the oldest
+    uploads: BTreeMap<u32, Upload>,
+}
+
+/// The uploads in progress, by peer.
+///
+/// A peer starting more uploads than allowed drops its oldest one. An upload can only
+/// be continued and committed by the peer that started it.
+#[derive(Debug)]
+pub struct Uploads {
+    max_size: u32,
+    max_pending: usize,
+    state: Mutex<State>,
+}
+
+impl Default for Uploads {
+    fn default() -> Self {
+        Self {
+            max_size: MAX_UPLOAD_SIZE,
+            max_pending: MAX_PENDING_UPLOADS,
+            state: Mutex::new(State::default()),
+        }
+    }
+}
+
+impl Uploads {
+    /// No upload in progress, up to [MAX_PENDING_UPLOADS] per peer of at most
+    /// [MAX_UPLOAD_SIZE] bytes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only allow uploads of at most this size
+    pub fn with_max_size(mut self, max_size: u32) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Only allow this number of uploads in progress per peer
+    pub fn with_max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending.max(1);
+        self
+    }
+
+    /// Start an upload for this peer
+    pub fn begin(
+        &self,
+        peer: &PublicKey,
+        begin: SetSpendTxBegin,
+    ) -> Result<UploadStarted, MessageError> {
+        if begin.total_size > self.max_size {
+            return Err(MessageError::TooLargeUpload {
+                size: begin.total_size.into(),
+                max_size: self.max_size.into(),
+            });
+        }
+
+        let mut state = self.state.lock().expect("Poisoned lock");
+        let pending: Vec<u32> = state
+            .uploads
+            .iter()
+            .filter(|(_, upload)| upload.peer == *peer)
+            .map(|(id, _)| *id)
+            .collect();
+        if pending.len() >= self.max_pending {
+            for id in &pending[..=pending.len() - self.max_pending] {
+                state.uploads.remove(id);
+            }
+        }
+
+        let upload_id = state.next_id;
+        state.next_id = state.next_id.wrapping_add(1);
+        state.uploads.insert(
+            upload_id,
+            Upload {
+                peer: *peer,
+                begin,
+                data: Vec::new(),
+                next_index: 0,
+            },
+        );
+
+        Ok(UploadStarted { upload_id })
+    }
+
+    /// Append a chunk to an upload of this peer
+    pub fn chunk(&self, peer: &PublicKey, chunk: SetSpendTxChunk) -> Result<(), MessageError> {
+        let mut state = self.state.lock().expect("Poisoned lock");
+        let upload = state
+            .uploads
+            .get_mut(&chunk.upload_id)
+            .filter(|upload| upload.peer == *peer)
+            .ok_or(MessageError::UnknownUpload(chunk.upload_id))?;
+
+        if chunk.index != upload.next_index {
+            return Err(MessageError::UnexpectedChunk {
+                expected: upload.next_index,
+                got: chunk.index,
+            });
+        }
+        let size = upload.data.len() + chunk.data.0.len();
+        if size > upload.begin.total_size as usize {
+            state.uploads.remove(&chunk.upload_id);
+            return Err(MessageError::CorruptedUpload(chunk.upload_id));
+        }
+        upload.data.extend_from_slice(&chunk.data.0);
+        upload.next_index += 1;
+
+        Ok(())
+    }
+
+    /// Complete an upload of this peer, checking and parsing the reassembled message.
+    /// The upload is done with, even if it fails.
+    pub fn commit(&self, peer: &PublicKey, commit: &SetSpendTxCommit) -> Result<SetSpendTx, Error> {
+        let mut state = self.state.lock().expect("Poisoned lock");
+        match state.uploads.get(&commit.upload_id) {
+            Some(upload) if upload.peer == *peer => {}
+            _ => return Err(MessageError::UnknownUpload(commit.upload_id).into()),
+        }
+        let upload = state
+            .uploads
+            .remove(&commit.upload_id)
+            .expect("Just checked");
+        drop(state);
+
+        if upload.data.len() != upload.begin.total_size as usize
+            || sha256::Hash::hash(&upload.data) != upload.begin.sha256
+        {
+            return Err(MessageError::CorruptedUpload(commit.upload_id).into());
+        }
+        from_json_slice(&upload.data)
+    }
+
+    /// The number of uploads in progress
+    pub fn len(&self) -> usize {
+        self.state.lock().expect("Poisoned lock").uploads.len()
+    }
+
+    /// Whether there is no upload in progress
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{message::coordinator::ChunkData, noise::gen_keypair};
+
+    use bitcoin::{OutPoint, Transaction, TxIn};
+
+    fn set_spend_tx(n_vaults: u32) -> SetSpendTx {
+        let deposit_outpoints: Vec<OutPoint> = (0..n_vaults)
+            .map(|vout| OutPoint {
+                vout,
+                ..OutPoint::default()
+            })
+            .collect();
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: deposit_outpoints
+                .iter()
+                .map(|outpoint| TxIn {
+                    previous_output: *outpoint,
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![Default::default()],
+        };
+        SetSpendTx::from_transaction(deposit_outpoints, transaction).unwrap()
+    }
+
+    fn chunk(upload_id: u32, index: u32, data: &ChunkData) -> SetSpendTxChunk {
+        SetSpendTxChunk {
+            upload_id,
+            index,
+            data: data.clone(),
+        }
+    }
+
+    #[test]
+    fn chunked_upload() {
+        let uploads = Uploads::new();
+        let (peer, _) = gen_keypair();
+        let (other_peer, _) = gen_keypair();
+        let msg = set_spend_tx(300);
+        let (begin, chunks) = msg.to_chunks(1_000);
+        assert!(chunks.len() > 10);
+
+        let UploadStarted { upload_id } = uploads.begin(&peer, begin.clone()).unwrap();
+        // Only the peer that started it can upload to it, and only in order
+        assert_eq!(
+            uploads.chunk(&other_peer, chunk(upload_id, 0, &chunks[0])),
+            Err(MessageError::UnknownUpload(upload_id))
+        );
+        assert_eq!(
+            uploads.chunk(&peer, chunk(upload_id, 1, &chunks[1])),
+            Err(MessageError::UnexpectedChunk {
+                expected: 0,
+                got: 1
+            })
+        );
+        for (index, data) in chunks.iter().enumerate() {
+            uploads
+                .chunk(&peer, chunk(upload_id, index as u32, data))
+                .unwrap();
+        }
+        let commit = SetSpendTxCommit { upload_id };
+        assert!(uploads.commit(&other_peer, &commit).is_err());
+        assert_eq!(uploads.commit(&peer, &commit).unwrap(), msg);
+        assert!(uploads.is_empty());
+        assert!(uploads.commit(&peer, &commit).is_err());
+
+        // An incomplete or tampered with upload is refused
+        let UploadStarted { upload_id } = uploads.begin(&peer, begin.clone()).unwrap();
+        uploads
+            .chunk(&peer, chunk(upload_id, 0, &chunks[0]))
+            .unwrap();
+        assert!(matches!(
+            uploads.commit(&peer, &SetSpendTxCommit { upload_id }),
+            Err(Error::Message(MessageError::CorruptedUpload(_)))
+        ));
+        let UploadStarted { upload_id } = uploads.begin(&peer, begin.clone()).unwrap();
+        let mut tampered = chunks.clone();
+        tampered[0].0[0] ^= 1;
+        for (index, data) in tampered.iter().enumerate() {
+            uploads
+                .chunk(&peer, chunk(upload_id, index as u32, data))
+                .unwrap();
+        }
+        assert!(matches!(
+            uploads.commit(&peer, &SetSpendTxCommit { upload_id }),
+            Err(Error::Message(MessageError::CorruptedUpload(_)))
+        ));
+
+        // Too large, and too many
+        let uploads = Uploads::new().with_max_size(1_000).with_max_pending(2);
+        assert!(matches!(
+            uploads.begin(&peer, begin),
+            Err(MessageError::TooLargeUpload { .. })
+        ));
+        let (begin, _) = set_spend_tx(1).to_chunks(1_000);
+        let first = uploads.begin(&peer, begin.clone()).unwrap();
+        uploads.begin(&peer, begin.clone()).unwrap();
+        uploads.begin(&other_peer, begin.clone()).unwrap();
+        uploads.begin(&peer, begin).unwrap();
+        assert_eq!(uploads.len(), 3);
+        assert!(uploads
+            .commit(
+                &peer,
+                &SetSpendTxCommit {
+                    upload_id: first.upload_id
+                }
+            )
+            .is_err());
+    }
+}
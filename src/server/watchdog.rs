@@ -0,0 +1,254 @@
+//! Stale connections
+//!
+//! A [Watchdog] tracks the last activity of each connection it watches and closes the
+//! ones silent for longer than its threshold, so that the peers that vanished without
+//! closing their connection (a common occurrence over Tor) don't hold a file
+//! descriptor and a thread forever. It is used by
+//! [listen_watched](crate::server::listen_watched).
+
+use crate::{
+    error::Error,
+    instrument::{self, log_warn},
+    noise::PublicKey,
+    transport::KKTransport,
+};
+
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// A connection being watched
+#[derive(Debug)]
+struct Watched {
+    peer: PublicKey,
+    last_activity: Instant,
+    // A handle to the stream, to shut it down
+    stream: TcpStream,
+}
+
+/// Closes the connections silent for longer than a threshold.
+///
+/// A connection is watched for as long as the [WatchGuard] returned by [Watchdog::watch]
+/// is alive. The stale connections are closed by [Watchdog::reap], which can be called
+/// periodically in the background by [Watchdog::spawn].
+pub struct Watchdog {
+    idle: Duration,
+    next_id: AtomicU64,
+    connections: Mutex<HashMap<u64, Watched>>,
+}
+
+impl fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watchdog")
+            .field("idle", &self.idle)
+            .field("connections", &self.len())
+            .finish()
+    }
+}
+
+impl Watchdog {
+    /// A watchdog closing the connections silent for longer than `idle`
+    pub fn new(idle: Duration) -> Self {
+        Self {
+            idle,
+            next_id: AtomicU64::new(0),
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long a connection may be silent for
+    pub fn idle(&self) -> Duration {
+        self.idle
+    }
+
+    /// Start watching this connection. It is watched until the returned guard is
+    /// dropped, and its activity can be reported through it.
+    pub fn watch(self: &Arc<Self>, transport: &KKTransport) -> Result<WatchGuard, Error> {
+        let stream = transport.try_clone_stream()?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.connections
+            .lock()
+            .expect("Watchdog lock poisoned")
+            .insert(
+                id,
+                Watched {
+                    peer: transport.remote_static(),
+                    last_activity: Instant::now(),
+                    stream,
+                },
+            );
+
+        Ok(WatchGuard {
+            watchdog: self.clone(),
+            id,
+        })
+    }
+
+    /// The number of connections being watched
+    pub fn len(&self) -> usize {
+        self.connections
+            .lock()
+            .expect("Watchdog lock poisoned")
+            .len()
+    }
+
+    /// Whether no connection is being watched
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Close the connections silent for longer than the threshold. Returns the number
+    /// of connections closed.
+    pub fn reap(&self) -> usize {
+        self.reap_at(Instant::now())
+    }
+
+    fn reap_at(&self, now: Instant) -> usize {
+        let mut connections = self.connections.lock().expect("Watchdog lock poisoned");
+        let idle = self.idle;
+        let before = connections.len();
+        connections.retain(|_, watched| {
+            let silent = now.saturating_duration_since(watched.last_activity);
+            if silent <= idle {
+                return true;
+            }
+
+            log_warn!(
+                "Closing the connection of '{:?}', silent for {:?}",
+                watched.peer,
+                silent
+            );
+            // It might have been closed already
+            let _ = watched.stream.shutdown(Shutdown::Both);
+            instrument::connection_closed("stale");
+            false
+        });
+
+        before - connections.len()
+    }
+
+    /// Close the stale connections every `interval` on a background thread, until the
+    /// watchdog is dropped.
+    pub fn spawn(self: &Arc<Self>, interval: Duration) -> io::Result<thread::JoinHandle<()>> {
+        let watchdog = Arc::downgrade(self);
+        thread::Builder::new()
+            .name("revault_net watchdog".to_string())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                match watchdog.upgrade() {
+                    Some(watchdog) => {
+                        watchdog.reap();
+                    }
+                    None => return,
+                }
+            })
+    }
+}
+
+/// A connection watched by a [Watchdog], until this is dropped
+#[derive(Debug)]
+pub struct WatchGuard {
+    watchdog: Arc<Watchdog>,
+    id: u64,
+}
+
+impl WatchGuard {
+    /// Report some activity on the connection
+    pub fn touch(&self) {
+        let mut connections = self
+            .watchdog
+            .connections
+            .lock()
+            .expect("Watchdog lock poisoned");
+        if let Some(watched) = connections.get_mut(&self.id) {
+            watched.last_activity = Instant::now();
+        }
+    }
+}
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        self.watchdog
+            .connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::local_endpoints,
+        message::{
+            coordinator::{GetSigs, Sigs},
+            Request, ResponseResult, SigSet,
+        },
+        server::listen_watched,
+    };
+
+    use bitcoin::Txid;
+
+    #[test]
+    fn watchdog() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let watchdog = Arc::new(Watchdog::new(Duration::from_millis(300)));
+        let server_watchdog = watchdog.clone();
+        thread::spawn(move || {
+            let handler = |_: &PublicKey, _| {
+                Some(ResponseResult::Sigs(Sigs {
+                    signatures: SigSet::new(),
+                }))
+            };
+            listen_watched(
+                &listener,
+                &server_privkey,
+                &[client_pubkey],
+                Arc::new(handler),
+                server_watchdog,
+            )
+            .unwrap();
+        });
+
+        let get_sigs = Request::from(GetSigs {
+            id: Txid::default(),
+            if_none_match: None,
+        });
+        let mut transport = KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        let _: Sigs = transport.send_req(&get_sigs).unwrap();
+        assert_eq!(watchdog.len(), 1);
+
+        // An active connection is kept
+        assert_eq!(watchdog.reap(), 0);
+        thread::sleep(Duration::from_millis(200));
+        let _: Sigs = transport.send_req(&get_sigs).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(watchdog.reap(), 0);
+
+        // A silent one is closed
+        assert_eq!(watchdog.reap_at(Instant::now() + Duration::from_secs(1)), 1);
+        assert!(transport.send_req::<Sigs>(&get_sigs).is_err());
+        // The serving thread is gone along with its guard
+        thread::sleep(Duration::from_millis(100));
+        assert!(watchdog.is_empty());
+
+        // In the background
+        let handle = watchdog.spawn(Duration::from_millis(50)).unwrap();
+        let mut transport = KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        let _: Sigs = transport.send_req(&get_sigs).unwrap();
+        thread::sleep(Duration::from_millis(600));
+        assert!(transport.send_req::<Sigs>(&get_sigs).is_err());
+        drop(handle);
+    }
+}
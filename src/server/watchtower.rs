@@ -0,0 +1,536 @@
+//! Watchtower server
+//!
+//! The message flow of a watchtower, receiving the revocation signatures for the
+//! vaults of the stakeholders and acknowledging them. Whether to guard a vault, and
+//! how to store its signatures, is up to the user through the [RevocationStore] trait.
+//! A watchtower may also tell the managers the spending policy it enforces, and give
+//! back to a stakeholder the signatures it holds for a vault (for instance after the
+//! stakeholder restored its wallet from seed), reconcile with the other watchtowers
+//! of the stakeholder, and approve or reject the Spend transactions of the managers.
+
+use crate::{
+    instrument::{log_error, log_warn},
+    message::{watchtower, ErrorCode, RequestParams, ResponseError, ResponseResult},
+    noise::PublicKey,
+    server::{Reply, RequestHandler},
+};
+
+use bitcoin::OutPoint;
+use std::{convert::Infallible, fmt};
+
+/// The policy and storage backend of a watchtower.
+pub trait RevocationStore {
+    /// The error returned by the backend
+    type Error: fmt::Display;
+
+    /// Decide whether to guard a vault with the revocation signatures shared by the
+    /// stakeholder with this static Noise key, and store them if so. Returns `true` if
+    /// the vault is going to be guarded.
+    fn store_revocation_sigs(
+        &self,
+        peer: &PublicKey,
+        sig: watchtower::Sig,
+    ) -> Result<bool, Self::Error>;
+
+    /// The revocation signatures stored for this vault of the stakeholder with this
+    /// static Noise key, one [watchtower::Sig] per revocation transaction. The default
+    /// implementation doesn't keep them and returns none.
+    fn get_revocation_sigs(
+        &self,
+        peer: &PublicKey,
+        deposit_outpoint: &OutPoint,
+    ) -> Result<Vec<watchtower::Sig>, Self::Error> {
+        let _ = (peer, deposit_outpoint);
+        Ok(Vec::new())
+    }
+
+    /// Reconcile with the other watchtower of the same stakeholder with this static
+    /// Noise key, which holds these revocation signatures: store those we are missing
+    /// for the vaults we guard, and return those it is missing. The default
+    /// implementation doesn't sync, and returns `None` for the request to be refused.
+    fn sync_vaults(
+        &self,
+        peer: &PublicKey,
+        vaults: Vec<watchtower::Sig>,
+    ) -> Result<Option<Vec<watchtower::Sig>>, Self::Error> {
+        let _ = (peer, vaults);
+        Ok(None)
+    }
+
+    /// Decide whether to approve the Spend transaction submitted by the manager with
+    /// this static Noise key, committing to not cancel the Unvaults for it if so. The
+    /// default implementation doesn't review Spends, and returns `None` for the request
+    /// to be refused.
+    fn approve_spend(
+        &self,
+        peer: &PublicKey,
+        approve: watchtower::ApproveSpend,
+    ) -> Result<Option<watchtower::SpendApproval>, Self::Error> {
+        let _ = (peer, approve);
+        Ok(None)
+    }
+}
+
+impl<F> RevocationStore for F
+where
+    F: Fn(&PublicKey, watchtower::Sig) -> bool,
+{
+    type Error = Infallible;
+
+    fn store_revocation_sigs(
+        &self,
+        peer: &PublicKey,
+        sig: watchtower::Sig,
+    ) -> Result<bool, Self::Error> {
+        Ok(self(peer, sig))
+    }
+}
+
+/// A watchtower, handling the `sig` and `recover_sigs` requests of the stakeholders,
+/// the `approve_spend` requests of the managers, and the `sync_vaults` requests of the
+/// other watchtowers, using this backend.
+///
+/// The stakeholder is answered with an acknowledgement for the revocation transaction
+/// it shared the signatures of. The acknowledgement is negative if the backend refused
+/// to guard the vault or returned an error, since the stakeholder must not assume its
+/// vault is guarded in this case. Likewise, a Spend is rejected if the backend returned
+/// an error reviewing it.
+#[derive(Debug)]
+pub struct Watchtower<S> {
+    store: S,
+    spend_policy: Option<watchtower::SpendPolicy>,
+}
+
+impl<S: RevocationStore> Watchtower<S> {
+    /// A watchtower using this backend
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            spend_policy: None,
+        }
+    }
+
+    /// Respond to the `get_spend_policy` requests with this policy. They are refused
+    /// otherwise.
+    pub fn with_spend_policy(mut self, spend_policy: watchtower::SpendPolicy) -> Self {
+        self.spend_policy = Some(spend_policy);
+        self
+    }
+
+    /// Get the backend of this watchtower
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+}
+
+impl<S: RevocationStore> RequestHandler for Watchtower<S> {
+    fn handle(&self, peer: &PublicKey, params: RequestParams) -> Option<ResponseResult> {
+        self.reply(peer, 0, params).and_then(Result::ok)
+    }
+
+    fn reply(&self, peer: &PublicKey, _id: u32, params: RequestParams) -> Option<Reply> {
+        let sig = match params {
+            RequestParams::WtSig(sig) => sig,
+            RequestParams::WtGetSpendPolicy(_) if self.spend_policy.is_some() => {
+                return self
+                    .spend_policy
+                    .clone()
+                    .map(|policy| Ok(ResponseResult::WtSpendPolicy(policy)));
+            }
+            RequestParams::WtRecoverSigs(watchtower::RecoverSigs { deposit_outpoint }) => {
+                return match self.store.get_revocation_sigs(peer, &deposit_outpoint) {
+                    Ok(sigs) => Some(Ok(ResponseResult::WtRecoveredSigs(
+                        watchtower::RecoveredSigs { sigs },
+                    ))),
+                    Err(e) => {
+                        log_error!(
+                            "Error getting revocation signatures for '{}': '{}'",
+                            deposit_outpoint,
+                            e
+                        );
+                        Some(Err(ResponseError::new(
+                            ErrorCode::InternalError,
+                            "Storage error",
+                        )))
+                    }
+                };
+            }
+            RequestParams::WtSyncVaults(watchtower::SyncVaults { vaults }) => {
+                return match self.store.sync_vaults(peer, vaults) {
+                    Ok(Some(missing)) => Some(Ok(ResponseResult::WtSyncVaults(
+                        watchtower::SyncVaultsResult { missing },
+                    ))),
+                    Ok(None) => Some(Err(ResponseError::new(
+                        ErrorCode::MethodNotFound,
+                        "This watchtower doesn't sync its vaults",
+                    ))),
+                    Err(e) => {
+                        log_error!("Error syncing vaults: '{}'", e);
+                        Some(Err(ResponseError::new(
+                            ErrorCode::InternalError,
+                            "Storage error",
+                        )))
+                    }
+                };
+            }
+            RequestParams::WtApproveSpend(approve) => {
+                let txid = approve.spend_tx.txid();
+                return match self.store.approve_spend(peer, approve) {
+                    Ok(Some(approval)) => Some(Ok(ResponseResult::WtSpendApproval(approval))),
+                    Ok(None) => Some(Err(ResponseError::new(
+                        ErrorCode::MethodNotFound,
+                        "This watchtower doesn't review Spend transactions",
+                    ))),
+                    Err(e) => {
+                        log_error!("Error reviewing Spend transaction '{}': '{}'", txid, e);
+                        Some(Ok(ResponseResult::WtSpendApproval(
+                            watchtower::SpendApproval::reject(txid, "Internal error"),
+                        )))
+                    }
+                };
+            }
+            params => {
+                log_warn!(
+                    "Refusing request not handled by the watchtower: {:?}",
+                    params
+                );
+                return Some(Err(ResponseError::new(
+                    ErrorCode::MethodNotFound,
+                    format!("'{}' is not a watchtower method", params.method()),
+                )));
+            }
+        };
+
+        let txid = sig.txid;
+        let ack = match self.store.store_revocation_sigs(peer, sig) {
+            Ok(ack) => ack,
+            Err(e) => {
+                log_error!(
+                    "Error storing revocation signatures for '{}': '{}'",
+                    txid,
+                    e
+                );
+                false
+            }
+        };
+
+        Some(Ok(ResponseResult::WtSig(watchtower::SigResult {
+            ack,
+            txid,
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::WatchtowerClient, fixtures::secp_sig, server::listen};
+
+    use crate::noise::gen_keypair;
+    use bitcoin::{hashes::Hash, OutPoint, Txid};
+    use std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    #[test]
+    fn watchtower_flow() {
+        let (pubkey, signature) = secp_sig();
+        let sig = watchtower::Sig {
+            signatures: [(pubkey, signature)].iter().cloned().collect(),
+            txid: Txid::default(),
+            deposit_outpoint: OutPoint::default(),
+        };
+
+        let ((stk_pubkey, stk_privkey), (other_pubkey, _), (wt_pubkey, wt_privkey)) =
+            (gen_keypair(), gen_keypair(), gen_keypair());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Only guard the vaults of a single stakeholder, and remember them
+        let guarded = Arc::new(Mutex::new(Vec::new()));
+        let store_guarded = guarded.clone();
+        let spend_policy = watchtower::SpendPolicy {
+            max_daily_amount: Some(100_000_000),
+            allowed_destinations: vec![],
+            unvault_csv: Some(144),
+        };
+        let watchtower = Watchtower::new(move |peer: &PublicKey, sig: watchtower::Sig| {
+            if peer != &stk_pubkey {
+                return false;
+            }
+            store_guarded.lock().unwrap().push(sig.deposit_outpoint);
+            true
+        })
+        .with_spend_policy(spend_policy.clone());
+        thread::spawn(move || {
+            listen(
+                &listener,
+                &wt_privkey,
+                &[stk_pubkey, other_pubkey],
+                Arc::new(watchtower),
+            )
+        });
+
+        let mut client = WatchtowerClient::connect(addr, &stk_privkey, &wt_pubkey).unwrap();
+        client.share_revocation_sigs(sig.clone()).unwrap();
+        assert_eq!(*guarded.lock().unwrap(), vec![OutPoint::default()]);
+        assert_eq!(client.get_spend_policy().unwrap(), spend_policy);
+        // This backend doesn't keep the signatures to give them back
+        assert!(client
+            .recover_revocation_sigs(OutPoint::default())
+            .unwrap()
+            .is_empty());
+
+        // Unknown peers can't connect, and a refused vault is not acknowledged
+        let (_, unknown_privkey) = gen_keypair();
+        assert!(WatchtowerClient::connect(addr, &unknown_privkey, &wt_pubkey).is_err());
+        let mut client = WatchtowerClient::connect(addr, &stk_privkey, &wt_pubkey).unwrap();
+        client.share_revocation_sigs(sig.clone()).unwrap();
+        let watchtower = Watchtower::new(|_: &PublicKey, _| false);
+        assert_eq!(
+            watchtower.handle(&other_pubkey, RequestParams::WtSig(sig.clone())),
+            Some(ResponseResult::WtSig(watchtower::SigResult {
+                ack: false,
+                txid: sig.txid
+            }))
+        );
+    }
+
+    // A backend which can't reach its storage
+    struct BrokenStore;
+
+    impl RevocationStore for BrokenStore {
+        type Error = &'static str;
+
+        fn store_revocation_sigs(
+            &self,
+            _: &PublicKey,
+            _: watchtower::Sig,
+        ) -> Result<bool, Self::Error> {
+            Err("unreachable database")
+        }
+
+        fn get_revocation_sigs(
+            &self,
+            _: &PublicKey,
+            _: &OutPoint,
+        ) -> Result<Vec<watchtower::Sig>, Self::Error> {
+            Err("unreachable database")
+        }
+
+        fn approve_spend(
+            &self,
+            _: &PublicKey,
+            _: watchtower::ApproveSpend,
+        ) -> Result<Option<watchtower::SpendApproval>, Self::Error> {
+            Err("unreachable database")
+        }
+    }
+
+    fn approve_spend() -> watchtower::ApproveSpend {
+        watchtower::ApproveSpend {
+            deposit_outpoints: vec![OutPoint::default()],
+            spend_tx: bitcoin::Transaction {
+                version: 2,
+                lock_time: 0,
+                input: vec![Default::default()],
+                output: vec![Default::default()],
+            },
+        }
+    }
+
+    #[test]
+    fn refused_requests() {
+        let (peer, _) = gen_keypair();
+        let watchtower = Watchtower::new(BrokenStore);
+        let params = RequestParams::WtRecoverSigs(watchtower::RecoverSigs {
+            deposit_outpoint: OutPoint::default(),
+        });
+        let reply = watchtower.reply(&peer, 0, params);
+        assert_eq!(reply.unwrap().unwrap_err().code, ErrorCode::InternalError);
+        // A Spend we could not review is not approved
+        let approve = approve_spend();
+        let txid = approve.spend_tx.txid();
+        assert_eq!(
+            watchtower.handle(&peer, RequestParams::WtApproveSpend(approve)),
+            Some(ResponseResult::WtSpendApproval(
+                watchtower::SpendApproval::reject(txid, "Internal error")
+            ))
+        );
+
+        // We don't tell a spending policy we don't have, nor answer the requests meant
+        // to a coordinator
+        let reply = watchtower.reply(
+            &peer,
+            0,
+            RequestParams::WtGetSpendPolicy(watchtower::GetSpendPolicy {}),
+        );
+        assert_eq!(reply.unwrap().unwrap_err().code, ErrorCode::MethodNotFound);
+        let params = RequestParams::GetTime(crate::message::coordinator::GetTime {});
+        let reply = watchtower.reply(&peer, 0, params.clone());
+        assert_eq!(reply.unwrap().unwrap_err().code, ErrorCode::MethodNotFound);
+        assert_eq!(watchtower.handle(&peer, params), None);
+
+        // Nor sync, or review Spends, if the backend doesn't
+        let params = RequestParams::WtSyncVaults(watchtower::SyncVaults { vaults: vec![] });
+        let reply = watchtower.reply(&peer, 0, params);
+        assert_eq!(reply.unwrap().unwrap_err().code, ErrorCode::MethodNotFound);
+        let watchtower = Watchtower::new(|_: &PublicKey, _| true);
+        let params = RequestParams::WtApproveSpend(approve_spend());
+        let reply = watchtower.reply(&peer, 0, params);
+        assert_eq!(reply.unwrap().unwrap_err().code, ErrorCode::MethodNotFound);
+    }
+
+    // A backend approving the Spends of the vaults it guards
+    struct ApprovingStore(Vec<OutPoint>);
+
+    impl RevocationStore for ApprovingStore {
+        type Error = Infallible;
+
+        fn store_revocation_sigs(
+            &self,
+            _: &PublicKey,
+            _: watchtower::Sig,
+        ) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+
+        fn approve_spend(
+            &self,
+            _: &PublicKey,
+            approve: watchtower::ApproveSpend,
+        ) -> Result<Option<watchtower::SpendApproval>, Self::Error> {
+            let txid = approve.spend_tx.txid();
+            Ok(Some(
+                if approve
+                    .deposit_outpoints
+                    .iter()
+                    .all(|outpoint| self.0.contains(outpoint))
+                {
+                    watchtower::SpendApproval::approve(txid)
+                } else {
+                    watchtower::SpendApproval::reject(txid, "Unknown vault")
+                },
+            ))
+        }
+    }
+
+    #[test]
+    fn spend_approval() {
+        let ((manager_pubkey, manager_privkey), (wt_pubkey, wt_privkey)) =
+            (gen_keypair(), gen_keypair());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let watchtower = Watchtower::new(ApprovingStore(vec![OutPoint::default()]));
+        thread::spawn(move || {
+            listen(
+                &listener,
+                &wt_privkey,
+                &[manager_pubkey],
+                Arc::new(watchtower),
+            )
+        });
+
+        let mut client = WatchtowerClient::connect(addr, &manager_privkey, &wt_pubkey).unwrap();
+        let approve = approve_spend();
+        let txid = approve.spend_tx.txid();
+        let approval = client
+            .approve_spend(approve.deposit_outpoints.clone(), approve.spend_tx.clone())
+            .unwrap();
+        assert_eq!(approval, watchtower::SpendApproval::approve(txid));
+
+        let unknown = OutPoint {
+            vout: 1,
+            ..OutPoint::default()
+        };
+        let approval = client
+            .approve_spend(vec![unknown], approve.spend_tx)
+            .unwrap();
+        assert_eq!(
+            approval,
+            watchtower::SpendApproval::reject(txid, "Unknown vault")
+        );
+    }
+
+    // A backend guarding all the vaults it's told about
+    #[derive(Default)]
+    struct SyncingStore {
+        sigs: Mutex<Vec<watchtower::Sig>>,
+    }
+
+    impl RevocationStore for SyncingStore {
+        type Error = Infallible;
+
+        fn store_revocation_sigs(
+            &self,
+            _: &PublicKey,
+            sig: watchtower::Sig,
+        ) -> Result<bool, Self::Error> {
+            self.sigs.lock().unwrap().push(sig);
+            Ok(true)
+        }
+
+        fn sync_vaults(
+            &self,
+            _: &PublicKey,
+            vaults: Vec<watchtower::Sig>,
+        ) -> Result<Option<Vec<watchtower::Sig>>, Self::Error> {
+            let mut sigs = self.sigs.lock().unwrap();
+            let missing = sigs
+                .iter()
+                .filter(|sig| !vaults.iter().any(|theirs| theirs.txid == sig.txid))
+                .cloned()
+                .collect();
+            for sig in vaults {
+                if !sigs.iter().any(|ours| ours.txid == sig.txid) {
+                    sigs.push(sig);
+                }
+            }
+            Ok(Some(missing))
+        }
+    }
+
+    #[test]
+    fn vaults_sync() {
+        let (pubkey, signature) = secp_sig();
+        let sig = |txid: Txid| watchtower::Sig {
+            signatures: [(pubkey, signature)].iter().cloned().collect(),
+            txid,
+            deposit_outpoint: OutPoint::default(),
+        };
+        let (ours, theirs) = (
+            sig(Txid::default()),
+            sig(Txid::from_slice(&[1; 32]).unwrap()),
+        );
+        let (stakeholder, _) = gen_keypair();
+        let (other_watchtower, _) = gen_keypair();
+
+        let watchtower = Watchtower::new(SyncingStore::default());
+        watchtower.handle(&stakeholder, RequestParams::WtSig(ours.clone()));
+        let params = RequestParams::WtSyncVaults(watchtower::SyncVaults {
+            vaults: vec![theirs.clone()],
+        });
+        assert_eq!(
+            watchtower.handle(&other_watchtower, params.clone()),
+            Some(ResponseResult::WtSyncVaults(watchtower::SyncVaultsResult {
+                missing: vec![ours]
+            }))
+        );
+        assert_eq!(
+            *watchtower.store().sigs.lock().unwrap(),
+            vec![sig(Txid::default()), theirs]
+        );
+        // Once in sync, there is nothing missing anymore
+        let params = RequestParams::WtSyncVaults(watchtower::SyncVaults {
+            vaults: watchtower.store().sigs.lock().unwrap().clone(),
+        });
+        assert_eq!(
+            watchtower.handle(&other_watchtower, params),
+            Some(ResponseResult::WtSyncVaults(watchtower::SyncVaultsResult {
+                missing: vec![]
+            }))
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! WebSocket gateway
+//!
+//! Browsers can't open TCP connections, and some deployments only let WebSocket
+//! traffic through their reverse proxies. [serve_websocket] accepts WebSocket
+//! connections and forwards the bytes carried by their binary messages to the TCP
+//! listener of the server, and back. The Noise channel is enacted end to end through
+//! it: it's the counterpart of the browsers' `WsTransport` (see the `websocket`
+//! feature) and neither it nor the proxies need to be trusted.
+//!
+//! Only the subset of [RFC 6455](https://www.rfc-editor.org/rfc/rfc6455) used by such
+//! clients is implemented: binary (and continuation) messages, pings and closes.
+//! Extensions and subprotocols are not negotiated.
+
+use crate::{error::Error, instrument::log_warn, server::accept_backing_off};
+
+use bitcoin::hashes::{sha1, Hash};
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+// Appended to the key of the client to compute our accept key
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// The maximum size of the upgrade request of a client
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+// The opcodes of the frames
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// The accept key answering the key of a client
+fn accept_key(key: &str) -> String {
+    let hash = sha1::Hash::hash(format!("{}{}", key, GUID).as_bytes());
+    base64::encode(&hash[..])
+}
+
+// Read the upgrade request of a client and accept it
+fn upgrade(stream: &mut TcpStream) -> io::Result<()> {
+    let mut request = Vec::with_capacity(512);
+    let mut byte = [0u8; 1];
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() == MAX_REQUEST_SIZE {
+            return Err(invalid("Upgrade request too large"));
+        }
+        stream.read_exact(&mut byte)?;
+        request.push(byte[0]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut lines = request.split("\r\n");
+    if !lines
+        .next()
+        .map(|line| line.starts_with("GET "))
+        .unwrap_or(false)
+    {
+        return Err(invalid("Not a GET request"));
+    }
+    let key = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("Sec-WebSocket-Key"))
+        .map(|(_, value)| value.trim())
+        .ok_or_else(|| invalid("No WebSocket key"))?;
+
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    )
+}
+
+// Write an unmasked frame, as a server does
+fn write_frame<W: Write>(writer: &mut W, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut header = Vec::with_capacity(10);
+    header.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => header.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            header.push(126);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            header.push(127);
+            header.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    writer.write_all(&header)?;
+    writer.write_all(payload)
+}
+
+// Read a frame sent by a client, returning its opcode and unmasked payload
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 2];
+    reader.read_exact(&mut header)?;
+    let opcode = header[0] & 0x0F;
+    if header[1] & 0x80 == 0 {
+        return Err(invalid("Unmasked client frame"));
+    }
+    let len = match header[1] & 0x7F {
+        126 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0u8; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    // Noise messages are at most 65K, and so is what we forward at once
+    if len > u16::MAX as u64 {
+        return Err(invalid("Frame too large"));
+    }
+
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask)?;
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok((opcode, payload))
+}
+
+// Forward the messages of the client to the server, until it closes
+fn forward_from_client(
+    mut client: TcpStream,
+    client_writer: &Mutex<TcpStream>,
+    mut server: TcpStream,
+) -> io::Result<()> {
+    loop {
+        let (opcode, payload) = read_frame(&mut client)?;
+        match opcode {
+            BINARY | CONTINUATION => server.write_all(&payload)?,
+            PING => {
+                let mut writer = client_writer.lock().unwrap_or_else(|e| e.into_inner());
+                write_frame(&mut *writer, PONG, &payload)?;
+            }
+            PONG => {}
+            CLOSE => {
+                let mut writer = client_writer.lock().unwrap_or_else(|e| e.into_inner());
+                // The close might cross one we are sending
+                let _ = write_frame(&mut *writer, CLOSE, &payload);
+                return Ok(());
+            }
+            TEXT => return Err(invalid("Unexpected text message")),
+            _ => return Err(invalid("Unknown opcode")),
+        }
+    }
+}
+
+// Forward the bytes of the server to the client, until it closes
+fn forward_from_server(client_writer: &Mutex<TcpStream>, mut server: TcpStream) -> io::Result<()> {
+    let mut buf = vec![0u8; u16::MAX as usize];
+    loop {
+        let read = server.read(&mut buf)?;
+        let mut writer = client_writer.lock().unwrap_or_else(|e| e.into_inner());
+        if read == 0 {
+            return write_frame(&mut *writer, CLOSE, &[]);
+        }
+        write_frame(&mut *writer, BINARY, &buf[..read])?;
+    }
+}
+
+/// Accept the WebSocket upgrade of a client on this connection, and forward its
+/// messages to the server at this address (and the server's bytes back) until either
+/// end closes the connection.
+pub fn forward(mut client: TcpStream, upstream: SocketAddr) -> Result<(), Error> {
+    upgrade(&mut client)?;
+    let server = TcpStream::connect(upstream)?;
+
+    let client_writer = Arc::new(Mutex::new(client.try_clone()?));
+    let (writer, server_reader) = (client_writer.clone(), server.try_clone()?);
+    let from_server = thread::Builder::new()
+        .name("revault_net websocket".to_string())
+        .spawn(move || forward_from_server(&writer, server_reader))?;
+
+    let res = forward_from_client(client.try_clone()?, &client_writer, server.try_clone()?);
+    // Either end is done, tear down both
+    let _ = server.shutdown(Shutdown::Both);
+    let _ = client.shutdown(Shutdown::Both);
+    let _ = from_server.join();
+
+    res.map_err(Error::from)
+}
+
+/// Accept WebSocket connections on this listener, and forward each of them on its own
+/// thread to the server at this address, see [forward]. A failed connection is logged
+/// and dropped. Only returns on a failure to spawn a thread.
+pub fn serve_websocket(listener: &TcpListener, upstream: SocketAddr) -> Result<(), Error> {
+    loop {
+        let client = accept_backing_off(listener, "WebSocket connection");
+
+        thread::Builder::new()
+            .name("revault_net websocket".to_string())
+            .spawn(move || {
+                if let Err(e) = forward(client, upstream) {
+                    log_warn!("Error forwarding WebSocket connection: '{}'", e);
+                }
+            })?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Write a masked frame, as a client does
+    fn write_client_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8]) {
+        let mask = [1u8, 2, 3, 4];
+        let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        stream.write_all(&frame).unwrap();
+    }
+
+    // Read an unmasked frame, as a client does
+    fn read_server_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).unwrap();
+        let mut payload = vec![0u8; (header[1] & 0x7F) as usize];
+        stream.read_exact(&mut payload).unwrap();
+        (header[0] & 0x0F, payload)
+    }
+
+    #[test]
+    fn websocket_gateway() {
+        // From the RFC
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        // An echo server behind the gateway
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            loop {
+                match stream.read(&mut buf).unwrap() {
+                    0 => return,
+                    n => stream.write_all(&buf[..n]).unwrap(),
+                }
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve_websocket(&listener, upstream_addr).unwrap());
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nsec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        let mut byte = [0u8; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            client.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101"));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        write_client_frame(&mut client, BINARY, b"hello");
+        assert_eq!(read_server_frame(&mut client), (BINARY, b"hello".to_vec()));
+        write_client_frame(&mut client, PING, b"ping");
+        assert_eq!(read_server_frame(&mut client), (PONG, b"ping".to_vec()));
+        write_client_frame(&mut client, CLOSE, &[]);
+        assert_eq!(read_server_frame(&mut client).0, CLOSE);
+
+        // Not a WebSocket client
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"POST / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(client.read_to_end(&mut buf).unwrap_or(0), 0);
+    }
+}
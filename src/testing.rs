@@ -0,0 +1,594 @@
+//! Test doubles
+//!
+//! Mock servers of the Revault network, for the functional tests of the wallets to not
+//! need to run the actual servers. A [MockServer] listens on a local port, performs the
+//! responder handshake with the clients it is started for, and answers their requests
+//! according to a script: canned responses, assertions and delays per method. The
+//! requests that are not scripted are handled by the actual message flow of the server
+//! it mocks (see [crate::server]), on a backend kept in memory.
+//!
+//! Connections to misbehave as a real network would can be wrapped in a
+//! [ChaosTransport].
+//!
+//! Examples of messages from the specification can be checked against this crate with
+//! the [test vectors](load_vectors) facility, and running servers with the
+//! [conformance checker](check_peer).
+//!
+//! Races between many peers can be reproduced in a [simulated network](sim).
+//!
+//! This module is available behind the `testing` feature.
+
+use crate::{
+    message::{watchtower, RequestParams, ResponseResult},
+    noise::{PublicKey, SecretKey},
+    server::{
+        self,
+        coordinator::{Coordinator, MemoryStorage},
+        cosigner::{Cosigner, MemoryOutpointStore, PrivateKeySigner, SigningBackend},
+        watchtower::{RevocationStore, Watchtower},
+        Reply, RequestHandler,
+    },
+};
+
+use revault_tx::{
+    bitcoin::{secp256k1, OutPoint},
+    transactions::SpendTransaction,
+};
+
+use crate::noise::gen_keypair;
+
+mod chaos;
+mod conformance;
+pub mod sim;
+mod vectors;
+pub use chaos::{ChaosSchedule, ChaosTransport, Fault};
+pub use conformance::{check_peer, Check, ConformanceReport, Outcome};
+pub use vectors::{load_vectors, TestVector, VectorError, VectorKind};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    io,
+    net::{SocketAddr, TcpListener},
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+// An assertion on the parameters of a request
+type Assertion = Box<dyn Fn(&RequestParams) + Send>;
+
+// What a mock server does with the requests it receives
+#[derive(Default)]
+struct Script {
+    // Responses to send, in order, per method. `None` not to respond.
+    replies: HashMap<&'static str, VecDeque<Option<ResponseResult>>>,
+    assertions: HashMap<&'static str, Vec<Assertion>>,
+    delays: HashMap<&'static str, Duration>,
+    // One-off delays, used in order before `delays`
+    next_delays: HashMap<&'static str, VecDeque<Duration>>,
+    received: Vec<(PublicKey, RequestParams)>,
+    // The messages of the assertions that failed
+    failures: Vec<String>,
+}
+
+struct MockHandler<H> {
+    script: Mutex<Script>,
+    backend: H,
+}
+
+impl<H> MockHandler<H> {
+    fn script(&self) -> std::sync::MutexGuard<'_, Script> {
+        // An assertion panicking doesn't poison it, but a test thread might
+        self.script.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<H: RequestHandler> RequestHandler for MockHandler<H> {
+    fn handle(&self, peer: &PublicKey, params: RequestParams) -> Option<ResponseResult> {
+        self.reply(peer, 0, params).and_then(Result::ok)
+    }
+
+    fn reply(&self, peer: &PublicKey, id: u32, params: RequestParams) -> Option<Reply> {
+        let method = params.method();
+
+        let (reply, delay) = {
+            let mut script = self.script();
+            script.received.push((*peer, params.clone()));
+
+            let mut failures = Vec::new();
+            for assertion in script.assertions.get(method).into_iter().flatten() {
+                if let Err(e) = panic::catch_unwind(AssertUnwindSafe(|| assertion(&params))) {
+                    let msg = e
+                        .downcast_ref::<String>()
+                        .cloned()
+                        .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+                        .unwrap_or_else(|| "assertion failed".to_string());
+                    failures.push(format!("'{}' request: {}", method, msg));
+                }
+            }
+            script.failures.extend(failures);
+
+            let reply = script
+                .replies
+                .get_mut(method)
+                .and_then(|replies| replies.pop_front());
+            let delay = script
+                .next_delays
+                .get_mut(method)
+                .and_then(|delays| delays.pop_front())
+                .or_else(|| script.delays.get(method).copied());
+            (reply, delay)
+        };
+
+        if let Some(delay) = delay {
+            thread::sleep(delay);
+        }
+        match reply {
+            Some(reply) => reply.map(Ok),
+            None => self.backend.reply(peer, id, params),
+        }
+    }
+}
+
+/// A scripted server, answering the requests it isn't scripted for with the `H`
+/// handler.
+///
+/// It serves each connection on its own thread, which stays up until the end of the
+/// test process.
+pub struct MockServer<H> {
+    addr: SocketAddr,
+    noise_pubkey: PublicKey,
+    handler: Arc<MockHandler<H>>,
+}
+
+impl<H: RequestHandler + Send + Sync + 'static> MockServer<H> {
+    /// Start a mock server with its own Noise key, accepting the connections of the
+    /// peers with these static public keys and handling their requests with `backend`
+    /// unless scripted otherwise.
+    pub fn start(backend: H, client_pubkeys: &[PublicKey]) -> io::Result<Self> {
+        let (noise_pubkey, noise_privkey): (PublicKey, SecretKey) = gen_keypair();
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let handler = Arc::new(MockHandler {
+            script: Mutex::new(Script::default()),
+            backend,
+        });
+
+        let client_pubkeys = client_pubkeys.to_vec();
+        let server_handler = handler.clone();
+        thread::Builder::new()
+            .name("revault_net mock server".to_string())
+            .spawn(move || {
+                server::listen(&listener, &noise_privkey, &client_pubkeys, server_handler)
+            })?;
+
+        Ok(Self {
+            addr,
+            noise_pubkey,
+            handler,
+        })
+    }
+}
+
+impl<H> MockServer<H> {
+    /// The address to connect to
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The static Noise public key of this server
+    pub fn noise_pubkey(&self) -> PublicKey {
+        self.noise_pubkey
+    }
+
+    /// The handler answering the requests that are not scripted
+    pub fn backend(&self) -> &H {
+        &self.handler.backend
+    }
+
+    /// Respond to the next request for this method with this result, instead of
+    /// handling it. Canned responses for the same method are used in order.
+    pub fn respond(&self, method: &'static str, result: ResponseResult) -> &Self {
+        self.queue(method, Some(result))
+    }
+
+    /// Don't respond to the next request for this method.
+    pub fn ignore(&self, method: &'static str) -> &Self {
+        self.queue(method, None)
+    }
+
+    fn queue(&self, method: &'static str, reply: Option<ResponseResult>) -> &Self {
+        self.handler
+            .script()
+            .replies
+            .entry(method)
+            .or_default()
+            .push_back(reply);
+        self
+    }
+
+    /// Run this assertion on the parameters of all the requests for this method. A
+    /// failed assertion does not prevent the request from being answered, it is
+    /// reported by [verify](Self::verify).
+    pub fn expect<F>(&self, method: &'static str, assertion: F) -> &Self
+    where
+        F: Fn(&RequestParams) + Send + 'static,
+    {
+        self.handler
+            .script()
+            .assertions
+            .entry(method)
+            .or_default()
+            .push(Box::new(assertion));
+        self
+    }
+
+    /// Wait for this long before answering the requests for this method.
+    pub fn delay(&self, method: &'static str, delay: Duration) -> &Self {
+        self.handler.script().delays.insert(method, delay);
+        self
+    }
+
+    /// Wait for this long before answering the next request for this method only.
+    pub fn delay_next(&self, method: &'static str, delay: Duration) -> &Self {
+        self.handler
+            .script()
+            .next_delays
+            .entry(method)
+            .or_default()
+            .push_back(delay);
+        self
+    }
+
+    /// All the requests received so far, along with the public key of their sender
+    pub fn received(&self) -> Vec<(PublicKey, RequestParams)> {
+        self.handler.script().received.clone()
+    }
+
+    /// The parameters of the requests received so far for this method
+    pub fn received_for(&self, method: &str) -> Vec<RequestParams> {
+        self.handler
+            .script()
+            .received
+            .iter()
+            .filter(|(_, params)| params.method() == method)
+            .map(|(_, params)| params.clone())
+            .collect()
+    }
+
+    /// Panic if any assertion failed.
+    pub fn verify(&self) {
+        let failures = &self.handler.script().failures;
+        if !failures.is_empty() {
+            panic!("Mock server assertions failed:\n{}", failures.join("\n"));
+        }
+    }
+}
+
+/// A mock coordinator, storing the signatures and Spend transactions in memory.
+pub type MockCoordinator = MockServer<Coordinator<MemoryStorage>>;
+
+impl MockCoordinator {
+    /// Start a mock coordinator for the participants with these static public keys
+    pub fn start_coordinator(client_pubkeys: &[PublicKey]) -> io::Result<Self> {
+        Self::start(Coordinator::new(MemoryStorage::new()), client_pubkeys)
+    }
+}
+
+/// The backend of a [MockWatchtower], recording the revocation signatures it receives.
+/// They are acknowledged unless configured otherwise, and given back to their sender
+/// on recovery.
+#[derive(Debug)]
+pub struct MockRevocationStore {
+    default_ack: Mutex<bool>,
+    // One-off acknowledgements, used in order before the default one
+    next_acks: Mutex<VecDeque<bool>>,
+    sigs: Mutex<Vec<(PublicKey, watchtower::Sig)>>,
+}
+
+impl MockRevocationStore {
+    /// A store acknowledging all the signatures
+    pub fn new() -> Self {
+        Self {
+            default_ack: Mutex::new(true),
+            next_acks: Mutex::new(VecDeque::new()),
+            sigs: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Set whether to acknowledge the signatures by default
+    pub fn set_default_ack(&self, ack: bool) {
+        *self.default_ack.lock().unwrap() = ack;
+    }
+
+    /// Set whether to acknowledge the next signatures received. Successive calls
+    /// apply to the following ones, in order.
+    pub fn ack_next(&self, ack: bool) {
+        self.next_acks.lock().unwrap().push_back(ack);
+    }
+
+    /// All the signatures received so far, along with the public key of their sender
+    pub fn sigs(&self) -> Vec<(PublicKey, watchtower::Sig)> {
+        self.sigs.lock().unwrap().clone()
+    }
+}
+
+impl Default for MockRevocationStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RevocationStore for MockRevocationStore {
+    type Error = Infallible;
+
+    fn store_revocation_sigs(
+        &self,
+        peer: &PublicKey,
+        sig: watchtower::Sig,
+    ) -> Result<bool, Self::Error> {
+        self.sigs.lock().unwrap().push((*peer, sig));
+        let ack = self.next_acks.lock().unwrap().pop_front();
+        Ok(ack.unwrap_or_else(|| *self.default_ack.lock().unwrap()))
+    }
+
+    fn get_revocation_sigs(
+        &self,
+        peer: &PublicKey,
+        deposit_outpoint: &OutPoint,
+    ) -> Result<Vec<watchtower::Sig>, Self::Error> {
+        // The last signatures received for each revocation transaction
+        let mut recovered: Vec<watchtower::Sig> = Vec::new();
+        for (sender, sig) in self.sigs.lock().unwrap().iter() {
+            if sender != peer || &sig.deposit_outpoint != deposit_outpoint {
+                continue;
+            }
+            recovered.retain(|s| s.txid != sig.txid);
+            recovered.push(sig.clone());
+        }
+        Ok(recovered)
+    }
+}
+
+/// A mock watchtower, recording the revocation signatures it receives and
+/// acknowledging them or not as configured on its [MockRevocationStore].
+pub type MockWatchtower = MockServer<Watchtower<MockRevocationStore>>;
+
+impl MockWatchtower {
+    /// Start a mock watchtower for the stakeholders with these static public keys
+    pub fn start_watchtower(client_pubkeys: &[PublicKey]) -> io::Result<Self> {
+        Self::start(Watchtower::new(MockRevocationStore::new()), client_pubkeys)
+    }
+
+    /// The revocation signatures received so far, along with the public key of their
+    /// sender. Signatures answered with a canned response are not part of them.
+    pub fn sigs(&self) -> Vec<(PublicKey, watchtower::Sig)> {
+        self.backend().store().sigs()
+    }
+}
+
+/// The backend of a [MockCosigner], signing the Spend transactions with test keys.
+/// It signs them all unless configured otherwise.
+#[derive(Debug)]
+pub struct MockSigner {
+    signers: Vec<PrivateKeySigner>,
+    refuse: Mutex<bool>,
+    // One-off refusals, used in order before the default
+    next_refusals: Mutex<VecDeque<bool>>,
+}
+
+impl MockSigner {
+    /// A signer adding a signature with each of these private keys
+    pub fn new(privkeys: &[secp256k1::SecretKey]) -> Self {
+        Self {
+            signers: privkeys
+                .iter()
+                .copied()
+                .map(PrivateKeySigner::new)
+                .collect(),
+            refuse: Mutex::new(false),
+            next_refusals: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Set whether to refuse to sign by default
+    pub fn set_refuse(&self, refuse: bool) {
+        *self.refuse.lock().unwrap() = refuse;
+    }
+
+    /// Set whether to refuse to sign the next transaction. Successive calls apply to
+    /// the following ones, in order.
+    pub fn refuse_next(&self, refuse: bool) {
+        self.next_refusals.lock().unwrap().push_back(refuse);
+    }
+}
+
+impl SigningBackend for MockSigner {
+    type Error = String;
+
+    fn sign(&self, spend_tx: &mut SpendTransaction) -> Result<(), Self::Error> {
+        let refuse = self.next_refusals.lock().unwrap().pop_front();
+        if refuse.unwrap_or_else(|| *self.refuse.lock().unwrap()) {
+            return Err("Scripted refusal".to_string());
+        }
+
+        for signer in self.signers.iter() {
+            signer.sign(spend_tx).map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    // The first key, the others standing for other cosigning servers
+    fn pubkey(&self) -> Option<secp256k1::PublicKey> {
+        self.signers.first().and_then(|signer| signer.pubkey())
+    }
+}
+
+/// A mock cosigning server, signing the Spend transactions with test keys as configured
+/// on its [MockSigner]. Like the actual one, it never signs two different Spend
+/// transactions spending the same vault.
+pub type MockCosigner = MockServer<Cosigner<MockSigner, MemoryOutpointStore>>;
+
+impl MockCosigner {
+    /// Start a mock cosigning server signing with these private keys, for the managers
+    /// with these static public keys
+    pub fn start_cosigner(
+        privkeys: &[secp256k1::SecretKey],
+        client_pubkeys: &[PublicKey],
+    ) -> io::Result<Self> {
+        let cosigner = Cosigner::new(MockSigner::new(privkeys), MemoryOutpointStore::new());
+        Self::start(cosigner, client_pubkeys)
+    }
+
+    /// The signing backend of this cosigning server
+    pub fn signer(&self) -> &MockSigner {
+        self.backend().backend()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::{CoordinatorClient, CosignerClient, WatchtowerClient},
+        error::Error,
+        fixtures::{secp_sig, spend_tx},
+        message::{
+            coordinator::{Sig, SigResult},
+            method,
+        },
+        server::coordinator::Storage,
+    };
+    use bitcoin::{secp256k1::key::SecretKey as SecpKey, OutPoint, Txid};
+    use revault_tx::transactions::RevaultTransaction;
+
+    #[test]
+    fn mock_coordinator() {
+        let (client_pubkey, client_privkey) = gen_keypair();
+        let coordinator = MockCoordinator::start_coordinator(&[client_pubkey]).unwrap();
+        let mut client = CoordinatorClient::connect(
+            coordinator.addr(),
+            &client_privkey,
+            &coordinator.noise_pubkey(),
+        )
+        .unwrap();
+
+        let (pubkey, signature) = secp_sig();
+        let sig = Sig {
+            pubkey,
+            signature,
+            id: Txid::default(),
+        };
+
+        // Scripted
+        coordinator
+            .respond(method::SIG, ResponseResult::Sig(SigResult::refused()))
+            .expect(method::SIG, |params| {
+                assert!(matches!(params, RequestParams::CoordSig(_)))
+            });
+        assert!(matches!(
+            client.send_sig(sig.clone()),
+            Err(Error::NotAcknowledged(method::SIG))
+        ));
+        // Handled by the in-memory coordinator
+        client.send_sig(sig.clone()).unwrap();
+        assert_eq!(client.get_sigs(Txid::default()).unwrap().len(), 1);
+        assert_eq!(
+            coordinator
+                .backend()
+                .storage()
+                .get_sigs(&Txid::default())
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(coordinator.received_for(method::SIG).len(), 2);
+        assert_eq!(coordinator.received().len(), 3);
+        coordinator.verify();
+
+        coordinator.expect(method::GET_SIGS, |_| panic!("unexpected get_sigs"));
+        client.get_sigs(Txid::default()).unwrap();
+        let res = panic::catch_unwind(AssertUnwindSafe(|| coordinator.verify()));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn mock_watchtower() {
+        let (client_pubkey, client_privkey) = gen_keypair();
+        let watchtower = MockWatchtower::start_watchtower(&[client_pubkey]).unwrap();
+        let mut client = WatchtowerClient::connect(
+            watchtower.addr(),
+            &client_privkey,
+            &watchtower.noise_pubkey(),
+        )
+        .unwrap()
+        .with_timeout(Duration::from_millis(200));
+
+        let (pubkey, signature) = secp_sig();
+        let msg = watchtower::Sig {
+            signatures: [(pubkey, signature)].iter().cloned().collect(),
+            txid: Txid::default(),
+            deposit_outpoint: OutPoint::default(),
+        };
+
+        client.share_revocation_sigs(msg.clone()).unwrap();
+        watchtower.backend().store().ack_next(false);
+        assert!(matches!(
+            client.share_revocation_sigs(msg.clone()),
+            Err(Error::NotAcknowledged(method::SIG))
+        ));
+
+        // A slow answer is retried
+        watchtower.delay_next(method::SIG, Duration::from_millis(300));
+        assert!(matches!(
+            client.share_revocation_sigs(msg.clone()),
+            Err(Error::Timeout(Some(_)))
+        ));
+        let mut client = client.with_retries(1);
+        watchtower.delay_next(method::SIG, Duration::from_millis(300));
+        client.share_revocation_sigs(msg.clone()).unwrap();
+
+        let sigs = watchtower.sigs();
+        assert_eq!(sigs.len(), 5);
+        assert!(sigs
+            .iter()
+            .all(|(peer, sig)| peer == &client_pubkey && sig == &msg));
+
+        // They are given back once per revocation transaction
+        assert_eq!(
+            client.recover_revocation_sigs(OutPoint::default()).unwrap(),
+            vec![msg]
+        );
+    }
+
+    #[test]
+    fn mock_cosigner() {
+        let (client_pubkey, client_privkey) = gen_keypair();
+        let privkeys = [
+            SecpKey::from_slice(&[1; 32]).unwrap(),
+            SecpKey::from_slice(&[2; 32]).unwrap(),
+        ];
+        let cosigner = MockCosigner::start_cosigner(&privkeys, &[client_pubkey]).unwrap();
+        let mut client =
+            CosignerClient::connect(cosigner.addr(), &client_privkey, &cosigner.noise_pubkey())
+                .unwrap();
+
+        let spend_tx = spend_tx();
+        cosigner.signer().refuse_next(true);
+        assert!(matches!(
+            client.sign(spend_tx.clone()),
+            Err(Error::NotAcknowledged(method::SIGN))
+        ));
+        let signed_tx = client.sign(spend_tx.clone()).unwrap();
+        assert_eq!(signed_tx.inner_tx().inputs[0].partial_sigs.len(), 2);
+
+        // Another transaction spending the same vault
+        let mut other_tx = spend_tx;
+        other_tx.inner_tx_mut().global.unsigned_tx.lock_time += 1;
+        assert!(client.sign(other_tx).is_err());
+        assert_eq!(cosigner.received_for(method::SIGN).len(), 3);
+    }
+}
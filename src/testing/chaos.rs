@@ -0,0 +1,319 @@
+//! Fault injection
+//!
+//! A [ChaosTransport] wraps a [KKTransport] and tampers with the encrypted frames it
+//! writes according to a [ChaosSchedule]: it can drop, delay, duplicate, truncate or
+//! flip a bit of them. The other end of the connection sees a misbehaving network and
+//! fails to decrypt what follows a tampered frame, as it would with a real one.
+//!
+//! Schedules are seeded, so that a failing test can be replayed.
+
+use crate::{message::SeededIds, transport::KKTransport};
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// What to do with a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Don't write it
+    Drop,
+    /// Wait for this long before writing it
+    Delay(Duration),
+    /// Write it twice
+    Duplicate,
+    /// Only write this number of bytes of it
+    Truncate(usize),
+    /// Flip this bit of it (counting from the first bit of the first byte)
+    BitFlip(usize),
+}
+
+// A deterministic PRNG, we don't need more for scheduling faults
+#[derive(Debug, Clone)]
+pub(super) struct Rng(SeededIds);
+
+impl Rng {
+    pub(super) fn new(seed: u64) -> Self {
+        Self(SeededIds::new(seed))
+    }
+
+    pub(super) fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    // A float in [0, 1)
+    pub(super) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// When to inject which fault.
+///
+/// Each frame is subject to at most one fault. Faults set for a specific frame with
+/// [at](Self::at) take precedence, otherwise a fault is drawn according to the
+/// probabilities set. By default, no fault is injected.
+#[derive(Debug, Clone)]
+pub struct ChaosSchedule {
+    rng: Rng,
+    drop: f64,
+    delay: (f64, Duration),
+    duplicate: f64,
+    truncate: f64,
+    bit_flip: f64,
+    scripted: BTreeMap<usize, Fault>,
+}
+
+impl ChaosSchedule {
+    /// A schedule drawing its faults from this seed
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            drop: 0.0,
+            delay: (0.0, Duration::from_secs(0)),
+            duplicate: 0.0,
+            truncate: 0.0,
+            bit_flip: 0.0,
+            scripted: BTreeMap::new(),
+        }
+    }
+
+    /// Drop frames with this probability
+    pub fn drop(mut self, probability: f64) -> Self {
+        self.drop = probability;
+        self
+    }
+
+    /// Delay frames by `delay` with this probability
+    pub fn delay(mut self, probability: f64, delay: Duration) -> Self {
+        self.delay = (probability, delay);
+        self
+    }
+
+    /// Duplicate frames with this probability
+    pub fn duplicate(mut self, probability: f64) -> Self {
+        self.duplicate = probability;
+        self
+    }
+
+    /// Truncate frames at a random length with this probability
+    pub fn truncate(mut self, probability: f64) -> Self {
+        self.truncate = probability;
+        self
+    }
+
+    /// Flip a random bit of frames with this probability
+    pub fn bit_flip(mut self, probability: f64) -> Self {
+        self.bit_flip = probability;
+        self
+    }
+
+    /// Inject this fault on the frame with this index (starting from 0)
+    pub fn at(mut self, frame: usize, fault: Fault) -> Self {
+        self.scripted.insert(frame, fault);
+        self
+    }
+
+    // The fault to inject on this frame, if any
+    fn next_fault(&mut self, frame: usize, len: usize) -> Option<Fault> {
+        // Always draw, for the faults of the following frames not to depend on the
+        // scripted ones
+        let draw = self.rng.next_f64();
+        let arg = self.rng.next_u64() as usize;
+        if let Some(fault) = self.scripted.get(&frame) {
+            return Some(*fault);
+        }
+
+        let mut threshold = 0.0;
+        let faults = [
+            (self.drop, Fault::Drop),
+            (self.delay.0, Fault::Delay(self.delay.1)),
+            (self.duplicate, Fault::Duplicate),
+            (self.truncate, Fault::Truncate(arg % len.max(1))),
+            (self.bit_flip, Fault::BitFlip(arg % (len * 8).max(1))),
+        ];
+        for (probability, fault) in faults.iter() {
+            threshold += probability;
+            if draw < threshold {
+                return Some(*fault);
+            }
+        }
+
+        None
+    }
+}
+
+// Apply this fault to this frame, returning what to write
+fn apply(fault: Option<Fault>, mut frame: Vec<u8>) -> Vec<Vec<u8>> {
+    match fault {
+        None => vec![frame],
+        Some(Fault::Drop) => vec![],
+        Some(Fault::Delay(delay)) => {
+            thread::sleep(delay);
+            vec![frame]
+        }
+        Some(Fault::Duplicate) => vec![frame.clone(), frame],
+        Some(Fault::Truncate(len)) => {
+            frame.truncate(len);
+            vec![frame]
+        }
+        Some(Fault::BitFlip(bit)) => {
+            if let Some(byte) = frame.get_mut(bit / 8) {
+                *byte ^= 1 << (bit % 8);
+            }
+            vec![frame]
+        }
+    }
+}
+
+/// A [KKTransport] injecting faults in the frames it writes.
+#[derive(Debug)]
+pub struct ChaosTransport {
+    transport: KKTransport,
+    injected: Arc<Mutex<Vec<(usize, Fault)>>>,
+}
+
+impl ChaosTransport {
+    /// Inject faults in the frames this transport writes from now on, according to
+    /// this schedule.
+    pub fn new(mut transport: KKTransport, mut schedule: ChaosSchedule) -> Self {
+        let injected = Arc::new(Mutex::new(Vec::new()));
+        let hook_injected = injected.clone();
+        let mut frame = 0;
+
+        transport.set_frame_hook(Some(move |msg: Vec<u8>| {
+            let fault = schedule.next_fault(frame, msg.len());
+            if let Some(fault) = fault {
+                hook_injected.lock().unwrap().push((frame, fault));
+            }
+            frame += 1;
+            apply(fault, msg)
+        }));
+
+        Self {
+            transport,
+            injected,
+        }
+    }
+
+    /// The transport to use, as usual
+    pub fn transport(&mut self) -> &mut KKTransport {
+        &mut self.transport
+    }
+
+    /// The faults injected so far, along with the index of the frame they were
+    /// injected on
+    pub fn injected(&self) -> Vec<(usize, Fault)> {
+        self.injected.lock().unwrap().clone()
+    }
+
+    /// Stop injecting faults, and get back the transport
+    pub fn into_inner(mut self) -> KKTransport {
+        self.transport
+            .set_frame_hook(None::<fn(Vec<u8>) -> Vec<Vec<u8>>>);
+        self.transport
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::{Error, NoiseError},
+        fixtures::local_endpoints,
+        message::{coordinator::GetSigs, Request},
+    };
+
+    fn transports() -> (KKTransport, KKTransport) {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let server_thread = thread::spawn(move || {
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap()
+        });
+        let client = KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        (client, server_thread.join().unwrap())
+    }
+
+    fn req() -> Request<'static> {
+        GetSigs {
+            id: Default::default(),
+            if_none_match: None,
+        }
+        .into()
+    }
+
+    #[test]
+    fn chaos_schedule() {
+        let schedule = |seed| {
+            ChaosSchedule::new(seed)
+                .drop(0.1)
+                .duplicate(0.1)
+                .truncate(0.1)
+                .bit_flip(0.1)
+                .at(3, Fault::Drop)
+        };
+        let faults = |mut schedule: ChaosSchedule| {
+            (0..1000)
+                .map(|frame| schedule.next_fault(frame, 100))
+                .collect::<Vec<_>>()
+        };
+        // Deterministic
+        let drawn = faults(schedule(42));
+        assert_eq!(drawn, faults(schedule(42)));
+        assert_ne!(drawn, faults(schedule(43)));
+        assert_eq!(drawn[3], Some(Fault::Drop));
+        // 40% of faulty frames, roughly
+        let faulty = drawn.iter().filter(|fault| fault.is_some()).count();
+        assert!(faulty > 300 && faulty < 500, "{}", faulty);
+        assert!(drawn.iter().all(|fault| match fault {
+            Some(Fault::Truncate(len)) => *len < 100,
+            Some(Fault::BitFlip(bit)) => *bit < 800,
+            _ => true,
+        }));
+        // No fault by default
+        assert!(faults(ChaosSchedule::new(42)).iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn chaos_transport() {
+        // A bit flip makes the frame fail to decrypt
+        let (client, mut server) = transports();
+        let schedule = ChaosSchedule::new(0).at(1, Fault::BitFlip(3));
+        let mut client = ChaosTransport::new(client, schedule);
+        client.transport().write_req(&req()).unwrap();
+        client.transport().write_req(&req()).unwrap();
+        server.read_message().unwrap();
+        assert!(matches!(
+            server.read_message(),
+            Err(Error::Noise(NoiseError::Decryption))
+        ));
+        assert_eq!(client.injected(), vec![(1, Fault::BitFlip(3))]);
+
+        // So does replaying it
+        let (client, mut server) = transports();
+        let schedule = ChaosSchedule::new(0).at(0, Fault::Duplicate);
+        let mut client = ChaosTransport::new(client, schedule);
+        client.transport().write_req(&req()).unwrap();
+        server.read_message().unwrap();
+        assert!(server.read_message().is_err());
+
+        // A dropped frame is never received
+        let (client, mut server) = transports();
+        let mut client = ChaosTransport::new(client, ChaosSchedule::new(0).at(0, Fault::Drop));
+        client.transport().write_req(&req()).unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert!(matches!(server.read_message(), Err(Error::Timeout(None))));
+
+        // Faults stop once unwrapped
+        let (client, mut server) = transports();
+        let mut client =
+            ChaosTransport::new(client, ChaosSchedule::new(0).bit_flip(1.0)).into_inner();
+        client.write_req(&req()).unwrap();
+        server.read_message().unwrap();
+    }
+}
@@ -0,0 +1,617 @@
+//! Conformance checker
+//!
+//! Exercise every request a running coordinator, watchtower or cosigning server is
+//! specified to handle (see [method::REQUESTS]) and report the ones it mishandles. This
+//! is meant for implementations of the servers other than the ones of this crate to
+//! check their compatibility.
+//!
+//! The requests are valid but carry dummy data, that the server will store: only run
+//! the checker against a test deployment.
+
+use crate::{
+    error::Error,
+    fixtures::SPEND_PSBT,
+    message::{
+        coordinator::{
+            self, BlobId, ChunkData, MusigPartialSignature, MusigPubNonce, RecipientKey, SealedBlob,
+        },
+        cosigner,
+        method::{self, Peer},
+        replication, watchtower, ErrorCode, Request, ResponseError,
+    },
+    transport::KKTransport,
+};
+
+use revault_tx::{
+    bitcoin::{
+        hashes::{sha256, Hash},
+        secp256k1::{self, schnorrsig, Secp256k1},
+        OutPoint, Transaction, TxIn, TxOut, Txid,
+    },
+    transactions::{RevaultTransaction, SpendTransaction},
+};
+use serde::de::DeserializeOwned;
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// How a server handled a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// It responded as specified
+    Passed,
+    /// It responded with an error, as a server may do for a request it refuses to
+    /// process
+    Refused(ResponseError),
+    /// It did not respond as specified, for this reason
+    Mishandled(String),
+}
+
+/// The check of a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// The method of the request
+    pub method: &'static str,
+    /// The type of its parameters
+    pub params: &'static str,
+    /// How it was handled
+    pub outcome: Outcome,
+}
+
+/// The checks performed against a server
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ConformanceReport {
+    /// The checks, in the order they were performed
+    pub checks: Vec<Check>,
+}
+
+impl ConformanceReport {
+    /// The checks of the requests that were mishandled
+    pub fn mishandled(&self) -> impl Iterator<Item = &Check> {
+        self.checks
+            .iter()
+            .filter(|check| matches!(check.outcome, Outcome::Mishandled(_)))
+    }
+
+    /// Whether no request was mishandled
+    pub fn is_conformant(&self) -> bool {
+        self.mishandled().next().is_none()
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for check in self.checks.iter() {
+            let name = format!("{} ({})", check.method, check.params);
+            match &check.outcome {
+                Outcome::Passed => writeln!(f, "PASS    {}", name)?,
+                Outcome::Refused(e) => writeln!(f, "REFUSED {}: {}", name, e)?,
+                Outcome::Mishandled(reason) => writeln!(f, "FAIL    {}: {}", name, reason)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// The dummy data of the requests
+struct Samples {
+    pubkey: secp256k1::PublicKey,
+    signature: secp256k1::Signature,
+    schnorr_pubkey: schnorrsig::PublicKey,
+    schnorr_signature: schnorrsig::Signature,
+    txid: Txid,
+    deposit_outpoint: OutPoint,
+    spend_tx: Transaction,
+}
+
+impl Samples {
+    fn new() -> Self {
+        let secp = Secp256k1::new();
+        let privkey = secp256k1::SecretKey::from_slice(&[1; 32]).expect("Valid key");
+        let msg = secp256k1::Message::from_slice(&[1; 32]).expect("32 bytes");
+        let keypair = schnorrsig::KeyPair::from_seckey_slice(&secp, &[1; 32]).expect("Valid key");
+        let txid = Txid::hash(b"revault_net conformance");
+        let deposit_outpoint = OutPoint::new(Txid::hash(&txid[..]), 0);
+
+        Self {
+            pubkey: secp256k1::PublicKey::from_secret_key(&secp, &privkey),
+            signature: secp.sign(&msg, &privkey),
+            schnorr_pubkey: schnorrsig::PublicKey::from_keypair(&secp, &keypair),
+            schnorr_signature: secp.schnorrsig_sign_no_aux_rand(&msg, &keypair),
+            txid,
+            deposit_outpoint,
+            spend_tx: Transaction {
+                version: 2,
+                lock_time: 0,
+                input: vec![TxIn {
+                    previous_output: OutPoint::new(txid, 0),
+                    ..TxIn::default()
+                }],
+                output: vec![TxOut {
+                    value: 10_000,
+                    ..TxOut::default()
+                }],
+            },
+        }
+    }
+
+    fn request(&self, params: &str, subscription_id: u32) -> Request<'static> {
+        match params {
+            "watchtower::Sig" => watchtower::Sig {
+                signatures: [(self.pubkey, self.signature)].iter().cloned().collect(),
+                txid: self.txid,
+                deposit_outpoint: self.deposit_outpoint,
+            }
+            .into(),
+            "watchtower::SchnorrSig" => watchtower::SchnorrSig {
+                signatures: [(self.schnorr_pubkey, self.schnorr_signature)]
+                    .iter()
+                    .cloned()
+                    .collect(),
+                txid: self.txid,
+                deposit_outpoint: self.deposit_outpoint,
+            }
+            .into(),
+            "watchtower::GetSpendPolicy" => watchtower::GetSpendPolicy {}.into(),
+            "watchtower::RecoverSigs" => watchtower::RecoverSigs {
+                deposit_outpoint: self.deposit_outpoint,
+            }
+            .into(),
+            "watchtower::ApproveSpend" => watchtower::ApproveSpend {
+                deposit_outpoints: vec![self.deposit_outpoint],
+                spend_tx: self.spend_tx.clone(),
+            }
+            .into(),
+            "watchtower::SyncVaults" => watchtower::SyncVaults {
+                vaults: vec![watchtower::Sig {
+                    signatures: [(self.pubkey, self.signature)].iter().cloned().collect(),
+                    txid: self.txid,
+                    deposit_outpoint: self.deposit_outpoint,
+                }],
+            }
+            .into(),
+            "coordinator::Sig" => coordinator::Sig {
+                pubkey: self.pubkey,
+                signature: self.signature,
+                id: self.txid,
+            }
+            .into(),
+            "coordinator::SchnorrSig" => coordinator::SchnorrSig {
+                pubkey: self.schnorr_pubkey,
+                signature: self.schnorr_signature,
+                id: self.txid,
+            }
+            .into(),
+            "coordinator::GetSigs" => coordinator::GetSigs {
+                id: self.txid,
+                if_none_match: None,
+            }
+            .into(),
+            "coordinator::HasSigs" => coordinator::HasSigs { id: self.txid }.into(),
+            "coordinator::GetRevocationSigs" => coordinator::GetRevocationSigs {
+                txids: [(coordinator::RevocationTxType::Cancel, self.txid)]
+                    .iter()
+                    .cloned()
+                    .collect(),
+            }
+            .into(),
+            "coordinator::SetSpendTx" => coordinator::SetSpendTx::from_transaction(
+                vec![self.deposit_outpoint],
+                self.spend_tx.clone(),
+            )
+            .expect("Valid SetSpendTx")
+            .into(),
+            "coordinator::GetSpendTx" => coordinator::GetSpendTx {
+                deposit_outpoint: self.deposit_outpoint,
+            }
+            .into(),
+            "coordinator::GetSpendTxChunk" => coordinator::GetSpendTxChunk {
+                deposit_outpoint: self.deposit_outpoint,
+                chunk_index: 0,
+            }
+            .into(),
+            "coordinator::MusigNonce" => coordinator::MusigNonce {
+                txid: self.txid,
+                pubkey: self.pubkey,
+                nonce: MusigPubNonce([2; 66]),
+            }
+            .into(),
+            "coordinator::MusigPartialSig" => coordinator::MusigPartialSig {
+                txid: self.txid,
+                pubkey: self.pubkey,
+                partial_sig: MusigPartialSignature([1; 32]),
+            }
+            .into(),
+            "coordinator::GetMusigSession" => {
+                coordinator::GetMusigSession { txid: self.txid }.into()
+            }
+            "coordinator::SealedSig" => coordinator::SealedSig {
+                txid: self.txid,
+                sealed: [(RecipientKey([3; 32]), SealedBlob(vec![4; 80]))]
+                    .iter()
+                    .cloned()
+                    .collect(),
+            }
+            .into(),
+            "coordinator::GetSealedSigs" => coordinator::GetSealedSigs {
+                txid: self.txid,
+                recipient: RecipientKey([3; 32]),
+            }
+            .into(),
+            "coordinator::StoreBlob" => coordinator::StoreBlob {
+                blob_id: BlobId([5; 32]),
+                blob: SealedBlob(vec![4; 80]),
+            }
+            .into(),
+            "coordinator::GetBlobs" => coordinator::GetBlobs {
+                blob_id: BlobId([5; 32]),
+            }
+            .into(),
+            "coordinator::SetSpendTxBegin" => coordinator::SetSpendTxBegin {
+                total_size: 80,
+                sha256: sha256::Hash::hash(&[4; 80]),
+            }
+            .into(),
+            // Of an upload that was not started, only the response has to be valid
+            "coordinator::SetSpendTxChunk" => coordinator::SetSpendTxChunk {
+                upload_id: u32::MAX,
+                index: 0,
+                data: ChunkData(vec![4; 80]),
+            }
+            .into(),
+            "coordinator::SetSpendTxCommit" => coordinator::SetSpendTxCommit {
+                upload_id: u32::MAX,
+            }
+            .into(),
+            "coordinator::Subscribe" => coordinator::Subscribe {
+                txids: vec![self.txid],
+                deposit_outpoints: vec![self.deposit_outpoint],
+            }
+            .into(),
+            "coordinator::Unsubscribe" => coordinator::Unsubscribe { subscription_id }.into(),
+            "replication::Replicate" => replication::Replicate {
+                entries: vec![replication::Entry::Sig {
+                    txid: self.txid,
+                    pubkey: self.pubkey,
+                    signature: self.signature,
+                }],
+            }
+            .into(),
+            "replication::GetEntries" => replication::GetEntries { since: 0 }.into(),
+            "coordinator::GetTime" => coordinator::GetTime {}.into(),
+            "cosigner::SignRequest" => cosigner::SignRequest::new(
+                SpendTransaction::from_psbt_str(SPEND_PSBT).expect("Valid PSBT"),
+            )
+            .into(),
+            "cosigner::BatchSignRequest" => cosigner::BatchSignRequest::new(vec![
+                SpendTransaction::from_psbt_str(SPEND_PSBT).expect("Valid PSBT"),
+            ])
+            .into(),
+            "cosigner::AckKeyRotation" => cosigner::AckKeyRotation {
+                new_pubkey: self.pubkey,
+            }
+            .into(),
+            "cosigner::GetPubkey" => cosigner::GetPubkey {}.into(),
+            params => unreachable!("No sample for '{}'", params),
+        }
+    }
+}
+
+// Whether this value is a valid result of this type, checking what we can of its
+// consistency with the previous requests.
+fn check_result(
+    result: &str,
+    value: &serde_json::Value,
+    samples: &Samples,
+    state: &mut State,
+) -> Result<(), String> {
+    fn parse<T: DeserializeOwned>(value: &serde_json::Value) -> Result<T, String> {
+        serde_json::from_value(value.clone()).map_err(|e| e.to_string())
+    }
+
+    match result {
+        "watchtower::SigResult" => {
+            let res: watchtower::SigResult = parse(value)?;
+            state.sig_acked |= res.ack;
+            Ok(())
+        }
+        "watchtower::RecoveredSigs" => {
+            let res: watchtower::RecoveredSigs = parse(value)?;
+            if let Some(sig) = res
+                .sigs
+                .iter()
+                .find(|sig| sig.deposit_outpoint != samples.deposit_outpoint)
+            {
+                return Err(format!("Got signatures for '{}'", sig.deposit_outpoint));
+            }
+            if state.sig_acked && !res.sigs.iter().any(|sig| sig.txid == samples.txid) {
+                return Err("An acknowledged revocation signature is missing".to_string());
+            }
+            Ok(())
+        }
+        "watchtower::SyncVaultsResult" => parse::<watchtower::SyncVaultsResult>(value).map(|_| ()),
+        "watchtower::SpendPolicy" => parse::<watchtower::SpendPolicy>(value).map(|_| ()),
+        "watchtower::SpendApproval" => {
+            let res: watchtower::SpendApproval = parse(value)?;
+            if res.txid != samples.spend_tx.txid() {
+                return Err(format!("Got approval for '{}'", res.txid));
+            }
+            Ok(())
+        }
+        "coordinator::SigResult" => {
+            let res: coordinator::SigResult = parse(value)?;
+            state.sig_acked |= res.ack;
+            Ok(())
+        }
+        "coordinator::Sigs" => {
+            let sigs: coordinator::Sigs = parse(value)?;
+            if state.sig_acked && sigs.signatures.get(&samples.pubkey).is_none() {
+                return Err("An acknowledged signature is missing".to_string());
+            }
+            Ok(())
+        }
+        "coordinator::RevocationSigs" => {
+            let sigs: coordinator::RevocationSigs = parse(value)?;
+            let cancel_sigs = sigs
+                .get(coordinator::RevocationTxType::Cancel)
+                .ok_or_else(|| "No signatures for the requested transaction".to_string())?;
+            if state.sig_acked && cancel_sigs.get(&samples.pubkey).is_none() {
+                return Err("An acknowledged signature is missing".to_string());
+            }
+            Ok(())
+        }
+        "coordinator::SigsCount" => {
+            let res: coordinator::SigsCount = parse(value)?;
+            if state.sig_acked && !res.has_sigs() {
+                return Err("An acknowledged signature is not counted".to_string());
+            }
+            Ok(())
+        }
+        "coordinator::SchnorrSigs" => parse::<coordinator::SchnorrSigs>(value).map(|_| ()),
+        "coordinator::NotModified" => {
+            parse::<coordinator::NotModified>(value)?;
+            Err("Not modified, but no tag was sent".to_string())
+        }
+        "coordinator::SetSpendResult" => {
+            let res: coordinator::SetSpendResult = parse(value)?;
+            state.spend_acked = res.ack;
+            Ok(())
+        }
+        "coordinator::SpendTx" => {
+            let res: coordinator::SpendTx = parse(value)?;
+            match res.into_transaction() {
+                Some(tx) if tx.txid() != samples.spend_tx.txid() => {
+                    Err(format!("Got Spend transaction '{}'", tx.txid()))
+                }
+                None if state.spend_acked => {
+                    Err("An acknowledged Spend transaction is missing".to_string())
+                }
+                _ => Ok(()),
+            }
+        }
+        "coordinator::MusigSession" => parse::<coordinator::MusigSession>(value).map(|_| ()),
+        "coordinator::SealedSigs" => parse::<coordinator::SealedSigs>(value).map(|_| ()),
+        "coordinator::Blobs" => parse::<coordinator::Blobs>(value).map(|_| ()),
+        "coordinator::UploadStarted" => parse::<coordinator::UploadStarted>(value).map(|_| ()),
+        "coordinator::SpendTxChunk" => {
+            let res: coordinator::SpendTxChunk = parse(value)?;
+            if res.chunk_count == 0 && state.spend_acked {
+                return Err("An acknowledged Spend transaction is missing".to_string());
+            }
+            Ok(())
+        }
+        "coordinator::SubscribeResult" => {
+            let res: coordinator::SubscribeResult = parse(value)?;
+            state.subscription_id = Some(res.subscription_id);
+            Ok(())
+        }
+        "coordinator::UnsubscribeResult" => {
+            let res: coordinator::UnsubscribeResult = parse(value)?;
+            if state.subscription_id.is_some() && !res.ack {
+                return Err("The subscription was not known".to_string());
+            }
+            Ok(())
+        }
+        "replication::ReplicateResult" => {
+            let res: replication::ReplicateResult = parse(value)?;
+            if res.new_entries > 1 {
+                return Err(format!("{} new entries out of 1", res.new_entries));
+            }
+            Ok(())
+        }
+        "replication::Entries" => parse::<replication::Entries>(value).map(|_| ()),
+        "coordinator::ServerTime" => {
+            let res: coordinator::ServerTime = parse(value)?;
+            if res.time == 0 {
+                return Err("The time is not set".to_string());
+            }
+            Ok(())
+        }
+        "cosigner::SignResult" => parse::<cosigner::SignResult>(value).map(|_| ()),
+        // We can't know whether the cosigning server is rotating to this key
+        "cosigner::AckKeyRotationResult" => {
+            parse::<cosigner::AckKeyRotationResult>(value).map(|_| ())
+        }
+        "cosigner::GetPubkeyResult" => {
+            let res: cosigner::GetPubkeyResult = parse(value)?;
+            if res.sighash_types.is_empty() {
+                return Err("No signature hash type".to_string());
+            }
+            Ok(())
+        }
+        "cosigner::BatchSignResult" => {
+            let res: cosigner::BatchSignResult = parse(value)?;
+            if res.txs.len() != 1 {
+                return Err(format!("{} results for a batch of 1", res.txs.len()));
+            }
+            Ok(())
+        }
+        result => unreachable!("No check for '{}'", result),
+    }
+}
+
+// What the server acknowledged so far
+#[derive(Default)]
+struct State {
+    sig_acked: bool,
+    spend_acked: bool,
+    subscription_id: Option<u32>,
+}
+
+/// Send all the requests `peer` is specified to handle to the server at the other end
+/// of this transport, and check its responses. A request it did not respond to within
+/// `timeout` is mishandled.
+pub fn check_peer(transport: &mut KKTransport, peer: Peer, timeout: Duration) -> ConformanceReport {
+    let samples = Samples::new();
+    let mut state = State::default();
+    let mut report = ConformanceReport::default();
+
+    for spec in method::REQUESTS
+        .iter()
+        .filter(|spec| spec.recipient == peer)
+    {
+        for params in spec.params {
+            let req = samples.request(params, state.subscription_id.unwrap_or(0));
+            let outcome = match transport
+                .send_req_before::<serde_json::Value>(&req, Instant::now() + timeout)
+            {
+                Ok(value) => {
+                    let errors: Vec<String> = spec
+                        .results
+                        .iter()
+                        .filter_map(|result| {
+                            check_result(result, &value, &samples, &mut state).err()
+                        })
+                        .collect();
+                    // The result must be valid as one of the possible types
+                    if errors.len() < spec.results.len() {
+                        Outcome::Passed
+                    } else {
+                        Outcome::Mishandled(format!(
+                            "Invalid result '{}': {}",
+                            value,
+                            errors.join(", ")
+                        ))
+                    }
+                }
+                Err(Error::Remote(e)) => match e.code {
+                    ErrorCode::ParseError
+                    | ErrorCode::InvalidRequest
+                    | ErrorCode::MethodNotFound => {
+                        Outcome::Mishandled(format!("Valid request rejected: {}", e))
+                    }
+                    _ => Outcome::Refused(e),
+                },
+                Err(e) => Outcome::Mishandled(e.to_string()),
+            };
+
+            report.checks.push(Check {
+                method: spec.method,
+                params,
+                outcome,
+            });
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::noise::gen_keypair;
+    use crate::{
+        message::{coordinator::SetSpendResult, ResponseResult},
+        testing::{MockCoordinator, MockCosigner, MockWatchtower},
+    };
+
+    fn failed(report: &ConformanceReport) -> Vec<(&str, &str)> {
+        report
+            .mishandled()
+            .map(|check| (check.method, check.params))
+            .collect()
+    }
+
+    #[test]
+    fn conformance() {
+        let timeout = Duration::from_millis(200);
+        let (client_pubkey, client_privkey) = gen_keypair();
+
+        let coordinator = MockCoordinator::start_coordinator(&[client_pubkey]).unwrap();
+        let mut transport = KKTransport::connect(
+            coordinator.addr(),
+            &client_privkey,
+            &coordinator.noise_pubkey(),
+        )
+        .unwrap();
+        let report = check_peer(&mut transport, Peer::Coordinator, timeout);
+        assert_eq!(report.checks.len(), 23, "{}", report);
+        // The in-memory coordinator does not handle Taproot, MuSig2, blind storage,
+        // subscriptions nor replication
+        assert_eq!(
+            failed(&report),
+            vec![
+                (method::SIG, "coordinator::SchnorrSig"),
+                (method::MUSIG_NONCE, "coordinator::MusigNonce"),
+                (method::MUSIG_PARTIAL_SIG, "coordinator::MusigPartialSig"),
+                (method::GET_MUSIG_SESSION, "coordinator::GetMusigSession"),
+                (method::STORE_BLOB, "coordinator::StoreBlob"),
+                (method::GET_BLOBS, "coordinator::GetBlobs"),
+                (method::SUBSCRIBE, "coordinator::Subscribe"),
+                (method::UNSUBSCRIBE, "coordinator::Unsubscribe"),
+                (method::REPLICATE, "replication::Replicate"),
+                (method::GET_ENTRIES, "replication::GetEntries"),
+            ],
+            "{}",
+            report
+        );
+        assert!(!report.is_conformant());
+        assert!(report.to_string().contains("PASS    get_sigs"));
+
+        // A wrongly typed result, or an inconsistent one
+        coordinator
+            .respond(
+                method::GET_SIGS,
+                ResponseResult::SetSpend(SetSpendResult { ack: true }),
+            )
+            .respond(
+                method::GET_SPEND_TX,
+                ResponseResult::SpendTx(coordinator::SpendTx::not_found()),
+            );
+        let report = check_peer(&mut transport, Peer::Coordinator, timeout);
+        let mishandled = failed(&report);
+        assert!(mishandled.contains(&(method::GET_SIGS, "coordinator::GetSigs")));
+        assert!(mishandled.contains(&(method::GET_SPEND_TX, "coordinator::GetSpendTx")));
+
+        let watchtower = MockWatchtower::start_watchtower(&[client_pubkey]).unwrap();
+        let mut transport = KKTransport::connect(
+            watchtower.addr(),
+            &client_privkey,
+            &watchtower.noise_pubkey(),
+        )
+        .unwrap();
+        let report = check_peer(&mut transport, Peer::Watchtower, timeout);
+        // Nor does the in-memory watchtower handle Taproot, syncing and policies
+        assert_eq!(
+            failed(&report),
+            vec![
+                (method::SIG, "watchtower::SchnorrSig"),
+                (method::SYNC_VAULTS, "watchtower::SyncVaults"),
+                (method::GET_SPEND_POLICY, "watchtower::GetSpendPolicy"),
+                (method::APPROVE_SPEND, "watchtower::ApproveSpend"),
+            ],
+            "{}",
+            report
+        );
+
+        let privkeys = [secp256k1::SecretKey::from_slice(&[1; 32]).unwrap()];
+        let cosigner = MockCosigner::start_cosigner(&privkeys, &[client_pubkey]).unwrap();
+        let mut transport =
+            KKTransport::connect(cosigner.addr(), &client_privkey, &cosigner.noise_pubkey())
+                .unwrap();
+        let report = check_peer(&mut transport, Peer::Cosigner, timeout);
+        assert!(report.is_conformant(), "{}", report);
+        assert_eq!(report.checks.len(), 4);
+    }
+}
@@ -0,0 +1,458 @@
+//! Network simulation
+//!
+//! A [Simulation] runs servers and clients in process, exchanging messages over virtual
+//! links instead of sockets. Links have a latency, a jitter (which reorders the
+//! messages sent close to each other), may lose or hold back messages, and can be
+//! partitioned. Time is virtual and all randomness is drawn from a seed, so a race
+//! condition between peers found with a seed can be replayed at will.
+//!
+//! Messages are serialized as on the wire, but not encrypted.
+
+use super::chaos::Rng;
+use crate::{
+    message::{ErrorResponse, Request, Response},
+    noise::PublicKey,
+    server::RequestHandler,
+};
+
+use serde::de::DeserializeOwned;
+use std::{collections::BTreeMap, time::Duration};
+
+/// A peer of the simulated network
+pub type NodeId = usize;
+
+/// A virtual link between two nodes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Link {
+    latency: Duration,
+    jitter: Duration,
+    loss: f64,
+    reorder: f64,
+}
+
+impl Link {
+    /// A link delivering each message after this latency
+    pub fn new(latency: Duration) -> Self {
+        Self {
+            latency,
+            jitter: Duration::from_secs(0),
+            loss: 0.0,
+            reorder: 0.0,
+        }
+    }
+
+    /// Add a random delay of up to `jitter` to each message
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Lose messages with this probability
+    pub fn loss(mut self, probability: f64) -> Self {
+        self.loss = probability;
+        self
+    }
+
+    /// Hold back messages for another latency with this probability, for the following
+    /// ones to overtake them
+    pub fn reorder(mut self, probability: f64) -> Self {
+        self.reorder = probability;
+        self
+    }
+}
+
+/// What happened to a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceKind {
+    /// It was sent
+    Sent,
+    /// It was received
+    Delivered,
+    /// It was lost by the link
+    Lost,
+    /// It was dropped, as the nodes were partitioned or the receiver not linked to
+    /// the sender
+    Unreachable,
+}
+
+/// An entry of the trace of a simulation
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    /// The virtual time it happened at
+    pub at: Duration,
+    /// The sender of the message
+    pub from: NodeId,
+    /// The recipient of the message
+    pub to: NodeId,
+    /// What happened
+    pub kind: TraceKind,
+    /// The message, as serialized on the wire
+    pub message: String,
+}
+
+/// A response received by a client
+#[derive(Debug, Clone, PartialEq)]
+pub struct Received {
+    /// The virtual time it was received at
+    pub at: Duration,
+    /// The server that sent it
+    pub from: NodeId,
+    /// The id of the request it responds to
+    pub id: u32,
+    /// The result it contains
+    pub result: serde_json::Value,
+}
+
+impl Received {
+    /// Parse the result
+    pub fn result<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_value(self.result.clone())
+    }
+}
+
+enum Node {
+    Server(Box<dyn RequestHandler>),
+    Client(Vec<Received>),
+}
+
+// A message in flight
+struct InFlight {
+    at: Duration,
+    seq: u64,
+    from: NodeId,
+    to: NodeId,
+    message: String,
+}
+
+/// A simulated network, see the [module](self) documentation.
+pub struct Simulation {
+    rng: Rng,
+    now: Duration,
+    nodes: Vec<Node>,
+    links: BTreeMap<(NodeId, NodeId), Link>,
+    partitions: Vec<(NodeId, NodeId, Duration, Duration)>,
+    in_flight: Vec<InFlight>,
+    seq: u64,
+    trace: Vec<TraceEntry>,
+}
+
+impl Simulation {
+    /// An empty network drawing its randomness from this seed
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            now: Duration::from_secs(0),
+            nodes: Vec::new(),
+            links: BTreeMap::new(),
+            partitions: Vec::new(),
+            in_flight: Vec::new(),
+            seq: 0,
+            trace: Vec::new(),
+        }
+    }
+
+    /// Add a server answering requests with this handler
+    pub fn add_server<H: RequestHandler + 'static>(&mut self, handler: H) -> NodeId {
+        self.nodes.push(Node::Server(Box::new(handler)));
+        self.nodes.len() - 1
+    }
+
+    /// Add a client, sending requests and collecting the responses
+    pub fn add_client(&mut self) -> NodeId {
+        self.nodes.push(Node::Client(Vec::new()));
+        self.nodes.len() - 1
+    }
+
+    /// The static public key the servers know this node by
+    pub fn pubkey(&self, node: NodeId) -> PublicKey {
+        let mut key = [0; 32];
+        key[..8].copy_from_slice(&(node as u64 + 1).to_be_bytes());
+        PublicKey(key)
+    }
+
+    /// Link these two nodes, in both directions
+    pub fn link(&mut self, a: NodeId, b: NodeId, link: Link) -> &mut Self {
+        self.links.insert((a, b), link);
+        self.links.insert((b, a), link);
+        self
+    }
+
+    /// Partition these two nodes from `from` to `until`, in virtual time: the messages
+    /// between them that would be delivered during this period are dropped.
+    pub fn partition(
+        &mut self,
+        a: NodeId,
+        b: NodeId,
+        from: Duration,
+        until: Duration,
+    ) -> &mut Self {
+        self.partitions.push((a, b, from, until));
+        self
+    }
+
+    fn partitioned(&self, a: NodeId, b: NodeId, at: Duration) -> bool {
+        self.partitions.iter().any(|&(x, y, from, until)| {
+            ((x, y) == (a, b) || (x, y) == (b, a)) && from <= at && at < until
+        })
+    }
+
+    fn record(&mut self, at: Duration, from: NodeId, to: NodeId, kind: TraceKind, msg: &str) {
+        self.trace.push(TraceEntry {
+            at,
+            from,
+            to,
+            kind,
+            message: msg.to_string(),
+        });
+    }
+
+    // Put a message on the link between these two nodes
+    fn transmit(&mut self, from: NodeId, to: NodeId, message: String) {
+        self.record(self.now, from, to, TraceKind::Sent, &message);
+        let link = match self.links.get(&(from, to)) {
+            Some(link) => *link,
+            None => {
+                self.record(self.now, from, to, TraceKind::Unreachable, &message);
+                return;
+            }
+        };
+
+        // Always draw, for the following messages not to depend on this one
+        let (loss, reorder, jitter) = (
+            self.rng.next_f64(),
+            self.rng.next_f64(),
+            self.rng.next_f64(),
+        );
+        if loss < link.loss {
+            self.record(self.now, from, to, TraceKind::Lost, &message);
+            return;
+        }
+        let mut delay = link.latency + link.jitter.mul_f64(jitter);
+        if reorder < link.reorder {
+            delay += link.latency;
+        }
+
+        self.seq += 1;
+        self.in_flight.push(InFlight {
+            at: self.now + delay,
+            seq: self.seq,
+            from,
+            to,
+            message,
+        });
+    }
+
+    /// Send this request from a client to a server, now. Use a deterministic
+    /// [IdGenerator](crate::message::IdGenerator) to create the requests for the
+    /// simulation to be reproducible.
+    pub fn send(&mut self, client: NodeId, server: NodeId, req: &Request) {
+        let message = serde_json::to_string(req).expect("Serializing a request");
+        self.transmit(client, server, message);
+    }
+
+    // Process a message arriving at its recipient
+    fn deliver(&mut self, msg: InFlight) {
+        if self.partitioned(msg.from, msg.to, msg.at) {
+            self.record(
+                msg.at,
+                msg.from,
+                msg.to,
+                TraceKind::Unreachable,
+                &msg.message,
+            );
+            return;
+        }
+        self.record(msg.at, msg.from, msg.to, TraceKind::Delivered, &msg.message);
+
+        let peer = self.pubkey(msg.from);
+        match &mut self.nodes[msg.to] {
+            Node::Server(handler) => {
+                let req: Request = match serde_json::from_str(&msg.message) {
+                    Ok(req) => req,
+                    Err(_) => return,
+                };
+                let id = req.id();
+                let resp = match handler.reply(&peer, id, req.params()) {
+                    Some(Ok(result)) => serde_json::to_string(&Response { result, id }),
+                    Some(Err(error)) => serde_json::to_string(&ErrorResponse { error, id }),
+                    None => return,
+                };
+                let resp = resp.expect("Serializing a response");
+                self.transmit(msg.to, msg.from, resp);
+            }
+            Node::Client(received) => {
+                if let Ok(resp) = serde_json::from_str::<Response<serde_json::Value>>(&msg.message)
+                {
+                    received.push(Received {
+                        at: msg.at,
+                        from: msg.from,
+                        id: resp.id,
+                        result: resp.result,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Deliver the next message in flight, if any. Returns `false` if there is none.
+    pub fn step(&mut self) -> bool {
+        let next = self
+            .in_flight
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, msg)| (msg.at, msg.seq))
+            .map(|(i, _)| i);
+        match next {
+            Some(i) => {
+                let msg = self.in_flight.swap_remove(i);
+                self.now = msg.at;
+                self.deliver(msg);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Deliver the messages in flight up to this virtual time, which becomes the
+    /// current time.
+    pub fn run_until(&mut self, time: Duration) {
+        while self.in_flight.iter().any(|msg| msg.at <= time) {
+            self.step();
+        }
+        self.now = self.now.max(time);
+    }
+
+    /// Deliver messages until there is none left in flight
+    pub fn run(&mut self) {
+        while self.step() {}
+    }
+
+    /// The current virtual time
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// The responses received by this client so far
+    pub fn received(&self, client: NodeId) -> &[Received] {
+        match &self.nodes[client] {
+            Node::Client(received) => received,
+            Node::Server(_) => &[],
+        }
+    }
+
+    /// Everything that happened to the messages so far
+    pub fn trace(&self) -> &[TraceEntry] {
+        &self.trace
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::secp_sig,
+        message::{
+            coordinator::{GetSigs, Sig, SigResult, Sigs},
+            with_id_generator, SequentialIds,
+        },
+        server::coordinator::{Coordinator, MemoryStorage},
+    };
+    use bitcoin::Txid;
+
+    fn sig() -> Sig {
+        let (pubkey, signature) = secp_sig();
+        Sig {
+            pubkey,
+            signature,
+            id: Txid::default(),
+        }
+    }
+
+    // A stakeholder shares a signature while a manager polls for it
+    fn scenario(seed: u64, link: Link) -> Simulation {
+        let mut sim = Simulation::new(seed);
+        let coordinator = sim.add_server(Coordinator::new(MemoryStorage::new()));
+        let (stakeholder, manager) = (sim.add_client(), sim.add_client());
+        sim.link(stakeholder, coordinator, link)
+            .link(manager, coordinator, link);
+
+        with_id_generator(SequentialIds(0), || {
+            sim.send(stakeholder, coordinator, &sig().into());
+            for _ in 0..10 {
+                sim.send(
+                    manager,
+                    coordinator,
+                    &GetSigs {
+                        id: Txid::default(),
+                        if_none_match: None,
+                    }
+                    .into(),
+                );
+                let next = sim.now() + Duration::from_millis(20);
+                sim.run_until(next);
+            }
+        });
+        sim.run();
+        sim
+    }
+
+    #[test]
+    fn simulation() {
+        let link = Link::new(Duration::from_millis(50))
+            .jitter(Duration::from_millis(100))
+            .reorder(0.2)
+            .loss(0.1);
+
+        // Reproducible
+        let sim = scenario(7, link);
+        assert_eq!(sim.trace(), scenario(7, link).trace());
+        assert_ne!(sim.trace(), scenario(8, link).trace());
+
+        // Some polls were answered, before or after the signature was stored
+        let sigs: Vec<usize> = sim
+            .received(2)
+            .iter()
+            .map(|r| r.result::<Sigs>().unwrap().signatures.len())
+            .collect();
+        assert!(
+            !sigs.is_empty() && sigs.iter().all(|n| *n <= 1),
+            "{:?}",
+            sigs
+        );
+        assert!(sim
+            .trace()
+            .iter()
+            .all(|entry| entry.kind != TraceKind::Unreachable));
+
+        // A partition drops the messages, during its time only
+        let mut sim = Simulation::new(0);
+        let coordinator = sim.add_server(Coordinator::new(MemoryStorage::new()));
+        let client = sim.add_client();
+        sim.link(client, coordinator, Link::new(Duration::from_millis(10)))
+            .partition(
+                client,
+                coordinator,
+                Duration::from_millis(0),
+                Duration::from_millis(100),
+            );
+        sim.send(client, coordinator, &sig().into());
+        sim.run();
+        assert!(sim.received(client).is_empty());
+        assert_eq!(sim.trace().last().unwrap().kind, TraceKind::Unreachable);
+        sim.run_until(Duration::from_millis(100));
+        sim.send(client, coordinator, &sig().into());
+        sim.run();
+        let received = sim.received(client);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].at, Duration::from_millis(120));
+        assert_eq!(
+            received[0].result::<SigResult>().unwrap(),
+            SigResult::stored()
+        );
+
+        // Unlinked nodes can't talk
+        let other = sim.add_client();
+        sim.send(other, coordinator, &sig().into());
+        assert_eq!(sim.trace().last().unwrap().kind, TraceKind::Unreachable);
+    }
+}
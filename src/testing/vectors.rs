@@ -0,0 +1,204 @@
+//! Conformance test vectors
+//!
+//! Examples of messages, such as the ones of the
+//! [practical-revault](https://github.com/revault/practical-revault/blob/master/messages.md)
+//! specification, stored as JSON files: one message per file, laid out as in the
+//! specification. This crate must parse each of them and serialize it back to the same
+//! JSON document, so that a drift between the specification and the implementation is
+//! caught.
+//!
+//! Vectors for all the messages are kept in `contrib/test_vectors`, and checked by the
+//! tests. They are written by hand after the specification: a vector dumped by this
+//! crate's serializer would only check it against itself.
+
+use crate::message::{ErrorResponse, Notification, Request, Response, ResponseResult};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    error, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The kind of message a vector was parsed as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorKind {
+    /// A [Request]
+    Request,
+    /// A [Notification]
+    Notification,
+    /// A [Response]
+    Response,
+    /// An [ErrorResponse]
+    ErrorResponse,
+}
+
+/// Why a vector failed the check
+#[derive(Debug)]
+pub enum VectorError {
+    /// The vector could not be parsed as any message, with the error it failed with
+    /// as a response.
+    Parse(serde_json::Error),
+    /// The vector was parsed, but serialized back to a different document
+    Mismatch {
+        /// What it was parsed as
+        kind: VectorKind,
+        /// What it was serialized back to
+        serialized: String,
+    },
+}
+
+impl fmt::Display for VectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Parse(e) => write!(f, "Parsing vector: '{}'", e),
+            Self::Mismatch { kind, serialized } => {
+                write!(f, "{:?} serialized back as '{}'", kind, serialized)
+            }
+        }
+    }
+}
+
+impl error::Error for VectorError {}
+
+/// A message example
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestVector {
+    /// The file it was read from
+    pub path: PathBuf,
+    /// The message, without the trailing whitespaces of the file
+    pub message: Vec<u8>,
+}
+
+// Parse the message as a T, and check it serializes back to the same document. The
+// layout of the vectors (whitespaces, order of the members) is not significant.
+fn check_as<'a, T: Deserialize<'a> + Serialize>(
+    message: &'a [u8],
+    kind: VectorKind,
+) -> Result<Result<VectorKind, VectorError>, serde_json::Error> {
+    let parsed: T = serde_json::from_slice(message)?;
+    let serialized = serde_json::to_vec(&parsed).expect("Serializing a message");
+    let document: serde_json::Value = serde_json::from_slice(message)?;
+    let reserialized: serde_json::Value =
+        serde_json::from_slice(&serialized).expect("Parsing a serialized message");
+    Ok(if reserialized == document {
+        Ok(kind)
+    } else {
+        Err(VectorError::Mismatch {
+            kind,
+            serialized: String::from_utf8_lossy(&serialized).into_owned(),
+        })
+    })
+}
+
+impl TestVector {
+    /// Read a vector from this file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut message = fs::read(path)?;
+        while message.last().is_some_and(|b| b.is_ascii_whitespace()) {
+            message.pop();
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            message,
+        })
+    }
+
+    /// Check this crate round-trips the message, and get the kind of message it is.
+    pub fn check(&self) -> Result<VectorKind, VectorError> {
+        let msg = &self.message[..];
+
+        // Requests and notifications are told apart by their id, responses by their
+        // result or error. The parse errors are thus only meaningful for the last.
+        check_as::<Request>(msg, VectorKind::Request)
+            .or_else(|_| check_as::<Notification>(msg, VectorKind::Notification))
+            .or_else(|_| check_as::<ErrorResponse>(msg, VectorKind::ErrorResponse))
+            .or_else(|_| check_as::<Response<ResponseResult>>(msg, VectorKind::Response))
+            .unwrap_or_else(|e| Err(VectorError::Parse(e)))
+    }
+}
+
+/// Load all the vectors (files with a `.json` extension) in this directory and its
+/// subdirectories, sorted by path.
+pub fn load_vectors<P: AsRef<Path>>(dir: P) -> io::Result<Vec<TestVector>> {
+    let mut vectors = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            vectors.extend(load_vectors(&path)?);
+        } else if path.extension().is_some_and(|ext| ext == "json") {
+            vectors.push(TestVector::from_file(&path)?);
+        }
+    }
+
+    vectors.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(vectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_all(vectors: &[TestVector]) {
+        let failures: Vec<String> = vectors
+            .iter()
+            .filter_map(|v| {
+                v.check()
+                    .err()
+                    .map(|e| format!("{}: {}", v.path.display(), e))
+            })
+            .collect();
+        assert!(failures.is_empty(), "{:#?}", failures);
+    }
+
+    #[test]
+    fn test_vectors() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("contrib/test_vectors");
+        let vectors = load_vectors(&dir).unwrap();
+        assert_eq!(vectors.len(), 17);
+        check_all(&vectors);
+        let kind = |name: &str| {
+            vectors
+                .iter()
+                .find(|v| v.path.file_stem().unwrap() == name)
+                .unwrap()
+                .check()
+                .unwrap()
+        };
+        assert_eq!(kind("get_sigs"), VectorKind::Request);
+        assert_eq!(kind("new_sig"), VectorKind::Notification);
+        assert_eq!(kind("sigs"), VectorKind::Response);
+        assert_eq!(kind("error"), VectorKind::ErrorResponse);
+
+        let vector = |message: &[u8]| TestVector {
+            path: PathBuf::new(),
+            message: message.to_vec(),
+        };
+        assert_eq!(
+            vector(b"{\n    \"id\": 0,\n    \"result\": {\"ack\": true}\n}")
+                .check()
+                .unwrap(),
+            VectorKind::Response
+        );
+        assert!(matches!(
+            vector(br#"{"result":{"ack":true},"id":0,"jsonrpc":"2.0"}"#).check(),
+            Err(VectorError::Mismatch {
+                kind: VectorKind::Response,
+                ..
+            })
+        ));
+        assert!(matches!(
+            vector(br#"{"result":{"ack":true,"txid":"00000000000000000000000000000000000000000000000000000000000000AA"},"id":0}"#).check(),
+            Err(VectorError::Mismatch {
+                kind: VectorKind::Response,
+                ..
+            })
+        ));
+        assert!(matches!(
+            vector(br#"{"id":0}"#).check(),
+            Err(VectorError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,162 @@
+//! Bandwidth throttling
+//!
+//! A coordinator co-hosted with other services may need to bound the bandwidth the
+//! Revault protocol consumes. A [RateLimiter] is a token bucket of bytes: once its
+//! burst is spent, a [KKTransport](crate::transport::KKTransport) taking from it
+//! sleeps before reading or writing a frame until the bytes are replenished at its
+//! rate. Reading later makes the peer's writes block too, through TCP flow control.
+//!
+//! Limiters can be set per connection and direction with
+//! [set_rate_limits](crate::transport::KKTransport::set_rate_limits), and for all
+//! connections with [set_global_limits]. A clone of a limiter shares its bucket, so
+//! that the same limit may also apply to a group of connections.
+
+use crate::metrics::Direction;
+
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct Bucket {
+    // Negative when in debt: a frame larger than the tokens available was let through
+    tokens: f64,
+    last: Instant,
+}
+
+/// A limit on the rate of bytes read or written, shared by its clones
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    burst: u64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    /// A limit of this number of bytes per second, with bursts of up to a second's
+    /// worth of bytes.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        assert!(bytes_per_sec > 0, "The rate must not be null");
+        Self {
+            bytes_per_sec,
+            burst: bytes_per_sec,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: bytes_per_sec as f64,
+                last: Instant::now(),
+            })),
+        }
+    }
+
+    /// Allow bursts of up to this number of bytes instead
+    pub fn with_burst(self, burst: u64) -> Self {
+        self.bucket.lock().unwrap().tokens = burst as f64;
+        Self { burst, ..self }
+    }
+
+    /// The number of bytes per second allowed
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Take this number of bytes from the bucket, returning how long to wait before
+    /// using them.
+    fn reserve_at(&self, bytes: usize, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+        bucket.tokens =
+            (bucket.tokens + elapsed * self.bytes_per_sec as f64).min(self.burst as f64);
+        bucket.last = now.max(bucket.last);
+        bucket.tokens -= bytes as f64;
+
+        if bucket.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.bytes_per_sec as f64)
+        }
+    }
+
+    /// Take this number of bytes from the bucket, sleeping until they are available.
+    pub fn take(&self, bytes: usize) {
+        let delay = self.reserve_at(bytes, Instant::now());
+        if delay > Duration::from_secs(0) {
+            thread::sleep(delay);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Limits {
+    pub inbound: Option<RateLimiter>,
+    pub outbound: Option<RateLimiter>,
+}
+
+impl Limits {
+    const NONE: Self = Self {
+        inbound: None,
+        outbound: None,
+    };
+}
+
+static GLOBAL: RwLock<Limits> = RwLock::new(Limits::NONE);
+
+/// Limit the bytes read (`inbound`) and written (`outbound`) by all the transports
+/// from now on, in addition to their own limits. `None` removes a limit.
+pub fn set_global_limits(inbound: Option<RateLimiter>, outbound: Option<RateLimiter>) {
+    *GLOBAL.write().unwrap() = Limits { inbound, outbound };
+}
+
+// Take these bytes from the global limiter and this one for this direction, sleeping
+// until they are available from both.
+pub(crate) fn take(limiter: Option<&RateLimiter>, direction: Direction, bytes: usize) {
+    let global = {
+        let global = GLOBAL.read().unwrap();
+        match direction {
+            Direction::Inbound => global.inbound.clone(),
+            Direction::Outbound => global.outbound.clone(),
+        }
+    };
+    if limiter.is_none() && global.is_none() {
+        return;
+    }
+
+    let now = Instant::now();
+    let delay = limiter
+        .into_iter()
+        .chain(global.as_ref())
+        .map(|limiter| limiter.reserve_at(bytes, now))
+        .max()
+        .expect("At least one limiter");
+    if delay > Duration::from_secs(0) {
+        thread::sleep(delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let limiter = RateLimiter::new(1_000).with_burst(2_000);
+        let now = Instant::now();
+
+        // The burst goes through, then it's throttled
+        assert_eq!(limiter.reserve_at(1_500, now), Duration::from_secs(0));
+        assert_eq!(limiter.reserve_at(500, now), Duration::from_secs(0));
+        assert_eq!(limiter.reserve_at(500, now), Duration::from_millis(500));
+        // The debt is repaid first
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limiter.reserve_at(1_000, later), Duration::from_secs(1));
+
+        // Clones share the bucket, and it never holds more than the burst
+        let clone = limiter.clone();
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(clone.reserve_at(2_000, much_later), Duration::from_secs(0));
+        assert_eq!(
+            limiter.reserve_at(100, much_later),
+            Duration::from_millis(100)
+        );
+    }
+}
@@ -3,19 +3,96 @@
 //! This module is a wrapper for TCP functionality that uses noise API internally
 //! to automagically provide encrypted and authenticated channels.
 //!
+//! This crate does not implement TLS, and the connections are always plain TCP ones.
+//! Standard TLS infrastructure (corporate proxies, load balancers) is only supported
+//! through a TLS tunnel set up outside of this crate, such as stunnel or ghostunnel,
+//! exposing the tunneled connection as a local TCP one: [KKTransport::connect_stream]
+//! and [KKTransport::accept_stream] enact the handshake over it. The message layer is
+//! unchanged.
+//!
+//! The tunables of a connection can be given at once as a
+//! [TransportConfig](crate::config::TransportConfig), to
+//! [KKTransport::connect_with_config] and [KKTransport::accept_with_config].
 
 use crate::{
-    error::Error,
+    capabilities::{Advertisement, Capabilities, Capability},
+    capture::Capture,
+    codec::WireCodec,
+    config::TransportConfig,
+    dns,
+    error::{from_json_slice, Error, JsonError, MessageError},
+    events::{self, ConnectionEvent, DropReason, HandshakeSide},
+    instrument::{self, log_trace},
     message,
+    metrics::{Direction, Side},
     noise::{
-        KKChannel, KKHandshakeActOne, KKHandshakeActTwo, KKMessageActOne, KKMessageActTwo,
-        NoiseEncryptedHeader, NoiseEncryptedMessage, PublicKey, SecretKey, KK_MSG_1_SIZE,
-        KK_MSG_2_SIZE, NOISE_MESSAGE_HEADER_SIZE,
+        encrypted_msg_size, KKChannel, KKHandshakeActOne, KKHandshakeActTwo, KKMessageActOne,
+        KKMessageActTwo, NoiseEncryptedHeader, NoiseEncryptedMessage, PublicKey, SecretKey,
+        HANDSHAKE_HASH_SIZE, KK_MSG_1_SIZE, KK_MSG_2_SIZE, NOISE_MESSAGE_HEADER_SIZE,
+        NOISE_PLAINTEXT_MAX_SIZE,
     },
+    padding::{is_cover, CoverTraffic, Padding},
+    pool::BufferPool,
+    prelude::Prelude,
+    quarantine::Quarantine,
+    server::access::PeerPolicy,
+    throttle::{self, Limits, RateLimiter},
+    validation::ValidationConfig,
+};
+use serde::de::IgnoredAny;
+use serde_json::value::RawValue;
+use std::borrow::Cow;
+use std::collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    mpsc, Arc,
 };
-use std::io::{Read, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, thread};
+
+// Messages up to this size (most signatures and requests for signatures) are
+// serialized and encrypted on the stack, without any heap allocation
+const SMALL_MESSAGE_SIZE: usize = 1024;
+
+/// The number of most recent request ids of a connection checked for collisions
+pub const ID_HISTORY_SIZE: usize = 4096;
+
+// The ids of the most recent requests sent on a connection, with a digest of the
+// request they were sent for. A request sent again with the same id is a retry, another
+// request with the same id a collision: the responses would be mixed up, and the server
+// may take it for a retry (see crate::server::Dedup).
+#[derive(Debug, Default)]
+struct SentIds {
+    // From the oldest
+    ids: VecDeque<u32>,
+    digests: HashMap<u32, u64>,
+}
+
+impl SentIds {
+    // Record a request about to be sent, failing if another one was sent with its id
+    fn record(&mut self, req: &message::Request) -> Result<(), MessageError> {
+        let id = req.id();
+        let mut hasher = DefaultHasher::new();
+        req.hash(&mut hasher);
+        let digest = hasher.finish();
+
+        match self.digests.get(&id) {
+            Some(known) if *known == digest => return Ok(()),
+            Some(_) => return Err(MessageError::IdCollision(id)),
+            None => {}
+        }
+        if self.ids.len() == ID_HISTORY_SIZE {
+            let oldest = self.ids.pop_front().expect("Not empty");
+            self.digests.remove(&oldest);
+        }
+        self.ids.push_back(id);
+        self.digests.insert(id, digest);
+        Ok(())
+    }
+}
 
 /// Wrapper type for a TcpStream and KKChannel that automatically enforces authenticated and
 /// encrypted channels when communicating
@@ -23,6 +100,101 @@ use std::time::Duration;
 pub struct KKTransport {
     stream: TcpStream,
     channel: KKChannel,
+    // Notifications we read while waiting for a response
+    notifications: VecDeque<Vec<u8>>,
+    // Ids of the requests we stopped waiting a response for
+    abandoned: HashSet<u32>,
+    // Ids of the most recent requests we sent
+    sent_ids: SentIds,
+    // Where to record the messages we exchange, if anywhere
+    capture: Option<Arc<Capture>>,
+    quarantine: Option<Arc<Quarantine>>,
+    // What to actually write for each encrypted frame, for fault injection
+    frame_hook: Option<FrameHook>,
+    // Where to take the frame buffers from
+    pool: Arc<BufferPool>,
+    // The version of the protocol agreed on in the prelude, if one was exchanged
+    protocol_version: Option<u8>,
+    // The capabilities negotiated with the peer, if we exchanged them
+    capabilities: Option<Capabilities>,
+    // Whether the connection was shut down through a ShutdownHandle
+    shutdown: Arc<AtomicBool>,
+    // The bandwidth limits of this connection
+    limits: Limits,
+    // The sizes to pad the messages we send to, if any
+    padding: Option<Padding>,
+    // When to send dummy frames, and when the next one is due
+    cover: Option<(CoverTraffic, Instant)>,
+    // The size of the largest message we accept to read, at most a Noise message
+    max_message_size: u16,
+    // How the messages are encoded on the wire, if not as serialized
+    codec: Option<Arc<dyn WireCodec>>,
+    // The limits checked on the messages we read, if not the ones of the thread
+    validation: Option<ValidationConfig>,
+    // The id of the next request created through this connection, if they are
+    // numbered from a counter rather than random
+    next_id: Option<AtomicU32>,
+    // Whether we connected to the peer or accepted its connection
+    side: HandshakeSide,
+    // The address of the other end of the stream, if it could be read once connected
+    peer_addr: Option<SocketAddr>,
+    // When the handshake completed
+    established_at: SystemTime,
+    // The frame we are in the middle of reading, if a read timed out before its end
+    partial: PartialFrame,
+}
+
+// An encrypted frame being read. It is kept when a read times out in its middle for the
+// next read to resume it, as the bytes read so far would otherwise be lost and the next
+// frame decrypted with the wrong nonce.
+#[derive(Debug, Default)]
+struct PartialFrame {
+    header: [u8; NOISE_MESSAGE_HEADER_SIZE],
+    header_read: usize,
+    // The body and how much of it was read, once the header was decrypted
+    body: Option<(Vec<u8>, usize)>,
+}
+
+// Fill `buf` from the `filled`-th byte, keeping count in `filled` of the bytes read for the
+// read to be resumed where it failed.
+fn read_resumable(stream: &mut TcpStream, buf: &mut [u8], filled: &mut usize) -> io::Result<()> {
+    while *filled < buf.len() {
+        match stream.read(&mut buf[*filled..]) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(n) => *filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Fill `buf` from the stream before this deadline, however slowly the peer sends it
+fn read_exact_before(stream: &mut TcpStream, buf: &mut [u8], deadline: Instant) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout == Duration::from_secs(0) {
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        stream.set_read_timeout(Some(timeout))?;
+        match stream.read(&mut buf[filled..]) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Given an encrypted frame, returns the bytes to write in its place
+pub(crate) struct FrameHook(Box<dyn FnMut(Vec<u8>) -> Vec<Vec<u8>> + Send>);
+
+impl std::fmt::Debug for FrameHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "FrameHook")
+    }
 }
 
 impl KKTransport {
@@ -32,25 +204,307 @@ impl KKTransport {
         addr: SocketAddr,
         my_noise_privkey: &SecretKey,
         their_noise_pubkey: &PublicKey,
+    ) -> Result<KKTransport, Error> {
+        Self::connect_with_config(
+            addr,
+            my_noise_privkey,
+            their_noise_pubkey,
+            &TransportConfig::default(),
+        )
+    }
+
+    /// Connect to server at given address, possibly through a proxy, and enact Noise
+    /// handshake with given private key, the connection being set up according to this
+    /// [TransportConfig].
+    pub fn connect_with_config(
+        addr: SocketAddr,
+        my_noise_privkey: &SecretKey,
+        their_noise_pubkey: &PublicKey,
+        config: &TransportConfig,
+    ) -> Result<KKTransport, Error> {
+        config.connect(addr, |stream| {
+            Self::initiate_with_config(stream, my_noise_privkey, their_noise_pubkey, config)
+        })
+    }
+
+    /// Connect to server at given hostname (or IP address) and port and enact Noise
+    /// handshake with given private key, the connection being set up according to this
+    /// [TransportConfig]. Through a proxy, the hostname is resolved by the proxy (for
+    /// instance to reach a Tor hidden service, see
+    /// [TransportConfig::with_tor_retry]). Otherwise it is resolved like for
+    /// [KKTransport::connect_host].
+    pub fn connect_host_with_config(
+        host: &str,
+        port: u16,
+        my_noise_privkey: &SecretKey,
+        their_noise_pubkey: &PublicKey,
+        config: &TransportConfig,
+    ) -> Result<KKTransport, Error> {
+        if let Some(proxy) = config.proxy() {
+            return config.connect_proxied(proxy, host, port, |stream| {
+                Self::initiate_with_config(stream, my_noise_privkey, their_noise_pubkey, config)
+            });
+        }
+
+        let mut error = None;
+        for addr in dns::resolve(host, port).map_err(Error::Transport)? {
+            match Self::connect_with_config(addr, my_noise_privkey, their_noise_pubkey, config) {
+                Ok(transport) => return Ok(transport),
+                Err(e) => error = Some(e),
+            }
+        }
+
+        Err(error.expect("At least one address was resolved"))
+    }
+
+    // Exchange the prelude if configured, enact the handshake and configure the
+    // transport
+    fn initiate_with_config(
+        mut stream: TcpStream,
+        my_noise_privkey: &SecretKey,
+        their_noise_pubkey: &PublicKey,
+        config: &TransportConfig,
+    ) -> Result<KKTransport, Error> {
+        let version = config
+            .prelude()
+            .map(|prelude| prelude.initiate(&mut stream))
+            .transpose()?;
+        let mut transport = Self::connect_stream(stream, my_noise_privkey, their_noise_pubkey)?;
+        transport.protocol_version = version;
+        config.apply(&mut transport)?;
+        Ok(transport)
+    }
+
+    /// Enact the Noise handshake with given private key on an already established
+    /// TCP connection to the server, as an initiator. This allows to reach the server
+    /// through a connection set up by the caller, such as one to a local TLS tunnel
+    /// (stunnel, ghostunnel, ..) to a server behind a TLS-terminating proxy or load
+    /// balancer. TLS itself is not supported, see the [module docs](self). The read
+    /// timeout of the stream is left as is.
+    pub fn connect_stream(
+        mut stream: TcpStream,
+        my_noise_privkey: &SecretKey,
+        their_noise_pubkey: &PublicKey,
+    ) -> Result<KKTransport, Error> {
+        instrument::handshake(HandshakeSide::Initiator, Some(their_noise_pubkey), || {
+            let (cli_act_1, msg_1) =
+                KKHandshakeActOne::initiator(my_noise_privkey, their_noise_pubkey)?;
+
+            // write msg_1 to stream (e, es, ss)
+            stream.write_all(&msg_1.0)?;
+
+            // read msg_2 from stream (e, ee, se)
+            let mut msg_2 = [0u8; KK_MSG_2_SIZE];
+            stream.read_exact(&mut msg_2)?;
+
+            let msg_act_2 = KKMessageActTwo(msg_2);
+            let cli_act_2 = KKHandshakeActTwo::initiator(cli_act_1, &msg_act_2)?;
+            let channel = KKChannel::from_handshake(cli_act_2)?;
+            Ok(KKTransport::new(stream, channel, HandshakeSide::Initiator))
+        })
+    }
+
+    /// Connect to server at given address, exchange our [Prelude] with it and enact the
+    /// Noise handshake with given private key. Fails early with a
+    /// [PreludeError](crate::PreludeError) if the server is not a Revault endpoint on
+    /// our network and supporting one of our protocol versions, see
+    /// [KKTransport::protocol_version]. Sets a read timeout of 20 seconds.
+    pub fn connect_with_prelude(
+        addr: SocketAddr,
+        my_noise_privkey: &SecretKey,
+        their_noise_pubkey: &PublicKey,
+        prelude: &Prelude,
     ) -> Result<KKTransport, Error> {
         let timeout = Duration::from_secs(20);
         let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
         stream.set_read_timeout(Some(timeout))?;
+        let version = prelude.initiate(&mut stream)?;
 
-        let (cli_act_1, msg_1) =
-            KKHandshakeActOne::initiator(my_noise_privkey, their_noise_pubkey)?;
+        let mut transport = Self::connect_stream(stream, my_noise_privkey, their_noise_pubkey)?;
+        transport.protocol_version = Some(version);
+        Ok(transport)
+    }
 
-        // write msg_1 to stream (e, es, ss)
-        stream.write_all(&msg_1.0)?;
+    /// Connect to server at given hostname (or IP address) and port, and enact Noise
+    /// handshake with given private key. The hostname is resolved by the
+    /// [resolver](crate::dns::set_resolver) set, and each of its addresses is tried in
+    /// order.
+    pub fn connect_host(
+        host: &str,
+        port: u16,
+        my_noise_privkey: &SecretKey,
+        their_noise_pubkey: &PublicKey,
+    ) -> Result<KKTransport, Error> {
+        let mut error = None;
+        for addr in dns::resolve(host, port).map_err(Error::Transport)? {
+            match Self::connect(addr, my_noise_privkey, their_noise_pubkey) {
+                Ok(transport) => return Ok(transport),
+                Err(e) => error = Some(e),
+            }
+        }
 
-        // read msg_2 from stream (e, ee, se)
-        let mut msg_2 = [0u8; KK_MSG_2_SIZE];
-        stream.read_exact(&mut msg_2)?;
+        Err(error.expect("At least one address was resolved"))
+    }
+
+    /// Start connecting to server at given address and enacting the Noise handshake
+    /// with given private key, without blocking. The returned handshake is completed
+    /// by calling [PendingHandshake::advance] until it's done, and dropping it cancels
+    /// the attempt. The connection and the handshake must complete within 20 seconds,
+    /// and like [KKTransport::connect] a read timeout of 20 seconds is set.
+    pub fn connect_nonblocking(
+        addr: SocketAddr,
+        my_noise_privkey: &SecretKey,
+        their_noise_pubkey: &PublicKey,
+    ) -> Result<PendingHandshake, Error> {
+        let timeout = Duration::from_secs(20);
+        let (act_one, msg_1) = KKHandshakeActOne::initiator(my_noise_privkey, their_noise_pubkey)?;
+
+        // The standard library can only connect blocking, do it in the background. If
+        // the handshake is dropped meanwhile the stream is just closed once connected.
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("revault_net connect".to_string())
+            .spawn(move || {
+                let _ = sender.send(TcpStream::connect_timeout(&addr, timeout));
+            })?;
+
+        Ok(PendingHandshake {
+            connection: Connection::Connecting(receiver),
+            act_one: Box::new(act_one),
+            msg_1,
+            written: 0,
+            msg_2: [0u8; KK_MSG_2_SIZE],
+            read: 0,
+            deadline: Instant::now() + timeout,
+            timeout,
+            peer: *their_noise_pubkey,
+        })
+    }
+
+    fn new(stream: TcpStream, channel: KKChannel, side: HandshakeSide) -> Self {
+        let peer_addr = stream.peer_addr().ok();
+        KKTransport {
+            stream,
+            channel,
+            notifications: VecDeque::new(),
+            abandoned: HashSet::new(),
+            sent_ids: SentIds::default(),
+            capture: None,
+            quarantine: None,
+            frame_hook: None,
+            pool: BufferPool::global(),
+            protocol_version: None,
+            capabilities: None,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            limits: Limits::default(),
+            padding: None,
+            cover: None,
+            max_message_size: NOISE_PLAINTEXT_MAX_SIZE as u16,
+            codec: None,
+            validation: None,
+            next_id: None,
+            side,
+            peer_addr,
+            established_at: SystemTime::now(),
+            partial: PartialFrame::default(),
+        }
+    }
+
+    // Replace the encrypted frames we write with the output of this function
+    #[cfg(any(all(test, feature = "revault_tx"), feature = "testing"))]
+    pub(crate) fn set_frame_hook<F>(&mut self, hook: Option<F>)
+    where
+        F: FnMut(Vec<u8>) -> Vec<Vec<u8>> + Send + 'static,
+    {
+        self.frame_hook = hook.map(|hook| FrameHook(Box::new(hook)));
+    }
+
+    /// Record a decrypted copy of all the messages sent and received from now on to
+    /// this [Capture], or stop recording them if `None`. For debugging purposes.
+    pub fn set_capture(&mut self, capture: Option<Arc<Capture>>) {
+        self.capture = capture;
+    }
+
+    /// Keep a redacted copy of the requests read from now on that are rejected for
+    /// being invalid in this [Quarantine], or stop keeping them if `None`. For debugging
+    /// purposes.
+    pub fn set_quarantine(&mut self, quarantine: Option<Arc<Quarantine>>) {
+        self.quarantine = quarantine;
+    }
+
+    /// Take the buffers for the frames read and written from now on from this pool
+    /// rather than the [global](BufferPool::global) one.
+    pub fn set_buffer_pool(&mut self, pool: Arc<BufferPool>) {
+        self.pool = pool;
+    }
 
-        let msg_act_2 = KKMessageActTwo(msg_2);
-        let cli_act_2 = KKHandshakeActTwo::initiator(cli_act_1, &msg_act_2)?;
-        let channel = KKChannel::from_handshake(cli_act_2)?;
-        Ok(KKTransport { stream, channel })
+    /// Limit the rate of the bytes read (`inbound`) and written (`outbound`) on this
+    /// connection from now on, in addition to the [global](throttle::set_global_limits)
+    /// limits. `None` removes a limit.
+    pub fn set_rate_limits(&mut self, inbound: Option<RateLimiter>, outbound: Option<RateLimiter>) {
+        self.limits = Limits { inbound, outbound };
+    }
+
+    /// Pad the messages sent from now on to the sizes of this [Padding], or stop padding
+    /// them if `None`.
+    pub fn set_padding(&mut self, padding: Option<Padding>) {
+        self.padding = padding;
+    }
+
+    /// Send dummy frames at the intervals of this [CoverTraffic] from now on while
+    /// waiting for a frame on an idle connection, or stop sending them if `None`. The
+    /// peer must drop them, which this version of the library does.
+    pub fn set_cover_traffic(&mut self, cover: Option<CoverTraffic>) {
+        self.cover = cover.map(|cover| (cover, Instant::now() + cover.next_interval()));
+    }
+
+    /// Refuse the messages larger than this size read from now on. They are dropped,
+    /// and the read fails with a
+    /// [MessageError::TooLargeMessage](crate::error::MessageError::TooLargeMessage).
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size.min(NOISE_PLAINTEXT_MAX_SIZE) as u16;
+    }
+
+    /// Check the messages read from now on against the limits of this
+    /// [ValidationConfig], or against the ones of the current thread if `None` (see
+    /// [ValidationConfig::scope]).
+    pub fn set_validation_config(&mut self, config: Option<ValidationConfig>) {
+        self.validation = config;
+    }
+
+    // Parse a message we read, within the validation limits of this connection
+    fn validated<T>(&self, parse: impl FnOnce() -> T) -> T {
+        match &self.validation {
+            Some(config) => config.scope(parse),
+            None => parse(),
+        }
+    }
+
+    /// Encode the messages sent and decode the messages read from now on with this
+    /// [WireCodec], or send them as serialized if `None`. The peer must use the same.
+    pub fn set_codec(&mut self, codec: Option<Arc<dyn WireCodec>>) {
+        self.codec = codec;
+    }
+
+    /// Number the requests created through [KKTransport::request] from a per-connection
+    /// counter starting at this id, rather than randomly. Makes the logs of both ends
+    /// easier to correlate, and the order the requests were sent in obvious to the
+    /// server. `None` goes back to random ids.
+    pub fn set_sequential_ids(&mut self, first_id: Option<u32>) {
+        self.next_id = first_id.map(AtomicU32::new);
+    }
+
+    /// Create a request to send on this connection, with the next id of its counter if
+    /// the ids are sequential (see [KKTransport::set_sequential_ids]). A [message::Request]
+    /// that was already created keeps its id.
+    pub fn request<'a, P: Into<message::Request<'a>>>(&self, params: P) -> message::Request<'a> {
+        match &self.next_id {
+            Some(next_id) => {
+                let id = next_id.fetch_add(1, Ordering::Relaxed);
+                message::scoped_id_generator(message::SequentialIds(id), || params.into())
+            }
+            None => params.into(),
+        }
     }
 
     /// Accept an incoming connection and immediately perform the noise KK handshake
@@ -61,41 +515,206 @@ impl KKTransport {
         my_noise_privkey: &SecretKey,
         their_possible_pubkeys: &[PublicKey],
     ) -> Result<KKTransport, Error> {
-        let (mut stream, _) = listener.accept().map_err(|e| Error::Transport(e))?;
+        let (stream, _) = listener.accept().map_err(Error::Transport)?;
+        Self::accept_stream(stream, my_noise_privkey, their_possible_pubkeys)
+    }
+
+    /// Accept an incoming connection and perform the noise KK handshake as a responder,
+    /// the connection being set up according to this [TransportConfig]. Unlike with
+    /// [KKTransport::accept], the handshake must complete within the connection timeout
+    /// of the configuration.
+    pub fn accept_with_config(
+        listener: &TcpListener,
+        my_noise_privkey: &SecretKey,
+        their_possible_pubkeys: &[PublicKey],
+        config: &TransportConfig,
+    ) -> Result<KKTransport, Error> {
+        let (mut stream, _) = listener.accept().map_err(Error::Transport)?;
+        stream.set_read_timeout(Some(config.connect_timeout()))?;
+        let version = config
+            .prelude()
+            .map(|prelude| prelude.respond(&mut stream))
+            .transpose()?;
+        let mut transport = Self::accept_stream(stream, my_noise_privkey, their_possible_pubkeys)?;
+        transport.protocol_version = version;
+        config.apply(&mut transport)?;
+        Ok(transport)
+    }
+
+    /// Accept an incoming connection, exchange our [Prelude] with the peer and perform
+    /// the noise KK handshake as a responder. Fails early with a
+    /// [PreludeError](crate::PreludeError) if the peer is not a Revault endpoint on our
+    /// network and supporting one of our protocol versions, see
+    /// [KKTransport::protocol_version].
+    pub fn accept_with_prelude(
+        listener: &TcpListener,
+        my_noise_privkey: &SecretKey,
+        their_possible_pubkeys: &[PublicKey],
+        prelude: &Prelude,
+    ) -> Result<KKTransport, Error> {
+        let (mut stream, _) = listener.accept().map_err(Error::Transport)?;
+        let version = prelude.respond(&mut stream)?;
+        let mut transport = Self::accept_stream(stream, my_noise_privkey, their_possible_pubkeys)?;
+        transport.protocol_version = Some(version);
+        Ok(transport)
+    }
+
+    /// Perform the noise KK handshake as a responder on an already accepted TCP
+    /// connection, for instance one forwarded by the local end of a TLS tunnel behind a
+    /// TLS-terminating proxy. TLS itself is not supported, see the [module docs](self).
+    /// The read timeout of the stream is left as is.
+    pub fn accept_stream(
+        stream: TcpStream,
+        my_noise_privkey: &SecretKey,
+        their_possible_pubkeys: &[PublicKey],
+    ) -> Result<KKTransport, Error> {
+        Self::accept_stream_before(stream, my_noise_privkey, their_possible_pubkeys, None)
+    }
 
-        // read msg_1 from stream
-        let mut msg_1 = [0u8; KK_MSG_1_SIZE];
-        stream.read_exact(&mut msg_1)?;
-        let msg_act_1 = KKMessageActOne(msg_1);
+    // Perform the noise KK handshake as a responder on an already accepted connection,
+    // failing if the peer didn't complete it before this deadline, if any. The stream is
+    // left without read timeout once done if it has a deadline.
+    pub(crate) fn accept_stream_before(
+        mut stream: TcpStream,
+        my_noise_privkey: &SecretKey,
+        their_possible_pubkeys: &[PublicKey],
+        deadline: Option<Instant>,
+    ) -> Result<KKTransport, Error> {
+        instrument::handshake(HandshakeSide::Responder, None, || {
+            // read msg_1 from stream
+            let mut msg_1 = [0u8; KK_MSG_1_SIZE];
+            match deadline {
+                Some(deadline) => read_exact_before(&mut stream, &mut msg_1, deadline)?,
+                None => stream.read_exact(&mut msg_1)?,
+            }
+            let msg_act_1 = KKMessageActOne(msg_1);
 
-        let serv_act_1 =
-            KKHandshakeActOne::responder(&my_noise_privkey, their_possible_pubkeys, &msg_act_1)?;
-        let (serv_act_2, msg_2) = KKHandshakeActTwo::responder(serv_act_1)?;
-        let channel = KKChannel::from_handshake(serv_act_2)?;
+            let serv_act_1 =
+                KKHandshakeActOne::responder(my_noise_privkey, their_possible_pubkeys, &msg_act_1)?;
+            let (serv_act_2, msg_2) = KKHandshakeActTwo::responder(serv_act_1)?;
+            let channel = KKChannel::from_handshake(serv_act_2)?;
 
-        // write msg_2 to stream
-        stream.write_all(&msg_2.0)?;
+            // write msg_2 to stream
+            stream.write_all(&msg_2.0)?;
+            if deadline.is_some() {
+                stream.set_read_timeout(None)?;
+            }
 
-        Ok(KKTransport { stream, channel })
+            Ok(KKTransport::new(stream, channel, HandshakeSide::Responder))
+        })
     }
 
     // Read an encrypted Noise message from the communication channel
     fn read(&mut self) -> Result<Vec<u8>, Error> {
-        let mut cypherheader = [0u8; NOISE_MESSAGE_HEADER_SIZE];
-        self.stream.read_exact(&mut cypherheader)?;
-        let msg_len = self
+        let mut msg = Vec::new();
+        while !self.read_into(&mut msg)? {}
+        Ok(msg)
+    }
+
+    // Read an encrypted Noise message from the communication channel, and decrypt it
+    // directly into this buffer (replacing its content). Returns false if it was a
+    // dummy frame, which is to be dropped.
+    fn read_into(&mut self, msg: &mut Vec<u8>) -> Result<bool, Error> {
+        self.wait_sending_cover()?;
+        // Resume the frame a previous read stopped in the middle of, if any
+        if self.partial.body.is_none() {
+            let frame = &mut self.partial;
+            if let Err(e) =
+                read_resumable(&mut self.stream, &mut frame.header, &mut frame.header_read)
+            {
+                return Err(self.read_error(e));
+            }
+            self.partial.header_read = 0;
+            let msg_len = self
+                .channel
+                .decrypt_header(&NoiseEncryptedHeader(self.partial.header))?;
+            throttle::take(
+                self.limits.inbound.as_ref(),
+                Direction::Inbound,
+                NOISE_MESSAGE_HEADER_SIZE + msg_len as usize,
+            );
+
+            // Note that `msg_len` cannot be > 65K (2 bytes)
+            let mut body = self.pool.get();
+            body.resize(msg_len as usize, 0);
+            self.partial.body = Some((body, 0));
+        }
+        let (body, body_read) = self.partial.body.as_mut().expect("Set above");
+        if let Err(e) = read_resumable(&mut self.stream, body, body_read) {
+            return Err(self.read_error(e));
+        }
+        let (body, _) = self.partial.body.take().expect("Set above");
+        let cypherbody = NoiseEncryptedMessage(body);
+        let res = self
             .channel
-            .decrypt_header(&NoiseEncryptedHeader(cypherheader))?;
+            .decrypt_message_into(&cypherbody, msg)
+            .map_err(Error::from);
+        self.pool.put(cypherbody.0);
+        res?;
+        if msg.len() > self.max_message_size as usize {
+            return Err(MessageError::TooLargeMessage {
+                size: msg.len() as u64,
+                max_size: self.max_message_size.into(),
+            }
+            .into());
+        }
+        if is_cover(msg) {
+            log_trace!("Dropping a dummy frame");
+            return Ok(false);
+        }
+        if let Some(codec) = &self.codec {
+            if let Cow::Owned(json) = codec.decode(msg)? {
+                *msg = json;
+            }
+        }
+        instrument::message(Direction::Inbound, msg.len());
+        if let Some(capture) = &self.capture {
+            capture.record(Direction::Inbound, msg);
+        }
 
-        // Note that `msg_len` cannot be > 65K (2 bytes)
-        let mut cypherbody = vec![0u8; msg_len as usize];
-        self.stream.read_exact(&mut cypherbody)?;
-        self.channel
-            .decrypt_message(&NoiseEncryptedMessage(cypherbody))
-            .map_err(|e| e.into())
+        Ok(true)
     }
 
-    #[cfg(feature = "fuzz")]
+    // Classify an error reading from the stream, which fails once shut down through a
+    // ShutdownHandle
+    fn read_error(&self, error: io::Error) -> Error {
+        if self.shutdown.load(Ordering::SeqCst) {
+            return Error::Shutdown;
+        }
+        let error = Error::from_stream(error);
+        if let Error::Disconnected(_) = error {
+            events::notify(ConnectionEvent::Disconnected {
+                peer: self.remote_static(),
+                error: &error,
+            });
+        }
+        error
+    }
+
+    // Wait for the next frame, sending the dummy frames due meanwhile if we send cover
+    // traffic. Times out like a read would.
+    fn wait_sending_cover(&mut self) -> Result<(), Error> {
+        if self.cover.is_none() {
+            return Ok(());
+        }
+        let deadline = self.stream.read_timeout()?.map(|t| Instant::now() + t);
+
+        while let Some((_, next_cover)) = self.cover {
+            let until = deadline.map_or(next_cover, |deadline| deadline.min(next_cover));
+            if self.wait_readable(until)? {
+                return Ok(());
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(Error::Timeout(None));
+            }
+            let dummy = vec![b' '; self.padded_len(0)];
+            self.write(&dummy)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(any(test, feature = "fuzz"))]
     #[allow(missing_docs)]
     pub fn pubread(&mut self) -> Result<Vec<u8>, Error> {
         self.read()
@@ -103,101 +722,899 @@ impl KKTransport {
 
     // Encrypt and write a message to the communication channel
     fn write(&mut self, msg: &[u8]) -> Result<(), Error> {
-        let encrypted_msg = self.channel.encrypt_message(msg)?.0;
-        self.stream.write_all(&encrypted_msg).map_err(|e| e.into())
+        if msg.len() <= SMALL_MESSAGE_SIZE && self.frame_hook.is_none() {
+            let mut frame = [0u8; encrypted_msg_size(SMALL_MESSAGE_SIZE)];
+            let size = self.channel.encrypt_message_to_slice(msg, &mut frame)?;
+            self.throttle_write(size);
+            self.stream
+                .write_all(&frame[..size])
+                .map_err(Error::from_stream)?;
+            self.written(msg);
+            return Ok(());
+        }
+
+        let mut encrypted_msg = self.pool.get();
+        if let Err(e) = self.channel.encrypt_message_into(msg, &mut encrypted_msg) {
+            self.pool.put(encrypted_msg);
+            return Err(e.into());
+        }
+        match &mut self.frame_hook {
+            Some(FrameHook(hook)) => {
+                for frame in hook(encrypted_msg) {
+                    throttle::take(
+                        self.limits.outbound.as_ref(),
+                        Direction::Outbound,
+                        frame.len(),
+                    );
+                    self.stream.write_all(&frame).map_err(Error::from_stream)?;
+                }
+            }
+            None => {
+                self.throttle_write(encrypted_msg.len());
+                let written = self.stream.write_all(&encrypted_msg);
+                self.pool.put(encrypted_msg);
+                written.map_err(Error::from_stream)?;
+            }
+        }
+        self.written(msg);
+
+        Ok(())
     }
 
-    #[cfg(feature = "fuzz")]
+    // Wait for the bandwidth to write a frame of this size
+    fn throttle_write(&self, size: usize) {
+        throttle::take(self.limits.outbound.as_ref(), Direction::Outbound, size);
+    }
+
+    // Account for a message we just wrote
+    fn written(&mut self, msg: &[u8]) {
+        // The connection is not idle anymore
+        if let Some((cover, next_cover)) = &mut self.cover {
+            *next_cover = Instant::now() + cover.next_interval();
+        }
+        if is_cover(msg) {
+            return;
+        }
+        instrument::message(Direction::Outbound, msg.len());
+        if let Some(capture) = &self.capture {
+            capture.record(Direction::Outbound, msg);
+        }
+    }
+
+    #[cfg(any(test, feature = "fuzz"))]
     #[allow(missing_docs)]
     pub fn pubwrite(&mut self, msg: &[u8]) -> Result<(), Error> {
         self.write(msg)
     }
 
+    #[cfg(feature = "fuzz")]
+    #[allow(missing_docs)]
+    pub fn pubwrite_raw(&mut self, frame: &[u8]) -> Result<(), Error> {
+        self.stream.write_all(frame).map_err(Error::from_stream)
+    }
+
+    /// Send a request without waiting for its response, to read it later on with
+    /// [KKTransport::read_response]. Sending again an abandoned request means we are
+    /// waiting for its response again. Sending another request with the id of one of the
+    /// last [ID_HISTORY_SIZE] requests fails with a [MessageError::IdCollision].
+    pub fn write_req(&mut self, req: &message::Request) -> Result<(), Error> {
+        self.sent_ids.record(req)?;
+        self.abandoned.remove(&req.id());
+        self.write_serialized("request", req)
+    }
+
+    // Serialize and write a message to the communication channel, with the
+    // transactions encoded as negotiated with the peer
+    fn write_serialized<T: serde::Serialize>(
+        &mut self,
+        kind: &str,
+        value: &T,
+    ) -> Result<(), Error> {
+        let encoding = self.tx_encoding();
+        encoding.scope(|| self.write_json(kind, value))
+    }
+
+    fn write_json<T: serde::Serialize>(&mut self, _kind: &str, value: &T) -> Result<(), Error> {
+        if let Some(codec) = self.codec.clone() {
+            let json = serde_json::to_vec(value)?;
+            log_trace!("Sending {}: '{}'", _kind, String::from_utf8_lossy(&json));
+            let mut wire = codec.encode(&json)?.into_owned();
+            wire.resize(self.padded_len(wire.len()), b' ');
+            return self.write(&wire);
+        }
+
+        // Most messages are small enough not to need a buffer from the pool
+        let mut small = [0u8; SMALL_MESSAGE_SIZE];
+        let mut cursor = &mut small[..];
+        if serde_json::to_writer(&mut cursor, value).is_ok() {
+            let len = SMALL_MESSAGE_SIZE - cursor.len();
+            log_trace!(
+                "Sending {}: '{}'",
+                _kind,
+                String::from_utf8_lossy(&small[..len])
+            );
+            let padded_len = self.padded_len(len);
+            if padded_len <= SMALL_MESSAGE_SIZE {
+                small[len..padded_len].fill(b' ');
+                return self.write(&small[..padded_len]);
+            }
+        }
+
+        let mut raw = self.pool.get();
+        let res = serde_json::to_writer(&mut raw, value)
+            .map_err(Error::from)
+            .and_then(|_| {
+                log_trace!("Sending {}: '{}'", _kind, String::from_utf8_lossy(&raw));
+                // Whitespace is ignored by the JSON parser of the peer
+                raw.resize(self.padded_len(raw.len()), b' ');
+                self.write(&raw)
+            });
+        self.pool.put(raw);
+        res
+    }
+
+    // The size to pad a message of this size to
+    fn padded_len(&self, len: usize) -> usize {
+        self.padding
+            .as_ref()
+            .map(|padding| padding.padded_len(len))
+            .unwrap_or(len)
+    }
+
+    // Wait for a message to be available to read until this deadline. Returns false if
+    // none was.
+    fn wait_readable(&mut self, deadline: Instant) -> Result<bool, Error> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout == Duration::from_secs(0) {
+            return Ok(self.readable());
+        }
+
+        let prev_timeout = self.stream.read_timeout()?;
+        self.stream.set_read_timeout(Some(timeout))?;
+        let mut buf = [0u8; 1];
+        let res = self.stream.peek(&mut buf);
+        self.stream.set_read_timeout(prev_timeout)?;
+
+        match res {
+            Ok(_) => Ok(true),
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Read the next response (or error response), queuing the notifications and
+    // dropping the responses to abandoned requests. Returns `None` if none was read
+    // before the deadline.
+    fn next_response(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> Result<Option<message::Response<RawResult>>, Error> {
+        loop {
+            if let Some(deadline) = deadline {
+                if !self.wait_readable(deadline)? {
+                    return Ok(None);
+                }
+            }
+
+            let mut raw_resp = self.pool.get();
+            if !self.read_into(&mut raw_resp)? {
+                self.pool.put(raw_resp);
+                continue;
+            }
+            log_trace!("Read response: '{}'", String::from_utf8_lossy(&raw_resp));
+            let resp = match serde_json::from_slice::<message::Response<Box<RawValue>>>(&raw_resp) {
+                Ok(resp) => message::Response {
+                    result: Ok(resp.result),
+                    id: resp.id,
+                },
+                Err(e) => {
+                    if let Ok(resp) = serde_json::from_slice::<message::ErrorResponse>(&raw_resp) {
+                        message::Response {
+                            result: Err(resp.error),
+                            id: resp.id,
+                        }
+                    } else if serde_json::from_slice::<message::Notification>(&raw_resp).is_ok() {
+                        log_trace!("Got a notification. Queuing it and continuing to read.");
+                        self.notifications.push_back(raw_resp);
+                        continue;
+                    } else {
+                        return Err(Error::Json(JsonError::locate(e, &raw_resp)));
+                    }
+                }
+            };
+            self.pool.put(raw_resp);
+            if self.abandoned.remove(&resp.id) {
+                log_trace!("Response was for an abandoned request. Continuing to read.");
+                continue;
+            }
+
+            return Ok(Some(resp));
+        }
+    }
+
+    // Read the next response while waiting for the response to this request. If the
+    // read times out, the request is abandoned.
+    fn read_response_to(
+        &mut self,
+        req: &message::Request,
+    ) -> Result<message::Response<RawResult>, Error> {
+        match self.next_response(None) {
+            Ok(resp) => Ok(resp.expect("No deadline")),
+            Err(Error::Timeout(_)) => {
+                self.abandon(req.id());
+                Err(Error::Timeout(Some(req.id())))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     /// Send a request to the other end of the encrypted channel, and return their response.
+    ///
+    /// Fails with a [MessageError::IdCollision] if another request was sent with the
+    /// same id among the last [ID_HISTORY_SIZE] ones on this connection. Creating the
+    /// request again gives it a new id.
     pub fn send_req<T>(&mut self, req: &message::Request) -> Result<T, Error>
     where
         T: serde::de::DeserializeOwned,
     {
-        let raw_req = serde_json::to_vec(&req)?;
-        log::trace!("Sending request: '{}'", String::from_utf8_lossy(&raw_req));
-        self.write(&raw_req)?;
+        let peer = self.remote_static();
+        instrument::request(Side::Client, req.method(), req.id(), &peer, || {
+            self.write_req(req)?;
 
-        loop {
-            let raw_resp = self.read()?;
-            log::trace!("Read response: '{}'", String::from_utf8_lossy(&raw_resp));
-            let resp: message::Response<T> = serde_json::from_slice(&raw_resp)?;
-            if resp.id == req.id() {
-                return Ok(resp.result);
-            } else {
-                log::trace!("Reponse was not for us. Continuing to read.");
+            loop {
+                let resp = self.read_response_to(req)?;
+                if resp.id == req.id() {
+                    return self.validated(|| parse_result(resp.result));
+                } else {
+                    log_trace!("Reponse was not for us. Continuing to read.");
+                }
             }
-        }
+        })
     }
 
-    // DRY helper to write a response to the communication channel
-    fn _write_resp<T: serde::ser::Serialize>(
+    /// Send a request to the other end of the encrypted channel, and return their
+    /// response if it is received before this deadline. Otherwise the request is
+    /// abandoned (see [KKTransport::abandon]) and an [Error::Timeout] is returned.
+    pub fn send_req_before<T>(
         &mut self,
-        resp: &message::Response<T>,
-    ) -> Result<(), Error> {
-        let raw_resp = serde_json::to_vec(&resp)?;
-        log::trace!("Sending response: '{}'", String::from_utf8_lossy(&raw_resp));
-        self.write(&raw_resp)
+        req: &message::Request,
+        deadline: Instant,
+    ) -> Result<T, Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let peer = self.remote_static();
+        instrument::request(Side::Client, req.method(), req.id(), &peer, || {
+            self.write_req(req)?;
+
+            loop {
+                let resp = match self.next_response(Some(deadline))? {
+                    Some(resp) => resp,
+                    None => {
+                        self.abandon(req.id());
+                        return Err(Error::Timeout(Some(req.id())));
+                    }
+                };
+                if resp.id == req.id() {
+                    return self.validated(|| parse_result(resp.result));
+                } else {
+                    log_trace!("Reponse was not for us. Continuing to read.");
+                }
+            }
+        })
     }
 
-    /// Read a request from the other end of the encrypted channel.
-    pub fn read_req<F>(&mut self, response_cb: F) -> Result<(), Error>
+    /// Read the next response from the other end of the encrypted channel, to a request
+    /// sent with [KKTransport::write_req], and parse its result. Match it to its request
+    /// by its id. The notifications read meanwhile are queued for
+    /// [KKTransport::read_notification], the responses to abandoned requests dropped.
+    ///
+    /// An error response is returned as an [Error::Remote].
+    pub fn read_response<T>(&mut self) -> Result<message::Response<T>, Error>
     where
-        F: FnOnce(message::RequestParams) -> Option<message::ResponseResult>,
+        T: serde::de::DeserializeOwned,
     {
-        let raw_req = self.read()?;
-        log::trace!("Read request: '{}'", String::from_utf8_lossy(&raw_req));
-        let req: message::Request = serde_json::from_slice(&raw_req)?;
+        let message::Response { result, id } = self.next_response(None)?.expect("No deadline");
+        Ok(message::Response {
+            result: self.validated(|| parse_result(result))?,
+            id,
+        })
+    }
 
-        let id = req.id();
-        if let Some(result) = response_cb(req.params()) {
-            self._write_resp(&message::Response { result, id })?;
+    /// Wait until this deadline for the response to the request with this id, sent with
+    /// [KKTransport::write_req], and parse its result. Returns `None` if it was not
+    /// received in time: the request is still pending, and may be polled again (or
+    /// abandoned).
+    pub fn poll_response<T>(&mut self, id: u32, deadline: Instant) -> Result<Option<T>, Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        loop {
+            match self.next_response(Some(deadline))? {
+                None => return Ok(None),
+                Some(resp) if resp.id == id => {
+                    return self.validated(|| parse_result(resp.result)).map(Some)
+                }
+                Some(_) => log_trace!("Reponse was not for us. Continuing to read."),
+            }
         }
+    }
 
-        Ok(())
+    /// Stop waiting for the response to the request with this id: if it is received
+    /// later on, it will be dropped.
+    pub fn abandon(&mut self, id: u32) {
+        self.abandoned.insert(id);
     }
 
-    /// This is the async version of [read_req]
-    pub async fn read_req_async<F, Fut>(&mut self, response_cb: F) -> Result<(), Error>
+    /// Send a request to the other end of the encrypted channel, and return their
+    /// response. Unlike [KKTransport::send_req], a response for another request is not
+    /// skipped: it is discarded and an [Error::IdMismatch] is returned.
+    pub fn send_and_wait<T>(&mut self, req: &message::Request) -> Result<T, Error>
     where
-        F: FnOnce(message::RequestParams) -> Fut,
-        Fut: std::future::Future<Output = Option<message::ResponseResult>>,
+        T: serde::de::DeserializeOwned,
     {
-        let raw_req = self.read()?;
-        log::trace!("Read request: '{}'", String::from_utf8_lossy(&raw_req));
-        let req: message::Request = serde_json::from_slice(&raw_req)?;
+        let peer = self.remote_static();
+        instrument::request(Side::Client, req.method(), req.id(), &peer, || {
+            self.write_req(req)?;
 
-        let id = req.id();
-        if let Some(result) = response_cb(req.params()).await {
-            self._write_resp(&message::Response { result, id })?;
-        }
+            let resp = self.read_response_to(req)?;
+            if resp.id != req.id() {
+                return Err(Error::IdMismatch {
+                    expected: req.id(),
+                    got: resp.id,
+                });
+            }
 
-        Ok(())
+            parse_result(resp.result)
+        })
     }
 
-    /// Get the static public key of the peer
+    /// Respond to the request with this id with an error.
+    pub fn respond_error(&mut self, id: u32, error: message::ResponseError) -> Result<(), Error> {
+        self.write_serialized("error response", &message::ErrorResponse { error, id })
+    }
+
+    // DRY helper to write a response to the communication channel
+    pub(crate) fn _write_resp<T: serde::ser::Serialize>(
+        &mut self,
+        resp: &message::Response<T>,
+    ) -> Result<(), Error> {
+        self.write_serialized("response", resp)
+    }
+
+    /// Read the next request from the other end of the encrypted channel, to respond to
+    /// it with [KKTransport::respond] (or [KKTransport::respond_error]) using its id.
+    /// Requests of any revision are accepted (see
+    /// [message::v2::Request::from_versioned_slice]), and given as v1 ones.
+    pub fn read_request(&mut self) -> Result<IncomingRequest, Error> {
+        let mut raw_req = self.pool.get();
+        while !self.read_into(&mut raw_req)? {}
+        log_trace!("Read request: '{}'", String::from_utf8_lossy(&raw_req));
+        let req = self
+            .validated(|| parse_versioned_request(&raw_req))
+            .map(|req| IncomingRequest {
+                id: req.id(),
+                params: req.params(),
+            });
+        let req = req.map_err(|e| {
+            let (peer, reason) = (self.remote_static(), DropReason::of_parse_error(&e));
+            let (offset, context) = events::parse_error_context(&raw_req, &e);
+            events::notify(ConnectionEvent::MessageDropped {
+                peer,
+                reason,
+                offset: Some(offset),
+                context: &context,
+            });
+            if let Some(quarantine) = &self.quarantine {
+                quarantine.record(peer, reason, &raw_req);
+            }
+            Error::Json(JsonError::locate(e, &raw_req))
+        });
+        self.pool.put(raw_req);
+
+        req
+    }
+
+    /// Read a request from the other end of the encrypted channel.
+    pub fn read_req<F>(&mut self, response_cb: F) -> Result<(), Error>
+    where
+        F: FnOnce(message::RequestParams) -> Option<message::ResponseResult>,
+    {
+        let IncomingRequest { id, params } = self.read_request()?;
+
+        let peer = self.remote_static();
+        instrument::request(Side::Server, params.method(), id, &peer, || {
+            if let Some(result) = response_cb(params) {
+                self._write_resp(&message::Response { result, id })?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Get an iterator over the requests sent by the other end of the encrypted
+    /// channel. It ends when the peer closes the connection, or when it is shut down
+    /// (see [KKTransport::shutdown_handle]). Responses are sent
+    /// using [Incoming::respond].
+    pub fn incoming(&mut self) -> Incoming<'_> {
+        Incoming { transport: self }
+    }
+
+    /// Respond to the request with this id.
+    pub fn respond(&mut self, id: u32, result: message::ResponseResult) -> Result<(), Error> {
+        self._write_resp(&message::Response { result, id })
+    }
+
+    /// This is the async version of [read_req]
+    pub async fn read_req_async<F, Fut>(&mut self, response_cb: F) -> Result<(), Error>
+    where
+        F: FnOnce(message::RequestParams) -> Fut,
+        Fut: std::future::Future<Output = Option<message::ResponseResult>>,
+    {
+        let IncomingRequest { id, params } = self.read_request()?;
+
+        if let Some(result) = response_cb(params).await {
+            self._write_resp(&message::Response { result, id })?;
+        }
+
+        Ok(())
+    }
+
+    /// Send a notification to the other end of the encrypted channel.
+    pub fn send_notification(&mut self, notif: &message::Notification) -> Result<(), Error> {
+        self.write_serialized("notification", notif)
+    }
+
+    /// Read a notification from the other end of the encrypted channel. Notifications
+    /// that were received while waiting for a response in [KKTransport::send_req] are
+    /// returned first.
+    pub fn read_notification(&mut self) -> Result<message::NotificationParams, Error> {
+        loop {
+            let raw_notif = self.read_message()?;
+            log_trace!(
+                "Read notification: '{}'",
+                String::from_utf8_lossy(&raw_notif)
+            );
+            match self.validated(|| serde_json::from_slice::<message::Notification>(&raw_notif)) {
+                Ok(notif) => return Ok(notif.params()),
+                Err(e) => {
+                    match serde_json::from_slice::<message::Response<IgnoredAny>>(&raw_notif) {
+                        Ok(resp) if self.abandoned.remove(&resp.id) => {
+                            log_trace!("Got a response to an abandoned request. Dropping it.");
+                        }
+                        _ => return Err(e.into()),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Set the timeout of the reads on the underlying stream. `None` means reads
+    /// block indefinitely.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.stream.set_read_timeout(timeout).map_err(|e| e.into())
+    }
+
+    /// Get the timeout of the reads on the underlying stream
+    pub fn read_timeout(&self) -> Result<Option<Duration>, Error> {
+        self.stream.read_timeout().map_err(|e| e.into())
+    }
+
+    /// Set the timeout of the writes on the underlying stream. `None` means writes
+    /// block indefinitely.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.stream.set_write_timeout(timeout).map_err(|e| e.into())
+    }
+
+    // Peek at the stream without blocking
+    fn peek(&self) -> std::io::Result<usize> {
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0u8; 1];
+        let res = self.stream.peek(&mut buf);
+        self.stream.set_nonblocking(false)?;
+        res
+    }
+
+    // Whether the peer closed the connection (or it is broken), without blocking
+    pub(crate) fn peer_closed(&self) -> bool {
+        match self.peek() {
+            Ok(n) => n == 0,
+            Err(e) => e.kind() != std::io::ErrorKind::WouldBlock,
+        }
+    }
+
+    /// Probe whether the connection is still alive, for instance to evict from a pool
+    /// the connections whose remote end silently vanished (after a NAT or Tor circuit
+    /// reset). A dummy frame, dropped by the peer, is sent and the connection is
+    /// considered dead if it fails or is reset or closed within `timeout`. Note a peer
+    /// that vanished without its connection being reset can't be told apart from a
+    /// quiet one.
+    pub fn is_alive(&mut self, timeout: Duration) -> bool {
+        if self.peer_closed() {
+            return false;
+        }
+        let dummy = vec![b' '; self.padded_len(0)];
+        if self.write(&dummy).is_err() {
+            return false;
+        }
+
+        let prev_timeout = match self.stream.read_timeout() {
+            Ok(prev_timeout) => prev_timeout,
+            Err(_) => return false,
+        };
+        let timeout = timeout.max(Duration::from_millis(1));
+        if self.stream.set_read_timeout(Some(timeout)).is_err() {
+            return false;
+        }
+        let mut buf = [0u8; 1];
+        let res = self.stream.peek(&mut buf);
+        let _ = self.stream.set_read_timeout(prev_timeout);
+
+        match res {
+            Ok(n) => n > 0,
+            Err(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+            ),
+        }
+    }
+
+    // Whether a read would not block. Note it would not either if the connection was
+    // closed.
+    pub(crate) fn readable(&self) -> bool {
+        !matches!(self.peek(), Err(e) if e.kind() == std::io::ErrorKind::WouldBlock)
+    }
+
+    // Read a raw message from the communication channel
+    pub(crate) fn read_message(&mut self) -> Result<Vec<u8>, Error> {
+        match self.notifications.pop_front() {
+            Some(msg) => Ok(msg),
+            None => self.read(),
+        }
+    }
+
+    // A handle to the underlying stream, to shut it down from another thread
+    pub(crate) fn try_clone_stream(&self) -> std::io::Result<TcpStream> {
+        self.stream.try_clone()
+    }
+
+    /// A handle to interrupt the reads on this connection from another thread, for
+    /// instance to stop a thread blocked waiting for a quiet peer
+    pub fn shutdown_handle(&self) -> Result<ShutdownHandle, Error> {
+        Ok(ShutdownHandle {
+            stream: Arc::new(self.stream.try_clone()?),
+            shutdown: self.shutdown.clone(),
+        })
+    }
+
+    /// Get the static public key of the peer
     pub fn remote_static(&self) -> PublicKey {
         self.channel.remote_static()
     }
+
+    /// Whether we connected to the peer or accepted its connection
+    pub fn side(&self) -> HandshakeSide {
+        self.side
+    }
+
+    /// The roles of the peer in the Revault network, as this policy resolves them from
+    /// its static key. They are not negotiated, the same policy always gives the same
+    /// roles for a connection.
+    pub fn roles<'a>(&self, policy: &'a PeerPolicy) -> &'a [message::Role] {
+        policy.roles(&self.remote_static())
+    }
+
+    /// The address of the peer, as read once connected. This is the address of the
+    /// other end of the stream: the proxy's for a connection through Tor or a tunnel.
+    /// `None` if it couldn't be read.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// When the handshake with the peer completed
+    pub fn established_at(&self) -> SystemTime {
+        self.established_at
+    }
+
+    /// Get the hash of the handshake of this session, for channel binding (see
+    /// [KKChannel::handshake_hash])
+    pub fn handshake_hash(&self) -> [u8; HANDSHAKE_HASH_SIZE] {
+        self.channel.handshake_hash()
+    }
+
+    /// The version of the protocol agreed on with the peer, if we exchanged a
+    /// [Prelude] with it
+    pub fn protocol_version(&self) -> Option<u8> {
+        self.protocol_version
+    }
+
+    /// Advertise our capabilities to the peer and read its ones, as the initiator of
+    /// the connection. Returns the capabilities to use, supported by both ends. The
+    /// peer must respond with [KKTransport::respond_capabilities].
+    pub fn initiate_capabilities(&mut self, ours: &Capabilities) -> Result<Capabilities, Error> {
+        self.write_serialized("capabilities", &Advertisement::from(ours))?;
+        self.read_capabilities(ours)
+    }
+
+    /// Read the capabilities advertised by the peer and answer with ours, as the
+    /// responder of the connection. Returns the capabilities to use, supported by both
+    /// ends.
+    pub fn respond_capabilities(&mut self, ours: &Capabilities) -> Result<Capabilities, Error> {
+        let negotiated = self.read_capabilities(ours)?;
+        self.write_serialized("capabilities", &Advertisement::from(ours))?;
+        Ok(negotiated)
+    }
+
+    fn read_capabilities(&mut self, ours: &Capabilities) -> Result<Capabilities, Error> {
+        let msg = self.read()?;
+        let theirs: Capabilities = serde_json::from_slice::<Advertisement>(&msg)?.into();
+        log_trace!("Peer advertised capabilities '{}'", theirs);
+        let negotiated = ours.negotiate(&theirs);
+        self.capabilities = Some(negotiated);
+        Ok(negotiated)
+    }
+
+    /// The capabilities negotiated with the peer, if we exchanged them (see
+    /// [KKTransport::initiate_capabilities])
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities
+    }
+
+    /// How the raw transactions of the messages we send are encoded: as base64 if the
+    /// peer negotiated [Capability::Base64Tx], as hex otherwise. Both are always
+    /// accepted from the peer.
+    pub fn tx_encoding(&self) -> message::TxEncoding {
+        match self.capabilities {
+            Some(caps) if caps.contains(Capability::Base64Tx) => message::TxEncoding::Base64,
+            _ => message::TxEncoding::Hex,
+        }
+    }
+}
+
+/// Interrupts the reads on a connection from another thread, see
+/// [KKTransport::shutdown_handle]
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    stream: Arc<TcpStream>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+    /// Close the connection: the read it is blocked on, if any, and all the next ones
+    /// fail with [Error::Shutdown].
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // It might have been closed already
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+
+    /// Whether the connection was shut down through this handle (or a clone of it)
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+}
+
+// The result of a response, left unparsed until we know what to expect. Parsing it
+// directly from the raw JSON avoids building (and allocating) an intermediary tree.
+pub(crate) type RawResult = Result<Box<RawValue>, message::ResponseError>;
+
+// Parse the result of a response, or get the error it contains
+
+pub(crate) fn parse_result<T: serde::de::DeserializeOwned>(result: RawResult) -> Result<T, Error> {
+    from_json_slice(result.map_err(Error::Remote)?.get().as_bytes())
+}
+
+// Parse a request of any revision (see [message::v2::Request::from_versioned_slice]) as
+// a v1 one, which the handlers expect
+fn parse_versioned_request(raw: &[u8]) -> Result<message::Request<'_>, serde_json::Error> {
+    match message::v2::request_version(raw)? {
+        message::v1::VERSION => serde_json::from_slice(raw),
+        message::v2::VERSION => serde_json::from_slice::<message::v2::Request>(raw).map(Into::into),
+        version => Err(serde::de::Error::custom(MessageError::UnknownVersion(
+            version,
+        ))),
+    }
+}
+
+/// A request read from a [KKTransport]
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncomingRequest {
+    /// The id to respond with
+    pub id: u32,
+    /// The parameters of the request
+    pub params: message::RequestParams,
+}
+
+/// An iterator over the requests read from a [KKTransport], see [KKTransport::incoming].
+///
+/// Each item is either a parsed request or the error encountered while reading it. A
+/// request that failed to parse does not prevent reading the next ones, but a Noise
+/// or transport error most likely does.
+#[derive(Debug)]
+pub struct Incoming<'a> {
+    transport: &'a mut KKTransport,
+}
+
+impl Incoming<'_> {
+    /// Respond to the request with this id.
+    pub fn respond(&mut self, id: u32, result: message::ResponseResult) -> Result<(), Error> {
+        self.transport.respond(id, result)
+    }
+
+    /// Respond to the request with this id with an error.
+    pub fn respond_error(&mut self, id: u32, error: message::ResponseError) -> Result<(), Error> {
+        self.transport.respond_error(id, error)
+    }
+
+    /// Get the underlying connection
+    pub fn transport(&mut self) -> &mut KKTransport {
+        self.transport
+    }
+}
+
+impl Iterator for Incoming<'_> {
+    type Item = Result<IncomingRequest, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.transport.read_request() {
+            Err(Error::Disconnected(_)) | Err(Error::Shutdown) => None,
+            res => Some(res),
+        }
+    }
+}
+
+/// The stage a [PendingHandshake] is at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeStage {
+    /// Establishing the TCP connection
+    Connecting,
+    /// Sending the first message of the handshake
+    SendingActOne,
+    /// Waiting for the second message of the handshake
+    AwaitingActTwo,
+}
+
+/// The outcome of advancing a [PendingHandshake]
+// The transport is only moved out of it once, not worth a box
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum Progress {
+    /// The handshake is not completed yet, advance it again later
+    Pending(PendingHandshake),
+    /// The handshake is completed
+    Done(KKTransport),
+}
+
+enum Connection {
+    // The background connection, once done
+    Connecting(mpsc::Receiver<io::Result<TcpStream>>),
+    // Non-blocking
+    Connected(TcpStream),
+}
+
+/// A connection attempt in progress, see [KKTransport::connect_nonblocking].
+///
+/// Nothing happens unless it's advanced, which never blocks. Dropping it cancels the
+/// attempt.
+pub struct PendingHandshake {
+    connection: Connection,
+    // Boxed, it's large
+    act_one: Box<KKHandshakeActOne>,
+    msg_1: KKMessageActOne,
+    // How much of the act one message was written
+    written: usize,
+    msg_2: [u8; KK_MSG_2_SIZE],
+    // How much of the act two message was read
+    read: usize,
+    deadline: Instant,
+    timeout: Duration,
+    // The static key of the server
+    peer: PublicKey,
+}
+
+impl fmt::Debug for PendingHandshake {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PendingHandshake")
+            .field("stage", &self.stage())
+            .field("deadline", &self.deadline)
+            .finish()
+    }
+}
+
+impl PendingHandshake {
+    /// The stage the handshake is at
+    pub fn stage(&self) -> HandshakeStage {
+        match self.connection {
+            Connection::Connecting(_) => HandshakeStage::Connecting,
+            Connection::Connected(_) if self.written < KK_MSG_1_SIZE => {
+                HandshakeStage::SendingActOne
+            }
+            Connection::Connected(_) => HandshakeStage::AwaitingActTwo,
+        }
+    }
+
+    /// Make as much progress as possible without blocking. Errors with
+    /// [Error::Timeout] if the handshake didn't complete in time.
+    pub fn advance(mut self) -> Result<Progress, Error> {
+        if Instant::now() > self.deadline {
+            return Err(Error::Timeout(None));
+        }
+
+        if let Connection::Connecting(receiver) = &self.connection {
+            match receiver.try_recv() {
+                Ok(stream) => {
+                    let stream = stream?;
+                    stream.set_nonblocking(true)?;
+                    self.connection = Connection::Connected(stream);
+                }
+                Err(mpsc::TryRecvError::Empty) => return Ok(Progress::Pending(self)),
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    return Err(io::Error::new(io::ErrorKind::Other, "Connection aborted").into())
+                }
+            }
+        }
+        let stream = match &mut self.connection {
+            Connection::Connected(stream) => stream,
+            Connection::Connecting(_) => unreachable!("We just connected"),
+        };
+
+        // write msg_1 to stream (e, es, ss)
+        while self.written < KK_MSG_1_SIZE {
+            match stream.write(&self.msg_1.0[self.written..]) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+                Ok(n) => self.written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Progress::Pending(self))
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // read msg_2 from stream (e, ee, se)
+        while self.read < KK_MSG_2_SIZE {
+            match stream.read(&mut self.msg_2[self.read..]) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(n) => self.read += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Progress::Pending(self))
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let (act_one, msg_2, timeout, peer) = (self.act_one, self.msg_2, self.timeout, self.peer);
+        let stream = match self.connection {
+            Connection::Connected(stream) => stream,
+            Connection::Connecting(_) => unreachable!("We just connected"),
+        };
+        instrument::handshake(HandshakeSide::Initiator, Some(&peer), move || {
+            let cli_act_2 = KKHandshakeActTwo::initiator(*act_one, &KKMessageActTwo(msg_2))?;
+            let channel = KKChannel::from_handshake(cli_act_2)?;
+            stream.set_nonblocking(false)?;
+            stream.set_read_timeout(Some(timeout))?;
+            Ok(KKTransport::new(stream, channel, HandshakeSide::Initiator))
+        })
+        .map(Progress::Done)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use sodiumoxide::crypto::box_::curve25519xsalsa20poly1305::gen_keypair;
-    use std::{collections::BTreeMap, str::FromStr, thread};
+    use crate::fixtures::local_endpoints;
+
+    use std::{str::FromStr, thread};
 
     #[test]
     fn test_transport_kk() {
-        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
-            (gen_keypair(), gen_keypair());
-
-        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-        let addr = listener.local_addr().unwrap();
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
 
         // client thread
         let cli_thread = thread::spawn(move || {
@@ -208,43 +1625,380 @@ mod tests {
                 KKTransport::connect(addr, &my_noise_privkey, &their_noise_pubkey)
                     .expect("Client channel connecting");
             let msg = "Test message".as_bytes();
-            cli_channel.write(&msg).expect("Sending test message");
-            msg
+            cli_channel.write(msg).expect("Sending test message");
+            (msg, cli_channel.handshake_hash())
         });
 
         let mut server_transport =
             KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
                 .expect("Server channel binding and accepting");
 
-        let sent_msg = cli_thread.join().unwrap();
+        let (sent_msg, handshake_hash) = cli_thread.join().unwrap();
         let received_msg = server_transport.read().unwrap();
         assert_eq!(sent_msg.to_vec(), received_msg);
+        assert_eq!(server_transport.handshake_hash(), handshake_hash);
+    }
+
+    #[test]
+    fn peer_metadata() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let before = SystemTime::now();
+
+        let cli_thread = thread::spawn(move || {
+            KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap()
+        });
+        let server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        let cli_transport = cli_thread.join().unwrap();
+
+        assert_eq!(cli_transport.remote_static(), server_pubkey);
+        assert_eq!(cli_transport.side(), HandshakeSide::Initiator);
+        assert_eq!(cli_transport.peer_addr(), Some(addr));
+        assert_eq!(server_transport.remote_static(), client_pubkey);
+        assert_eq!(server_transport.side(), HandshakeSide::Responder);
+        assert_eq!(
+            server_transport.peer_addr(),
+            Some(cli_transport.stream.local_addr().unwrap())
+        );
+        let policy = PeerPolicy::new().with_peer(client_pubkey, message::Role::Manager);
+        assert_eq!(server_transport.roles(&policy), &[message::Role::Manager]);
+        assert!(cli_transport.roles(&policy).is_empty());
+        for transport in &[cli_transport, server_transport] {
+            assert!(transport.established_at() >= before);
+            assert!(transport.established_at() <= SystemTime::now());
+        }
+    }
+
+    #[test]
+    fn timeout_mid_frame() {
+        use message::coordinator::{GetTime, ServerTime};
+        use std::sync::mpsc;
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let (timed_out, wait_timeout) = mpsc::channel();
+
+        let cli_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            transport
+                .set_read_timeout(Some(Duration::from_millis(100)))
+                .unwrap();
+            let req = transport.request(GetTime {});
+            let err = transport.send_req::<ServerTime>(&req).unwrap_err();
+            assert!(matches!(err, Error::Timeout(_)), "{:?}", err);
+            timed_out.send(()).unwrap();
+
+            // The retry reads the response from where the timed out read stopped, and the
+            // channel is still usable after it
+            let resp: ServerTime = transport.send_req(&req).unwrap();
+            assert_eq!(resp, ServerTime { time: 42 });
+            let resp: ServerTime = transport.send_req(&transport.request(GetTime {})).unwrap();
+            assert_eq!(resp, ServerTime { time: 43 });
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        let id = server_transport.read_request().unwrap().id;
+        let resp = serde_json::to_vec(&message::Response {
+            result: message::ResponseResult::ServerTime(ServerTime { time: 42 }),
+            id,
+        })
+        .unwrap();
+        let mut frame = Vec::new();
+        server_transport
+            .channel
+            .encrypt_message_into(&resp, &mut frame)
+            .unwrap();
+        // Stop halfway through the body until the client gave up on reading it
+        let half = NOISE_MESSAGE_HEADER_SIZE + (frame.len() - NOISE_MESSAGE_HEADER_SIZE) / 2;
+        server_transport.stream.write_all(&frame[..half]).unwrap();
+        wait_timeout.recv().unwrap();
+        server_transport.stream.write_all(&frame[half..]).unwrap();
+
+        // Resent by the client, but already answered
+        assert_eq!(server_transport.read_request().unwrap().id, id);
+        let IncomingRequest { id, .. } = server_transport.read_request().unwrap();
+        server_transport
+            .respond(
+                id,
+                message::ResponseResult::ServerTime(ServerTime { time: 43 }),
+            )
+            .unwrap();
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn request_id_collisions() {
+        use message::{coordinator::GetSpendTx, with_id_generator, SequentialIds};
+
+        let get_spend_tx = |id, vout| {
+            with_id_generator(SequentialIds(id), || {
+                message::Request::from(GetSpendTx {
+                    deposit_outpoint: bitcoin::OutPoint {
+                        vout,
+                        ..Default::default()
+                    },
+                })
+            })
+        };
+        let mut sent_ids = SentIds::default();
+        sent_ids.record(&get_spend_tx(0, 0)).unwrap();
+        // A retry is fine, another request with the same id is not
+        sent_ids.record(&get_spend_tx(0, 0)).unwrap();
+        assert_eq!(
+            sent_ids.record(&get_spend_tx(0, 1)),
+            Err(MessageError::IdCollision(0))
+        );
+
+        // Only the most recent ids are remembered
+        for id in 1..ID_HISTORY_SIZE as u32 {
+            sent_ids.record(&get_spend_tx(id, 0)).unwrap();
+        }
+        assert!(sent_ids.record(&get_spend_tx(0, 1)).is_err());
+        sent_ids
+            .record(&get_spend_tx(ID_HISTORY_SIZE as u32, 0))
+            .unwrap();
+        sent_ids.record(&get_spend_tx(0, 1)).unwrap();
+        assert_eq!(sent_ids.ids.len(), ID_HISTORY_SIZE);
+    }
+
+    #[test]
+    fn sequential_ids() {
+        use message::coordinator::{GetSpendTx, GetTime};
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_thread = thread::spawn(move || {
+            let config = TransportConfig::new().with_sequential_ids(41);
+            let mut transport =
+                KKTransport::connect_with_config(addr, &client_privkey, &server_pubkey, &config)
+                    .unwrap();
+            let first = transport.request(GetTime {});
+            let second = transport.request(GetSpendTx {
+                deposit_outpoint: Default::default(),
+            });
+            // An already created request keeps its id
+            let resent = transport.request(first.clone());
+            assert_eq!((first.id(), second.id(), resent.id()), (41, 42, 41));
+            for req in &[first, second] {
+                transport.write_req(req).unwrap();
+            }
+
+            // Back to random ids
+            transport.set_sequential_ids(None);
+            let ids: HashSet<u32> = (0..8).map(|_| transport.request(GetTime {}).id()).collect();
+            assert!(ids.len() > 1);
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        for id in 41..43 {
+            let msg = server_transport.read_message().unwrap();
+            assert_eq!(
+                serde_json::from_slice::<message::Request>(&msg)
+                    .unwrap()
+                    .id(),
+                id
+            );
+        }
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn typed_reads() {
+        use message::coordinator::{GetTime, ServerTime};
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            let (first, second) = (transport.request(GetTime {}), transport.request(GetTime {}));
+            transport.write_req(&first).unwrap();
+            transport.write_req(&second).unwrap();
+
+            // Responses are read in the order they are sent, not the requests'
+            let resp = transport.read_response::<ServerTime>().unwrap();
+            assert_eq!(resp.id, second.id());
+            assert_eq!(resp.result, ServerTime { time: 21 });
+            assert!(matches!(
+                transport.read_response::<ServerTime>(),
+                Err(Error::Remote(_))
+            ));
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        let first = server_transport.read_request().unwrap();
+        let second = server_transport.read_request().unwrap();
+        assert_eq!(second.params, message::RequestParams::GetTime(GetTime {}));
+        server_transport
+            .respond(
+                second.id,
+                message::ResponseResult::ServerTime(ServerTime { time: 21 }),
+            )
+            .unwrap();
+        server_transport
+            .respond_error(
+                first.id,
+                message::ResponseError::quota_exceeded("Too early", None),
+            )
+            .unwrap();
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn padded_messages() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            let req = message::Request::from(message::coordinator::GetSigs {
+                id: Default::default(),
+                if_none_match: None,
+            });
+            transport.set_padding(Some(Padding::default()));
+            transport.write_req(&req).unwrap();
+            // Padded beyond what fits on the stack
+            transport.set_padding(Some(Padding::new(vec![4096])));
+            transport.write_req(&req).unwrap();
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        for size in &[512, 4096] {
+            let msg = server_transport.read_message().unwrap();
+            assert_eq!(msg.len(), *size);
+            serde_json::from_slice::<message::Request>(&msg).unwrap();
+        }
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn cover_traffic() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            transport.set_padding(Some(Padding::default()));
+            transport.set_cover_traffic(Some(CoverTraffic::new(
+                Duration::from_millis(10),
+                Duration::from_millis(20),
+            )));
+            let req = message::Request::from(message::coordinator::GetSigs {
+                id: Default::default(),
+                if_none_match: None,
+            });
+            // Dummy frames are sent while waiting for the response
+            for _ in 0..3 {
+                transport
+                    .send_req::<message::coordinator::Sigs>(&req)
+                    .unwrap();
+            }
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        let sigs = || {
+            message::ResponseResult::Sigs(message::coordinator::Sigs {
+                signatures: message::SigSet::new(),
+            })
+        };
+        let respond = |_| {
+            thread::sleep(Duration::from_millis(200));
+            Some(sigs())
+        };
+        server_transport.read_req(respond).unwrap();
+        let mut msg = Vec::new();
+        let mut dummies = 0;
+        while !server_transport.read_into(&mut msg).unwrap() {
+            dummies += 1;
+        }
+        assert!(dummies > 0);
+        let id = serde_json::from_slice::<message::Request>(&msg)
+            .unwrap()
+            .id();
+        thread::sleep(Duration::from_millis(200));
+        server_transport.respond(id, sigs()).unwrap();
+        // The dummy frames are dropped when reading a request
+        server_transport.read_req(respond).unwrap();
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn buffer_pooling() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let (client_pool, server_pool) =
+            (Arc::new(BufferPool::new(4)), Arc::new(BufferPool::new(4)));
+        let cli_pool = client_pool.clone();
+        let cli_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+            transport.set_buffer_pool(cli_pool);
+            let req = message::coordinator::GetSigs {
+                id: Default::default(),
+                if_none_match: None,
+            };
+            for _ in 0..3 {
+                transport
+                    .send_req::<message::coordinator::Sigs>(&req.clone().into())
+                    .unwrap();
+            }
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+        server_transport.set_buffer_pool(server_pool.clone());
+        for _ in 0..3 {
+            server_transport
+                .read_req(|_| {
+                    Some(message::ResponseResult::Sigs(message::coordinator::Sigs {
+                        signatures: message::SigSet::new(),
+                    }))
+                })
+                .unwrap();
+        }
+        cli_thread.join().unwrap();
+
+        // The plaintext and ciphertext buffers were given back, and reused
+        assert_eq!(client_pool.len(), 2);
+        assert_eq!(server_pool.len(), 2);
     }
 
     // Send a get_sigs from a client and get back a sigs
     #[test]
     fn rw_sanity_check() {
-        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey)) =
-            (gen_keypair(), gen_keypair());
-
-        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-        let addr = listener.local_addr().unwrap();
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
 
         let id = bitcoin::Txid::from_str(
             "0000000000000000000000000000000000000000000000000000000000000000",
         )
         .unwrap();
-        let req = message::coordinator::GetSigs { id };
+        let req = message::coordinator::GetSigs {
+            id,
+            if_none_match: None,
+        };
         let params_str =
             r#"{"id":"0000000000000000000000000000000000000000000000000000000000000000"}"#;
 
-        let mut signatures = BTreeMap::new();
+        let mut signatures = message::SigSet::new();
         let pubkey = bitcoin::PublicKey::from_str(
             "035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c",
         )
         .unwrap();
         let sig = bitcoin::secp256k1::Signature::from_str("3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2").unwrap();
-        signatures.insert(pubkey.key, sig);
+        signatures.insert(pubkey.key, sig).unwrap();
         let resp = message::coordinator::Sigs { signatures };
         // Note how it does not contain 'result'
         let resp_str = r#"{"signatures":{"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c":"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2"}}"#;
@@ -276,4 +2030,495 @@ mod tests {
 
         cli_thread.join().unwrap();
     }
+
+    #[test]
+    fn incoming_requests() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_thread = thread::spawn(move || {
+            let mut cli_channel = KKTransport::connect(addr, &client_privkey, &server_pubkey)
+                .expect("Client channel connecting");
+            for i in 0..3 {
+                let req = message::coordinator::GetSigs {
+                    id: bitcoin::Txid::default(),
+                    if_none_match: None,
+                };
+                let resp: message::coordinator::Sigs =
+                    cli_channel.send_req(&req.into()).expect("Sending get_sigs");
+                assert!(resp.signatures.is_empty());
+                if i == 1 {
+                    cli_channel.pubwrite(b"garbage").unwrap();
+                }
+            }
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
+                .expect("Server channel binding and accepting");
+        let mut incoming = server_transport.incoming();
+        let (mut requests, mut errors) = (0, 0);
+        while let Some(req) = incoming.next() {
+            let req = match req {
+                Ok(req) => req,
+                Err(Error::Json(_)) => {
+                    errors += 1;
+                    continue;
+                }
+                Err(e) => panic!("{}", e),
+            };
+            assert!(matches!(req.params, message::RequestParams::GetSigs(_)));
+            incoming
+                .respond(
+                    req.id,
+                    message::ResponseResult::Sigs(message::coordinator::Sigs {
+                        signatures: message::SigSet::new(),
+                    }),
+                )
+                .unwrap();
+            requests += 1;
+        }
+        assert_eq!((requests, errors), (3, 1));
+
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn versioned_requests() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_thread = thread::spawn(move || {
+            let mut cli_channel = KKTransport::connect(addr, &client_privkey, &server_pubkey)
+                .expect("Client channel connecting");
+            let req = message::v2::Request::from(message::v2::coordinator::GetSigs {
+                txid: bitcoin::Txid::default(),
+                if_none_match: None,
+            });
+            cli_channel.pubwrite(&req.to_versioned_vec()).unwrap();
+            cli_channel
+                .pubwrite(br#"{"method":"get_sigs","params":{"txid":"0000000000000000000000000000000000000000000000000000000000000000","version":3},"id":3}"#)
+                .unwrap();
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
+                .expect("Server channel binding and accepting");
+        // A v2 request is given as a v1 one, an unknown revision is refused
+        let req = server_transport.read_request().unwrap();
+        assert_eq!(
+            req.params,
+            message::RequestParams::GetSigs(message::coordinator::GetSigs {
+                id: bitcoin::Txid::default(),
+                if_none_match: None,
+            })
+        );
+        assert!(matches!(
+            server_transport.read_request(),
+            Err(Error::Json(_))
+        ));
+
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn response_id_mismatch() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_thread = thread::spawn(move || {
+            let mut cli_channel = KKTransport::connect(addr, &client_privkey, &server_pubkey)
+                .expect("Client channel connecting");
+            let req: message::Request = message::coordinator::GetSigs {
+                id: bitcoin::Txid::default(),
+                if_none_match: None,
+            }
+            .into();
+            let _: message::coordinator::Sigs = cli_channel.send_and_wait(&req).unwrap();
+            let err = cli_channel
+                .send_and_wait::<message::coordinator::Sigs>(&req)
+                .unwrap_err();
+            assert!(
+                matches!(err, Error::IdMismatch { expected, got } if expected == req.id() && got == req.id().wrapping_add(1))
+            );
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
+                .expect("Server channel binding and accepting");
+        let sigs = || {
+            message::ResponseResult::Sigs(message::coordinator::Sigs {
+                signatures: message::SigSet::new(),
+            })
+        };
+        let req = server_transport.incoming().next().unwrap().unwrap();
+        server_transport.respond(req.id, sigs()).unwrap();
+        let req = server_transport.incoming().next().unwrap().unwrap();
+        server_transport
+            .respond(req.id.wrapping_add(1), sigs())
+            .unwrap();
+
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn error_response() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let error = message::ResponseError::new(message::ErrorCode::AccessDenied, "Not allowed");
+        let cli_error = error.clone();
+
+        let cli_thread = thread::spawn(move || {
+            let mut cli_channel = KKTransport::connect(addr, &client_privkey, &server_pubkey)
+                .expect("Client channel connecting");
+            let req: message::Request = message::coordinator::GetSigs {
+                id: bitcoin::Txid::default(),
+                if_none_match: None,
+            }
+            .into();
+            let err = cli_channel
+                .send_req::<message::coordinator::Sigs>(&req)
+                .unwrap_err();
+            assert!(matches!(err, Error::Remote(e) if e == cli_error));
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
+                .expect("Server channel binding and accepting");
+        let req = server_transport.incoming().next().unwrap().unwrap();
+        server_transport.respond_error(req.id, error).unwrap();
+
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn alive_probe() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let (probed_tx, probed_rx) = mpsc::channel();
+
+        let serv_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+            // The probes are transparent to the peer
+            assert_eq!(transport.read().unwrap(), b"ping".to_vec());
+            probed_rx.recv().unwrap();
+        });
+
+        let mut cli_channel = KKTransport::connect(addr, &client_privkey, &server_pubkey)
+            .expect("Client channel connecting");
+        let timeout = Duration::from_millis(50);
+        assert!(cli_channel.is_alive(timeout));
+        cli_channel.write(b"ping").unwrap();
+        probed_tx.send(()).unwrap();
+        serv_thread.join().unwrap();
+        assert!(!cli_channel.is_alive(timeout));
+    }
+
+    #[test]
+    fn interrupted_read() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let (served_tx, served_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        let serv_thread = thread::spawn(move || {
+            // Serving a quiet peer stops cleanly once shut down
+            let mut transport =
+                KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+            let handle = transport.shutdown_handle().unwrap();
+            let shutdown_thread = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                handle.shutdown();
+            });
+            assert!(transport.incoming().next().is_none());
+            shutdown_thread.join().unwrap();
+            served_tx.send(()).unwrap();
+
+            // Being quiet until the peer is done
+            let _transport =
+                KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+            done_rx.recv().unwrap();
+        });
+
+        let quiet = KKTransport::connect(addr, &client_privkey, &server_pubkey).unwrap();
+        served_rx.recv().unwrap();
+        drop(quiet);
+
+        // Reading from a quiet peer is interrupted
+        let mut cli_channel = KKTransport::connect(addr, &client_privkey, &server_pubkey)
+            .expect("Client channel connecting");
+        cli_channel.set_read_timeout(None).unwrap();
+        let handle = cli_channel.shutdown_handle().unwrap();
+        assert!(!handle.is_shutdown());
+        let shutdown_handle = handle.clone();
+        let shutdown_thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            shutdown_handle.shutdown();
+        });
+        assert!(matches!(
+            cli_channel.read_notification(),
+            Err(Error::Shutdown)
+        ));
+        assert!(handle.is_shutdown());
+        assert!(matches!(
+            cli_channel.read_notification(),
+            Err(Error::Shutdown)
+        ));
+        done_tx.send(()).unwrap();
+
+        shutdown_thread.join().unwrap();
+        serv_thread.join().unwrap();
+    }
+
+    #[test]
+    fn capabilities_exchange() {
+        use crate::capabilities::Capability;
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let cli_thread = thread::spawn(move || {
+            let mut cli_channel = KKTransport::connect(addr, &client_privkey, &server_pubkey)
+                .expect("Client channel connecting");
+            assert_eq!(cli_channel.capabilities(), None);
+            let ours = Capabilities::new()
+                .with(Capability::Batching)
+                .with(Capability::Cbor);
+            let negotiated = cli_channel.initiate_capabilities(&ours).unwrap();
+            assert_eq!(negotiated, Capabilities::new().with(Capability::Batching));
+            assert_eq!(cli_channel.capabilities(), Some(negotiated));
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
+                .expect("Server channel binding and accepting");
+        let ours = Capabilities::new()
+            .with(Capability::Batching)
+            .with(Capability::Subscriptions);
+        let negotiated = server_transport.respond_capabilities(&ours).unwrap();
+        assert_eq!(negotiated, Capabilities::new().with(Capability::Batching));
+        assert_eq!(server_transport.capabilities(), Some(negotiated));
+
+        cli_thread.join().unwrap();
+    }
+
+    // The transactions are sent as base64 only to the peers which negotiated it
+    #[test]
+    fn negotiated_tx_encoding() {
+        use crate::capabilities::Capability;
+        use bitcoin::{consensus::encode, OutPoint, Transaction, TxIn, TxOut};
+
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let event = message::coordinator::NewSpendTxEvent {
+            subscription_id: 1,
+            deposit_outpoint: OutPoint::default(),
+            transaction: Transaction {
+                version: 2,
+                lock_time: 0,
+                input: vec![TxIn::default()],
+                output: vec![TxOut::default()],
+            },
+        };
+        let hex_tx = encode::serialize_hex(&event.transaction);
+        let base64_tx = base64::encode(encode::serialize(&event.transaction));
+        let event_srv = event.clone();
+
+        let cli_thread = thread::spawn(move || {
+            let mut cli_channel = KKTransport::connect(addr, &client_privkey, &server_pubkey)
+                .expect("Client channel connecting");
+            assert_eq!(cli_channel.tx_encoding(), message::TxEncoding::Hex);
+            cli_channel
+                .initiate_capabilities(&Capabilities::new().with(Capability::Base64Tx))
+                .unwrap();
+            assert_eq!(cli_channel.tx_encoding(), message::TxEncoding::Base64);
+
+            let raw = cli_channel.read().unwrap();
+            let notif = String::from_utf8_lossy(&raw);
+            assert!(notif.contains(&base64_tx));
+            assert!(!notif.contains(&hex_tx));
+            match serde_json::from_slice::<message::Notification>(&raw).unwrap() {
+                message::Notification::NewSpendTx { params, .. } => assert_eq!(params, event),
+                _ => panic!("Unexpected notification"),
+            }
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
+                .expect("Server channel binding and accepting");
+        server_transport
+            .respond_capabilities(&Capabilities::all())
+            .unwrap();
+        assert_eq!(server_transport.tx_encoding(), message::TxEncoding::Base64);
+        server_transport
+            .send_notification(&event_srv.into())
+            .unwrap();
+
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn request_deadline() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let cli_thread = thread::spawn(move || {
+            let mut cli_channel = KKTransport::connect(addr, &client_privkey, &server_pubkey)
+                .expect("Client channel connecting");
+            let get_sigs = || -> message::Request {
+                message::coordinator::GetSigs {
+                    id: bitcoin::Txid::default(),
+                    if_none_match: None,
+                }
+                .into()
+            };
+            let req = get_sigs();
+            let deadline = Instant::now() + Duration::from_millis(100);
+            let err = cli_channel
+                .send_req_before::<message::coordinator::Sigs>(&req, deadline)
+                .unwrap_err();
+            assert!(matches!(err, Error::Timeout(Some(id)) if id == req.id()));
+            tx.send(()).unwrap();
+
+            // The late response to the first request is dropped
+            let _: message::coordinator::Sigs = cli_channel.send_and_wait(&get_sigs()).unwrap();
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
+                .expect("Server channel binding and accepting");
+        let sigs = || {
+            message::ResponseResult::Sigs(message::coordinator::Sigs {
+                signatures: message::SigSet::new(),
+            })
+        };
+        let first_req = server_transport.incoming().next().unwrap().unwrap();
+        rx.recv().unwrap();
+        server_transport.respond(first_req.id, sigs()).unwrap();
+        let req = server_transport.incoming().next().unwrap().unwrap();
+        server_transport.respond(req.id, sigs()).unwrap();
+
+        cli_thread.join().unwrap();
+    }
+
+    // A notification sent before the response is queued and can be read afterward
+    #[test]
+    fn notification_while_waiting_response() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let id = bitcoin::Txid::default();
+        let pubkey = bitcoin::PublicKey::from_str(
+            "035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c",
+        )
+        .unwrap()
+        .key;
+        let signature = bitcoin::secp256k1::Signature::from_str("3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2").unwrap();
+        let event = message::coordinator::NewSigEvent {
+            subscription_id: 1,
+            pubkey,
+            signature,
+            id,
+        };
+        let event_srv = event.clone();
+
+        let cli_thread = thread::spawn(move || {
+            let mut cli_channel = KKTransport::connect(addr, &client_privkey, &server_pubkey)
+                .expect("Client channel connecting");
+            let req = message::coordinator::Subscribe {
+                txids: vec![id],
+                deposit_outpoints: vec![],
+            };
+            let resp: message::coordinator::SubscribeResult = cli_channel
+                .send_req(&req.into())
+                .expect("Sending subscribe");
+            assert_eq!(resp.subscription_id, 1);
+            assert_eq!(
+                cli_channel.read_notification().unwrap(),
+                message::NotificationParams::NewSig(event)
+            );
+        });
+
+        let mut server_transport =
+            KKTransport::accept(&listener, &server_privkey, &[client_pubkey])
+                .expect("Server channel binding and accepting");
+        server_transport
+            .send_notification(&event_srv.into())
+            .expect("Sending notification");
+        server_transport
+            .read_req(|params| {
+                assert!(matches!(params, message::RequestParams::Subscribe(_)));
+                Some(message::ResponseResult::Subscribed(
+                    message::coordinator::SubscribeResult { subscription_id: 1 },
+                ))
+            })
+            .expect("Reading request");
+
+        cli_thread.join().unwrap();
+    }
+
+    #[test]
+    fn nonblocking_connect() {
+        let ((client_pubkey, client_privkey), (server_pubkey, server_privkey), listener, addr) =
+            local_endpoints();
+
+        let mut pending =
+            KKTransport::connect_nonblocking(addr, &client_privkey, &server_pubkey).unwrap();
+        assert_eq!(pending.stage(), HandshakeStage::Connecting);
+        // The server doesn't respond yet
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while pending.stage() != HandshakeStage::AwaitingActTwo {
+            assert!(Instant::now() < deadline);
+            pending = match pending.advance().unwrap() {
+                Progress::Pending(pending) => pending,
+                Progress::Done(_) => panic!("The server didn't respond"),
+            };
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let server_thread = thread::spawn(move || {
+            let mut transport =
+                KKTransport::accept(&listener, &server_privkey, &[client_pubkey]).unwrap();
+            assert_eq!(transport.read().unwrap(), b"ping".to_vec());
+            listener
+        });
+        let mut transport = loop {
+            assert!(Instant::now() < deadline);
+            match pending.advance().unwrap() {
+                Progress::Pending(p) => pending = p,
+                Progress::Done(transport) => break transport,
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(
+            transport.read_timeout().unwrap(),
+            Some(Duration::from_secs(20))
+        );
+        transport.write(b"ping").unwrap();
+        let _listener = server_thread.join().unwrap();
+        // A connection attempt can be cancelled
+        let pending =
+            KKTransport::connect_nonblocking(addr, &client_privkey, &server_pubkey).unwrap();
+        drop(pending);
+
+        // A refused connection errors
+        let closed_addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut pending =
+            KKTransport::connect_nonblocking(closed_addr, &client_privkey, &server_pubkey).unwrap();
+        let err = loop {
+            assert!(Instant::now() < deadline);
+            match pending.advance() {
+                Ok(Progress::Pending(p)) => pending = p,
+                Ok(Progress::Done(_)) => panic!("Nothing is listening"),
+                Err(e) => break e,
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert!(matches!(err, Error::Transport(_) | Error::Disconnected(_)));
+    }
 }
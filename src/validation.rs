@@ -0,0 +1,563 @@
+//! Message validation
+//!
+//! This module defines the sanity checks performed on the content of messages when
+//! deserializing them, so that servers don't store or relay obvious garbage.
+//! The limits can be configured with a [ValidationConfig].
+
+use crate::error::MessageError;
+
+use bitcoin::{
+    hashes::hex,
+    secp256k1::{key::PublicKey, Signature},
+    Transaction,
+};
+
+use std::{cell::RefCell, collections::BTreeSet};
+
+// The standardness limit on the weight of a transaction. It's the one of `revault_tx`,
+// which we may be built without.
+const MAX_STANDARD_TX_WEIGHT: u64 = 400_000;
+// About as many inputs as fit in a standard transaction
+const MAX_DEPOSIT_OUTPOINTS: usize = 2_500;
+// Way more participants than any deployment would have
+const MAX_SIGNATURES: usize = 100;
+
+/// The limits enforced when deserializing messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationConfig {
+    /// The maximum weight of a transaction contained in a message. Defaults to the
+    /// standardness limit.
+    pub max_tx_weight: u64,
+    /// Accept any signature that can be parsed by a lax DER parser, including
+    /// high-S ones and non-canonical encodings. Defaults to `false`: only strict DER,
+    /// low-S and canonically (lower case) hex-encoded signatures are accepted.
+    pub lenient_signatures: bool,
+    /// The maximum number of deposit outpoints in a message, that is of vaults spent by
+    /// a Spend transaction. Defaults to about as many inputs as fit in a standard
+    /// transaction.
+    pub max_deposit_outpoints: usize,
+    /// The maximum number of signatures in a [SigSet](crate::message::SigSet), at most
+    /// one per participant to the deployment (see [ValidationConfig::with_participants]).
+    /// Defaults to 100.
+    pub max_signatures: usize,
+    /// Accept the fields being renamed under their new name too (as `txid` for the
+    /// `id` of a coordinator [Sig](crate::message::coordinator::Sig)), for the
+    /// deprecation window of their current one. Defaults to `true`: either name is
+    /// accepted, but not both at once.
+    pub field_aliases: bool,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            max_tx_weight: MAX_STANDARD_TX_WEIGHT,
+            lenient_signatures: false,
+            max_deposit_outpoints: MAX_DEPOSIT_OUTPOINTS,
+            max_signatures: MAX_SIGNATURES,
+            field_aliases: true,
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT_CONFIG: RefCell<ValidationConfig> = RefCell::new(ValidationConfig::default());
+}
+
+// Restores the previous configuration when going out of scope, even on panic
+struct ScopeGuard(Option<ValidationConfig>);
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        if let Some(prev) = self.0.take() {
+            CURRENT_CONFIG.with(|c| *c.borrow_mut() = prev);
+        }
+    }
+}
+
+impl ValidationConfig {
+    /// Run `f` with this configuration applied to all the messages deserialized on the
+    /// current thread. The default configuration is used outside of a scope.
+    pub fn scope<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let prev = CURRENT_CONFIG.with(|c| c.replace(self.clone()));
+        let _guard = ScopeGuard(Some(prev));
+        f()
+    }
+
+    /// Only accept as many signatures for a transaction as there are participants to
+    /// the deployment. More can only be garbage, or a peer configured for another
+    /// deployment.
+    pub fn with_participants(mut self, participants: usize) -> Self {
+        self.max_signatures = participants;
+        self
+    }
+
+    /// Get the configuration in use on the current thread
+    pub fn current() -> Self {
+        CURRENT_CONFIG.with(|c| c.borrow().clone())
+    }
+}
+
+/// The value of a field being renamed, given under its name or its alias. If aliases
+/// are not accepted, the alias is ignored as any unknown field.
+pub(crate) fn aliased_field<T>(
+    (field, value): (&'static str, Option<T>),
+    (alias, alias_value): (&'static str, Option<T>),
+    config: &ValidationConfig,
+) -> Result<T, MessageError> {
+    match (value, alias_value.filter(|_| config.field_aliases)) {
+        (Some(value), None) | (None, Some(value)) => Ok(value),
+        (Some(_), Some(_)) => Err(MessageError::AliasedField { field, alias }),
+        (None, None) => Err(MessageError::MissingField(field)),
+    }
+}
+
+/// Check the structure of a transaction: it must have inputs and outputs, must not
+/// spend the same outpoint twice and must not be heavier than `weight`.
+pub(crate) fn check_transaction_structure(
+    tx: &Transaction,
+    weight: u64,
+    config: &ValidationConfig,
+) -> Result<(), MessageError> {
+    if tx.input.is_empty() {
+        return Err(MessageError::NoInput);
+    }
+    if tx.output.is_empty() {
+        return Err(MessageError::NoOutput);
+    }
+
+    let mut seen = BTreeSet::new();
+    for txin in tx.input.iter() {
+        if !seen.insert(txin.previous_output) {
+            return Err(MessageError::DuplicateInput(txin.previous_output));
+        }
+    }
+
+    if weight > config.max_tx_weight {
+        return Err(MessageError::TooHeavyTransaction {
+            weight,
+            max_weight: config.max_tx_weight,
+        });
+    }
+
+    Ok(())
+}
+
+/// Decode a hex string into this buffer, without allocating. Returns the number of
+/// bytes decoded.
+pub(crate) fn hex_into(hex_str: &str, buf: &mut [u8]) -> Result<usize, hex::Error> {
+    if hex_str.len() % 2 != 0 {
+        return Err(hex::Error::OddLengthString(hex_str.len()));
+    }
+    if let Some(c) = hex_str.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(hex::Error::InvalidChar(c as u8));
+    }
+    let len = hex_str.len() / 2;
+    if len > buf.len() {
+        return Err(hex::Error::InvalidLength(buf.len() * 2, hex_str.len()));
+    }
+
+    let digit = |c: u8| (c as char).to_digit(16).expect("Checked above") as u8;
+    for (byte, pair) in buf.iter_mut().zip(hex_str.as_bytes().chunks(2)) {
+        *byte = digit(pair[0]) << 4 | digit(pair[1]);
+    }
+    Ok(len)
+}
+
+/// Parse a hex-encoded public key, refusing uncompressed ones as they would not fit
+/// in the Revault script templates. Note that any point on secp256k1 is part of the
+/// group generated by G since the curve has a cofactor of 1, so there is no subgroup
+/// check to perform.
+pub(crate) fn parse_pubkey(hex_pubkey: &str) -> Result<PublicKey, MessageError> {
+    let mut raw_pubkey = [0u8; 65];
+    match hex_into(hex_pubkey, &mut raw_pubkey) {
+        Ok(33) => {}
+        Ok(65) if raw_pubkey[0] == 0x04 => {
+            return Err(MessageError::UncompressedPublicKey(hex_pubkey.to_string()))
+        }
+        _ => return Err(MessageError::InvalidPublicKey(hex_pubkey.to_string())),
+    }
+
+    PublicKey::from_slice(&raw_pubkey[..33])
+        .map_err(|_| MessageError::InvalidPublicKey(hex_pubkey.to_string()))
+}
+
+/// Parse a hex-encoded ECDSA signature, checking it's strictly DER-encoded, low-S
+/// and canonically hex-encoded unless the configuration is lenient.
+pub(crate) fn parse_signature(
+    hex_sig: &str,
+    config: &ValidationConfig,
+) -> Result<Signature, MessageError> {
+    let invalid_sig = || MessageError::InvalidSignature(hex_sig.to_string());
+    // A DER-encoded signature is at most 72 bytes, but lax DER allows for some padding
+    let mut buf = [0u8; 80];
+    let raw_sig = hex_into(hex_sig, &mut buf)
+        .map(|len| &buf[..len])
+        .map_err(|_| invalid_sig())?;
+
+    if config.lenient_signatures {
+        return Signature::from_der_lax(raw_sig).map_err(|_| invalid_sig());
+    }
+
+    let sig = Signature::from_der(raw_sig).map_err(|_| invalid_sig())?;
+    check_signature(&sig, config)?;
+    if sig.serialize_der()[..] != raw_sig[..] || hex_sig.bytes().any(|c| c.is_ascii_uppercase()) {
+        return Err(MessageError::NonCanonicalSignature(hex_sig.to_string()));
+    }
+
+    Ok(sig)
+}
+
+/// Check a signature has a low S value, unless the configuration is lenient
+pub(crate) fn check_signature(
+    sig: &Signature,
+    config: &ValidationConfig,
+) -> Result<(), MessageError> {
+    if config.lenient_signatures {
+        return Ok(());
+    }
+
+    let mut normalized_sig = *sig;
+    normalized_sig.normalize_s();
+    if &normalized_sig != sig {
+        return Err(MessageError::HighSSignature(*sig));
+    }
+
+    Ok(())
+}
+
+/// Check there aren't more than the configured maximum of deposit outpoints
+pub(crate) fn check_deposit_outpoints_count(count: usize) -> Result<(), MessageError> {
+    let max = ValidationConfig::current().max_deposit_outpoints;
+    if count > max {
+        return Err(MessageError::TooManyDepositOutpoints { count, max });
+    }
+
+    Ok(())
+}
+
+/// Sanity check a (fully signed) transaction against the current configuration
+pub(crate) fn check_transaction(tx: &Transaction) -> Result<(), MessageError> {
+    check_transaction_structure(tx, tx.get_weight() as u64, &ValidationConfig::current())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::coordinator;
+
+    #[cfg(feature = "revault_tx")]
+    use crate::fixtures::spend_tx;
+    #[cfg(feature = "revault_tx")]
+    use crate::message::cosigner;
+    use bitcoin::{hashes::hex::ToHex, OutPoint, TxIn, TxOut};
+    #[cfg(feature = "revault_tx")]
+    use revault_tx::transactions::RevaultTransaction;
+
+    fn dummy_tx(n_inputs: u32, n_outputs: usize) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..n_inputs)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint {
+                        vout,
+                        ..OutPoint::default()
+                    },
+                    ..TxIn::default()
+                })
+                .collect(),
+            output: vec![TxOut::default(); n_outputs],
+        }
+    }
+
+    #[test]
+    fn transaction_structure() {
+        check_transaction(&dummy_tx(1, 1)).unwrap();
+        assert_eq!(
+            check_transaction(&dummy_tx(0, 1)),
+            Err(MessageError::NoInput)
+        );
+        assert_eq!(
+            check_transaction(&dummy_tx(1, 0)),
+            Err(MessageError::NoOutput)
+        );
+
+        let mut tx = dummy_tx(2, 1);
+        tx.input[1] = tx.input[0].clone();
+        assert_eq!(
+            check_transaction(&tx),
+            Err(MessageError::DuplicateInput(tx.input[0].previous_output))
+        );
+
+        // A big but standard transaction
+        let tx = dummy_tx(2000, 1);
+        check_transaction(&tx).unwrap();
+        let config = ValidationConfig {
+            max_tx_weight: 1000,
+            ..ValidationConfig::default()
+        };
+        assert_eq!(
+            config.scope(|| check_transaction(&tx)),
+            Err(MessageError::TooHeavyTransaction {
+                weight: tx.get_weight() as u64,
+                max_weight: 1000,
+            })
+        );
+        // The configuration only applies within the scope
+        check_transaction(&tx).unwrap();
+        assert_eq!(ValidationConfig::current(), ValidationConfig::default());
+        // A non-standard one
+        let tx = dummy_tx(10_000, 1);
+        check_transaction(&tx).unwrap_err();
+    }
+
+    #[cfg(feature = "revault_tx")]
+    #[test]
+    fn deserialization_sanity_checks() {
+        assert_eq!(
+            MAX_STANDARD_TX_WEIGHT,
+            revault_tx::transactions::MAX_STANDARD_TX_WEIGHT as u64
+        );
+
+        let spend_tx = spend_tx();
+
+        // SpendTx
+        let ser = serde_json::to_string(&coordinator::SpendTx::found(
+            spend_tx.clone().into_psbt().extract_tx(),
+        ))
+        .unwrap();
+        serde_json::from_str::<coordinator::SpendTx>(&ser).unwrap();
+        let strict = ValidationConfig {
+            max_tx_weight: 100,
+            ..ValidationConfig::default()
+        };
+        strict
+            .scope(|| serde_json::from_str::<coordinator::SpendTx>(&ser))
+            .unwrap_err();
+        let no_output =
+            serde_json::to_string(&coordinator::SpendTx::found(dummy_tx(1, 0))).unwrap();
+        serde_json::from_str::<coordinator::SpendTx>(&no_output).unwrap_err();
+
+        // SignRequest
+        let ser = serde_json::to_string(&cosigner::SignRequest::new(spend_tx)).unwrap();
+        serde_json::from_str::<cosigner::SignRequest>(&ser).unwrap();
+        strict
+            .scope(|| serde_json::from_str::<cosigner::SignRequest>(&ser))
+            .unwrap_err();
+    }
+
+    #[test]
+    fn signature_strictness() {
+        let low_s = "3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2";
+        let high_s = "3046022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae022100c4b9e44bcc94a134510299d8556dd102b61ef0da272c8f76f68fcec266750e9f";
+        let strict = ValidationConfig::default();
+        let lenient = ValidationConfig {
+            lenient_signatures: true,
+            ..ValidationConfig::default()
+        };
+
+        parse_signature(low_s, &strict).unwrap();
+        assert!(matches!(
+            parse_signature(high_s, &strict),
+            Err(MessageError::HighSSignature(_))
+        ));
+        assert!(matches!(
+            parse_signature(&low_s.to_uppercase(), &strict),
+            Err(MessageError::NonCanonicalSignature(_))
+        ));
+        // Not DER
+        let compact = "dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae3b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2";
+        assert!(matches!(
+            parse_signature(compact, &strict),
+            Err(MessageError::InvalidSignature(_))
+        ));
+        parse_signature("3045zz", &strict).unwrap_err();
+
+        // The escape hatch
+        parse_signature(high_s, &lenient).unwrap();
+        parse_signature(&low_s.to_uppercase(), &lenient).unwrap();
+        parse_signature(compact, &lenient).unwrap_err();
+
+        // It's enforced when parsing messages
+        let msg = format!(
+            r#"{{"pubkey":"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c","signature":"{}","id":"0000000000000000000000000000000000000000000000000000000000000000"}}"#,
+            high_s
+        );
+        serde_json::from_str::<coordinator::Sig>(&msg).unwrap_err();
+        lenient
+            .scope(|| serde_json::from_str::<coordinator::Sig>(&msg))
+            .unwrap();
+        let msg = format!(
+            r#"{{"signatures":{{"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c":"{}"}}}}"#,
+            high_s
+        );
+        serde_json::from_str::<coordinator::Sigs>(&msg).unwrap_err();
+        lenient
+            .scope(|| serde_json::from_str::<coordinator::Sigs>(&msg))
+            .unwrap();
+    }
+
+    #[test]
+    fn compressed_pubkeys() {
+        let compressed = "035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c";
+        let uncompressed = parse_pubkey(compressed)
+            .unwrap()
+            .serialize_uncompressed()
+            .to_hex();
+        assert!(matches!(
+            parse_pubkey(&uncompressed),
+            Err(MessageError::UncompressedPublicKey(_))
+        ));
+        // Not on the curve
+        assert!(matches!(
+            parse_pubkey("025be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42d"),
+            Err(MessageError::InvalidPublicKey(_))
+        ));
+        // Neither compressed nor uncompressed
+        let invalid = [
+            "zz",
+            "",
+            // 32 bytes
+            &compressed[2..],
+            // 34 bytes
+            &format!("{}00", compressed),
+            // 65 bytes, hybrid
+            &format!("06{}", &uncompressed[2..]),
+            // 65 bytes, compressed prefix
+            &format!("02{}", &uncompressed[2..]),
+            // 66 bytes
+            &format!("{}00", uncompressed),
+        ];
+        for pubkey in invalid.iter() {
+            assert!(
+                matches!(parse_pubkey(pubkey), Err(MessageError::InvalidPublicKey(_))),
+                "{}",
+                pubkey
+            );
+        }
+
+        // It's enforced when parsing messages
+        let msg = |pubkey: &str| {
+            format!(
+                r#"{{"pubkey":"{}","signature":"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2","id":"0000000000000000000000000000000000000000000000000000000000000000"}}"#,
+                pubkey
+            )
+        };
+        serde_json::from_str::<coordinator::Sig>(&msg(compressed)).unwrap();
+        serde_json::from_str::<coordinator::Sig>(&msg(&uncompressed)).unwrap_err();
+        let msg = |pubkey: &str| {
+            format!(
+                r#"{{"signatures":{{"{}":"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2"}}}}"#,
+                pubkey
+            )
+        };
+        serde_json::from_str::<coordinator::Sigs>(&msg(compressed)).unwrap();
+        serde_json::from_str::<coordinator::Sigs>(&msg(&uncompressed)).unwrap_err();
+    }
+
+    #[test]
+    fn signatures_count() {
+        use crate::message::{watchtower, SigSet};
+        use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+
+        let secp = Secp256k1::new();
+        let signatures: SigSet = (1..4u8)
+            .map(|i| {
+                let privkey = SecretKey::from_slice(&[i; 32]).unwrap();
+                (
+                    PublicKey::from_secret_key(&secp, &privkey),
+                    secp.sign(&Message::from_slice(&[1; 32]).unwrap(), &privkey),
+                )
+            })
+            .collect();
+        let sigs = serde_json::to_string(&coordinator::Sigs {
+            signatures: signatures.clone(),
+        })
+        .unwrap();
+        let revocation_sigs = serde_json::to_string(&watchtower::Sig {
+            signatures,
+            txid: Default::default(),
+            deposit_outpoint: Default::default(),
+        })
+        .unwrap();
+
+        let three = ValidationConfig::default().with_participants(3);
+        let two = ValidationConfig::default().with_participants(2);
+        three
+            .scope(|| serde_json::from_str::<coordinator::Sigs>(&sigs))
+            .unwrap();
+        three
+            .scope(|| serde_json::from_str::<watchtower::Sig>(&revocation_sigs))
+            .unwrap();
+        let err = two
+            .scope(|| serde_json::from_str::<coordinator::Sigs>(&sigs))
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&MessageError::TooManySignatures { count: 3, max: 2 }.to_string()));
+        two.scope(|| serde_json::from_str::<watchtower::Sig>(&revocation_sigs))
+            .unwrap_err();
+    }
+
+    #[test]
+    fn hex_decoding() {
+        let mut buf = [0u8; 4];
+        assert_eq!(hex_into("", &mut buf), Ok(0));
+        assert_eq!(hex_into("0aFf", &mut buf), Ok(2));
+        assert_eq!(&buf[..2], &[0x0a, 0xff]);
+        assert_eq!(hex_into("0a0b0c0d", &mut buf), Ok(4));
+        assert_eq!(buf, [0x0a, 0x0b, 0x0c, 0x0d]);
+        assert_eq!(
+            hex_into("0a0b0c0d0e", &mut buf),
+            Err(hex::Error::InvalidLength(8, 10))
+        );
+        assert_eq!(
+            hex_into("0a0", &mut buf),
+            Err(hex::Error::OddLengthString(3))
+        );
+        assert_eq!(hex_into("0g", &mut buf), Err(hex::Error::InvalidChar(b'g')));
+        assert_eq!(hex_into("é", &mut buf), Err(hex::Error::InvalidChar(0xe9)));
+    }
+
+    #[test]
+    fn field_aliases() {
+        let msg = |fields: &str| {
+            format!(
+                r#"{{"pubkey":"035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c","signature":"3045022100dc4dc264a9fef17a3f253449cf8c397ab6f16fb3d63d86940b5586823dfd02ae02203b461bb4336b5ecbaefd6627aa922efc048fec0c881c10c4c9428fca69c132a2",{}}}"#,
+                fields
+            )
+        };
+        let txid = r#""0000000000000000000000000000000000000000000000000000000000000000""#;
+        let under_name = msg(&format!(r#""id":{}"#, txid));
+        let under_alias = msg(&format!(r#""txid":{}"#, txid));
+        let under_both = msg(&format!(r#""id":{},"txid":{}"#, txid, txid));
+
+        // Either name is accepted, and the current one is used for serialization
+        let sig = serde_json::from_str::<coordinator::Sig>(&under_name).unwrap();
+        assert_eq!(
+            serde_json::from_str::<coordinator::Sig>(&under_alias).unwrap(),
+            sig
+        );
+        assert_eq!(serde_json::to_string(&sig).unwrap(), under_name);
+        let err = serde_json::from_str::<coordinator::Sig>(&under_both).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Field 'id' was given along with its alias 'txid'"));
+
+        // Unless aliases are refused
+        let strict = ValidationConfig {
+            field_aliases: false,
+            ..ValidationConfig::default()
+        };
+        strict
+            .scope(|| serde_json::from_str::<coordinator::Sig>(&under_name))
+            .unwrap();
+        let err = strict
+            .scope(|| serde_json::from_str::<coordinator::Sig>(&under_alias))
+            .unwrap_err();
+        assert!(err.to_string().contains("Missing field 'id'"));
+        strict
+            .scope(|| serde_json::from_str::<coordinator::Sig>(&under_both))
+            .unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+//! Signature verification
+//!
+//! Routines to check the signatures contained in [Sig](crate::message::coordinator::Sig),
+//! [Sigs](crate::message::coordinator::Sigs) and [watchtower's Sig](crate::message::watchtower::Sig)
+//! messages against the transaction they are for. The sighash is computed using the
+//! witness script of the PSBT input.
+//!
+//! All the transactions stakeholders exchange signatures for (Unvault, Cancel, Emergency
+//! and Unvault Emergency) have a single internal input at signing time, so the
+//! signatures are checked against the first input.
+
+use crate::{
+    error::{Error, MessageError},
+    message::{coordinator, watchtower, SigSet},
+};
+
+use revault_tx::{
+    bitcoin::{
+        secp256k1::{self, key::PublicKey, Signature},
+        SigHashType, Txid,
+    },
+    transactions::RevaultTransaction,
+};
+
+// The index of the input signed by the stakeholders
+const INTERNAL_INPUT_INDEX: usize = 0;
+
+/// Check a single ECDSA signature for the given input of a Revault transaction.
+pub fn verify_signature<C: secp256k1::Verification, T: RevaultTransaction>(
+    secp: &secp256k1::Secp256k1<C>,
+    tx: &T,
+    input_index: usize,
+    sighash_type: SigHashType,
+    pubkey: &PublicKey,
+    signature: &Signature,
+) -> Result<(), Error> {
+    let sighash = tx
+        .signature_hash_internal_input(input_index, sighash_type)
+        .map_err(MessageError::InputSatisfaction)?;
+    let msg = secp256k1::Message::from_slice(&sighash).expect("sighash is 32 bytes");
+
+    secp.verify(&msg, signature, pubkey)
+        .map_err(|_| MessageError::InvalidSignatureFor(*pubkey).into())
+}
+
+// Check the message is for this transaction
+fn check_txid<T: RevaultTransaction>(tx: &T, msg_txid: &Txid) -> Result<(), Error> {
+    let txid = tx.txid();
+    if &txid != msg_txid {
+        return Err(MessageError::TxidMismatch {
+            expected: txid,
+            got: *msg_txid,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Check all the signatures of a set against the internal input of a Revault transaction.
+pub fn verify_sigset<C: secp256k1::Verification, T: RevaultTransaction>(
+    secp: &secp256k1::Secp256k1<C>,
+    tx: &T,
+    sighash_type: SigHashType,
+    signatures: &SigSet,
+) -> Result<(), Error> {
+    for (pubkey, sig) in signatures.iter() {
+        verify_signature(secp, tx, INTERNAL_INPUT_INDEX, sighash_type, pubkey, sig)?;
+    }
+
+    Ok(())
+}
+
+/// Check the signature shared with the coordinator is for this transaction and valid.
+pub fn verify_coordinator_sig<C: secp256k1::Verification, T: RevaultTransaction>(
+    secp: &secp256k1::Secp256k1<C>,
+    msg: &coordinator::Sig,
+    tx: &T,
+    sighash_type: SigHashType,
+) -> Result<(), Error> {
+    check_txid(tx, &msg.id)?;
+    verify_signature(
+        secp,
+        tx,
+        INTERNAL_INPUT_INDEX,
+        sighash_type,
+        &msg.pubkey,
+        &msg.signature,
+    )
+}
+
+/// Check all the signatures returned by the coordinator are valid for this transaction.
+pub fn verify_coordinator_sigs<C: secp256k1::Verification, T: RevaultTransaction>(
+    secp: &secp256k1::Secp256k1<C>,
+    msg: &coordinator::Sigs,
+    tx: &T,
+    sighash_type: SigHashType,
+) -> Result<(), Error> {
+    verify_sigset(secp, tx, sighash_type, &msg.signatures)
+}
+
+/// Check the signatures shared with a watchtower are for this revocation transaction
+/// and valid.
+pub fn verify_watchtower_sig<C: secp256k1::Verification, T: RevaultTransaction>(
+    secp: &secp256k1::Secp256k1<C>,
+    msg: &watchtower::Sig,
+    tx: &T,
+    sighash_type: SigHashType,
+) -> Result<(), Error> {
+    check_txid(tx, &msg.txid)?;
+    verify_sigset(secp, tx, sighash_type, &msg.signatures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{secp_privkey, secp_sig, spend_tx};
+    use revault_tx::{bitcoin::secp256k1::key::SecretKey, transactions::SpendTransaction};
+    use std::str::FromStr;
+
+    fn sign(tx: &SpendTransaction, privkey: &SecretKey, sighash_type: SigHashType) -> Signature {
+        let secp = secp256k1::Secp256k1::new();
+        let sighash = tx
+            .signature_hash_internal_input(INTERNAL_INPUT_INDEX, sighash_type)
+            .unwrap();
+        secp.sign(&secp256k1::Message::from_slice(&sighash).unwrap(), privkey)
+    }
+
+    #[test]
+    fn signatures_verification() {
+        let secp = secp256k1::Secp256k1::new();
+        let tx = spend_tx();
+        let (privkey, pubkey) = (secp_privkey(), secp_sig().0);
+        let other_pubkey =
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2; 32]).unwrap());
+        let signature = sign(&tx, &privkey, SigHashType::All);
+
+        let msg = coordinator::Sig {
+            pubkey,
+            signature,
+            id: tx.txid(),
+        };
+        verify_coordinator_sig(&secp, &msg, &tx, SigHashType::All).unwrap();
+        // Wrong sighash type
+        assert_eq!(
+            verify_coordinator_sig(&secp, &msg, &tx, SigHashType::AllPlusAnyoneCanPay)
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Message error: 'Invalid signature for public key '{}''",
+                pubkey
+            )
+        );
+        // Wrong transaction
+        let wrong_txid_msg = coordinator::Sig {
+            id: Txid::from_str("6a276a96807dd45ceed9cbd6fd48b5edf185623b23339a1643e19e8dcbf2e474")
+                .unwrap(),
+            ..msg.clone()
+        };
+        verify_coordinator_sig(&secp, &wrong_txid_msg, &tx, SigHashType::All).unwrap_err();
+        // Wrong key
+        let wrong_key_msg = coordinator::Sig {
+            pubkey: other_pubkey,
+            ..msg
+        };
+        verify_coordinator_sig(&secp, &wrong_key_msg, &tx, SigHashType::All).unwrap_err();
+        let builder = coordinator::SigBuilder::new()
+            .pubkey(pubkey)
+            .signature(signature)
+            .unwrap()
+            .tx(&tx);
+        builder
+            .clone()
+            .build_verified(&secp, &tx, SigHashType::All)
+            .unwrap();
+        builder
+            .pubkey(other_pubkey)
+            .build_verified(&secp, &tx, SigHashType::All)
+            .unwrap_err();
+
+        let msg = coordinator::Sigs {
+            signatures: [(pubkey, signature)].iter().cloned().collect(),
+        };
+        verify_coordinator_sigs(&secp, &msg, &tx, SigHashType::All).unwrap();
+        let msg = coordinator::Sigs {
+            signatures: [(pubkey, signature), (other_pubkey, signature)]
+                .iter()
+                .cloned()
+                .collect(),
+        };
+        verify_coordinator_sigs(&secp, &msg, &tx, SigHashType::All).unwrap_err();
+
+        let signature = sign(&tx, &privkey, SigHashType::AllPlusAnyoneCanPay);
+        let msg = watchtower::Sig {
+            signatures: [(pubkey, signature)].iter().cloned().collect(),
+            txid: tx.txid(),
+            deposit_outpoint: Default::default(),
+        };
+        verify_watchtower_sig(&secp, &msg, &tx, SigHashType::AllPlusAnyoneCanPay).unwrap();
+        verify_watchtower_sig(&secp, &msg, &tx, SigHashType::All).unwrap_err();
+        msg.signatures
+            .verify(&secp, &tx, SigHashType::AllPlusAnyoneCanPay)
+            .unwrap();
+    }
+}
@@ -0,0 +1,341 @@
+//! WebSocket wrapper API, for browsers
+//!
+//! Browsers can't open TCP connections. A [WsTransport] enacts the same Noise KK channel
+//! as a [KKTransport](crate::transport::KKTransport), over a WebSocket instead: the
+//! binary messages sent and received carry the bytes of the stream, regardless of the
+//! frames boundaries. The servers listen on TCP, so the WebSocket must be terminated by
+//! a gateway forwarding these bytes to their port (such as the one in
+//! `server::websocket`, or websockify).
+//!
+//! The browser can't block, so the methods reading from the connection are `async`.
+//! They don't time out: race them against a timer if needed.
+
+use crate::{
+    error::{Error, JsonError},
+    instrument::log_trace,
+    message,
+    noise::{
+        KKChannel, KKHandshakeActOne, KKHandshakeActTwo, KKMessageActTwo, NoiseEncryptedHeader,
+        NoiseEncryptedMessage, PublicKey, SecretKey, HANDSHAKE_HASH_SIZE, KK_MSG_2_SIZE,
+        NOISE_MESSAGE_HEADER_SIZE,
+    },
+    transport::{parse_result, RawResult},
+};
+
+use serde::de::IgnoredAny;
+use serde_json::value::RawValue;
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    convert::TryInto,
+    future::poll_fn,
+    io,
+    rc::Rc,
+    task::{Poll, Waker},
+};
+use wasm_bindgen::{closure::Closure, JsCast};
+use web_sys::{BinaryType, CloseEvent, Event, MessageEvent, WebSocket};
+
+// What the event handlers of the WebSocket share with the transport
+#[derive(Debug, Default)]
+struct State {
+    // The bytes received and not read yet
+    received: VecDeque<u8>,
+    open: bool,
+    // Why the connection was closed, if it was
+    closed: Option<String>,
+    // The task waiting for one of the above to change
+    waker: Option<Waker>,
+}
+
+impl State {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+// The error for a closed connection
+fn disconnected(reason: &str) -> Error {
+    Error::Disconnected(io::Error::new(io::ErrorKind::UnexpectedEof, reason))
+}
+
+// The handlers of the events of the WebSocket, kept alive as long as it is used
+struct Handlers {
+    _on_open: Closure<dyn FnMut(Event)>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+    _on_error: Closure<dyn FnMut(Event)>,
+}
+
+/// Wrapper type for a WebSocket and KKChannel that automatically enforces authenticated
+/// and encrypted channels when communicating
+pub struct WsTransport {
+    socket: WebSocket,
+    state: Rc<RefCell<State>>,
+    _handlers: Handlers,
+    channel: KKChannel,
+    // Notifications we read while waiting for a response
+    notifications: VecDeque<Vec<u8>>,
+}
+
+impl std::fmt::Debug for WsTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("WsTransport")
+            .field("url", &self.socket.url())
+            .field("state", &self.state)
+            .field("channel", &self.channel)
+            .finish()
+    }
+}
+
+// Open a WebSocket to this url, feeding the state from its events
+fn open(url: &str) -> Result<(WebSocket, Rc<RefCell<State>>, Handlers), Error> {
+    let socket = WebSocket::new(url).map_err(js_error)?;
+    socket.set_binary_type(BinaryType::Arraybuffer);
+    let state = Rc::new(RefCell::new(State::default()));
+
+    let open_state = state.clone();
+    let on_open = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+        let mut state = open_state.borrow_mut();
+        state.open = true;
+        state.wake();
+    });
+    let message_state = state.clone();
+    let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+        if let Ok(buf) = event.data().dyn_into::<js_sys::ArrayBuffer>() {
+            let mut state = message_state.borrow_mut();
+            state
+                .received
+                .extend(js_sys::Uint8Array::new(&buf).to_vec());
+            state.wake();
+        }
+    });
+    let close_state = state.clone();
+    let on_close = Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+        let mut state = close_state.borrow_mut();
+        state.closed = Some(format!(
+            "WebSocket closed with code {}: '{}'",
+            event.code(),
+            event.reason()
+        ));
+        state.wake();
+    });
+    let error_state = state.clone();
+    let on_error = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+        let mut state = error_state.borrow_mut();
+        if state.closed.is_none() {
+            state.closed = Some("WebSocket error".to_string());
+        }
+        state.wake();
+    });
+
+    socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+    socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+    socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+    let handlers = Handlers {
+        _on_open: on_open,
+        _on_message: on_message,
+        _on_close: on_close,
+        _on_error: on_error,
+    };
+    Ok((socket, state, handlers))
+}
+
+// An exception thrown by the WebSocket API
+fn js_error(e: wasm_bindgen::JsValue) -> Error {
+    Error::Transport(io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))
+}
+
+// Wait until the WebSocket is open
+async fn wait_open(state: &RefCell<State>) -> Result<(), Error> {
+    poll_fn(|cx| {
+        let mut state = state.borrow_mut();
+        if let Some(reason) = &state.closed {
+            return Poll::Ready(Err(disconnected(reason)));
+        }
+        if state.open {
+            return Poll::Ready(Ok(()));
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    })
+    .await
+}
+
+// Read exactly this number of bytes from the WebSocket
+async fn read_exact(state: &RefCell<State>, len: usize) -> Result<Vec<u8>, Error> {
+    poll_fn(|cx| {
+        let mut state = state.borrow_mut();
+        if state.received.len() >= len {
+            return Poll::Ready(Ok(state.received.drain(..len).collect()));
+        }
+        if let Some(reason) = &state.closed {
+            return Poll::Ready(Err(disconnected(reason)));
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    })
+    .await
+}
+
+// Write these bytes to the WebSocket
+fn write_all(socket: &WebSocket, state: &RefCell<State>, bytes: &[u8]) -> Result<(), Error> {
+    if let Some(reason) = &state.borrow().closed {
+        return Err(disconnected(reason));
+    }
+    socket.send_with_u8_array(bytes).map_err(js_error)
+}
+
+impl WsTransport {
+    /// Connect to the server behind the WebSocket proxy at this url (`ws://` or
+    /// `wss://`), and enact Noise handshake with given private key.
+    pub async fn connect(
+        url: &str,
+        my_noise_privkey: &SecretKey,
+        their_noise_pubkey: &PublicKey,
+    ) -> Result<WsTransport, Error> {
+        let (socket, state, handlers) = open(url)?;
+        wait_open(&state).await?;
+
+        let (cli_act_1, msg_1) =
+            KKHandshakeActOne::initiator(my_noise_privkey, their_noise_pubkey)?;
+
+        // write msg_1 to stream (e, es, ss)
+        write_all(&socket, &state, &msg_1.0)?;
+
+        // read msg_2 from stream (e, ee, se)
+        let msg_2 = read_exact(&state, KK_MSG_2_SIZE).await?;
+        let msg_act_2 = KKMessageActTwo(msg_2.try_into().expect("Read KK_MSG_2_SIZE bytes"));
+        let cli_act_2 = KKHandshakeActTwo::initiator(cli_act_1, &msg_act_2)?;
+        let channel = KKChannel::from_handshake(cli_act_2)?;
+
+        Ok(WsTransport {
+            socket,
+            state,
+            _handlers: handlers,
+            channel,
+            notifications: VecDeque::new(),
+        })
+    }
+
+    // Read and decrypt a message from the communication channel
+    async fn read(&mut self) -> Result<Vec<u8>, Error> {
+        let header = read_exact(&self.state, NOISE_MESSAGE_HEADER_SIZE).await?;
+        let header = NoiseEncryptedHeader(header.try_into().expect("Read the header size"));
+        let msg_len = self.channel.decrypt_header(&header)?;
+
+        let body = read_exact(&self.state, msg_len as usize).await?;
+        let msg = self.channel.decrypt_message(&NoiseEncryptedMessage(body))?;
+
+        Ok(msg)
+    }
+
+    // Read a raw message from the communication channel
+    async fn read_message(&mut self) -> Result<Vec<u8>, Error> {
+        match self.notifications.pop_front() {
+            Some(msg) => Ok(msg),
+            None => self.read().await,
+        }
+    }
+
+    // Encrypt and write a message to the communication channel
+    fn write(&mut self, msg: &[u8]) -> Result<(), Error> {
+        let encrypted_msg = self.channel.encrypt_message(msg)?;
+        write_all(&self.socket, &self.state, &encrypted_msg.0)
+    }
+
+    // Read the next response (or error response), queuing the notifications
+    async fn read_response(&mut self) -> Result<message::Response<RawResult>, Error> {
+        loop {
+            let raw_resp = self.read().await?;
+            log_trace!("Read response: '{}'", String::from_utf8_lossy(&raw_resp));
+            match serde_json::from_slice::<message::Response<Box<RawValue>>>(&raw_resp) {
+                Ok(resp) => {
+                    return Ok(message::Response {
+                        result: Ok(resp.result),
+                        id: resp.id,
+                    })
+                }
+                Err(e) => {
+                    if let Ok(resp) = serde_json::from_slice::<message::ErrorResponse>(&raw_resp) {
+                        return Ok(message::Response {
+                            result: Err(resp.error),
+                            id: resp.id,
+                        });
+                    } else if serde_json::from_slice::<message::Notification>(&raw_resp).is_ok() {
+                        log_trace!("Got a notification. Queuing it and continuing to read.");
+                        self.notifications.push_back(raw_resp);
+                    } else {
+                        return Err(Error::Json(JsonError::locate(e, &raw_resp)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send a request to the other end of the encrypted channel, and return their response.
+    pub async fn send_req<T>(&mut self, req: &message::Request<'_>) -> Result<T, Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.write(&serde_json::to_vec(req)?)?;
+
+        loop {
+            let resp = self.read_response().await?;
+            if resp.id == req.id() {
+                return parse_result(resp.result);
+            } else {
+                log_trace!("Reponse was not for us. Continuing to read.");
+            }
+        }
+    }
+
+    /// Read a notification from the other end of the encrypted channel. Notifications
+    /// that were received while waiting for a response in [WsTransport::send_req] are
+    /// returned first.
+    pub async fn read_notification(&mut self) -> Result<message::NotificationParams, Error> {
+        loop {
+            let raw_notif = self.read_message().await?;
+            log_trace!(
+                "Read notification: '{}'",
+                String::from_utf8_lossy(&raw_notif)
+            );
+            match serde_json::from_slice::<message::Notification>(&raw_notif) {
+                Ok(notif) => return Ok(notif.params()),
+                Err(e) => {
+                    if serde_json::from_slice::<message::Response<IgnoredAny>>(&raw_notif).is_err()
+                    {
+                        return Err(e.into());
+                    }
+                    log_trace!("Got a response to no pending request. Dropping it.");
+                }
+            }
+        }
+    }
+
+    /// Get the static public key of the peer
+    pub fn remote_static(&self) -> PublicKey {
+        self.channel.remote_static()
+    }
+
+    /// Get the hash of the handshake of this session, for channel binding (see
+    /// [KKChannel::handshake_hash])
+    pub fn handshake_hash(&self) -> [u8; HANDSHAKE_HASH_SIZE] {
+        self.channel.handshake_hash()
+    }
+}
+
+impl Drop for WsTransport {
+    fn drop(&mut self) {
+        // The handlers are about to be freed, they must not be called anymore
+        self.socket.set_onopen(None);
+        self.socket.set_onmessage(None);
+        self.socket.set_onclose(None);
+        self.socket.set_onerror(None);
+        let _ = self.socket.close();
+    }
+}